@@ -0,0 +1,81 @@
+mod messages;
+mod payload;
+mod render;
+mod server;
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use clap::Parser;
+use matrix_bot_core::{
+    http::{HttpConfig, HttpServer},
+    AccountConfig, Bot,
+};
+use matrix_sdk::ruma::OwnedRoomId;
+use messages::Messages;
+use server::Receiver;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The address to listen for Alertmanager on
+    #[arg(long, default_value = "0.0.0.0:9095", env = "ALERTMANAGER_LISTEN")]
+    pub listen: SocketAddr,
+
+    #[clap(flatten)]
+    pub http: HttpConfig,
+
+    /// Post alerts for an Alertmanager receiver to a room, as
+    /// `receiver=!room:example.org`. May be given more than once
+    #[arg(long = "route", required = true, value_parser = parse_route, value_delimiter = ',', env = "ALERTMANAGER_ROUTES")]
+    pub routes: Vec<(String, OwnedRoomId)>,
+
+    /// A bearer token Alertmanager must send, set with `http_config.authorization`
+    #[arg(long, env = "ALERTMANAGER_TOKEN")]
+    pub token: Option<String>,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_route(route: &str) -> Result<(String, OwnedRoomId), String> {
+    let (receiver, room_id) = route
+        .split_once('=')
+        .ok_or_else(|| format!("expected receiver=!room:example.org, got {route}"))?;
+    let room_id =
+        OwnedRoomId::try_from(room_id.trim()).map_err(|err| format!("{room_id}: {err}"))?;
+    Ok((receiver.trim().to_owned(), room_id))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut routes: HashMap<_, Vec<_>> = HashMap::new();
+    for (receiver, room_id) in config.routes {
+        routes.entry(receiver).or_default().push(room_id);
+    }
+
+    let mut bot = Bot::login("matrix-alertmanager", config.account_config).await?;
+    let messages = Messages::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let receiver = Arc::new(Receiver {
+        client: bot.client().clone(),
+        messages,
+        routes,
+        token: config.token,
+    });
+    bot.serve_http(HttpServer::new(config.listen, config.http).merge(server::router(receiver)))
+        .await?;
+
+    bot.run().await
+}
@@ -0,0 +1,64 @@
+use matrix_bot_core::{store::Store, Bot};
+use matrix_sdk::ruma::OwnedEventId;
+use rusqlite::{params, OptionalExtension};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE messages (
+        group_key TEXT NOT NULL,
+        room_id TEXT NOT NULL,
+        event_id TEXT NOT NULL,
+        PRIMARY KEY (group_key, room_id)
+    );"];
+
+/// The messages posted for each group of alerts, so that updates to a group
+/// can edit its message instead of posting a new one.
+#[derive(Clone)]
+pub struct Messages {
+    store: Store,
+}
+
+impl Messages {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn message(&self, group_key: &str, room_id: &str) -> anyhow::Result<Option<OwnedEventId>> {
+        let event_id: Option<String> = self.store.with(|conn| {
+            conn.query_row(
+                "SELECT event_id FROM messages WHERE group_key = ?1 AND room_id = ?2",
+                [group_key, room_id],
+                |row| row.get(0),
+            )
+            .optional()
+        })?;
+        Ok(event_id.map(OwnedEventId::try_from).transpose()?)
+    }
+
+    pub fn set_message(
+        &self,
+        group_key: &str,
+        room_id: &str,
+        event_id: &str,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO messages (group_key, room_id, event_id) VALUES (?1, ?2, ?3)
+                ON CONFLICT DO UPDATE SET event_id = excluded.event_id",
+                params![group_key, room_id, event_id],
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Forget a group's message, so that it fires afresh next time.
+    pub fn remove_message(&self, group_key: &str, room_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "DELETE FROM messages WHERE group_key = ?1 AND room_id = ?2",
+                [group_key, room_id],
+            )
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt::Write;
+
+use matrix_bot_core::html::escape;
+use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+
+use crate::payload::{Alert, Notification, Status};
+
+/// The most alerts listed in one message.
+const MAX_ALERTS: usize = 10;
+
+/// How an alert's severity is shown.
+fn severity_style(alert: &Alert) -> (&'static str, &'static str) {
+    if alert.status == Status::Resolved {
+        return ("✅", "#2da44e");
+    }
+    match alert.labels.get("severity").map(String::as_str) {
+        Some("critical" | "page" | "error") => ("🔴", "#cf222e"),
+        Some("warning" | "warn") => ("🟠", "#bc4c00"),
+        Some("info" | "informational") => ("🔵", "#0969da"),
+        _ => ("⚪", "#6e7781"),
+    }
+}
+
+/// Render a group of alerts as a single message.
+pub fn render(notification: &Notification) -> RoomMessageEventContent {
+    let firing = notification
+        .alerts
+        .iter()
+        .filter(|alert| alert.status == Status::Firing)
+        .count();
+    let resolved = notification.alerts.len() - firing;
+
+    let name = notification
+        .group_labels
+        .get("alertname")
+        .or_else(|| notification.common_labels.get("alertname"))
+        .map_or("alerts", String::as_str);
+    let (status, color) = match notification.status {
+        Status::Firing => ("🔥 FIRING", "#cf222e"),
+        Status::Resolved => ("✅ RESOLVED", "#2da44e"),
+    };
+    let counts = match (firing, resolved) {
+        (firing, 0) => format!("{firing} firing"),
+        (0, resolved) => format!("{resolved} resolved"),
+        (firing, resolved) => format!("{firing} firing, {resolved} resolved"),
+    };
+
+    let mut plain = format!("{status}: {name} ({counts})");
+    let mut html = format!(
+        "<p><font data-mx-color=\"{color}\"><b>{status}</b></font>: {} ({counts})</p>",
+        escape(name)
+    );
+    if let Some(summary) = notification.common_annotations.get("summary") {
+        let _ = write!(plain, "\n{summary}");
+        let _ = write!(html, "<p>{}</p>", escape(summary));
+    }
+
+    // Firing alerts first, as they're the ones needing attention
+    let mut alerts: Vec<_> = notification.alerts.iter().collect();
+    alerts.sort_by_key(|alert| alert.status == Status::Resolved);
+
+    html += "<ul>";
+    for alert in alerts.iter().take(MAX_ALERTS) {
+        let (icon, color) = severity_style(alert);
+        let description = alert
+            .annotations
+            .get("summary")
+            .or_else(|| alert.annotations.get("description"))
+            .or_else(|| alert.labels.get("alertname"))
+            .map_or("", String::as_str);
+        // Labels that differ between the alerts in the group identify them
+        let labels: Vec<_> = alert
+            .labels
+            .iter()
+            .filter(|(key, _)| !notification.common_labels.contains_key(*key))
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        let labels = labels.join(", ");
+
+        let _ = write!(plain, "\n{icon} {description}");
+        let _ = write!(
+            html,
+            "<li><font data-mx-color=\"{color}\">{icon}</font> {}",
+            escape(description)
+        );
+        if !labels.is_empty() {
+            let _ = write!(plain, " ({labels})");
+            let _ = write!(html, " <code>{}</code>", escape(&labels));
+        }
+        if !alert.generator_url.is_empty() {
+            let _ = write!(
+                html,
+                " <a href=\"{}\">source</a>",
+                escape(&alert.generator_url)
+            );
+        }
+        html += "</li>";
+    }
+    if alerts.len() > MAX_ALERTS {
+        let more = alerts.len() - MAX_ALERTS;
+        let _ = write!(plain, "\n…and {more} more");
+        let _ = write!(html, "<li>…and {more} more</li>");
+    }
+    html += "</ul>";
+
+    if !notification.external_url.is_empty() {
+        let _ = write!(
+            html,
+            "<p><a href=\"{}\">Alertmanager</a></p>",
+            escape(&notification.external_url)
+        );
+    }
+
+    RoomMessageEventContent::notice_html(plain, html)
+}
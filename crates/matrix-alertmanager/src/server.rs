@@ -0,0 +1,119 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use matrix_bot_core::{can_reply, http::constant_time_eq, replacement, send, send_or_log_error};
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId},
+    Client, Room, RoomState,
+};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    messages::Messages,
+    payload::{Notification, Status},
+    render::render,
+};
+
+pub struct Receiver {
+    pub client: Client,
+    pub messages: Messages,
+    /// The rooms each Alertmanager receiver posts to.
+    pub routes: HashMap<String, Vec<OwnedRoomId>>,
+    /// If set, Alertmanager must send this as a bearer token.
+    pub token: Option<String>,
+}
+
+pub fn router(receiver: Arc<Receiver>) -> Router {
+    Router::new()
+        .route("/alerts", post(alerts))
+        .with_state(receiver)
+}
+
+#[instrument(skip_all, fields(receiver = notification.receiver.as_str(), group = notification.group_key.as_str()))]
+async fn alerts(
+    State(receiver): State<Arc<Receiver>>,
+    headers: HeaderMap,
+    Json(notification): Json<Notification>,
+) -> (StatusCode, &'static str) {
+    if let Some(token) = &receiver.token {
+        let sent = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !sent.is_some_and(|sent| constant_time_eq(sent.as_bytes(), token.as_bytes())) {
+            debug!("Rejected request with a bad token");
+            return (StatusCode::UNAUTHORIZED, "bad token");
+        }
+    }
+    if notification.version != "4" {
+        warn!(
+            version = notification.version.as_str(),
+            "Unexpected webhook version"
+        );
+    }
+
+    let Some(rooms) = receiver.routes.get(&notification.receiver) else {
+        warn!("No rooms are routed for this receiver");
+        return (StatusCode::NOT_FOUND, "unknown receiver");
+    };
+
+    let content = render(&notification);
+    for room_id in rooms {
+        let Some(room) = receiver.client.get_room(room_id) else {
+            warn!(
+                room = room_id.as_str(),
+                "Not in a room alerts are routed to"
+            );
+            continue;
+        };
+        if room.state() != RoomState::Joined || !can_reply(&room).await {
+            continue;
+        }
+        if let Err(err) = post(&receiver.messages, &room, &notification, content.clone()).await {
+            error!(room = room_id.as_str(), "Failed to post alerts: {err}");
+        }
+    }
+    info!(
+        status = ?notification.status,
+        alerts = notification.alerts.len(),
+        "Delivered alerts"
+    );
+    (StatusCode::OK, "ok")
+}
+
+/// Post a group's alerts, editing the group's earlier message if it has one.
+async fn post(
+    messages: &Messages,
+    room: &Room,
+    notification: &Notification,
+    content: RoomMessageEventContent,
+) -> anyhow::Result<()> {
+    let group_key = notification.group_key.as_str();
+    let room_id = room.room_id().as_str();
+
+    match messages.message(group_key, room_id)? {
+        Some(event_id) => {
+            debug!(event = event_id.as_str(), "Updating the group's message");
+            send_or_log_error(room, replacement(event_id, content)).await;
+        }
+        None if notification.status == Status::Resolved => {
+            // We never saw this group fire, so there's nothing to coalesce with
+            send_or_log_error(room, content).await;
+        }
+        None => {
+            let event_id = send(room, content).await?;
+            messages.set_message(group_key, room_id, event_id.as_str())?;
+        }
+    }
+
+    // Once everything has resolved, the group starts afresh if it fires again
+    if notification.status == Status::Resolved {
+        messages.remove_message(group_key, room_id)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use matrix_bot_core::{can_reply, rooms};
+use matrix_sdk::{
+    ruma::{
+        events::{
+            room::message::RoomMessageEventContent, space::child::SpaceChildEventContent,
+            SyncOrStrippedState, SyncStateEvent,
+        },
+        OwnedRoomId, OwnedRoomOrAliasId, OwnedUserId, RoomId, UserId,
+    },
+    Client, Room, RoomState,
+};
+use tracing::{info, instrument, warn};
+
+/// An announcement waiting to be confirmed.
+pub struct Draft {
+    pub text: String,
+    pub rooms: Vec<OwnedRoomId>,
+}
+
+/// Where an announcement was and wasn't delivered.
+pub struct Report {
+    pub sent: usize,
+    pub failed: Vec<(OwnedRoomId, String)>,
+}
+
+impl Report {
+    pub fn summary(&self) -> String {
+        let total = self.sent + self.failed.len();
+        let mut summary = format!("Sent the announcement to {} of {total} rooms.", self.sent);
+        if !self.failed.is_empty() {
+            summary += "\nFailed:";
+            for (room_id, reason) in &self.failed {
+                summary += &format!("\n{room_id}: {reason}");
+            }
+        }
+        summary
+    }
+}
+
+#[derive(Clone)]
+pub struct Announcer {
+    /// Rooms whose members may announce, besides `admins`.
+    pub admin_room: Option<OwnedRoomOrAliasId>,
+    pub admins: Arc<Vec<OwnedUserId>>,
+    /// Rooms and spaces are resolved when they're used, so an alias
+    /// that moves is followed.
+    pub rooms: Arc<Vec<OwnedRoomOrAliasId>>,
+    /// Spaces whose rooms get every announcement.
+    pub spaces: Arc<Vec<OwnedRoomOrAliasId>>,
+    /// How long to wait between rooms, to stay under rate limits.
+    pub delay: Duration,
+    /// Each person's unconfirmed announcement.
+    drafts: Arc<Mutex<HashMap<OwnedUserId, Draft>>>,
+}
+
+impl Announcer {
+    pub fn new(
+        admin_room: Option<OwnedRoomOrAliasId>,
+        admins: Vec<OwnedUserId>,
+        rooms: Vec<OwnedRoomOrAliasId>,
+        spaces: Vec<OwnedRoomOrAliasId>,
+        delay: Duration,
+    ) -> Self {
+        Self {
+            admin_room,
+            admins: Arc::new(admins),
+            rooms: Arc::new(rooms),
+            spaces: Arc::new(spaces),
+            delay,
+            drafts: Default::default(),
+        }
+    }
+
+    /// Whether `user_id` may announce from `room`: anyone in the admin room,
+    /// or an admin in the admin room or their DM with the bot.
+    pub async fn is_allowed(&self, room: &Room, user_id: &UserId) -> anyhow::Result<bool> {
+        if self.admin_room_id(&room.client()).await.as_deref() == Some(room.room_id()) {
+            return Ok(true);
+        }
+        Ok(self.admins.iter().any(|admin| admin == user_id) && room.is_direct().await?)
+    }
+
+    /// The rooms an announcement goes to: the configured ones and every room
+    /// in the configured spaces.
+    pub async fn targets(&self, client: &Client) -> anyhow::Result<Vec<OwnedRoomId>> {
+        let mut targets = rooms::resolve_all(client, &self.rooms).await;
+        for space_id in rooms::resolve_all(client, &self.spaces).await {
+            let Some(space) = client.get_room(&space_id) else {
+                warn!("Not in space {space_id}, skipping its rooms");
+                continue;
+            };
+            for child in space_children(&space).await? {
+                if !targets.contains(&child) {
+                    targets.push(child);
+                }
+            }
+        }
+        // Announcing to the admin room would only echo the preview
+        let admin_room = self.admin_room_id(client).await;
+        targets.retain(|room_id| admin_room.as_ref() != Some(room_id));
+        Ok(targets)
+    }
+
+    async fn admin_room_id(&self, client: &Client) -> Option<OwnedRoomId> {
+        let admin_room = self.admin_room.as_ref()?;
+        rooms::resolve(client, admin_room)
+            .await
+            .inspect_err(|err| warn!("Failed to resolve the admin room: {err:#}"))
+            .ok()
+    }
+
+    pub fn save_draft(&self, user_id: &UserId, draft: Draft) {
+        self.drafts
+            .lock()
+            .unwrap()
+            .insert(user_id.to_owned(), draft);
+    }
+
+    pub fn take_draft(&self, user_id: &UserId) -> Option<Draft> {
+        self.drafts.lock().unwrap().remove(user_id)
+    }
+
+    /// Send an announcement to each of its rooms in turn.
+    #[instrument(skip_all, fields(rooms = draft.rooms.len()))]
+    pub async fn broadcast(&self, client: &Client, draft: Draft) -> Report {
+        let mut report = Report {
+            sent: 0,
+            failed: Vec::new(),
+        };
+        for (i, room_id) in draft.rooms.into_iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(self.delay).await;
+            }
+            match send(client, &room_id, &draft.text).await {
+                Ok(()) => report.sent += 1,
+                Err(reason) => {
+                    warn!(room = room_id.as_str(), "Failed to announce: {reason}");
+                    report.failed.push((room_id, reason));
+                }
+            }
+        }
+        info!(
+            sent = report.sent,
+            failed = report.failed.len(),
+            "Finished announcing"
+        );
+        report
+    }
+}
+
+async fn send(client: &Client, room_id: &RoomId, text: &str) -> Result<(), String> {
+    let Some(room) = client
+        .get_room(room_id)
+        .filter(|room| room.state() == RoomState::Joined)
+    else {
+        return Err("I'm not in this room".to_owned());
+    };
+    if !can_reply(&room).await {
+        return Err("I'm not allowed to post here".to_owned());
+    }
+    matrix_bot_core::send(&room, RoomMessageEventContent::text_markdown(text))
+        .await
+        .map_err(|err| format!("{err:#}"))?;
+    Ok(())
+}
+
+/// The rooms a space lists as its children, leaving out ones it has removed.
+async fn space_children(space: &Room) -> anyhow::Result<Vec<OwnedRoomId>> {
+    let events = space
+        .get_state_events_static::<SpaceChildEventContent>()
+        .await?;
+    Ok(events
+        .into_iter()
+        .filter_map(|raw| match raw.deserialize().ok()? {
+            SyncOrStrippedState::Sync(SyncStateEvent::Original(event))
+                if !event.content.via.is_empty() =>
+            {
+                Some(event.state_key)
+            }
+            _ => None,
+        })
+        .collect())
+}
@@ -0,0 +1,111 @@
+use matrix_bot_core::{can_reply, reply, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+    Room, RoomState,
+};
+use tracing::{info, instrument};
+
+use crate::announce::{Announcer, Draft};
+
+const HELP: &str = "Usage:
+!announce <message> to preview an announcement, which can use Markdown
+!announce confirm to send it to every room
+!announce cancel to throw it away";
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    announcer: Ctx<Announcer>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!announce")) else {
+        return Ok(());
+    };
+    // Stay quiet everywhere else, so the bot can't be used to find the
+    // admins or the rooms it announces to
+    if !announcer.is_allowed(&room, &event.sender).await? || !can_reply(&room).await {
+        return Ok(());
+    }
+
+    match args {
+        "" | "help" => reply_notice(&room, &event, HELP).await,
+        "cancel" => {
+            let response = match announcer.take_draft(&event.sender) {
+                Some(_) => "Thrown away your announcement.",
+                None => "You don't have an announcement waiting.",
+            };
+            reply_notice(&room, &event, response).await;
+        }
+        "confirm" => {
+            let Some(draft) = announcer.take_draft(&event.sender) else {
+                reply_notice(&room, &event, "You don't have an announcement waiting.").await;
+                return Ok(());
+            };
+            info!(sender = event.sender.as_str(), "Announcing");
+            reply_notice(
+                &room,
+                &event,
+                format!("Sending the announcement to {} rooms…", draft.rooms.len()),
+            )
+            .await;
+            // Sending to many rooms takes a while, don't hold up other events
+            let announcer = announcer.clone();
+            tokio::spawn(async move {
+                let report = announcer.broadcast(&room.client(), draft).await;
+                reply_notice(&room, &event, report.summary()).await;
+            });
+        }
+        text => {
+            let rooms = announcer.targets(&room.client()).await?;
+            if rooms.is_empty() {
+                reply_notice(&room, &event, "There are no rooms to announce to.").await;
+                return Ok(());
+            }
+            let list: Vec<String> = rooms
+                .iter()
+                .map(|room_id| {
+                    let name = room
+                        .client()
+                        .get_room(room_id)
+                        .and_then(|target| target.name());
+                    match name {
+                        Some(name) => format!("{name} ({room_id})"),
+                        None => room_id.to_string(),
+                    }
+                })
+                .collect();
+            reply(
+                &room,
+                &event,
+                RoomMessageEventContent::notice_markdown(text),
+            )
+            .await;
+            reply_notice(
+                &room,
+                &event,
+                format!(
+                    "That's how the announcement will look. It will go to {} rooms:\n{}\n\
+                    Say `!announce confirm` to send it, or `!announce cancel`.",
+                    rooms.len(),
+                    list.join("\n")
+                ),
+            )
+            .await;
+            announcer.save_draft(
+                &event.sender,
+                Draft {
+                    text: text.to_owned(),
+                    rooms,
+                },
+            );
+        }
+    }
+    Ok(())
+}
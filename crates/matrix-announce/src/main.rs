@@ -0,0 +1,80 @@
+mod announce;
+mod handlers;
+
+use std::time::Duration;
+
+use announce::Announcer;
+use anyhow::ensure;
+use clap::Parser;
+use handlers::on_room_message;
+use matrix_bot_core::{AccountConfig, Bot};
+use matrix_sdk::ruma::{OwnedRoomOrAliasId, OwnedUserId};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// A room whose members may send announcements, by ID or alias
+    #[arg(long, env = "ANNOUNCE_ADMIN_ROOM")]
+    pub admin_room: Option<OwnedRoomOrAliasId>,
+
+    /// People who may send announcements from their DM with the bot
+    #[arg(long, value_delimiter = ',', env = "ANNOUNCE_ADMINS")]
+    pub admin: Vec<OwnedUserId>,
+
+    /// Rooms to send announcements to, by ID or alias
+    #[arg(long, value_delimiter = ',', env = "ANNOUNCE_ROOMS")]
+    pub room: Vec<OwnedRoomOrAliasId>,
+
+    /// Spaces to send announcements to every room of, by ID or alias
+    #[arg(long, value_delimiter = ',', env = "ANNOUNCE_SPACES")]
+    pub space: Vec<OwnedRoomOrAliasId>,
+
+    /// How long to wait between sending to each room
+    #[arg(long, default_value = "2s", value_parser = parse_delay, env = "ANNOUNCE_DELAY")]
+    pub delay: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_delay(delay: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(delay).ok_or_else(|| format!("invalid delay: {delay}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    ensure!(
+        config.admin_room.is_some() || !config.admin.is_empty(),
+        "nobody could send announcements, give an admin room or admins"
+    );
+    ensure!(
+        !config.room.is_empty() || !config.space.is_empty(),
+        "there's nowhere to send announcements, give rooms or spaces"
+    );
+
+    let mut bot = Bot::login("matrix-announce", config.account_config).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Announcer::new(
+        config.admin_room,
+        config.admin,
+        config.room,
+        config.space,
+        config.delay,
+    ));
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,291 @@
+use std::time::Instant;
+
+use matrix_bot_core::{
+    can_reply, html, is_moderator, reply_notice, rooms::resolve_room, send_or_log_error,
+    strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::{
+            member::{MembershipState, OriginalSyncRoomMemberEvent},
+            message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+        },
+        Int, OwnedRoomId, UserId,
+    },
+    Client, Room, RoomState,
+};
+use tracing::{info, instrument, warn};
+
+use crate::{protected::ProtectedRooms, score::Tracker};
+
+/// The most of a message quoted in reports.
+const QUOTE_LENGTH: usize = 300;
+
+/// The power level given to muted users.
+const MUTED_POWER_LEVEL: i32 = -1;
+
+const MUTED: &str =
+    "your message looked like spam, so I've muted you until a moderator can take a look.";
+
+const HELP: &str = "Commands, from this room only:
+!antispam protect <room> / !antispam unprotect <room> / !antispam rooms
+!antispam sensitivity <room> <low|normal|high|percentage>";
+
+const SENSITIVITY_HELP: &str =
+    "Give a sensitivity of low, normal, high or a percentage from 10 to 500.";
+
+/// The scores at which each action is taken, or 0 to never take it.
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    pub report: u32,
+    pub redact: u32,
+    pub mute: u32,
+    pub kick: u32,
+}
+
+fn reached(threshold: u32, points: u32) -> bool {
+    threshold > 0 && points >= threshold
+}
+
+#[derive(Clone)]
+pub struct Antispam {
+    pub protected: ProtectedRooms,
+    pub tracker: Tracker,
+    pub thresholds: Thresholds,
+    /// The room commands are taken from and reports are posted to.
+    pub admin_room: OwnedRoomId,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    antispam: Ctx<Antispam>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+
+    if room.room_id() == antispam.admin_room {
+        let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!antispam")) else {
+            return Ok(());
+        };
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        let response = if is_moderator(&room, &event.sender).await? {
+            command(args, &room.client(), &antispam.protected).await?
+        } else {
+            "Only moderators of this room can use me.".to_owned()
+        };
+        reply_notice(&room, &event, response).await;
+        return Ok(());
+    }
+
+    let Some(sensitivity) = antispam.protected.sensitivity(room.room_id().as_str())? else {
+        return Ok(());
+    };
+    if is_moderator(&room, &event.sender).await? {
+        return Ok(());
+    }
+
+    let body = event.content.body();
+    let mentions = event
+        .content
+        .mentions
+        .as_ref()
+        .map_or(0, |mentions| mentions.user_ids.len());
+    let score = antispam.tracker.score(
+        room.room_id().as_str(),
+        event.sender.as_str(),
+        body,
+        mentions,
+        Instant::now(),
+    );
+    let points = score.scaled(sensitivity);
+    let thresholds = antispam.thresholds;
+    if points == 0 {
+        return Ok(());
+    }
+
+    let mut actions = Vec::new();
+    if reached(thresholds.redact, points) {
+        match room.redact(&event.event_id, Some("Spam"), None).await {
+            Ok(_) => actions.push("redacted"),
+            Err(err) => warn!("Failed to redact spam: {err}"),
+        }
+    }
+    if reached(thresholds.kick, points) {
+        match room.kick_user(&event.sender, Some("Spam")).await {
+            Ok(()) => actions.push("kicked"),
+            Err(err) => warn!("Failed to kick {}: {err}", event.sender),
+        }
+    } else if reached(thresholds.mute, points) {
+        match mute(&room, &event.sender).await {
+            Ok(()) => actions.push("muted"),
+            Err(err) => warn!("Failed to mute {}: {err}", event.sender),
+        }
+    }
+    if actions.is_empty() && !reached(thresholds.report, points) {
+        return Ok(());
+    }
+
+    info!(
+        points,
+        user = event.sender.as_str(),
+        actions = ?actions,
+        "Caught spam"
+    );
+    let Some(admin_room) = room.client().get_room(&antispam.admin_room) else {
+        return Ok(());
+    };
+    let mut quote: String = body.chars().take(QUOTE_LENGTH).collect();
+    if quote.len() < body.len() {
+        quote.push('…');
+    }
+    let mut text = format!(
+        "🚨 Spam score {points} from {} in {}: {}.",
+        event.sender,
+        room.room_id(),
+        score.reasons.join(", ")
+    );
+    if !actions.is_empty() {
+        text += &format!(" I {} them.", actions.join(" and "));
+    }
+    let formatted = format!(
+        "{}<blockquote>{}</blockquote>",
+        html::escape(&text),
+        html::escape(&quote)
+    );
+    text += &format!("\n> {quote}");
+    send_or_log_error(
+        &admin_room,
+        RoomMessageEventContent::notice_html(text, formatted),
+    )
+    .await;
+    Ok(())
+}
+
+/// Lower a user's power level so that they can't talk, and tell them why.
+async fn mute(room: &Room, user_id: &UserId) -> anyhow::Result<()> {
+    room.update_power_levels(vec![(user_id, Int::from(MUTED_POWER_LEVEL))])
+        .await?;
+    let text = format!("{user_id}, {MUTED}");
+    let formatted = format!("{}, {MUTED}", html::user_pill(user_id));
+    send_or_log_error(room, RoomMessageEventContent::notice_html(text, formatted)).await;
+    Ok(())
+}
+
+/// Remember when people join protected rooms, to spot new members posting
+/// spam and waves of joins.
+#[instrument(skip_all, fields(room = room.room_id().as_str(), user = event.state_key.as_str()))]
+pub async fn on_room_member(
+    event: OriginalSyncRoomMemberEvent,
+    room: Room,
+    antispam: Ctx<Antispam>,
+) -> anyhow::Result<()> {
+    if event.content.membership != MembershipState::Join {
+        return Ok(());
+    }
+    // Profile changes are joins too, but only to a room they're already in
+    let was_joined = event
+        .unsigned
+        .prev_content
+        .as_ref()
+        .is_some_and(|prev| prev.membership == MembershipState::Join);
+    if was_joined
+        || antispam
+            .protected
+            .sensitivity(room.room_id().as_str())?
+            .is_none()
+    {
+        return Ok(());
+    }
+    antispam.tracker.joined(
+        room.room_id().as_str(),
+        event.state_key.as_str(),
+        Instant::now(),
+    );
+    Ok(())
+}
+
+async fn command(
+    args: &str,
+    client: &Client,
+    protected: &ProtectedRooms,
+) -> anyhow::Result<String> {
+    let mut words = args.split_whitespace();
+    let subcommand = words.next().unwrap_or_default();
+
+    if subcommand == "rooms" {
+        let rooms = protected.rooms()?;
+        if rooms.is_empty() {
+            return Ok("No rooms are protected.".to_owned());
+        }
+        return Ok(rooms
+            .iter()
+            .map(|(room_id, sensitivity)| format!("{room_id}: {sensitivity}% sensitivity"))
+            .collect::<Vec<_>>()
+            .join("\n"));
+    }
+
+    if !matches!(subcommand, "protect" | "unprotect" | "sensitivity") {
+        return Ok(HELP.to_owned());
+    }
+    let Some(room) = words.next() else {
+        return Ok(HELP.to_owned());
+    };
+    let room_id = match resolve_room(client, room).await {
+        Ok(room_id) => room_id,
+        Err(_) => return Ok(format!("I couldn't find the room {room}.")),
+    };
+
+    Ok(match subcommand {
+        "protect" => {
+            let joined = client
+                .get_room(&room_id)
+                .is_some_and(|room| room.state() == RoomState::Joined);
+            if !joined {
+                format!("I need to be in {room} to protect it.")
+            } else if protected.protect(room_id.as_str())? {
+                format!("Now protecting {room}.")
+            } else {
+                format!("{room} is already protected.")
+            }
+        }
+        "unprotect" => {
+            if protected.unprotect(room_id.as_str())? {
+                format!("No longer protecting {room}.")
+            } else {
+                format!("{room} isn't protected.")
+            }
+        }
+        "sensitivity" => {
+            let sensitivity = match words.next() {
+                Some("low") => 50,
+                Some("normal") => 100,
+                Some("high") => 150,
+                Some(n) => match n.trim_end_matches('%').parse() {
+                    Ok(n) if (10..=500).contains(&n) => n,
+                    _ => return Ok(SENSITIVITY_HELP.to_owned()),
+                },
+                None => match protected.sensitivity(room_id.as_str())? {
+                    Some(sensitivity) => {
+                        return Ok(format!("{room} has {sensitivity}% sensitivity."))
+                    }
+                    None => return Ok(format!("{room} isn't protected.")),
+                },
+            };
+            if protected.set_sensitivity(room_id.as_str(), sensitivity)? {
+                format!("{room} now has {sensitivity}% sensitivity.")
+            } else {
+                format!("{room} isn't protected.")
+            }
+        }
+        _ => unreachable!(),
+    })
+}
@@ -0,0 +1,72 @@
+mod handlers;
+mod protected;
+mod score;
+
+use clap::Parser;
+use handlers::{on_room_member, on_room_message, Antispam, Thresholds};
+use matrix_bot_core::{AccountConfig, Bot};
+use matrix_sdk::ruma::OwnedRoomId;
+use protected::ProtectedRooms;
+use score::Tracker;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The room to take commands from and post reports to
+    #[arg(long, env = "ANTISPAM_ADMIN_ROOM")]
+    pub admin_room: OwnedRoomId,
+
+    /// The spam score at which to report a message to the admin room, or 0 to never
+    #[arg(long, default_value_t = 40, env = "ANTISPAM_REPORT_AT")]
+    pub report_at: u32,
+
+    /// The spam score at which to redact a message, or 0 to never
+    #[arg(long, default_value_t = 60, env = "ANTISPAM_REDACT_AT")]
+    pub redact_at: u32,
+
+    /// The spam score at which to mute and warn the sender, or 0 to never
+    #[arg(long, default_value_t = 80, env = "ANTISPAM_MUTE_AT")]
+    pub mute_at: u32,
+
+    /// The spam score at which to kick the sender, or 0 to never
+    #[arg(long, default_value_t = 120, env = "ANTISPAM_KICK_AT")]
+    pub kick_at: u32,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-antispam", config.account_config).await?;
+    let protected = ProtectedRooms::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new events.
+    bot.client().add_event_handler_context(Antispam {
+        protected,
+        tracker: Tracker::default(),
+        thresholds: Thresholds {
+            report: config.report_at,
+            redact: config.redact_at,
+            mute: config.mute_at,
+            kick: config.kick_at,
+        },
+        admin_room: config.admin_room,
+    });
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_room_member);
+
+    bot.run().await
+}
@@ -0,0 +1,78 @@
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE rooms (
+        room_id TEXT PRIMARY KEY,
+        sensitivity INTEGER NOT NULL
+    );"];
+
+/// The sensitivity rooms start with, as a percentage.
+pub const DEFAULT_SENSITIVITY: u32 = 100;
+
+/// Protected rooms and how sensitive the bot is in each, persisted in
+/// SQLite.
+#[derive(Clone)]
+pub struct ProtectedRooms {
+    store: Store,
+}
+
+impl ProtectedRooms {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// The sensitivity of a protected room, or `None` if it isn't protected.
+    pub fn sensitivity(&self, room_id: &str) -> anyhow::Result<Option<u32>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT sensitivity FROM rooms WHERE room_id = ?1",
+                [room_id],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+
+    /// Protected rooms and their sensitivities.
+    pub fn rooms(&self) -> anyhow::Result<Vec<(String, u32)>> {
+        self.store.with(|conn| {
+            let mut statement =
+                conn.prepare("SELECT room_id, sensitivity FROM rooms ORDER BY room_id")?;
+            let rooms = statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?;
+            Ok(rooms)
+        })
+    }
+
+    /// Protect a room, returning whether it wasn't already.
+    pub fn protect(&self, room_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let added = conn.execute(
+                "INSERT OR IGNORE INTO rooms (room_id, sensitivity) VALUES (?1, ?2)",
+                params![room_id, DEFAULT_SENSITIVITY],
+            )?;
+            Ok(added > 0)
+        })
+    }
+
+    pub fn unprotect(&self, room_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute("DELETE FROM rooms WHERE room_id = ?1", [room_id])?;
+            Ok(removed > 0)
+        })
+    }
+
+    /// Set a protected room's sensitivity, returning whether it's protected.
+    pub fn set_sensitivity(&self, room_id: &str, sensitivity: u32) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let updated = conn.execute(
+                "UPDATE rooms SET sensitivity = ?2 WHERE room_id = ?1",
+                params![room_id, sensitivity],
+            )?;
+            Ok(updated > 0)
+        })
+    }
+}
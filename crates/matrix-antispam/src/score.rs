@@ -0,0 +1,221 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\bhttps?://\S+").unwrap());
+
+/// Links to chat invites, which spammers use to pull people elsewhere.
+static INVITE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(?:discord\.gg/|discord(?:app)?\.com/invite/|t\.me/|telegram\.(?:me|dog)/|chat\.whatsapp\.com/|matrix\.to/#/[#!])\S+",
+    )
+    .unwrap()
+});
+
+static MENTION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"@[a-z0-9._=\-/+]+:[a-zA-Z0-9.\-]+").unwrap());
+
+/// How long messages are remembered to spot duplicates and floods.
+const HISTORY: Duration = Duration::from_secs(120);
+
+/// The window in which too many messages count as a flood.
+const FLOOD_WINDOW: Duration = Duration::from_secs(10);
+const FLOOD_MESSAGES: usize = 5;
+
+/// How long someone counts as a new member after joining.
+const NEW_MEMBER: Duration = Duration::from_secs(600);
+
+/// How many joins within [`JOIN_BURST_WINDOW`] count as a burst.
+const JOIN_BURST: usize = 5;
+const JOIN_BURST_WINDOW: Duration = Duration::from_secs(60);
+
+/// Mentions beyond this many count as a mass ping.
+const MENTION_LIMIT: usize = 4;
+
+/// Why a message looks like spam, and how much.
+#[derive(Debug, Default)]
+pub struct Score {
+    pub points: u32,
+    pub reasons: Vec<String>,
+}
+
+impl Score {
+    fn add(&mut self, points: u32, reason: impl Into<String>) {
+        self.points += points;
+        self.reasons.push(reason.into());
+    }
+
+    /// Scale the score by a room's sensitivity, as a percentage.
+    pub fn scaled(&self, sensitivity: u32) -> u32 {
+        self.points * sensitivity / 100
+    }
+}
+
+struct Seen {
+    at: Instant,
+    room_id: String,
+    hash: u64,
+}
+
+#[derive(Default)]
+struct State {
+    /// Recent messages by each user.
+    messages: HashMap<String, VecDeque<Seen>>,
+    /// Recent joins to each room.
+    joins: HashMap<String, VecDeque<(Instant, String)>>,
+}
+
+/// Recent activity in the protected rooms, kept in memory to score new
+/// messages against.
+#[derive(Clone, Default)]
+pub struct Tracker {
+    state: Arc<Mutex<State>>,
+}
+
+impl Tracker {
+    pub fn joined(&self, room_id: &str, user_id: &str, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let joins = state.joins.entry(room_id.to_owned()).or_default();
+        while joins
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > NEW_MEMBER)
+        {
+            joins.pop_front();
+        }
+        joins.push_back((now, user_id.to_owned()));
+    }
+
+    /// Score a message and remember it for scoring later ones.
+    pub fn score(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        body: &str,
+        mentions: usize,
+        now: Instant,
+    ) -> Score {
+        let mut score = content_score(body, mentions);
+        let mut state = self.state.lock().unwrap();
+
+        let hash = normalized_hash(body);
+        let history = state.messages.entry(user_id.to_owned()).or_default();
+        while history
+            .front()
+            .is_some_and(|seen| now.duration_since(seen.at) > HISTORY)
+        {
+            history.pop_front();
+        }
+        let duplicates: Vec<_> = history.iter().filter(|seen| seen.hash == hash).collect();
+        if !duplicates.is_empty() && !body.trim().is_empty() {
+            let rooms: HashSet<_> = duplicates.iter().map(|seen| &seen.room_id).collect();
+            if rooms.iter().any(|other| *other != room_id) {
+                score.add(30, "the same message in several rooms");
+            }
+            match duplicates.len() {
+                1 => score.add(20, "a repeated message"),
+                n => score.add(40, format!("the same message {} times", n + 1)),
+            }
+        }
+        let recent = history
+            .iter()
+            .filter(|seen| now.duration_since(seen.at) <= FLOOD_WINDOW)
+            .count();
+        if recent + 1 >= FLOOD_MESSAGES {
+            score.add(
+                20,
+                format!("{} messages in {}s", recent + 1, FLOOD_WINDOW.as_secs()),
+            );
+        }
+        history.push_back(Seen {
+            at: now,
+            room_id: room_id.to_owned(),
+            hash,
+        });
+
+        if let Some(joins) = state.joins.get(room_id) {
+            if let Some((joined_at, _)) = joins.iter().rev().find(|(_, user)| user == user_id) {
+                if now.duration_since(*joined_at) <= NEW_MEMBER {
+                    let burst = joins
+                        .iter()
+                        .filter(|(at, _)| {
+                            at.max(joined_at).duration_since(*at.min(joined_at))
+                                <= JOIN_BURST_WINDOW
+                        })
+                        .count();
+                    if burst >= JOIN_BURST {
+                        score.add(25, format!("joined with {} others at once", burst - 1));
+                    } else {
+                        score.add(15, "joined recently");
+                    }
+                }
+            }
+        }
+
+        // Forget people who have gone quiet
+        state.messages.retain(|_, history| {
+            history
+                .back()
+                .is_some_and(|seen| now.duration_since(seen.at) <= HISTORY)
+        });
+        score
+    }
+}
+
+/// Score what a message says, regardless of who sent it.
+fn content_score(body: &str, mentions: usize) -> Score {
+    let mut score = Score::default();
+
+    let links = LINK.find_iter(body).count();
+    if links > 0 {
+        let words = body.split_whitespace().count().max(1);
+        if links > 2 {
+            score.add(10 * (links as u32 - 2).min(5), format!("{links} links"));
+        }
+        if links * 3 >= words {
+            score.add(15, "mostly links");
+        }
+    }
+
+    let invites = INVITE.find_iter(body).count();
+    if invites > 0 {
+        score.add(
+            25 * invites.min(2) as u32,
+            match invites {
+                1 => "a chat invite link".to_owned(),
+                n => format!("{n} chat invite links"),
+            },
+        );
+    }
+
+    let mentions = mentions.max(MENTION.find_iter(body).count());
+    if mentions > MENTION_LIMIT {
+        score.add(
+            20 + 5 * (mentions - MENTION_LIMIT).min(10) as u32,
+            format!("{mentions} mentions"),
+        );
+    }
+    if body.contains("@room") || body.contains("@everyone") {
+        score.add(15, "a room-wide ping");
+    }
+
+    score
+}
+
+/// Hash a message ignoring case, spacing and numbers, which spammers vary
+/// to slip past exact matches.
+fn normalized_hash(body: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for word in body.split_whitespace() {
+        word.chars()
+            .filter(|c| !c.is_ascii_digit())
+            .flat_map(char::to_lowercase)
+            .for_each(|c| c.hash(&mut hasher));
+        ' '.hash(&mut hasher);
+    }
+    hasher.finish()
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The archive format this version writes, and the only one it reads.
+pub const VERSION: u32 = 1;
+
+/// How many servers to try joining a room through.
+const MAX_VIA: usize = 3;
+
+/// Account data that is tied to the old account's keys or managed by the
+/// server, and would do harm or be rejected if restored.
+const UNRESTORABLE_PREFIXES: &[&str] = &[
+    "m.secret_storage.",
+    "m.cross_signing.",
+    "m.megolm_backup.",
+    "m.push_rules",
+    "m.fully_read",
+];
+
+/// Everything exported from an account, as plain JSON events so it survives
+/// changes to the SDK.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    pub user_id: String,
+    /// When the archive was written, in seconds since the Unix epoch.
+    pub exported_at: u64,
+    /// The content of each type of global account data.
+    pub account_data: BTreeMap<String, Value>,
+    pub rooms: Vec<RoomArchive>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomArchive {
+    pub room_id: String,
+    /// The current state events of the room.
+    pub state: Vec<Value>,
+    /// The content of each type of the account's data for the room.
+    pub account_data: BTreeMap<String, Value>,
+    /// The latest messages, oldest first, if history was exported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<Value>,
+}
+
+impl Archive {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read archive {}", path.display()))?;
+        let archive: Self = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse archive {}", path.display()))?;
+        if archive.version != VERSION {
+            bail!(
+                "archive {} is version {}, but only version {VERSION} is supported",
+                path.display(),
+                archive.version
+            );
+        }
+        Ok(archive)
+    }
+
+    /// Write the archive next to `path` first, so a failed export never
+    /// clobbers a good one.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        std::fs::write(&partial, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("failed to write archive {}", path.display()))?;
+        std::fs::rename(&partial, path)
+            .with_context(|| format!("failed to write archive {}", path.display()))?;
+        Ok(())
+    }
+}
+
+impl RoomArchive {
+    /// The room's canonical alias, if it has one.
+    pub fn alias(&self) -> Option<&str> {
+        self.state
+            .iter()
+            .find(|event| event["type"] == "m.room.canonical_alias")
+            .and_then(|event| event["content"]["alias"].as_str())
+    }
+
+    /// The servers of the room's joined members, most common first, to join
+    /// the room through.
+    pub fn via_servers(&self) -> Vec<String> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for event in &self.state {
+            if event["type"] != "m.room.member" || event["content"]["membership"] != "join" {
+                continue;
+            }
+            let server = event["state_key"]
+                .as_str()
+                .and_then(|user_id| user_id.split_once(':'))
+                .map(|(_, server)| server);
+            if let Some(server) = server {
+                *counts.entry(server).or_default() += 1;
+            }
+        }
+        let mut servers: Vec<(&str, usize)> = counts.into_iter().collect();
+        servers.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        servers
+            .into_iter()
+            .take(MAX_VIA)
+            .map(|(server, _)| server.to_owned())
+            .collect()
+    }
+}
+
+/// Key account data events by their type.
+pub fn by_type(events: impl IntoIterator<Item = Value>) -> BTreeMap<String, Value> {
+    events
+        .into_iter()
+        .filter_map(|mut event| {
+            let event_type = event["type"].as_str()?.to_owned();
+            Some((event_type, event["content"].take()))
+        })
+        .collect()
+}
+
+/// The room state from a sync, updated with any state events in the
+/// timeline, which come after it.
+pub fn merge_state(state: Vec<Value>, timeline: Vec<Value>) -> Vec<Value> {
+    let mut merged: BTreeMap<(String, String), Value> = BTreeMap::new();
+    for event in state.into_iter().chain(timeline) {
+        let (Some(event_type), Some(state_key)) =
+            (event["type"].as_str(), event["state_key"].as_str())
+        else {
+            continue;
+        };
+        merged.insert((event_type.to_owned(), state_key.to_owned()), event);
+    }
+    merged.into_values().collect()
+}
+
+/// Whether a type of account data can be restored on another account.
+pub fn restorable(event_type: &str) -> bool {
+    !UNRESTORABLE_PREFIXES
+        .iter()
+        .any(|prefix| event_type.starts_with(prefix))
+}
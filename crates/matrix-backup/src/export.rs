@@ -0,0 +1,113 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use matrix_sdk::{config::SyncSettings, room::MessagesOptions, Client, Room};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::archive::{self, Archive, RoomArchive, VERSION};
+
+/// Export the account to an archive at `output`, with up to `history` of
+/// the latest messages in each room.
+///
+/// Messages in encrypted rooms are saved as they are, still encrypted, as
+/// this session has no keys for them.
+pub async fn run(client: &Client, output: &Path, history: usize) -> anyhow::Result<()> {
+    info!("Syncing the account…");
+    // With no token and no filter, this is a full sync with all the state
+    let response = client.sync_once(SyncSettings::default()).await?;
+
+    let user_id = client.user_id().context("not logged in")?.to_string();
+    let account_data = archive::by_type(
+        response
+            .account_data
+            .iter()
+            .filter_map(|event| event.deserialize_as::<Value>().ok()),
+    );
+
+    let mut rooms = Vec::new();
+    for (room_id, update) in response.rooms.join {
+        let state = update
+            .state
+            .iter()
+            .filter_map(|event| event.deserialize_as::<Value>().ok())
+            .collect();
+        let timeline = update
+            .timeline
+            .events
+            .into_iter()
+            .filter_map(|event| event.into_raw().deserialize_as::<Value>().ok())
+            .collect();
+        let account_data = archive::by_type(
+            update
+                .account_data
+                .iter()
+                .filter_map(|event| event.deserialize_as::<Value>().ok()),
+        );
+
+        let messages = match client.get_room(&room_id) {
+            Some(room) if history > 0 => match messages(&room, history).await {
+                Ok(messages) => messages,
+                Err(err) => {
+                    warn!(room = room_id.as_str(), "Failed to export history: {err:#}");
+                    Vec::new()
+                }
+            },
+            _ => Vec::new(),
+        };
+
+        rooms.push(RoomArchive {
+            room_id: room_id.to_string(),
+            state: archive::merge_state(state, timeline),
+            account_data,
+            messages,
+        });
+    }
+
+    let archive = Archive {
+        version: VERSION,
+        user_id,
+        exported_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        account_data,
+        rooms,
+    };
+    archive.save(output)?;
+    info!(
+        "Exported {} rooms and {} types of account data to {}",
+        archive.rooms.len(),
+        archive.account_data.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+/// Up to `limit` of the latest events in a room, oldest first.
+async fn messages(room: &Room, limit: usize) -> anyhow::Result<Vec<Value>> {
+    let mut events = Vec::new();
+    let mut options = MessagesOptions::backward();
+    while events.len() < limit {
+        let messages = room.messages(options).await?;
+        if messages.chunk.is_empty() {
+            break;
+        }
+        events.extend(
+            messages
+                .chunk
+                .iter()
+                .filter_map(|event| event.raw().deserialize_as::<Value>().ok()),
+        );
+        let Some(end) = messages.end else {
+            break;
+        };
+        options = MessagesOptions::backward().from(Some(end.as_str()));
+    }
+    events.truncate(limit);
+    events.reverse();
+    Ok(events)
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+
+use matrix_sdk::{
+    ruma::{
+        events::{GlobalAccountDataEventType, RoomAccountDataEventType},
+        serde::Raw,
+        OwnedServerName, RoomOrAliasId,
+    },
+    Client,
+};
+use tracing::{info, warn};
+
+use crate::archive::{self, Archive, RoomArchive};
+
+/// Restore the account data in the archive at `path` onto the account, and
+/// rejoin its rooms if `join` is set.
+///
+/// Each part is restored on its own, so one failing doesn't stop the rest.
+pub async fn run(client: &Client, path: &Path, join: bool) -> anyhow::Result<()> {
+    let archive = Archive::load(path)?;
+    info!(
+        "Restoring the backup of {} taken at {}",
+        archive.user_id, archive.exported_at
+    );
+
+    let mut restored = 0;
+    let mut failures = Vec::new();
+    for (event_type, content) in &archive.account_data {
+        if !archive::restorable(event_type) {
+            continue;
+        }
+        let result = async {
+            client
+                .account()
+                .set_account_data_raw(
+                    GlobalAccountDataEventType::from(event_type.as_str()),
+                    Raw::new(content)?.cast(),
+                )
+                .await?;
+            anyhow::Ok(())
+        }
+        .await;
+        match result {
+            Ok(()) => restored += 1,
+            Err(err) => {
+                warn!("Failed to restore {event_type}: {err:#}");
+                failures.push(event_type.clone());
+            }
+        }
+    }
+    info!("Restored {restored} types of account data");
+
+    if join {
+        let mut joined = 0;
+        for room in &archive.rooms {
+            match rejoin(client, room).await {
+                Ok(()) => joined += 1,
+                Err(err) => {
+                    warn!(room = room.room_id.as_str(), "Failed to rejoin: {err:#}");
+                    failures.push(room.room_id.clone());
+                }
+            }
+        }
+        info!("Rejoined {joined} of {} rooms", archive.rooms.len());
+    }
+
+    if failures.is_empty() {
+        info!("Everything was restored");
+    } else {
+        warn!("Couldn't restore: {}", failures.join(", "));
+    }
+    Ok(())
+}
+
+/// Join a room through the servers its members are on, then restore the
+/// account's data for it.
+async fn rejoin(client: &Client, room: &RoomArchive) -> anyhow::Result<()> {
+    let room_id = <&RoomOrAliasId>::try_from(room.room_id.as_str())?;
+    let via: Vec<OwnedServerName> = room
+        .via_servers()
+        .iter()
+        .filter_map(|server| server.as_str().try_into().ok())
+        .collect();
+    let joined = match client.join_room_by_id_or_alias(room_id, &via).await {
+        Ok(joined) => joined,
+        // The servers its members were on may be gone, but the alias can
+        // still lead to the room
+        Err(err) => match room.alias() {
+            Some(alias) => {
+                let alias = <&RoomOrAliasId>::try_from(alias)?;
+                client.join_room_by_id_or_alias(alias, &via).await?
+            }
+            None => return Err(err.into()),
+        },
+    };
+    info!(room = room.room_id.as_str(), "Rejoined");
+
+    for (event_type, content) in &room.account_data {
+        if !archive::restorable(event_type) {
+            continue;
+        }
+        joined
+            .set_account_data_raw(
+                RoomAccountDataEventType::from(event_type.as_str()),
+                Raw::new(content)?.cast(),
+            )
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+mod archive;
+mod export;
+mod import;
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use matrix_bot_core::AccountConfig;
+use matrix_sdk::Client;
+use tracing::{info, warn};
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    #[command(subcommand)]
+    pub command: Command,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Save the account's joined rooms, their state and the account data to
+    /// an archive
+    Export {
+        /// The file to write the archive to
+        output: PathBuf,
+
+        /// How many of the latest messages in each room to save as well
+        #[arg(long, default_value_t = 0, env = "BACKUP_HISTORY")]
+        history: usize,
+    },
+    /// Restore the account data from an archive and rejoin its rooms, on
+    /// the same account or a new one
+    Import {
+        /// The archive to restore from
+        archive: PathBuf,
+
+        /// Only restore the account data, without rejoining any rooms
+        #[arg(long)]
+        no_join: bool,
+    },
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let client = login(&config.account_config).await?;
+    let result = match config.command {
+        Command::Export { output, history } => export::run(&client, &output, history).await,
+        Command::Import { archive, no_join } => import::run(&client, &archive, !no_join).await,
+    };
+
+    // Don't leave a device behind for each run
+    if let Err(err) = client.matrix_auth().logout().await {
+        warn!("Failed to log out: {err}");
+    }
+    result
+}
+
+/// Log in with a throwaway session.
+///
+/// Unlike the bots, nothing is persisted: each run needs a full sync from
+/// scratch to export everything, and an import is usually for a different
+/// account than the last run.
+async fn login(config: &AccountConfig) -> anyhow::Result<Client> {
+    let client = Client::builder()
+        .homeserver_url(&config.server)
+        .build()
+        .await?;
+    let device_name = config.device_name.as_deref().unwrap_or("matrix-backup");
+    client
+        .matrix_auth()
+        .login_username(&config.username, &config.password())
+        .initial_device_display_name(device_name)
+        .await?;
+    info!("Logged in as {}", config.username);
+    Ok(client)
+}
@@ -0,0 +1,230 @@
+//! Reporting people who misuse the bot, like flooding it with commands or
+//! using it to harass someone.
+//!
+//! Bots decide what misuse is and [`Abuse::record`] each time they see it.
+//! Once someone has misused the bot the configured number of times within
+//! the window, in the same way and room, the bot tells the abuse room if
+//! there is one, files a report against the latest event with the
+//! homeserver's report API if asked to, and fires the
+//! [`ABUSE`](crate::egress::ABUSE) activity. Then counting starts again.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use matrix_sdk::{
+    ruma::{
+        events::room::message::RoomMessageEventContent, EventId, OwnedEventId, OwnedRoomId,
+        OwnedRoomOrAliasId, OwnedUserId, RoomOrAliasId, UserId,
+    },
+    Client, Room,
+};
+use tracing::{info, warn};
+
+use crate::{
+    egress::{self, Egress},
+    format_duration, html, parse_duration, rooms, send_or_log_error,
+};
+
+#[derive(Parser, Debug, Clone)]
+pub struct AbuseConfig {
+    /// A room to tell about people misusing the bot, like the moderators',
+    /// by ID or alias
+    #[arg(long, env = "BOT_ABUSE_ROOM")]
+    pub abuse_room: Option<OwnedRoomOrAliasId>,
+    /// Also report people misusing the bot to the homeserver's admins
+    #[arg(long, env = "BOT_ABUSE_REPORT")]
+    pub abuse_report: bool,
+    /// How many times someone can misuse the bot in the window before
+    /// they're reported
+    #[arg(long, default_value_t = 5, env = "BOT_ABUSE_THRESHOLD")]
+    pub abuse_threshold: usize,
+    /// The window misuse is counted over
+    #[arg(long, default_value = "10m", value_parser = parse_window, env = "BOT_ABUSE_WINDOW")]
+    pub abuse_window: Duration,
+}
+
+fn parse_window(window: &str) -> Result<Duration, String> {
+    parse_duration(window).ok_or_else(|| format!("invalid abuse window: {window}"))
+}
+
+/// Who misused the bot, where, how and against whom.
+type Key = (OwnedRoomId, OwnedUserId, &'static str, Option<OwnedUserId>);
+
+/// Counts misuse and reports it. Cloning it shares the counts.
+#[derive(Clone)]
+pub struct Abuse {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: AbuseConfig,
+    egress: Egress,
+    /// When each kind of misuse happened, oldest first.
+    seen: Mutex<HashMap<Key, VecDeque<Instant>>>,
+}
+
+impl Abuse {
+    pub fn new(config: AbuseConfig, egress: Egress) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                egress,
+                seen: Default::default(),
+            }),
+        }
+    }
+
+    /// Count misuse of the `kind` the bot names, like `"flooding"`, by
+    /// `sender` with `event_id`, and if it's against someone, who. Reporting
+    /// happens in the background.
+    pub fn record(
+        &self,
+        room: &Room,
+        event_id: &EventId,
+        sender: &UserId,
+        kind: &'static str,
+        against: Option<&UserId>,
+    ) {
+        let config = &self.inner.config;
+        let key = (
+            room.room_id().to_owned(),
+            sender.to_owned(),
+            kind,
+            against.map(ToOwned::to_owned),
+        );
+        {
+            let mut seen = self.inner.seen.lock().unwrap();
+            // Forget anyone who's stopped
+            seen.retain(|_, times| {
+                while times
+                    .front()
+                    .is_some_and(|at| at.elapsed() > config.abuse_window)
+                {
+                    times.pop_front();
+                }
+                !times.is_empty()
+            });
+            let times = seen.entry(key).or_default();
+            times.push_back(Instant::now());
+            if times.len() < config.abuse_threshold {
+                return;
+            }
+            times.clear();
+        }
+
+        let report = Report {
+            room_id: room.room_id().to_owned(),
+            event_id: event_id.to_owned(),
+            sender: sender.to_owned(),
+            against: against.map(ToOwned::to_owned),
+            reason: reason(config, sender, kind, against),
+        };
+        info!(
+            room = room.room_id().as_str(),
+            sender = sender.as_str(),
+            kind,
+            "Someone is misusing the bot"
+        );
+        metrics::counter!("bot_abuse_reports_total", "kind" => kind).increment(1);
+        self.inner.egress.notify(
+            egress::ABUSE,
+            Some(room.room_id()),
+            serde_json::json!({
+                "sender": report.sender,
+                "event_id": report.event_id,
+                "kind": kind,
+                "against": report.against,
+                "count": config.abuse_threshold,
+            }),
+        );
+        tokio::spawn(report.file(room.clone(), self.inner.clone()));
+    }
+}
+
+struct Report {
+    room_id: OwnedRoomId,
+    event_id: OwnedEventId,
+    sender: OwnedUserId,
+    against: Option<OwnedUserId>,
+    reason: String,
+}
+
+impl Report {
+    async fn file(self, room: Room, inner: Arc<Inner>) {
+        let config = &inner.config;
+        if config.abuse_report {
+            match room
+                .report_content(self.event_id.clone(), None, Some(self.reason.clone()))
+                .await
+            {
+                Ok(_) => info!(
+                    event = self.event_id.as_str(),
+                    "Reported misuse to the homeserver"
+                ),
+                Err(err) => warn!(
+                    event = self.event_id.as_str(),
+                    "Failed to report misuse: {err}"
+                ),
+            }
+        }
+        if let Some(abuse_room) = &config.abuse_room {
+            self.tell(&room.client(), abuse_room, config.abuse_report)
+                .await;
+        }
+    }
+
+    /// Let the abuse room know.
+    async fn tell(&self, client: &Client, abuse_room: &RoomOrAliasId, reported: bool) {
+        let abuse_room_id = match rooms::resolve(client, abuse_room).await {
+            Ok(room_id) => room_id,
+            Err(err) => {
+                warn!(
+                    room = abuse_room.as_str(),
+                    "Failed to resolve the abuse room: {err:#}"
+                );
+                return;
+            }
+        };
+        let Some(abuse_room) = client.get_room(&abuse_room_id) else {
+            warn!(
+                room = abuse_room.as_str(),
+                "The abuse room isn't one the bot is in"
+            );
+            return;
+        };
+        let link = self
+            .room_id
+            .matrix_to_event_uri(self.event_id.clone())
+            .to_string();
+        let reported = if reported {
+            " I've reported it to the homeserver's admins."
+        } else {
+            ""
+        };
+        let content = RoomMessageEventContent::notice_html(
+            format!("{} {link}{reported}", self.reason),
+            format!(
+                "{} <a href=\"{}\">Latest message</a>.{}",
+                html::escape(&self.reason),
+                html::escape(&link),
+                reported
+            ),
+        );
+        send_or_log_error(&abuse_room, content).await;
+    }
+}
+
+fn reason(config: &AbuseConfig, sender: &UserId, kind: &str, against: Option<&UserId>) -> String {
+    let against = against
+        .map(|against| format!(" ({against})"))
+        .unwrap_or_default();
+    format!(
+        "{sender} misused the bot: {kind}{against}, {} times in {}.",
+        config.abuse_threshold,
+        format_duration(config.abuse_window)
+    )
+}
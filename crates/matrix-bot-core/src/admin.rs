@@ -0,0 +1,234 @@
+//! An HTTP API for controlling a running bot, for dashboards and scripts
+//! that would rather not send Matrix commands.
+//!
+//! It's served under `/admin` on the bot's [`HttpServer`](crate::http::HttpServer),
+//! or on [`AccountConfig::http_listen`](crate::AccountConfig::http_listen)
+//! for bots without one, when [`AccountConfig::admin_token`](crate::AccountConfig::admin_token)
+//! is set, and every request needs that token as
+//! `Authorization: Bearer <token>`. Bodies and responses are JSON. So the
+//! token isn't sent in the clear, the bot refuses to start if the API would
+//! be served over plain HTTP anywhere but a loopback address.
+//!
+//! Rooms can be given by ID or alias.
+//!
+//! - `GET /admin/rooms` lists the rooms the bot is in or invited to
+//! - `POST /admin/rooms/{room}/join` joins a room
+//! - `POST /admin/rooms/{room}/leave` leaves a room
+//! - `GET /admin/rooms/{room}/settings` lists a room's settings
+//! - `PUT /admin/rooms/{room}/settings/{key}` sets one to the JSON body
+//! - `DELETE /admin/rooms/{room}/settings/{key}` resets one to its default
+//! - `GET /admin/ignored` lists the users the bot ignores
+//! - `PUT /admin/ignored/{user}` and `DELETE /admin/ignored/{user}` ignore
+//!   and unignore someone
+//! - `POST /admin/shutdown` stops the bot gracefully
+
+use std::sync::{Arc, OnceLock};
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use matrix_sdk::{
+    ruma::{
+        events::ignored_user_list::IgnoredUserListEventContent, OwnedRoomOrAliasId, OwnedUserId,
+    },
+    Client,
+};
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::{http::constant_time_eq, rooms, store::Store};
+
+/// What the admin API works with.
+#[derive(Clone)]
+pub(crate) struct Admin {
+    pub(crate) client: Client,
+    /// The bot's store, once it's opened one, for room settings.
+    pub(crate) store: Arc<OnceLock<Store>>,
+    /// Notified to stop the bot.
+    pub(crate) stop: Arc<Notify>,
+}
+
+/// An error to send back, with the status to send it with.
+struct AdminError(StatusCode, String);
+
+impl From<anyhow::Error> for AdminError {
+    fn from(err: anyhow::Error) -> Self {
+        warn!("Admin API request failed: {err:#}");
+        Self(StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}"))
+    }
+}
+
+impl From<matrix_sdk::Error> for AdminError {
+    fn from(err: matrix_sdk::Error) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+type AdminResult = Result<Json<Value>, AdminError>;
+
+/// The admin routes, only answering requests with `token`.
+pub(crate) fn router(admin: Admin, token: String) -> Router {
+    let token: Arc<str> = token.into();
+    Router::new()
+        .route("/rooms", get(rooms))
+        .route("/rooms/:room/join", post(join))
+        .route("/rooms/:room/leave", post(leave))
+        .route("/rooms/:room/settings", get(settings))
+        .route(
+            "/rooms/:room/settings/:key",
+            put(set_setting).delete(remove_setting),
+        )
+        .route("/ignored", get(ignored))
+        .route("/ignored/:user", put(ignore).delete(unignore))
+        .route("/shutdown", post(shutdown))
+        .route_layer(middleware::from_fn(move |request: Request, next: Next| {
+            authenticate(token.clone(), request, next)
+        }))
+        .with_state(admin)
+}
+
+async fn authenticate(token: Arc<str>, request: Request, next: Next) -> Response {
+    let given = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !given.is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())) {
+        return AdminError(StatusCode::UNAUTHORIZED, "bad token".to_owned()).into_response();
+    }
+    next.run(request).await
+}
+
+fn not_found(what: &str) -> AdminError {
+    AdminError(StatusCode::NOT_FOUND, format!("no such {what}"))
+}
+
+async fn rooms(State(admin): State<Admin>) -> AdminResult {
+    let mut rooms = Vec::new();
+    for room in admin.client.rooms() {
+        rooms.push(json!({
+            "room_id": room.room_id(),
+            "name": room.name(),
+            "state": format!("{:?}", room.state()).to_lowercase(),
+            "direct": room.is_direct().await?,
+            "members": room.joined_members_count(),
+        }));
+    }
+    Ok(Json(rooms.into()))
+}
+
+async fn join(State(admin): State<Admin>, Path(room): Path<OwnedRoomOrAliasId>) -> AdminResult {
+    let joined = admin.client.join_room_by_id_or_alias(&room, &[]).await?;
+    info!(
+        room = joined.room_id().as_str(),
+        "Joined a room from the admin API"
+    );
+    Ok(Json(json!({ "room_id": joined.room_id() })))
+}
+
+async fn leave(State(admin): State<Admin>, Path(room): Path<OwnedRoomOrAliasId>) -> AdminResult {
+    let room_id = rooms::resolve(&admin.client, &room).await?;
+    let room = admin
+        .client
+        .get_room(&room_id)
+        .ok_or_else(|| not_found("room"))?;
+    room.leave().await?;
+    info!(room = room_id.as_str(), "Left a room from the admin API");
+    Ok(Json(json!({})))
+}
+
+fn store(admin: &Admin) -> Result<&Store, AdminError> {
+    admin.store.get().ok_or_else(|| {
+        AdminError(
+            StatusCode::NOT_IMPLEMENTED,
+            "this bot doesn't have room settings".to_owned(),
+        )
+    })
+}
+
+async fn settings(State(admin): State<Admin>, Path(room): Path<OwnedRoomOrAliasId>) -> AdminResult {
+    let room_id = rooms::resolve(&admin.client, &room).await?;
+    let settings: serde_json::Map<_, _> = store(&admin)?
+        .room_settings(&room_id)?
+        .into_iter()
+        .collect();
+    Ok(Json(settings.into()))
+}
+
+async fn set_setting(
+    State(admin): State<Admin>,
+    Path((room, key)): Path<(OwnedRoomOrAliasId, String)>,
+    Json(value): Json<Value>,
+) -> AdminResult {
+    let room_id = rooms::resolve(&admin.client, &room).await?;
+    store(&admin)?.set_room_setting(&room_id, &key, &value)?;
+    info!(
+        room = room_id.as_str(),
+        setting = key.as_str(),
+        "Changed a room setting from the admin API"
+    );
+    Ok(Json(value))
+}
+
+async fn remove_setting(
+    State(admin): State<Admin>,
+    Path((room, key)): Path<(OwnedRoomOrAliasId, String)>,
+) -> AdminResult {
+    let room_id = rooms::resolve(&admin.client, &room).await?;
+    if !store(&admin)?.remove_room_setting(&room_id, &key)? {
+        return Err(not_found("setting"));
+    }
+    info!(
+        room = room_id.as_str(),
+        setting = key.as_str(),
+        "Reset a room setting from the admin API"
+    );
+    Ok(Json(json!({})))
+}
+
+async fn ignored(State(admin): State<Admin>) -> AdminResult {
+    let ignored: Vec<OwnedUserId> = admin
+        .client
+        .account()
+        .account_data::<IgnoredUserListEventContent>()
+        .await?
+        .map(|raw| raw.deserialize())
+        .transpose()
+        .map_err(anyhow::Error::from)?
+        .map(|content| content.ignored_users.into_keys().collect())
+        .unwrap_or_default();
+    Ok(Json(json!(ignored)))
+}
+
+async fn ignore(State(admin): State<Admin>, Path(user_id): Path<OwnedUserId>) -> AdminResult {
+    admin.client.account().ignore_user(&user_id).await?;
+    info!(user = user_id.as_str(), "Ignored a user from the admin API");
+    Ok(Json(json!({})))
+}
+
+async fn unignore(State(admin): State<Admin>, Path(user_id): Path<OwnedUserId>) -> AdminResult {
+    admin.client.account().unignore_user(&user_id).await?;
+    info!(
+        user = user_id.as_str(),
+        "Unignored a user from the admin API"
+    );
+    Ok(Json(json!({})))
+}
+
+async fn shutdown(State(admin): State<Admin>) -> impl IntoResponse {
+    info!("Shutting down from the admin API");
+    admin.stop.notify_one();
+    (StatusCode::ACCEPTED, Json(json!({})))
+}
@@ -0,0 +1,50 @@
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::member::StrippedRoomMemberEvent, Client, Room,
+};
+use serde_json::json;
+use tokio::time::{sleep, Duration};
+use tracing::{error, info, instrument, warn};
+
+use crate::egress::{self, Egress};
+
+#[instrument(skip(egress), fields(room_member = room_member.state_key.as_str(), room = room.room_id().as_str(), client = client.user_id().map(|u| u.as_str()).unwrap_or("None")))]
+pub async fn on_stripped_state_member(
+    room_member: StrippedRoomMemberEvent,
+    client: Client,
+    room: Room,
+    egress: Ctx<Egress>,
+) {
+    if room_member.state_key != client.user_id().unwrap() {
+        return;
+    }
+    egress.notify(
+        egress::INVITE,
+        Some(room.room_id()),
+        json!({ "inviter": room_member.sender }),
+    );
+
+    tokio::spawn(async move {
+        info!("Autojoining room {}", room.room_id());
+        let mut delay = 2;
+
+        while let Err(err) = room.join().await {
+            // retry autojoin due to synapse sending invites, before the
+            // invited user can join for more information see
+            // https://github.com/matrix-org/synapse/issues/4345
+            warn!(
+                "Failed to join room {} ({err:?}), retrying in {delay}s",
+                room.room_id()
+            );
+
+            sleep(Duration::from_secs(delay)).await;
+            delay *= 2;
+
+            if delay > 3600 {
+                error!("Can't join room {} ({err:?})", room.room_id());
+                return;
+            }
+        }
+        info!("Successfully joined room {}", room.room_id());
+        egress.notify(egress::JOINED, Some(room.room_id()), json!({}));
+    });
+}
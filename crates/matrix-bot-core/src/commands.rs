@@ -0,0 +1,216 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use matrix_sdk::{
+    deserialized_responses::SyncTimelineEvent,
+    event_handler::Ctx,
+    ruma::{
+        events::{
+            room::message::{
+                sanitize::remove_plain_reply_fallback, MessageType, OriginalSyncRoomMessageEvent,
+                Relation, RoomMessageEventContent,
+            },
+            AnySyncMessageLikeEvent, AnySyncTimelineEvent, SyncMessageLikeEvent,
+        },
+        EventId, UserId,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, instrument, trace};
+
+use crate::{can_reply, html, origins, reply};
+
+/// The power level needed to manage a bot's per-room settings, matching the
+/// default for moderators.
+pub const MODERATOR_POWER_LEVEL: i64 = 50;
+
+/// Whether commands follow maubot's conventions, set from
+/// [`AccountConfig::maubot_compat`](crate::AccountConfig::maubot_compat).
+static MAUBOT_COMPAT: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_maubot_compat(enabled: bool) {
+    MAUBOT_COMPAT.store(enabled, Ordering::Relaxed);
+}
+
+/// If `body` is the bang command `command` (e.g. `!remind`), return its
+/// arguments.
+///
+/// In maubot compatibility mode, the command's name can be in any case, as
+/// maubot plugins accept.
+pub fn strip_command<'a>(body: &'a str, command: &str) -> Option<&'a str> {
+    let body = body.trim();
+    let args = if MAUBOT_COMPAT.load(Ordering::Relaxed) {
+        body.get(..command.len())
+            .filter(|name| name.eq_ignore_ascii_case(command))
+            .map(|_| &body[command.len()..])
+    } else {
+        body.strip_prefix(command)
+    };
+    args.filter(|args| args.is_empty() || args.starts_with(char::is_whitespace))
+        .map(str::trim)
+}
+
+/// A command for `!help` to list.
+#[derive(Debug, Clone)]
+struct HelpEntry {
+    usage: String,
+    description: String,
+}
+
+/// The commands a bot has, answering `!help` with all of them together,
+/// like maubot's help plugin. Cloning it shares them.
+#[derive(Clone, Default)]
+pub struct Help {
+    entries: Arc<Mutex<Vec<HelpEntry>>>,
+}
+
+impl Help {
+    /// List a command, e.g. `add("!remind <when> <what>", "Remind you")`.
+    pub fn add(&self, usage: impl Into<String>, description: impl Into<String>) {
+        self.entries.lock().unwrap().push(HelpEntry {
+            usage: usage.into(),
+            description: description.into(),
+        });
+    }
+
+    fn message(&self) -> RoomMessageEventContent {
+        let entries = self.entries.lock().unwrap();
+        if entries.is_empty() {
+            return RoomMessageEventContent::notice_plain("I don't have any commands.");
+        }
+        let mut plain = String::new();
+        let mut formatted = "<ul>".to_owned();
+        for entry in entries.iter() {
+            let _ = writeln!(plain, "{} - {}", entry.usage, entry.description);
+            let _ = write!(
+                formatted,
+                "<li><code>{}</code> - {}</li>",
+                html::escape(&entry.usage),
+                html::escape(&entry.description)
+            );
+        }
+        formatted.push_str("</ul>");
+        RoomMessageEventContent::notice_html(plain.trim_end(), formatted)
+    }
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub(crate) async fn on_help(event: OriginalSyncRoomMessageEvent, room: Room, help: Ctx<Help>) {
+    if room.state() != RoomState::Joined {
+        return;
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return;
+    }
+    let Some(body) = text_body(&event) else {
+        return;
+    };
+    if strip_command(body, "!help").is_none() || !can_reply(&room).await {
+        return;
+    }
+    reply(&room, &event, help.message()).await;
+}
+
+/// The text of a text message without its reply fallback, or `None` for
+/// other kinds of message.
+pub fn text_body(event: &OriginalSyncRoomMessageEvent) -> Option<&str> {
+    match &event.content.msgtype {
+        MessageType::Text(text_content) => Some(remove_plain_reply_fallback(&text_content.body)),
+        _ => None,
+    }
+}
+
+/// Split `input` into words, keeping text in straight or curly double quotes
+/// together.
+pub fn quoted_args(input: &str) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = input.trim().chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut arg = String::new();
+        if matches!(c, '"' | '“' | '”') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"' | '“' | '”') => break,
+                    Some(c) => arg.push(c),
+                    None => anyhow::bail!("There's an unclosed quote in that command."),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                arg.push(c);
+                chars.next();
+            }
+        }
+
+        let arg = arg.trim();
+        if !arg.is_empty() {
+            args.push(arg.to_owned());
+        }
+    }
+
+    Ok(args)
+}
+
+/// The event that `event` is an explicit reply to, in or out of a thread.
+pub fn reply_target(event: &OriginalSyncRoomMessageEvent) -> Option<&EventId> {
+    match event.content.relates_to.as_ref()? {
+        Relation::Reply { in_reply_to } => Some(&in_reply_to.event_id),
+        Relation::Thread(thread) if !thread.is_falling_back => thread
+            .in_reply_to
+            .as_ref()
+            .map(|in_reply_to| &*in_reply_to.event_id),
+        _ => None,
+    }
+}
+
+/// Fetch a message from the room, trying the event cache before asking the
+/// server. Returns `None` if the event isn't a message or was redacted.
+pub async fn fetch_message(
+    room: &Room,
+    event_id: &EventId,
+) -> anyhow::Result<Option<OriginalSyncRoomMessageEvent>> {
+    let cached = match room.event_cache().await {
+        Ok((event_cache, _drop_handles)) => event_cache.event(event_id).await,
+        Err(err) => {
+            debug!("error when getting the event cache: {err}");
+            None
+        }
+    };
+    let event = match cached {
+        Some(event) => event,
+        None => {
+            trace!("trying with /event now");
+            SyncTimelineEvent::from(origins::event(room, event_id).await?)
+        }
+    };
+
+    Ok(match event.into_raw().deserialize()? {
+        AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+            SyncMessageLikeEvent::Original(message),
+        )) => Some(message),
+        _ => None,
+    })
+}
+
+/// Whether `user_id` is at least a moderator in the room.
+pub async fn is_moderator(room: &Room, user_id: &UserId) -> anyhow::Result<bool> {
+    Ok(room
+        .get_member(user_id)
+        .await?
+        .is_some_and(|member| member.power_level() >= MODERATOR_POWER_LEVEL))
+}
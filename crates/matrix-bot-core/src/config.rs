@@ -0,0 +1,176 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use matrix_sdk::ruma::{presence::PresenceState, OwnedRoomOrAliasId};
+
+use crate::{abuse::AbuseConfig, egress::EgressConfig, parse_duration, workers::Backlog, Delivery};
+
+#[derive(Parser, Debug, Clone)]
+pub struct AccountConfig {
+    /// URL of the homeserver to connect to
+    #[arg(short, long, env = "MATRIX_SERVER")]
+    pub server: String,
+    /// Username of the bot
+    #[arg(short, long, env = "MATRIX_USERNAME")]
+    pub username: String,
+    /// Password of the bot
+    #[arg(short, long, env = "MATRIX_PASSWORD")]
+    pub password: Option<String>,
+    /// Delete devices other than the one being used by this instance
+    #[arg(long)]
+    pub delete_other_devices: bool,
+    /// Device name to set, if it doesn't exist [default: "<bot name> client"]
+    #[arg(long, env = "MATRIX_CLIENT_NAME")]
+    pub device_name: Option<String>,
+    /// Set the device name, even if it already exists
+    #[arg(long, default_value_t = false)]
+    pub set_device_name: bool,
+    /// Where to keep the session and stores, instead of a directory named
+    /// after the bot in the platform's data directory
+    #[arg(long, env = "MATRIX_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Delete the bot's session and Matrix store, and log in again as a new
+    /// device. For when the store is corrupt or its passphrase is lost
+    #[arg(long, default_value_t = false)]
+    pub reset_store: bool,
+    /// What to do when restarting with events that weren't finished being
+    /// handled, e.g. after a crash: skip them (`at-most-once`) or handle
+    /// them again (`at-least-once`)
+    #[arg(long, default_value = "at-most-once", env = "MATRIX_DELIVERY")]
+    pub delivery: Delivery,
+    /// How long the homeserver holds each sync open waiting for events.
+    /// Lower it if a proxy cuts long requests off, or use 0 to poll
+    #[arg(long, default_value = "30s", value_parser = parse_sync_timeout, env = "MATRIX_SYNC_TIMEOUT")]
+    pub sync_timeout: Duration,
+    /// The presence to sync with: online, unavailable or offline
+    #[arg(long, default_value = "online", value_parser = parse_presence, env = "MATRIX_PRESENCE")]
+    pub presence: PresenceState,
+    /// Follow maubot's conventions, for people used to its plugins: command
+    /// names in any case, and `!help` listing every command
+    #[arg(long, env = "MATRIX_MAUBOT_COMPAT")]
+    pub maubot_compat: bool,
+    /// How long a command can take before the bot replies that it's still
+    /// working on it, editing the answer in when it's ready. 0 turns that
+    /// off
+    #[arg(long, default_value = "3s", value_parser = parse_latency_budget, env = "MATRIX_LATENCY_BUDGET")]
+    pub latency_budget: Duration,
+    /// How many requests for events or media from any one server can be
+    /// in flight at once, so a slow server can't hold up the rest. 0 for
+    /// no limit
+    #[arg(long, default_value_t = 4, env = "MATRIX_ORIGIN_CONCURRENCY")]
+    pub origin_concurrency: usize,
+    /// Skip events that handling has failed on this many times, even
+    /// across restarts. 0 never skips them
+    #[arg(long, default_value_t = 3, env = "MATRIX_QUARANTINE_AFTER")]
+    pub quarantine_after: u32,
+    /// A room to tell about problems running the bot, like events it's
+    /// given up on, and whose moderators can change what's logged with
+    /// `!loglevel`, by ID or alias
+    #[arg(long, env = "MATRIX_ADMIN_ROOM")]
+    pub admin_room: Option<OwnedRoomOrAliasId>,
+    /// Only run while holding this lock, waiting as a standby while another
+    /// instance does: a file both instances can reach, or a Postgres URL,
+    /// which needs the `postgres` feature. For a second instance to take
+    /// over if the first dies
+    #[arg(long, env = "MATRIX_LEADER_LOCK")]
+    pub leader_lock: Option<String>,
+    /// An address to serve `/health` and `/metrics` on, and the admin API
+    /// with an admin token, for bots without an HTTP server of their own. Those with one
+    /// serve them there as well
+    #[arg(long, env = "MATRIX_HTTP_LISTEN")]
+    pub http_listen: Option<SocketAddr>,
+    /// The token to serve the admin API under /admin with. It's off without
+    /// one, and only served on loopback addresses unless over HTTPS
+    #[arg(long, env = "MATRIX_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+    /// Options only bots that queue their work on the worker pool offer,
+    /// set from their own config, and left at the defaults elsewhere
+    #[arg(skip)]
+    pub scale: ScaleConfig,
+    #[clap(flatten)]
+    pub egress: EgressConfig,
+    #[clap(flatten)]
+    pub abuse: AbuseConfig,
+}
+
+/// Options for bots that handle events on the [worker pool](crate::workers)
+/// and keep a [store](crate::store), for handling many rooms and running
+/// several instances. They're flattened into those bots' configs and moved
+/// into [`AccountConfig::scale`] before logging in, so bots that can't use
+/// them don't offer them.
+#[derive(Parser, Debug, Clone)]
+pub struct ScaleConfig {
+    /// How many rooms' events can be handled at once
+    #[arg(long, default_value_t = 8, env = "MATRIX_WORKERS")]
+    pub workers: usize,
+    /// What to do with events that pile up, e.g. after downtime: handle
+    /// `all` of them, only the `latest` in each room, or skip those older
+    /// than a duration like `10m`
+    #[arg(long, default_value = "all", env = "MATRIX_BACKLOG")]
+    pub backlog: Backlog,
+    /// Skip events the bot was handling when it last crashed, in case one
+    /// of them is what crashed it, rather than handling them again
+    #[arg(long, env = "MATRIX_SKIP_CRASHED")]
+    pub skip_crashed: bool,
+    /// A Postgres URL to keep room settings, user preferences and counters
+    /// in, rather than SQLite in the data directory, so several instances
+    /// can share them. Needs the bot built with the `postgres` feature, and
+    /// bots with tables of their own refuse it
+    #[arg(long, env = "MATRIX_STORE_URL")]
+    pub store_url: Option<String>,
+    /// Share rooms out between every instance of the bot with this set and
+    /// the same store URL. Each needs its own data directory
+    #[arg(long, env = "MATRIX_SHARDED")]
+    pub sharded: bool,
+}
+
+impl Default for ScaleConfig {
+    fn default() -> Self {
+        Self {
+            workers: 8,
+            backlog: Backlog::All,
+            skip_crashed: false,
+            store_url: None,
+            sharded: false,
+        }
+    }
+}
+
+fn parse_sync_timeout(timeout: &str) -> Result<Duration, String> {
+    if timeout == "0" {
+        return Ok(Duration::ZERO);
+    }
+    parse_duration(timeout).ok_or_else(|| format!("invalid sync timeout: {timeout}"))
+}
+
+fn parse_latency_budget(budget: &str) -> Result<Duration, String> {
+    if budget == "0" {
+        return Ok(Duration::ZERO);
+    }
+    parse_duration(budget).ok_or_else(|| format!("invalid latency budget: {budget}"))
+}
+
+fn parse_presence(presence: &str) -> Result<PresenceState, String> {
+    match presence {
+        "online" => Ok(PresenceState::Online),
+        "unavailable" => Ok(PresenceState::Unavailable),
+        "offline" => Ok(PresenceState::Offline),
+        _ => Err(format!("invalid presence: {presence}")),
+    }
+}
+
+impl AccountConfig {
+    /// The configured password, prompting for it on the terminal if it wasn't
+    /// given.
+    pub fn password(&self) -> String {
+        self.password.clone().unwrap_or_else(|| {
+            println!("Type password for the bot (characters won't show up as you type them)");
+            match rpassword::prompt_password("Password: ") {
+                Ok(p) => p,
+                Err(err) => {
+                    panic!("FATAL: failed to get password: {err}");
+                }
+            }
+        })
+    }
+}
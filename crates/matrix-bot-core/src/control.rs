@@ -0,0 +1,203 @@
+//! Controlling a running bot from the command line, over a Unix socket in
+//! its data directory.
+//!
+//! Bots that call [`Bot::enable_control`](crate::Bot::enable_control) listen
+//! on the socket, and their `ctl` subcommand sends it one command and prints
+//! the answer:
+//!
+//! - `status` shows whether the bot is ready, its rooms and how long
+//!   starting up took
+//! - `reload` has the bot reload whatever it can without restarting
+//! - `leave-room <room>` leaves a room
+//! - `set-log-level <filter>` changes what's logged, like `RUST_LOG`
+
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use clap::Parser;
+use matrix_sdk::{ruma::RoomId, Client, RoomState};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::watch,
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use crate::{set_log_filter, startup::Startup};
+
+/// The socket's name in the bot's data directory.
+const SOCKET: &str = "control.sock";
+
+/// What a bot does when asked to reload.
+pub(crate) type Reload = Arc<dyn Fn() -> anyhow::Result<()> + Send + Sync>;
+
+/// The `ctl` subcommand.
+#[derive(Parser, Debug)]
+#[command(name = "ctl")]
+struct Ctl {
+    /// The command to send: status, reload, leave-room <room> or
+    /// set-log-level <filter>
+    #[arg(required = true, num_args = 1..)]
+    command: Vec<String>,
+    /// The bot's data directory, if it was given one when started
+    #[arg(long, env = "MATRIX_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+}
+
+/// Whether the program was run as `<bot> ctl ...`, and so should send a
+/// command rather than start the bot.
+pub fn requested() -> bool {
+    std::env::args().nth(1).as_deref() == Some("ctl")
+}
+
+/// Send the command from the arguments to the bot called `name`, print its
+/// answer, and fail if the command did.
+pub async fn ctl(name: &str) -> anyhow::Result<()> {
+    let ctl = Ctl::parse_from(std::env::args().skip(1));
+    let socket = ctl
+        .data_dir
+        .unwrap_or_else(|| crate::data_dir(name))
+        .join(SOCKET);
+    let mut stream = UnixStream::connect(&socket).await.with_context(|| {
+        format!(
+            "couldn't reach {name} at {}, is it running?",
+            socket.display()
+        )
+    })?;
+    stream
+        .write_all(format!("{}\n", ctl.command.join(" ")).as_bytes())
+        .await?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).await?;
+    match answer.strip_prefix("error: ") {
+        Some(error) => anyhow::bail!("{}", error.trim_end()),
+        None => {
+            print!("{answer}");
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Control {
+    client: Client,
+    startup: Startup,
+    reload: Reload,
+}
+
+/// Listen on the control socket in `data_dir` until `shutdown` becomes true.
+pub(crate) fn listen(
+    data_dir: &Path,
+    client: Client,
+    startup: Startup,
+    reload: Reload,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<JoinHandle<()>> {
+    let socket = data_dir.join(SOCKET);
+    // Left behind if the bot didn't stop cleanly
+    if socket.exists() {
+        std::fs::remove_file(&socket)?;
+    }
+    let listener = UnixListener::bind(&socket)
+        .with_context(|| format!("failed to listen on {}", socket.display()))?;
+    // Only the bot's own user may control it
+    std::fs::set_permissions(&socket, std::fs::Permissions::from_mode(0o600))?;
+    info!("Listening for control commands on {}", socket.display());
+
+    let control = Control {
+        client,
+        startup,
+        reload,
+    };
+    Ok(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        tokio::spawn(control.clone().serve(stream));
+                    }
+                    Err(err) => warn!("Failed to accept a control connection: {err}"),
+                },
+                _ = shutdown.wait_for(|&shutdown| shutdown) => break,
+            }
+        }
+        let _ = std::fs::remove_file(&socket);
+    }))
+}
+
+impl Control {
+    async fn serve(self, stream: UnixStream) {
+        let (reader, mut writer) = stream.into_split();
+        let mut line = String::new();
+        if let Err(err) = BufReader::new(reader).read_line(&mut line).await {
+            warn!("Failed to read a control command: {err}");
+            return;
+        }
+        let answer = match self.run(line.trim()).await {
+            Ok(answer) => answer,
+            Err(err) => format!("error: {err:#}\n"),
+        };
+        if let Err(err) = writer.write_all(answer.as_bytes()).await {
+            warn!("Failed to answer a control command: {err}");
+        }
+    }
+
+    async fn run(&self, line: &str) -> anyhow::Result<String> {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+        match command {
+            "status" => Ok(self.status()),
+            "reload" => {
+                (self.reload)()?;
+                info!("Reloaded from the control socket");
+                Ok("Reloaded.\n".to_owned())
+            }
+            "leave-room" => {
+                let room_id = <&RoomId>::try_from(argument)
+                    .map_err(|_| anyhow::anyhow!("usage: leave-room <room ID>"))?;
+                let room = self
+                    .client
+                    .get_room(room_id)
+                    .context("the bot isn't in that room")?;
+                room.leave().await?;
+                info!(
+                    room = room_id.as_str(),
+                    "Left a room from the control socket"
+                );
+                Ok(format!("Left {room_id}.\n"))
+            }
+            "set-log-level" if !argument.is_empty() => {
+                set_log_filter(argument)?;
+                info!(
+                    filter = argument,
+                    "Changed the log level from the control socket"
+                );
+                Ok(format!("Logging {argument}.\n"))
+            }
+            "set-log-level" => anyhow::bail!("usage: set-log-level <filter>"),
+            _ => anyhow::bail!(
+                "unknown command {command}: use status, reload, leave-room or set-log-level"
+            ),
+        }
+    }
+
+    fn status(&self) -> String {
+        let rooms = self.client.rooms();
+        let count = |state| rooms.iter().filter(|room| room.state() == state).count();
+        format!(
+            "user: {}\nready: {}\nrooms: {} joined, {} invited\nstartup: {}\n",
+            self.client
+                .user_id()
+                .map_or_else(|| "not logged in".to_owned(), ToString::to_string),
+            if self.startup.is_ready() { "yes" } else { "no" },
+            count(RoomState::Joined),
+            count(RoomState::Invited),
+            self.startup.summary()
+        )
+    }
+}
@@ -0,0 +1,109 @@
+//! A D-Bus service on the session bus, for people running a bot on their
+//! desktop to check on it and pause it from tray tools and scripts.
+//!
+//! A bot called `matrix-sed` takes the name
+//! `io.github.jadedblueeyes.bots.matrix_sed`, and serves [`INTERFACE`] at
+//! [`PATH`] with these methods:
+//!
+//! - `Status() -> s`: whether the bot is ready or paused, and its rooms
+//! - `Pause()` and `Resume()`: stop and start handling events queued on
+//!   [`Workers`], which keep queueing meanwhile
+//! - `RecentActivity() -> as`: the last few things the bot did, oldest
+//!   first
+
+use matrix_sdk::{Client, RoomState};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::info;
+use zbus::{connection, interface};
+
+use crate::{egress::Egress, startup::Startup, store, workers::Workers};
+
+/// The interface's name.
+pub const INTERFACE: &str = "io.github.jadedblueeyes.Bot";
+
+/// Where the interface is served.
+pub const PATH: &str = "/io/github/jadedblueeyes/Bot";
+
+struct Service {
+    client: Client,
+    startup: Startup,
+    workers: Workers,
+    egress: Egress,
+}
+
+#[interface(name = "io.github.jadedblueeyes.Bot")]
+impl Service {
+    fn status(&self) -> String {
+        let state = if !self.startup.is_ready() {
+            "starting"
+        } else if self.workers.is_paused() {
+            "paused"
+        } else {
+            "running"
+        };
+        let joined = self
+            .client
+            .rooms()
+            .iter()
+            .filter(|room| room.state() == RoomState::Joined)
+            .count();
+        format!("{state}, in {joined} rooms")
+    }
+
+    fn pause(&self) {
+        info!("Paused over D-Bus");
+        self.workers.pause();
+    }
+
+    fn resume(&self) {
+        info!("Resumed over D-Bus");
+        self.workers.resume();
+    }
+
+    fn recent_activity(&self) -> Vec<String> {
+        let now = store::now();
+        self.egress
+            .recent()
+            .into_iter()
+            .map(|activity| {
+                let ago = now.saturating_sub(activity.at);
+                match activity.room_id {
+                    Some(room_id) => format!("{} in {room_id}, {ago}s ago", activity.activity),
+                    None => format!("{}, {ago}s ago", activity.activity),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Serve the interface for the bot called `name` until `shutdown` becomes
+/// true.
+pub(crate) async fn serve(
+    name: &str,
+    client: Client,
+    startup: Startup,
+    workers: Workers,
+    egress: Egress,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<JoinHandle<()>> {
+    // Bus names can't have dashes in them
+    let bus_name = format!("io.github.jadedblueeyes.bots.{}", name.replace('-', "_"));
+    let service = Service {
+        client,
+        startup,
+        workers,
+        egress,
+    };
+    let connection = connection::Builder::session()?
+        .name(bus_name.as_str())?
+        .serve_at(PATH, service)?
+        .build()
+        .await?;
+    info!("Serving {INTERFACE} on the session bus as {bus_name}");
+
+    Ok(tokio::spawn(async move {
+        let _ = shutdown.wait_for(|&shutdown| shutdown).await;
+        // Releases the name
+        drop(connection);
+    }))
+}
@@ -0,0 +1,263 @@
+//! Checking a bot can log in, sync, send and read messages, for when it
+//! doesn't respond and it isn't clear why.
+//!
+//! The `doctor` subcommand takes the bot's usual account options and runs
+//! each check in turn, stopping at the first that fails, then prints what
+//! it found. It tests in a new private room, which it leaves afterwards and
+//! which is encrypted with `--encrypted`, or in the room given with
+//! `--room`.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use matrix_sdk::{
+    config::SyncSettings,
+    deserialized_responses::SyncTimelineEvent,
+    ruma::{
+        api::client::{
+            filter::FilterDefinition,
+            room::create_room::v3::{Request as CreateRoomRequest, RoomPreset},
+        },
+        events::{
+            room::{
+                encryption::RoomEncryptionEventContent,
+                message::{MessageType, RoomMessageEventContent},
+            },
+            AnySyncMessageLikeEvent, AnySyncTimelineEvent, InitialStateEvent, SyncMessageLikeEvent,
+        },
+        OwnedRoomOrAliasId,
+    },
+    Client, Room, RoomState,
+};
+
+use crate::{send, AccountConfig, Bot};
+
+/// How long the sync check waits for the homeserver.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The `doctor` subcommand.
+#[derive(Parser, Debug)]
+#[command(name = "doctor")]
+struct Doctor {
+    #[clap(flatten)]
+    account_config: AccountConfig,
+    /// A room the bot is in or can join to test in, rather than a new one
+    #[arg(long)]
+    room: Option<OwnedRoomOrAliasId>,
+    /// Encrypt the new test room, to check encryption works
+    #[arg(long)]
+    encrypted: bool,
+}
+
+/// What each check found.
+#[derive(Default)]
+struct Report {
+    lines: Vec<String>,
+    failed: bool,
+}
+
+impl Report {
+    /// Run a check, noting how it went and how long it took.
+    async fn check<T>(
+        &mut self,
+        name: &str,
+        check: impl Future<Output = anyhow::Result<T>>,
+        detail: impl FnOnce(&T) -> String,
+    ) -> anyhow::Result<T> {
+        let started = Instant::now();
+        let result = check.await;
+        let took = started.elapsed().as_millis();
+        match &result {
+            Ok(value) => self
+                .lines
+                .push(format!("ok    {name} ({took}ms): {}", detail(value))),
+            Err(err) => {
+                self.failed = true;
+                self.lines.push(format!("FAIL  {name} ({took}ms): {err:#}"));
+            }
+        }
+        result
+    }
+
+    fn skip(&mut self, name: &str, why: &str) {
+        self.lines.push(format!("skip  {name}: {why}"));
+    }
+}
+
+/// Whether the program was run as `<bot> doctor ...`, and so should check
+/// the bot over rather than start it.
+pub fn requested() -> bool {
+    std::env::args().nth(1).as_deref() == Some("doctor")
+}
+
+/// Check over the bot called `name` and print what was found, failing if
+/// any check did.
+pub async fn run(name: &str) -> anyhow::Result<()> {
+    let doctor = Doctor::parse_from(std::env::args().skip(1));
+    let mut report = Report::default();
+    // Each check needs the ones before it, so the first failure is enough
+    let _ = diagnose(name, doctor, &mut report).await;
+    for line in &report.lines {
+        println!("{line}");
+    }
+    if report.failed {
+        anyhow::bail!("{name} isn't healthy");
+    }
+    println!("{name} looks healthy");
+    Ok(())
+}
+
+async fn diagnose(name: &str, doctor: Doctor, report: &mut Report) -> anyhow::Result<()> {
+    let bot = report
+        .check(
+            "logging in",
+            Bot::login(name, doctor.account_config),
+            |bot| {
+                format!(
+                    "{} on device {}",
+                    bot.client()
+                        .user_id()
+                        .map_or("?".into(), ToString::to_string),
+                    bot.client()
+                        .device_id()
+                        .map_or("?".into(), ToString::to_string)
+                )
+            },
+        )
+        .await?;
+    let client = bot.client();
+
+    report
+        .check(
+            "checking the access token",
+            async { Ok(client.whoami().await?) },
+            |_| "the homeserver accepts it".to_owned(),
+        )
+        .await?;
+
+    report
+        .check("syncing", sync(client), |(joined, invited)| {
+            format!("in {joined} rooms, invited to {invited}")
+        })
+        .await?;
+
+    let created = doctor.room.is_none();
+    let room = report
+        .check(
+            "finding a room to test in",
+            test_room(client, doctor.room, doctor.encrypted),
+            |room| {
+                if created {
+                    format!("created {}", room.room_id())
+                } else {
+                    format!("using {}", room.room_id())
+                }
+            },
+        )
+        .await?;
+
+    let result = exchange(&room, report).await;
+    if created {
+        let _ = report
+            .check(
+                "leaving the test room",
+                async { Ok(room.leave().await?) },
+                |_| "left".to_owned(),
+            )
+            .await;
+    }
+    result
+}
+
+/// Sync once, returning how many rooms the bot is in and invited to.
+async fn sync(client: &Client) -> anyhow::Result<(usize, usize)> {
+    let settings = SyncSettings::default()
+        .filter(FilterDefinition::with_lazy_loading().into())
+        .timeout(SYNC_TIMEOUT);
+    client.sync_once(settings).await?;
+    let rooms = client.rooms();
+    let count = |state| rooms.iter().filter(|room| room.state() == state).count();
+    Ok((count(RoomState::Joined), count(RoomState::Invited)))
+}
+
+async fn test_room(
+    client: &Client,
+    room: Option<OwnedRoomOrAliasId>,
+    encrypted: bool,
+) -> anyhow::Result<Room> {
+    if let Some(room) = room {
+        return Ok(client.join_room_by_id_or_alias(&room, &[]).await?);
+    }
+    let mut request = CreateRoomRequest::new();
+    request.name = Some("Bot doctor".to_owned());
+    request.preset = Some(RoomPreset::PrivateChat);
+    if encrypted {
+        request.initial_state =
+            vec![
+                InitialStateEvent::new(RoomEncryptionEventContent::with_recommended_defaults())
+                    .to_raw_any(),
+            ];
+    }
+    Ok(client.create_room(request).await?)
+}
+
+/// Send a message and read it back, checking it was encrypted if the room
+/// is.
+async fn exchange(room: &Room, report: &mut Report) -> anyhow::Result<()> {
+    const TEST_MESSAGE: &str = "Checking I can send messages here.";
+
+    let event_id = report
+        .check(
+            "sending a message",
+            send(room, RoomMessageEventContent::notice_plain(TEST_MESSAGE)),
+            |event_id| event_id.to_string(),
+        )
+        .await?;
+
+    let encrypted = report
+        .check(
+            "reading it back",
+            async {
+                let event = SyncTimelineEvent::from(room.event(&event_id, None).await?);
+                let encrypted = event.encryption_info().is_some();
+                let AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+                    SyncMessageLikeEvent::Original(message),
+                )) = event.into_raw().deserialize()?
+                else {
+                    anyhow::bail!("it came back as something other than a message");
+                };
+                match message.content.msgtype {
+                    MessageType::Notice(notice) if notice.body == TEST_MESSAGE => Ok(encrypted),
+                    _ => anyhow::bail!("it came back different"),
+                }
+            },
+            |_| "it came back the same".to_owned(),
+        )
+        .await?;
+
+    if room.is_encrypted().await? {
+        report
+            .check(
+                "checking encryption",
+                async {
+                    if !encrypted {
+                        anyhow::bail!("the room is encrypted but the message wasn't");
+                    }
+                    Ok(room.client().encryption().cross_signing_status().await)
+                },
+                |status| match status {
+                    Some(status) if status.is_complete() => {
+                        "the message was encrypted, and the device is cross-signed".to_owned()
+                    }
+                    _ => "the message was encrypted, but the device isn't cross-signed".to_owned(),
+                },
+            )
+            .await?;
+    } else {
+        report.skip("checking encryption", "the room isn't encrypted");
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+/// The number of seconds in a unit such as `h`, `min` or `days`.
+pub fn unit_seconds(unit: &str) -> Option<u64> {
+    Some(match unit.to_lowercase().trim_end_matches(',') {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+        "d" | "day" | "days" => 86400,
+        "w" | "wk" | "wks" | "week" | "weeks" => 604800,
+        _ => return None,
+    })
+}
+
+/// Parse a duration written without spaces, such as `90s`, `2h` or `1h30m`.
+pub fn parse_duration(word: &str) -> Option<Duration> {
+    let mut total = 0u64;
+    let mut rest = word;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let letters = rest[digits..]
+            .find(|c: char| c.is_ascii_digit())
+            .map_or(rest.len(), |i| i + digits);
+        if digits == 0 {
+            return None;
+        }
+        let amount: u64 = rest[..digits].parse().ok()?;
+        let unit = unit_seconds(&rest[digits..letters])?;
+        total = total.checked_add(amount.checked_mul(unit)?)?;
+        rest = &rest[letters..];
+    }
+    (total > 0).then_some(Duration::from_secs(total))
+}
+
+/// Format a duration compactly, e.g. `1d 2h 30m`.
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    if seconds == 0 {
+        return "0s".to_owned();
+    }
+
+    let mut parts = Vec::new();
+    for (unit, name) in [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")] {
+        if seconds >= unit {
+            parts.push(format!("{}{name}", seconds / unit));
+            seconds %= unit;
+        }
+    }
+    parts.join(" ")
+}
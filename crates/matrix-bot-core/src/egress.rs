@@ -0,0 +1,278 @@
+//! Telling other systems what the bot is doing, by posting to their
+//! webhooks, so monitoring and automation can react to it.
+//!
+//! Webhooks are listed in a TOML file, each with the activities it wants:
+//!
+//! ```toml
+//! [[webhooks]]
+//! url = "https://example.org/hooks/bot"
+//! activities = ["correction", "invite", "joined", "errors", "abuse"]
+//! # Optional: the JSON to post, as a Tera template
+//! template = '{ "text": {{ bot | json_encode() }} }'
+//! ```
+//!
+//! Without a template, the JSON posted has the `activity`, the `bot`'s user
+//! ID, the `room` if there is one, the activity's own `data` and a Unix
+//! `timestamp`, which templates can use too. Failed posts are retried a few
+//! times before being given up on.
+//!
+//! The last few activities are also kept for [`Egress::recent`], whether
+//! or not any webhooks want them.
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use clap::Parser;
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, RoomId};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::{
+    store::now,
+    template::{Context, Templates},
+};
+
+/// A correction or other command the bot carried out.
+pub const CORRECTION: &str = "correction";
+/// The bot was invited to a room.
+pub const INVITE: &str = "invite";
+/// The bot joined a room.
+pub const JOINED: &str = "joined";
+/// Handlers failed more than the configured number of times in a window.
+pub const ERRORS: &str = "errors";
+/// Someone misused the bot enough to be [reported](crate::abuse).
+pub const ABUSE: &str = "abuse";
+
+/// How many times a post is tried.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// How long to wait before the first retry, doubling with each one.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How long a post can take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The window errors are counted over.
+const ERROR_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// How many activities [`Egress::recent`] remembers.
+const MAX_RECENT: usize = 50;
+
+#[derive(Parser, Debug, Clone, Default)]
+pub struct EgressConfig {
+    /// A TOML file of webhooks to tell about what the bot does
+    #[arg(long, env = "BOT_EGRESS")]
+    pub egress: Option<PathBuf>,
+    /// How many errors in five minutes fire the `errors` activity
+    #[arg(long, default_value_t = 10, env = "BOT_EGRESS_ERROR_THRESHOLD")]
+    pub egress_error_threshold: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EgressFile {
+    #[serde(default)]
+    webhooks: Vec<WebhookConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WebhookConfig {
+    url: String,
+    activities: Vec<String>,
+    template: Option<String>,
+}
+
+/// Something the bot did.
+#[derive(Debug, Clone)]
+pub struct Activity {
+    /// When, as a Unix timestamp.
+    pub at: u64,
+    pub activity: &'static str,
+    pub room_id: Option<OwnedRoomId>,
+}
+
+/// Posts activities to the configured webhooks. Cloning it shares them.
+#[derive(Clone)]
+pub struct Egress {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    webhooks: Vec<WebhookConfig>,
+    templates: Templates,
+    http: reqwest::Client,
+    /// Who the bot is, once it's logged in.
+    bot: OnceLock<OwnedUserId>,
+    error_threshold: usize,
+    /// When recent errors happened, oldest first.
+    errors: Mutex<VecDeque<Instant>>,
+    /// The last few activities, oldest first.
+    recent: Mutex<VecDeque<Activity>>,
+}
+
+impl Egress {
+    /// Load the webhooks in the config, if there are any.
+    pub fn new(config: &EgressConfig) -> anyhow::Result<Self> {
+        let file = match &config.egress {
+            Some(path) => load(path)?,
+            None => EgressFile { webhooks: vec![] },
+        };
+        let mut templates = Templates::new();
+        for (index, webhook) in file.webhooks.iter().enumerate() {
+            if let Some(template) = &webhook.template {
+                templates.add(&index.to_string(), template, None)?;
+            }
+        }
+        Ok(Self {
+            inner: Arc::new(Inner {
+                webhooks: file.webhooks,
+                templates,
+                http: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+                bot: OnceLock::new(),
+                error_threshold: config.egress_error_threshold,
+                errors: Default::default(),
+                recent: Default::default(),
+            }),
+        })
+    }
+
+    pub(crate) fn logged_in(&self, bot: OwnedUserId) {
+        let _ = self.inner.bot.set(bot);
+    }
+
+    /// Tell the webhooks that want it about `activity` in the room, in the
+    /// background.
+    pub fn notify(&self, activity: &'static str, room_id: Option<&RoomId>, data: Value) {
+        {
+            let mut recent = self.inner.recent.lock().unwrap();
+            if recent.len() >= MAX_RECENT {
+                recent.pop_front();
+            }
+            recent.push_back(Activity {
+                at: now(),
+                activity,
+                room_id: room_id.map(ToOwned::to_owned),
+            });
+        }
+
+        let mut context = Context::new();
+        context.insert("activity", activity);
+        context.insert("bot", &self.inner.bot.get());
+        context.insert("room", &room_id);
+        context.insert("data", &data);
+        context.insert("timestamp", &now());
+
+        for (index, webhook) in self.inner.webhooks.iter().enumerate() {
+            if !webhook.activities.iter().any(|wanted| wanted == activity) {
+                continue;
+            }
+            let payload = match self.payload(index, webhook, &context) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    warn!(
+                        url = webhook.url.as_str(),
+                        "Failed to build a webhook payload: {err:#}"
+                    );
+                    continue;
+                }
+            };
+            tokio::spawn(post(
+                self.inner.http.clone(),
+                webhook.url.clone(),
+                activity,
+                payload,
+            ));
+        }
+    }
+
+    /// The last few activities, oldest first.
+    pub fn recent(&self) -> Vec<Activity> {
+        self.inner.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Count an error, firing [`ERRORS`] when there have been more than the
+    /// threshold in the window.
+    pub fn error(&self) {
+        let crossed = {
+            let mut errors = self.inner.errors.lock().unwrap();
+            while errors.front().is_some_and(|at| at.elapsed() > ERROR_WINDOW) {
+                errors.pop_front();
+            }
+            errors.push_back(Instant::now());
+            // Only when crossing it, not for every error over it
+            errors.len() == self.inner.error_threshold + 1
+        };
+        if crossed {
+            self.notify(
+                ERRORS,
+                None,
+                serde_json::json!({
+                    "count": self.inner.error_threshold + 1,
+                    "window_seconds": ERROR_WINDOW.as_secs(),
+                }),
+            );
+        }
+    }
+
+    fn payload(
+        &self,
+        index: usize,
+        webhook: &WebhookConfig,
+        context: &Context,
+    ) -> anyhow::Result<Value> {
+        if webhook.template.is_none() {
+            return Ok(context.clone().into_json());
+        }
+        let (rendered, _) = self.inner.templates.render(&index.to_string(), context)?;
+        serde_json::from_str(&rendered).context("the template didn't make valid JSON")
+    }
+}
+
+fn load(path: &Path) -> anyhow::Result<EgressFile> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read egress file {}", path.display()))?;
+    let file: EgressFile = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse egress file {}", path.display()))?;
+    for webhook in &file.webhooks {
+        reqwest::Url::parse(&webhook.url)
+            .with_context(|| format!("invalid webhook URL {}", webhook.url))?;
+    }
+    Ok(file)
+}
+
+async fn post(http: reqwest::Client, url: String, activity: &'static str, payload: Value) {
+    let mut delay = RETRY_DELAY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = http
+            .post(&url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        match result {
+            Ok(_) => {
+                debug!(url = url.as_str(), activity, "Posted to a webhook");
+                return;
+            }
+            Err(err) if attempt < MAX_ATTEMPTS => {
+                debug!(
+                    url = url.as_str(),
+                    activity, attempt, "Failed to post to a webhook, retrying: {err}"
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(err) => warn!(
+                url = url.as_str(),
+                activity, "Failed to post to a webhook: {err}"
+            ),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! Where the bots' metrics go: a Prometheus recorder, scraped from
+//! `/metrics` on the bot's [HTTP server](crate::http).
+//!
+//! Without a recorder the `metrics` macros do nothing, so it's installed
+//! before logging in, for everything from then on to be counted.
+
+use std::sync::OnceLock;
+
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use tracing::warn;
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the recorder, if it isn't already.
+pub(crate) fn install() {
+    if HANDLE.get().is_some() {
+        return;
+    }
+    match PrometheusBuilder::new().install_recorder() {
+        Ok(handle) => {
+            let _ = HANDLE.set(handle);
+        }
+        // Something else got there first, so its metrics go there instead
+        Err(err) => warn!("Failed to install the metrics recorder: {err}"),
+    }
+}
+
+/// Everything recorded so far, in Prometheus's text format.
+pub(crate) fn render() -> Option<String> {
+    let handle = HANDLE.get()?;
+    handle.run_upkeep();
+    Some(handle.render())
+}
@@ -0,0 +1,14 @@
+use matrix_sdk::ruma::UserId;
+
+/// Escape text for use in the `formatted_body` of a message.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A link to a user, which clients render as a pill.
+pub fn user_pill(user_id: &UserId) -> String {
+    format!("<a href=\"{}\">{user_id}</a>", user_id.matrix_to_uri())
+}
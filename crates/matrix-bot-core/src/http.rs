@@ -0,0 +1,207 @@
+//! An HTTP listener for bots that need one, for webhooks, feeds, health
+//! checks and the like.
+//!
+//! Every server also has `/health` and `/metrics`, for Prometheus to scrape.
+//!
+//! Bots build an [`HttpServer`] from the routes their modules provide,
+//! listening on an address of their own choosing, and hand it to [`Bot::serve_http`](crate::Bot::serve_http), which stops it
+//! gracefully when the bot shuts down.
+
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::IntoResponse,
+    routing::{get, MethodRouter},
+    Json, Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use clap::Parser;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{error, info};
+
+use crate::{admin, exporter, startup::Startup};
+
+/// How long requests in flight get to finish when shutting down.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+#[derive(Parser, Debug, Clone, Default)]
+pub struct HttpConfig {
+    /// A PEM certificate chain to serve HTTPS with, instead of plain HTTP
+    #[arg(long, env = "HTTP_TLS_CERT", requires = "http_tls_key")]
+    pub http_tls_cert: Option<PathBuf>,
+    /// The PEM private key for the certificate
+    #[arg(long, env = "HTTP_TLS_KEY", requires = "http_tls_cert")]
+    pub http_tls_key: Option<PathBuf>,
+}
+
+/// Compare secrets, e.g. a request's token, without leaking how much of
+/// them matched through timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// The routes a bot serves, waiting to be started.
+pub struct HttpServer {
+    address: SocketAddr,
+    config: HttpConfig,
+    router: Router,
+}
+
+impl HttpServer {
+    /// Routes to serve on `address`, with TLS and the admin API set up by
+    /// `config`.
+    pub fn new(address: SocketAddr, config: HttpConfig) -> Self {
+        Self {
+            address,
+            config,
+            router: Router::new(),
+        }
+    }
+
+    /// Serve `method_router` at `path`.
+    pub fn route(mut self, path: &str, method_router: MethodRouter) -> Self {
+        self.router = self.router.route(path, method_router);
+        self
+    }
+
+    /// Serve all of a module's routes, e.g. a webhook receiver's.
+    pub fn merge(mut self, router: Router) -> Self {
+        self.router = self.router.merge(router);
+        self
+    }
+
+    /// Serve a module's routes under `path`.
+    pub fn nest(mut self, path: &str, router: Router) -> Self {
+        self.router = self.router.nest(path, router);
+        self
+    }
+
+    /// Serve `/health`: 200 once the bot is ready and 503 before, with how
+    /// long each phase of starting up took.
+    pub(crate) fn health(self, startup: Startup) -> Self {
+        self.route(
+            "/health",
+            get(move || async move {
+                let phases: serde_json::Map<_, _> = startup
+                    .phases()
+                    .into_iter()
+                    .map(|(name, took)| (name.to_owned(), took.as_secs_f64().into()))
+                    .collect();
+                let status = if startup.is_ready() {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                let body = serde_json::json!({
+                    "ready": startup.is_ready(),
+                    "startup_seconds": phases,
+                });
+                (status, Json(body))
+            }),
+        )
+    }
+
+    /// Serve `/metrics`, everything the bot has recorded, for Prometheus.
+    pub(crate) fn metrics(self) -> Self {
+        self.route(
+            "/metrics",
+            get(|| async {
+                match exporter::render() {
+                    Some(metrics) => (
+                        StatusCode::OK,
+                        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+                        metrics,
+                    )
+                        .into_response(),
+                    None => {
+                        (StatusCode::NOT_FOUND, "metrics aren't being recorded").into_response()
+                    }
+                }
+            }),
+        )
+    }
+
+    /// Serve the admin API under `/admin`, if there's a token for it.
+    ///
+    /// Requests carry the token, so without TLS it's only served on loopback
+    /// addresses, e.g. behind a reverse proxy that adds it.
+    pub(crate) fn admin(
+        mut self,
+        token: Option<String>,
+        admin: admin::Admin,
+    ) -> anyhow::Result<Self> {
+        if let Some(token) = token {
+            if self.config.http_tls_cert.is_none() && !self.address.ip().is_loopback() {
+                anyhow::bail!(
+                    "the admin API would be served on {} without TLS, sending its token in the \
+                     clear; listen on a loopback address behind a reverse proxy that adds TLS, \
+                     or give the server a certificate",
+                    self.address
+                );
+            }
+            self.router = self.router.nest("/admin", admin::router(admin, token));
+        }
+        Ok(self)
+    }
+
+    /// Start listening, until `shutdown` becomes true.
+    ///
+    /// Binding happens before this returns, so a bad address or certificate
+    /// is reported straight away.
+    pub(crate) async fn start(
+        self,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> anyhow::Result<JoinHandle<()>> {
+        let address = self.address;
+        let listener = std::net::TcpListener::bind(address)
+            .with_context(|| format!("failed to listen on {address}"))?;
+        listener.set_nonblocking(true)?;
+
+        let tls = match (&self.config.http_tls_cert, &self.config.http_tls_key) {
+            (Some(cert), Some(key)) => Some(
+                RustlsConfig::from_pem_file(cert, key)
+                    .await
+                    .context("failed to load the TLS certificate")?,
+            ),
+            _ => None,
+        };
+        info!(
+            "Listening for {} on {address}",
+            if tls.is_some() { "HTTPS" } else { "HTTP" }
+        );
+
+        let handle = Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                // An error means the bot is gone, so stop too
+                let _ = shutdown.wait_for(|&shutdown| shutdown).await;
+                info!("Stopping the HTTP server");
+                handle.graceful_shutdown(Some(SHUTDOWN_GRACE));
+            }
+        });
+
+        let service = self.router.into_make_service();
+        Ok(tokio::spawn(async move {
+            let result = match tls {
+                Some(tls) => {
+                    axum_server::from_tcp_rustls(listener, tls)
+                        .handle(handle)
+                        .serve(service)
+                        .await
+                }
+                None => {
+                    axum_server::from_tcp(listener)
+                        .handle(handle)
+                        .serve(service)
+                        .await
+                }
+            };
+            if let Err(err) = result {
+                error!("HTTP server stopped: {err}");
+            }
+        }))
+    }
+}
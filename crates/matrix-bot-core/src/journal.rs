@@ -0,0 +1,230 @@
+//! A crash journal: the last few events the bot handled and what it was
+//! doing with them, kept on disk so that after a crash the next run can say
+//! what the bot was in the middle of.
+//!
+//! [`Workers`](crate::workers::Workers) notes each event as it starts and
+//! finishes handling it, and a panic adds its message to whatever was
+//! running. When the bot starts again after stopping without shutting down
+//! cleanly, it logs the events it hadn't finished. With
+//! [`ScaleConfig::skip_crashed`](crate::ScaleConfig::skip_crashed),
+//! those events are skipped if they come round again, in case one is what
+//! keeps crashing the bot.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use matrix_sdk::ruma::{EventId, OwnedEventId, OwnedRoomId, RoomId};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+/// How many events the journal remembers.
+const MAX_ENTRIES: usize = 20;
+
+tokio::task_local! {
+    /// The event the current task is handling, for blaming panics on.
+    static HANDLING: OwnedEventId;
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Contents {
+    /// Oldest first.
+    entries: VecDeque<Entry>,
+    /// Whether the bot shut down cleanly after writing this.
+    clean: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub room_id: OwnedRoomId,
+    pub event_id: OwnedEventId,
+    /// What the bot was doing with the event, like the command.
+    pub command: String,
+    /// When the bot started handling it, in seconds since the Unix epoch.
+    pub started: i64,
+    pub finished: bool,
+    /// What the bot panicked with while handling it, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub panic: Option<String>,
+}
+
+/// The bot's crash journal. Cloning it shares it.
+#[derive(Clone)]
+pub struct Journal {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    path: PathBuf,
+    contents: Mutex<Contents>,
+    /// Events that were being handled when the last run crashed.
+    crashed: Vec<Entry>,
+    skip_crashed: bool,
+}
+
+impl Journal {
+    /// Open the journal in `data_dir`, logging what the last run was doing
+    /// if it didn't shut down cleanly. Nothing is written to it until
+    /// [`Journal::begin`].
+    pub(crate) fn open(data_dir: &Path, skip_crashed: bool) -> Self {
+        let path = data_dir.join("journal.json");
+        let previous = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .inspect_err(|err| warn!("The crash journal is unreadable, ignoring it: {err}"))
+                .unwrap_or_default(),
+            Err(_) => Contents {
+                clean: true,
+                ..Default::default()
+            },
+        };
+        let crashed: Vec<Entry> = if previous.clean {
+            Vec::new()
+        } else {
+            previous
+                .entries
+                .iter()
+                .filter(|entry| !entry.finished)
+                .cloned()
+                .collect()
+        };
+        if !previous.clean {
+            error!("The bot didn't shut down cleanly last time");
+        }
+        for entry in &crashed {
+            error!(
+                room = entry.room_id.as_str(),
+                event = entry.event_id.as_str(),
+                command = entry.command.as_str(),
+                started = %DateTime::from_timestamp(entry.started, 0).unwrap_or_default(),
+                panic = entry.panic.as_deref(),
+                "Was handling an event when the bot stopped{}",
+                if skip_crashed { ", so it will be skipped" } else { "" }
+            );
+        }
+
+        Self {
+            inner: Arc::new(Inner {
+                path,
+                contents: Mutex::new(Contents {
+                    entries: previous.entries,
+                    clean: false,
+                }),
+                crashed,
+                skip_crashed,
+            }),
+        }
+    }
+
+    /// Note that the bot has started running, so that if it stops without
+    /// [`Journal::close`] the next run knows it crashed, and start noting
+    /// panics.
+    pub(crate) fn begin(&self) {
+        self.write(&self.inner.contents.lock().unwrap());
+
+        let journal = self.clone();
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            journal.panicked(&info.to_string());
+            previous_hook(info);
+        }));
+    }
+
+    /// The events that were being handled when the last run crashed.
+    pub fn crashed(&self) -> &[Entry] {
+        &self.inner.crashed
+    }
+
+    /// Whether to skip `event_id` because the last run crashed handling it.
+    pub fn should_skip(&self, event_id: &EventId) -> bool {
+        self.inner.skip_crashed
+            && self
+                .inner
+                .crashed
+                .iter()
+                .any(|entry| entry.event_id == event_id)
+    }
+
+    /// Note that the bot has started handling an event.
+    pub(crate) fn start(&self, room_id: &RoomId, event_id: &EventId, command: &str) {
+        let mut contents = self.inner.contents.lock().unwrap();
+        if contents.entries.len() >= MAX_ENTRIES {
+            contents.entries.pop_front();
+        }
+        contents.entries.push_back(Entry {
+            room_id: room_id.to_owned(),
+            event_id: event_id.to_owned(),
+            command: command.to_owned(),
+            started: Utc::now().timestamp(),
+            finished: false,
+            panic: None,
+        });
+        self.write(&contents);
+    }
+
+    /// Note that the bot has finished handling an event, however it went.
+    pub(crate) fn finish(&self, event_id: &EventId) {
+        let mut contents = self.inner.contents.lock().unwrap();
+        if let Some(entry) = contents
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.event_id == event_id)
+        {
+            entry.finished = true;
+        }
+        self.write(&contents);
+    }
+
+    /// Note that the bot is shutting down cleanly.
+    pub(crate) fn close(&self) {
+        let mut contents = self.inner.contents.lock().unwrap();
+        contents.clean = true;
+        self.write(&contents);
+    }
+
+    /// Run `job`, which handles `event_id`, so that a panic in it is
+    /// blamed on that event.
+    pub(crate) async fn scope<T>(event_id: OwnedEventId, job: impl Future<Output = T>) -> T {
+        HANDLING.scope(event_id, job).await
+    }
+
+    fn panicked(&self, panic: &str) {
+        // Panics outside of handling an event crash the bot without one to
+        // blame, which the unfinished entries show well enough
+        let Ok(event_id) = HANDLING.try_with(Clone::clone) else {
+            return;
+        };
+        // A panic while the journal is locked mustn't deadlock
+        let Ok(mut contents) = self.inner.contents.try_lock() else {
+            return;
+        };
+        if let Some(entry) = contents
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.event_id == event_id)
+        {
+            entry.panic = Some(panic.to_owned());
+        }
+        self.write(&contents);
+    }
+
+    /// Write the journal out, replacing the old one in one go so a crash
+    /// partway through leaves one or the other.
+    fn write(&self, contents: &Contents) {
+        let result = serde_json::to_vec_pretty(contents)
+            .map_err(std::io::Error::from)
+            .and_then(|data| {
+                let temp = self.inner.path.with_extension("json.tmp");
+                std::fs::write(&temp, data)?;
+                std::fs::rename(&temp, &self.inner.path)
+            });
+        if let Err(err) = result {
+            warn!("Failed to write the crash journal: {err}");
+        }
+    }
+}
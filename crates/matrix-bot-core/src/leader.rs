@@ -0,0 +1,126 @@
+//! Running a bot active/passive, so a standby instance takes over within
+//! seconds if the one answering dies.
+//!
+//! Every instance is given the same [`AccountConfig::leader_lock`](crate::AccountConfig::leader_lock),
+//! and [`Bot::login`](crate::Bot::login) waits to hold it before doing
+//! anything else, so only the leader logs in and syncs. The lock is either
+//! a file, held until the process exits, or a Postgres advisory lock, held
+//! until its connection closes.
+//!
+//! A leader that loses its connection to Postgres can't know whether it's
+//! still the leader, so it stops, to be restarted as a standby. A leader
+//! cut off from the network is only noticed by Postgres once its TCP
+//! keepalives fail, so set them low on the server if that matters.
+
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    future::pending,
+    time::Duration,
+};
+
+use anyhow::Context;
+use tokio::{sync::watch, time::sleep};
+use tracing::info;
+
+/// How often a standby checks whether the lock's free.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The lock, held for as long as this is.
+pub struct Leader {
+    // Never read, but dropping them releases the lock
+    _file: Option<File>,
+    #[cfg(feature = "postgres")]
+    _client: Option<tokio_postgres::Client>,
+    /// Set if the lock might have been lost.
+    lost: watch::Receiver<bool>,
+}
+
+impl Leader {
+    /// Wait until `lock`, a file path or a Postgres URL, can be taken for the
+    /// bot called `name`, then take it.
+    pub async fn acquire(name: &str, lock: &str) -> anyhow::Result<Self> {
+        if lock.starts_with("postgres://") || lock.starts_with("postgresql://") {
+            return Self::acquire_postgres(name, lock).await;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(lock)
+            .with_context(|| format!("failed to open the leader lock {lock}"))?;
+        let mut waiting = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(err)) => {
+                    return Err(err).with_context(|| format!("failed to lock {lock}"));
+                }
+            }
+            if !waiting {
+                info!("Another instance is the leader; waiting as a standby");
+                waiting = true;
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+        info!("Became the leader");
+        Ok(Self {
+            _file: Some(file),
+            #[cfg(feature = "postgres")]
+            _client: None,
+            // Never lost: the lock goes with the process
+            lost: watch::channel(false).1,
+        })
+    }
+
+    #[cfg(feature = "postgres")]
+    async fn acquire_postgres(name: &str, url: &str) -> anyhow::Result<Self> {
+        use tracing::error;
+
+        let (client, connection) = tokio_postgres::connect(url, tokio_postgres::NoTls)
+            .await
+            .context("failed to connect to Postgres for the leader lock")?;
+        let (lost_tx, lost) = watch::channel(false);
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                error!("Lost the connection holding the leader lock: {err}");
+            }
+            lost_tx.send_replace(true);
+        });
+        let mut waiting = false;
+        loop {
+            let row = client
+                .query_one("SELECT pg_try_advisory_lock(hashtext($1))", &[&name])
+                .await?;
+            if row.get(0) {
+                break;
+            }
+            if !waiting {
+                info!("Another instance is the leader; waiting as a standby");
+                waiting = true;
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+        info!("Became the leader");
+        Ok(Self {
+            _file: None,
+            _client: Some(client),
+            lost,
+        })
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    async fn acquire_postgres(_name: &str, _url: &str) -> anyhow::Result<Self> {
+        anyhow::bail!(
+            "the leader lock is a Postgres URL, but the bot was built without Postgres support"
+        )
+    }
+
+    /// Wait until the lock might have been lost, which is never for a file.
+    pub async fn lost(&mut self) {
+        if self.lost.wait_for(|lost| *lost).await.is_err() {
+            // Nothing can lose it
+            pending::<()>().await;
+        }
+    }
+}
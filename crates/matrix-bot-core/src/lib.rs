@@ -0,0 +1,719 @@
+//! Plumbing shared by the bots in this workspace: logging in and persisting
+//! the session, syncing, autojoining rooms, sending replies and keeping
+//! state in SQLite.
+
+pub mod abuse;
+mod admin;
+mod autojoin;
+mod commands;
+mod config;
+#[cfg(unix)]
+pub mod control;
+#[cfg(target_os = "linux")]
+pub mod dbus;
+pub mod doctor;
+mod duration;
+pub mod egress;
+mod exporter;
+pub mod html;
+pub mod http;
+pub mod journal;
+pub mod leader;
+mod loglevel;
+pub mod media;
+mod mirror;
+pub mod origins;
+pub mod pacing;
+pub mod permissions;
+pub mod policy;
+pub mod portable;
+pub mod quarantine;
+pub mod quiet;
+pub mod replay;
+pub mod rooms;
+pub mod scheduler;
+mod send;
+mod session;
+pub mod shard;
+mod snooze;
+pub mod startup;
+mod status;
+pub mod store;
+pub mod template;
+pub mod wizard;
+pub mod workers;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use abuse::Abuse;
+use anyhow::Context;
+use egress::Egress;
+use http::{HttpConfig, HttpServer};
+use journal::Journal;
+use leader::Leader;
+use quarantine::Quarantine;
+use startup::Startup;
+use workers::Workers;
+
+use matrix_sdk::{
+    config::SyncSettings,
+    ruma::{
+        api::client::{
+            filter::FilterDefinition,
+            uiaa::{AuthData, Password, UserIdentifier},
+        },
+        events::room::message::OriginalSyncRoomMessageEvent,
+    },
+    Client, LoopCtrl, Room,
+};
+use tokio::{
+    sync::{watch, Notify},
+    task::JoinHandle,
+};
+use tracing::{info, trace, warn};
+use tracing_log::AsTrace;
+use tracing_subscriber::{
+    layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+pub use autojoin::on_stripped_state_member;
+pub use commands::{
+    fetch_message, is_moderator, quoted_args, reply_target, strip_command, text_body, Help,
+    MODERATOR_POWER_LEVEL,
+};
+pub use config::{AccountConfig, ScaleConfig};
+pub use duration::{format_duration, parse_duration, unit_seconds};
+pub use send::{
+    can_reply, replacement, reply, reply_notice, send, send_or_log_error, send_raw, within_budget,
+};
+pub use session::Delivery;
+
+/// Lets the log filter be changed while the bot runs.
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// The log filter the bot started with, to go back to.
+static INITIAL_LOG_FILTER: OnceLock<String> = OnceLock::new();
+
+/// Set up logging to stdout, filtered by the verbosity flags and `RUST_LOG`.
+pub fn init_logging(verbose: &clap_verbosity_flag::Verbosity) {
+    let filter = EnvFilter::builder()
+        .with_default_directive(verbose.log_level_filter().as_trace().into())
+        .from_env_lossy();
+    let _ = INITIAL_LOG_FILTER.set(filter.to_string());
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    let _ = LOG_FILTER.set(handle);
+}
+
+/// Replace the log filter with `directives`, in the same syntax as
+/// `RUST_LOG`.
+pub fn set_log_filter(directives: &str) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_new(directives)?;
+    LOG_FILTER
+        .get()
+        .context("logging hasn't been set up")?
+        .reload(filter)?;
+    Ok(())
+}
+
+/// The log filter in use, in the same syntax as `RUST_LOG`.
+pub fn log_filter() -> anyhow::Result<String> {
+    Ok(LOG_FILTER
+        .get()
+        .context("logging hasn't been set up")?
+        .with_current(ToString::to_string)?)
+}
+
+/// Go back to the log filter the bot started with, returning it.
+pub fn reset_log_filter() -> anyhow::Result<String> {
+    let initial = INITIAL_LOG_FILTER
+        .get()
+        .context("logging hasn't been set up")?;
+    set_log_filter(initial)?;
+    Ok(initial.clone())
+}
+
+/// The directory the bot called `name` keeps its session and state in.
+fn data_dir(name: &str) -> PathBuf {
+    dirs::data_dir()
+        .expect("no data_dir directory found")
+        .join(name)
+}
+
+/// A logged-in bot account, along with the state needed to keep it syncing.
+pub struct Bot {
+    /// What the bot is called, like `matrix-sed`.
+    name: String,
+    client: Client,
+    data_dir: PathBuf,
+    session_file: PathBuf,
+    sync_settings: SyncSettings,
+    device_name: String,
+    config: AccountConfig,
+    /// Set when the bot is shutting down, for anything that needs to stop
+    /// cleanly with it.
+    shutdown: watch::Sender<bool>,
+    /// Notified to stop the bot from inside, like the admin API does.
+    stop: Arc<Notify>,
+    /// Tasks to wait for when shutting down.
+    tasks: Vec<JoinHandle<()>>,
+    workers: Workers,
+    journal: Journal,
+    quarantine: Quarantine,
+    egress: Egress,
+    abuse: Abuse,
+    help: Help,
+    startup: Startup,
+    /// Whether to handle events since the last sync token again, rather
+    /// than skipping them with the initial sync.
+    replay: bool,
+    /// The leader lock, when running active/passive.
+    leader: Option<Leader>,
+    /// The store from [`Bot::open_store`], for the admin API.
+    store: Arc<OnceLock<store::Store>>,
+}
+
+impl Bot {
+    /// Restore the previous session of the bot called `name`, or log in to a
+    /// new one.
+    ///
+    /// The session and stores are kept in a directory named after the bot in
+    /// the platform's data directory, unless [`AccountConfig::data_dir`]
+    /// says otherwise.
+    pub async fn login(name: &str, config: AccountConfig) -> anyhow::Result<Self> {
+        let data_dir = config.data_dir.clone().unwrap_or_else(|| data_dir(name));
+        let session_file = data_dir.join("session");
+        let device_name = config
+            .device_name
+            .clone()
+            .unwrap_or_else(|| format!("{name} client"));
+        let startup = Startup::new();
+        exporter::install();
+        // Before logging in, so a bad file is reported straight away
+        let egress = Egress::new(&config.egress)?;
+        // A standby mustn't touch the session until the leader's gone
+        let leader = match &config.leader_lock {
+            Some(lock) => {
+                let leader = Leader::acquire(name, lock).await?;
+                startup.finish("waiting to lead");
+                Some(leader)
+            }
+            None => None,
+        };
+        commands::set_maubot_compat(config.maubot_compat);
+        send::set_latency_budget(config.latency_budget);
+        origins::set_concurrency(config.origin_concurrency);
+
+        if config.reset_store {
+            session::reset_store(&session_file).await?;
+        }
+
+        let (client, sync_token) = if session_file.exists() {
+            let restored = session::restore_session(&session_file).await?;
+            startup.finish("restoring the session");
+            restored
+        } else {
+            let client = session::login(&data_dir, &session_file, &config, &device_name).await?;
+            startup.finish("logging in");
+            (client, None)
+        };
+
+        client.event_cache().subscribe()?;
+
+        if let Some(user_id) = client.user_id() {
+            egress.logged_in(user_id.to_owned());
+        }
+        client.add_event_handler_context(egress.clone());
+        let abuse = Abuse::new(config.abuse.clone(), egress.clone());
+        client.add_event_handler_context(abuse.clone());
+
+        let journal = Journal::open(&data_dir, config.scale.skip_crashed);
+        let quarantine = Quarantine::open(
+            &data_dir,
+            client.clone(),
+            config.quarantine_after,
+            config.admin_room.clone(),
+        )?;
+        // Handlers can take this as context to move their work off the sync
+        let workers = Workers::new(
+            config.scale.workers,
+            config.scale.backlog,
+            egress.clone(),
+            journal.clone(),
+            quarantine.clone(),
+        );
+        client.add_event_handler_context(workers.clone());
+
+        // Enable room members lazy-loading, it will speed up the initial sync a lot
+        // with accounts in lots of rooms.
+        // See <https://spec.matrix.org/v1.6/client-server-api/#lazy-loading-room-members>.
+        let filter = FilterDefinition::with_lazy_loading();
+
+        let mut sync_settings = SyncSettings::default()
+            .filter(filter.into())
+            .timeout(config.sync_timeout)
+            .set_presence(config.presence.clone());
+
+        let replay = sync_token.is_some() && config.delivery == Delivery::AtLeastOnce;
+
+        // We restore the sync where we left.
+        // This is not necessary when not using `sync_once`. The other sync methods get
+        // the sync token from the store.
+        if let Some(sync_token) = sync_token {
+            sync_settings = sync_settings.token(sync_token);
+        }
+
+        let mut bot = Self {
+            name: name.to_owned(),
+            client,
+            data_dir,
+            session_file,
+            sync_settings,
+            device_name,
+            config,
+            shutdown: watch::Sender::new(false),
+            stop: Default::default(),
+            tasks: Vec::new(),
+            workers,
+            journal,
+            quarantine,
+            egress,
+            abuse,
+            help: Help::default(),
+            startup,
+            replay,
+            leader,
+            store: Default::default(),
+        };
+        // Served from the start, so it can say the bot isn't ready yet
+        if let Some(address) = bot.config.http_listen {
+            bot.serve_http(HttpServer::new(address, HttpConfig::default()))
+                .await?;
+        }
+        Ok(bot)
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The pool handlers can queue work on, also available to them as
+    /// `Ctx<Workers>`.
+    pub fn workers(&self) -> &Workers {
+        &self.workers
+    }
+
+    /// What the bot was doing when it last crashed.
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// Posts what the bot does to the configured webhooks, also available
+    /// to handlers as `Ctx<Egress>`.
+    pub fn egress(&self) -> &Egress {
+        &self.egress
+    }
+
+    /// Counts and reports misuse of the bot, also available to handlers as
+    /// `Ctx<Abuse>`.
+    pub fn abuse(&self) -> &Abuse {
+        &self.abuse
+    }
+
+    /// The bot's commands, answered with `!help` in maubot compatibility
+    /// mode. Bots list their commands here as they set them up.
+    pub fn help(&self) -> &Help {
+        &self.help
+    }
+
+    /// How long each phase of starting up took.
+    pub fn startup(&self) -> &Startup {
+        &self.startup
+    }
+
+    /// The directory the bot keeps its session and any other state in.
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Open the bot's SQLite database in its data directory, applying
+    /// `migrations` as described in [`store::Store::open`].
+    ///
+    /// With [`ScaleConfig::store_url`] set, the shared tables are kept in
+    /// Postgres instead. Migrations are SQLite and the bot's own queries run
+    /// on its SQLite connection, so bots with tables of their own can't be
+    /// given a store URL, and fail to start if they are.
+    pub async fn open_store(&self, migrations: &[&str]) -> anyhow::Result<store::Store> {
+        let store = match &self.config.scale.store_url {
+            None => store::Store::open(&self.data_dir.join("store.sqlite3"), migrations)?,
+            Some(_) if !migrations.is_empty() => anyhow::bail!(
+                "{} keeps tables of its own, which can only be kept in SQLite, so it can't \
+                 use a store URL; unset --store-url or MATRIX_STORE_URL",
+                self.name
+            ),
+            #[cfg(feature = "postgres")]
+            Some(url) => store::Store::connect(url).await?,
+            #[cfg(not(feature = "postgres"))]
+            Some(_) => anyhow::bail!(
+                "{} was built without Postgres support, so it can't use a store URL; unset \
+                 --store-url or MATRIX_STORE_URL, or build it with the `postgres` feature",
+                self.name
+            ),
+        };
+        let _ = self.store.set(store.clone());
+        Ok(store)
+    }
+
+    /// Back up `store`'s room settings and user preferences to the bot's
+    /// account data, restoring them from it when the account's copy is
+    /// newer, like after the store was recreated or moved hosts.
+    ///
+    /// This must be called after [`Bot::initial_sync`], which fetches the
+    /// account's copies. The first restore happens before this returns, so
+    /// call it before adding handlers that read the settings.
+    pub async fn enable_settings_backup(&mut self, store: &store::Store) {
+        let task = mirror::start(
+            self.name.clone(),
+            self.client.clone(),
+            store.clone(),
+            self.shutdown.subscribe(),
+        )
+        .await;
+        self.tasks.push(task);
+    }
+
+    /// Share rooms out between the instances of the bot keeping `store` in
+    /// the same database, if [`ScaleConfig::sharded`] is set.
+    pub fn enable_sharding(&mut self, store: &store::Store) -> anyhow::Result<()> {
+        if !self.config.scale.sharded {
+            return Ok(());
+        }
+        if store.is_sqlite() {
+            anyhow::bail!("sharding needs a store every instance can reach; set the store URL");
+        }
+        let device_id = self.client.device_id().context("not logged in")?;
+        let task = shard::start(
+            device_id.to_string(),
+            store.clone(),
+            self.shutdown.subscribe(),
+        )?;
+        self.tasks.push(task);
+        Ok(())
+    }
+
+    /// Sync once to skip past messages, then tidy up the bot's devices.
+    ///
+    /// Autojoining is set up before syncing, as it should also act on
+    /// invites received while the bot was offline. Handlers that should only
+    /// see new events must be added after this returns.
+    ///
+    /// When restarting with [`Delivery::AtLeastOnce`], nothing is skipped:
+    /// events since the last handled sync batch are left for [`Bot::run`]
+    /// to handle again.
+    pub async fn initial_sync(&mut self) -> anyhow::Result<()> {
+        // Whatever the bot did since logging in, like opening its stores
+        self.startup.finish("setting up");
+
+        // handler for autojoin
+        // Handers here run for historic messages too
+        self.client.add_event_handler(on_stripped_state_member);
+
+        if self.replay {
+            info!("Resuming from the last handled sync batch");
+        } else {
+            self.skip_past_messages().await?;
+        }
+        self.startup.finish("initial sync");
+
+        self.manage_devices().await?;
+        self.startup.finish("managing devices");
+        Ok(())
+    }
+
+    async fn skip_past_messages(&mut self) -> anyhow::Result<()> {
+        info!("Launching a first sync to ignore past messages…");
+
+        // Let's ignore messages before the program was launched.
+        // This is a loop in case the initial sync is longer than our timeout. The
+        // server should cache the response and it will ultimately take less time to
+        // receive.
+        loop {
+            match self.client.sync_once(self.sync_settings.clone()).await {
+                Ok(response) => {
+                    // This is the last time we need to provide this token, the sync method after
+                    // will handle it on its own.
+                    self.sync_settings = self
+                        .sync_settings
+                        .clone()
+                        .token(response.next_batch.clone());
+                    session::persist_sync_token(&self.session_file, response.next_batch).await?;
+                    break;
+                }
+                Err(error) => {
+                    warn!("An error occurred during initial sync: {error}");
+                }
+            }
+        }
+        info!("Initial sync done");
+        Ok(())
+    }
+
+    async fn manage_devices(&self) -> anyhow::Result<()> {
+        let client = &self.client;
+        let config = &self.config;
+
+        let current_session = client.device_id().map(|d| d.to_owned());
+        if config.delete_other_devices {
+            info!(
+                current_session = format!("{current_session:?}"),
+                "Checking for other devices to delete"
+            );
+            let other_devices: Vec<_> = client
+                .devices()
+                .await?
+                .devices
+                .iter()
+                .filter(|device| Some(&device.device_id) != current_session.as_ref())
+                .map(|device| device.device_id.clone())
+                .collect();
+            if !other_devices.is_empty() {
+                trace!(
+                    current_session = format!("{current_session:?}"),
+                    other_devices = format!("{other_devices:?}"),
+                    "Deleting other devices"
+                );
+                client
+                    .delete_devices(
+                        &other_devices,
+                        Some(AuthData::Password(Password::new(
+                            UserIdentifier::UserIdOrLocalpart(config.username.clone()),
+                            config.password(),
+                        ))),
+                    )
+                    .await?;
+            }
+        }
+
+        if config.set_device_name {
+            if let Some(current_session) = current_session {
+                info!(
+                    current_session = format!("{current_session:?}"),
+                    "Renaming device to {}", &self.device_name
+                );
+                client
+                    .rename_device(&current_session, &self.device_name)
+                    .await?;
+            } else {
+                warn!("No device ID found, cannot name device");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Let people react to any message with ⏰ to be reminded about it later
+    /// in a DM.
+    ///
+    /// Like other handlers for new events, this must be enabled after
+    /// [`Bot::initial_sync`].
+    pub fn enable_snooze(&self) -> anyhow::Result<()> {
+        snooze::enable(&self.client, &self.data_dir)
+    }
+
+    /// Start serving HTTP, stopping when the bot shuts down.
+    ///
+    /// `/health` is added to the routes, reporting whether the bot is ready
+    /// and how long starting up took, as is `/metrics` for Prometheus, and
+    /// so is the admin API if it's configured.
+    pub async fn serve_http(&mut self, server: HttpServer) -> anyhow::Result<()> {
+        let task = server
+            .health(self.startup.clone())
+            .metrics()
+            .admin(
+                self.config.admin_token.clone(),
+                admin::Admin {
+                    client: self.client.clone(),
+                    store: self.store.clone(),
+                    stop: self.stop.clone(),
+                },
+            )?
+            .start(self.shutdown.subscribe())
+            .await?;
+        self.tasks.push(task);
+        Ok(())
+    }
+
+    /// Listen for commands from the bot's `ctl` subcommand on a Unix socket
+    /// in its data directory, calling `reload` when asked to reload.
+    #[cfg(unix)]
+    pub fn enable_control(
+        &mut self,
+        reload: impl Fn() -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> anyhow::Result<()> {
+        let task = control::listen(
+            &self.data_dir,
+            self.client.clone(),
+            self.startup.clone(),
+            Arc::new(reload),
+            self.shutdown.subscribe(),
+        )?;
+        self.tasks.push(task);
+        Ok(())
+    }
+
+    /// Serve the [D-Bus interface](dbus) on the session bus, for desktop
+    /// tools to check on and pause the bot.
+    #[cfg(target_os = "linux")]
+    pub async fn enable_dbus(&mut self) -> anyhow::Result<()> {
+        let task = dbus::serve(
+            &self.name,
+            self.client.clone(),
+            self.startup.clone(),
+            self.workers.clone(),
+            self.egress.clone(),
+            self.shutdown.subscribe(),
+        )
+        .await?;
+        self.tasks.push(task);
+        Ok(())
+    }
+
+    /// Sync forever, persisting the token of the last fully handled sync
+    /// batch every so often and when stopping.
+    ///
+    /// This loops until an error happens or the program is asked to stop with
+    /// SIGINT or SIGTERM, or through the admin API, when it lets anything
+    /// else running finish first.
+    pub async fn run(self) -> anyhow::Result<()> {
+        let Self {
+            client,
+            session_file,
+            sync_settings,
+            shutdown,
+            stop,
+            tasks,
+            workers,
+            journal,
+            quarantine,
+            help,
+            startup,
+            config,
+            mut leader,
+            ..
+        } = self;
+        // What crashed last time counts towards quarantining it
+        for crashed in journal.crashed() {
+            let error = crashed.panic.as_deref().unwrap_or("the bot crashed");
+            quarantine.failed(&crashed.room_id, &crashed.event_id, error);
+        }
+        journal.begin();
+        client.add_event_handler(quiet::on_quiet);
+        client.add_event_handler(rooms::on_canonical_alias);
+        help.add("!quiet", "See or change this room's quiet hours");
+        client.add_event_handler(status::on_status);
+        help.add("!status", "See how I'm getting on with the homeserver");
+        if let Some(admin_room) = config.admin_room.clone() {
+            client.add_event_handler(move |event: OriginalSyncRoomMessageEvent, room: Room| {
+                loglevel::on_loglevel(event, room, admin_room.clone())
+            });
+            help.add("!loglevel", "Change what's logged (admin room only)");
+        }
+        if config.maubot_compat {
+            client.add_event_handler_context(help);
+            client.add_event_handler(commands::on_help);
+        }
+
+        // Handlers are attached between the initial sync and now
+        startup.finish("registering handlers");
+        startup.ready();
+        let sync_tokens = session::SyncTokens::new(&session_file);
+        let sync_tokens = &sync_tokens;
+        let workers = &workers;
+
+        let sync = client.sync_with_result_callback(sync_settings, |sync_result| async move {
+            let response = sync_result?;
+
+            // The handlers have run by now, but may have left work queued
+            // that the token has to wait for
+            let batch = workers.finish_batch();
+
+            // We persist the token to be able to restore our session
+            sync_tokens
+                .update(response.next_batch, batch)
+                .await
+                .map_err(|err| matrix_sdk::Error::UnknownError(err.into()))?;
+
+            Ok(LoopCtrl::Continue)
+        });
+        let result = tokio::select! {
+            result = sync => result.map_err(Into::into),
+            () = shutdown_requested() => {
+                info!("Shutting down");
+                Ok(())
+            }
+            () = stop.notified() => {
+                info!("Shutting down");
+                Ok(())
+            }
+            () = replay::finished() => {
+                workers.idle().await;
+                replay::report();
+                Ok(())
+            }
+            () = lost_leadership(leader.as_mut()) => {
+                Err(anyhow::anyhow!(
+                    "might have lost the leader lock, so stopping to let another instance lead"
+                ))
+            }
+        };
+        if let Err(err) = sync_tokens.flush().await {
+            warn!("Failed to save the sync token: {err:#}");
+        }
+
+        shutdown.send_replace(true);
+        for task in tasks {
+            if let Err(err) = task.await {
+                warn!("A task failed while shutting down: {err}");
+            }
+        }
+        journal.close();
+        result
+    }
+}
+
+/// Wait until `leader`'s lock might have been lost, if there is one.
+async fn lost_leadership(leader: Option<&mut Leader>) {
+    match leader {
+        Some(leader) => leader.lost().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for the program to be asked to stop.
+async fn shutdown_requested() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(err) => {
+                warn!("Failed to listen for SIGTERM: {err}");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,115 @@
+//! `!loglevel`, for changing what's logged from the admin room, so a flaky
+//! problem can be caught at a higher level without restarting the bot.
+//!
+//! It's only answered in [`AccountConfig::admin_room`](crate::AccountConfig::admin_room),
+//! for its moderators. Only plain levels and targets can be set, not span
+//! or field filters, which can be slow to check on every event.
+
+use matrix_sdk::{
+    ruma::{events::room::message::OriginalSyncRoomMessageEvent, OwnedRoomOrAliasId},
+    Room, RoomState,
+};
+use tracing::{info, instrument, level_filters::LevelFilter};
+
+use crate::{
+    can_reply, is_moderator, log_filter, reply_notice, reset_log_filter, rooms, set_log_filter,
+    strip_command, text_body,
+};
+
+const HELP: &str = "Usage:
+!loglevel to see what's logged
+!loglevel <level> to log everything at that level, e.g. `!loglevel info`
+!loglevel <level> <target>... to log those targets at it, e.g. `!loglevel debug matrix_sdk::http`
+!loglevel reset to go back to what the bot started with";
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub(crate) async fn on_loglevel(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    admin_room: OwnedRoomOrAliasId,
+) {
+    if room.state() != RoomState::Joined {
+        return;
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return;
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!loglevel")) else {
+        return;
+    };
+    // Anywhere else, it's as if the command doesn't exist
+    match rooms::resolve(&room.client(), &admin_room).await {
+        Ok(admin_room_id) if &*admin_room_id == room.room_id() => {}
+        _ => return,
+    }
+    if !can_reply(&room).await {
+        return;
+    }
+    if !is_moderator(&room, &event.sender).await.unwrap_or(false) {
+        reply_notice(
+            &room,
+            &event,
+            "Only moderators of this room can change what's logged.",
+        )
+        .await;
+        return;
+    }
+    let response = match loglevel(args) {
+        Ok(Some(filter)) => {
+            info!(
+                sender = event.sender.as_str(),
+                filter = filter.as_str(),
+                "Changed the log level from the admin room"
+            );
+            format!("Now logging {filter}.")
+        }
+        Ok(None) => HELP.to_owned(),
+        Err(err) => format!("Couldn't change what's logged: {err:#}"),
+    };
+    reply_notice(&room, &event, response).await;
+}
+
+/// Carry out the command, returning the filter now in use, or `None` if
+/// the arguments need the usage explaining.
+fn loglevel(args: &str) -> anyhow::Result<Option<String>> {
+    let mut args = args.split_whitespace();
+    let Some(level) = args.next() else {
+        return Ok(Some(log_filter()?));
+    };
+    if level == "reset" {
+        return reset_log_filter().map(Some);
+    }
+    let Ok(level) = level.parse::<LevelFilter>() else {
+        return Ok(None);
+    };
+    let targets: Vec<&str> = args.collect();
+    if !targets.iter().all(|target| is_target(target)) {
+        return Ok(None);
+    }
+    let filter = if targets.is_empty() {
+        level.to_string()
+    } else {
+        // Keep what's already set for everything else
+        let mut filter = log_filter()?;
+        for target in targets {
+            if !filter.is_empty() {
+                filter.push(',');
+            }
+            filter += &format!("{target}={level}");
+        }
+        filter
+    };
+    set_log_filter(&filter)?;
+    log_filter().map(Some)
+}
+
+/// Whether `target` looks like a module path, like `matrix_sdk::http`.
+fn is_target(target: &str) -> bool {
+    !target.is_empty()
+        && target.split("::").all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        })
+}
@@ -0,0 +1,268 @@
+//! Fetching and sending media: downloading attachments within a size limit,
+//! thumbnails, working out what a file really is, and re-uploading.
+//!
+//! The SDK uses the authenticated media endpoints when the homeserver
+//! supports them, and decrypts attachments from encrypted rooms as they're
+//! downloaded. Uploads with [`send_file`] are encrypted in encrypted rooms,
+//! and sent through the room's [queue](fn@crate::send) like any other message.
+
+use std::{fmt, io::Cursor};
+
+use anyhow::Context;
+use matrix_sdk::{
+    media::{MediaFormat, MediaRequestParameters, MediaThumbnailSettings},
+    ruma::{
+        events::room::{
+            message::{
+                AudioInfo, AudioMessageEventContent, FileInfo, FileMessageEventContent,
+                ImageMessageEventContent, MessageType, RoomMessageEventContent, VideoInfo,
+                VideoMessageEventContent,
+            },
+            ImageInfo, MediaSource,
+        },
+        OwnedEventId, OwnedMxcUri, UInt,
+    },
+    Client, Room,
+};
+use mime::Mime;
+
+use crate::{origins, send};
+
+/// The media in a message, with what the sender said about it.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub source: MediaSource,
+    pub filename: String,
+    /// The MIME type the sender gave, which may be wrong.
+    pub mimetype: Option<String>,
+    /// The size the sender gave in bytes, which may be wrong.
+    pub size: Option<u64>,
+}
+
+impl Attachment {
+    /// The attachment in an image, file, audio or video message.
+    pub fn from_message(msgtype: &MessageType) -> Option<Self> {
+        let (source, filename, body, info) = match msgtype {
+            MessageType::Image(image) => (
+                &image.source,
+                &image.filename,
+                &image.body,
+                image
+                    .info
+                    .as_ref()
+                    .map(|info| (info.mimetype.clone(), info.size)),
+            ),
+            MessageType::File(file) => (
+                &file.source,
+                &file.filename,
+                &file.body,
+                file.info
+                    .as_ref()
+                    .map(|info| (info.mimetype.clone(), info.size)),
+            ),
+            MessageType::Audio(audio) => (
+                &audio.source,
+                &audio.filename,
+                &audio.body,
+                audio
+                    .info
+                    .as_ref()
+                    .map(|info| (info.mimetype.clone(), info.size)),
+            ),
+            MessageType::Video(video) => (
+                &video.source,
+                &video.filename,
+                &video.body,
+                video
+                    .info
+                    .as_ref()
+                    .map(|info| (info.mimetype.clone(), info.size)),
+            ),
+            _ => return None,
+        };
+        let (mimetype, size) = info.unwrap_or_default();
+        Some(Self {
+            source: source.clone(),
+            filename: filename.clone().unwrap_or_else(|| body.clone()),
+            mimetype,
+            size: size.map(u64::from),
+        })
+    }
+
+    /// Download the attachment, decrypting it if needed, as long as it's no
+    /// bigger than `max_size` bytes.
+    ///
+    /// Fails with [`TooBig`] if it's over the limit, before downloading if
+    /// the sender said how big it is.
+    pub async fn download(&self, client: &Client, max_size: u64) -> anyhow::Result<Vec<u8>> {
+        if let Some(size) = self.size.filter(|&size| size > max_size) {
+            return Err(TooBig { size, max_size }.into());
+        }
+        let data = origins::media(
+            client,
+            &MediaRequestParameters {
+                source: self.source.clone(),
+                format: MediaFormat::File,
+            },
+        )
+        .await
+        .with_context(|| format!("failed to download {}", self.filename))?;
+        // The size in the event is only what the sender claims
+        if data.len() as u64 > max_size {
+            return Err(TooBig {
+                size: data.len() as u64,
+                max_size,
+            }
+            .into());
+        }
+        Ok(data)
+    }
+
+    /// A thumbnail of the attachment from the homeserver, scaled to fit in
+    /// `width` by `height`.
+    pub async fn thumbnail(
+        &self,
+        client: &Client,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(origins::media(
+            client,
+            &MediaRequestParameters {
+                source: self.source.clone(),
+                format: MediaFormat::Thumbnail(MediaThumbnailSettings::new(
+                    UInt::from(width),
+                    UInt::from(height),
+                )),
+            },
+        )
+        .await?)
+    }
+
+    /// What the attachment really is, going by its contents first and then
+    /// what the sender said.
+    pub fn mime(&self, data: &[u8]) -> Mime {
+        sniff(data)
+            .or_else(|| self.mimetype.as_deref()?.parse().ok())
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+    }
+}
+
+/// Media over a bot's size limit.
+#[derive(Debug)]
+pub struct TooBig {
+    pub size: u64,
+    pub max_size: u64,
+}
+
+impl fmt::Display for TooBig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "media is {} bytes, over the limit of {}",
+            self.size, self.max_size
+        )
+    }
+}
+
+impl std::error::Error for TooBig {}
+
+/// Work out what a file is from its first few bytes, for the common types
+/// bots deal with.
+pub fn sniff(data: &[u8]) -> Option<Mime> {
+    let mime = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WEBP"[..]) {
+        "image/webp"
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WAVE"[..]) {
+        "audio/wav"
+    } else if data.starts_with(b"OggS") {
+        "audio/ogg"
+    } else if data.starts_with(b"ID3") || data.starts_with(b"\xff\xfb") {
+        "audio/mpeg"
+    } else if data.starts_with(b"fLaC") {
+        "audio/flac"
+    } else if data.get(4..8) == Some(&b"ftyp"[..]) {
+        if data.get(8..11) == Some(&b"M4A"[..]) {
+            "audio/mp4"
+        } else {
+            "video/mp4"
+        }
+    } else if data.starts_with(b"\x1a\x45\xdf\xa3") {
+        "video/webm"
+    } else if data.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if data.starts_with(b"PK\x03\x04") {
+        "application/zip"
+    } else {
+        return None;
+    };
+    mime.parse().ok()
+}
+
+/// Upload media to the homeserver unencrypted, e.g. to embed in HTML.
+pub async fn upload(client: &Client, mime: &Mime, data: Vec<u8>) -> anyhow::Result<OwnedMxcUri> {
+    Ok(client.media().upload(mime, data, None).await?.content_uri)
+}
+
+/// Send a file to the room, encrypting it if the room is encrypted.
+///
+/// It's uploaded straight away, then waits behind any messages already
+/// queued for the room.
+pub async fn send_file(
+    room: &Room,
+    filename: &str,
+    mime: &Mime,
+    data: Vec<u8>,
+) -> anyhow::Result<OwnedEventId> {
+    let size = UInt::new(data.len() as u64);
+    let source = if room.is_encrypted().await? {
+        let file = room
+            .client()
+            .upload_encrypted_file(mime, &mut Cursor::new(data))
+            .await?;
+        MediaSource::Encrypted(Box::new(file))
+    } else {
+        MediaSource::Plain(upload(&room.client(), mime, data).await?)
+    };
+    let content = RoomMessageEventContent::new(file_message(filename, mime, size, source));
+    send(room, content).await
+}
+
+/// A message with an uploaded file, as an image, audio or video message
+/// when it's one of those.
+fn file_message(
+    filename: &str,
+    mime: &Mime,
+    size: Option<UInt>,
+    source: MediaSource,
+) -> MessageType {
+    let body = filename.to_owned();
+    let mimetype = Some(mime.essence_str().to_owned());
+    let kind = mime.type_();
+    if kind == mime::IMAGE {
+        let mut info = ImageInfo::new();
+        info.mimetype = mimetype;
+        info.size = size;
+        MessageType::Image(ImageMessageEventContent::new(body, source).info(Box::new(info)))
+    } else if kind == mime::AUDIO {
+        let mut info = AudioInfo::new();
+        info.mimetype = mimetype;
+        info.size = size;
+        MessageType::Audio(AudioMessageEventContent::new(body, source).info(Box::new(info)))
+    } else if kind == mime::VIDEO {
+        let mut info = VideoInfo::new();
+        info.mimetype = mimetype;
+        info.size = size;
+        MessageType::Video(VideoMessageEventContent::new(body, source).info(Box::new(info)))
+    } else {
+        let mut info = FileInfo::new();
+        info.mimetype = mimetype;
+        info.size = size;
+        MessageType::File(FileMessageEventContent::new(body, source).info(Box::new(info)))
+    }
+}
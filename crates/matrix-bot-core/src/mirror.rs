@@ -0,0 +1,173 @@
+//! Backing up the bot's settings to its account, so they come back after
+//! the local store is recreated or the bot moves hosts.
+//!
+//! Each room's settings are copied into the room's account data, and
+//! everyone's preferences into the account's global account data, both as
+//! `io.github.jadedblueeyes.bots.<bot>.settings` events. Each copy says
+//! when its settings last changed, and whichever of the local store and the
+//! account has the newer copy wins: a fresh store has none, so it takes
+//! what's on the account.
+
+use std::time::Duration;
+
+use matrix_sdk::{
+    ruma::{
+        events::{GlobalAccountDataEventType, RoomAccountDataEventType},
+        serde::Raw,
+    },
+    Client, Room,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+use crate::store::{self, Settings, Store, USER_PREFS_SCOPE};
+
+/// How often to compare the store with the account.
+const INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// A copy of some settings, as kept in account data.
+#[derive(Serialize, Deserialize)]
+struct Copy<T> {
+    /// When the settings last changed, in seconds since the Unix epoch.
+    updated_at: u64,
+    settings: T,
+}
+
+/// Room account data events come with their content wrapped.
+#[derive(Deserialize)]
+struct RoomCopy<T> {
+    content: Copy<T>,
+}
+
+/// Which copy of some settings to keep.
+enum Newer {
+    Local,
+    Account(u64),
+    Neither,
+}
+
+fn newer(local: Option<u64>, account: Option<u64>) -> Newer {
+    match (local, account) {
+        (local, Some(account)) if local.is_none_or(|local| account > local) => {
+            Newer::Account(account)
+        }
+        (Some(local), account) if account.is_none_or(|account| local > account) => Newer::Local,
+        _ => Newer::Neither,
+    }
+}
+
+/// Bring the store and the account up to date with each other straight
+/// away, then every so often and once more when the bot shuts down.
+pub(crate) async fn start(
+    name: String,
+    client: Client,
+    store: Store,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    let event_type = format!("io.github.jadedblueeyes.bots.{name}.settings");
+    sync(&client, &store, &event_type).await;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                () = tokio::time::sleep(INTERVAL) => {}
+                _ = shutdown.wait_for(|&stopping| stopping) => {
+                    sync(&client, &store, &event_type).await;
+                    return;
+                }
+            }
+            sync(&client, &store, &event_type).await;
+        }
+    })
+}
+
+/// Copy whichever side is newer over the other, for the preferences and
+/// each joined room.
+async fn sync(client: &Client, store: &Store, event_type: &str) {
+    if let Err(err) = sync_user_prefs(client, store, event_type).await {
+        warn!("Failed to back up preferences: {err:#}");
+    }
+    for room in client.joined_rooms() {
+        if let Err(err) = sync_room(&room, store, event_type).await {
+            warn!(
+                room = room.room_id().as_str(),
+                "Failed to back up the room's settings: {err:#}"
+            );
+        }
+    }
+}
+
+async fn sync_user_prefs(client: &Client, store: &Store, event_type: &str) -> anyhow::Result<()> {
+    let event_type = GlobalAccountDataEventType::from(event_type);
+    let account: Option<Copy<_>> = deserialize(
+        client
+            .account()
+            .account_data_raw(event_type.clone())
+            .await?
+            .map(Raw::cast),
+    );
+    let local = store.settings_updated(USER_PREFS_SCOPE)?;
+    match newer(local, account.as_ref().map(|copy| copy.updated_at)) {
+        Newer::Account(updated_at) => {
+            let settings = account.map(|copy| copy.settings).unwrap_or_default();
+            store.restore_user_prefs(&settings, updated_at)?;
+            info!("Restored preferences from the account");
+        }
+        Newer::Local => {
+            let copy = Copy {
+                updated_at: local.unwrap_or_else(store::now),
+                settings: store.user_prefs()?,
+            };
+            client
+                .account()
+                .set_account_data_raw(event_type, Raw::new(&copy)?.cast())
+                .await?;
+            debug!("Backed up preferences to the account");
+        }
+        Newer::Neither => {}
+    }
+    Ok(())
+}
+
+async fn sync_room(room: &Room, store: &Store, event_type: &str) -> anyhow::Result<()> {
+    let event_type = RoomAccountDataEventType::from(event_type);
+    let account: Option<RoomCopy<Settings>> =
+        deserialize(room.account_data(event_type.clone()).await?.map(Raw::cast));
+    let account = account.map(|event| event.content);
+    let local = store.settings_updated(room.room_id().as_str())?;
+    match newer(local, account.as_ref().map(|copy| copy.updated_at)) {
+        Newer::Account(updated_at) => {
+            let settings = account.map(|copy| copy.settings).unwrap_or_default();
+            store.restore_room_settings(room.room_id(), &settings, updated_at)?;
+            info!(
+                room = room.room_id().as_str(),
+                "Restored the room's settings from the account"
+            );
+        }
+        Newer::Local => {
+            let copy = Copy {
+                updated_at: local.unwrap_or_else(store::now),
+                settings: store
+                    .room_settings(room.room_id())?
+                    .into_iter()
+                    .collect::<Settings>(),
+            };
+            room.set_account_data_raw(event_type, Raw::new(&copy)?.cast())
+                .await?;
+            debug!(
+                room = room.room_id().as_str(),
+                "Backed up the room's settings to the account"
+            );
+        }
+        Newer::Neither => {}
+    }
+    Ok(())
+}
+
+/// Read a copy, treating one that doesn't parse as missing so a good local
+/// copy replaces it.
+fn deserialize<T: DeserializeOwned>(raw: Option<Raw<T>>) -> Option<T> {
+    raw?.deserialize()
+        .inspect_err(|err| warn!("Ignoring an unreadable settings backup: {err}"))
+        .ok()
+}
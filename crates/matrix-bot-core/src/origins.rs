@@ -0,0 +1,86 @@
+//! Limiting how many requests for each server's events and media are in
+//! flight at once, so one slow server can't take up all of them.
+//!
+//! Fetching an event or a file from another server goes through the bot's
+//! homeserver, which waits on that server over federation. Media belongs to
+//! the server in its `mxc://` URI. Event IDs don't say where they're from,
+//! so events count against the server that created their room, which is
+//! the one most likely to be asked for them.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, LazyLock, Mutex, OnceLock},
+};
+
+use matrix_sdk::{
+    deserialized_responses::TimelineEvent,
+    media::MediaRequestParameters,
+    ruma::{events::room::MediaSource, EventId, OwnedServerName, ServerName},
+    Client, Room,
+};
+use tokio::sync::Semaphore;
+use tracing::trace;
+
+/// How many requests to one server can be in flight at once, set from
+/// [`AccountConfig::origin_concurrency`](crate::AccountConfig::origin_concurrency).
+static CONCURRENCY: OnceLock<usize> = OnceLock::new();
+
+static SLOTS: LazyLock<Mutex<HashMap<OwnedServerName, Arc<Semaphore>>>> =
+    LazyLock::new(Default::default);
+
+/// Run `request` for something from `origin` once fewer than the limit of
+/// requests to it are in flight.
+pub async fn limit<T>(origin: &ServerName, request: impl Future<Output = T>) -> T {
+    let Some(concurrency) = CONCURRENCY.get().copied().filter(|&limit| limit > 0) else {
+        return request.await;
+    };
+    let slots = {
+        let mut slots = SLOTS.lock().unwrap();
+        // Forget servers nothing is waiting on
+        slots.retain(|_, slots| Arc::strong_count(slots) > 1);
+        slots
+            .entry(origin.to_owned())
+            .or_insert_with(|| Arc::new(Semaphore::new(concurrency)))
+            .clone()
+    };
+    if slots.available_permits() == 0 {
+        trace!(origin = origin.as_str(), "Waiting for a request slot");
+        metrics::counter!("bot_origin_waits_total").increment(1);
+    }
+    // The semaphore is never closed
+    let _permit = slots.acquire().await.unwrap();
+    request.await
+}
+
+/// Fetch an event from the homeserver with [`Room::event`], within the
+/// limit for the server that created the room.
+pub async fn event(room: &Room, event_id: &EventId) -> matrix_sdk::Result<TimelineEvent> {
+    match room.room_id().server_name() {
+        Some(origin) => limit(origin, room.event(event_id, None)).await,
+        None => room.event(event_id, None).await,
+    }
+}
+
+/// Fetch media with
+/// [`Media::get_media_content`](matrix_sdk::media::Media::get_media_content),
+/// using the cache, within
+/// the limit for the server the media is on.
+pub async fn media(
+    client: &Client,
+    request: &MediaRequestParameters,
+) -> matrix_sdk::Result<Vec<u8>> {
+    let uri = match &request.source {
+        MediaSource::Plain(uri) => uri,
+        MediaSource::Encrypted(file) => &file.url,
+    };
+    let fetch = client.media().get_media_content(request, true);
+    match uri.server_name() {
+        Ok(origin) => limit(origin, fetch).await,
+        Err(_) => fetch.await,
+    }
+}
+
+pub(crate) fn set_concurrency(concurrency: usize) {
+    let _ = CONCURRENCY.set(concurrency);
+}
@@ -0,0 +1,108 @@
+//! Pacing the bot's own messages to each homeserver, so it backs off before
+//! the homeserver has to throttle it.
+//!
+//! Each homeserver starts with no gap between messages. Being rate limited
+//! doubles the gap, to at least half of what the homeserver asked the bot to
+//! wait, and failing to send for other reasons widens it by half. Each
+//! message sent fine narrows it by a tenth until it's gone again. The gaps
+//! are shown by `!status`.
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+use tracing::debug;
+
+/// The gap to start from when a homeserver first pushes back.
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The widest the gap gets.
+const MAX_INTERVAL: Duration = Duration::from_secs(10);
+
+static LIMITS: LazyLock<Mutex<HashMap<String, Limit>>> = LazyLock::new(Default::default);
+
+#[derive(Clone, Copy)]
+struct Limit {
+    interval: Duration,
+    /// When the next message to the homeserver can go.
+    next: Instant,
+}
+
+/// Wait for the homeserver's turn to be sent a message.
+pub(crate) async fn wait(homeserver: &str) {
+    let at = {
+        let mut limits = LIMITS.lock().unwrap();
+        let Some(limit) = limits.get_mut(homeserver) else {
+            return;
+        };
+        let at = limit.next.max(Instant::now());
+        limit.next = at + limit.interval;
+        at
+    };
+    tokio::time::sleep_until(at).await;
+}
+
+/// A message reached the homeserver.
+pub(crate) fn sent(homeserver: &str) {
+    let mut limits = LIMITS.lock().unwrap();
+    let Some(limit) = limits.get_mut(homeserver) else {
+        return;
+    };
+    limit.interval = limit.interval.mul_f64(0.9);
+    if limit.interval < MIN_INTERVAL {
+        debug!(homeserver, "No longer pacing messages");
+        limits.remove(homeserver);
+        metrics::gauge!("bot_send_interval_seconds", "homeserver" => homeserver.to_owned())
+            .set(0.0);
+    } else {
+        set_gauge(homeserver, limit.interval);
+    }
+}
+
+/// The homeserver rate limited the bot, asking it to wait `retry_after` if
+/// it said.
+pub(crate) fn limited(homeserver: &str, retry_after: Option<Duration>) {
+    tighten(homeserver, |interval| {
+        (interval * 2).max(retry_after.unwrap_or_default() / 2)
+    });
+}
+
+/// A message couldn't be sent to the homeserver for some other reason.
+pub(crate) fn failed(homeserver: &str) {
+    tighten(homeserver, |interval| interval.mul_f64(1.5));
+}
+
+fn tighten(homeserver: &str, widen: impl FnOnce(Duration) -> Duration) {
+    let mut limits = LIMITS.lock().unwrap();
+    let limit = limits.entry(homeserver.to_owned()).or_insert(Limit {
+        interval: Duration::ZERO,
+        next: Instant::now(),
+    });
+    limit.interval = widen(limit.interval).clamp(MIN_INTERVAL, MAX_INTERVAL);
+    debug!(
+        homeserver,
+        interval_ms = limit.interval.as_millis(),
+        "Pacing messages"
+    );
+    set_gauge(homeserver, limit.interval);
+}
+
+fn set_gauge(homeserver: &str, interval: Duration) {
+    metrics::gauge!("bot_send_interval_seconds", "homeserver" => homeserver.to_owned())
+        .set(interval.as_secs_f64());
+}
+
+/// The homeservers messages are being paced to, and the gap between them.
+pub fn limits() -> Vec<(String, Duration)> {
+    let mut limits: Vec<_> = LIMITS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(homeserver, limit)| (homeserver.clone(), limit.interval))
+        .collect();
+    limits.sort();
+    limits
+}
@@ -0,0 +1,339 @@
+//! Who may run which commands where.
+//!
+//! Someone has a [`Permission`] in a room if they're one of the admins in
+//! the bot's config, they've been made an admin of a space the room is
+//! under, their power level is high enough, or the room has given them the
+//! permission's role with a [`ROLES_EVENT_TYPE`] state event.
+//! That event's state key is the user ID, and its content lists their roles:
+//!
+//! ```json
+//! { "roles": ["faq", "polls"] }
+//! ```
+//!
+//! Only people with the power to send that state event can hand out roles,
+//! moderators by default. The role `*` stands for every role.
+//!
+//! A room is under a space if it's one of the space's children, or under
+//! one of its subspaces, going by the `m.space.child` events the bot can
+//! see. So the bot has to be in a space and its subspaces for their admins
+//! to be recognised.
+//!
+//! Lookups are cached for a short while, and forgotten as soon as a room's
+//! power levels or roles, or a space's children, change.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use matrix_sdk::{
+    deserialized_responses::RawAnySyncOrStrippedState,
+    ruma::{
+        events::{
+            space::child::SpaceChildEventContent, AnySyncStateEvent, StateEventType,
+            SyncOrStrippedState, SyncStateEvent,
+        },
+        serde::Raw,
+        OwnedRoomId, OwnedRoomOrAliasId, OwnedUserId, RoomId, UserId,
+    },
+    Client, Room,
+};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::{rooms, MODERATOR_POWER_LEVEL};
+
+/// The state event type rooms give people roles with.
+pub const ROLES_EVENT_TYPE: &str = "io.github.jadedblueeyes.bots.roles";
+
+/// The role that grants every permission.
+const ANY_ROLE: &str = "*";
+
+/// How long a lookup is trusted without a change to the room's state.
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Parser, Debug, Clone, Default)]
+pub struct PermissionConfig {
+    /// Users who may run any command in any room, separated by commas
+    #[arg(long = "admin", value_delimiter = ',', env = "BOT_ADMINS")]
+    pub admins: Vec<OwnedUserId>,
+    /// Users who may run any command in the rooms under a space, as
+    /// `<space>=<user>` separated by commas, with the space's ID or alias
+    #[arg(long = "space-admin", value_delimiter = ',', value_parser = parse_space_admin, env = "BOT_SPACE_ADMINS")]
+    pub space_admins: Vec<(OwnedRoomOrAliasId, OwnedUserId)>,
+}
+
+fn parse_space_admin(delegation: &str) -> Result<(OwnedRoomOrAliasId, OwnedUserId), String> {
+    let (space, user_id) = delegation.split_once('=').ok_or_else(|| {
+        format!("expected #space:example.org=@user:example.org, got {delegation}")
+    })?;
+    let space =
+        OwnedRoomOrAliasId::try_from(space.trim()).map_err(|err| format!("{space}: {err}"))?;
+    let user_id =
+        OwnedUserId::try_from(user_id.trim()).map_err(|err| format!("{user_id}: {err}"))?;
+    Ok((space, user_id))
+}
+
+/// Something a command needs permission for.
+#[derive(Debug, Clone, Copy)]
+pub struct Permission {
+    /// The role that grants it.
+    pub role: &'static str,
+    /// The power level that grants it without the role, or `None` if only
+    /// the role and admins do.
+    pub power_level: Option<i64>,
+}
+
+impl Permission {
+    /// A permission moderators have, and others can be given with `role`.
+    pub const fn moderator(role: &'static str) -> Self {
+        Self {
+            role,
+            power_level: Some(MODERATOR_POWER_LEVEL),
+        }
+    }
+
+    /// The uniform message for someone who doesn't have the permission.
+    pub fn denial(&self) -> String {
+        match self.power_level {
+            Some(power_level) => format!(
+                "You need the {} role or a power level of {power_level} to do that here.",
+                self.role
+            ),
+            None => format!("You need the {} role to do that here.", self.role),
+        }
+    }
+}
+
+/// What a user can do in a room, as last looked up.
+#[derive(Debug, Clone)]
+struct Access {
+    power_level: i64,
+    roles: Vec<String>,
+    looked_up: Instant,
+}
+
+#[derive(Deserialize)]
+struct RolesEvent {
+    content: RolesContent,
+}
+
+#[derive(Deserialize)]
+struct RolesContent {
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+/// The rooms under a space, as last looked up.
+#[derive(Debug, Clone)]
+struct Hierarchy {
+    rooms: HashSet<OwnedRoomId>,
+    looked_up: Instant,
+}
+
+/// Checks permissions for a bot. Cloning it shares the cache.
+#[derive(Clone)]
+pub struct Permissions {
+    admins: Arc<HashSet<OwnedUserId>>,
+    space_admins: Arc<Vec<(OwnedRoomOrAliasId, OwnedUserId)>>,
+    cache: Arc<Mutex<HashMap<(OwnedRoomId, OwnedUserId), Access>>>,
+    hierarchies: Arc<Mutex<HashMap<OwnedRoomId, Hierarchy>>>,
+}
+
+impl Permissions {
+    /// Start checking permissions, keeping the cache up to date with the
+    /// rooms `client` syncs.
+    pub fn new(client: &Client, config: PermissionConfig) -> Self {
+        let permissions = Self {
+            admins: Arc::new(config.admins.into_iter().collect()),
+            space_admins: Arc::new(config.space_admins),
+            cache: Default::default(),
+            hierarchies: Default::default(),
+        };
+        client.add_event_handler({
+            let permissions = permissions.clone();
+            move |event: Raw<AnySyncStateEvent>, room: Room| {
+                let permissions = permissions.clone();
+                async move { permissions.on_state(event, room) }
+            }
+        });
+        permissions
+    }
+
+    /// Whether `user_id` is one of the bot's admins.
+    pub fn is_admin(&self, user_id: &UserId) -> bool {
+        self.admins.contains(user_id)
+    }
+
+    /// Whether `user_id` is one of the bot's admins, or an admin of a space
+    /// the room is under.
+    pub async fn is_room_admin(&self, room: &Room, user_id: &UserId) -> bool {
+        if self.is_admin(user_id) {
+            return true;
+        }
+        let client = room.client();
+        for (space, admin) in self.space_admins.iter() {
+            if admin != user_id {
+                continue;
+            }
+            let space_id = match rooms::resolve(&client, space).await {
+                Ok(space_id) => space_id,
+                Err(err) => {
+                    warn!(space = space.as_str(), "Failed to resolve a space: {err:#}");
+                    continue;
+                }
+            };
+            if self.is_under(&client, &space_id, room.room_id()).await {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether `user_id` has `permission` in the room.
+    pub async fn allowed(
+        &self,
+        room: &Room,
+        user_id: &UserId,
+        permission: Permission,
+    ) -> anyhow::Result<bool> {
+        if self.is_room_admin(room, user_id).await {
+            return Ok(true);
+        }
+        let access = self.access(room, user_id).await?;
+        Ok(permission
+            .power_level
+            .is_some_and(|power_level| access.power_level >= power_level)
+            || access
+                .roles
+                .iter()
+                .any(|role| role == permission.role || role == ANY_ROLE))
+    }
+
+    /// Check `user_id` has `permission` in the room, returning the message
+    /// to send them if they don't.
+    pub async fn require(
+        &self,
+        room: &Room,
+        user_id: &UserId,
+        permission: Permission,
+    ) -> anyhow::Result<Option<String>> {
+        Ok((!self.allowed(room, user_id, permission).await?).then(|| permission.denial()))
+    }
+
+    async fn access(&self, room: &Room, user_id: &UserId) -> anyhow::Result<Access> {
+        let key = (room.room_id().to_owned(), user_id.to_owned());
+        if let Some(access) = self.cache.lock().unwrap().get(&key) {
+            if access.looked_up.elapsed() < CACHE_TTL {
+                return Ok(access.clone());
+            }
+        }
+
+        let power_level = room
+            .get_member(user_id)
+            .await?
+            .map_or(0, |member| member.power_level());
+        let roles = match room
+            .get_state_event(StateEventType::from(ROLES_EVENT_TYPE), user_id.as_str())
+            .await?
+        {
+            Some(RawAnySyncOrStrippedState::Sync(raw)) => raw.deserialize_as::<RolesEvent>().ok(),
+            Some(RawAnySyncOrStrippedState::Stripped(raw)) => raw.deserialize_as().ok(),
+            None => None,
+        }
+        .map(|event| event.content.roles)
+        .unwrap_or_default();
+        let access = Access {
+            power_level,
+            roles,
+            looked_up: Instant::now(),
+        };
+        self.cache.lock().unwrap().insert(key, access.clone());
+        Ok(access)
+    }
+
+    /// Whether the room is the space or under it.
+    async fn is_under(&self, client: &Client, space_id: &RoomId, room_id: &RoomId) -> bool {
+        if space_id == room_id {
+            return true;
+        }
+        if let Some(hierarchy) = self.hierarchies.lock().unwrap().get(space_id) {
+            if hierarchy.looked_up.elapsed() < CACHE_TTL {
+                return hierarchy.rooms.contains(room_id);
+            }
+        }
+        let hierarchy = Hierarchy {
+            rooms: rooms_under(client, space_id).await,
+            looked_up: Instant::now(),
+        };
+        let under = hierarchy.rooms.contains(room_id);
+        self.hierarchies
+            .lock()
+            .unwrap()
+            .insert(space_id.to_owned(), hierarchy);
+        under
+    }
+
+    /// Forget what's cached for a room when its power levels or roles
+    /// change, and every space's rooms when any space's children do.
+    fn on_state(&self, event: Raw<AnySyncStateEvent>, room: Room) {
+        let Ok(Some(event_type)) = event.get_field::<String>("type") else {
+            return;
+        };
+        if event_type == StateEventType::SpaceChild.to_string() {
+            // A change to a subspace changes its parents too
+            debug!(space = room.room_id().as_str(), "Space children changed");
+            self.hierarchies.lock().unwrap().clear();
+            return;
+        }
+        if event_type != StateEventType::RoomPowerLevels.to_string()
+            && event_type != ROLES_EVENT_TYPE
+        {
+            return;
+        }
+        debug!(room = room.room_id().as_str(), "Permissions changed");
+        self.cache
+            .lock()
+            .unwrap()
+            .retain(|(room_id, _), _| room_id != room.room_id());
+    }
+}
+
+/// Every room under a space, through its subspaces, as far as the bot can
+/// see them.
+async fn rooms_under(client: &Client, space_id: &RoomId) -> HashSet<OwnedRoomId> {
+    let mut rooms = HashSet::new();
+    let mut spaces = vec![space_id.to_owned()];
+    while let Some(space_id) = spaces.pop() {
+        // Rooms the bot isn't in are only children, as far as it knows
+        let Some(space) = client.get_room(&space_id) else {
+            continue;
+        };
+        let events = match space
+            .get_state_events_static::<SpaceChildEventContent>()
+            .await
+        {
+            Ok(events) => events,
+            Err(err) => {
+                warn!(
+                    space = space_id.as_str(),
+                    "Failed to look up a space's children: {err}"
+                );
+                continue;
+            }
+        };
+        for raw in events {
+            let Ok(SyncOrStrippedState::Sync(SyncStateEvent::Original(event))) = raw.deserialize()
+            else {
+                continue;
+            };
+            // Children are removed by emptying their via
+            if !event.content.via.is_empty() && rooms.insert(event.state_key.clone()) {
+                spaces.push(event.state_key);
+            }
+        }
+    }
+    rooms
+}
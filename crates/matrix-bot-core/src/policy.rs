@@ -0,0 +1,156 @@
+//! Ban rules from policy lists, shared by the bots that enforce them.
+
+use futures_util::{stream, StreamExt};
+use matrix_sdk::{
+    ruma::{
+        events::{
+            policy::rule::{
+                server::PolicyRuleServerEventContent, user::PolicyRuleUserEventContent,
+                PolicyRuleEventContent, Recommendation,
+            },
+            SyncOrStrippedState, SyncStateEvent,
+        },
+        OwnedRoomId, ServerName, UserId,
+    },
+    Client,
+};
+use tracing::warn;
+
+/// How many policy lists are loaded at once.
+const MAX_CONCURRENT_LISTS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    User,
+    Server,
+}
+
+/// A ban from a policy list (MSC2313). Entities may contain `*` and `?`
+/// globs.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub kind: RuleKind,
+    pub entity: String,
+    pub reason: String,
+    /// The policy list the rule came from.
+    pub list: OwnedRoomId,
+}
+
+impl Rule {
+    /// Turn a policy event into a rule, if it recommends a ban.
+    pub fn from_content(
+        kind: RuleKind,
+        content: &PolicyRuleEventContent,
+        list: OwnedRoomId,
+    ) -> Option<Self> {
+        (content.recommendation == Recommendation::Ban).then(|| Self {
+            kind,
+            entity: content.entity.clone(),
+            reason: content.reason.clone(),
+            list,
+        })
+    }
+
+    /// Whether the rule bans `user_id`, either directly or by their server.
+    pub fn matches_user(&self, user_id: &UserId) -> bool {
+        match self.kind {
+            RuleKind::User => glob_matches(&self.entity, user_id.as_str()),
+            RuleKind::Server => self.matches_server(user_id.server_name()),
+        }
+    }
+
+    pub fn matches_server(&self, server_name: &ServerName) -> bool {
+        self.kind == RuleKind::Server && glob_matches(&self.entity, server_name.as_str())
+    }
+}
+
+/// Load the ban rules from every watched policy list, a few lists at a
+/// time.
+pub async fn load_rules(client: &Client, lists: &[OwnedRoomId]) -> Vec<Rule> {
+    stream::iter(lists)
+        .map(|list| load_list(client, list))
+        .buffered(MAX_CONCURRENT_LISTS)
+        .concat()
+        .await
+}
+
+async fn load_list(client: &Client, list: &OwnedRoomId) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    let Some(room) = client.get_room(list) else {
+        warn!(list = list.as_str(), "Not in a watched policy list");
+        return rules;
+    };
+
+    match room
+        .get_state_events_static::<PolicyRuleUserEventContent>()
+        .await
+    {
+        Ok(events) => {
+            for event in events {
+                if let Ok(SyncOrStrippedState::Sync(SyncStateEvent::Original(event))) =
+                    event.deserialize()
+                {
+                    rules.extend(Rule::from_content(
+                        RuleKind::User,
+                        &event.content.0,
+                        list.clone(),
+                    ));
+                }
+            }
+        }
+        Err(err) => warn!(list = list.as_str(), "Failed to load user rules: {err}"),
+    }
+
+    match room
+        .get_state_events_static::<PolicyRuleServerEventContent>()
+        .await
+    {
+        Ok(events) => {
+            for event in events {
+                if let Ok(SyncOrStrippedState::Sync(SyncStateEvent::Original(event))) =
+                    event.deserialize()
+                {
+                    rules.extend(Rule::from_content(
+                        RuleKind::Server,
+                        &event.content.0,
+                        list.clone(),
+                    ));
+                }
+            }
+        }
+        Err(err) => warn!(list = list.as_str(), "Failed to load server rules: {err}"),
+    }
+    rules
+}
+
+/// Match `text` against a glob where `*` matches any run of characters and
+/// `?` matches any one character.
+pub fn glob_matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut g, mut t) = (0, 0);
+    // Where to resume from if the current attempt after a `*` fails
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    g = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,432 @@
+//! Moving a bot's state between hosts, with its `config export` and
+//! `config import` subcommands.
+//!
+//! The export is one JSON document holding every table of every SQLite
+//! database in the bot's data directory, so per-room settings, stats,
+//! subscriptions and whatever else the bot keeps, along with the users its
+//! account ignores:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "bot": "matrix-rss",
+//!   "databases": {
+//!     "store.sqlite3": { "feeds": [{ "room_id": "!abc:example.org", "url": "..." }] }
+//!   },
+//!   "ignored_users": ["@spammer:example.org"]
+//! }
+//! ```
+//!
+//! The SDK's own store isn't included, since it's tied to the device and
+//! the bot rebuilds it by syncing. Importing adds the rows, replacing any
+//! with the same key, and ignores the users. Stop the bot first, and start
+//! it once beforehand on a new host so its databases exist.
+//!
+//! A bot given a `--store-url` keeps its room settings, user preferences
+//! and counters in Postgres rather than its data directory, so they can't
+//! be exported this way; back that database up with `pg_dump` instead.
+//! Both subcommands refuse to run with a store URL, rather than leave them
+//! out without saying.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use matrix_sdk::ruma::{events::ignored_user_list::IgnoredUserListEventContent, OwnedUserId};
+use rusqlite::{types::Value as SqlValue, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::session;
+
+/// The version of the document's format.
+const VERSION: u32 = 1;
+
+/// The `config` subcommand.
+#[derive(Parser, Debug)]
+#[command(name = "config")]
+struct Portable {
+    #[command(subcommand)]
+    command: Command,
+    /// The bot's data directory, if it was given one when started
+    #[arg(long, global = true, env = "MATRIX_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// The bot's store URL, if it was given one when started
+    #[arg(long, global = true, env = "MATRIX_STORE_URL")]
+    store_url: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write the bot's state out as JSON
+    Export {
+        /// The file to write to, instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Restore state from an export
+    Import {
+        /// The file to read
+        file: PathBuf,
+        /// Empty each table before restoring it, rather than merging
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+/// Each table's rows, as objects of column names to values.
+type Tables = BTreeMap<String, Vec<BTreeMap<String, Value>>>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Document {
+    version: u32,
+    bot: String,
+    /// Each database's tables, by file name.
+    databases: BTreeMap<String, Tables>,
+    #[serde(default)]
+    ignored_users: Vec<OwnedUserId>,
+}
+
+/// Whether the program was run as `<bot> config ...`, and so should export
+/// or import rather than start the bot.
+pub fn requested() -> bool {
+    std::env::args().nth(1).as_deref() == Some("config")
+}
+
+/// Export or import the state of the bot called `name`, as the arguments
+/// say.
+pub async fn run(name: &str) -> anyhow::Result<()> {
+    let portable = Portable::parse_from(std::env::args().skip(1));
+    if portable.store_url.is_some() {
+        anyhow::bail!(
+            "{name} keeps its room settings, user preferences and counters in Postgres, which \
+             config can't export or import; back that database up with pg_dump instead"
+        );
+    }
+    let data_dir = portable.data_dir.unwrap_or_else(|| crate::data_dir(name));
+    match portable.command {
+        Command::Export { output } => {
+            let document = export(name, &data_dir).await?;
+            let json = serde_json::to_string_pretty(&document)?;
+            match output {
+                Some(output) => std::fs::write(&output, json)
+                    .with_context(|| format!("failed to write {}", output.display()))?,
+                None => println!("{json}"),
+            }
+        }
+        Command::Import { file, replace } => {
+            let json = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            let document: Document = serde_json::from_str(&json)
+                .with_context(|| format!("{} isn't an export", file.display()))?;
+            if document.version > VERSION {
+                anyhow::bail!("the export is from a newer version of the bot");
+            }
+            if document.bot != name {
+                eprintln!("Importing state exported from {} into {name}", document.bot);
+            }
+            import(name, &data_dir, document, replace).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn export(name: &str, data_dir: &Path) -> anyhow::Result<Document> {
+    let mut databases = BTreeMap::new();
+    for file in database_files(data_dir)? {
+        let conn = Connection::open(data_dir.join(&file))?;
+        databases.insert(file, export_tables(&conn)?);
+    }
+
+    let ignored_users = match restore(name, data_dir).await? {
+        Some(client) => client
+            .account()
+            .account_data::<IgnoredUserListEventContent>()
+            .await?
+            .map(|raw| raw.deserialize())
+            .transpose()?
+            .map(|content| content.ignored_users.into_keys().collect())
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+
+    Ok(Document {
+        version: VERSION,
+        bot: name.to_owned(),
+        databases,
+        ignored_users,
+    })
+}
+
+async fn import(
+    name: &str,
+    data_dir: &Path,
+    document: Document,
+    replace: bool,
+) -> anyhow::Result<()> {
+    let existing = database_files(data_dir)?;
+    for (file, tables) in &document.databases {
+        if !existing.contains(file) {
+            anyhow::bail!("{name} has no {file}; start it once so it's created");
+        }
+        let mut conn = Connection::open(data_dir.join(file))?;
+        import_tables(&mut conn, tables, replace)
+            .with_context(|| format!("failed to import into {file}"))?;
+    }
+
+    if !document.ignored_users.is_empty() {
+        let client = restore(name, data_dir)
+            .await?
+            .context("the bot has to have logged in once to import ignored users")?;
+        for user_id in &document.ignored_users {
+            client.account().ignore_user(user_id).await?;
+        }
+        eprintln!("Ignored {} users", document.ignored_users.len());
+    }
+    Ok(())
+}
+
+fn export_tables(conn: &Connection) -> anyhow::Result<Tables> {
+    let mut tables = BTreeMap::new();
+    for table in table_names(conn)? {
+        let mut statement = conn.prepare(&format!("SELECT * FROM \"{table}\""))?;
+        let columns: Vec<String> = statement
+            .column_names()
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        let rows = statement
+            .query_map([], |row| {
+                columns
+                    .iter()
+                    .enumerate()
+                    .map(|(index, column)| Ok((column.clone(), to_json(row.get(index)?))))
+                    .collect()
+            })?
+            .collect::<Result<_, _>>()?;
+        tables.insert(table, rows);
+    }
+    Ok(tables)
+}
+
+/// Import all the tables in one transaction, so a failure leaves the
+/// database as it was.
+fn import_tables(conn: &mut Connection, tables: &Tables, replace: bool) -> anyhow::Result<()> {
+    let existing = table_names(conn)?;
+    let transaction = conn.transaction()?;
+    for (table, rows) in tables {
+        if !existing.contains(table) {
+            anyhow::bail!("there's no {table} table");
+        }
+        // Names are put in the SQL as they are, so only take ones the table has
+        let columns = column_names(&transaction, table)?;
+        if let Some(column) = rows
+            .iter()
+            .flat_map(BTreeMap::keys)
+            .find(|column| !columns.contains(column))
+        {
+            anyhow::bail!("{table} has no {column} column");
+        }
+        if replace {
+            transaction.execute(&format!("DELETE FROM \"{table}\""), [])?;
+        }
+        for row in rows {
+            let columns: Vec<String> = row.keys().map(|column| format!("\"{column}\"")).collect();
+            let placeholders = vec!["?"; row.len()].join(", ");
+            let values = row
+                .values()
+                .map(from_json)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            transaction
+                .execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO \"{table}\" ({}) VALUES ({placeholders})",
+                        columns.join(", ")
+                    ),
+                    rusqlite::params_from_iter(values),
+                )
+                .with_context(|| format!("failed to import a row into {table}"))?;
+        }
+        eprintln!("Imported {} rows into {table}", rows.len());
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The names of the bot's databases, directly in its data directory.
+fn database_files(data_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(data_dir)
+        .with_context(|| format!("failed to read {}", data_dir.display()))?
+    {
+        let entry = entry?;
+        let file = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_file() && file.ends_with(".sqlite3") {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The bot's tables, without SQLite's own.
+fn table_names(conn: &Connection) -> anyhow::Result<Vec<String>> {
+    let mut statement = conn.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' \
+         ORDER BY name",
+    )?;
+    let names = statement
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(names)
+}
+
+/// The names of a table's columns.
+fn column_names(conn: &Connection, table: &str) -> anyhow::Result<Vec<String>> {
+    let mut statement = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+    let names = statement
+        .query_map([table], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(names)
+}
+
+/// The bot's Matrix client from its saved session, if it has one.
+async fn restore(name: &str, data_dir: &Path) -> anyhow::Result<Option<matrix_sdk::Client>> {
+    let session_file = data_dir.join("session");
+    if !session_file.exists() {
+        eprintln!("{name} hasn't logged in, so its ignored users are left out");
+        return Ok(None);
+    }
+    let (client, _) = session::restore_session(&session_file).await?;
+    Ok(Some(client))
+}
+
+fn to_json(value: SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Integer(integer) => integer.into(),
+        SqlValue::Real(real) => real.into(),
+        SqlValue::Text(text) => text.into(),
+        SqlValue::Blob(blob) => blob.into(),
+    }
+}
+
+fn from_json(value: &Value) -> anyhow::Result<SqlValue> {
+    Ok(match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(boolean) => SqlValue::Integer((*boolean).into()),
+        Value::Number(number) => match number.as_i64() {
+            Some(integer) => SqlValue::Integer(integer),
+            None => SqlValue::Real(number.as_f64().unwrap_or_default()),
+        },
+        Value::String(text) => SqlValue::Text(text.clone()),
+        // Blobs are exported as arrays of bytes
+        Value::Array(bytes) => SqlValue::Blob(
+            bytes
+                .iter()
+                .map(|byte| {
+                    byte.as_u64()
+                        .and_then(|byte| u8::try_from(byte).ok())
+                        .context("invalid blob")
+                })
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        Value::Object(_) => anyhow::bail!("columns can't hold objects"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE feeds (room_id TEXT NOT NULL, url TEXT NOT NULL, etag BLOB,
+                PRIMARY KEY (room_id, url));
+            CREATE TABLE scores (user_id TEXT PRIMARY KEY, score INTEGER, ratio REAL);",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn tables_round_trip() {
+        let conn = database();
+        conn.execute_batch(
+            "INSERT INTO feeds VALUES ('!a:example.org', 'https://example.org/feed', x'00ff');
+            INSERT INTO feeds VALUES ('!b:example.org', 'https://example.org/feed', NULL);
+            INSERT INTO scores VALUES ('@a:example.org', -3, 0.5);",
+        )
+        .unwrap();
+        let exported = export_tables(&conn).unwrap();
+        assert_eq!(exported["feeds"][0]["etag"], json!([0, 255]));
+
+        // Through JSON, as it would be written to a file
+        let exported: Tables =
+            serde_json::from_str(&serde_json::to_string(&exported).unwrap()).unwrap();
+        let mut imported = database();
+        import_tables(&mut imported, &exported, false).unwrap();
+        assert_eq!(export_tables(&imported).unwrap(), exported);
+
+        // Importing again replaces the rows with the same key
+        import_tables(&mut imported, &exported, false).unwrap();
+        assert_eq!(export_tables(&imported).unwrap(), exported);
+    }
+
+    #[test]
+    fn replacing_empties_the_tables_first() {
+        let mut conn = database();
+        conn.execute(
+            "INSERT INTO scores VALUES ('@old:example.org', 1, NULL)",
+            [],
+        )
+        .unwrap();
+        let tables: Tables = serde_json::from_value(json!({
+            "scores": [{ "user_id": "@new:example.org", "score": 2 }]
+        }))
+        .unwrap();
+        import_tables(&mut conn, &tables, true).unwrap();
+
+        let users: Vec<String> = export_tables(&conn).unwrap()["scores"]
+            .iter()
+            .map(|row| row["user_id"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(users, ["@new:example.org"]);
+    }
+
+    #[test]
+    fn unknown_columns_are_refused() {
+        let mut conn = database();
+        let tables: Tables = serde_json::from_value(json!({
+            "scores": [
+                { "user_id": "@a:example.org", "score": 1 },
+                { "user_id": "@b:example.org", "score\") VALUES (1); DROP TABLE feeds; --": 1 }
+            ]
+        }))
+        .unwrap();
+        let err = import_tables(&mut conn, &tables, false).unwrap_err();
+        assert!(
+            err.to_string().starts_with("scores has no score\")"),
+            "{err}"
+        );
+
+        // Nothing was imported, and the other table is untouched
+        let exported = export_tables(&conn).unwrap();
+        assert!(exported["scores"].is_empty());
+        assert!(exported.contains_key("feeds"));
+    }
+
+    #[test]
+    fn unknown_tables_are_refused() {
+        let mut conn = database();
+        let tables: Tables = serde_json::from_value(json!({ "nope": [{ "a": 1 }] })).unwrap();
+        let err = import_tables(&mut conn, &tables, false).unwrap_err();
+        assert_eq!(err.to_string(), "there's no nope table");
+    }
+}
@@ -0,0 +1,171 @@
+//! Quarantining events the bot keeps failing on, so one bad event isn't
+//! tried again every time the bot restarts.
+//!
+//! Each time handling an event fails, panics or was cut short by a crash
+//! (going by the [crash journal](crate::journal)), the failure is counted
+//! in `quarantine.sqlite3` in the bot's data directory. Once an event has
+//! failed [`AccountConfig::quarantine_after`](crate::AccountConfig::quarantine_after)
+//! times, it's skipped from then on, and the admin room is told if there is
+//! one.
+
+use std::{path::Path, sync::Arc};
+
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, EventId, OwnedRoomOrAliasId, RoomId},
+    Client,
+};
+use rusqlite::{params, OptionalExtension};
+use tracing::{info, warn};
+
+use crate::{html, rooms, send_or_log_error, store::Store};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE quarantine (
+        event_id TEXT PRIMARY KEY NOT NULL,
+        room_id TEXT NOT NULL,
+        failures INTEGER NOT NULL,
+        last_error TEXT NOT NULL,
+        quarantined_at INTEGER
+    );"];
+
+/// Counts failures and keeps the skip list. Cloning it shares them.
+#[derive(Clone)]
+pub struct Quarantine {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    store: Store,
+    client: Client,
+    /// How many failures quarantine an event, or 0 to never.
+    after: u32,
+    admin_room: Option<OwnedRoomOrAliasId>,
+}
+
+impl Quarantine {
+    pub(crate) fn open(
+        data_dir: &Path,
+        client: Client,
+        after: u32,
+        admin_room: Option<OwnedRoomOrAliasId>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            inner: Arc::new(Inner {
+                store: Store::open(&data_dir.join("quarantine.sqlite3"), MIGRATIONS)?,
+                client,
+                after,
+                admin_room,
+            }),
+        })
+    }
+
+    /// Whether the event has been quarantined.
+    pub fn contains(&self, event_id: &EventId) -> bool {
+        self.inner
+            .store
+            .with(|conn| {
+                conn.query_row(
+                    "SELECT 1 FROM quarantine WHERE event_id = ?1 AND quarantined_at IS NOT NULL",
+                    [event_id.as_str()],
+                    |_| Ok(()),
+                )
+                .optional()
+            })
+            .inspect_err(|err| warn!("Failed to check the quarantine: {err:#}"))
+            .is_ok_and(|found| found.is_some())
+    }
+
+    /// Count a failure to handle an event, quarantining it if it's failed
+    /// too many times.
+    pub fn failed(&self, room_id: &RoomId, event_id: &EventId, error: &str) {
+        let inner = &self.inner;
+        if inner.after == 0 {
+            return;
+        }
+        let failures = inner.store.transaction(|transaction| {
+            transaction.execute(
+                "INSERT INTO quarantine (event_id, room_id, failures, last_error)
+                 VALUES (?1, ?2, 1, ?3)
+                 ON CONFLICT (event_id) DO UPDATE
+                 SET failures = failures + 1, last_error = excluded.last_error",
+                params![event_id.as_str(), room_id.as_str(), error],
+            )?;
+            let failures: u32 = transaction.query_row(
+                "SELECT failures FROM quarantine WHERE event_id = ?1",
+                [event_id.as_str()],
+                |row| row.get(0),
+            )?;
+            if failures == inner.after {
+                transaction.execute(
+                    "UPDATE quarantine SET quarantined_at = ?2 WHERE event_id = ?1",
+                    params![event_id.as_str(), crate::store::now()],
+                )?;
+            }
+            Ok(failures)
+        });
+        let failures = match failures {
+            Ok(failures) => failures,
+            Err(err) => {
+                warn!("Failed to count a failure towards quarantine: {err:#}");
+                return;
+            }
+        };
+        if failures != inner.after {
+            return;
+        }
+
+        warn!(
+            room = room_id.as_str(),
+            event = event_id.as_str(),
+            failures,
+            "Quarantining an event the bot keeps failing on: {error}"
+        );
+        metrics::counter!("bot_events_quarantined_total").increment(1);
+        let Some(admin_room) = inner.admin_room.clone() else {
+            return;
+        };
+        let link = room_id.matrix_to_event_uri(event_id.to_owned()).to_string();
+        let content = RoomMessageEventContent::notice_html(
+            format!("Handling {link} failed {failures} times, so I'm skipping it from now on. The last error was: {error}"),
+            format!(
+                "Handling <a href=\"{}\">an event</a> failed {failures} times, so I'm skipping it \
+                 from now on. The last error was: <code>{}</code>",
+                html::escape(&link),
+                html::escape(error)
+            ),
+        );
+        let client = inner.client.clone();
+        tokio::spawn(async move {
+            let admin_room = match rooms::resolve(&client, &admin_room).await {
+                Ok(room_id) => client.get_room(&room_id),
+                Err(err) => {
+                    warn!(
+                        room = admin_room.as_str(),
+                        "Failed to resolve the admin room: {err:#}"
+                    );
+                    return;
+                }
+            };
+            match admin_room {
+                Some(admin_room) => send_or_log_error(&admin_room, content).await,
+                None => warn!("The admin room isn't one the bot is in"),
+            }
+        });
+    }
+
+    /// Let an event be handled again, returning whether it was quarantined.
+    pub fn release(&self, event_id: &EventId) -> anyhow::Result<bool> {
+        let released = self.inner.store.with(|conn| {
+            conn.execute(
+                "DELETE FROM quarantine WHERE event_id = ?1",
+                [event_id.as_str()],
+            )
+        })? > 0;
+        if released {
+            info!(
+                event = event_id.as_str(),
+                "Released an event from quarantine"
+            );
+        }
+        Ok(released)
+    }
+}
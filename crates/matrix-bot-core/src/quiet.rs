@@ -0,0 +1,501 @@
+//! Quiet hours: times of day a room would rather the bot didn't post, like
+//! nights in a room that uses it socially.
+//!
+//! A room sets them with a [`QUIET_HOURS_EVENT_TYPE`] state event with an
+//! empty state key, or with `!quiet` which has the bot send that event:
+//!
+//! ```json
+//! { "start": "22:00", "end": "07:00", "timezone": "Europe/London", "policy": "queue" }
+//! ```
+//!
+//! The time zone defaults to UTC. With the `drop` policy, the default, what
+//! the bot would have said is dropped, and with `queue` it's sent when quiet
+//! hours end, unless the bot restarts meanwhile. Only messages sent with
+//! [`send_or_log_error`](crate::send_or_log_error), [`reply`](crate::reply)
+//! or [`reply_notice`](crate::reply_notice) are held back; the bot's answers
+//! to `!quiet` itself aren't.
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use matrix_sdk::{
+    deserialized_responses::RawAnySyncOrStrippedState,
+    ruma::{
+        api::client::error::ErrorKind,
+        events::{
+            room::message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+            AnyMessageLikeEventContent, StateEventType,
+        },
+        serde::Raw,
+        OwnedRoomId, RoomId,
+    },
+    Room, RoomState,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    can_reply, is_moderator,
+    send::{self, reply_content},
+    strip_command, text_body,
+};
+
+/// The state event type rooms set quiet hours with.
+pub const QUIET_HOURS_EVENT_TYPE: &str = "io.github.jadedblueeyes.bots.quiet_hours";
+
+/// The most messages held for a room until its quiet hours end.
+const MAX_HELD: usize = 50;
+
+const HELP: &str = "Usage:
+!quiet to see this room's quiet hours
+!quiet 22:00-07:00 [Europe/London] [queue|drop] to stay quiet then, sending or dropping what I'd have said
+!quiet off
+Changing them needs moderator power.";
+
+/// Messages waiting for their room's quiet hours to end.
+static HELD: LazyLock<Mutex<HashMap<OwnedRoomId, Vec<Held>>>> = LazyLock::new(Default::default);
+
+struct Held {
+    event_type: String,
+    content: Raw<AnyMessageLikeEventContent>,
+}
+
+/// What became of a message held for quiet hours.
+#[derive(Debug, PartialEq, Eq)]
+enum Queued {
+    /// The first held for the room, so the one to start releasing them.
+    First,
+    /// Behind others held for the room.
+    Behind,
+    /// Dropped, since the room has as many held as it can.
+    Full,
+}
+
+/// What happens to messages during quiet hours.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    #[default]
+    Drop,
+    Queue,
+}
+
+#[derive(Deserialize)]
+struct QuietHoursEvent {
+    content: QuietHoursContent,
+}
+
+/// The state event's content. Turning quiet hours off leaves it empty.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QuietHoursContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    #[serde(default)]
+    policy: Policy,
+}
+
+/// A room's quiet hours, which may span midnight.
+#[derive(Debug, Clone, Copy)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub timezone: Tz,
+    pub policy: Policy,
+}
+
+impl QuietHours {
+    fn from_content(content: &QuietHoursContent) -> Option<Self> {
+        let start = NaiveTime::parse_from_str(content.start.as_deref()?, "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(content.end.as_deref()?, "%H:%M").ok()?;
+        let timezone = match &content.timezone {
+            Some(timezone) => timezone.parse().ok()?,
+            None => Tz::UTC,
+        };
+        (start != end).then_some(Self {
+            start,
+            end,
+            timezone,
+            policy: content.policy,
+        })
+    }
+
+    fn to_content(self) -> QuietHoursContent {
+        QuietHoursContent {
+            start: Some(self.start.format("%H:%M").to_string()),
+            end: Some(self.end.format("%H:%M").to_string()),
+            timezone: Some(self.timezone.name().to_owned()),
+            policy: self.policy,
+        }
+    }
+
+    /// How long until quiet hours end, if it's quiet hours at `now`.
+    pub fn remaining(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let time = now.with_timezone(&self.timezone).time();
+        let quiet = if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        };
+        if !quiet {
+            return None;
+        }
+        let mut remaining = self.end - time;
+        if remaining < TimeDelta::zero() {
+            remaining += TimeDelta::days(1);
+        }
+        remaining.to_std().ok()
+    }
+}
+
+/// The room's quiet hours, if it has any.
+pub async fn quiet_hours(room: &Room) -> Option<QuietHours> {
+    let event = room
+        .get_state_event(StateEventType::from(QUIET_HOURS_EVENT_TYPE), "")
+        .await
+        .inspect_err(|err| warn!("Failed to look up quiet hours: {err}"))
+        .ok()??;
+    let event: QuietHoursEvent = match event {
+        RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as().ok()?,
+        RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as().ok()?,
+    };
+    QuietHours::from_content(&event.content)
+}
+
+/// Drop or hold a message if it's quiet hours in the room, returning whether
+/// it was.
+pub(crate) async fn hold(
+    room: &Room,
+    event_type: &str,
+    content: &Raw<AnyMessageLikeEventContent>,
+) -> bool {
+    let Some(quiet) = quiet_hours(room).await else {
+        return false;
+    };
+    let Some(remaining) = quiet.remaining(Utc::now()) else {
+        return false;
+    };
+    let room_id = room.room_id();
+    match quiet.policy {
+        Policy::Drop => {
+            debug!(room = room_id.as_str(), "Dropping a message in quiet hours");
+            metrics::counter!("bot_quiet_hours_messages_total", "policy" => "drop").increment(1);
+        }
+        Policy::Queue => {
+            let queued = queue(room_id, event_type, content);
+            if queued == Queued::Full {
+                debug!(
+                    room = room_id.as_str(),
+                    "Too many messages held for quiet hours, dropping one"
+                );
+                metrics::counter!("bot_quiet_hours_messages_total", "policy" => "overflow")
+                    .increment(1);
+                return true;
+            }
+            metrics::counter!("bot_quiet_hours_messages_total", "policy" => "queue").increment(1);
+            debug!(
+                room = room_id.as_str(),
+                "Holding a message until quiet hours end"
+            );
+            if queued == Queued::First {
+                tokio::spawn(release(room.clone(), remaining));
+            }
+        }
+    }
+    true
+}
+
+/// Hold a message for the room until its quiet hours end.
+fn queue(room_id: &RoomId, event_type: &str, content: &Raw<AnyMessageLikeEventContent>) -> Queued {
+    let mut held = HELD.lock().unwrap();
+    let first = !held.contains_key(room_id);
+    let queue = held.entry(room_id.to_owned()).or_default();
+    if queue.len() >= MAX_HELD {
+        return Queued::Full;
+    }
+    queue.push(Held {
+        event_type: event_type.to_owned(),
+        content: content.clone(),
+    });
+    if first {
+        Queued::First
+    } else {
+        Queued::Behind
+    }
+}
+
+/// Send the room's held messages once its quiet hours are over, waiting
+/// longer if they're changed meanwhile.
+async fn release(room: Room, mut remaining: Duration) {
+    loop {
+        tokio::time::sleep(remaining).await;
+        match quiet_hours(&room)
+            .await
+            .filter(|quiet| quiet.policy == Policy::Queue)
+            .and_then(|quiet| quiet.remaining(Utc::now()))
+        {
+            Some(longer) => remaining = longer,
+            None => break,
+        }
+    }
+
+    let held = HELD
+        .lock()
+        .unwrap()
+        .remove(room.room_id())
+        .unwrap_or_default();
+    info!(
+        room = room.room_id().as_str(),
+        count = held.len(),
+        "Quiet hours are over, sending held messages"
+    );
+    for message in held {
+        if let Err(err) = send::send_raw(&room, message.event_type, message.content).await {
+            send::log_error(&room, &err);
+        }
+    }
+}
+
+/// Show or change the room's quiet hours.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub(crate) async fn on_quiet(event: OriginalSyncRoomMessageEvent, room: Room) {
+    if room.state() != RoomState::Joined {
+        return;
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return;
+    }
+    let Some(body) = text_body(&event) else {
+        return;
+    };
+    let Some(args) = strip_command(body, "!quiet") else {
+        return;
+    };
+    if !can_reply(&room).await {
+        return;
+    }
+
+    let response = match command(args, &event, &room).await {
+        Ok(response) => response,
+        Err(err) => {
+            warn!("Failed to change quiet hours: {err:#}");
+            "Sorry, I couldn't change the quiet hours.".to_owned()
+        }
+    };
+    // Not held back, whatever the time
+    let content = reply_content(
+        &room,
+        &event,
+        RoomMessageEventContent::notice_plain(response),
+    );
+    if let Err(err) = send::send(&room, content).await {
+        send::log_error(&room, &err);
+    }
+}
+
+async fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+) -> anyhow::Result<String> {
+    if args.is_empty() {
+        return Ok(match quiet_hours(room).await {
+            Some(quiet) => describe(&quiet),
+            None => "This room has no quiet hours.".to_owned(),
+        });
+    }
+    if args == "help" {
+        return Ok(HELP.to_owned());
+    }
+    if !is_moderator(room, &event.sender).await? {
+        return Ok("You need moderator power to change quiet hours here.".to_owned());
+    }
+
+    let (content, response) = if args == "off" {
+        (
+            QuietHoursContent::default(),
+            "Quiet hours are off.".to_owned(),
+        )
+    } else {
+        let Some(quiet) = parse(args) else {
+            return Ok(HELP.to_owned());
+        };
+        (quiet.to_content(), describe(&quiet))
+    };
+    if let Err(err) = room
+        .send_state_event_raw(QUIET_HOURS_EVENT_TYPE, "", serde_json::to_value(content)?)
+        .await
+    {
+        if let Some(ErrorKind::Forbidden { .. }) = err.client_api_error_kind() {
+            return Ok(format!(
+                "I'm not allowed to set quiet hours here. Give me the power to send \
+                 {QUIET_HOURS_EVENT_TYPE} state events, or send one yourself."
+            ));
+        }
+        return Err(err.into());
+    }
+    info!(
+        sender = event.sender.as_str(),
+        "Changed the room's quiet hours"
+    );
+    Ok(response)
+}
+
+/// Parse `22:00-07:00 [time zone] [queue|drop]`.
+fn parse(args: &str) -> Option<QuietHours> {
+    let mut words = args.split_whitespace();
+    let (start, end) = words.next()?.split_once('-')?;
+    let mut quiet = QuietHours {
+        start: NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?,
+        end: NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?,
+        timezone: Tz::UTC,
+        policy: Policy::Drop,
+    };
+    for word in words {
+        match word {
+            "queue" => quiet.policy = Policy::Queue,
+            "drop" => quiet.policy = Policy::Drop,
+            timezone => quiet.timezone = timezone.parse().ok()?,
+        }
+    }
+    (quiet.start != quiet.end).then_some(quiet)
+}
+
+fn describe(quiet: &QuietHours) -> String {
+    format!(
+        "Quiet hours are {}-{} {}, when I {}.",
+        quiet.start.format("%H:%M"),
+        quiet.end.format("%H:%M"),
+        quiet.timezone,
+        match quiet.policy {
+            Policy::Drop => "drop what I'd have said",
+            Policy::Queue => "hold what I'd say until they end",
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use matrix_sdk::ruma::room_id;
+
+    use super::*;
+
+    fn quiet(start: &str, end: &str, timezone: Tz) -> QuietHours {
+        QuietHours {
+            start: NaiveTime::parse_from_str(start, "%H:%M").unwrap(),
+            end: NaiveTime::parse_from_str(end, "%H:%M").unwrap(),
+            timezone,
+            policy: Policy::Drop,
+        }
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, 0).unwrap()
+    }
+
+    fn minutes(minutes: u64) -> Option<Duration> {
+        Some(Duration::from_secs(minutes * 60))
+    }
+
+    #[test]
+    fn daytime_window() {
+        let quiet = quiet("09:00", "17:00", Tz::UTC);
+        assert_eq!(quiet.remaining(at(8, 59)), None);
+        assert_eq!(quiet.remaining(at(9, 0)), minutes(8 * 60));
+        assert_eq!(quiet.remaining(at(16, 30)), minutes(30));
+        assert_eq!(quiet.remaining(at(17, 0)), None);
+        assert_eq!(quiet.remaining(at(23, 0)), None);
+    }
+
+    #[test]
+    fn window_crossing_midnight() {
+        let quiet = quiet("22:00", "07:00", Tz::UTC);
+        assert_eq!(quiet.remaining(at(21, 59)), None);
+        assert_eq!(quiet.remaining(at(22, 0)), minutes(9 * 60));
+        assert_eq!(quiet.remaining(at(23, 30)), minutes(7 * 60 + 30));
+        assert_eq!(quiet.remaining(at(0, 0)), minutes(7 * 60));
+        assert_eq!(quiet.remaining(at(6, 59)), minutes(1));
+        assert_eq!(quiet.remaining(at(7, 0)), None);
+        assert_eq!(quiet.remaining(at(12, 0)), None);
+    }
+
+    #[test]
+    fn window_in_another_time_zone() {
+        // Tokyo is 9 hours ahead of UTC, with no daylight saving
+        let quiet = quiet("22:00", "07:00", chrono_tz::Asia::Tokyo);
+        assert_eq!(quiet.remaining(at(13, 0)), minutes(9 * 60));
+        assert_eq!(quiet.remaining(at(21, 0)), minutes(60));
+        assert_eq!(quiet.remaining(at(22, 0)), None);
+        assert_eq!(quiet.remaining(at(12, 59)), None);
+    }
+
+    #[test]
+    fn parses_the_command() {
+        let quiet = parse("22:00-07:00 Europe/London queue").unwrap();
+        assert_eq!(quiet.start, NaiveTime::from_hms_opt(22, 0, 0).unwrap());
+        assert_eq!(quiet.end, NaiveTime::from_hms_opt(7, 0, 0).unwrap());
+        assert_eq!(quiet.timezone, chrono_tz::Europe::London);
+        assert_eq!(quiet.policy, Policy::Queue);
+
+        // Dropping is the default, in UTC
+        let quiet = parse("22:00-07:00").unwrap();
+        assert_eq!(quiet.timezone, Tz::UTC);
+        assert_eq!(quiet.policy, Policy::Drop);
+        assert_eq!(
+            parse("22:00-07:00 queue drop").unwrap().policy,
+            Policy::Drop
+        );
+
+        assert!(parse("22:00-22:00").is_none());
+        assert!(parse("22:00").is_none());
+        assert!(parse("25:00-07:00").is_none());
+        assert!(parse("22:00-07:00 Nowhere/Special").is_none());
+    }
+
+    #[test]
+    fn content_round_trips() {
+        let quiet = parse("23:30-06:15 Europe/Berlin queue").unwrap();
+        let content: QuietHoursContent =
+            serde_json::from_value(serde_json::to_value(quiet.to_content()).unwrap()).unwrap();
+        let parsed = QuietHours::from_content(&content).unwrap();
+        assert_eq!((parsed.start, parsed.end), (quiet.start, quiet.end));
+        assert_eq!(parsed.timezone, quiet.timezone);
+        assert_eq!(parsed.policy, Policy::Queue);
+
+        // Turned off, or without a policy
+        assert!(QuietHours::from_content(&QuietHoursContent::default()).is_none());
+        let content: QuietHoursContent =
+            serde_json::from_value(serde_json::json!({ "start": "22:00", "end": "07:00" }))
+                .unwrap();
+        let parsed = QuietHours::from_content(&content).unwrap();
+        assert_eq!(parsed.timezone, Tz::UTC);
+        assert_eq!(parsed.policy, Policy::Drop);
+    }
+
+    #[test]
+    fn held_messages_queue_up_to_a_limit() {
+        let room_id = room_id!("!quiet-queue:example.org");
+        let content = Raw::new(&RoomMessageEventContent::notice_plain("hello"))
+            .unwrap()
+            .cast();
+        assert_eq!(queue(room_id, "m.room.message", &content), Queued::First);
+        for _ in 1..MAX_HELD {
+            assert_eq!(queue(room_id, "m.room.message", &content), Queued::Behind);
+        }
+        assert_eq!(queue(room_id, "m.room.message", &content), Queued::Full);
+        assert_eq!(HELD.lock().unwrap()[room_id].len(), MAX_HELD);
+
+        // Other rooms have queues of their own
+        let other = room_id!("!quiet-other:example.org");
+        assert_eq!(queue(other, "m.room.message", &content), Queued::First);
+    }
+}
@@ -0,0 +1,354 @@
+//! Feeding archived events through a bot's handlers without a homeserver,
+//! with its `replay` subcommand, for debugging what triggers it and
+//! measuring how fast it handles events.
+//!
+//! ```text
+//! matrix-sed replay matrix-logger/store.sqlite3 -- --feedback
+//! ```
+//!
+//! The events come from the logger bot's `store.sqlite3`, or from a file
+//! of sync responses, one JSON object per line, as the homeserver sent
+//! them. The bot logs in to a stand-in homeserver on localhost that serves
+//! them as its syncs, in order, so they go through the same handlers as
+//! when running for real. What the bot sends is printed as JSON lines
+//! rather than sent anywhere, and once it's handled everything, it reports
+//! how long that took and stops.
+//!
+//! Options after `--` are passed to the bot. It starts from nothing in a
+//! data directory of its own, so the real one's session and stores aren't
+//! touched.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{Path as UrlPath, Query, State},
+    routing::{get, post, put},
+    Json, Router,
+};
+use clap::Parser;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::watch};
+
+/// The user the bot logs in as.
+const USER_ID: &str = "@replay:localhost";
+
+/// The longest a sync is held open once everything's been served.
+const MAX_SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+static REPLAY: OnceLock<Arc<Replay>> = OnceLock::new();
+
+/// The `replay` subcommand.
+#[derive(Parser, Debug)]
+#[command(name = "replay")]
+struct Args {
+    /// The logger bot's archive, or a file of sync responses
+    file: PathBuf,
+    /// How many archived events to serve in each sync
+    #[arg(long, default_value_t = 50)]
+    batch: usize,
+    /// Options for the bot
+    #[arg(last = true)]
+    bot_args: Vec<String>,
+}
+
+struct Replay {
+    /// The sync responses to serve, in order.
+    batches: Vec<Value>,
+    events: usize,
+    replies: AtomicUsize,
+    /// When the first batch was served.
+    started: Mutex<Option<Instant>>,
+    /// Set once the bot has handled every batch.
+    finished: watch::Sender<bool>,
+}
+
+/// Whether the program was run as `<bot> replay ...`.
+pub fn requested() -> bool {
+    std::env::args().nth(1).as_deref() == Some("replay")
+}
+
+/// Start serving the events for the bot called `name`, returning the
+/// arguments for it to parse its config from, which point it at them.
+pub async fn start(name: &str) -> anyhow::Result<Vec<String>> {
+    let args = Args::parse_from(std::env::args().skip(1));
+    let batches = if is_archive(&args.file) {
+        from_archive(&args.file, args.batch.max(1))?
+    } else {
+        from_sync_responses(&args.file)?
+    };
+    let events = batches.iter().map(count_events).sum();
+
+    let data_dir = std::env::temp_dir().join(format!("{name}-replay"));
+    if data_dir.exists() {
+        fs::remove_dir_all(&data_dir)
+            .with_context(|| format!("failed to clear {}", data_dir.display()))?;
+    }
+    fs::create_dir_all(&data_dir)?;
+
+    let replay = Arc::new(Replay {
+        batches,
+        events,
+        replies: AtomicUsize::new(0),
+        started: Mutex::new(None),
+        finished: watch::Sender::new(false),
+    });
+    let _ = REPLAY.set(replay.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    tokio::spawn(async move { axum::serve(listener, router(replay)).await });
+
+    let mut bot_args = vec![
+        name.to_owned(),
+        "--server".to_owned(),
+        format!("http://{address}"),
+        "--username".to_owned(),
+        "replay".to_owned(),
+        "--password".to_owned(),
+        "replay".to_owned(),
+        "--data-dir".to_owned(),
+        data_dir.display().to_string(),
+    ];
+    bot_args.extend(args.bot_args);
+    Ok(bot_args)
+}
+
+/// Wait until the bot has handled every batch, which is never when it
+/// isn't replaying.
+pub(crate) async fn finished() {
+    match REPLAY.get() {
+        Some(replay) => {
+            let _ = replay
+                .finished
+                .subscribe()
+                .wait_for(|&finished| finished)
+                .await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Say how the replay went.
+pub(crate) fn report() {
+    let Some(replay) = REPLAY.get() else {
+        return;
+    };
+    let took = replay
+        .started
+        .lock()
+        .unwrap()
+        .map_or(Duration::ZERO, |started| started.elapsed());
+    let rate = replay.events as f64 / took.as_secs_f64().max(f64::EPSILON);
+    // Printed rather than logged, so it shows at any verbosity
+    eprintln!(
+        "Replayed {} events in {} syncs in {:.3}s, {rate:.0} a second, sending {} replies",
+        replay.events,
+        replay.batches.len(),
+        took.as_secs_f64(),
+        replay.replies.load(Ordering::Relaxed)
+    );
+}
+
+fn is_archive(file: &Path) -> bool {
+    file.extension()
+        .is_some_and(|extension| extension == "sqlite3" || extension == "db")
+}
+
+/// Sync responses with the archived events, `batch` at a time.
+fn from_archive(file: &Path, batch: usize) -> anyhow::Result<Vec<Value>> {
+    let conn = Connection::open(file)
+        .with_context(|| format!("failed to open the archive {}", file.display()))?;
+    let mut statement =
+        conn.prepare("SELECT room_id, json FROM events WHERE json IS NOT NULL ORDER BY ts, rowid")?;
+    let events = statement
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    events
+        .chunks(batch)
+        .map(|chunk| -> anyhow::Result<Value> {
+            let mut rooms = BTreeMap::<&str, Vec<Value>>::new();
+            for (room_id, event) in chunk {
+                rooms
+                    .entry(room_id.as_str())
+                    .or_default()
+                    .push(serde_json::from_str(event)?);
+            }
+            let join: serde_json::Map<_, _> = rooms
+                .into_iter()
+                .map(|(room_id, events)| {
+                    let room = json!({ "timeline": { "events": events, "limited": false } });
+                    (room_id.to_owned(), room)
+                })
+                .collect();
+            Ok(json!({ "rooms": { "join": join } }))
+        })
+        .collect()
+}
+
+fn from_sync_responses(file: &Path) -> anyhow::Result<Vec<Value>> {
+    let text =
+        fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("line {} isn't a sync response", number + 1))
+        })
+        .collect()
+}
+
+/// How many timeline events a sync response has.
+fn count_events(response: &Value) -> usize {
+    response["rooms"]["join"]
+        .as_object()
+        .into_iter()
+        .flat_map(|rooms| rooms.values())
+        .filter_map(|room| room["timeline"]["events"].as_array())
+        .map(Vec::len)
+        .sum()
+}
+
+/// The stand-in homeserver, answering just enough for the bot to log in,
+/// sync and send.
+fn router(replay: Arc<Replay>) -> Router {
+    Router::new()
+        .route("/_matrix/client/versions", get(versions))
+        .route("/_matrix/client/v3/login", post(login))
+        .route("/_matrix/client/v3/user/:user_id/filter", post(filter))
+        .route("/_matrix/client/v3/sync", get(sync))
+        .route(
+            "/_matrix/client/v3/rooms/:room_id/send/:event_type/:txn_id",
+            put(send),
+        )
+        .route(
+            "/_matrix/client/v3/rooms/:room_id/state/:event_type",
+            put(send_state),
+        )
+        .route(
+            "/_matrix/client/v3/rooms/:room_id/state/:event_type/:state_key",
+            put(send_state_with_key),
+        )
+        .route(
+            "/_matrix/client/v3/rooms/:room_id/redact/:event_id/:txn_id",
+            put(redact),
+        )
+        .route("/_matrix/client/v3/rooms/:room_id/members", get(members))
+        .route("/_matrix/client/v3/keys/upload", post(upload_keys))
+        .route("/_matrix/client/v3/keys/query", post(query_keys))
+        // Anything else, like typing notifications, just succeeds
+        .fallback(|| async { Json(json!({})) })
+        .with_state(replay)
+}
+
+async fn versions() -> Json<Value> {
+    Json(json!({ "versions": ["v1.1", "v1.2", "v1.3", "v1.4", "v1.5", "v1.6"] }))
+}
+
+async fn login() -> Json<Value> {
+    Json(json!({
+        "user_id": USER_ID,
+        "access_token": "replay",
+        "device_id": "REPLAY",
+    }))
+}
+
+async fn filter() -> Json<Value> {
+    Json(json!({ "filter_id": "replay" }))
+}
+
+#[derive(Deserialize)]
+struct SyncParams {
+    since: Option<String>,
+    /// In milliseconds.
+    timeout: Option<u64>,
+}
+
+async fn sync(State(replay): State<Arc<Replay>>, Query(params): Query<SyncParams>) -> Json<Value> {
+    // The initial sync, which the bot skips past
+    let Some(since) = params.since else {
+        return Json(json!({ "next_batch": "0" }));
+    };
+    let served: usize = since.parse().unwrap_or_default();
+    if let Some(batch) = replay.batches.get(served) {
+        if served == 0 {
+            *replay.started.lock().unwrap() = Some(Instant::now());
+        }
+        let mut batch = batch.clone();
+        batch["next_batch"] = (served + 1).to_string().into();
+        return Json(batch);
+    }
+    // Asking for more means the handlers have run for the last batch
+    replay.finished.send_replace(true);
+    let timeout = Duration::from_millis(params.timeout.unwrap_or_default());
+    tokio::time::sleep(timeout.min(MAX_SYNC_TIMEOUT)).await;
+    Json(json!({ "next_batch": since }))
+}
+
+/// Print what the bot sent, giving it an event ID.
+fn sent(replay: &Replay, room_id: &str, event_type: &str, content: Value) -> Json<Value> {
+    let sent = replay.replies.fetch_add(1, Ordering::Relaxed) + 1;
+    println!(
+        "{}",
+        json!({ "room_id": room_id, "type": event_type, "content": content })
+    );
+    Json(json!({ "event_id": format!("$replay{sent}") }))
+}
+
+async fn send(
+    State(replay): State<Arc<Replay>>,
+    UrlPath((room_id, event_type, _)): UrlPath<(String, String, String)>,
+    Json(content): Json<Value>,
+) -> Json<Value> {
+    sent(&replay, &room_id, &event_type, content)
+}
+
+async fn send_state(
+    State(replay): State<Arc<Replay>>,
+    UrlPath((room_id, event_type)): UrlPath<(String, String)>,
+    Json(content): Json<Value>,
+) -> Json<Value> {
+    sent(&replay, &room_id, &event_type, content)
+}
+
+async fn send_state_with_key(
+    State(replay): State<Arc<Replay>>,
+    UrlPath((room_id, event_type, _)): UrlPath<(String, String, String)>,
+    Json(content): Json<Value>,
+) -> Json<Value> {
+    sent(&replay, &room_id, &event_type, content)
+}
+
+async fn redact(
+    State(replay): State<Arc<Replay>>,
+    UrlPath((room_id, event_id, _)): UrlPath<(String, String, String)>,
+    Json(mut content): Json<Value>,
+) -> Json<Value> {
+    content["redacts"] = event_id.into();
+    sent(&replay, &room_id, "m.room.redaction", content)
+}
+
+async fn members() -> Json<Value> {
+    Json(json!({ "chunk": [] }))
+}
+
+async fn upload_keys() -> Json<Value> {
+    Json(json!({ "one_time_key_counts": {} }))
+}
+
+async fn query_keys() -> Json<Value> {
+    Json(json!({ "device_keys": {} }))
+}
@@ -0,0 +1,87 @@
+//! Resolving room aliases, so anywhere a room is given, `#room:server`
+//! works as well as a room ID.
+//!
+//! Resolved aliases are cached for [`TTL`], so config naming a room by
+//! alias costs a lookup now and then rather than on every use. When a room
+//! the bot is in changes its canonical alias, the aliases it had and has
+//! are looked up again on their next use, in case they've moved.
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use matrix_sdk::{
+    ruma::{
+        events::room::canonical_alias::OriginalSyncRoomCanonicalAliasEvent, OwnedRoomAliasId,
+        OwnedRoomId, OwnedRoomOrAliasId, RoomAliasId, RoomId, RoomOrAliasId,
+    },
+    Client, Room,
+};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// How long a resolved alias is trusted for.
+pub const TTL: Duration = Duration::from_secs(60 * 60);
+
+static CACHE: LazyLock<Mutex<HashMap<OwnedRoomAliasId, (OwnedRoomId, Instant)>>> =
+    LazyLock::new(Default::default);
+
+/// The room ID of `room`, resolving it if it's an alias.
+pub async fn resolve(client: &Client, room: &RoomOrAliasId) -> anyhow::Result<OwnedRoomId> {
+    match <&RoomId>::try_from(room) {
+        Ok(room_id) => Ok(room_id.to_owned()),
+        Err(alias) => resolve_alias(client, alias).await,
+    }
+}
+
+/// The room ID of `room`, a room ID or alias someone typed.
+pub async fn resolve_room(client: &Client, room: &str) -> anyhow::Result<OwnedRoomId> {
+    resolve(client, &OwnedRoomOrAliasId::try_from(room)?).await
+}
+
+/// The room IDs of `rooms`, leaving out and logging any that don't resolve.
+pub async fn resolve_all(client: &Client, rooms: &[OwnedRoomOrAliasId]) -> Vec<OwnedRoomId> {
+    let mut room_ids = Vec::with_capacity(rooms.len());
+    for room in rooms {
+        match resolve(client, room).await {
+            Ok(room_id) => room_ids.push(room_id),
+            Err(err) => warn!(room = room.as_str(), "Failed to resolve a room: {err:#}"),
+        }
+    }
+    room_ids
+}
+
+async fn resolve_alias(client: &Client, alias: &RoomAliasId) -> anyhow::Result<OwnedRoomId> {
+    if let Some((room_id, resolved)) = CACHE.lock().unwrap().get(alias) {
+        if resolved.elapsed() < TTL {
+            return Ok(room_id.clone());
+        }
+    }
+    let room_id = client.resolve_room_alias(alias).await?.room_id;
+    debug!(
+        alias = alias.as_str(),
+        room = room_id.as_str(),
+        "Resolved an alias"
+    );
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(alias.to_owned(), (room_id.clone(), Instant::now()));
+    Ok(room_id)
+}
+
+/// Forget resolutions a room's new canonical alias could have changed.
+pub(crate) async fn on_canonical_alias(event: OriginalSyncRoomCanonicalAliasEvent, room: Room) {
+    let content = event.content;
+    let mentioned: Vec<OwnedRoomAliasId> = content
+        .alias
+        .into_iter()
+        .chain(content.alt_aliases)
+        .collect();
+    CACHE
+        .lock()
+        .unwrap()
+        .retain(|alias, (room_id, _)| &**room_id != room.room_id() && !mentioned.contains(alias));
+}
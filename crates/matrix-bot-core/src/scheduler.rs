@@ -0,0 +1,52 @@
+//! A loop for bots that do things at set times, like sending reminders.
+//!
+//! The bot keeps track of what's due when, and says how through [`Jobs`].
+//! [`run`] fires whatever's due, then sleeps until the next job is due or
+//! its `wake` is notified, e.g. because something new was scheduled.
+
+use std::{future::Future, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use tokio::{
+    sync::Notify,
+    time::{sleep, Duration},
+};
+use tracing::error;
+
+/// How long to sleep when nothing is due. Scheduling something wakes the
+/// loop up early, so this only guards against missed notifications.
+const IDLE_SLEEP: Duration = Duration::from_secs(3600);
+
+/// What a bot has scheduled.
+pub trait Jobs: Send + Sync {
+    /// When the next job is due, or `None` if nothing is scheduled.
+    fn next_due(&self) -> anyhow::Result<Option<DateTime<Utc>>>;
+
+    /// Fire every job due by `now`, and reschedule or remove them so they
+    /// aren't due again.
+    fn fire_due(&self, now: DateTime<Utc>) -> impl Future<Output = ()> + Send;
+}
+
+/// Fire `jobs` as they come due, forever.
+pub async fn run(jobs: impl Jobs, wake: Arc<Notify>) {
+    loop {
+        jobs.fire_due(Utc::now()).await;
+
+        let sleep_for = match jobs.next_due() {
+            Ok(Some(due)) => (due - Utc::now())
+                .to_std()
+                .unwrap_or_default()
+                .min(IDLE_SLEEP),
+            Ok(None) => IDLE_SLEEP,
+            Err(err) => {
+                error!("Failed to get when the next job is due: {err}");
+                IDLE_SLEEP
+            }
+        };
+
+        tokio::select! {
+            _ = sleep(sleep_for) => {}
+            _ = wake.notified() => {}
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Sending messages. Everything goes through one queue per room, so messages
+//! arrive in the order they were sent in even when some have to be retried,
+//! like when the bot is rate limited.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use matrix_sdk::{
+    ruma::{
+        api::client::error::{ErrorKind, RetryAfter},
+        events::{
+            room::{
+                message::{
+                    AddMentions, ForwardThread, OriginalSyncRoomMessageEvent, Relation,
+                    Replacement, RoomMessageEventContent,
+                },
+                server_acl::RoomServerAclEventContent,
+            },
+            AnyMessageLikeEventContent, MessageLikeEventContent, MessageLikeEventType,
+            SyncOrStrippedState, SyncStateEvent,
+        },
+        serde::Raw,
+        OwnedEventId, OwnedRoomId, ServerName,
+    },
+    Room,
+};
+use tokio::sync::oneshot;
+use tracing::{debug, info, warn};
+
+use crate::{pacing, quiet, shard};
+
+/// How many times a message is tried before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How long to wait before retrying when the homeserver doesn't say,
+/// doubling with each attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// How long working out a reply can take before the bot says it's still
+/// working on it, set from
+/// [`AccountConfig::latency_budget`](crate::AccountConfig::latency_budget).
+static LATENCY_BUDGET: OnceLock<Duration> = OnceLock::new();
+
+/// Messages waiting to be sent, by room. A room is in here for as long as
+/// something is sending its messages, even once its queue is empty.
+static QUEUES: LazyLock<Mutex<HashMap<OwnedRoomId, VecDeque<Outgoing>>>> =
+    LazyLock::new(Default::default);
+
+struct Outgoing {
+    room: Room,
+    event_type: String,
+    content: Raw<AnyMessageLikeEventContent>,
+    sent: oneshot::Sender<Result<OwnedEventId, matrix_sdk::Error>>,
+}
+
+/// Send `content` to the room after any messages queued for it before,
+/// retrying if the homeserver rate limits the bot or can't be reached, and
+/// return the new event's ID.
+pub async fn send(
+    room: &Room,
+    content: impl MessageLikeEventContent,
+) -> anyhow::Result<OwnedEventId> {
+    send_raw(
+        room,
+        content.event_type().to_string(),
+        Raw::new(&content)?.cast(),
+    )
+    .await
+}
+
+/// [`send`], for content that's already serialized, like content with
+/// fields the SDK doesn't know about.
+pub async fn send_raw(
+    room: &Room,
+    event_type: String,
+    content: Raw<AnyMessageLikeEventContent>,
+) -> anyhow::Result<OwnedEventId> {
+    let (sent, result) = oneshot::channel();
+    let outgoing = Outgoing {
+        room: room.clone(),
+        event_type,
+        content,
+        sent,
+    };
+
+    {
+        let mut queues = QUEUES.lock().unwrap();
+        metrics::gauge!("bot_send_queue_depth").increment(1.0);
+        if let Some(queue) = queues.get_mut(room.room_id()) {
+            queue.push_back(outgoing);
+        } else {
+            // Nothing is sending to the room, so start sending
+            queues.insert(room.room_id().to_owned(), VecDeque::new());
+            tokio::spawn(deliver(room.room_id().to_owned(), outgoing));
+        }
+    }
+
+    Ok(result.await.context("the message was dropped")??)
+}
+
+/// Send a room's messages, starting with `first`, until its queue is empty.
+async fn deliver(room_id: OwnedRoomId, first: Outgoing) {
+    let mut outgoing = first;
+    loop {
+        let result = send_with_retries(&outgoing).await;
+        metrics::gauge!("bot_send_queue_depth").decrement(1.0);
+        // Whoever sent it may have stopped waiting
+        let _ = outgoing.sent.send(result);
+
+        outgoing = {
+            let mut queues = QUEUES.lock().unwrap();
+            match queues.get_mut(&room_id).and_then(VecDeque::pop_front) {
+                Some(outgoing) => outgoing,
+                None => {
+                    queues.remove(&room_id);
+                    return;
+                }
+            }
+        };
+    }
+}
+
+async fn send_with_retries(outgoing: &Outgoing) -> Result<OwnedEventId, matrix_sdk::Error> {
+    let client = outgoing.room.client();
+    let homeserver = client.homeserver();
+    let homeserver = homeserver.host_str().unwrap_or_default();
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        pacing::wait(homeserver).await;
+        let err = match outgoing
+            .room
+            .send_raw(&outgoing.event_type, outgoing.content.clone())
+            .await
+        {
+            Ok(response) => {
+                pacing::sent(homeserver);
+                return Ok(response.event_id);
+            }
+            Err(err) => err,
+        };
+        let wait = retry_after(&err, delay);
+        if let Some(ErrorKind::LimitExceeded { .. }) = err.client_api_error_kind() {
+            metrics::counter!("bot_send_errors_total", "kind" => "rate_limited").increment(1);
+            pacing::limited(homeserver, wait);
+        } else {
+            metrics::counter!("bot_send_errors_total", "kind" => "other").increment(1);
+            pacing::failed(homeserver);
+        }
+        let Some(wait) = wait.filter(|_| attempt < MAX_ATTEMPTS) else {
+            return Err(err);
+        };
+        debug!(
+            room = outgoing.room.room_id().as_str(),
+            attempt,
+            "Retrying a message in {}ms: {err}",
+            wait.as_millis()
+        );
+        metrics::counter!("bot_send_retries_total").increment(1);
+        tokio::time::sleep(wait).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// How many messages are waiting behind ones being sent.
+pub(crate) fn queued() -> usize {
+    QUEUES.lock().unwrap().values().map(VecDeque::len).sum()
+}
+
+/// Send `message` to the room, logging rather than returning any error.
+///
+/// During the room's [quiet hours](crate::quiet), the message is dropped or
+/// held until they end.
+pub async fn send_or_log_error(room: &Room, message: impl MessageLikeEventContent) {
+    let event_type = message.event_type().to_string();
+    let content = match Raw::new(&message) {
+        Ok(content) => content.cast(),
+        Err(e) => return log_error(room, &e.into()),
+    };
+    if quiet::hold(room, &event_type, &content).await {
+        return;
+    }
+    if let Err(e) = send_raw(room, event_type, content).await {
+        log_error(room, &e);
+    }
+}
+
+/// Log a failure to send a message to the room.
+pub(crate) fn log_error(room: &Room, e: &anyhow::Error) {
+    let kind = e
+        .downcast_ref::<matrix_sdk::Error>()
+        .and_then(matrix_sdk::Error::client_api_error_kind);
+    if let Some(ErrorKind::Forbidden { .. }) = kind {
+        // Our permissions changed between the check and the send, don't be noisy about it
+        info!(
+            "Not allowed to send message to room {}: {}",
+            room.room_id(),
+            e
+        );
+        metrics::counter!("bot_replies_refused_total", "reason" => "forbidden").increment(1);
+    } else {
+        warn!("Failed to send message to room {}: {:#}", room.room_id(), e);
+    }
+}
+
+/// Reply to `event` with a plain-text notice, staying in its thread if it
+/// has one.
+pub async fn reply_notice(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    text: impl Into<String>,
+) {
+    reply(room, event, RoomMessageEventContent::notice_plain(text)).await;
+}
+
+/// Reply to `event` with `content`, staying in its thread if it has one.
+pub async fn reply(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    content: RoomMessageEventContent,
+) {
+    send_or_log_error(room, reply_content(room, event, content)).await;
+}
+
+/// `content` as a reply to `event`, in its thread if it has one.
+pub(crate) fn reply_content(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    content: RoomMessageEventContent,
+) -> RoomMessageEventContent {
+    let original = event.clone().into_full_event(room.room_id().to_owned());
+    content.make_reply_to(&original, ForwardThread::Yes, AddMentions::No)
+}
+
+pub(crate) fn set_latency_budget(budget: Duration) {
+    let _ = LATENCY_BUDGET.set(budget);
+}
+
+/// Run `work` to answer `event`, and if it takes longer than the latency
+/// budget, reply saying it's still being worked on meanwhile. Returns what
+/// `work` did and the ID of that reply if there was one, for the answer to
+/// be edited into with [`replacement`].
+pub async fn within_budget<T>(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    work: impl Future<Output = T>,
+) -> (T, Option<OwnedEventId>) {
+    let budget = LATENCY_BUDGET.get().copied().unwrap_or_default();
+    tokio::pin!(work);
+    if budget.is_zero() {
+        return (work.await, None);
+    }
+    tokio::select! {
+        done = &mut work => return (done, None),
+        () = tokio::time::sleep(budget) => {}
+    }
+
+    debug!(
+        room = room.room_id().as_str(),
+        "Over the latency budget, saying so"
+    );
+    metrics::counter!("bot_latency_budget_exceeded_total").increment(1);
+    let still_working = reply_content(
+        room,
+        event,
+        RoomMessageEventContent::notice_plain("Still working on it…"),
+    );
+    let (done, placeholder) = tokio::join!(work, send(room, still_working));
+    let placeholder = placeholder.inspect_err(|err| log_error(room, err)).ok();
+    (done, placeholder)
+}
+
+/// An edit of the message `event_id`, replacing its content with `content`.
+pub fn replacement(
+    event_id: OwnedEventId,
+    content: RoomMessageEventContent,
+) -> RoomMessageEventContent {
+    let mut edit = content.clone();
+    edit.relates_to = Some(Relation::Replacement(Replacement::new(
+        event_id,
+        content.into(),
+    )));
+    edit
+}
+
+/// Check that the bot is still allowed to post in the room, so that rooms
+/// where it was demoted, muted or denied by the server ACL don't get a
+/// stream of `M_FORBIDDEN` errors.
+///
+/// Rooms another instance of the bot handles when [sharded](crate::shard)
+/// count as not allowed too.
+pub async fn can_reply(room: &Room) -> bool {
+    if !shard::owns(room.room_id()) {
+        return false;
+    }
+    let Some(user_id) = room.client().user_id().map(ToOwned::to_owned) else {
+        return false;
+    };
+
+    let reason = match room
+        .can_user_send_message(&user_id, MessageLikeEventType::RoomMessage)
+        .await
+    {
+        Ok(false) => Some("power_levels"),
+        Ok(true) if !server_acl_allows(room, user_id.server_name()).await => Some("server_acl"),
+        Ok(true) => None,
+        Err(err) => {
+            // Let the send fail later rather than guessing
+            warn!(
+                "Failed to check power levels in room {}: {err}",
+                room.room_id()
+            );
+            None
+        }
+    };
+
+    let Some(reason) = reason else {
+        return true;
+    };
+    info!(
+        reason,
+        "Not allowed to send messages in room {}, ignoring command",
+        room.room_id()
+    );
+    metrics::counter!("bot_replies_refused_total", "reason" => reason).increment(1);
+    false
+}
+
+async fn server_acl_allows(room: &Room, server_name: &ServerName) -> bool {
+    let acl = match room
+        .get_state_event_static::<RoomServerAclEventContent>()
+        .await
+    {
+        Ok(Some(raw)) => raw.deserialize(),
+        Ok(None) => return true,
+        Err(err) => {
+            warn!("Failed to get server ACL in room {}: {err}", room.room_id());
+            return true;
+        }
+    };
+
+    match acl {
+        Ok(SyncOrStrippedState::Sync(SyncStateEvent::Original(event))) => {
+            event.content.is_allowed(server_name)
+        }
+        _ => true,
+    }
+}
@@ -0,0 +1,423 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use matrix_sdk::{matrix_auth::MatrixSession, Client};
+use rand::{distributions::Alphanumeric, Rng};
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::{debug, error, info, warn};
+
+use crate::AccountConfig;
+
+/// The most often the sync token is written to disk while syncing.
+const SYNC_TOKEN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The SDK's crypto store, holding the device's keys.
+const CRYPTO_STORE: &str = "matrix-sdk-crypto.sqlite3";
+
+/// The crypto store schema version the SDK in `Cargo.lock` expects, from
+/// `DATABASE_VERSION` in matrix-sdk-sqlite's `crypto_store.rs`. It has to be
+/// kept in step when the SDK is updated.
+const CRYPTO_STORE_VERSION: u64 = 9;
+
+/// What happens to events that arrived before a restart but weren't
+/// finished being handled, e.g. because the bot crashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Handle them again after restarting. Some may be handled twice.
+    AtLeastOnce,
+    /// Skip past them when restarting. Some may never be handled.
+    AtMostOnce,
+}
+
+impl FromStr for Delivery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "at-least-once" => Ok(Self::AtLeastOnce),
+            "at-most-once" => Ok(Self::AtMostOnce),
+            _ => Err(format!(
+                "invalid delivery {s}: use at-least-once or at-most-once"
+            )),
+        }
+    }
+}
+
+/// The data needed to re-build a client.
+#[derive(Debug, Serialize, Deserialize)]
+struct ClientSession {
+    /// The URL of the homeserver of the user.
+    homeserver: String,
+
+    /// The path of the database.
+    db_path: std::path::PathBuf,
+
+    /// The passphrase of the database.
+    passphrase: String,
+}
+
+/// The full session to persist.
+#[derive(Debug, Serialize, Deserialize)]
+struct FullSession {
+    /// The data to re-build the client.
+    client_session: ClientSession,
+
+    /// The Matrix user session.
+    user_session: MatrixSession,
+
+    /// The latest sync token, from before it was kept in its own file.
+    ///
+    /// It is only needed to persist it when using `Client::sync_once()` and we
+    /// want to make our syncs faster by not receiving all the initial sync
+    /// again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sync_token: Option<String>,
+}
+
+/// Restore a previous session.
+pub(crate) async fn restore_session(
+    session_file: &Path,
+) -> anyhow::Result<(Client, Option<String>)> {
+    info!(
+        "Previous session found in '{}'",
+        session_file.to_string_lossy()
+    );
+
+    // The session was serialized as JSON in a file.
+    let serialized_session = fs::read_to_string(session_file).await?;
+    let FullSession {
+        client_session,
+        user_session,
+        sync_token,
+    } = serde_json::from_str(&serialized_session)?;
+    let token_file = sync_token_file(session_file);
+    let sync_token = if fs::try_exists(&token_file).await? {
+        Some(fs::read_to_string(&token_file).await?)
+    } else {
+        sync_token
+    };
+
+    // The SDK's own errors for a broken store don't say what to do about it
+    check_store(&client_session.db_path)
+        .with_context(|| store_advice(&client_session.db_path, "it can't be used"))?;
+
+    // Build the client with the previous settings from the session.
+    let client = Client::builder()
+        .homeserver_url(client_session.homeserver)
+        .sqlite_store(&client_session.db_path, Some(&client_session.passphrase))
+        .build()
+        .await
+        .map_err(anyhow::Error::from)
+        .with_context(|| {
+            store_advice(
+                &client_session.db_path,
+                "the passphrase in the session file doesn't unlock it, or a newer version \
+                 of the bot made it",
+            )
+        })?;
+
+    info!("Restoring session for {}…", user_session.meta.user_id);
+
+    // Restore the Matrix user session.
+    client.restore_session(user_session).await?;
+
+    Ok((client, sync_token))
+}
+
+/// Check the SDK's databases in `db_path` are there and intact, and that the
+/// crypto store's schema is one the SDK can use, before the SDK opens them.
+///
+/// A crypto store from a newer SDK would otherwise fail deep in the SDK, or
+/// a missing one be replaced by an empty one, losing the device's keys.
+fn check_store(db_path: &Path) -> anyhow::Result<()> {
+    let databases: Vec<PathBuf> = std::fs::read_dir(db_path)
+        .context("the store is missing")?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| {
+            path.as_ref().map_or(true, |path| {
+                path.extension().is_some_and(|ext| ext == "sqlite3")
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    if databases.is_empty() {
+        anyhow::bail!("the store is empty");
+    }
+    if !databases
+        .iter()
+        .any(|path| path.file_name().is_some_and(|name| name == CRYPTO_STORE))
+    {
+        anyhow::bail!("{CRYPTO_STORE} is missing");
+    }
+    for database in databases {
+        let name = database.file_name().unwrap_or_default().to_string_lossy();
+        let conn = Connection::open_with_flags(&database, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("{name} can't be opened"))?;
+        let check: String = conn
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))
+            .with_context(|| format!("{name} isn't a database"))?;
+        if check != "ok" {
+            anyhow::bail!("{name} is corrupt: {check}");
+        }
+        // The SDK keeps its schema version as big-endian bytes
+        let version: Option<Vec<u8>> = conn
+            .query_row(
+                "SELECT value FROM kv WHERE CAST(key AS TEXT) = 'version'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap_or_default();
+        let version = version.map(|version| {
+            version
+                .iter()
+                .fold(0u64, |version, byte| (version << 8) | u64::from(*byte))
+        });
+        debug!(database = &*name, version, "Store schema version");
+        if name != CRYPTO_STORE {
+            continue;
+        }
+        match version {
+            None => anyhow::bail!("{name} has no schema version"),
+            Some(version) if version > CRYPTO_STORE_VERSION => anyhow::bail!(
+                "{name} has schema version {version}, but this version of the bot only \
+                 knows up to {CRYPTO_STORE_VERSION}, so a newer version of the bot made it"
+            ),
+            Some(version) if version < CRYPTO_STORE_VERSION => {
+                info!("Upgrading {name} from schema version {version} to {CRYPTO_STORE_VERSION}")
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// What to tell an operator whose store can't be opened because of
+/// `reason`.
+fn store_advice(db_path: &Path, reason: &str) -> String {
+    format!(
+        "Couldn't open the bot's store in '{}', as {reason}. If it can't be recovered, \
+         start the bot with --reset-store to delete it and log in again as a new device",
+        db_path.to_string_lossy()
+    )
+}
+
+/// Delete the session, its sync token and its store, so the next start logs
+/// in from scratch.
+pub(crate) async fn reset_store(session_file: &Path) -> anyhow::Result<()> {
+    if let Ok(serialized_session) = fs::read_to_string(session_file).await {
+        match serde_json::from_str::<FullSession>(&serialized_session) {
+            Ok(session) => {
+                let db_path = session.client_session.db_path;
+                warn!("Deleting the store in '{}'", db_path.to_string_lossy());
+                if fs::try_exists(&db_path).await? {
+                    fs::remove_dir_all(&db_path).await?;
+                }
+            }
+            Err(err) => warn!("The session file can't be read, keeping its store: {err}"),
+        }
+    }
+    for file in [session_file.to_owned(), sync_token_file(session_file)] {
+        if fs::try_exists(&file).await? {
+            fs::remove_file(&file).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Login to a new session.
+pub(crate) async fn login(
+    data_dir: &Path,
+    session_file: &Path,
+    config: &AccountConfig,
+    device_name: &str,
+) -> anyhow::Result<Client> {
+    info!("No previous session found, logging in…");
+    let mut rng = rand::thread_rng();
+
+    // Generate a random passphrase.
+    let passphrase: String = (&mut rng)
+        .sample_iter(Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    let db_subfolder: String = (&mut rng)
+        .sample_iter(Alphanumeric)
+        .take(7)
+        .map(char::from)
+        .collect();
+    let db_path = data_dir.join(db_subfolder);
+
+    let client = Client::builder()
+        .homeserver_url(&config.server)
+        .sqlite_store(&db_path, Some(&passphrase))
+        .build()
+        .await?;
+
+    let client_session = ClientSession {
+        homeserver: config.server.clone(),
+        db_path,
+        passphrase,
+    };
+    let matrix_auth = client.matrix_auth();
+
+    loop {
+        let username = &config.username;
+        let password = config.password();
+
+        match matrix_auth
+            .login_username(username, &password)
+            .initial_device_display_name(device_name)
+            .await
+        {
+            Ok(_) => {
+                info!("Logged in as {username}");
+                break;
+            }
+            Err(error) => {
+                error!("Error logging in: {error}");
+                if config.password.is_some() {
+                    return Err(error.into());
+                }
+            }
+        }
+    }
+
+    // Persist the session to reuse it later.
+    // This is not very secure, for simplicity. If the system provides a way of
+    // storing secrets securely, it should be used instead.
+    // Note that we could also build the user session from the login response.
+    let user_session = matrix_auth
+        .session()
+        .expect("A logged-in client should have a session");
+    let serialized_session = serde_json::to_string(&FullSession {
+        client_session,
+        user_session,
+        sync_token: None,
+    })?;
+    fs::write(session_file, serialized_session).await?;
+
+    info!("Session persisted in {}", session_file.to_string_lossy());
+
+    Ok(client)
+}
+
+/// Where the sync token is kept, next to the session.
+fn sync_token_file(session_file: &Path) -> PathBuf {
+    session_file.with_file_name("sync_token")
+}
+
+/// Persist the sync token for a future session.
+/// Note that this is needed only when using `sync_once`. Other sync methods get
+/// the sync token from the store.
+///
+/// Only the token is written, replacing the file in one go so a crash can't
+/// leave half of it behind.
+pub(crate) async fn persist_sync_token(
+    session_file: &Path,
+    sync_token: String,
+) -> anyhow::Result<()> {
+    let token_file = sync_token_file(session_file);
+    let temporary = token_file.with_extension("tmp");
+    fs::write(&temporary, sync_token).await?;
+    fs::rename(&temporary, &token_file).await?;
+    Ok(())
+}
+
+/// Persists sync tokens while syncing, at most every
+/// [`SYNC_TOKEN_INTERVAL`], so busy accounts don't write on every sync.
+///
+/// Writing a token is the second half of a two-phase commit: each batch is
+/// held in flight until the work it queued on
+/// [`Workers`](crate::workers::Workers) has finished, and only then is its
+/// token written. The token on disk never gets ahead of what was handled,
+/// and never moves backwards, so restarting from it is deterministic.
+pub(crate) struct SyncTokens<'a> {
+    session_file: &'a Path,
+    state: Mutex<SyncTokenState>,
+}
+
+struct SyncTokenState {
+    /// Batches whose work is still running, oldest first, with the token
+    /// that follows each.
+    in_flight: VecDeque<(String, Weak<()>)>,
+    /// The newest token whose batch and every earlier one were handled, if
+    /// it hasn't been written yet.
+    unwritten: Option<String>,
+    last_written: Instant,
+}
+
+impl<'a> SyncTokens<'a> {
+    pub(crate) fn new(session_file: &'a Path) -> Self {
+        Self {
+            session_file,
+            state: Mutex::new(SyncTokenState {
+                in_flight: VecDeque::new(),
+                unwritten: None,
+                last_written: Instant::now(),
+            }),
+        }
+    }
+
+    /// Note the token from a sync, along with the batch of work its events
+    /// queued, writing the newest handled token if it's been long enough.
+    pub(crate) async fn update(&self, sync_token: String, batch: Weak<()>) -> anyhow::Result<()> {
+        let sync_token = {
+            let mut state = self.state.lock().unwrap();
+            state.in_flight.push_back((sync_token, batch));
+            state.confirm();
+            if state.unwritten.is_none() || state.last_written.elapsed() < SYNC_TOKEN_INTERVAL {
+                return Ok(());
+            }
+            state.last_written = Instant::now();
+            state.unwritten.take()
+        };
+        match sync_token {
+            Some(sync_token) => persist_sync_token(self.session_file, sync_token).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Write the newest handled token, if it hasn't been yet, e.g. when
+    /// shutting down. Batches still being worked on are left for the next
+    /// run to decide about, according to its [`Delivery`].
+    pub(crate) async fn flush(&self) -> anyhow::Result<()> {
+        let sync_token = {
+            let mut state = self.state.lock().unwrap();
+            state.confirm();
+            if !state.in_flight.is_empty() {
+                info!(
+                    batches = state.in_flight.len(),
+                    "Stopping with sync batches still being handled"
+                );
+            }
+            state.unwritten.take()
+        };
+        match sync_token {
+            Some(sync_token) => persist_sync_token(self.session_file, sync_token).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl SyncTokenState {
+    /// Move batches that have finished off the front of the queue. A batch
+    /// that finished early waits for the ones before it.
+    fn confirm(&mut self) {
+        while let Some((_, batch)) = self.in_flight.front() {
+            if batch.strong_count() > 0 {
+                break;
+            }
+            let (sync_token, _) = self.in_flight.pop_front().unwrap();
+            self.unwritten = Some(sync_token);
+        }
+    }
+}
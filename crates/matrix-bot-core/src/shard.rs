@@ -0,0 +1,207 @@
+//! Sharing a bot's rooms out between several instances of it, for accounts
+//! in more rooms than one instance can keep up with.
+//!
+//! Each instance logs in as its own device, with its own data directory,
+//! and keeps its [store](crate::store) in the same Postgres database. They
+//! note that they're running there every few seconds, and each room is
+//! handled by whichever running instance its ID hashes highest with, so a
+//! room only moves when the instance handling it starts or stops. Every
+//! instance still syncs every room, but work is only
+//! [queued](crate::workers::Workers::spawn) and
+//! [replies](crate::can_reply) only sent for the rooms it handles.
+//!
+//! While instances come and go, events can be handled twice, or not at
+//! all, for as long as it takes the others to notice.
+
+use std::{sync::RwLock, time::Duration};
+
+use matrix_sdk::ruma::RoomId;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{info, warn};
+
+use crate::store::{self, Store};
+
+/// How often each instance notes that it's running.
+const HEARTBEAT: Duration = Duration::from_secs(5);
+
+/// How long since an instance last noted it was running before its rooms
+/// are taken over.
+const EXPIRY: Duration = Duration::from_secs(20);
+
+static MEMBERSHIP: RwLock<Option<Membership>> = RwLock::new(None);
+
+struct Membership {
+    /// This instance.
+    me: String,
+    /// Every running instance, including this one, in order.
+    members: Vec<String>,
+}
+
+/// Whether this instance handles the room, which it does for every room
+/// when not sharded.
+pub fn owns(room_id: &RoomId) -> bool {
+    let membership = MEMBERSHIP.read().unwrap();
+    let Some(membership) = &*membership else {
+        return true;
+    };
+    owner(&membership.members, room_id).is_none_or(|owner| owner == membership.me)
+}
+
+/// How many instances the rooms are shared between, if they are.
+pub fn members() -> Option<usize> {
+    let membership = MEMBERSHIP.read().unwrap();
+    membership
+        .as_ref()
+        .map(|membership| membership.members.len())
+}
+
+/// The instance that handles the room.
+fn owner<'a>(members: &'a [String], room_id: &RoomId) -> Option<&'a str> {
+    members
+        .iter()
+        .max_by_key(|member| weight(member, room_id))
+        .map(String::as_str)
+}
+
+/// How strongly an instance is drawn to a room. This is FNV-1a, mixed, as
+/// it has to come out the same in every build of every instance, which
+/// std's hasher doesn't promise.
+fn weight(member: &str, room_id: &RoomId) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in member.bytes().chain([0]).chain(room_id.as_str().bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^ (hash >> 33)
+}
+
+/// Start sharing rooms with the other instances in `store` as `me`,
+/// leaving when the bot shuts down.
+pub(crate) fn start(
+    me: String,
+    store: Store,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<JoinHandle<()>> {
+    store.shard_heartbeat(&me)?;
+    refresh(&me, &store);
+    Ok(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                () = tokio::time::sleep(HEARTBEAT) => {}
+                _ = shutdown.wait_for(|&stopping| stopping) => {
+                    // So the others take over without waiting for it to expire
+                    if let Err(err) = store.leave_shards(&me) {
+                        warn!("Failed to leave the shards: {err:#}");
+                    }
+                    return;
+                }
+            }
+            if let Err(err) = store.shard_heartbeat(&me) {
+                warn!("Failed to note that this instance is running: {err:#}");
+            }
+            refresh(&me, &store);
+        }
+    }))
+}
+
+/// Catch up with which instances are running.
+fn refresh(me: &str, store: &Store) {
+    let since = store::now().saturating_sub(EXPIRY.as_secs());
+    let mut members = match store.shard_members(since) {
+        Ok(members) => members,
+        Err(err) => {
+            warn!("Failed to see which instances are running: {err:#}");
+            return;
+        }
+    };
+    if !members.iter().any(|member| member == me) {
+        members.push(me.to_owned());
+        members.sort();
+    }
+    let mut membership = MEMBERSHIP.write().unwrap();
+    if membership
+        .as_ref()
+        .is_some_and(|membership| membership.members == members)
+    {
+        return;
+    }
+    info!(
+        instances = members.len(),
+        "Sharing rooms between {} instances",
+        members.len()
+    );
+    metrics::gauge!("bot_shard_members").set(members.len() as f64);
+    *membership = Some(Membership {
+        me: me.to_owned(),
+        members,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk::ruma::{room_id, OwnedRoomId};
+
+    use super::*;
+
+    fn rooms() -> impl Iterator<Item = OwnedRoomId> {
+        (0..1000).map(|i| RoomId::parse(format!("!{i}:example.org")).unwrap())
+    }
+
+    fn instances(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&name| name.to_owned()).collect()
+    }
+
+    #[test]
+    fn weight_is_the_same_in_every_build() {
+        // Instances on different builds have to agree on who handles what
+        assert_eq!(
+            weight("a", room_id!("!room:example.org")),
+            0x6784_7995_ebdf_9029
+        );
+    }
+
+    #[test]
+    fn highest_weight_wins() {
+        let members = instances(&["a", "b", "c"]);
+        for room_id in rooms() {
+            let best = members
+                .iter()
+                .map(|member| weight(member, &room_id))
+                .max()
+                .unwrap();
+            let owner = owner(&members, &room_id).unwrap();
+            assert_eq!(weight(owner, &room_id), best);
+        }
+        assert_eq!(owner(&[], room_id!("!room:example.org")), None);
+    }
+
+    #[test]
+    fn rooms_spread_between_instances() {
+        let members = instances(&["a", "b", "c", "d"]);
+        for member in &members {
+            let owned = rooms()
+                .filter(|room_id| owner(&members, room_id) == Some(member.as_str()))
+                .count();
+            // An even share would be 250
+            assert!((200..300).contains(&owned), "{member} owns {owned}");
+        }
+    }
+
+    #[test]
+    fn rooms_only_move_to_a_new_instance() {
+        let before = instances(&["a", "b", "c", "d"]);
+        let after = instances(&["a", "b", "c", "d", "e"]);
+        let mut moved = 0;
+        for room_id in rooms() {
+            let old = owner(&before, &room_id).unwrap();
+            let new = owner(&after, &room_id).unwrap();
+            if old != new {
+                assert_eq!(new, "e");
+                moved += 1;
+            }
+        }
+        assert!((100..300).contains(&moved), "{moved} rooms moved");
+    }
+}
@@ -0,0 +1,351 @@
+//! "Remind me about this": react to any message with ⏰, tell the bot how
+//! long in the DM it opens, and it sends you a link to the message then.
+//!
+//! Timers are kept in a JSON file in the bot's data directory, so they
+//! survive restarts.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::{
+            reaction::OriginalSyncReactionEvent,
+            room::message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+        },
+        OwnedEventId, OwnedRoomId, OwnedUserId, UserId,
+    },
+    Client, Room, RoomState,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Notify, time::sleep};
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    fetch_message, format_duration, html, parse_duration, reply_notice, send_or_log_error,
+    text_body,
+};
+
+/// The reaction that asks for a reminder.
+const SNOOZE_REACTION: &str = "⏰";
+
+/// How long the bot waits for someone to say when to remind them.
+const PENDING_TTL: u64 = 60 * 60;
+
+/// The longest a message can be snoozed for.
+const MAX_SNOOZE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// How much of the message to quote in the reminder.
+const MAX_QUOTE_CHARS: usize = 300;
+
+/// How long to sleep when nothing is snoozed. New timers wake the task up
+/// early, so this only guards against missed notifications.
+const IDLE_SLEEP: Duration = Duration::from_secs(3600);
+
+/// A message someone reacted to, waiting for them to say when.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Pending {
+    message: Message,
+    asked_at: u64,
+}
+
+/// A message someone has asked to be reminded about.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Message {
+    room_id: OwnedRoomId,
+    event_id: OwnedEventId,
+    sender: OwnedUserId,
+    quote: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Timer {
+    user_id: OwnedUserId,
+    message: Message,
+    due: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    pending: BTreeMap<OwnedUserId, Pending>,
+    timers: Vec<Timer>,
+}
+
+#[derive(Clone)]
+struct Snoozes {
+    file: Arc<PathBuf>,
+    state: Arc<Mutex<State>>,
+    /// Wakes the timer task when a message is snoozed.
+    wake: Arc<Notify>,
+}
+
+impl Snoozes {
+    fn load(file: PathBuf) -> anyhow::Result<Self> {
+        let state = if file.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&file)?)?
+        } else {
+            State::default()
+        };
+        Ok(Self {
+            file: Arc::new(file),
+            state: Arc::new(Mutex::new(state)),
+            wake: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Change the state and write it out, replacing the file in one go so a
+    /// crash can't leave half of it behind.
+    fn update<T>(&self, change: impl FnOnce(&mut State) -> T) -> anyhow::Result<T> {
+        let mut state = self.state.lock().unwrap();
+        let result = change(&mut state);
+        let temporary = self.file.with_extension("json.tmp");
+        std::fs::write(&temporary, serde_json::to_string(&*state)?)?;
+        std::fs::rename(&temporary, &*self.file)?;
+        Ok(result)
+    }
+}
+
+/// Let people snooze messages by reacting to them with ⏰.
+///
+/// Call this after the initial sync, so old reactions aren't acted on.
+pub(crate) fn enable(client: &Client, data_dir: &Path) -> anyhow::Result<()> {
+    let snoozes = Snoozes::load(data_dir.join("snoozes.json"))?;
+    tokio::spawn(run(client.clone(), snoozes.clone()));
+    client.add_event_handler_context(snoozes);
+    client.add_event_handler(on_reaction);
+    client.add_event_handler(on_direct_message);
+    Ok(())
+}
+
+/// Ask someone who reacted with ⏰ when they want to be reminded.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+async fn on_reaction(
+    event: OriginalSyncReactionEvent,
+    room: Room,
+    snoozes: Ctx<Snoozes>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let annotation = &event.content.relates_to;
+    if annotation.key.trim_end_matches('\u{fe0f}') != SNOOZE_REACTION {
+        return Ok(());
+    }
+    let Some(target) = fetch_message(&room, &annotation.event_id).await? else {
+        return Ok(());
+    };
+
+    let message = Message {
+        room_id: room.room_id().to_owned(),
+        event_id: target.event_id.clone(),
+        sender: target.sender.clone(),
+        quote: quote(target.content.body()),
+    };
+    snoozes.update(|state| {
+        state.pending.insert(
+            event.sender.clone(),
+            Pending {
+                message,
+                asked_at: now(),
+            },
+        )
+    })?;
+    info!("Asking when to snooze a message until");
+
+    let dm = dm_room(&room.client(), &event.sender).await?;
+    send_or_log_error(
+        &dm,
+        RoomMessageEventContent::notice_plain(
+            "When should I remind you about that message? Send me how long to wait, \
+             like 30m, 2h or 1d, or cancel.",
+        ),
+    )
+    .await;
+    Ok(())
+}
+
+/// Take the answer to when someone wants to be reminded.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+async fn on_direct_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    snoozes: Ctx<Snoozes>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+    let answer = body.trim().to_lowercase();
+    let duration = parse_duration(&answer.replace(' ', ""));
+    // Anything else is for the bot itself
+    if duration.is_none() && answer != "cancel" {
+        return Ok(());
+    }
+    let pending = snoozes
+        .state
+        .lock()
+        .unwrap()
+        .pending
+        .get(&event.sender)
+        .cloned();
+    let Some(pending) = pending else {
+        return Ok(());
+    };
+    if !room.is_direct().await? {
+        return Ok(());
+    }
+
+    let response = if now().saturating_sub(pending.asked_at) > PENDING_TTL {
+        snoozes.update(|state| state.pending.remove(&event.sender))?;
+        format!("That was a while ago, react with {SNOOZE_REACTION} again to snooze it.")
+    } else {
+        match duration {
+            None => {
+                snoozes.update(|state| state.pending.remove(&event.sender))?;
+                "Okay, I won't remind you.".to_owned()
+            }
+            Some(duration) if duration > MAX_SNOOZE => {
+                "That's too far away, I can snooze messages for up to a year.".to_owned()
+            }
+            Some(duration) => {
+                snoozes.update(|state| {
+                    state.pending.remove(&event.sender);
+                    state.timers.push(Timer {
+                        user_id: event.sender.clone(),
+                        message: pending.message,
+                        due: now() + duration.as_secs(),
+                    });
+                })?;
+                snoozes.wake.notify_one();
+                info!(after = %format_duration(duration), "Snoozed a message");
+                format!("I'll remind you in {}.", format_duration(duration))
+            }
+        }
+    };
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+/// Send reminders as they come due, forever.
+async fn run(client: Client, snoozes: Snoozes) {
+    loop {
+        let now = now();
+        let any_due = snoozes
+            .state
+            .lock()
+            .unwrap()
+            .timers
+            .iter()
+            .any(|timer| timer.due <= now);
+        let due = if !any_due {
+            Ok(Vec::new())
+        } else {
+            snoozes.update(|state| {
+                let (due, waiting) = std::mem::take(&mut state.timers)
+                    .into_iter()
+                    .partition(|timer| timer.due <= now);
+                state.timers = waiting;
+                due
+            })
+        };
+        match due {
+            Ok(due) => {
+                for timer in due {
+                    remind(&client, &timer).await;
+                }
+            }
+            Err(err) => error!("Failed to save snoozed messages: {err:#}"),
+        }
+
+        let next_due = snoozes
+            .state
+            .lock()
+            .unwrap()
+            .timers
+            .iter()
+            .map(|timer| timer.due)
+            .min();
+        let sleep_for = match next_due {
+            Some(due) => Duration::from_secs(due.saturating_sub(now)).min(IDLE_SLEEP),
+            None => IDLE_SLEEP,
+        };
+
+        tokio::select! {
+            _ = sleep(sleep_for) => {}
+            _ = snoozes.wake.notified() => {}
+        }
+    }
+}
+
+#[instrument(skip_all, fields(user = timer.user_id.as_str()))]
+async fn remind(client: &Client, timer: &Timer) {
+    let message = &timer.message;
+    let link = message
+        .room_id
+        .matrix_to_event_uri(message.event_id.clone())
+        .to_string();
+    let room_name = client
+        .get_room(&message.room_id)
+        .and_then(|room| room.name())
+        .unwrap_or_else(|| message.room_id.to_string());
+    let content = RoomMessageEventContent::text_html(
+        format!(
+            "{SNOOZE_REACTION} You asked me to remind you about this message from {} in \
+             {room_name}:\n> {}\n{link}",
+            message.sender, message.quote
+        ),
+        format!(
+            "{SNOOZE_REACTION} You asked me to remind you about <a href=\"{}\">this message</a> \
+             from {} in {}:<blockquote>{}</blockquote>",
+            html::escape(&link),
+            html::user_pill(&message.sender),
+            html::escape(&room_name),
+            html::escape(&message.quote)
+        ),
+    );
+
+    match dm_room(client, &timer.user_id).await {
+        Ok(room) => {
+            info!("Sending snoozed message");
+            send_or_log_error(&room, content).await;
+        }
+        Err(err) => warn!("Failed to open a DM: {err:#}"),
+    }
+}
+
+/// The start of a message, on one line.
+fn quote(body: &str) -> String {
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    if body.chars().count() > MAX_QUOTE_CHARS {
+        let truncated: String = body.chars().take(MAX_QUOTE_CHARS).collect();
+        format!("{}…", truncated.trim_end())
+    } else {
+        body
+    }
+}
+
+async fn dm_room(client: &Client, user_id: &UserId) -> anyhow::Result<Room> {
+    if let Some(room) = client.get_dm_room(user_id) {
+        return Ok(room);
+    }
+    Ok(client.create_dm(user_id).await?)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
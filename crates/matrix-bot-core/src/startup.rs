@@ -0,0 +1,87 @@
+//! How long starting up took, phase by phase, for working out why a bot on
+//! a big account takes minutes to start.
+//!
+//! [`Bot`](crate::Bot) ends a phase at each step of starting up, so the time
+//! a bot spends on its own setup, like opening stores, shows up between
+//! logging in and the initial sync.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+/// Startup timings. Cloning it shares them.
+#[derive(Clone)]
+pub struct Startup {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    started: Instant,
+    phase_started: Instant,
+    phases: Vec<(&'static str, Duration)>,
+    ready: bool,
+}
+
+impl Startup {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                started: now,
+                phase_started: now,
+                phases: Vec::new(),
+                ready: false,
+            })),
+        }
+    }
+
+    /// End the phase that's been running, calling it `name`.
+    pub(crate) fn finish(&self, name: &'static str) {
+        let mut inner = self.inner.lock().unwrap();
+        let took = inner.phase_started.elapsed();
+        inner.phases.push((name, took));
+        inner.phase_started = Instant::now();
+        info!(phase = name, "Startup phase took {}", seconds(took));
+    }
+
+    /// Mark the bot as ready, logging how long each phase took.
+    pub(crate) fn ready(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.ready = true;
+        info!(
+            "Ready after {} ({})",
+            seconds(inner.started.elapsed()),
+            summary(&inner.phases)
+        );
+    }
+
+    /// Whether the bot has finished starting up and is handling events.
+    pub fn is_ready(&self) -> bool {
+        self.inner.lock().unwrap().ready
+    }
+
+    /// The phases finished so far, in order, with how long they took.
+    pub fn phases(&self) -> Vec<(&'static str, Duration)> {
+        self.inner.lock().unwrap().phases.clone()
+    }
+
+    /// The phases on one line, e.g. for a status command.
+    pub fn summary(&self) -> String {
+        summary(&self.inner.lock().unwrap().phases)
+    }
+}
+
+fn summary(phases: &[(&'static str, Duration)]) -> String {
+    phases
+        .iter()
+        .map(|(name, took)| format!("{name} {}", seconds(*took)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
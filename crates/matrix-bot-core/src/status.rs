@@ -0,0 +1,48 @@
+//! `!status`, for seeing how the bot is getting on with its homeserver.
+
+use std::fmt::Write;
+
+use matrix_sdk::{ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState};
+use tracing::instrument;
+
+use crate::{can_reply, pacing, reply_notice, send, shard, strip_command, text_body};
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub(crate) async fn on_status(event: OriginalSyncRoomMessageEvent, room: Room) {
+    if room.state() != RoomState::Joined {
+        return;
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return;
+    }
+    let Some(body) = text_body(&event) else {
+        return;
+    };
+    if strip_command(body, "!status").is_none() || !can_reply(&room).await {
+        return;
+    }
+    reply_notice(&room, &event, status()).await;
+}
+
+fn status() -> String {
+    let mut status = String::new();
+    let limits = pacing::limits();
+    if limits.is_empty() {
+        status.push_str("Sending messages as fast as the homeserver allows.");
+    }
+    for (homeserver, interval) in limits {
+        let _ = writeln!(
+            status,
+            "Pacing messages to {homeserver}: one every {}ms.",
+            interval.as_millis()
+        );
+    }
+    let queued = send::queued();
+    if queued > 0 {
+        let _ = write!(status, "\n{queued} messages waiting to be sent.");
+    }
+    if let Some(members) = shard::members() {
+        let _ = write!(status, "\nSharing rooms between {members} instances.");
+    }
+    status.trim_end().to_owned()
+}
@@ -0,0 +1,587 @@
+//! Persistence for bots, so they don't each have to invent their own.
+//!
+//! A [`Store`] comes with tables for the things most bots keep: per-room
+//! settings, per-user preferences and counters. Values are stored as JSON,
+//! so anything serde can handle fits. When each room's settings and the
+//! preferences last changed is kept too, for
+//! [backing them up](crate::Bot::enable_settings_backup) to the account.
+//!
+//! Those tables are kept by a [`Backend`]: SQLite by default, or with the
+//! `postgres` feature, a Postgres database that several instances of a bot
+//! can share. Bots can add tables of their own with migrations, applied in
+//! order and tracked in the database's `user_version`, but only in SQLite.
+//!
+//! Bots open theirs with [`Bot::open_store`](crate::Bot::open_store), in
+//! `store.sqlite3` in their data directory.
+
+mod sqlite;
+
+#[cfg(feature = "postgres")]
+mod postgres;
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use matrix_sdk::ruma::{RoomId, UserId};
+use rusqlite::{Connection, Transaction};
+use serde::{de::DeserializeOwned, Serialize};
+
+use sqlite::Sqlite;
+
+/// The scope in `settings_updated` for user preferences, which are kept
+/// together. Rooms' settings are under their room ID.
+pub(crate) const USER_PREFS_SCOPE: &str = "user_prefs";
+
+/// A room's settings, by key.
+pub(crate) type Settings = BTreeMap<String, serde_json::Value>;
+
+/// One of the shared tables of keys and values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Table {
+    RoomSettings,
+    UserPrefs,
+}
+
+impl Table {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::RoomSettings => "room_settings",
+            Self::UserPrefs => "user_prefs",
+        }
+    }
+
+    /// The column of the ID the keys belong to.
+    pub fn column(self) -> &'static str {
+        match self {
+            Self::RoomSettings => "room_id",
+            Self::UserPrefs => "user_id",
+        }
+    }
+
+    /// Where in `settings_updated` changes to the rows for `id` are noted.
+    pub fn scope(self, id: &str) -> &str {
+        match self {
+            Self::RoomSettings => id,
+            Self::UserPrefs => USER_PREFS_SCOPE,
+        }
+    }
+}
+
+/// A row of a [`Table`]: the ID, the key and the value as JSON.
+pub type Row = (String, String, String);
+
+/// Where a [`Store`]'s shared tables are kept.
+///
+/// Setting or removing a value notes the time under the table's
+/// [scope](Table::scope) for the ID, in the same transaction.
+pub trait Backend: Send + Sync {
+    /// A value as JSON, or `None` if it hasn't been set.
+    fn get(&self, table: Table, id: &str, key: &str) -> anyhow::Result<Option<String>>;
+
+    fn set(&self, table: Table, id: &str, key: &str, value: &str, now: u64) -> anyhow::Result<()>;
+
+    /// Remove a value, returning whether it had been set.
+    fn remove(&self, table: Table, id: &str, key: &str, now: u64) -> anyhow::Result<bool>;
+
+    /// The rows for `id`, or every row if it's `None`, ordered by ID and
+    /// key.
+    fn list(&self, table: Table, id: Option<&str>) -> anyhow::Result<Vec<Row>>;
+
+    /// Replace the rows for `id`, or every row if it's `None`, noting the
+    /// change as made at `updated_at`.
+    fn replace(
+        &self,
+        table: Table,
+        id: Option<&str>,
+        rows: &[Row],
+        updated_at: u64,
+    ) -> anyhow::Result<()>;
+
+    /// When the rows under a scope last changed, if they ever have.
+    fn updated(&self, scope: &str) -> anyhow::Result<Option<u64>>;
+
+    /// A counter's value, zero if it's never been changed.
+    fn counter(&self, scope: &str, key: &str) -> anyhow::Result<i64>;
+
+    /// Add `by` to a counter, returning its new value.
+    fn increment(&self, scope: &str, key: &str, by: i64) -> anyhow::Result<i64>;
+
+    /// The counters in a scope, highest first.
+    fn counters(&self, scope: &str) -> anyhow::Result<Vec<(String, i64)>>;
+
+    /// Remove every counter in a scope, returning how many there were.
+    fn reset_counters(&self, scope: &str) -> anyhow::Result<usize>;
+
+    /// Note that the instance `member` of a [sharded](crate::shard) bot was
+    /// running at `now`.
+    fn heartbeat(&self, member: &str, now: u64) -> anyhow::Result<()>;
+
+    /// The instances that have noted they were running since `since`, in
+    /// order.
+    fn members(&self, since: u64) -> anyhow::Result<Vec<String>>;
+
+    /// Forget the instance `member`, which is stopping.
+    fn leave(&self, member: &str) -> anyhow::Result<()>;
+}
+
+/// A bot's store. Cloning it shares the connection.
+#[derive(Clone)]
+pub struct Store {
+    backend: Arc<dyn Backend>,
+    /// The SQLite connection, for bots' own tables, if that's the backend.
+    conn: Option<Arc<Mutex<Connection>>>,
+}
+
+impl Store {
+    /// Open the SQLite database at `path`, creating it if needed, and bring
+    /// it up to date with `migrations`.
+    ///
+    /// Migrations are applied in order, each in its own transaction, and
+    /// only once: add new ones to the end of the list, and never change one
+    /// that has shipped.
+    pub fn open(path: &Path, migrations: &[&str]) -> anyhow::Result<Self> {
+        let conn =
+            Connection::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        Self::sqlite(conn, migrations)
+    }
+
+    /// A store that only lives in memory, for trying things out and tests.
+    pub fn in_memory(migrations: &[&str]) -> anyhow::Result<Self> {
+        Self::sqlite(Connection::open_in_memory()?, migrations)
+    }
+
+    /// Connect to the Postgres database at `url`, creating the shared
+    /// tables if needed.
+    ///
+    /// Give each bot its own database or schema: instances of the same bot
+    /// can share one, but different bots' settings would get mixed up.
+    #[cfg(feature = "postgres")]
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        Ok(Self::with_backend(postgres::Postgres::connect(url).await?))
+    }
+
+    /// A store keeping the shared tables in `backend`, with no tables of
+    /// the bot's own.
+    pub fn with_backend(backend: impl Backend + 'static) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            conn: None,
+        }
+    }
+
+    fn sqlite(conn: Connection, migrations: &[&str]) -> anyhow::Result<Self> {
+        let conn = Arc::new(Mutex::new(conn));
+        let backend = Sqlite::new(conn.clone(), migrations)?;
+        Ok(Self {
+            backend: Arc::new(backend),
+            conn: Some(conn),
+        })
+    }
+
+    /// Whether the shared tables are in SQLite, so only this instance of
+    /// the bot sees them.
+    pub fn is_sqlite(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    fn conn(&self) -> anyhow::Result<&Mutex<Connection>> {
+        self.conn
+            .as_deref()
+            .context("the bot's own tables can only be kept in SQLite")
+    }
+
+    /// Run queries of the bot's own against the database.
+    pub fn with<T>(&self, f: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> anyhow::Result<T> {
+        let conn = self.conn()?.lock().unwrap();
+        Ok(f(&conn)?)
+    }
+
+    /// Run queries of the bot's own in a transaction, committed if `f`
+    /// succeeds.
+    pub fn transaction<T>(
+        &self,
+        f: impl FnOnce(&Transaction) -> rusqlite::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut conn = self.conn()?.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let result = f(&transaction)?;
+        transaction.commit()?;
+        Ok(result)
+    }
+
+    /// A room's setting, or `None` if it hasn't been set.
+    pub fn room_setting<T: DeserializeOwned>(
+        &self,
+        room_id: &RoomId,
+        key: &str,
+    ) -> anyhow::Result<Option<T>> {
+        self.get(Table::RoomSettings, room_id.as_str(), key)
+    }
+
+    pub fn set_room_setting<T: Serialize>(
+        &self,
+        room_id: &RoomId,
+        key: &str,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        self.set(Table::RoomSettings, room_id.as_str(), key, value)
+    }
+
+    /// Go back to the default for a room's setting, returning whether it had
+    /// been set.
+    pub fn remove_room_setting(&self, room_id: &RoomId, key: &str) -> anyhow::Result<bool> {
+        self.backend
+            .remove(Table::RoomSettings, room_id.as_str(), key, now())
+    }
+
+    /// Every setting that's been set in a room, by key.
+    pub fn room_settings(
+        &self,
+        room_id: &RoomId,
+    ) -> anyhow::Result<Vec<(String, serde_json::Value)>> {
+        self.backend
+            .list(Table::RoomSettings, Some(room_id.as_str()))?
+            .into_iter()
+            .map(|(_, key, value)| {
+                let value = serde_json::from_str(&value)
+                    .with_context(|| format!("invalid value for {key} in room_settings"))?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    /// A user's preference, or `None` if they haven't set it.
+    pub fn user_pref<T: DeserializeOwned>(
+        &self,
+        user_id: &UserId,
+        key: &str,
+    ) -> anyhow::Result<Option<T>> {
+        self.get(Table::UserPrefs, user_id.as_str(), key)
+    }
+
+    pub fn set_user_pref<T: Serialize>(
+        &self,
+        user_id: &UserId,
+        key: &str,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        self.set(Table::UserPrefs, user_id.as_str(), key, value)
+    }
+
+    /// Go back to the default for a user's preference, returning whether
+    /// they had set it.
+    pub fn remove_user_pref(&self, user_id: &UserId, key: &str) -> anyhow::Result<bool> {
+        self.backend
+            .remove(Table::UserPrefs, user_id.as_str(), key, now())
+    }
+
+    /// A counter's value, zero if it's never been changed.
+    ///
+    /// `scope` groups counters, e.g. a room or user ID.
+    pub fn counter(&self, scope: &str, key: &str) -> anyhow::Result<i64> {
+        self.backend.counter(scope, key)
+    }
+
+    /// Add `by` to a counter, which may be negative, returning its new value.
+    pub fn increment(&self, scope: &str, key: &str, by: i64) -> anyhow::Result<i64> {
+        self.backend.increment(scope, key, by)
+    }
+
+    /// The counters in a scope, highest first.
+    pub fn counters(&self, scope: &str) -> anyhow::Result<Vec<(String, i64)>> {
+        self.backend.counters(scope)
+    }
+
+    /// Set every counter in a scope back to zero, returning how many there
+    /// were.
+    pub fn reset_counters(&self, scope: &str) -> anyhow::Result<usize> {
+        self.backend.reset_counters(scope)
+    }
+
+    pub(crate) fn shard_heartbeat(&self, member: &str) -> anyhow::Result<()> {
+        self.backend.heartbeat(member, now())
+    }
+
+    pub(crate) fn shard_members(&self, since: u64) -> anyhow::Result<Vec<String>> {
+        self.backend.members(since)
+    }
+
+    pub(crate) fn leave_shards(&self, member: &str) -> anyhow::Result<()> {
+        self.backend.leave(member)
+    }
+
+    /// When a room's settings, or the preferences for
+    /// [`USER_PREFS_SCOPE`], last changed, if they ever have.
+    pub(crate) fn settings_updated(&self, scope: &str) -> anyhow::Result<Option<u64>> {
+        self.backend.updated(scope)
+    }
+
+    /// Everyone's preferences, by user ID.
+    pub(crate) fn user_prefs(&self) -> anyhow::Result<BTreeMap<String, Settings>> {
+        let mut prefs = BTreeMap::<String, Settings>::new();
+        for (user_id, key, value) in self.backend.list(Table::UserPrefs, None)? {
+            let value = serde_json::from_str(&value)
+                .with_context(|| format!("invalid value for {key} in user_prefs"))?;
+            prefs.entry(user_id).or_default().insert(key, value);
+        }
+        Ok(prefs)
+    }
+
+    /// Replace a room's settings with a copy of them from `updated_at`.
+    pub(crate) fn restore_room_settings(
+        &self,
+        room_id: &RoomId,
+        settings: &Settings,
+        updated_at: u64,
+    ) -> anyhow::Result<()> {
+        let rows = settings
+            .iter()
+            .map(|(key, value)| {
+                Ok((
+                    room_id.to_string(),
+                    key.clone(),
+                    serde_json::to_string(value)?,
+                ))
+            })
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        self.backend.replace(
+            Table::RoomSettings,
+            Some(room_id.as_str()),
+            &rows,
+            updated_at,
+        )
+    }
+
+    /// Replace everyone's preferences with a copy of them from `updated_at`.
+    pub(crate) fn restore_user_prefs(
+        &self,
+        prefs: &BTreeMap<String, Settings>,
+        updated_at: u64,
+    ) -> anyhow::Result<()> {
+        let rows = prefs
+            .iter()
+            .flat_map(|(user_id, prefs)| {
+                prefs.iter().map(move |(key, value)| {
+                    Ok((user_id.clone(), key.clone(), serde_json::to_string(value)?))
+                })
+            })
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        self.backend
+            .replace(Table::UserPrefs, None, &rows, updated_at)
+    }
+
+    fn get<T: DeserializeOwned>(
+        &self,
+        table: Table,
+        id: &str,
+        key: &str,
+    ) -> anyhow::Result<Option<T>> {
+        self.backend
+            .get(table, id, key)?
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .with_context(|| format!("invalid value for {key} in {}", table.name()))
+    }
+
+    fn set<T: Serialize>(
+        &self,
+        table: Table,
+        id: &str,
+        key: &str,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        let value = serde_json::to_string(value)?;
+        self.backend.set(table, id, key, &value, now())
+    }
+}
+
+/// The current time as a Unix timestamp in seconds, as stores keep it.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use matrix_sdk::ruma::{room_id, user_id};
+
+    use super::*;
+
+    /// A database file of its own for a test, removed when dropped.
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "matrix-bot-core-{name}-{}.sqlite3",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn user_version(store: &Store) -> usize {
+        store
+            .with(|conn| conn.query_row("PRAGMA user_version", [], |row| row.get(0)))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrations_are_applied_once_and_in_order() {
+        let db = TempDb::new("migrations");
+        let first = ["CREATE TABLE notes (body TEXT NOT NULL);"];
+        let store = Store::open(&db.0, &first).unwrap();
+        store
+            .with(|conn| conn.execute("INSERT INTO notes (body) VALUES ('hello')", []))
+            .unwrap();
+        assert_eq!(user_version(&store), 1);
+        drop(store);
+
+        // Opening again with a new migration only applies that one
+        let second = [first[0], "ALTER TABLE notes ADD COLUMN author TEXT;"];
+        let store = Store::open(&db.0, &second).unwrap();
+        assert_eq!(user_version(&store), 2);
+        let (body, author): (String, Option<String>) = store
+            .with(|conn| {
+                conn.query_row("SELECT body, author FROM notes", [], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+            })
+            .unwrap();
+        assert_eq!(body, "hello");
+        assert_eq!(author, None);
+    }
+
+    #[test]
+    fn a_database_from_a_newer_version_is_refused() {
+        let db = TempDb::new("newer");
+        let migrations = ["CREATE TABLE a (x);", "CREATE TABLE b (x);"];
+        drop(Store::open(&db.0, &migrations).unwrap());
+        assert!(Store::open(&db.0, &migrations[..1]).is_err());
+    }
+
+    #[test]
+    fn a_failed_migration_is_rolled_back() {
+        let db = TempDb::new("failed");
+        let migrations = ["CREATE TABLE a (x);", "CREATE TABLE b (x); NOT SQL;"];
+        assert!(Store::open(&db.0, &migrations).is_err());
+
+        let store = Store::open(&db.0, &migrations[..1]).unwrap();
+        assert_eq!(user_version(&store), 1);
+        let tables: i64 = store
+            .with(|conn| {
+                conn.query_row(
+                    "SELECT COUNT(*) FROM sqlite_master WHERE name = 'b'",
+                    [],
+                    |row| row.get(0),
+                )
+            })
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+
+    #[test]
+    fn room_settings_round_trip() {
+        let store = Store::in_memory(&[]).unwrap();
+        let room = room_id!("!room:example.org");
+        let other = room_id!("!other:example.org");
+
+        assert_eq!(store.room_setting::<bool>(room, "enabled").unwrap(), None);
+        assert_eq!(store.settings_updated(room.as_str()).unwrap(), None);
+
+        store.set_room_setting(room, "enabled", &true).unwrap();
+        store.set_room_setting(room, "greeting", &"hi").unwrap();
+        store.set_room_setting(other, "enabled", &false).unwrap();
+        assert_eq!(
+            store.room_setting::<bool>(room, "enabled").unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            store.room_setting::<String>(room, "greeting").unwrap(),
+            Some("hi".to_owned())
+        );
+        assert_eq!(
+            store.room_setting::<bool>(other, "enabled").unwrap(),
+            Some(false)
+        );
+        assert!(store.settings_updated(room.as_str()).unwrap().is_some());
+
+        // Reading a setting as the wrong type is an error, not a default
+        assert!(store.room_setting::<u32>(room, "greeting").is_err());
+
+        assert!(store.remove_room_setting(room, "enabled").unwrap());
+        assert!(!store.remove_room_setting(room, "enabled").unwrap());
+        assert_eq!(store.room_setting::<bool>(room, "enabled").unwrap(), None);
+        assert_eq!(
+            store.room_setting::<bool>(other, "enabled").unwrap(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn restoring_room_settings_replaces_them() {
+        let store = Store::in_memory(&[]).unwrap();
+        let room = room_id!("!room:example.org");
+        store.set_room_setting(room, "stale", &1).unwrap();
+
+        let settings = Settings::from([("fresh".to_owned(), serde_json::json!(2))]);
+        store.restore_room_settings(room, &settings, 1234).unwrap();
+        assert_eq!(store.room_setting::<i64>(room, "stale").unwrap(), None);
+        assert_eq!(store.room_setting::<i64>(room, "fresh").unwrap(), Some(2));
+        assert_eq!(store.settings_updated(room.as_str()).unwrap(), Some(1234));
+    }
+
+    #[test]
+    fn user_prefs_round_trip() {
+        let store = Store::in_memory(&[]).unwrap();
+        let alice = user_id!("@alice:example.org");
+        let bob = user_id!("@bob:example.org");
+
+        store.set_user_pref(alice, "plain", &true).unwrap();
+        store.set_user_pref(bob, "plain", &false).unwrap();
+        assert_eq!(store.user_pref::<bool>(alice, "plain").unwrap(), Some(true));
+        assert_eq!(store.user_pref::<bool>(bob, "plain").unwrap(), Some(false));
+        assert!(store.settings_updated(USER_PREFS_SCOPE).unwrap().is_some());
+
+        let prefs = store.user_prefs().unwrap();
+        assert_eq!(prefs.len(), 2);
+        assert_eq!(prefs[alice.as_str()]["plain"], serde_json::json!(true));
+
+        assert!(store.remove_user_pref(alice, "plain").unwrap());
+        assert_eq!(store.user_pref::<bool>(alice, "plain").unwrap(), None);
+    }
+
+    #[test]
+    fn counters_add_up() {
+        let store = Store::in_memory(&[]).unwrap();
+        assert_eq!(store.counter("karma", "alice").unwrap(), 0);
+        assert_eq!(store.increment("karma", "alice", 2).unwrap(), 2);
+        assert_eq!(store.increment("karma", "alice", -1).unwrap(), 1);
+        assert_eq!(store.increment("karma", "bob", 5).unwrap(), 5);
+        assert_eq!(store.increment("other", "alice", 7).unwrap(), 7);
+        assert_eq!(
+            store.counters("karma").unwrap(),
+            [("bob".to_owned(), 5), ("alice".to_owned(), 1)]
+        );
+
+        assert_eq!(store.reset_counters("karma").unwrap(), 2);
+        assert_eq!(store.counter("karma", "bob").unwrap(), 0);
+        assert!(store.counters("karma").unwrap().is_empty());
+        assert_eq!(store.counter("other", "alice").unwrap(), 7);
+    }
+}
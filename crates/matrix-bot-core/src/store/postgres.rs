@@ -0,0 +1,288 @@
+//! Keeping a store's shared tables in Postgres, so several instances of a
+//! bot, or one in a container with nowhere to keep files, can share them.
+//!
+//! The store's API is synchronous like SQLite's, so queries are run on the
+//! runtime from a blocking section, which needs the multi-threaded runtime
+//! the bots use. The connection doesn't use TLS, so keep the database on a
+//! network you trust.
+
+use std::future::Future;
+
+use anyhow::Context;
+use tokio::{runtime::Handle, sync::Mutex};
+use tokio_postgres::{Client, NoTls};
+use tracing::error;
+
+use super::{Backend, Row, Table};
+
+/// The shared tables, as in SQLite.
+const SHARED_TABLES: &str = "CREATE TABLE IF NOT EXISTS room_settings (
+        room_id TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (room_id, key)
+    );
+    CREATE TABLE IF NOT EXISTS user_prefs (
+        user_id TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (user_id, key)
+    );
+    CREATE TABLE IF NOT EXISTS counters (
+        scope TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value BIGINT NOT NULL,
+        PRIMARY KEY (scope, key)
+    );
+    CREATE TABLE IF NOT EXISTS settings_updated (
+        scope TEXT PRIMARY KEY NOT NULL,
+        updated_at BIGINT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS shard_members (
+        member TEXT PRIMARY KEY NOT NULL,
+        seen_at BIGINT NOT NULL
+    );";
+
+const TOUCH: &str = "INSERT INTO settings_updated (scope, updated_at) VALUES ($1, $2)
+    ON CONFLICT (scope) DO UPDATE SET updated_at = excluded.updated_at";
+
+pub(super) struct Postgres {
+    // Transactions need the client to themselves
+    client: Mutex<Client>,
+}
+
+impl Postgres {
+    pub(super) async fn connect(url: &str) -> anyhow::Result<Self> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls)
+            .await
+            .context("failed to connect to Postgres")?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                error!("Lost the connection to Postgres: {err}");
+            }
+        });
+        client.batch_execute(SHARED_TABLES).await?;
+        Ok(Self {
+            client: Mutex::new(client),
+        })
+    }
+}
+
+/// Wait for a query from the store's synchronous API.
+fn block_on<T>(query: impl Future<Output = Result<T, tokio_postgres::Error>>) -> anyhow::Result<T> {
+    Ok(tokio::task::block_in_place(|| {
+        Handle::current().block_on(query)
+    })?)
+}
+
+/// Timestamps are unsigned, but Postgres only has signed integers.
+fn timestamp(at: u64) -> i64 {
+    i64::try_from(at).unwrap_or(i64::MAX)
+}
+
+impl Backend for Postgres {
+    fn get(&self, table: Table, id: &str, key: &str) -> anyhow::Result<Option<String>> {
+        let query = format!(
+            "SELECT value FROM {} WHERE {} = $1 AND key = $2",
+            table.name(),
+            table.column()
+        );
+        let row = block_on(async {
+            self.client
+                .lock()
+                .await
+                .query_opt(&query, &[&id, &key])
+                .await
+        })?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    fn set(&self, table: Table, id: &str, key: &str, value: &str, now: u64) -> anyhow::Result<()> {
+        let (name, column) = (table.name(), table.column());
+        let query = format!(
+            "INSERT INTO {name} ({column}, key, value) VALUES ($1, $2, $3)
+             ON CONFLICT ({column}, key) DO UPDATE SET value = excluded.value"
+        );
+        let scope = table.scope(id);
+        block_on(async {
+            let mut client = self.client.lock().await;
+            let transaction = client.transaction().await?;
+            transaction.execute(&query, &[&id, &key, &value]).await?;
+            transaction
+                .execute(TOUCH, &[&scope, &timestamp(now)])
+                .await?;
+            transaction.commit().await
+        })
+    }
+
+    fn remove(&self, table: Table, id: &str, key: &str, now: u64) -> anyhow::Result<bool> {
+        let query = format!(
+            "DELETE FROM {} WHERE {} = $1 AND key = $2",
+            table.name(),
+            table.column()
+        );
+        let scope = table.scope(id);
+        block_on(async {
+            let mut client = self.client.lock().await;
+            let transaction = client.transaction().await?;
+            let removed = transaction.execute(&query, &[&id, &key]).await?;
+            if removed > 0 {
+                transaction
+                    .execute(TOUCH, &[&scope, &timestamp(now)])
+                    .await?;
+            }
+            transaction.commit().await?;
+            Ok::<_, tokio_postgres::Error>(removed > 0)
+        })
+    }
+
+    fn list(&self, table: Table, id: Option<&str>) -> anyhow::Result<Vec<Row>> {
+        let (name, column) = (table.name(), table.column());
+        let query = format!(
+            "SELECT {column}, key, value FROM {name}
+             WHERE $1::TEXT IS NULL OR {column} = $1 ORDER BY {column}, key"
+        );
+        let rows = block_on(async { self.client.lock().await.query(&query, &[&id]).await })?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect())
+    }
+
+    fn replace(
+        &self,
+        table: Table,
+        id: Option<&str>,
+        rows: &[Row],
+        updated_at: u64,
+    ) -> anyhow::Result<()> {
+        let (name, column) = (table.name(), table.column());
+        let delete = format!("DELETE FROM {name} WHERE $1::TEXT IS NULL OR {column} = $1");
+        let insert = format!("INSERT INTO {name} ({column}, key, value) VALUES ($1, $2, $3)");
+        let scope = table.scope(id.unwrap_or_default());
+        block_on(async {
+            let mut client = self.client.lock().await;
+            let transaction = client.transaction().await?;
+            transaction.execute(&delete, &[&id]).await?;
+            for (row_id, key, value) in rows {
+                transaction.execute(&insert, &[row_id, key, value]).await?;
+            }
+            transaction
+                .execute(TOUCH, &[&scope, &timestamp(updated_at)])
+                .await?;
+            transaction.commit().await
+        })
+    }
+
+    fn updated(&self, scope: &str) -> anyhow::Result<Option<u64>> {
+        let row = block_on(async {
+            self.client
+                .lock()
+                .await
+                .query_opt(
+                    "SELECT updated_at FROM settings_updated WHERE scope = $1",
+                    &[&scope],
+                )
+                .await
+        })?;
+        Ok(row.map(|row| row.get::<_, i64>(0).max(0) as u64))
+    }
+
+    fn counter(&self, scope: &str, key: &str) -> anyhow::Result<i64> {
+        let row = block_on(async {
+            self.client
+                .lock()
+                .await
+                .query_opt(
+                    "SELECT value FROM counters WHERE scope = $1 AND key = $2",
+                    &[&scope, &key],
+                )
+                .await
+        })?;
+        Ok(row.map_or(0, |row| row.get(0)))
+    }
+
+    fn increment(&self, scope: &str, key: &str, by: i64) -> anyhow::Result<i64> {
+        let row = block_on(async {
+            self.client
+                .lock()
+                .await
+                .query_one(
+                    "INSERT INTO counters (scope, key, value) VALUES ($1, $2, $3)
+             ON CONFLICT (scope, key) DO UPDATE SET value = counters.value + $3
+             RETURNING value",
+                    &[&scope, &key, &by],
+                )
+                .await
+        })?;
+        Ok(row.get(0))
+    }
+
+    fn counters(&self, scope: &str) -> anyhow::Result<Vec<(String, i64)>> {
+        let rows = block_on(async {
+            self.client
+                .lock()
+                .await
+                .query(
+                    "SELECT key, value FROM counters WHERE scope = $1 ORDER BY value DESC, key",
+                    &[&scope],
+                )
+                .await
+        })?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    fn reset_counters(&self, scope: &str) -> anyhow::Result<usize> {
+        let removed = block_on(async {
+            self.client
+                .lock()
+                .await
+                .execute("DELETE FROM counters WHERE scope = $1", &[&scope])
+                .await
+        })?;
+        Ok(removed as usize)
+    }
+
+    fn heartbeat(&self, member: &str, now: u64) -> anyhow::Result<()> {
+        block_on(async {
+            self.client
+                .lock()
+                .await
+                .execute(
+                    "INSERT INTO shard_members (member, seen_at) VALUES ($1, $2)
+                     ON CONFLICT (member) DO UPDATE SET seen_at = excluded.seen_at",
+                    &[&member, &timestamp(now)],
+                )
+                .await
+        })?;
+        Ok(())
+    }
+
+    fn members(&self, since: u64) -> anyhow::Result<Vec<String>> {
+        let rows = block_on(async {
+            self.client
+                .lock()
+                .await
+                .query(
+                    "SELECT member FROM shard_members WHERE seen_at >= $1 ORDER BY member",
+                    &[&timestamp(since)],
+                )
+                .await
+        })?;
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    fn leave(&self, member: &str) -> anyhow::Result<()> {
+        block_on(async {
+            self.client
+                .lock()
+                .await
+                .execute("DELETE FROM shard_members WHERE member = $1", &[&member])
+                .await
+        })?;
+        Ok(())
+    }
+}
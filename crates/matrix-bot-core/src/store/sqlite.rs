@@ -0,0 +1,245 @@
+//! Keeping a store's tables in a local SQLite database.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{Backend, Row, Table};
+
+/// The tables every store has, alongside the bot's own.
+const SHARED_TABLES: &str = "CREATE TABLE IF NOT EXISTS room_settings (
+        room_id TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (room_id, key)
+    );
+    CREATE TABLE IF NOT EXISTS user_prefs (
+        user_id TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (user_id, key)
+    );
+    CREATE TABLE IF NOT EXISTS counters (
+        scope TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value INTEGER NOT NULL,
+        PRIMARY KEY (scope, key)
+    );
+    CREATE TABLE IF NOT EXISTS settings_updated (
+        scope TEXT PRIMARY KEY NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS shard_members (
+        member TEXT PRIMARY KEY NOT NULL,
+        seen_at INTEGER NOT NULL
+    );";
+
+pub(super) struct Sqlite {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Sqlite {
+    /// Set up the shared tables in `conn`, and apply the bot's migrations.
+    pub(super) fn new(conn: Arc<Mutex<Connection>>, migrations: &[&str]) -> anyhow::Result<Self> {
+        {
+            let mut conn = conn.lock().unwrap();
+            conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+            conn.execute_batch(SHARED_TABLES)?;
+            migrate(&mut conn, migrations)?;
+        }
+        Ok(Self { conn })
+    }
+}
+
+impl Backend for Sqlite {
+    fn get(&self, table: Table, id: &str, key: &str) -> anyhow::Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                &format!(
+                    "SELECT value FROM {} WHERE {} = ?1 AND key = ?2",
+                    table.name(),
+                    table.column()
+                ),
+                [id, key],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn set(&self, table: Table, id: &str, key: &str, value: &str, now: u64) -> anyhow::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} ({}, key, value) VALUES (?1, ?2, ?3)",
+                table.name(),
+                table.column()
+            ),
+            [id, key, value],
+        )?;
+        touch(&conn, table.scope(id), now)
+    }
+
+    fn remove(&self, table: Table, id: &str, key: &str, now: u64) -> anyhow::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            &format!(
+                "DELETE FROM {} WHERE {} = ?1 AND key = ?2",
+                table.name(),
+                table.column()
+            ),
+            [id, key],
+        )?;
+        if removed > 0 {
+            touch(&conn, table.scope(id), now)?;
+        }
+        Ok(removed > 0)
+    }
+
+    fn list(&self, table: Table, id: Option<&str>) -> anyhow::Result<Vec<Row>> {
+        let conn = self.conn.lock().unwrap();
+        let (name, column) = (table.name(), table.column());
+        let mut statement = conn.prepare(&format!(
+            "SELECT {column}, key, value FROM {name}
+             WHERE ?1 IS NULL OR {column} = ?1 ORDER BY {column}, key"
+        ))?;
+        let rows = statement
+            .query_map([id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(rows)
+    }
+
+    fn replace(
+        &self,
+        table: Table,
+        id: Option<&str>,
+        rows: &[Row],
+        updated_at: u64,
+    ) -> anyhow::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let (name, column) = (table.name(), table.column());
+        transaction.execute(
+            &format!("DELETE FROM {name} WHERE ?1 IS NULL OR {column} = ?1"),
+            [id],
+        )?;
+        for (row_id, key, value) in rows {
+            transaction.execute(
+                &format!("INSERT INTO {name} ({column}, key, value) VALUES (?1, ?2, ?3)"),
+                [row_id, key, value],
+            )?;
+        }
+        touch(
+            &transaction,
+            table.scope(id.unwrap_or_default()),
+            updated_at,
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn updated(&self, scope: &str) -> anyhow::Result<Option<u64>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                "SELECT updated_at FROM settings_updated WHERE scope = ?1",
+                [scope],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn counter(&self, scope: &str, key: &str) -> anyhow::Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        let value = conn
+            .query_row(
+                "SELECT value FROM counters WHERE scope = ?1 AND key = ?2",
+                [scope, key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value.unwrap_or(0))
+    }
+
+    fn increment(&self, scope: &str, key: &str, by: i64) -> anyhow::Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.query_row(
+            "INSERT INTO counters (scope, key, value) VALUES (?1, ?2, ?3)
+            ON CONFLICT (scope, key) DO UPDATE SET value = value + ?3
+            RETURNING value",
+            params![scope, key, by],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn counters(&self, scope: &str) -> anyhow::Result<Vec<(String, i64)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare("SELECT key, value FROM counters WHERE scope = ?1 ORDER BY value DESC, key")?;
+        let counters = statement
+            .query_map([scope], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(counters)
+    }
+
+    fn reset_counters(&self, scope: &str) -> anyhow::Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM counters WHERE scope = ?1", [scope])?)
+    }
+
+    fn heartbeat(&self, member: &str, now: u64) -> anyhow::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO shard_members (member, seen_at) VALUES (?1, ?2)",
+            params![member, now],
+        )?;
+        Ok(())
+    }
+
+    fn members(&self, since: u64) -> anyhow::Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement =
+            conn.prepare("SELECT member FROM shard_members WHERE seen_at >= ?1 ORDER BY member")?;
+        let members = statement
+            .query_map([since], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(members)
+    }
+
+    fn leave(&self, member: &str) -> anyhow::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM shard_members WHERE member = ?1", [member])?;
+        Ok(())
+    }
+}
+
+/// Note that the rows under `scope` changed at `at`.
+fn touch(conn: &Connection, scope: &str, at: u64) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO settings_updated (scope, updated_at) VALUES (?1, ?2)",
+        params![scope, at],
+    )?;
+    Ok(())
+}
+
+/// Apply the migrations the database hasn't had yet.
+fn migrate(conn: &mut Connection, migrations: &[&str]) -> anyhow::Result<()> {
+    let applied: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if applied > migrations.len() {
+        anyhow::bail!(
+            "the database has {applied} migrations applied, but this version only knows {}; \
+             was it opened by a newer version?",
+            migrations.len()
+        );
+    }
+    for (version, migration) in migrations.iter().enumerate().skip(applied) {
+        let transaction = conn.transaction()?;
+        transaction
+            .execute_batch(migration)
+            .with_context(|| format!("migration {} failed", version + 1))?;
+        transaction.pragma_update(None, "user_version", version + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+//! Tera templates for messages operators can customise without code
+//! changes.
+//!
+//! Each template has a plain-text body and optionally an HTML one, in which
+//! values are escaped. Both can use these filters:
+//!
+//! - `pill`: a user ID as a mention pill, or just the ID in plain text
+//! - `room_link`: a room ID or alias as a matrix.to link
+//! - `timestamp`: a Unix timestamp, in seconds or milliseconds, as a UTC
+//!   date, optionally with a strftime `format`
+
+use std::collections::HashMap;
+
+use anyhow::Context as _;
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime,
+};
+use matrix_sdk::ruma::{
+    events::room::message::RoomMessageEventContent, RoomAliasId, RoomId, UserId,
+};
+use tera::{Filter, Tera, Value};
+
+use crate::html;
+
+pub use tera::Context;
+
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+
+/// Timestamps bigger than this are taken to be in milliseconds, like
+/// `origin_server_ts`. In seconds, it's in the year 5138.
+const MILLISECONDS_THRESHOLD: i64 = 100_000_000_000;
+
+/// A set of named templates.
+pub struct Templates {
+    plain: Tera,
+    html: Tera,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Templates {
+    pub fn new() -> Self {
+        let mut plain = Tera::default();
+        plain.autoescape_on(Vec::new());
+        register_filters(&mut plain, false);
+        let mut html = Tera::default();
+        html.autoescape_on(vec![""]);
+        register_filters(&mut html, true);
+        Self { plain, html }
+    }
+
+    /// Add a template, checking its syntax.
+    pub fn add(&mut self, name: &str, plain: &str, html: Option<&str>) -> anyhow::Result<()> {
+        self.plain
+            .add_raw_template(name, plain)
+            .with_context(|| format!("invalid template {name}"))?;
+        if let Some(html) = html {
+            self.html
+                .add_raw_template(name, html)
+                .with_context(|| format!("invalid HTML template {name}"))?;
+        }
+        Ok(())
+    }
+
+    /// Render a template's plain and HTML bodies.
+    pub fn render(
+        &self,
+        name: &str,
+        context: &Context,
+    ) -> anyhow::Result<(String, Option<String>)> {
+        let plain = self
+            .plain
+            .render(name, context)
+            .with_context(|| format!("failed to render {name}"))?;
+        let html = if self.html.get_template_names().any(|html| html == name) {
+            Some(
+                self.html
+                    .render(name, context)
+                    .with_context(|| format!("failed to render the HTML of {name}"))?,
+            )
+        } else {
+            None
+        };
+        Ok((plain, html))
+    }
+
+    /// Render a template as a notice.
+    pub fn render_notice(
+        &self,
+        name: &str,
+        context: &Context,
+    ) -> anyhow::Result<RoomMessageEventContent> {
+        Ok(match self.render(name, context)? {
+            (plain, Some(html)) => RoomMessageEventContent::notice_html(plain, html),
+            (plain, None) => RoomMessageEventContent::notice_plain(plain),
+        })
+    }
+}
+
+fn register_filters(tera: &mut Tera, html: bool) {
+    tera.register_filter("pill", Pill { html });
+    tera.register_filter("room_link", RoomLink { html });
+    tera.register_filter("timestamp", timestamp);
+}
+
+struct Pill {
+    html: bool,
+}
+
+impl Filter for Pill {
+    fn filter(&self, value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+        let user_id = value.as_str().unwrap_or_default();
+        let user_id = UserId::parse(user_id)
+            .map_err(|_| tera::Error::msg(format!("`{value}` isn't a user ID")))?;
+        Ok(Value::String(if self.html {
+            html::user_pill(&user_id)
+        } else {
+            user_id.to_string()
+        }))
+    }
+
+    fn is_safe(&self) -> bool {
+        self.html
+    }
+}
+
+struct RoomLink {
+    html: bool,
+}
+
+impl Filter for RoomLink {
+    fn filter(&self, value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+        let room = value.as_str().unwrap_or_default();
+        let uri = if let Ok(room_id) = RoomId::parse(room) {
+            room_id.matrix_to_uri().to_string()
+        } else if let Ok(alias) = RoomAliasId::parse(room) {
+            alias.matrix_to_uri().to_string()
+        } else {
+            return Err(tera::Error::msg(format!(
+                "`{value}` isn't a room ID or alias"
+            )));
+        };
+        Ok(Value::String(if self.html {
+            format!(
+                "<a href=\"{}\">{}</a>",
+                html::escape(&uri),
+                html::escape(room)
+            )
+        } else {
+            uri
+        }))
+    }
+
+    fn is_safe(&self) -> bool {
+        self.html
+    }
+}
+
+fn timestamp(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let timestamp = value
+        .as_i64()
+        .ok_or_else(|| tera::Error::msg(format!("`{value}` isn't a timestamp")))?;
+    let date = if timestamp > MILLISECONDS_THRESHOLD {
+        DateTime::from_timestamp_millis(timestamp)
+    } else {
+        DateTime::from_timestamp(timestamp, 0)
+    }
+    .ok_or_else(|| tera::Error::msg(format!("{timestamp} is out of range")))?;
+    let format = args
+        .get("format")
+        .and_then(Value::as_str)
+        .unwrap_or(DEFAULT_TIMESTAMP_FORMAT);
+    // Formatting with a bad format panics, so check it first
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.contains(&Item::Error) {
+        return Err(tera::Error::msg(format!(
+            "`{format}` isn't a valid timestamp format"
+        )));
+    }
+    Ok(Value::String(
+        date.format_with_items(items.into_iter()).to_string(),
+    ))
+}
@@ -0,0 +1,366 @@
+//! Configuring rooms by DMing the bot, for operators who'd rather not edit
+//! config files.
+//!
+//! Send the bot `config` in a DM and it lists the rooms you can configure,
+//! then the settings the bot offers for the room you pick, as numbered
+//! menus. Answers are saved as room settings in the bot's [`Store`], under
+//! each [`Setting`]'s key, for the bot to read back with
+//! [`Setting::enabled`] and [`Setting::text`].
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::message::OriginalSyncRoomMessageEvent, OwnedRoomId, OwnedUserId, RoomId,
+        UserId,
+    },
+    Client, Room, RoomState,
+};
+use tracing::{info, instrument};
+
+use crate::{
+    permissions::{Permission, Permissions},
+    reply_notice,
+    store::Store,
+    text_body,
+};
+
+/// What people need in a room to configure it.
+pub const CONFIGURE: Permission = Permission::moderator("config");
+
+/// The message that starts the wizard.
+const START: &str = "config";
+
+/// How long the wizard waits for an answer before giving up.
+const SESSION_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The longest a text setting can be.
+const MAX_TEXT_CHARS: usize = 2000;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SettingKind {
+    /// A feature that's on or off.
+    Toggle { default: bool },
+    /// Some text, like a template.
+    Text { default: &'static str },
+}
+
+/// A room setting people can change with the wizard.
+#[derive(Debug, Clone, Copy)]
+pub struct Setting {
+    /// The key it's stored under.
+    pub key: &'static str,
+    /// What it's called in the menu.
+    pub description: &'static str,
+    pub kind: SettingKind,
+}
+
+impl Setting {
+    pub const fn toggle(key: &'static str, description: &'static str, default: bool) -> Self {
+        Self {
+            key,
+            description,
+            kind: SettingKind::Toggle { default },
+        }
+    }
+
+    pub const fn text(key: &'static str, description: &'static str, default: &'static str) -> Self {
+        Self {
+            key,
+            description,
+            kind: SettingKind::Text { default },
+        }
+    }
+
+    /// Whether a toggle is on in the room.
+    pub fn enabled(&self, store: &Store, room_id: &RoomId) -> anyhow::Result<bool> {
+        let default = match self.kind {
+            SettingKind::Toggle { default } => default,
+            SettingKind::Text { .. } => false,
+        };
+        Ok(store.room_setting(room_id, self.key)?.unwrap_or(default))
+    }
+
+    /// A text setting's value in the room.
+    pub fn text(&self, store: &Store, room_id: &RoomId) -> anyhow::Result<String> {
+        let default = match self.kind {
+            SettingKind::Text { default } => default,
+            SettingKind::Toggle { .. } => "",
+        };
+        Ok(store
+            .room_setting(room_id, self.key)?
+            .unwrap_or_else(|| default.to_owned()))
+    }
+
+    /// The setting's value in the room, for the menu.
+    fn describe(&self, store: &Store, room_id: &RoomId) -> anyhow::Result<String> {
+        Ok(match self.kind {
+            SettingKind::Toggle { .. } if self.enabled(store, room_id)? => "on".to_owned(),
+            SettingKind::Toggle { .. } => "off".to_owned(),
+            SettingKind::Text { .. } => format!("\"{}\"", self.text(store, room_id)?),
+        })
+    }
+}
+
+/// Where someone is in the wizard.
+#[derive(Debug, Clone)]
+enum Step {
+    /// Picking a room from the list.
+    Room { rooms: Vec<OwnedRoomId> },
+    /// Picking a setting for the room.
+    Setting { room_id: OwnedRoomId },
+    /// Typing the new value of a text setting.
+    Value {
+        room_id: OwnedRoomId,
+        setting: usize,
+    },
+}
+
+#[derive(Debug)]
+struct Session {
+    step: Step,
+    last_active: Instant,
+}
+
+#[derive(Clone)]
+struct Wizard {
+    store: Store,
+    permissions: Permissions,
+    settings: &'static [Setting],
+    sessions: Arc<Mutex<HashMap<OwnedUserId, Session>>>,
+}
+
+/// Let people configure `settings` for their rooms by DMing the bot.
+///
+/// Call this after the initial sync, so old DMs aren't answered.
+pub fn enable(
+    client: &Client,
+    store: Store,
+    permissions: Permissions,
+    settings: &'static [Setting],
+) {
+    client.add_event_handler_context(Wizard {
+        store,
+        permissions,
+        settings,
+        sessions: Default::default(),
+    });
+    client.add_event_handler(on_direct_message);
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+async fn on_direct_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    wizard: Ctx<Wizard>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+    let answer = body.trim();
+    let starting = answer.eq_ignore_ascii_case(START);
+    let active = wizard.sessions.lock().unwrap().contains_key(&event.sender);
+    if !starting && !active {
+        return Ok(());
+    }
+    if !room.is_direct().await? {
+        return Ok(());
+    }
+
+    let session = wizard.sessions.lock().unwrap().remove(&event.sender);
+    let (response, step) = match session {
+        _ if starting => wizard.rooms(&room.client(), &event.sender).await?,
+        Some(session) if session.last_active.elapsed() > SESSION_TTL => (
+            format!("That was a while ago, send {START} to start again."),
+            None,
+        ),
+        Some(_) if answer.eq_ignore_ascii_case("done") || answer.eq_ignore_ascii_case("cancel") => {
+            ("Okay, done.".to_owned(), None)
+        }
+        Some(session) => {
+            wizard
+                .answer(&room.client(), &event.sender, session.step, answer)
+                .await?
+        }
+        None => return Ok(()),
+    };
+    if let Some(step) = step {
+        wizard.sessions.lock().unwrap().insert(
+            event.sender.clone(),
+            Session {
+                step,
+                last_active: Instant::now(),
+            },
+        );
+    }
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+impl Wizard {
+    /// Take an answer to the last menu, returning the reply and the next
+    /// step.
+    async fn answer(
+        &self,
+        client: &Client,
+        user_id: &UserId,
+        step: Step,
+        answer: &str,
+    ) -> anyhow::Result<(String, Option<Step>)> {
+        match step {
+            Step::Room { rooms } => {
+                let Some(index) = choose(rooms.len(), answer) else {
+                    return Ok((
+                        format!("Send a number from 1 to {}, or done.", rooms.len()),
+                        Some(Step::Room { rooms }),
+                    ));
+                };
+                let room_id = rooms[index].clone();
+                self.settings_menu(client, user_id, room_id, String::new())
+                    .await
+            }
+            Step::Setting { room_id } => {
+                if answer.eq_ignore_ascii_case("back") {
+                    return self.rooms(client, user_id).await;
+                }
+                let Some(index) = choose(self.settings.len(), answer) else {
+                    return Ok((
+                        format!(
+                            "Send a number from 1 to {}, back or done.",
+                            self.settings.len()
+                        ),
+                        Some(Step::Setting { room_id }),
+                    ));
+                };
+                let setting = &self.settings[index];
+                match setting.kind {
+                    SettingKind::Toggle { .. } => {
+                        let enabled = !setting.enabled(&self.store, &room_id)?;
+                        self.store
+                            .set_room_setting(&room_id, setting.key, &enabled)?;
+                        info!(
+                            room = room_id.as_str(),
+                            setting = setting.key,
+                            enabled,
+                            "Changed a room setting"
+                        );
+                        let done = format!(
+                            "Turned {} {}.\n\n",
+                            setting.description,
+                            if enabled { "on" } else { "off" }
+                        );
+                        self.settings_menu(client, user_id, room_id, done).await
+                    }
+                    SettingKind::Text { .. } => Ok((
+                        format!(
+                            "{} is currently {}. Send the new value, reset to go back to \
+                             the default, or back to leave it.",
+                            setting.description,
+                            setting.describe(&self.store, &room_id)?
+                        ),
+                        Some(Step::Value {
+                            room_id,
+                            setting: index,
+                        }),
+                    )),
+                }
+            }
+            Step::Value { room_id, setting } => {
+                let setting = &self.settings[setting];
+                let done = if answer.eq_ignore_ascii_case("back") {
+                    String::new()
+                } else if answer.eq_ignore_ascii_case("reset") {
+                    self.store.remove_room_setting(&room_id, setting.key)?;
+                    format!("Reset {}.\n\n", setting.description)
+                } else if answer.chars().count() > MAX_TEXT_CHARS {
+                    format!("That's too long, it can be up to {MAX_TEXT_CHARS} characters.\n\n")
+                } else {
+                    self.store
+                        .set_room_setting(&room_id, setting.key, &answer)?;
+                    info!(
+                        room = room_id.as_str(),
+                        setting = setting.key,
+                        "Changed a room setting"
+                    );
+                    format!("Saved {}.\n\n", setting.description)
+                };
+                self.settings_menu(client, user_id, room_id, done).await
+            }
+        }
+    }
+
+    /// The menu of rooms `user_id` can configure.
+    async fn rooms(
+        &self,
+        client: &Client,
+        user_id: &UserId,
+    ) -> anyhow::Result<(String, Option<Step>)> {
+        let mut rooms = Vec::new();
+        let mut menu = "Which room do you want to configure?\n".to_owned();
+        for room in client.joined_rooms() {
+            if room.is_direct().await?
+                || !self.permissions.allowed(&room, user_id, CONFIGURE).await?
+            {
+                continue;
+            }
+            rooms.push(room.room_id().to_owned());
+            writeln!(menu, "{}. {}", rooms.len(), room_name(&room))?;
+        }
+        if rooms.is_empty() {
+            return Ok(("There aren't any rooms you can configure.".to_owned(), None));
+        }
+        menu.push_str("Send a number, or done.");
+        Ok((menu, Some(Step::Room { rooms })))
+    }
+
+    /// The menu of settings for a room, after `done` to say what changed.
+    async fn settings_menu(
+        &self,
+        client: &Client,
+        user_id: &UserId,
+        room_id: OwnedRoomId,
+        done: String,
+    ) -> anyhow::Result<(String, Option<Step>)> {
+        // They may have lost their power since the menu was sent
+        let Some(room) = client.get_room(&room_id) else {
+            return Ok(("I'm not in that room any more.".to_owned(), None));
+        };
+        if !self.permissions.allowed(&room, user_id, CONFIGURE).await? {
+            return Ok((CONFIGURE.denial(), None));
+        }
+        let mut menu = format!("{done}Settings for {}:\n", room_name(&room));
+        for (number, setting) in self.settings.iter().enumerate() {
+            writeln!(
+                menu,
+                "{}. {}: {}",
+                number + 1,
+                setting.description,
+                setting.describe(&self.store, &room_id)?
+            )?;
+        }
+        menu.push_str("Send a number to change one, back to pick another room, or done.");
+        Ok((menu, Some(Step::Setting { room_id })))
+    }
+}
+
+/// The index of the item someone chose from a numbered menu of `count`.
+fn choose(count: usize, answer: &str) -> Option<usize> {
+    let number: usize = answer.trim_end_matches('.').parse().ok()?;
+    (1..=count).contains(&number).then(|| number - 1)
+}
+
+fn room_name(room: &Room) -> String {
+    room.name().unwrap_or_else(|| room.room_id().to_string())
+}
@@ -0,0 +1,286 @@
+//! Handling events in the background, so that a slow handler in one room
+//! doesn't hold up every other room.
+//!
+//! The SDK runs event handlers one after another as it processes each sync,
+//! so a handler waiting on a laggy homeserver stalls the whole bot. Work
+//! given to [`Workers::spawn`] runs on its own task instead, in order
+//! within each room, with a limit on how many rooms are worked on at once.
+//!
+//! When work piles up, like when catching up after downtime, the
+//! [`Backlog`] policy decides what's dropped, so the bot doesn't answer a
+//! flood of stale messages.
+//!
+//! Jobs are grouped into the sync batch that queued them, so the bot only
+//! counts a batch as handled once all of its work has finished.
+//!
+//! Each job is noted in the [crash journal](crate::journal) while it runs,
+//! and events that keep failing are [quarantined](crate::quarantine).
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, SystemTime},
+};
+
+use matrix_sdk::ruma::{EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, RoomId};
+use tokio::sync::{watch, Semaphore};
+use tracing::{debug, error, warn, Instrument};
+
+use crate::{egress::Egress, journal::Journal, parse_duration, quarantine::Quarantine, shard};
+
+/// The most jobs a room can have waiting. Older ones are dropped past this.
+const MAX_QUEUED_PER_ROOM: usize = 1000;
+
+type Job = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+/// What to do with events that have piled up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backlog {
+    /// Handle every event, however old.
+    All,
+    /// Skip events sent longer ago than this.
+    MaxAge(Duration),
+    /// Only handle the newest event waiting in each room.
+    Latest,
+}
+
+impl FromStr for Backlog {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "latest" => Ok(Self::Latest),
+            _ => parse_duration(s).map(Self::MaxAge).ok_or_else(|| {
+                format!("invalid backlog policy {s}: use all, latest or a duration like 10m")
+            }),
+        }
+    }
+}
+
+struct Queued {
+    job: Job,
+    event_id: OwnedEventId,
+    sent_at: MilliSecondsSinceUnixEpoch,
+    /// What the job does, for the crash journal.
+    command: String,
+    /// Held until the job has finished or been dropped.
+    _batch: Arc<()>,
+}
+
+/// A pool of per-room queues. Cloning it shares the pool.
+#[derive(Clone)]
+pub struct Workers {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    backlog: Backlog,
+    /// The jobs waiting in rooms that have a worker. A room is in here for
+    /// as long as its worker runs, even once its queue is empty.
+    queues: Mutex<HashMap<OwnedRoomId, VecDeque<Queued>>>,
+    /// Limits how many rooms are worked on at once.
+    permits: Semaphore,
+    /// Shared by the jobs queued while handling the current sync batch.
+    batch: Mutex<Arc<()>>,
+    /// Told about failed jobs.
+    egress: Egress,
+    journal: Journal,
+    quarantine: Quarantine,
+    /// While true, jobs wait rather than run.
+    paused: watch::Sender<bool>,
+}
+
+impl Workers {
+    /// A pool that works on up to `concurrency` rooms at once, dropping
+    /// work that piles up according to `backlog`, counting failures towards
+    /// `egress`'s error threshold and `quarantine`, and noting jobs in
+    /// `journal`.
+    pub fn new(
+        concurrency: usize,
+        backlog: Backlog,
+        egress: Egress,
+        journal: Journal,
+        quarantine: Quarantine,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                backlog,
+                queues: Default::default(),
+                permits: Semaphore::new(concurrency.max(1)),
+                batch: Default::default(),
+                egress,
+                journal,
+                quarantine,
+                paused: watch::Sender::new(false),
+            }),
+        }
+    }
+
+    /// Queue `job`, which does `command` with the event `event_id` sent at
+    /// `sent_at`, to run after the room's earlier jobs have finished.
+    ///
+    /// Errors are logged, like the SDK does for handlers. The job keeps the
+    /// caller's tracing span. It's dropped if another instance handles the
+    /// room when [sharded](crate::shard).
+    pub fn spawn(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+        sent_at: MilliSecondsSinceUnixEpoch,
+        command: &str,
+        job: impl Future<Output = anyhow::Result<()>> + Send + 'static,
+    ) {
+        // Another instance has it
+        if !shard::owns(room_id) {
+            return;
+        }
+        let queued = Queued {
+            job: Box::pin(job.in_current_span()),
+            event_id: event_id.to_owned(),
+            sent_at,
+            command: command.to_owned(),
+            _batch: self.inner.batch.lock().unwrap().clone(),
+        };
+        let mut queues = self.inner.queues.lock().unwrap();
+        if let Some(queue) = queues.get_mut(room_id) {
+            if self.inner.backlog == Backlog::Latest && !queue.is_empty() {
+                debug!(
+                    room = room_id.as_str(),
+                    dropped = queue.len(),
+                    "Dropping older events"
+                );
+                queue.clear();
+            }
+            if queue.len() >= MAX_QUEUED_PER_ROOM {
+                warn!(
+                    room = room_id.as_str(),
+                    "Too many events waiting, dropping the oldest"
+                );
+                queue.pop_front();
+            }
+            queue.push_back(queued);
+            return;
+        }
+
+        // Nothing is working on the room, so start a worker for it
+        queues.insert(room_id.to_owned(), VecDeque::new());
+        tokio::spawn(work(self.inner.clone(), room_id.to_owned(), queued));
+    }
+
+    /// Stop running jobs until [`Workers::resume`]. They keep being queued,
+    /// and the [`Backlog`] policy still applies to them.
+    pub fn pause(&self) {
+        self.inner.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.inner.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.inner.paused.borrow()
+    }
+
+    /// Wait until no room has work waiting or running.
+    pub(crate) async fn idle(&self) {
+        while !self.is_idle() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.queues.lock().unwrap().is_empty()
+    }
+
+    /// Start a new batch, returning the one jobs were being queued in until
+    /// now. It's finished once the `Weak` can't be upgraded.
+    pub(crate) fn finish_batch(&self) -> Weak<()> {
+        let batch = std::mem::take(&mut *self.inner.batch.lock().unwrap());
+        Arc::downgrade(&batch)
+    }
+}
+
+impl Inner {
+    /// Whether the policy says to skip an event sent at `sent_at`.
+    fn too_old(&self, sent_at: MilliSecondsSinceUnixEpoch) -> bool {
+        let Backlog::MaxAge(max_age) = self.backlog else {
+            return false;
+        };
+        sent_at
+            .to_system_time()
+            .and_then(|sent_at| SystemTime::now().duration_since(sent_at).ok())
+            .is_some_and(|age| age > max_age)
+    }
+}
+
+/// Run a room's jobs, starting with `first`, until its queue is empty.
+async fn work(inner: Arc<Inner>, room_id: OwnedRoomId, first: Queued) {
+    let mut queued = first;
+    let mut paused = inner.paused.subscribe();
+    loop {
+        // Only fails if the pool is gone, which it can't be while this runs
+        let _ = paused.wait_for(|&paused| !paused).await;
+        if inner.too_old(queued.sent_at) {
+            debug!(room = room_id.as_str(), "Skipping an old event");
+        } else if inner.quarantine.contains(&queued.event_id) {
+            debug!(
+                room = room_id.as_str(),
+                event = queued.event_id.as_str(),
+                "Skipping a quarantined event"
+            );
+        } else if inner.journal.should_skip(&queued.event_id) {
+            warn!(
+                room = room_id.as_str(),
+                event = queued.event_id.as_str(),
+                "Skipping an event the bot crashed handling last time"
+            );
+        } else {
+            let Ok(permit) = inner.permits.acquire().await else {
+                return;
+            };
+            inner
+                .journal
+                .start(&room_id, &queued.event_id, &queued.command);
+            // On its own task, so a panic can't leave the room stuck
+            let job = Journal::scope(queued.event_id.clone(), queued.job);
+            let result = tokio::spawn(job).await;
+            inner.journal.finish(&queued.event_id);
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => {
+                    error!(
+                        room = room_id.as_str(),
+                        "Failed to handle an event: {err:#}"
+                    );
+                    inner.egress.error();
+                    let error = format!("{err:#}");
+                    inner.quarantine.failed(&room_id, &queued.event_id, &error);
+                }
+                Err(err) => {
+                    error!(room = room_id.as_str(), "Event handler failed: {err}");
+                    inner.egress.error();
+                    let error = err.to_string();
+                    inner.quarantine.failed(&room_id, &queued.event_id, &error);
+                }
+            }
+            drop(permit);
+        }
+
+        queued = {
+            // Holding the lock means nothing can be queued between finding
+            // the queue empty and removing it
+            let mut queues = inner.queues.lock().unwrap();
+            match queues.get_mut(&room_id).and_then(VecDeque::pop_front) {
+                Some(queued) => queued,
+                None => {
+                    queues.remove(&room_id);
+                    return;
+                }
+            }
+        };
+    }
+}
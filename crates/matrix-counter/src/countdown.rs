@@ -0,0 +1,114 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// How often a countdown posts how long is left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cadence {
+    Daily,
+    /// On the same day of the week as the countdown ends.
+    Weekly,
+    /// Only the final announcement.
+    Never,
+}
+
+impl Cadence {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Cadence::Daily => "daily",
+            Cadence::Weekly => "weekly",
+            Cadence::Never => "never",
+        }
+    }
+}
+
+impl FromStr for Cadence {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "daily" => Ok(Cadence::Daily),
+            "weekly" => Ok(Cadence::Weekly),
+            "never" | "quiet" => Ok(Cadence::Never),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Cadence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// When a countdown should next post, strictly after `after`. Once this is
+/// `target`, it's time for the final announcement.
+pub fn next_update(
+    after: DateTime<Utc>,
+    target: DateTime<Utc>,
+    tz: Tz,
+    cadence: Cadence,
+    at: NaiveTime,
+) -> DateTime<Utc> {
+    let target_day = target.with_timezone(&tz).weekday();
+    let mut date = after.with_timezone(&tz).date_naive();
+    let next = loop {
+        if date > target.with_timezone(&tz).date_naive() {
+            break target;
+        }
+        let matches = match cadence {
+            Cadence::Daily => true,
+            Cadence::Weekly => date.weekday() == target_day,
+            Cadence::Never => break target,
+        };
+        if matches {
+            let candidate = localize(tz, date, at);
+            if candidate > after {
+                break candidate;
+            }
+        }
+        date = date + Days::new(1);
+    };
+    next.min(target)
+}
+
+/// `time` on `date` in `tz`, falling back to the equivalent UTC time if a
+/// DST change skips it.
+pub fn localize(tz: Tz, date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    let local = date.and_time(time);
+    tz.from_local_datetime(&local)
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&local))
+}
+
+/// How long is left, in calendar days in the countdown's time zone, e.g.
+/// `12 days to go` or `tomorrow`.
+pub fn remaining(now: DateTime<Utc>, target: DateTime<Utc>, tz: Tz, has_time: bool) -> String {
+    let target = target.with_timezone(&tz);
+    let days = (target.date_naive() - now.with_timezone(&tz).date_naive()).num_days();
+    match days {
+        ..=0 if has_time => format!("today at {}", target.format("%H:%M")),
+        ..=0 => "today".to_owned(),
+        1 if has_time => format!("tomorrow at {}", target.format("%H:%M")),
+        1 => "tomorrow".to_owned(),
+        2..=13 => format!("{days} days to go"),
+        _ => match days % 7 {
+            0 => format!("{} weeks to go", days / 7),
+            1 => format!("{} weeks and 1 day to go", days / 7),
+            extra => format!("{} weeks and {extra} days to go", days / 7),
+        },
+    }
+}
+
+/// When a countdown ends, for people reading it, e.g. `Mon 1 Dec 2025 at
+/// 18:00 CET`.
+pub fn describe_target(target: DateTime<Utc>, tz: Tz, has_time: bool) -> String {
+    let target = target.with_timezone(&tz);
+    if has_time {
+        target.format("%a %-d %b %Y at %H:%M %Z").to_string()
+    } else {
+        target.format("%a %-d %b %Y").to_string()
+    }
+}
@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use chrono::{NaiveTime, Utc};
+use chrono_tz::Tz;
+use matrix_bot_core::{can_reply, is_moderator, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState,
+};
+use tokio::sync::Notify;
+use tracing::{info, instrument};
+
+use crate::{
+    countdown::{describe_target, next_update, remaining, Cadence},
+    parse::{self, Command, NewCountdown},
+    status,
+    table::{Countdown, CountdownTable},
+};
+
+/// The most countdowns a room can have running at once.
+const MAX_COUNTDOWNS_PER_ROOM: usize = 20;
+
+const HELP: &str = "Usage:
+!countdown add \"Release\" 2025-12-01
+!countdown add \"Launch party\" 2025-12-01 18:00 Europe/Berlin weekly
+!countdown list
+!countdown remove <number>
+Updates are posted daily by default, or weekly, or never for just the final announcement.";
+
+#[derive(Clone)]
+pub struct Countdowns {
+    pub table: CountdownTable,
+    /// Wakes the scheduler when a countdown is added.
+    pub wake: Arc<Notify>,
+    /// Used for countdowns that don't give a time zone.
+    pub timezone: Tz,
+    /// The time of day updates are posted, in each countdown's time zone.
+    pub update_time: NaiveTime,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    countdowns: Ctx<Countdowns>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!countdown")) else {
+        return Ok(());
+    };
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let response = match parse::parse(args, Utc::now(), countdowns.timezone) {
+        Ok(command) => run_command(command, &event, &room, &countdowns).await?,
+        Err(err) => err.to_string(),
+    };
+
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn run_command(
+    command: Command,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    countdowns: &Countdowns,
+) -> anyhow::Result<String> {
+    let room_id = room.room_id().as_str();
+    let table = &countdowns.table;
+
+    Ok(match command {
+        Command::Help => HELP.to_owned(),
+        Command::List => {
+            let running = table.for_room(room_id)?;
+            if running.is_empty() {
+                return Ok("There are no countdowns here.".to_owned());
+            }
+            let now = Utc::now();
+            running
+                .iter()
+                .map(|countdown| {
+                    format!(
+                        "{}. {}: {}, {} (updates {})",
+                        countdown.id,
+                        countdown.name,
+                        remaining(
+                            now,
+                            countdown.target,
+                            countdown.timezone,
+                            countdown.has_time
+                        ),
+                        describe_target(countdown.target, countdown.timezone, countdown.has_time),
+                        countdown.cadence,
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Command::Remove(id) => {
+            let Some(countdown) = table.get(id, room_id)? else {
+                return Ok(format!("There's no countdown {id} here."));
+            };
+            if countdown.created_by != event.sender.as_str()
+                && !is_moderator(room, &event.sender).await?
+            {
+                return Ok(
+                    "Only whoever added a countdown or a moderator can remove it.".to_owned(),
+                );
+            }
+            table.remove(id)?;
+            status::edit(room, &countdown, status::finished(&countdown, true)).await;
+            status::unpin(room, &countdown).await;
+            info!(id, "Removed countdown");
+            format!("Removed the countdown to {}.", countdown.name)
+        }
+        Command::Add(new) => add(new, event, room, countdowns).await?,
+    })
+}
+
+async fn add(
+    new: NewCountdown,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    countdowns: &Countdowns,
+) -> anyhow::Result<String> {
+    let room_id = room.room_id().as_str();
+    let table = &countdowns.table;
+    if table.count_for_room(room_id)? >= MAX_COUNTDOWNS_PER_ROOM {
+        return Ok(format!(
+            "This room already has {MAX_COUNTDOWNS_PER_ROOM} countdowns, remove some first."
+        ));
+    }
+    if table.has_name(room_id, &new.name)? {
+        return Ok(format!("There's already a countdown to {} here.", new.name));
+    }
+
+    let now = Utc::now();
+    let mut countdown = Countdown {
+        id: 0,
+        room_id: room_id.to_owned(),
+        name: new.name,
+        target: new.target,
+        timezone: new.timezone,
+        has_time: new.has_time,
+        cadence: new.cadence,
+        status_event: None,
+        next_update: next_update(
+            now,
+            new.target,
+            new.timezone,
+            new.cadence,
+            countdowns.update_time,
+        ),
+        created_by: event.sender.to_string(),
+    };
+    countdown.id = table.add(&countdown)?;
+    let status_event = match status::post(room, &countdown, now).await {
+        Ok(status_event) => status_event,
+        Err(err) => {
+            table.remove(countdown.id)?;
+            return Err(err);
+        }
+    };
+    table.set_status_event(countdown.id, status_event.as_str())?;
+    countdowns.wake.notify_one();
+    info!(id = countdown.id, ends = %countdown.target, "Added countdown");
+
+    let updates = match countdown.cadence {
+        Cadence::Never => "I'll announce it when it's time".to_owned(),
+        cadence => format!("I'll post {cadence} updates and announce it when it's time"),
+    };
+    Ok(format!(
+        "Counting down to {} on {}. {updates}, remove it with `!countdown remove {}`.",
+        countdown.name,
+        describe_target(countdown.target, countdown.timezone, countdown.has_time),
+        countdown.id
+    ))
+}
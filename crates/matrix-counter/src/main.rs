@@ -0,0 +1,74 @@
+mod countdown;
+mod handlers;
+mod parse;
+mod scheduler;
+mod status;
+mod table;
+
+use std::sync::Arc;
+
+use chrono::NaiveTime;
+use chrono_tz::Tz;
+use clap::Parser;
+use handlers::{on_room_message, Countdowns};
+use matrix_bot_core::{AccountConfig, Bot};
+use table::CountdownTable;
+use tokio::sync::Notify;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Time zone for countdowns that don't give one, e.g. `Europe/Berlin`
+    #[arg(long, default_value_t = Tz::UTC, env = "COUNTER_TIMEZONE")]
+    pub timezone: Tz,
+
+    /// Time of day to post countdown updates, in each countdown's time zone
+    #[arg(long, default_value = "09:00", value_parser = parse_update_time, env = "COUNTER_UPDATE_TIME")]
+    pub update_time: NaiveTime,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_update_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| format!("invalid update time: {time}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-counter", config.account_config).await?;
+    let table = CountdownTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let countdowns = Countdowns {
+        table,
+        wake: Arc::new(Notify::new()),
+        timezone: config.timezone,
+        update_time: config.update_time,
+    };
+    tokio::spawn(matrix_bot_core::scheduler::run(
+        scheduler::Scheduler {
+            client: bot.client().clone(),
+            table: countdowns.table.clone(),
+            update_time: config.update_time,
+        },
+        countdowns.wake.clone(),
+    ));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(countdowns);
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
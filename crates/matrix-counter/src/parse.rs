@@ -0,0 +1,101 @@
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use matrix_bot_core::quoted_args;
+
+use crate::countdown::{localize, Cadence};
+
+/// The longest name a countdown can have.
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    List,
+    Remove(i64),
+    Add(NewCountdown),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NewCountdown {
+    pub name: String,
+    pub target: DateTime<Utc>,
+    pub timezone: Tz,
+    /// Whether a time of day was given, rather than just a date.
+    pub has_time: bool,
+    pub cadence: Cadence,
+}
+
+/// Parse the text following `!countdown`.
+pub fn parse(input: &str, now: DateTime<Utc>, default_tz: Tz) -> anyhow::Result<Command> {
+    let args = quoted_args(input)?;
+    let Some((first, rest)) = args.split_first() else {
+        return Ok(Command::Help);
+    };
+
+    match first.to_lowercase().as_str() {
+        "list" => Ok(Command::List),
+        "remove" | "delete" | "cancel" => {
+            let id = rest
+                .first()
+                .and_then(|id| id.trim_start_matches('#').parse().ok())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Which countdown should I remove? Give its number from `!countdown list`."
+                    )
+                })?;
+            Ok(Command::Remove(id))
+        }
+        "add" => parse_add(rest, now, default_tz).map(Command::Add),
+        _ => Ok(Command::Help),
+    }
+}
+
+/// Parse `"Name" 2025-12-01 [18:00] [Europe/Berlin] [daily|weekly|never]`.
+fn parse_add(args: &[String], now: DateTime<Utc>, default_tz: Tz) -> anyhow::Result<NewCountdown> {
+    let [name, date, options @ ..] = args else {
+        bail!(
+            "Give the countdown a name and a date, e.g. `!countdown add \"Release\" 2025-12-01`."
+        );
+    };
+    if name.chars().count() > MAX_NAME_LENGTH {
+        bail!("Countdown names can be at most {MAX_NAME_LENGTH} characters long.");
+    }
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| anyhow!("{date} isn't a date, write it like 2025-12-01."))?;
+
+    let mut time = None;
+    let mut timezone = None;
+    let mut cadence = None;
+    for option in options {
+        if let Ok(parsed) = NaiveTime::parse_from_str(option, "%H:%M") {
+            if time.replace(parsed).is_some() {
+                bail!("That countdown has more than one time.");
+            }
+        } else if let Ok(parsed) = option.parse::<Cadence>() {
+            if cadence.replace(parsed).is_some() {
+                bail!("Give at most one of daily, weekly or never.");
+            }
+        } else if let Ok(parsed) = option.parse::<Tz>() {
+            if timezone.replace(parsed).is_some() {
+                bail!("That countdown has more than one time zone.");
+            }
+        } else {
+            bail!("I don't understand {option}. After the date, give a time like 18:00, a time zone like Europe/Berlin, or daily, weekly or never.");
+        }
+    }
+
+    let timezone = timezone.unwrap_or(default_tz);
+    let target = localize(timezone, date, time.unwrap_or(NaiveTime::MIN));
+    if target <= now {
+        bail!("That's in the past.");
+    }
+
+    Ok(NewCountdown {
+        name: name.clone(),
+        target,
+        timezone,
+        has_time: time.is_some(),
+        cadence: cadence.unwrap_or(Cadence::Daily),
+    })
+}
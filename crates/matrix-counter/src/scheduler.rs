@@ -0,0 +1,103 @@
+use chrono::{DateTime, NaiveTime, Utc};
+use matrix_bot_core::{can_reply, html, scheduler::Jobs, send_or_log_error};
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, RoomId},
+    Client, RoomState,
+};
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    countdown::{next_update, remaining},
+    status,
+    table::{Countdown, CountdownTable},
+};
+
+/// Countdowns, updated and announced as they come due.
+pub struct Scheduler {
+    pub client: Client,
+    pub table: CountdownTable,
+    pub update_time: NaiveTime,
+}
+
+impl Jobs for Scheduler {
+    fn next_due(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.table.next_due()
+    }
+
+    async fn fire_due(&self, now: DateTime<Utc>) {
+        let countdowns = match self.table.due(now) {
+            Ok(countdowns) => countdowns,
+            Err(err) => {
+                error!("Failed to load due countdowns: {err}");
+                return;
+            }
+        };
+        for countdown in countdowns {
+            if let Err(err) = post(&self.client, &self.table, &countdown, self.update_time).await {
+                error!(id = countdown.id, "Failed to update countdown: {err:#}");
+            }
+        }
+    }
+}
+
+#[instrument(skip_all, fields(id = countdown.id, room = countdown.room_id.as_str()))]
+async fn post(
+    client: &Client,
+    table: &CountdownTable,
+    countdown: &Countdown,
+    update_time: NaiveTime,
+) -> anyhow::Result<()> {
+    let room = <&RoomId>::try_from(countdown.room_id.as_str())
+        .ok()
+        .and_then(|room_id| client.get_room(room_id))
+        .filter(|room| room.state() == RoomState::Joined);
+    let Some(room) = room else {
+        warn!("Not in the room for this countdown, dropping it");
+        return table.remove(countdown.id);
+    };
+
+    let now = Utc::now();
+    let name = &countdown.name;
+    if now >= countdown.target {
+        if can_reply(&room).await {
+            let content = RoomMessageEventContent::text_html(
+                format!("🎉 {name} is here!"),
+                format!("🎉 <b>{}</b> is here!", html::escape(name)),
+            );
+            send_or_log_error(&room, content).await;
+        }
+        status::edit(&room, countdown, status::finished(countdown, false)).await;
+        status::unpin(&room, countdown).await;
+        table.remove(countdown.id)?;
+        info!("Countdown finished");
+        return Ok(());
+    }
+
+    // Updates missed while offline are posted once, then carry on from now
+    table.reschedule(
+        countdown.id,
+        next_update(
+            now,
+            countdown.target,
+            countdown.timezone,
+            countdown.cadence,
+            update_time,
+        ),
+    )?;
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+    let left = remaining(
+        now,
+        countdown.target,
+        countdown.timezone,
+        countdown.has_time,
+    );
+    let content = RoomMessageEventContent::notice_html(
+        format!("⏳ {name}: {left}"),
+        format!("⏳ <b>{}</b>: {left}", html::escape(name)),
+    );
+    send_or_log_error(&room, content).await;
+    status::edit(&room, countdown, status::running(countdown, now)).await;
+    Ok(())
+}
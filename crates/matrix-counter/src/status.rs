@@ -0,0 +1,153 @@
+//! The status message each countdown keeps pinned and edits in place.
+
+use chrono::{DateTime, Utc};
+use matrix_bot_core::{html, replacement, send};
+use matrix_sdk::{
+    ruma::{
+        events::{
+            room::{message::RoomMessageEventContent, pinned_events::RoomPinnedEventsEventContent},
+            SyncOrStrippedState, SyncStateEvent,
+        },
+        EventId, OwnedEventId,
+    },
+    Room,
+};
+use tracing::{info, warn};
+
+use crate::{
+    countdown::{describe_target, remaining, Cadence},
+    table::Countdown,
+};
+
+/// What the status message says while the countdown is running.
+pub fn running(countdown: &Countdown, now: DateTime<Utc>) -> RoomMessageEventContent {
+    let left = remaining(
+        now,
+        countdown.target,
+        countdown.timezone,
+        countdown.has_time,
+    );
+    let when = describe_target(countdown.target, countdown.timezone, countdown.has_time);
+    let updated = match countdown.cadence {
+        Cadence::Daily => " Updated daily.",
+        Cadence::Weekly => " Updated weekly.",
+        Cadence::Never => "",
+    };
+    RoomMessageEventContent::notice_html(
+        format!("⏳ {}: {left} ({when}).{updated}", countdown.name),
+        format!(
+            "⏳ <b>{}</b>: {left} ({when}).{updated}",
+            html::escape(&countdown.name)
+        ),
+    )
+}
+
+/// What the status message says once the countdown is over or removed.
+pub fn finished(countdown: &Countdown, removed: bool) -> RoomMessageEventContent {
+    let when = describe_target(countdown.target, countdown.timezone, countdown.has_time);
+    let (plain, formatted) = if removed {
+        (
+            format!("{} (countdown removed)", countdown.name),
+            format!(
+                "<del>{}</del> (countdown removed)",
+                html::escape(&countdown.name)
+            ),
+        )
+    } else {
+        (
+            format!("🎉 {} was on {when}.", countdown.name),
+            format!("🎉 <b>{}</b> was on {when}.", html::escape(&countdown.name)),
+        )
+    };
+    RoomMessageEventContent::notice_html(plain, formatted)
+}
+
+/// Post a countdown's status message and pin it, returning its event ID.
+///
+/// The countdown still works if the bot isn't allowed to pin messages, the
+/// message just won't be pinned.
+pub async fn post(
+    room: &Room,
+    countdown: &Countdown,
+    now: DateTime<Utc>,
+) -> anyhow::Result<OwnedEventId> {
+    let event_id = send(room, running(countdown, now)).await?;
+    match pinned(room).await {
+        Ok(mut pinned) => {
+            pinned.push(event_id.clone());
+            if let Err(err) = room
+                .send_state_event(RoomPinnedEventsEventContent::new(pinned))
+                .await
+            {
+                info!(id = countdown.id, "Couldn't pin the status message: {err}");
+            }
+        }
+        Err(err) => warn!("Failed to get pinned messages: {err}"),
+    }
+    Ok(event_id)
+}
+
+/// Edit a countdown's status message in place, if it has one.
+pub async fn edit(room: &Room, countdown: &Countdown, content: RoomMessageEventContent) {
+    let Some(event_id) = status_event(countdown) else {
+        return;
+    };
+    if let Err(err) = send(room, replacement(event_id, content)).await {
+        warn!(
+            id = countdown.id,
+            "Failed to edit the status message: {err:#}"
+        );
+    }
+}
+
+/// Unpin a countdown's status message, if it's pinned.
+pub async fn unpin(room: &Room, countdown: &Countdown) {
+    let Some(event_id) = status_event(countdown) else {
+        return;
+    };
+    let mut pinned = match pinned(room).await {
+        Ok(pinned) => pinned,
+        Err(err) => {
+            warn!("Failed to get pinned messages: {err}");
+            return;
+        }
+    };
+    let before = pinned.len();
+    pinned.retain(|pinned| *pinned != event_id);
+    if pinned.len() == before {
+        return;
+    }
+    if let Err(err) = room
+        .send_state_event(RoomPinnedEventsEventContent::new(pinned))
+        .await
+    {
+        info!(
+            id = countdown.id,
+            "Couldn't unpin the status message: {err}"
+        );
+    }
+}
+
+async fn pinned(room: &Room) -> anyhow::Result<Vec<OwnedEventId>> {
+    let raw = room
+        .get_state_event_static::<RoomPinnedEventsEventContent>()
+        .await?;
+    Ok(match raw.map(|raw| raw.deserialize()).transpose()? {
+        Some(SyncOrStrippedState::Sync(SyncStateEvent::Original(event))) => event.content.pinned,
+        _ => Vec::new(),
+    })
+}
+
+fn status_event(countdown: &Countdown) -> Option<OwnedEventId> {
+    let event_id = countdown.status_event.as_deref()?;
+    match EventId::parse(event_id) {
+        Ok(event_id) => Some(event_id),
+        Err(err) => {
+            warn!(
+                id = countdown.id,
+                "Invalid status message {event_id}: {err}"
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::countdown::Cadence;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE countdowns (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        target INTEGER NOT NULL,
+        timezone TEXT NOT NULL,
+        has_time INTEGER NOT NULL,
+        cadence TEXT NOT NULL,
+        status_event TEXT,
+        next_update INTEGER NOT NULL,
+        created_by TEXT NOT NULL
+    );
+    CREATE INDEX countdowns_room ON countdowns (room_id);
+    CREATE INDEX countdowns_next_update ON countdowns (next_update);"];
+
+#[derive(Debug, Clone)]
+pub struct Countdown {
+    pub id: i64,
+    pub room_id: String,
+    pub name: String,
+    pub target: DateTime<Utc>,
+    pub timezone: Tz,
+    pub has_time: bool,
+    pub cadence: Cadence,
+    /// The message that's kept up to date with how long is left.
+    pub status_event: Option<String>,
+    pub next_update: DateTime<Utc>,
+    pub created_by: String,
+}
+
+const COLUMNS: &str = "id, room_id, name, target, timezone, has_time, cadence, status_event, \
+                       next_update, created_by";
+
+impl Countdown {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        fn invalid(index: usize, value: String) -> rusqlite::Error {
+            rusqlite::Error::FromSqlConversionFailure(
+                index,
+                rusqlite::types::Type::Text,
+                format!("invalid value {value}").into(),
+            )
+        }
+
+        let timezone: String = row.get(4)?;
+        let cadence: String = row.get(6)?;
+        Ok(Self {
+            id: row.get(0)?,
+            room_id: row.get(1)?,
+            name: row.get(2)?,
+            target: DateTime::from_timestamp(row.get(3)?, 0).unwrap_or_default(),
+            timezone: timezone.parse().map_err(|_| invalid(4, timezone))?,
+            has_time: row.get(5)?,
+            cadence: cadence.parse().map_err(|_| invalid(6, cadence))?,
+            status_event: row.get(7)?,
+            next_update: DateTime::from_timestamp(row.get(8)?, 0).unwrap_or_default(),
+            created_by: row.get(9)?,
+        })
+    }
+}
+
+/// Countdowns persisted in SQLite, so that they survive restarts.
+#[derive(Clone)]
+pub struct CountdownTable {
+    store: Store,
+}
+
+impl CountdownTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Store a new countdown, returning its number.
+    pub fn add(&self, countdown: &Countdown) -> anyhow::Result<i64> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO countdowns (room_id, name, target, timezone, has_time, cadence,
+                    status_event, next_update, created_by)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    countdown.room_id,
+                    countdown.name,
+                    countdown.target.timestamp(),
+                    countdown.timezone.name(),
+                    countdown.has_time,
+                    countdown.cadence.as_str(),
+                    countdown.status_event,
+                    countdown.next_update.timestamp(),
+                    countdown.created_by,
+                ],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    pub fn get(&self, id: i64, room_id: &str) -> anyhow::Result<Option<Countdown>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                &format!("SELECT {COLUMNS} FROM countdowns WHERE id = ?1 AND room_id = ?2"),
+                params![id, room_id],
+                Countdown::from_row,
+            )
+            .optional()
+        })
+    }
+
+    /// A room's countdowns, soonest first.
+    pub fn for_room(&self, room_id: &str) -> anyhow::Result<Vec<Countdown>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {COLUMNS} FROM countdowns WHERE room_id = ?1 ORDER BY target"
+            ))?;
+            let countdowns = statement
+                .query_map([room_id], Countdown::from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(countdowns)
+        })
+    }
+
+    /// Whether the room has a countdown by this name, ignoring case.
+    pub fn has_name(&self, room_id: &str, name: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM countdowns WHERE room_id = ?1 AND name = ?2 COLLATE NOCASE)",
+                params![room_id, name],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    pub fn count_for_room(&self, room_id: &str) -> anyhow::Result<usize> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM countdowns WHERE room_id = ?1",
+                [room_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    /// The countdowns that should post at `now`, oldest first.
+    pub fn due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<Countdown>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {COLUMNS} FROM countdowns WHERE next_update <= ?1 ORDER BY next_update"
+            ))?;
+            let countdowns = statement
+                .query_map([now.timestamp()], Countdown::from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(countdowns)
+        })
+    }
+
+    /// When the next countdown should post, if there are any.
+    pub fn next_due(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.store.with(|conn| {
+            let due: Option<i64> =
+                conn.query_row("SELECT MIN(next_update) FROM countdowns", [], |row| {
+                    row.get(0)
+                })?;
+            Ok(due.and_then(|due| DateTime::from_timestamp(due, 0)))
+        })
+    }
+
+    pub fn set_status_event(&self, id: i64, event_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE countdowns SET status_event = ?2 WHERE id = ?1",
+                params![id, event_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn reschedule(&self, id: i64, next_update: DateTime<Utc>) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE countdowns SET next_update = ?2 WHERE id = ?1",
+                params![id, next_update.timestamp()],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn remove(&self, id: i64) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute("DELETE FROM countdowns WHERE id = ?1", [id])?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use matrix_bot_core::{
+    can_reply, is_moderator, quoted_args, reply_notice, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState,
+};
+use tokio::sync::Notify;
+use tracing::{info, instrument};
+
+use crate::{
+    schedule::{next_fire, parse_cron},
+    table::ScheduleTable,
+};
+
+/// The most schedules a room can have.
+const MAX_SCHEDULES_PER_ROOM: usize = 25;
+
+const HELP: &str = "Usage (moderators only):
+!schedule \"0 9 * * MON\" \"Weekly standup in 10 minutes\"
+!schedule list
+!schedule remove <number>
+Cron expressions have five fields: minute, hour, day of month, month and day of week.
+Messages can use {date}, {time} and {weekday}, and are formatted as Markdown.";
+
+#[derive(Clone)]
+pub struct Schedules {
+    pub table: ScheduleTable,
+    /// Wakes the scheduler when a schedule is added.
+    pub wake: Arc<Notify>,
+    pub timezone: Tz,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    schedules: Ctx<Schedules>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!schedule")) else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let response = if !is_moderator(&room, &event.sender).await? {
+        "Only moderators can manage scheduled messages.".to_owned()
+    } else {
+        match command(args, &event, &room, &schedules) {
+            Ok(response) => response,
+            Err(err) => err.to_string(),
+        }
+    };
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    schedules: &Schedules,
+) -> anyhow::Result<String> {
+    let table = &schedules.table;
+    let room_id = room.room_id().as_str();
+    let tz = schedules.timezone;
+
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    Ok(match subcommand {
+        "" | "help" => HELP.to_owned(),
+        "list" => {
+            let list = table.list(room_id)?;
+            if list.is_empty() {
+                "There are no scheduled messages in this room.".to_owned()
+            } else {
+                list.iter()
+                    .map(|schedule| {
+                        format!(
+                            "#{} `{}` (next {}): {}",
+                            schedule.id,
+                            schedule.cron,
+                            schedule
+                                .next_fire
+                                .with_timezone(&tz)
+                                .format("%a %-d %b %H:%M"),
+                            schedule.template
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        "remove" | "delete" => {
+            let id: i64 = rest.trim().trim_start_matches('#').parse().map_err(|_| {
+                anyhow::anyhow!("Which schedule? Give its number from `!schedule list`.")
+            })?;
+            if table.remove(room_id, id)? {
+                format!("Removed schedule #{id}.")
+            } else {
+                format!("There's no schedule #{id} in this room.")
+            }
+        }
+        _ => {
+            let [cron, template] = <[String; 2]>::try_from(quoted_args(args)?)
+                .map_err(|_| anyhow::anyhow!("Quote the cron expression and the message, e.g. `!schedule \"0 9 * * MON\" \"Standup in 10 minutes\"`."))?;
+            if table.list(room_id)?.len() >= MAX_SCHEDULES_PER_ROOM {
+                anyhow::bail!("This room already has {MAX_SCHEDULES_PER_ROOM} scheduled messages.");
+            }
+            let next = next_fire(&parse_cron(&cron)?, Utc::now(), tz)?;
+            let id = table.add(room_id, &cron, &template, event.sender.as_str(), next)?;
+            schedules.wake.notify_one();
+            info!(id, cron, "Added schedule");
+            format!(
+                "Added schedule #{id}, next sending {}.",
+                next.with_timezone(&tz).format("%a %-d %b %H:%M %Z")
+            )
+        }
+    })
+}
@@ -0,0 +1,81 @@
+mod handlers;
+mod schedule;
+mod scheduler;
+mod table;
+
+use std::{sync::Arc, time::Duration};
+
+use chrono_tz::Tz;
+use clap::Parser;
+use handlers::{on_room_message, Schedules};
+use matrix_bot_core::{AccountConfig, Bot};
+use schedule::CatchUp;
+use scheduler::Policy;
+use table::ScheduleTable;
+use tokio::sync::Notify;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Time zone cron expressions are interpreted in, e.g. `Europe/Berlin`
+    #[arg(long, default_value_t = Tz::UTC, env = "CRON_TIMEZONE")]
+    pub timezone: Tz,
+
+    /// What to do about messages that should have been sent while the bot
+    /// was offline
+    #[arg(long, value_enum, default_value = "once", env = "CRON_CATCH_UP")]
+    pub catch_up: CatchUp,
+
+    /// How late a message can be sent before it counts as missed
+    #[arg(long, default_value = "5m", value_parser = parse_grace, env = "CRON_GRACE")]
+    pub grace: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_grace(grace: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(grace).ok_or_else(|| format!("invalid grace period: {grace}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-cron", config.account_config).await?;
+    let table = ScheduleTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let schedules = Schedules {
+        table,
+        wake: Arc::new(Notify::new()),
+        timezone: config.timezone,
+    };
+    tokio::spawn(matrix_bot_core::scheduler::run(
+        scheduler::Scheduler {
+            client: bot.client().clone(),
+            table: schedules.table.clone(),
+            policy: Policy {
+                timezone: config.timezone,
+                catch_up: config.catch_up,
+                grace: chrono::Duration::from_std(config.grace)?,
+            },
+        },
+        schedules.wake.clone(),
+    ));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(schedules);
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,45 @@
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use croner::Cron;
+
+/// What to do about fires missed while the bot was offline.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum CatchUp {
+    /// Drop missed fires and wait for the next one.
+    Skip,
+    /// Send one message for any number of missed fires.
+    Once,
+    /// Send a message for every missed fire, up to a limit.
+    All,
+}
+
+/// The most missed fires of one schedule sent with `--catch-up all`.
+pub const MAX_CATCH_UP: usize = 10;
+
+/// Parse a standard five field cron expression, such as `0 9 * * MON`.
+pub fn parse_cron(expression: &str) -> anyhow::Result<Cron> {
+    if expression.split_whitespace().count() != 5 {
+        bail!("Cron expressions have five fields: minute, hour, day of month, month and day of week, e.g. `0 9 * * MON`.");
+    }
+    Cron::new(expression)
+        .parse()
+        .map_err(|err| anyhow!("That isn't a valid cron expression: {err}"))
+}
+
+/// The first time `cron` fires strictly after `after`, in `tz`.
+pub fn next_fire(cron: &Cron, after: DateTime<Utc>, tz: Tz) -> anyhow::Result<DateTime<Utc>> {
+    Ok(cron
+        .find_next_occurrence(&after.with_timezone(&tz), false)?
+        .with_timezone(&Utc))
+}
+
+/// Fill in a message template's placeholders for a fire at `at`.
+pub fn render(template: &str, at: DateTime<Utc>, tz: Tz) -> String {
+    let at = at.with_timezone(&tz);
+    template
+        .replace("{date}", &at.format("%Y-%m-%d").to_string())
+        .replace("{time}", &at.format("%H:%M").to_string())
+        .replace("{weekday}", &at.format("%A").to_string())
+}
@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use matrix_bot_core::{can_reply, scheduler::Jobs, send_or_log_error};
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, RoomId},
+    Client, RoomState,
+};
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    schedule::{next_fire, parse_cron, render, CatchUp, MAX_CATCH_UP},
+    table::{Schedule, ScheduleTable},
+};
+
+/// How the scheduler handles fires.
+#[derive(Clone, Copy)]
+pub struct Policy {
+    pub timezone: Tz,
+    pub catch_up: CatchUp,
+    /// How late a fire can be and still count as on time rather than missed.
+    pub grace: chrono::Duration,
+}
+
+/// Schedules, whose messages are sent as they come due.
+pub struct Scheduler {
+    pub client: Client,
+    pub table: ScheduleTable,
+    pub policy: Policy,
+}
+
+impl Jobs for Scheduler {
+    fn next_due(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.table.next_due()
+    }
+
+    async fn fire_due(&self, now: DateTime<Utc>) {
+        let schedules = match self.table.due(now) {
+            Ok(schedules) => schedules,
+            Err(err) => {
+                error!("Failed to load due schedules: {err}");
+                return;
+            }
+        };
+        for schedule in schedules {
+            if let Err(err) = fire(&self.client, &self.table, &schedule, now, self.policy).await {
+                error!(id = schedule.id, "Failed to run schedule: {err}");
+            }
+        }
+    }
+}
+
+#[instrument(skip_all, fields(id = schedule.id, room = schedule.room_id.as_str()))]
+async fn fire(
+    client: &Client,
+    table: &ScheduleTable,
+    schedule: &Schedule,
+    now: DateTime<Utc>,
+    policy: Policy,
+) -> anyhow::Result<()> {
+    let cron = parse_cron(&schedule.cron)?;
+    let tz = policy.timezone;
+
+    // Every time the schedule should have fired, up to a point
+    let mut fires = vec![schedule.next_fire];
+    while fires.len() < MAX_CATCH_UP {
+        let next = next_fire(&cron, *fires.last().unwrap(), tz)?;
+        if next > now {
+            break;
+        }
+        fires.push(next);
+    }
+
+    let latest = *fires.last().unwrap();
+    let on_time = now - latest <= policy.grace;
+    let missed = fires.len();
+    let to_send = match policy.catch_up {
+        CatchUp::All => fires,
+        CatchUp::Once => vec![latest],
+        CatchUp::Skip if on_time => vec![latest],
+        CatchUp::Skip => Vec::new(),
+    };
+    if to_send.is_empty() {
+        info!(missed, "Skipping missed fires");
+    }
+    for at in to_send {
+        send(client, schedule, at, tz).await;
+    }
+
+    table.reschedule(schedule.id, next_fire(&cron, now, tz)?)
+}
+
+async fn send(client: &Client, schedule: &Schedule, at: DateTime<Utc>, tz: Tz) {
+    let Ok(room_id) = <&RoomId>::try_from(schedule.room_id.as_str()) else {
+        return;
+    };
+    let Some(room) = client.get_room(room_id) else {
+        warn!("Not in the room for this schedule, not sending");
+        return;
+    };
+    if room.state() != RoomState::Joined || !can_reply(&room).await {
+        warn!("Can't post in the room for this schedule, not sending");
+        return;
+    }
+
+    info!(%at, "Sending scheduled message");
+    let content = RoomMessageEventContent::text_markdown(render(&schedule.template, at, tz));
+    send_or_log_error(&room, content).await;
+}
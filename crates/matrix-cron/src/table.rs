@@ -0,0 +1,121 @@
+use chrono::{DateTime, Utc};
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, Row};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE schedules (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        cron TEXT NOT NULL,
+        template TEXT NOT NULL,
+        creator TEXT NOT NULL,
+        next_fire INTEGER NOT NULL
+    );
+    CREATE INDEX schedules_next_fire ON schedules (next_fire);"];
+
+/// A recurring message.
+#[derive(Debug)]
+pub struct Schedule {
+    pub id: i64,
+    pub room_id: String,
+    pub cron: String,
+    pub template: String,
+    pub next_fire: DateTime<Utc>,
+}
+
+/// The configured schedules, persisted in SQLite so they survive restarts.
+#[derive(Clone)]
+pub struct ScheduleTable {
+    store: Store,
+}
+
+const COLUMNS: &str = "id, room_id, cron, template, next_fire";
+
+impl ScheduleTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn add(
+        &self,
+        room_id: &str,
+        cron: &str,
+        template: &str,
+        creator: &str,
+        next_fire: DateTime<Utc>,
+    ) -> anyhow::Result<i64> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO schedules (room_id, cron, template, creator, next_fire)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![room_id, cron, template, creator, next_fire.timestamp()],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    pub fn list(&self, room_id: &str) -> anyhow::Result<Vec<Schedule>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {COLUMNS} FROM schedules WHERE room_id = ?1 ORDER BY id"
+            ))?;
+            let schedules = statement
+                .query_map(params![room_id], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(schedules)
+        })
+    }
+
+    /// Returns whether there was a schedule to remove in the room.
+    pub fn remove(&self, room_id: &str, id: i64) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM schedules WHERE room_id = ?1 AND id = ?2",
+                params![room_id, id],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+
+    /// Schedules that should have fired by `now`.
+    pub fn due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<Schedule>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {COLUMNS} FROM schedules WHERE next_fire <= ?1 ORDER BY next_fire"
+            ))?;
+            let schedules = statement
+                .query_map(params![now.timestamp()], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(schedules)
+        })
+    }
+
+    pub fn next_due(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.store.with(|conn| {
+            let next_fire: Option<i64> =
+                conn.query_row("SELECT MIN(next_fire) FROM schedules", [], |row| row.get(0))?;
+            Ok(next_fire.and_then(|next_fire| DateTime::from_timestamp(next_fire, 0)))
+        })
+    }
+
+    pub fn reschedule(&self, id: i64, next_fire: DateTime<Utc>) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE schedules SET next_fire = ?2 WHERE id = ?1",
+                params![id, next_fire.timestamp()],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Schedule> {
+    Ok(Schedule {
+        id: row.get(0)?,
+        room_id: row.get(1)?,
+        cron: row.get(2)?,
+        template: row.get(3)?,
+        next_fire: DateTime::from_timestamp(row.get(4)?, 0).unwrap_or_default(),
+    })
+}
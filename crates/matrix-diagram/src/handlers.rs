@@ -0,0 +1,149 @@
+use matrix_bot_core::{
+    can_reply, html, is_moderator, reply, reply_notice, store::Store, strip_command, text_body,
+    wizard::Setting,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::{
+            message::{
+                ImageMessageEventContent, MessageType, OriginalSyncRoomMessageEvent,
+                RoomMessageEventContent,
+            },
+            ImageInfo,
+        },
+        RoomId, UInt,
+    },
+    Room, RoomState,
+};
+use tracing::{instrument, warn};
+
+use crate::render::{diagrams, png_size, Renderer};
+
+/// The most diagrams to render from one message.
+const MAX_DIAGRAMS: usize = 3;
+
+const HELP: &str = "I render ```mermaid and ```dot code blocks in rooms that turn me on.
+!diagram on / !diagram off to turn me on or off here (moderators only)";
+
+/// Whether a room has turned diagram rendering on.
+const ENABLED: Setting = Setting::toggle("enabled", "Render diagrams", false);
+
+#[derive(Clone)]
+pub struct Diagrams {
+    pub renderer: Renderer,
+    pub store: Store,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    diagram_bot: Ctx<Diagrams>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    let room_id = room.room_id();
+    if let Some(args) = strip_command(body, "!diagram") {
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        let response = match args {
+            "on" | "off" if !is_moderator(&room, &event.sender).await? => {
+                "Only moderators can change that."
+            }
+            "on" | "off" => {
+                let enabled = args == "on";
+                match (set_enabled(&diagram_bot.store, room_id, enabled)?, enabled) {
+                    (true, true) => "I'll render diagrams here from now on.",
+                    (true, false) => "I won't render diagrams here any more.",
+                    (false, true) => "I'm already rendering diagrams here.",
+                    (false, false) => "I'm not rendering diagrams here.",
+                }
+            }
+            _ => HELP,
+        };
+        reply_notice(&room, &event, response).await;
+        return Ok(());
+    }
+
+    if !ENABLED.enabled(&diagram_bot.store, room_id)? {
+        return Ok(());
+    }
+    let diagrams = diagrams(body);
+    if diagrams.is_empty() || !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let numbered = diagrams.len() > 1;
+    for (i, diagram) in diagrams.iter().enumerate().take(MAX_DIAGRAMS) {
+        let name = if numbered {
+            format!("Diagram {}", i + 1)
+        } else {
+            "That diagram".to_owned()
+        };
+        let content = match diagram_bot.renderer.render(diagram).await {
+            Ok(Ok(png)) => match image(&room, png).await {
+                Ok(content) => content,
+                Err(err) => {
+                    warn!("Failed to upload a diagram: {err}");
+                    RoomMessageEventContent::notice_plain(format!("{name} couldn't be uploaded."))
+                }
+            },
+            Ok(Err(error)) => {
+                let text = format!("{name} didn't render:");
+                RoomMessageEventContent::notice_html(
+                    format!("{text}\n```\n{error}\n```"),
+                    format!(
+                        "<p>{text}</p><pre><code>{}</code></pre>",
+                        html::escape(&error)
+                    ),
+                )
+            }
+            Err(err) => {
+                warn!("Failed to render a diagram: {err}");
+                RoomMessageEventContent::notice_plain(format!("{name} couldn't be rendered: {err}"))
+            }
+        };
+        reply(&room, &event, content).await;
+    }
+    Ok(())
+}
+
+/// Upload a rendered diagram and make a message to post it.
+async fn image(room: &Room, png: Vec<u8>) -> anyhow::Result<RoomMessageEventContent> {
+    let mut info = ImageInfo::new();
+    info.mimetype = Some(mime::IMAGE_PNG.to_string());
+    info.size = UInt::new(png.len() as u64);
+    if let Some((width, height)) = png_size(&png) {
+        info.width = Some(UInt::from(width));
+        info.height = Some(UInt::from(height));
+    }
+
+    let response = room
+        .client()
+        .media()
+        .upload(&mime::IMAGE_PNG, png, None)
+        .await?;
+    let image = ImageMessageEventContent::plain("diagram.png".to_owned(), response.content_uri)
+        .info(Box::new(info));
+    Ok(RoomMessageEventContent::new(MessageType::Image(image)))
+}
+
+/// Turn diagram rendering on or off in a room, returning whether that changed
+/// anything.
+fn set_enabled(store: &Store, room_id: &RoomId, enabled: bool) -> anyhow::Result<bool> {
+    if ENABLED.enabled(store, room_id)? == enabled {
+        return Ok(false);
+    }
+    store.set_room_setting(room_id, ENABLED.key, &enabled)?;
+    Ok(true)
+}
@@ -0,0 +1,63 @@
+mod handlers;
+mod render;
+
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+use handlers::{on_room_message, Diagrams};
+use matrix_bot_core::{AccountConfig, Bot};
+use render::Renderer;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The mermaid-cli program to render Mermaid diagrams with
+    #[arg(long, default_value = "mmdc", env = "DIAGRAM_MMDC")]
+    pub mmdc: PathBuf,
+
+    /// The Graphviz program to render dot diagrams with
+    #[arg(long, default_value = "dot", env = "DIAGRAM_DOT")]
+    pub dot: PathBuf,
+
+    /// How long rendering a diagram may take before it's given up on
+    #[arg(long, default_value = "20s", value_parser = parse_timeout, env = "DIAGRAM_TIMEOUT")]
+    pub timeout: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_timeout(timeout: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(timeout).ok_or_else(|| format!("invalid timeout: {timeout}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-diagram", config.account_config).await?;
+    let store = bot.open_store(&[]).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Diagrams {
+        renderer: Renderer {
+            mmdc: config.mmdc,
+            dot: config.dot,
+            timeout: config.timeout,
+        },
+        store,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
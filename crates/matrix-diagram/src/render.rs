@@ -0,0 +1,162 @@
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use anyhow::anyhow;
+use tokio::process::Command;
+
+/// The longest diagram source to render, in bytes.
+pub const MAX_SOURCE_LEN: usize = 10_000;
+
+/// The most lines of a renderer's error output to pass on.
+const MAX_ERROR_LINES: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Mermaid,
+    Graphviz,
+}
+
+impl Kind {
+    /// The kind of diagram a fenced code block's language is for.
+    fn from_language(language: &str) -> Option<Self> {
+        match language.to_ascii_lowercase().as_str() {
+            "mermaid" => Some(Kind::Mermaid),
+            "dot" | "graphviz" => Some(Kind::Graphviz),
+            _ => None,
+        }
+    }
+}
+
+/// A diagram in a fenced code block.
+#[derive(Debug)]
+pub struct Diagram {
+    pub kind: Kind,
+    pub source: String,
+}
+
+/// The diagrams in a message's fenced code blocks.
+pub fn diagrams(body: &str) -> Vec<Diagram> {
+    let mut diagrams = Vec::new();
+    let mut current: Option<Diagram> = None;
+    for line in body.lines() {
+        let trimmed = line.trim();
+        match &mut current {
+            Some(_) if trimmed == "```" => diagrams.extend(current.take()),
+            Some(diagram) => {
+                diagram.source.push_str(line);
+                diagram.source.push('\n');
+            }
+            None => {
+                current = trimmed
+                    .strip_prefix("```")
+                    .and_then(|language| Kind::from_language(language.trim()))
+                    .map(|kind| Diagram {
+                        kind,
+                        source: String::new(),
+                    });
+            }
+        }
+    }
+    diagrams
+}
+
+/// Renders diagrams to PNG, which unlike SVG every client shows inline.
+#[derive(Clone, Debug)]
+pub struct Renderer {
+    /// mermaid-cli
+    pub mmdc: PathBuf,
+    /// Graphviz
+    pub dot: PathBuf,
+    /// How long rendering may take.
+    pub timeout: Duration,
+}
+
+impl Renderer {
+    /// Render a diagram, or say what's wrong with it.
+    pub async fn render(&self, diagram: &Diagram) -> anyhow::Result<Result<Vec<u8>, String>> {
+        if diagram.source.len() > MAX_SOURCE_LEN {
+            return Ok(Err(format!(
+                "That diagram is too big, I only render up to {MAX_SOURCE_LEN} characters."
+            )));
+        }
+
+        // Paths are relative to this, so errors don't mention it
+        let dir = tempfile::tempdir()?;
+        let input = "diagram.txt";
+        let output = "diagram.png";
+        tokio::fs::write(dir.path().join(input), &diagram.source).await?;
+
+        let mut command = match diagram.kind {
+            Kind::Mermaid => {
+                let mut command = Command::new(&self.mmdc);
+                command.args([
+                    "--quiet",
+                    "--backgroundColor",
+                    "white",
+                    "--input",
+                    input,
+                    "--output",
+                    output,
+                ]);
+                command
+            }
+            Kind::Graphviz => {
+                let mut command = Command::new(&self.dot);
+                command
+                    // Stop diagrams from pulling in files from outside this directory
+                    .env("SERVER_NAME", "matrix-diagram")
+                    .env("GV_FILE_PATH", dir.path())
+                    .args(["-Tpng", "-o", output, input]);
+                command
+            }
+        };
+        let child = command
+            .current_dir(dir.path())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let result = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| anyhow!("rendering took too long"))??;
+
+        if !result.status.success() {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            let stdout = String::from_utf8_lossy(&result.stdout);
+            return Ok(Err(error_message(if stderr.trim().is_empty() {
+                &stdout
+            } else {
+                &stderr
+            })));
+        }
+        Ok(Ok(tokio::fs::read(dir.path().join(output)).await?))
+    }
+}
+
+/// The useful part of a renderer's error output, which says which line the
+/// problem is on, without the stack traces mermaid-cli adds.
+fn error_message(output: &str) -> String {
+    let lines: Vec<&str> = output
+        .lines()
+        .filter(|line| {
+            let trimmed = line.trim_start();
+            !trimmed.is_empty() && !trimmed.starts_with("at ") && !trimmed.contains("file://")
+        })
+        .take(MAX_ERROR_LINES)
+        .collect();
+    if lines.is_empty() {
+        "The renderer failed without saying why.".to_owned()
+    } else {
+        lines.join("\n")
+    }
+}
+
+/// The width and height of a PNG image, from its header.
+pub fn png_size(png: &[u8]) -> Option<(u32, u32)> {
+    if png.get(..8)? != b"\x89PNG\r\n\x1a\n" || png.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(png.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(png.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
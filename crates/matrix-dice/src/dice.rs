@@ -0,0 +1,324 @@
+use std::fmt::Write;
+
+use anyhow::{bail, ensure};
+use rand::Rng;
+
+/// The most dice a single roll may use, including explosions.
+const MAX_DICE: u32 = 100;
+
+/// The most sides a die may have.
+const MAX_SIDES: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    /// Roll twice and keep the higher total.
+    Advantage,
+    /// Roll twice and keep the lower total.
+    Disadvantage,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Dice {
+        count: u32,
+        sides: u32,
+        /// Roll again whenever a die shows its highest face.
+        exploding: bool,
+        negative: bool,
+    },
+    Constant(i64),
+}
+
+/// A parsed dice expression such as `3d6+2` or `d20 adv`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    terms: Vec<Term>,
+    mode: Mode,
+}
+
+#[derive(Debug)]
+pub struct Roll {
+    pub total: i64,
+    /// The individual dice, for showing the working.
+    pub detail: String,
+}
+
+impl Expression {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut mode = Mode::Normal;
+        let mut expression = String::new();
+        for word in input.split_whitespace() {
+            match word.to_lowercase().as_str() {
+                "adv" | "advantage" => mode = Mode::Advantage,
+                "dis" | "disadv" | "disadvantage" => mode = Mode::Disadvantage,
+                word => expression.push_str(word),
+            }
+        }
+        if expression.is_empty() {
+            // `!roll adv` is a d20 with advantage
+            expression.push_str("1d20");
+        }
+
+        let mut terms = Vec::new();
+        let mut dice = 0u32;
+        for (negative, term) in split_terms(&expression.to_lowercase())? {
+            let term = match term.split_once('d') {
+                Some((count, sides)) => {
+                    let (sides, exploding) = match sides.strip_suffix('!') {
+                        Some(sides) => (sides, true),
+                        None => (sides, false),
+                    };
+                    let count = if count.is_empty() {
+                        1
+                    } else {
+                        parse_number(count)?
+                    };
+                    let sides = parse_number(sides)?;
+                    ensure!(count > 0, "Roll at least one die.");
+                    ensure!(
+                        (2..=MAX_SIDES).contains(&sides),
+                        "Dice need between 2 and {MAX_SIDES} sides."
+                    );
+                    dice = dice.saturating_add(count);
+                    ensure!(dice <= MAX_DICE, "I can only roll {MAX_DICE} dice at once.");
+                    Term::Dice {
+                        count,
+                        sides,
+                        exploding,
+                        negative,
+                    }
+                }
+                None => {
+                    let value = i64::from(parse_number(term)?);
+                    Term::Constant(if negative { -value } else { value })
+                }
+            };
+            terms.push(term);
+        }
+        ensure!(
+            terms.iter().any(|term| matches!(term, Term::Dice { .. })),
+            "What should I roll? Try something like `3d6+2`."
+        );
+        Ok(Self { terms, mode })
+    }
+
+    pub fn roll(&self, rng: &mut impl Rng) -> Roll {
+        match self.mode {
+            Mode::Normal => self.roll_once(rng),
+            Mode::Advantage | Mode::Disadvantage => {
+                let first = self.roll_once(rng);
+                let second = self.roll_once(rng);
+                let keep_first = if self.mode == Mode::Advantage {
+                    first.total >= second.total
+                } else {
+                    first.total <= second.total
+                };
+                let (kept, dropped) = if keep_first {
+                    (first, second)
+                } else {
+                    (second, first)
+                };
+                Roll {
+                    total: kept.total,
+                    detail: format!(
+                        "{} (dropped {} = {})",
+                        kept.detail, dropped.detail, dropped.total
+                    ),
+                }
+            }
+        }
+    }
+
+    fn roll_once(&self, rng: &mut impl Rng) -> Roll {
+        let mut total = 0i64;
+        let mut detail = String::new();
+        for term in &self.terms {
+            let sign = match term {
+                Term::Dice { negative: true, .. } => "-",
+                Term::Constant(value) if *value < 0 => "-",
+                _ => "+",
+            };
+            if !detail.is_empty() || sign == "-" {
+                let _ = write!(detail, " {sign} ");
+            }
+            match *term {
+                Term::Dice {
+                    count,
+                    sides,
+                    exploding,
+                    negative,
+                } => {
+                    let mut faces = Vec::new();
+                    let mut remaining = count;
+                    // Explosions are capped so a run of luck can't go on forever
+                    let mut budget = MAX_DICE;
+                    while remaining > 0 && budget > 0 {
+                        remaining -= 1;
+                        budget -= 1;
+                        let face = rng.gen_range(1..=sides);
+                        faces.push(face);
+                        if exploding && face == sides {
+                            remaining += 1;
+                        }
+                    }
+                    let sum: i64 = faces.iter().map(|&face| i64::from(face)).sum();
+                    total += if negative { -sum } else { sum };
+                    let faces: Vec<_> = faces.iter().map(u32::to_string).collect();
+                    let _ = write!(detail, "[{}]", faces.join(", "));
+                }
+                Term::Constant(value) => {
+                    total += value;
+                    let _ = write!(detail, "{}", value.abs());
+                }
+            }
+        }
+        Roll {
+            total,
+            detail: detail.trim().to_owned(),
+        }
+    }
+}
+
+/// Split an expression into signed terms, e.g. `2d6-1` into `+2d6` and `-1`.
+fn split_terms(expression: &str) -> anyhow::Result<Vec<(bool, &str)>> {
+    let mut terms = Vec::new();
+    let mut negative = false;
+    let mut start = 0;
+    for (i, c) in expression.char_indices() {
+        if c == '+' || c == '-' {
+            if i > start {
+                terms.push((negative, &expression[start..i]));
+            } else if i > 0 {
+                bail!("That roll has two signs in a row.");
+            }
+            negative = c == '-';
+            start = i + 1;
+        }
+    }
+    ensure!(start < expression.len(), "That roll ends with a sign.");
+    terms.push((negative, &expression[start..]));
+    Ok(terms)
+}
+
+fn parse_number(number: &str) -> anyhow::Result<u32> {
+    match number.parse() {
+        Ok(number) => Ok(number),
+        Err(_) => bail!("I don't understand `{number}`. Try something like `3d6+2`."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn dice(count: u32, sides: u32) -> Term {
+        Term::Dice {
+            count,
+            sides,
+            exploding: false,
+            negative: false,
+        }
+    }
+
+    fn error(input: &str) -> String {
+        Expression::parse(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_expressions() {
+        let expression = Expression::parse("3d6+2").unwrap();
+        assert_eq!(expression.terms, [dice(3, 6), Term::Constant(2)]);
+        assert_eq!(expression.mode, Mode::Normal);
+
+        let expression = Expression::parse("D20 - 1 adv").unwrap();
+        assert_eq!(expression.terms, [dice(1, 20), Term::Constant(-1)]);
+        assert_eq!(expression.mode, Mode::Advantage);
+
+        let expression = Expression::parse("-2d4! + 1d8").unwrap();
+        assert_eq!(
+            expression.terms,
+            [
+                Term::Dice {
+                    count: 2,
+                    sides: 4,
+                    exploding: true,
+                    negative: true,
+                },
+                dice(1, 8)
+            ]
+        );
+
+        // Just a mode rolls a d20
+        let expression = Expression::parse("disadvantage").unwrap();
+        assert_eq!(expression.terms, [dice(1, 20)]);
+        assert_eq!(expression.mode, Mode::Disadvantage);
+    }
+
+    #[test]
+    fn limits_dice_and_sides() {
+        assert!(Expression::parse("100d6").is_ok());
+        assert!(Expression::parse("1d1000").is_ok());
+        assert!(Expression::parse("1d2").is_ok());
+
+        assert_eq!(error("101d6"), "I can only roll 100 dice at once.");
+        // Counted across the whole expression
+        assert_eq!(error("60d6+41d4"), "I can only roll 100 dice at once.");
+        assert_eq!(error("0d6"), "Roll at least one die.");
+        assert_eq!(error("1d1"), "Dice need between 2 and 1000 sides.");
+        assert_eq!(error("1d1001"), "Dice need between 2 and 1000 sides.");
+        // Too big for a u32 is reported as not understood rather than wrapping
+        assert!(error("4294967296d6").starts_with("I don't understand `4294967296`."));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert_eq!(error("3d6+"), "That roll ends with a sign.");
+        assert_eq!(error("3d6+-2"), "That roll has two signs in a row.");
+        assert_eq!(
+            error("5"),
+            "What should I roll? Try something like `3d6+2`."
+        );
+        assert!(error("xd6").starts_with("I don't understand `x`."));
+        assert!(error("3d").starts_with("I don't understand ``."));
+        assert!(error("3d6+two").starts_with("I don't understand `two`."));
+        assert!(error("1d6d6").starts_with("I don't understand `6d6`."));
+    }
+
+    #[test]
+    fn totals_stay_in_range() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let expression = Expression::parse("3d6+2").unwrap();
+        for _ in 0..1000 {
+            let roll = expression.roll(&mut rng);
+            assert!((5..=20).contains(&roll.total), "{roll:?}");
+        }
+
+        // Explosions stop at the dice limit
+        let expression = Expression::parse("100d2!").unwrap();
+        for _ in 0..100 {
+            let roll = expression.roll(&mut rng);
+            assert!((100..=200).contains(&roll.total), "{roll:?}");
+        }
+    }
+
+    #[test]
+    fn advantage_keeps_the_higher_roll() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let advantage = Expression::parse("1d20 adv").unwrap();
+        let disadvantage = Expression::parse("1d20 dis").unwrap();
+        for _ in 0..100 {
+            let roll = advantage.roll(&mut rng);
+            let (_, dropped) = roll.detail.rsplit_once(" = ").unwrap();
+            let dropped: i64 = dropped.trim_end_matches(')').parse().unwrap();
+            assert!(roll.total >= dropped, "{roll:?}");
+
+            let roll = disadvantage.roll(&mut rng);
+            let (_, dropped) = roll.detail.rsplit_once(" = ").unwrap();
+            let dropped: i64 = dropped.trim_end_matches(')').parse().unwrap();
+            assert!(roll.total <= dropped, "{roll:?}");
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use chrono::Utc;
+use matrix_bot_core::{can_reply, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState,
+};
+use rand::{rngs::OsRng, seq::SliceRandom, Rng};
+use tracing::{debug, instrument};
+
+use crate::{
+    dice::Expression,
+    rolls::{HistoryEntry, Rolls},
+};
+
+/// How many rolls `!roll history` shows.
+const HISTORY_COUNT: usize = 10;
+
+const HELP: &str = "Usage:
+!roll 3d6+2, !roll d20 adv, !roll 1d20+5 dis, !roll 4d6! (exploding)
+!roll history
+!flip
+!choose pizza | tacos | curry";
+
+#[derive(Clone)]
+pub struct Dice {
+    pub rolls: Rolls,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    dice: Ctx<Dice>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    // Rolls use the OS's random number generator, so nobody can predict or
+    // influence them
+    let response = if let Some(args) = strip_command(body, "!roll") {
+        match args {
+            "" | "help" => HELP.to_owned(),
+            "history" => history(&dice.rolls, room.room_id().as_str())?,
+            expression => match Expression::parse(expression) {
+                Ok(parsed) => {
+                    let roll = parsed.roll(&mut OsRng);
+                    dice.rolls.record(
+                        room.room_id().as_str(),
+                        &HistoryEntry {
+                            user_id: event.sender.to_string(),
+                            expression: expression.to_owned(),
+                            total: roll.total,
+                            detail: roll.detail.clone(),
+                            rolled_at: Utc::now(),
+                        },
+                    )?;
+                    debug!(expression, total = roll.total, "Rolled");
+                    format!("🎲 {expression}: {} = {}", roll.detail, roll.total)
+                }
+                Err(err) => err.to_string(),
+            },
+        }
+    } else if strip_command(body, "!flip").is_some() {
+        if OsRng.gen_bool(0.5) {
+            "🪙 Heads".to_owned()
+        } else {
+            "🪙 Tails".to_owned()
+        }
+    } else if let Some(args) = strip_command(body, "!choose") {
+        let separator = if args.contains('|') { '|' } else { ',' };
+        let options: Vec<_> = args
+            .split(separator)
+            .map(str::trim)
+            .filter(|option| !option.is_empty())
+            .collect();
+        if options.len() < 2 {
+            "Give me at least two options, e.g. `!choose pizza | tacos`.".to_owned()
+        } else {
+            format!("I choose {}", options.choose(&mut OsRng).unwrap())
+        }
+    } else {
+        return Ok(());
+    };
+
+    if can_reply(&room).await {
+        reply_notice(&room, &event, response).await;
+    }
+    Ok(())
+}
+
+fn history(rolls: &Rolls, room_id: &str) -> anyhow::Result<String> {
+    let entries = rolls.history(room_id, HISTORY_COUNT)?;
+    if entries.is_empty() {
+        return Ok("Nobody has rolled here yet.".to_owned());
+    }
+    Ok(entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "{} {}: {} → {} = {}",
+                entry.rolled_at.format("%Y-%m-%d %H:%M"),
+                entry.user_id,
+                entry.expression,
+                entry.detail,
+                entry.total
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
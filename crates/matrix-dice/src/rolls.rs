@@ -0,0 +1,79 @@
+use chrono::{DateTime, Utc};
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::params;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE rolls (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        expression TEXT NOT NULL,
+        total INTEGER NOT NULL,
+        detail TEXT NOT NULL,
+        rolled_at INTEGER NOT NULL
+    );
+    CREATE INDEX rolls_room ON rolls (room_id, id);"];
+
+/// A roll in a room's history.
+#[derive(Debug)]
+pub struct HistoryEntry {
+    pub user_id: String,
+    pub expression: String,
+    pub total: i64,
+    pub detail: String,
+    pub rolled_at: DateTime<Utc>,
+}
+
+/// Each room's roll history, persisted in SQLite so that nobody can claim a
+/// roll they didn't get.
+#[derive(Clone)]
+pub struct Rolls {
+    store: Store,
+}
+
+impl Rolls {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn record(&self, room_id: &str, entry: &HistoryEntry) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO rolls (room_id, user_id, expression, total, detail, rolled_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    room_id,
+                    entry.user_id,
+                    entry.expression,
+                    entry.total,
+                    entry.detail,
+                    entry.rolled_at.timestamp()
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// A room's most recent rolls, newest first.
+    pub fn history(&self, room_id: &str, limit: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT user_id, expression, total, detail, rolled_at FROM rolls
+                WHERE room_id = ?1 ORDER BY id DESC LIMIT ?2",
+            )?;
+            let entries = statement
+                .query_map(params![room_id, limit], |row| {
+                    Ok(HistoryEntry {
+                        user_id: row.get(0)?,
+                        expression: row.get(1)?,
+                        total: row.get(2)?,
+                        detail: row.get(3)?,
+                        rolled_at: DateTime::from_timestamp(row.get(4)?, 0).unwrap_or_default(),
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(entries)
+        })
+    }
+}
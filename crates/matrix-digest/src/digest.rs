@@ -0,0 +1,302 @@
+use chrono::{DateTime, Days, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use matrix_bot_core::{html, send};
+use matrix_sdk::{
+    ruma::{
+        events::room::{member::MembershipState, message::RoomMessageEventContent},
+        RoomId, UserId,
+    },
+    Client, Room, RoomState,
+};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::{error, info, instrument, warn};
+
+use crate::history::{now, Activity, History, Subscriber};
+
+/// How often to check whether anyone's digest is due.
+const TICK: Duration = Duration::from_secs(60);
+
+/// How often digests are sent.
+pub const PERIOD: u64 = 24 * 60 * 60;
+
+/// How long to keep messages, long enough to cover a digest held back by
+/// quiet hours.
+const RETENTION: u64 = 2 * PERIOD;
+
+/// How many threads, links and mentions to show for each room.
+const MAX_ITEMS: usize = 5;
+
+#[derive(Clone)]
+pub struct Digests {
+    pub history: History,
+    pub timezone: Tz,
+    /// When digests are sent each day, in `timezone`.
+    pub send_at: NaiveTime,
+}
+
+/// Send digests as they come due, forever.
+pub async fn run(client: Client, digests: Digests) {
+    let mut interval = interval(TICK);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(err) = tick(&client, &digests).await {
+            error!("Failed to send digests: {err:#}");
+        }
+    }
+}
+
+async fn tick(client: &Client, digests: &Digests) -> anyhow::Result<()> {
+    let now = now();
+    let due = last_send_time(Utc::now().with_timezone(&digests.timezone), digests.send_at);
+    let local_time = Utc::now().with_timezone(&digests.timezone).time();
+    for subscriber in digests.history.subscribers()? {
+        if subscriber.last_sent >= due {
+            continue;
+        }
+        if let Some((start, end)) = subscriber.quiet {
+            if is_quiet(local_time, start, end) {
+                continue;
+            }
+        }
+        if let Err(err) = deliver(client, digests, &subscriber, now).await {
+            warn!(
+                user = subscriber.user_id.as_str(),
+                "Failed to send digest: {err:#}"
+            );
+        }
+    }
+    digests.history.prune(now.saturating_sub(RETENTION))?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(user = subscriber.user_id.as_str()))]
+async fn deliver(
+    client: &Client,
+    digests: &Digests,
+    subscriber: &Subscriber,
+    now: u64,
+) -> anyhow::Result<()> {
+    let user_id = UserId::parse(&subscriber.user_id)?;
+    let since = subscriber.last_sent.max(now.saturating_sub(RETENTION));
+    let mut sections = Vec::new();
+    for room_id in digests.history.subscriptions(user_id.as_str())? {
+        let Some(room) = <&RoomId>::try_from(room_id.as_str())
+            .ok()
+            .and_then(|room_id| client.get_room(room_id))
+            .filter(|room| room.state() == RoomState::Joined)
+        else {
+            continue;
+        };
+        if !is_member(&room, &user_id).await {
+            info!(room = room_id.as_str(), "Left the room, unsubscribing");
+            digests
+                .history
+                .unsubscribe(user_id.as_str(), Some(&room_id))?;
+            continue;
+        }
+        let activity = digests
+            .history
+            .activity(&room_id, since, user_id.as_str(), MAX_ITEMS)?;
+        if activity.messages > 0 {
+            sections.push(Section::new(&room, activity));
+        }
+    }
+
+    // Mark it first, so someone we can't DM doesn't get retried every tick
+    digests.history.set_last_sent(user_id.as_str(), now)?;
+    if sections.is_empty() {
+        info!("Nothing to report");
+        return Ok(());
+    }
+    info!(rooms = sections.len(), "Sending digest");
+    send_dm(client, &user_id, render(digests, since, &sections)).await
+}
+
+/// The digest of one room since `since`, to send on request.
+pub async fn preview(
+    room: &Room,
+    digests: &Digests,
+    user_id: &UserId,
+    since: u64,
+) -> anyhow::Result<Option<RoomMessageEventContent>> {
+    let activity =
+        digests
+            .history
+            .activity(room.room_id().as_str(), since, user_id.as_str(), MAX_ITEMS)?;
+    if activity.messages == 0 {
+        return Ok(None);
+    }
+    Ok(Some(render(
+        digests,
+        since,
+        &[Section::new(room, activity)],
+    )))
+}
+
+/// A room's part of a digest.
+struct Section {
+    room_id: String,
+    name: String,
+    activity: Activity,
+}
+
+impl Section {
+    fn new(room: &Room, activity: Activity) -> Self {
+        Self {
+            room_id: room.room_id().to_string(),
+            name: room_name(&room.client(), room.room_id().as_str()),
+            activity,
+        }
+    }
+
+    fn link(&self, event_id: &str) -> String {
+        format!("https://matrix.to/#/{}/{event_id}", self.room_id)
+    }
+}
+
+fn render(digests: &Digests, since: u64, sections: &[Section]) -> RoomMessageEventContent {
+    let since = DateTime::from_timestamp(since as i64, 0)
+        .map(|since| {
+            since
+                .with_timezone(&digests.timezone)
+                .format("%A %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default();
+    let footer = "Send !digest unsubscribe in a room to stop getting its digest.";
+    let mut plain = format!("Your digest since {since}\n");
+    let mut formatted = format!("<h3>Your digest since {since}</h3>");
+
+    for section in sections {
+        let activity = &section.activity;
+        let summary = format!(
+            "{} messages from {} people",
+            activity.messages, activity.senders
+        );
+        plain.push_str(&format!("\n{}: {summary}\n", section.name));
+        formatted.push_str(&format!(
+            "<h4>{}</h4><p>{summary}</p>",
+            html::escape(&section.name)
+        ));
+
+        if !activity.mentions.is_empty() {
+            plain.push_str("Mentions of you:\n");
+            formatted.push_str("<p>Mentions of you:</p><ul>");
+            for mention in &activity.mentions {
+                let link = section.link(&mention.event_id);
+                plain.push_str(&format!(
+                    "- {}: {} {link}\n",
+                    mention.sender, mention.snippet
+                ));
+                formatted.push_str(&format!(
+                    "<li>{}: <a href=\"{}\">{}</a></li>",
+                    UserId::parse(&mention.sender)
+                        .map(|sender| html::user_pill(&sender))
+                        .unwrap_or_else(|_| html::escape(&mention.sender)),
+                    html::escape(&link),
+                    html::escape(&mention.snippet)
+                ));
+            }
+            formatted.push_str("</ul>");
+        }
+
+        if !activity.threads.is_empty() {
+            plain.push_str("Busiest threads:\n");
+            formatted.push_str("<p>Busiest threads:</p><ul>");
+            for thread in &activity.threads {
+                let link = section.link(&thread.root);
+                let title = thread.snippet.as_deref().unwrap_or("A thread");
+                plain.push_str(&format!("- {title} ({} replies) {link}\n", thread.replies));
+                formatted.push_str(&format!(
+                    "<li><a href=\"{}\">{}</a> ({} replies)</li>",
+                    html::escape(&link),
+                    html::escape(title),
+                    thread.replies
+                ));
+            }
+            formatted.push_str("</ul>");
+        }
+
+        if !activity.links.is_empty() {
+            plain.push_str("Links shared:\n");
+            formatted.push_str("<p>Links shared:</p><ul>");
+            for link in &activity.links {
+                plain.push_str(&format!("- {link}\n"));
+                formatted.push_str(&format!(
+                    "<li><a href=\"{0}\">{0}</a></li>",
+                    html::escape(link)
+                ));
+            }
+            formatted.push_str("</ul>");
+        }
+    }
+
+    plain.push_str(&format!("\n{footer}"));
+    formatted.push_str(&format!("<p>{footer}</p>"));
+    RoomMessageEventContent::notice_html(plain, formatted)
+}
+
+/// The most recent time digests were due, as a timestamp.
+fn last_send_time(now: DateTime<Tz>, send_at: NaiveTime) -> u64 {
+    let today = now.date_naive();
+    let date = if now.time() >= send_at {
+        today
+    } else {
+        today - Days::new(1)
+    };
+    now.timezone()
+        .from_local_datetime(&date.and_time(send_at))
+        .earliest()
+        // Skipped by a DST change, so send an hour later
+        .or_else(|| {
+            now.timezone()
+                .from_local_datetime(&(date.and_time(send_at) + chrono::Duration::hours(1)))
+                .earliest()
+        })
+        .map_or(0, |time| time.timestamp() as u64)
+}
+
+/// Whether `time` is within quiet hours, which may span midnight.
+fn is_quiet(time: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        time >= start || time < end
+    }
+}
+
+async fn is_member(room: &Room, user_id: &UserId) -> bool {
+    match room.get_member_no_sync(user_id).await {
+        Ok(Some(member)) => *member.membership() == MembershipState::Join,
+        Ok(None) => false,
+        // Keep the subscription if we can't tell
+        Err(_) => true,
+    }
+}
+
+/// A room's name, or its ID if it doesn't have one or we aren't in it.
+pub fn room_name(client: &Client, room_id: &str) -> String {
+    <&RoomId>::try_from(room_id)
+        .ok()
+        .and_then(|room_id| client.get_room(room_id))
+        .and_then(|room| {
+            room.name()
+                .or_else(|| room.canonical_alias().map(|alias| alias.to_string()))
+        })
+        .unwrap_or_else(|| room_id.to_owned())
+}
+
+/// Send a message to a user in their DM with the bot, creating it if needed.
+pub async fn send_dm(
+    client: &Client,
+    user_id: &UserId,
+    content: RoomMessageEventContent,
+) -> anyhow::Result<()> {
+    let room = match client.get_dm_room(user_id) {
+        Some(room) => room,
+        None => client.create_dm(user_id).await?,
+    };
+    send(&room, content).await?;
+    Ok(())
+}
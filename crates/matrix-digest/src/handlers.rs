@@ -0,0 +1,242 @@
+use chrono::NaiveTime;
+use matrix_bot_core::{can_reply, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::{
+        message::{OriginalSyncRoomMessageEvent, Relation},
+        redaction::OriginalSyncRoomRedactionEvent,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, info, instrument};
+
+use crate::{
+    digest::{self, Digests},
+    history::{now, History, Message},
+};
+
+/// How much of a message to keep, to show for thread roots and mentions.
+const MAX_SNIPPET_CHARS: usize = 80;
+
+const HELP: &str = "Get a daily digest of this room in a DM:
+!digest subscribe, or !digest unsubscribe [all]
+!digest list, to see what you're subscribed to
+!digest quiet 22:00-07:00, to hold digests during those hours, or !digest quiet off
+!digest preview, to get this room's digest so far now";
+
+#[derive(Clone)]
+pub struct Recorder {
+    pub history: History,
+}
+
+/// Keep track of messages in rooms someone is subscribed to.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    recorder: Ctx<Recorder>,
+) -> anyhow::Result<()> {
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    if matches!(event.content.relates_to, Some(Relation::Replacement(_))) {
+        return Ok(());
+    }
+    if !recorder.history.is_watched(room.room_id().as_str())? {
+        return Ok(());
+    }
+    let body = event.content.body();
+    if strip_command(body, "!digest").is_some() {
+        return Ok(());
+    }
+
+    let thread_root = match &event.content.relates_to {
+        Some(Relation::Thread(thread)) => Some(thread.event_id.as_str()),
+        _ => None,
+    };
+    let mentions: Vec<String> = event
+        .content
+        .mentions
+        .iter()
+        .flat_map(|mentions| mentions.user_ids.iter().map(|user_id| user_id.to_string()))
+        .collect();
+    debug!("Recording message");
+    recorder.history.record(&Message {
+        room_id: room.room_id().as_str(),
+        event_id: event.event_id.as_str(),
+        sender: event.sender.as_str(),
+        thread_root,
+        snippet: &snippet(body),
+        links: &find_links(body),
+        mentions: &mentions,
+        sent_at: event.origin_server_ts.as_secs().into(),
+    })
+}
+
+/// Leave redacted messages out of digests.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_redaction(
+    event: OriginalSyncRoomRedactionEvent,
+    room: Room,
+    recorder: Ctx<Recorder>,
+) -> anyhow::Result<()> {
+    let redacts = event.content.redacts.as_ref().or(event.redacts.as_ref());
+    if let Some(redacts) = redacts {
+        recorder.history.forget(redacts.as_str())?;
+    }
+    Ok(())
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_command(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    digests: Ctx<Digests>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!digest")) else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let history = &digests.history;
+    let user_id = event.sender.as_str();
+    let room_id = room.room_id().as_str();
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+    let direct = room.is_direct().await?;
+
+    let response = match subcommand {
+        "subscribe" if direct => {
+            "Send !digest subscribe in the room you want a digest of.".to_owned()
+        }
+        "subscribe" => {
+            if history.subscribe(user_id, room_id)? {
+                info!("Subscribed");
+                format!(
+                    "Subscribed, I'll DM you a digest of this room every day at {}.",
+                    digests.send_at.format("%H:%M")
+                )
+            } else {
+                "You're already subscribed to this room.".to_owned()
+            }
+        }
+        "unsubscribe" if direct && rest.is_empty() => {
+            "Send !digest unsubscribe in the room, or !digest unsubscribe all here.".to_owned()
+        }
+        "unsubscribe" => {
+            let room_id = match rest {
+                "all" => None,
+                "" => Some(room_id),
+                room_id => Some(room_id),
+            };
+            let removed = history.unsubscribe(user_id, room_id)?;
+            if removed == 0 {
+                "You weren't subscribed.".to_owned()
+            } else {
+                info!(removed, "Unsubscribed");
+                if room_id.is_some() {
+                    "Unsubscribed.".to_owned()
+                } else {
+                    "Unsubscribed from everything.".to_owned()
+                }
+            }
+        }
+        "list" => {
+            let rooms = history.subscriptions(user_id)?;
+            if rooms.is_empty() {
+                "You aren't subscribed to any rooms.".to_owned()
+            } else {
+                let names: Vec<String> = rooms
+                    .iter()
+                    .map(|room_id| format!("- {}", digest::room_name(&room.client(), room_id)))
+                    .collect();
+                format!("You get digests of:\n{}", names.join("\n"))
+            }
+        }
+        "quiet" => match rest {
+            "off" => {
+                history.set_quiet(user_id, None)?;
+                "Quiet hours are off.".to_owned()
+            }
+            hours => match parse_quiet(hours) {
+                Some((start, end)) => {
+                    history.set_quiet(user_id, Some((start, end)))?;
+                    format!(
+                        "I'll hold digests between {} and {} ({}).",
+                        start.format("%H:%M"),
+                        end.format("%H:%M"),
+                        digests.timezone
+                    )
+                }
+                None => "Give quiet hours like !digest quiet 22:00-07:00.".to_owned(),
+            },
+        },
+        "preview" if direct => "Send !digest preview in the room you want a digest of.".to_owned(),
+        "preview" => {
+            let since = history
+                .last_sent(user_id)?
+                .unwrap_or_else(|| now().saturating_sub(digest::PERIOD));
+            let preview = digest::preview(&room, &digests, &event.sender, since).await?;
+            match preview {
+                Some(content) => {
+                    digest::send_dm(&room.client(), &event.sender, content).await?;
+                    "I've sent it to you in a DM.".to_owned()
+                }
+                None => "Nothing's happened here since your last digest.".to_owned(),
+            }
+        }
+        _ => HELP.to_owned(),
+    };
+
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+/// Parse quiet hours like `22:00-07:00`.
+fn parse_quiet(hours: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = hours.split_once('-')?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+    (start != end).then_some((start, end))
+}
+
+/// The start of the message's first line.
+fn snippet(body: &str) -> String {
+    let line = body
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("");
+    if line.chars().count() > MAX_SNIPPET_CHARS {
+        let truncated: String = line.chars().take(MAX_SNIPPET_CHARS).collect();
+        format!("{}…", truncated.trim_end())
+    } else {
+        line.trim().to_owned()
+    }
+}
+
+/// Links in a message, without trailing punctuation.
+fn find_links(body: &str) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+    for word in body.split_whitespace() {
+        if !(word.starts_with("https://") || word.starts_with("http://")) {
+            continue;
+        }
+        let mut link = word.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '"', '>']);
+        // Keep brackets that are part of the URL, like Wikipedia's
+        while link.ends_with(')') && link.matches('(').count() < link.matches(')').count() {
+            link = &link[..link.len() - 1];
+        }
+        if !links.iter().any(|existing| existing == link) {
+            links.push(link.to_owned());
+        }
+    }
+    links
+}
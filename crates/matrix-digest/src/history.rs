@@ -0,0 +1,339 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::NaiveTime;
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE subscribers (
+        user_id TEXT PRIMARY KEY,
+        quiet_start TEXT,
+        quiet_end TEXT,
+        last_sent INTEGER NOT NULL
+    );
+    CREATE TABLE subscriptions (
+        user_id TEXT NOT NULL REFERENCES subscribers (user_id),
+        room_id TEXT NOT NULL,
+        PRIMARY KEY (user_id, room_id)
+    );
+    CREATE INDEX subscriptions_room ON subscriptions (room_id);
+    CREATE TABLE messages (
+        event_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        thread_root TEXT,
+        snippet TEXT NOT NULL,
+        sent_at INTEGER NOT NULL
+    );
+    CREATE INDEX messages_room ON messages (room_id, sent_at);
+    CREATE TABLE links (
+        event_id TEXT NOT NULL REFERENCES messages (event_id) ON DELETE CASCADE,
+        url TEXT NOT NULL
+    );
+    CREATE TABLE mentions (
+        event_id TEXT NOT NULL REFERENCES messages (event_id) ON DELETE CASCADE,
+        user_id TEXT NOT NULL
+    );"];
+
+/// Subscriptions, and what's been said in subscribed rooms recently,
+/// persisted in SQLite.
+#[derive(Clone)]
+pub struct History {
+    store: Store,
+}
+
+/// A message as the digest needs it.
+pub struct Message<'a> {
+    pub room_id: &'a str,
+    pub event_id: &'a str,
+    pub sender: &'a str,
+    pub thread_root: Option<&'a str>,
+    /// The start of the message, to show for thread roots and mentions.
+    pub snippet: &'a str,
+    pub links: &'a [String],
+    pub mentions: &'a [String],
+    pub sent_at: u64,
+}
+
+#[derive(Debug)]
+pub struct Subscriber {
+    pub user_id: String,
+    /// Quiet hours, during which digests wait, in the bot's time zone.
+    pub quiet: Option<(NaiveTime, NaiveTime)>,
+    /// When the last digest was sent, or they subscribed.
+    pub last_sent: u64,
+}
+
+#[derive(Debug)]
+pub struct Thread {
+    pub root: String,
+    pub replies: usize,
+    pub snippet: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Mention {
+    pub event_id: String,
+    pub sender: String,
+    pub snippet: String,
+}
+
+/// What happened in a room since the last digest.
+#[derive(Debug)]
+pub struct Activity {
+    pub messages: usize,
+    pub senders: usize,
+    pub threads: Vec<Thread>,
+    pub links: Vec<String>,
+    pub mentions: Vec<Mention>,
+}
+
+impl History {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Subscribe a user to a room, returning whether they weren't already.
+    pub fn subscribe(&self, user_id: &str, room_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO subscribers (user_id, last_sent) VALUES (?1, ?2)",
+                params![user_id, now()],
+            )?;
+            let added = conn.execute(
+                "INSERT OR IGNORE INTO subscriptions (user_id, room_id) VALUES (?1, ?2)",
+                [user_id, room_id],
+            )?;
+            Ok(added > 0)
+        })
+    }
+
+    /// Unsubscribe a user from a room, or every room, returning how many
+    /// subscriptions they had.
+    pub fn unsubscribe(&self, user_id: &str, room_id: Option<&str>) -> anyhow::Result<usize> {
+        self.store.with(|conn| {
+            let removed = match room_id {
+                Some(room_id) => conn.execute(
+                    "DELETE FROM subscriptions WHERE user_id = ?1 AND room_id = ?2",
+                    [user_id, room_id],
+                )?,
+                None => conn.execute("DELETE FROM subscriptions WHERE user_id = ?1", [user_id])?,
+            };
+            Ok(removed)
+        })
+    }
+
+    pub fn subscriptions(&self, user_id: &str) -> anyhow::Result<Vec<String>> {
+        self.store.with(|conn| {
+            let mut statement = conn
+                .prepare("SELECT room_id FROM subscriptions WHERE user_id = ?1 ORDER BY room_id")?;
+            let rooms = statement
+                .query_map([user_id], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            Ok(rooms)
+        })
+    }
+
+    /// Whether anyone is subscribed to the room, so its messages are worth
+    /// recording.
+    pub fn is_watched(&self, room_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM subscriptions WHERE room_id = ?1)",
+                [room_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    /// Everyone with at least one subscription.
+    pub fn subscribers(&self) -> anyhow::Result<Vec<Subscriber>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT user_id, quiet_start, quiet_end, last_sent FROM subscribers
+                WHERE user_id IN (SELECT user_id FROM subscriptions)",
+            )?;
+            let subscribers = statement
+                .query_map([], |row| {
+                    let start: Option<String> = row.get(1)?;
+                    let end: Option<String> = row.get(2)?;
+                    Ok(Subscriber {
+                        user_id: row.get(0)?,
+                        quiet: start.zip(end).and_then(|(start, end)| {
+                            Some((
+                                NaiveTime::parse_from_str(&start, "%H:%M").ok()?,
+                                NaiveTime::parse_from_str(&end, "%H:%M").ok()?,
+                            ))
+                        }),
+                        last_sent: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(subscribers)
+        })
+    }
+
+    pub fn set_quiet(
+        &self,
+        user_id: &str,
+        quiet: Option<(NaiveTime, NaiveTime)>,
+    ) -> anyhow::Result<()> {
+        let format = |time: NaiveTime| time.format("%H:%M").to_string();
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO subscribers (user_id, quiet_start, quiet_end, last_sent)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (user_id) DO UPDATE SET quiet_start = ?2, quiet_end = ?3",
+                params![
+                    user_id,
+                    quiet.map(|(start, _)| format(start)),
+                    quiet.map(|(_, end)| format(end)),
+                    now()
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn set_last_sent(&self, user_id: &str, last_sent: u64) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE subscribers SET last_sent = ?2 WHERE user_id = ?1",
+                params![user_id, last_sent],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn record(&self, message: &Message) -> anyhow::Result<()> {
+        self.store.transaction(|transaction| {
+            let added = transaction.execute(
+                "INSERT OR IGNORE INTO messages (event_id, room_id, sender, thread_root, snippet, sent_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    message.event_id,
+                    message.room_id,
+                    message.sender,
+                    message.thread_root,
+                    message.snippet,
+                    message.sent_at
+                ],
+            )?;
+            if added > 0 {
+                for url in message.links {
+                    transaction.execute(
+                        "INSERT INTO links (event_id, url) VALUES (?1, ?2)",
+                        [message.event_id, url],
+                    )?;
+                }
+                for user_id in message.mentions {
+                    transaction.execute(
+                        "INSERT INTO mentions (event_id, user_id) VALUES (?1, ?2)",
+                        [message.event_id, user_id],
+                    )?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    pub fn forget(&self, event_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute("DELETE FROM messages WHERE event_id = ?1", [event_id])?;
+            Ok(())
+        })
+    }
+
+    /// Forget messages older than `before`, which no digest will need.
+    pub fn prune(&self, before: u64) -> anyhow::Result<usize> {
+        self.store
+            .with(|conn| conn.execute("DELETE FROM messages WHERE sent_at < ?1", [before]))
+    }
+
+    /// What happened in a room since `since`, with mentions of `user_id`.
+    pub fn activity(
+        &self,
+        room_id: &str,
+        since: u64,
+        user_id: &str,
+        limit: usize,
+    ) -> anyhow::Result<Activity> {
+        self.store.with(|conn| {
+            let (messages, senders) = conn.query_row(
+                "SELECT COUNT(*), COUNT(DISTINCT sender) FROM messages
+                WHERE room_id = ?1 AND sent_at >= ?2",
+                params![room_id, since],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+
+            let mut statement = conn.prepare(
+                "SELECT thread_root, COUNT(*) AS replies,
+                    (SELECT snippet FROM messages AS root WHERE root.event_id = reply.thread_root)
+                FROM messages AS reply
+                WHERE room_id = ?1 AND sent_at >= ?2 AND thread_root IS NOT NULL
+                GROUP BY thread_root ORDER BY replies DESC, MAX(sent_at) DESC LIMIT ?3",
+            )?;
+            let threads = statement
+                .query_map(params![room_id, since, limit], |row| {
+                    Ok(Thread {
+                        root: row.get(0)?,
+                        replies: row.get(1)?,
+                        snippet: row.get(2)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+
+            let mut statement = conn.prepare(
+                "SELECT url FROM links JOIN messages USING (event_id)
+                WHERE room_id = ?1 AND sent_at >= ?2
+                GROUP BY url ORDER BY MIN(sent_at) LIMIT ?3",
+            )?;
+            let links = statement
+                .query_map(params![room_id, since, limit], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+
+            let mut statement = conn.prepare(
+                "SELECT event_id, sender, snippet FROM mentions JOIN messages USING (event_id)
+                WHERE room_id = ?1 AND sent_at >= ?2 AND user_id = ?3
+                ORDER BY sent_at LIMIT ?4",
+            )?;
+            let mentions = statement
+                .query_map(params![room_id, since, user_id, limit], |row| {
+                    Ok(Mention {
+                        event_id: row.get(0)?,
+                        sender: row.get(1)?,
+                        snippet: row.get(2)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+
+            Ok(Activity {
+                messages,
+                senders,
+                threads,
+                links,
+                mentions,
+            })
+        })
+    }
+
+    /// When a user's last digest was sent, or `None` if they've never
+    /// subscribed.
+    pub fn last_sent(&self, user_id: &str) -> anyhow::Result<Option<u64>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT last_sent FROM subscribers WHERE user_id = ?1",
+                [user_id],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
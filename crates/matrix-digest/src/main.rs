@@ -0,0 +1,69 @@
+mod digest;
+mod handlers;
+mod history;
+
+use chrono::NaiveTime;
+use chrono_tz::Tz;
+use clap::Parser;
+use digest::Digests;
+use handlers::{on_command, on_redaction, on_room_message, Recorder};
+use history::History;
+use matrix_bot_core::{AccountConfig, Bot};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Time zone for the send time and quiet hours, e.g. `Europe/Berlin`
+    #[arg(long, default_value_t = Tz::UTC, env = "DIGEST_TIMEZONE")]
+    pub timezone: Tz,
+
+    /// Time of day to send digests
+    #[arg(long, default_value = "08:00", value_parser = parse_send_at, env = "DIGEST_SEND_AT")]
+    pub send_at: NaiveTime,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_send_at(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| format!("invalid send time: {time}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-digest", config.account_config).await?;
+    let history = History::open(&bot).await?;
+
+    // Unlike most bots, record messages from the initial sync too, so ones
+    // sent while the bot was down still make it into digests.
+    bot.client().add_event_handler_context(Recorder {
+        history: history.clone(),
+    });
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_redaction);
+    bot.initial_sync().await?;
+
+    let digests = Digests {
+        history,
+        timezone: config.timezone,
+        send_at: config.send_at,
+    };
+    tokio::spawn(digest::run(bot.client().clone(), digests.clone()));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(digests);
+    bot.client().add_event_handler(on_command);
+
+    bot.run().await
+}
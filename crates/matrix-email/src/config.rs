@@ -0,0 +1,65 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, Context};
+use matrix_bot_core::policy::glob_matches;
+use matrix_sdk::ruma::OwnedRoomId;
+use serde::Deserialize;
+
+/// The mailboxes file, e.g.
+///
+/// ```toml
+/// [mailboxes.support]
+/// address = "support@example.org"
+/// room = "!abc:example.org"
+/// allow = ["*@example.org", "alice@example.com"]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MailboxesFile {
+    pub mailboxes: HashMap<String, Mailbox>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mailbox {
+    /// The address mail is accepted for.
+    pub address: String,
+    /// Where mail to the address is posted.
+    pub room: OwnedRoomId,
+    /// Globs of the sender addresses to accept mail from, where `*` matches
+    /// anything. Mail from anyone else is rejected.
+    pub allow: Vec<String>,
+}
+
+impl Mailbox {
+    pub fn allows(&self, sender: &str) -> bool {
+        let sender = sender.to_lowercase();
+        self.allow
+            .iter()
+            .any(|glob| glob_matches(&glob.to_lowercase(), &sender))
+    }
+}
+
+impl MailboxesFile {
+    pub fn find(&self, address: &str) -> Option<&Mailbox> {
+        self.mailboxes
+            .values()
+            .find(|mailbox| mailbox.address.eq_ignore_ascii_case(address))
+    }
+}
+
+pub fn load(path: &Path) -> anyhow::Result<MailboxesFile> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read mailboxes file {}", path.display()))?;
+    let file: MailboxesFile = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse mailboxes file {}", path.display()))?;
+    for (name, mailbox) in &file.mailboxes {
+        if !mailbox.address.contains('@') {
+            bail!("mailbox {name} has an invalid address {}", mailbox.address);
+        }
+        if mailbox.allow.is_empty() {
+            bail!("mailbox {name} doesn't allow anyone to send to it");
+        }
+    }
+    Ok(file)
+}
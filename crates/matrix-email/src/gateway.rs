@@ -0,0 +1,291 @@
+use anyhow::{ensure, Context};
+use mail_parser::{Message, MessageParser, MessagePart, MimeHeaders};
+use matrix_bot_core::{html, send};
+use matrix_sdk::{
+    ruma::{
+        events::{
+            relation::Thread,
+            room::{
+                message::{
+                    FileInfo, FileMessageEventContent, ImageMessageEventContent, MessageType,
+                    Relation, RoomMessageEventContent,
+                },
+                ImageInfo,
+            },
+        },
+        OwnedEventId, UInt,
+    },
+    Client, Room, RoomState,
+};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    config::{Mailbox, MailboxesFile},
+    smtp::Envelope,
+    threads::Threads,
+};
+
+/// The most of an email's text to post, leaving room in the event for the
+/// HTML version too.
+const MAX_BODY: usize = 24 * 1024;
+
+/// The largest attachment to upload.
+const MAX_ATTACHMENT_SIZE: usize = 20 * 1024 * 1024;
+
+/// What became of a message, to tell the SMTP client.
+pub enum Verdict {
+    Accepted,
+    /// Don't try again.
+    Rejected(String),
+    /// Try again later.
+    Failed(String),
+}
+
+/// Posts mail to the rooms of the mailboxes it was sent to.
+pub struct Gateway {
+    pub client: Client,
+    pub mailboxes: MailboxesFile,
+    pub threads: Threads,
+}
+
+impl Gateway {
+    pub fn accepts(&self, address: &str) -> bool {
+        self.mailboxes.find(address).is_some()
+    }
+
+    pub async fn deliver(&self, envelope: Envelope) -> Verdict {
+        let Some(message) = MessageParser::default().parse(&envelope.data) else {
+            return Verdict::Rejected("Couldn't parse the message".to_owned());
+        };
+        // People allow who they see mail from, not the bounce address
+        let sender = message
+            .from()
+            .and_then(|from| from.first())
+            .and_then(|from| from.address())
+            .unwrap_or(envelope.from.as_str());
+
+        let mut mailboxes: Vec<&Mailbox> = Vec::new();
+        for address in &envelope.to {
+            if let Some(mailbox) = self.mailboxes.find(address) {
+                if mailbox.allows(sender) && !mailboxes.iter().any(|m| std::ptr::eq(*m, mailbox)) {
+                    mailboxes.push(mailbox);
+                }
+            }
+        }
+        if mailboxes.is_empty() {
+            info!(sender, "Rejected mail from a sender that isn't allowed");
+            return Verdict::Rejected(format!("{sender} isn't allowed to send here"));
+        }
+
+        let mut posted = 0;
+        for mailbox in &mailboxes {
+            match self.post(mailbox, &message, sender).await {
+                Ok(()) => posted += 1,
+                Err(err) => warn!(room = mailbox.room.as_str(), "Failed to post mail: {err:#}"),
+            }
+        }
+        // Once it's in some rooms, trying again would post it there twice
+        if posted == 0 {
+            return Verdict::Failed("Couldn't post the message, try again later".to_owned());
+        }
+        Verdict::Accepted
+    }
+
+    #[instrument(skip_all, fields(room = mailbox.room.as_str()))]
+    async fn post(
+        &self,
+        mailbox: &Mailbox,
+        message: &Message<'_>,
+        sender: &str,
+    ) -> anyhow::Result<()> {
+        let room = self
+            .client
+            .get_room(&mailbox.room)
+            .filter(|room| room.state() == RoomState::Joined)
+            .context("not in the mailbox's room")?;
+        let room_id = room.room_id().as_str();
+
+        let subject = message.subject().unwrap_or("(no subject)");
+        let key = thread_key(subject);
+        let thread = self
+            .threads
+            .thread(room_id, &key)?
+            .and_then(|event_id| OwnedEventId::try_from(event_id).ok());
+
+        let mut content = render(message, sender, subject);
+        if let Some(root) = &thread {
+            content.relates_to = Some(Relation::Thread(Thread::plain(root.clone(), root.clone())));
+        }
+        let event_id = send(&room, content).await?;
+        info!(sender, "Posted mail");
+        let root = match thread {
+            Some(root) => root,
+            None => {
+                self.threads.set_thread(room_id, &key, event_id.as_str())?;
+                event_id
+            }
+        };
+
+        for attachment in message.attachments() {
+            if let Err(err) = post_attachment(&room, &root, attachment).await {
+                warn!("Failed to post attachment: {err:#}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The subject without any `Re:` or `Fwd:` in front, so replies land in the
+/// same thread.
+fn thread_key(subject: &str) -> String {
+    const PREFIXES: [&str; 5] = ["re:", "fwd:", "fw:", "aw:", "sv:"];
+    let mut subject = subject.trim();
+    while let Some(prefix) = PREFIXES.iter().find(|prefix| {
+        subject
+            .get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    }) {
+        subject = subject[prefix.len()..].trim_start();
+    }
+    subject.to_lowercase()
+}
+
+fn render(message: &Message<'_>, sender: &str, subject: &str) -> RoomMessageEventContent {
+    let name = message
+        .from()
+        .and_then(|from| from.first())
+        .and_then(|from| from.name());
+    let from = match name {
+        Some(name) => format!("{name} <{sender}>"),
+        None => sender.to_owned(),
+    };
+    let text = message.body_text(0).unwrap_or_default();
+    let plain = format!(
+        "From: {from}\nSubject: {subject}\n\n{}",
+        truncate(text.trim(), MAX_BODY)
+    );
+
+    let body = message.body_html(0).map(|body| sanitize(&body));
+    match body.filter(|body| body.len() <= MAX_BODY) {
+        Some(body) => {
+            let formatted = format!(
+                "<p><b>From:</b> {}<br><b>Subject:</b> {}</p>{body}",
+                html::escape(&from),
+                html::escape(subject)
+            );
+            RoomMessageEventContent::text_html(plain, formatted)
+        }
+        None => RoomMessageEventContent::text_plain(plain),
+    }
+}
+
+/// Cut an email's HTML down to what Matrix clients are asked to render.
+/// Images are left out, since they'd have to be uploaded first.
+fn sanitize(body: &str) -> String {
+    ammonia::Builder::empty()
+        .add_tags([
+            "font",
+            "del",
+            "h1",
+            "h2",
+            "h3",
+            "h4",
+            "h5",
+            "h6",
+            "blockquote",
+            "p",
+            "a",
+            "ul",
+            "ol",
+            "sup",
+            "sub",
+            "li",
+            "b",
+            "i",
+            "u",
+            "strong",
+            "em",
+            "strike",
+            "code",
+            "hr",
+            "br",
+            "div",
+            "table",
+            "thead",
+            "tbody",
+            "tr",
+            "th",
+            "td",
+            "caption",
+            "pre",
+            "span",
+        ])
+        .add_tag_attributes("a", ["href"])
+        .add_tag_attributes("font", ["color"])
+        .add_tag_attributes("ol", ["start"])
+        .url_schemes(["http", "https", "mailto"].into())
+        .link_rel(None)
+        .clean(body)
+        .to_string()
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_owned();
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+async fn post_attachment(
+    room: &Room,
+    thread: &OwnedEventId,
+    attachment: &MessagePart<'_>,
+) -> anyhow::Result<()> {
+    let name = attachment
+        .attachment_name()
+        .unwrap_or("attachment")
+        .to_owned();
+    let data = attachment.contents();
+    ensure!(
+        data.len() <= MAX_ATTACHMENT_SIZE,
+        "{name} is bigger than {MAX_ATTACHMENT_SIZE} bytes"
+    );
+    let content_type = attachment
+        .content_type()
+        .and_then(|content_type| {
+            format!("{}/{}", content_type.ctype(), content_type.subtype()?)
+                .parse::<mime::Mime>()
+                .ok()
+        })
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+    let uri = room
+        .client()
+        .media()
+        .upload(&content_type, data.to_vec(), None)
+        .await?
+        .content_uri;
+    let msgtype = if content_type.type_() == mime::IMAGE {
+        let mut info = ImageInfo::new();
+        info.mimetype = Some(content_type.to_string());
+        info.size = UInt::new(data.len() as u64);
+        MessageType::Image(ImageMessageEventContent::plain(name, uri).info(Box::new(info)))
+    } else {
+        let mut info = FileInfo::new();
+        info.mimetype = Some(content_type.to_string());
+        info.size = UInt::new(data.len() as u64);
+        MessageType::File(FileMessageEventContent::plain(name, uri).info(Box::new(info)))
+    };
+
+    let mut content = RoomMessageEventContent::new(msgtype);
+    content.relates_to = Some(Relation::Thread(Thread::plain(
+        thread.clone(),
+        thread.clone(),
+    )));
+    send(room, content).await?;
+    Ok(())
+}
@@ -0,0 +1,64 @@
+mod config;
+mod gateway;
+mod smtp;
+mod threads;
+
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+
+use clap::Parser;
+use gateway::Gateway;
+use matrix_bot_core::{AccountConfig, Bot};
+use threads::Threads;
+use tokio::net::TcpListener;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The address to listen for SMTP on. Put a mail server such as Postfix
+    /// in front of it, since it doesn't do TLS
+    #[arg(long, default_value = "0.0.0.0:2525", env = "EMAIL_LISTEN")]
+    pub listen: SocketAddr,
+
+    /// The name to greet SMTP clients with
+    #[arg(long, default_value = "localhost", env = "EMAIL_HOSTNAME")]
+    pub hostname: String,
+
+    /// The TOML file mapping addresses to rooms
+    #[arg(long, env = "EMAIL_MAILBOXES")]
+    pub mailboxes: PathBuf,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    // Fail on a bad mailboxes file before logging in
+    let mailboxes = config::load(&config.mailboxes)?;
+
+    let mut bot = Bot::login("matrix-email", config.account_config).await?;
+    let threads = Threads::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let gateway = Arc::new(Gateway {
+        client: bot.client().clone(),
+        mailboxes,
+        threads,
+    });
+    let listener = TcpListener::bind(config.listen).await?;
+    info!("Listening for SMTP on {}", config.listen);
+    tokio::spawn(smtp::serve(listener, gateway, config.hostname));
+
+    bot.run().await
+}
@@ -0,0 +1,210 @@
+//! Just enough of an SMTP server to take mail handed over by a relay such as
+//! Postfix. There's no TLS or authentication, so it shouldn't be exposed to
+//! the internet directly.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::bail;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+use tracing::{debug, warn};
+
+use crate::gateway::{Gateway, Verdict};
+
+/// The largest message accepted, attachments and all.
+pub const MAX_MESSAGE_SIZE: usize = 25 * 1024 * 1024;
+
+/// The longest command line accepted, as RFC 5321 allows.
+const MAX_LINE: usize = 1000;
+
+const MAX_RECIPIENTS: usize = 100;
+
+/// How long to wait for the next line from a client before hanging up.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A message and who it's from and to, as told by the client rather than
+/// the message headers.
+pub struct Envelope {
+    pub from: String,
+    pub to: Vec<String>,
+    pub data: Vec<u8>,
+}
+
+/// Take mail from each connection, forever.
+pub async fn serve(listener: TcpListener, gateway: Arc<Gateway>, hostname: String) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!("Failed to accept SMTP connection: {err}");
+                continue;
+            }
+        };
+        let gateway = gateway.clone();
+        let hostname = hostname.clone();
+        tokio::spawn(async move {
+            if let Err(err) = session(stream, &gateway, &hostname).await {
+                debug!("SMTP session with {address} ended: {err}");
+            }
+        });
+    }
+}
+
+async fn session<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    gateway: &Gateway,
+    hostname: &str,
+) -> anyhow::Result<()> {
+    let mut stream = BufReader::new(stream);
+    send(&mut stream, &format!("220 {hostname} ESMTP ready")).await?;
+
+    let mut from: Option<String> = None;
+    let mut to: Vec<String> = Vec::new();
+    loop {
+        let Some(line) = read_line(&mut stream, MAX_LINE).await? else {
+            return Ok(());
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (verb, args) = line.split_once(' ').unwrap_or((line, ""));
+
+        let response = match verb.to_ascii_uppercase().as_str() {
+            "HELO" => format!("250 {hostname}"),
+            "EHLO" => format!("250-{hostname}\r\n250-SIZE {MAX_MESSAGE_SIZE}\r\n250 8BITMIME"),
+            "MAIL" if from.is_some() => "503 5.5.1 Already have a sender".to_owned(),
+            "MAIL" => match parse_path(args, "FROM:") {
+                None => "501 5.5.4 Expected MAIL FROM:<address>".to_owned(),
+                Some((_, params)) if declared_size(params) > MAX_MESSAGE_SIZE => {
+                    "552 5.3.4 Message too big".to_owned()
+                }
+                Some((address, _)) => {
+                    from = Some(address.to_owned());
+                    to.clear();
+                    "250 2.1.0 OK".to_owned()
+                }
+            },
+            "RCPT" if from.is_none() => "503 5.5.1 Need MAIL first".to_owned(),
+            "RCPT" => match parse_path(args, "TO:") {
+                None => "501 5.5.4 Expected RCPT TO:<address>".to_owned(),
+                Some(_) if to.len() >= MAX_RECIPIENTS => "452 4.5.3 Too many recipients".to_owned(),
+                Some((address, _)) if gateway.accepts(address) => {
+                    to.push(address.to_owned());
+                    "250 2.1.5 OK".to_owned()
+                }
+                Some(_) => "550 5.1.1 No such mailbox".to_owned(),
+            },
+            "DATA" if to.is_empty() => "503 5.5.1 Need RCPT first".to_owned(),
+            "DATA" => {
+                send(&mut stream, "354 End data with <CR><LF>.<CR><LF>").await?;
+                let Some(data) = read_data(&mut stream).await? else {
+                    from = None;
+                    to.clear();
+                    send(&mut stream, "552 5.3.4 Message too big").await?;
+                    continue;
+                };
+                let envelope = Envelope {
+                    from: from.take().unwrap_or_default(),
+                    to: std::mem::take(&mut to),
+                    data,
+                };
+                match gateway.deliver(envelope).await {
+                    Verdict::Accepted => "250 2.0.0 OK".to_owned(),
+                    Verdict::Rejected(reason) => format!("550 5.7.1 {reason}"),
+                    Verdict::Failed(reason) => format!("451 4.3.0 {reason}"),
+                }
+            }
+            "RSET" => {
+                from = None;
+                to.clear();
+                "250 2.0.0 OK".to_owned()
+            }
+            "NOOP" => "250 2.0.0 OK".to_owned(),
+            "VRFY" => "252 2.5.0 Can't verify addresses".to_owned(),
+            "QUIT" => {
+                send(&mut stream, "221 2.0.0 Bye").await?;
+                return Ok(());
+            }
+            _ => "502 5.5.2 Command not recognised".to_owned(),
+        };
+        send(&mut stream, &response).await?;
+    }
+}
+
+async fn send<S: AsyncWrite + Unpin>(stream: &mut S, response: &str) -> anyhow::Result<()> {
+    stream.write_all(response.as_bytes()).await?;
+    stream.write_all(b"\r\n").await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read a line, including its line ending, or `None` once the client hangs up.
+async fn read_line<S: AsyncRead + Unpin>(
+    stream: &mut BufReader<S>,
+    limit: usize,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    let mut limited = stream.take(limit as u64);
+    match tokio::time::timeout(IDLE_TIMEOUT, limited.read_until(b'\n', &mut line)).await {
+        Err(_) => bail!("timed out"),
+        Ok(read) => read?,
+    };
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\n") {
+        bail!("line too long or cut off");
+    }
+    Ok(Some(line))
+}
+
+/// Read a message up to the line with just a `.`, undoing dot-stuffing, or
+/// `None` if it's too big.
+async fn read_data<S: AsyncRead + Unpin>(
+    stream: &mut BufReader<S>,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let mut too_big = false;
+    loop {
+        let Some(line) = read_line(stream, MAX_MESSAGE_SIZE).await? else {
+            bail!("hung up partway through a message");
+        };
+        if line == b".\r\n" || line == b".\n" {
+            break;
+        }
+        let line = line.strip_prefix(b".").unwrap_or(&line);
+        if data.len() + line.len() > MAX_MESSAGE_SIZE {
+            // Keep reading so the client hears why
+            too_big = true;
+            data.clear();
+        }
+        if !too_big {
+            data.extend_from_slice(line);
+        }
+    }
+    Ok((!too_big).then_some(data))
+}
+
+/// The address in `FROM:<address> PARAMS`, and the params.
+fn parse_path<'a>(args: &'a str, prefix: &str) -> Option<(&'a str, &'a str)> {
+    let start = args.get(..prefix.len())?;
+    if !start.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    let rest = args[prefix.len()..].trim_start().strip_prefix('<')?;
+    let (address, params) = rest.split_once('>')?;
+    Some((address, params.trim()))
+}
+
+/// The `SIZE=` a client declared up front, or 0.
+fn declared_size(params: &str) -> usize {
+    params
+        .split_whitespace()
+        .find_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            key.eq_ignore_ascii_case("SIZE")
+                .then(|| value.parse().ok())?
+        })
+        .unwrap_or(0)
+}
@@ -0,0 +1,45 @@
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE threads (
+        room_id TEXT NOT NULL,
+        subject TEXT NOT NULL,
+        event_id TEXT NOT NULL,
+        PRIMARY KEY (room_id, subject)
+    );"];
+
+/// The thread each subject is posted in, persisted in SQLite.
+#[derive(Clone)]
+pub struct Threads {
+    store: Store,
+}
+
+impl Threads {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// The root of the thread for a subject in a room, if there is one.
+    pub fn thread(&self, room_id: &str, subject: &str) -> anyhow::Result<Option<String>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT event_id FROM threads WHERE room_id = ?1 AND subject = ?2",
+                params![room_id, subject],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+
+    pub fn set_thread(&self, room_id: &str, subject: &str, event_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO threads (room_id, subject, event_id) VALUES (?1, ?2, ?3)",
+                params![room_id, subject, event_id],
+            )?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use matrix_bot_core::{
+    can_reply, format_duration, html, reply, reply_notice, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+    Room, RoomState,
+};
+use tracing::{instrument, warn};
+
+use crate::{
+    limit::RateLimiter,
+    provider::{AnyProvider, Limits, Output, Provider},
+};
+
+/// The longest snippet to run, in bytes.
+const MAX_CODE_LEN: usize = 10_000;
+
+/// The most of each of stdout and stderr to send back.
+const MAX_OUTPUT_LINES: usize = 30;
+const MAX_OUTPUT_CHARS: usize = 2000;
+
+const HELP: &str = "!eval <language> <code> to run a snippet, e.g. !eval python print(1 + 1)
+The code can be in a ``` code block on the following lines too.
+!eval languages to list the languages I can run";
+
+#[derive(Clone)]
+pub struct Evaluator {
+    /// Tried in order for each snippet, until one supports its language.
+    pub providers: Vec<AnyProvider>,
+    pub limits: Limits,
+    pub rate_limiter: RateLimiter,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    evaluator: Ctx<Evaluator>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+    let Some(args) = strip_command(body, "!eval") else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    if args == "languages" {
+        let mut languages: Vec<String> = evaluator
+            .providers
+            .iter()
+            .flat_map(|provider| provider.languages())
+            .collect();
+        languages.sort();
+        languages.dedup();
+        reply_notice(
+            &room,
+            &event,
+            format!("I can run: {}", languages.join(", ")),
+        )
+        .await;
+        return Ok(());
+    }
+
+    let Some((language, code)) = parse_snippet(args) else {
+        reply_notice(&room, &event, HELP).await;
+        return Ok(());
+    };
+    if code.len() > MAX_CODE_LEN {
+        reply_notice(
+            &room,
+            &event,
+            format!("That's too long, I only run snippets up to {MAX_CODE_LEN} characters."),
+        )
+        .await;
+        return Ok(());
+    }
+    let Some(provider) = evaluator
+        .providers
+        .iter()
+        .find(|provider| provider.supports(&language))
+    else {
+        let response = format!("I can't run {language}. `!eval languages` lists the ones I can.");
+        reply_notice(&room, &event, response).await;
+        return Ok(());
+    };
+    if let Err(wait) = evaluator.rate_limiter.check(&event.sender) {
+        // Round up, so it never says to wait 0s
+        let wait = format_duration(Duration::from_secs(wait.as_secs() + 1));
+        let response = format!("You've run a lot of code lately, please try again in {wait}.");
+        reply_notice(&room, &event, response).await;
+        return Ok(());
+    }
+
+    match provider.run(&language, code, evaluator.limits).await {
+        Ok(output) => reply(&room, &event, format_output(&output)).await,
+        Err(err) => {
+            warn!("Failed to run a {language} snippet: {err}");
+            reply_notice(&room, &event, "Sorry, I couldn't run that.").await;
+        }
+    }
+    Ok(())
+}
+
+/// The language and code in `!eval`'s arguments, which are a language and
+/// then code, in a code block or not. The language can be given on the code
+/// block instead.
+fn parse_snippet(args: &str) -> Option<(String, &str)> {
+    let (language, rest) = if args.starts_with("```") {
+        ("", args)
+    } else {
+        args.split_once(char::is_whitespace)?
+    };
+    let rest = rest.trim();
+
+    let (block_language, code) = match rest.strip_prefix("```") {
+        Some(block) => {
+            let (info, code) = block.split_once('\n').unwrap_or(("", block));
+            let code = code.trim_end();
+            (info.trim(), code.strip_suffix("```").unwrap_or(code))
+        }
+        None => ("", rest),
+    };
+    let language = if language.is_empty() {
+        block_language
+    } else {
+        language
+    };
+    if language.is_empty() || code.trim().is_empty() {
+        return None;
+    }
+    Some((language.to_ascii_lowercase(), code))
+}
+
+fn format_output(output: &Output) -> RoomMessageEventContent {
+    let mut plain = Vec::new();
+    let mut formatted = String::new();
+    let stdout = truncate(&output.stdout);
+    let stderr = truncate(&output.stderr);
+
+    if !stdout.is_empty() {
+        plain.push(format!("```\n{stdout}\n```"));
+        formatted += &format!("<pre><code>{}</code></pre>", html::escape(&stdout));
+    }
+    if !stderr.is_empty() {
+        plain.push(format!("stderr:\n```\n{stderr}\n```"));
+        formatted += &format!(
+            "<p>stderr:</p><pre><code>{}</code></pre>",
+            html::escape(&stderr)
+        );
+    }
+    if let Some(failure) = &output.failure {
+        plain.push(format!("({failure})"));
+        formatted += &format!("<p><em>({})</em></p>", html::escape(failure));
+    }
+    if plain.is_empty() {
+        return RoomMessageEventContent::notice_plain("(no output)");
+    }
+    RoomMessageEventContent::notice_html(plain.join("\n"), formatted)
+}
+
+/// Cut output down to size, saying so if anything was cut.
+fn truncate(output: &str) -> String {
+    let output = output.trim_end();
+    let mut truncated: String = output
+        .lines()
+        .take(MAX_OUTPUT_LINES)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if let Some((end, _)) = truncated.char_indices().nth(MAX_OUTPUT_CHARS) {
+        truncated.truncate(end);
+    }
+    if truncated.len() < output.len() {
+        truncated += "\n…";
+    }
+    truncated
+}
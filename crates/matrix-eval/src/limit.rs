@@ -0,0 +1,56 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use matrix_sdk::ruma::{OwnedUserId, UserId};
+
+/// Keeps track of how many snippets each person has run recently.
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// How many snippets someone may run in each `window`, or 0 for no limit.
+    pub max: usize,
+    pub window: Duration,
+    runs: Arc<Mutex<HashMap<OwnedUserId, VecDeque<Instant>>>>,
+}
+
+impl RateLimiter {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            runs: Default::default(),
+        }
+    }
+
+    /// Count a run for `user_id` if they're under the limit, or return how
+    /// long until they will be.
+    pub fn check(&self, user_id: &UserId) -> Result<(), Duration> {
+        if self.max == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut runs = self.runs.lock().unwrap();
+        // Forget about people who haven't run anything lately
+        runs.retain(|_, times| {
+            times
+                .back()
+                .is_some_and(|last| now.duration_since(*last) < self.window)
+        });
+
+        let times = runs.entry(user_id.to_owned()).or_default();
+        while times
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.window)
+        {
+            times.pop_front();
+        }
+        if times.len() >= self.max {
+            let first = times.front().copied().unwrap_or(now);
+            return Err(self.window.saturating_sub(now.duration_since(first)));
+        }
+        times.push_back(now);
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+mod handlers;
+mod limit;
+mod provider;
+
+use std::time::Duration;
+
+use clap::Parser;
+use handlers::{on_room_message, Evaluator};
+use limit::RateLimiter;
+use matrix_bot_core::{AccountConfig, Bot};
+use provider::{AnyProvider, Limits, Piston, ProviderKind, RustPlayground};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The services to run code with, tried in order for each language
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "rust-playground,piston",
+        env = "EVAL_PROVIDERS"
+    )]
+    pub providers: Vec<ProviderKind>,
+
+    /// The Rust playground to use
+    #[arg(
+        long,
+        default_value = "https://play.rust-lang.org",
+        env = "EVAL_RUST_PLAYGROUND_URL"
+    )]
+    pub rust_playground_url: String,
+
+    /// The Piston API to use
+    #[arg(
+        long,
+        default_value = "https://emkc.org/api/v2/piston",
+        env = "EVAL_PISTON_URL"
+    )]
+    pub piston_url: String,
+
+    /// How long a snippet may run for, where the service allows setting it
+    #[arg(long, default_value = "3s", value_parser = parse_timeout, env = "EVAL_TIMEOUT")]
+    pub timeout: Duration,
+
+    /// How much memory a snippet may use in megabytes, where the service
+    /// allows setting it
+    #[arg(long, default_value_t = 128, env = "EVAL_MEMORY_MB")]
+    pub memory_mb: u64,
+
+    /// How many snippets each person may run per `--rate-window`, or 0 for no
+    /// limit
+    #[arg(long, default_value_t = 5, env = "EVAL_RATE_LIMIT")]
+    pub rate_limit: usize,
+
+    /// The window for `--rate-limit`
+    #[arg(long, default_value = "1m", value_parser = parse_timeout, env = "EVAL_RATE_WINDOW")]
+    pub rate_window: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_timeout(timeout: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(timeout).ok_or_else(|| format!("invalid duration: {timeout}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    // Leave time for compiling and queueing on top of running
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("matrix-eval/", env!("CARGO_PKG_VERSION")))
+        .timeout(config.timeout + Duration::from_secs(60))
+        .build()?;
+    let mut providers = Vec::new();
+    for kind in config.providers {
+        providers.push(match kind {
+            ProviderKind::RustPlayground => AnyProvider::RustPlayground(RustPlayground {
+                http: http.clone(),
+                api_url: config.rust_playground_url.clone(),
+            }),
+            ProviderKind::Piston => {
+                let piston = Piston::new(http.clone(), config.piston_url.clone()).await?;
+                info!("Piston has {} languages", piston.runtimes.len());
+                AnyProvider::Piston(piston)
+            }
+        });
+    }
+
+    let mut bot = Bot::login("matrix-eval", config.account_config).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Evaluator {
+        providers,
+        limits: Limits {
+            timeout: config.timeout,
+            memory: config.memory_mb * 1024 * 1024,
+        },
+        rate_limiter: RateLimiter::new(config.rate_limit, config.rate_window),
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
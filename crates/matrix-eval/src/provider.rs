@@ -0,0 +1,279 @@
+use std::{future::Future, time::Duration};
+
+use anyhow::bail;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::json;
+
+/// What a snippet printed, and how it ended if it didn't succeed.
+#[derive(Debug, Default)]
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    /// Why the snippet failed, like `exit code 1`.
+    pub failure: Option<String>,
+}
+
+/// Caps on what a snippet may use.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub timeout: Duration,
+    /// In bytes.
+    pub memory: u64,
+}
+
+/// A service that runs code in a sandbox.
+pub trait Provider {
+    /// Whether this can run code in `language`, given in lowercase.
+    fn supports(&self, language: &str) -> bool;
+
+    /// The languages this can run.
+    fn languages(&self) -> Vec<String>;
+
+    fn run(
+        &self,
+        language: &str,
+        code: &str,
+        limits: Limits,
+    ) -> impl Future<Output = anyhow::Result<Output>> + Send;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ProviderKind {
+    /// The Rust playground, for Rust
+    RustPlayground,
+    /// A Piston server, for most other languages
+    Piston,
+}
+
+/// One of the supported providers, picked at startup.
+#[derive(Clone)]
+pub enum AnyProvider {
+    RustPlayground(RustPlayground),
+    Piston(Piston),
+}
+
+impl Provider for AnyProvider {
+    fn supports(&self, language: &str) -> bool {
+        match self {
+            Self::RustPlayground(provider) => provider.supports(language),
+            Self::Piston(provider) => provider.supports(language),
+        }
+    }
+
+    fn languages(&self) -> Vec<String> {
+        match self {
+            Self::RustPlayground(provider) => provider.languages(),
+            Self::Piston(provider) => provider.languages(),
+        }
+    }
+
+    async fn run(&self, language: &str, code: &str, limits: Limits) -> anyhow::Result<Output> {
+        match self {
+            Self::RustPlayground(provider) => provider.run(language, code, limits).await,
+            Self::Piston(provider) => provider.run(language, code, limits).await,
+        }
+    }
+}
+
+/// The Rust playground, or anything with the same API. It sets its own time
+/// and memory limits.
+#[derive(Clone)]
+pub struct RustPlayground {
+    pub http: reqwest::Client,
+    pub api_url: String,
+}
+
+#[derive(Deserialize)]
+struct PlaygroundResponse {
+    success: bool,
+    #[serde(default)]
+    stdout: String,
+    #[serde(default)]
+    stderr: String,
+    #[serde(rename = "exitDetail")]
+    exit_detail: Option<String>,
+}
+
+impl Provider for RustPlayground {
+    fn supports(&self, language: &str) -> bool {
+        matches!(language, "rust" | "rs")
+    }
+
+    fn languages(&self) -> Vec<String> {
+        vec!["rust".to_owned()]
+    }
+
+    async fn run(&self, _language: &str, code: &str, _limits: Limits) -> anyhow::Result<Output> {
+        // Let snippets skip `main` for a quick expression
+        let code = if code.contains("fn main") {
+            code.to_owned()
+        } else {
+            format!("fn main() {{\n{code}\n}}")
+        };
+        let response: PlaygroundResponse = self
+            .http
+            .post(format!("{}/execute", self.api_url.trim_end_matches('/')))
+            .json(&json!({
+                "channel": "stable",
+                "mode": "debug",
+                "edition": "2021",
+                "crateType": "bin",
+                "tests": false,
+                "backtrace": false,
+                "code": code,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // Cargo's progress isn't interesting
+        let stderr = response
+            .stderr
+            .lines()
+            .filter(|line| {
+                let line = line.trim_start();
+                !line.starts_with("Compiling playground")
+                    && !line.starts_with("Finished ")
+                    && !line.starts_with("Running `target")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(Output {
+            stdout: response.stdout,
+            stderr,
+            failure: (!response.success)
+                .then(|| response.exit_detail.unwrap_or_else(|| "failed".to_owned())),
+        })
+    }
+}
+
+/// A Piston server, which runs many languages.
+#[derive(Clone)]
+pub struct Piston {
+    pub http: reqwest::Client,
+    pub api_url: String,
+    /// The languages the server has, with their aliases.
+    pub runtimes: Vec<Runtime>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Runtime {
+    pub language: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PistonResponse {
+    compile: Option<PistonStage>,
+    run: Option<PistonStage>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PistonStage {
+    #[serde(default)]
+    stdout: String,
+    #[serde(default)]
+    stderr: String,
+    code: Option<i64>,
+    signal: Option<String>,
+}
+
+impl PistonStage {
+    fn into_output(self) -> Output {
+        let failure = match (self.signal, self.code) {
+            (Some(signal), _) => Some(format!("killed by {signal}, maybe for going over a limit")),
+            (None, Some(0)) => None,
+            (None, Some(code)) => Some(format!("exit code {code}")),
+            (None, None) => Some("failed".to_owned()),
+        };
+        Output {
+            stdout: self.stdout,
+            stderr: self.stderr,
+            failure,
+        }
+    }
+}
+
+impl Piston {
+    /// Connect to a Piston server, finding out which languages it has.
+    pub async fn new(http: reqwest::Client, api_url: String) -> anyhow::Result<Self> {
+        let runtimes = http
+            .get(format!("{}/runtimes", api_url.trim_end_matches('/')))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(Self {
+            http,
+            api_url,
+            runtimes,
+        })
+    }
+
+    fn runtime(&self, language: &str) -> Option<&Runtime> {
+        self.runtimes.iter().find(|runtime| {
+            runtime.language == language || runtime.aliases.iter().any(|alias| alias == language)
+        })
+    }
+}
+
+impl Provider for Piston {
+    fn supports(&self, language: &str) -> bool {
+        self.runtime(language).is_some()
+    }
+
+    fn languages(&self) -> Vec<String> {
+        let mut languages: Vec<String> = self
+            .runtimes
+            .iter()
+            .map(|runtime| runtime.language.clone())
+            .collect();
+        languages.sort();
+        languages.dedup();
+        languages
+    }
+
+    async fn run(&self, language: &str, code: &str, limits: Limits) -> anyhow::Result<Output> {
+        let language = self
+            .runtime(language)
+            .map_or(language, |runtime| runtime.language.as_str());
+        let timeout = limits.timeout.as_millis() as u64;
+        let response: PistonResponse = self
+            .http
+            .post(format!("{}/execute", self.api_url.trim_end_matches('/')))
+            .json(&json!({
+                "language": language,
+                "version": "*",
+                "files": [{ "content": code }],
+                "compile_timeout": timeout,
+                "run_timeout": timeout,
+                "compile_memory_limit": limits.memory,
+                "run_memory_limit": limits.memory,
+            }))
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if let Some(message) = response.message {
+            bail!("Piston: {message}");
+        }
+        // A failed compile has no run
+        if let Some(compile) = response.compile {
+            let output = compile.into_output();
+            if output.failure.is_some() {
+                return Ok(output);
+            }
+        }
+        Ok(response
+            .run
+            .map(PistonStage::into_output)
+            .unwrap_or_default())
+    }
+}
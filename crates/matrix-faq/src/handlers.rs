@@ -0,0 +1,282 @@
+use anyhow::{anyhow, bail};
+use matrix_bot_core::{
+    can_reply, fetch_message, media, origins,
+    permissions::{Permission, Permissions},
+    quoted_args, reply, reply_notice, reply_target, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    media::{MediaFormat, MediaRequestParameters},
+    ruma::events::room::message::{
+        MessageType, OriginalSyncRoomMessageEvent, RoomMessageEventContent,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    matcher::Matcher,
+    table::{Entry, EntryTable, Kind},
+};
+
+/// The most entries a room's FAQ can have.
+const MAX_ENTRIES_PER_ROOM: usize = 200;
+
+/// The largest file `!faq import` reads.
+const MAX_IMPORT_BYTES: usize = 1024 * 1024;
+
+/// Changing a room's FAQ.
+const EDIT: Permission = Permission::moderator("faq");
+
+const HELP: &str = "Usage:
+!faq add \"how do I.*install\" \"See the install guide: …\" to respond to a regular expression
+!faq add --keyword \"install\" \"See the install guide: …\" to respond to a word or phrase
+!faq add --shadow … to only log what a new entry would respond to, to try it out
+!faq list
+!faq promote <number> to make a shadow entry respond
+!faq remove <number>
+!faq export
+!faq import [replace] in reply to an exported file
+Adding, promoting, removing and importing needs moderator power or the faq role.";
+
+#[derive(Clone)]
+pub struct Faq {
+    pub table: EntryTable,
+    pub matcher: Matcher,
+    /// Matches shadow entries, apart from the live ones so they don't share
+    /// cooldowns.
+    pub shadow: Matcher,
+    pub permissions: Permissions,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    faq: Ctx<Faq>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if let Some(args) = strip_command(body, "!faq") {
+        if can_reply(&room).await {
+            let response = match command(args, &event, &room, &faq).await {
+                Ok(Some(response)) => response,
+                Ok(None) => return Ok(()),
+                Err(err) => err.to_string(),
+            };
+            reply_notice(&room, &event, response).await;
+        }
+        return Ok(());
+    }
+
+    let (shadow, entries): (Vec<_>, Vec<_>) = faq
+        .table
+        .list(room.room_id().as_str())?
+        .into_iter()
+        .partition(|(_, entry)| entry.shadow);
+    let room_id = room.room_id().to_owned();
+    let live = faq.matcher.find(&room_id, &entries, body);
+    if let Some(entry) = faq.shadow.find(&room_id, &shadow, body) {
+        info!(
+            pattern = entry.pattern.as_str(),
+            live = live.map(|entry| entry.pattern.as_str()),
+            "Shadow FAQ entry would have responded"
+        );
+    }
+    if let Some(entry) = live {
+        if can_reply(&room).await {
+            debug!(
+                pattern = entry.pattern.as_str(),
+                "Responding to FAQ trigger"
+            );
+            reply(
+                &room,
+                &event,
+                RoomMessageEventContent::notice_markdown(&entry.response),
+            )
+            .await;
+        }
+    }
+    Ok(())
+}
+
+/// Run a `!faq` command, returning the reply to send, if any.
+async fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    faq: &Faq,
+) -> anyhow::Result<Option<String>> {
+    let table = &faq.table;
+    let room_id = room.room_id().as_str();
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+
+    match subcommand {
+        "list" => {
+            let entries = table.list(room_id)?;
+            if entries.is_empty() {
+                return Ok(Some("This room has no FAQ entries.".to_owned()));
+            }
+            let lines: Vec<_> = entries
+                .iter()
+                .map(|(id, entry)| {
+                    format!(
+                        "#{id} {}{} `{}` → {}",
+                        if entry.shadow { "shadow " } else { "" },
+                        entry.kind.as_str(),
+                        entry.pattern,
+                        entry.response
+                    )
+                })
+                .collect();
+            return Ok(Some(lines.join("\n")));
+        }
+        "export" => {
+            let entries: Vec<_> = table
+                .list(room_id)?
+                .into_iter()
+                .map(|(_, entry)| entry)
+                .collect();
+            if entries.is_empty() {
+                return Ok(Some("This room has no FAQ entries.".to_owned()));
+            }
+            let json = serde_json::to_vec_pretty(&entries)?;
+            if let Err(err) =
+                media::send_file(room, "faq.json", &mime::APPLICATION_JSON, json).await
+            {
+                warn!("Failed to send FAQ export: {err:#}");
+                return Ok(Some("Sorry, I couldn't export the FAQ.".to_owned()));
+            }
+            return Ok(None);
+        }
+        "add" | "promote" | "remove" | "delete" | "import" => {}
+        _ => return Ok(Some(HELP.to_owned())),
+    }
+
+    if let Some(denial) = faq.permissions.require(room, &event.sender, EDIT).await? {
+        return Ok(Some(denial));
+    }
+
+    Ok(Some(match subcommand {
+        "add" => {
+            let mut args = quoted_args(rest)?;
+            let mut kind = Kind::Regex;
+            let mut shadow = false;
+            loop {
+                match args.first().map(String::as_str) {
+                    Some("--keyword" | "-k") => kind = Kind::Keyword,
+                    Some("--shadow" | "-s") => shadow = true,
+                    _ => break,
+                }
+                args.remove(0);
+            }
+            let [pattern, response] = <[String; 2]>::try_from(args).map_err(|_| {
+                anyhow!("Quote the trigger and the response, e.g. `!faq add \"how do I.*install\" \"See the install guide\"`.")
+            })?;
+            Matcher::compile(kind, &pattern)?;
+            if table.list(room_id)?.len() >= MAX_ENTRIES_PER_ROOM {
+                bail!("This room already has {MAX_ENTRIES_PER_ROOM} FAQ entries.");
+            }
+            let id = table.add(
+                room_id,
+                &Entry {
+                    kind,
+                    pattern,
+                    response,
+                    shadow,
+                },
+            )?;
+            info!(id, shadow, "Added FAQ entry");
+            if shadow {
+                format!("Added shadow FAQ entry #{id}. It'll only be logged until it's promoted.")
+            } else {
+                format!("Added FAQ entry #{id}.")
+            }
+        }
+        "promote" => {
+            let id = entry_id(rest)?;
+            if table.promote(room_id, id)? {
+                info!(id, "Promoted FAQ entry");
+                format!("FAQ entry #{id} will respond now.")
+            } else {
+                format!("There's no shadow FAQ entry #{id} in this room.")
+            }
+        }
+        "import" => import(rest == "replace", event, room, table).await?,
+        _ => {
+            let id = entry_id(rest)?;
+            if table.remove(room_id, id)? {
+                format!("Removed FAQ entry #{id}.")
+            } else {
+                format!("There's no FAQ entry #{id} in this room.")
+            }
+        }
+    }))
+}
+
+/// The entry numbered in a command, as `!faq list` shows it.
+fn entry_id(arg: &str) -> anyhow::Result<i64> {
+    arg.trim_start_matches('#')
+        .parse()
+        .map_err(|_| anyhow!("Which entry? Give its number from `!faq list`."))
+}
+
+/// Import the entries in the file `event` replies to.
+async fn import(
+    replace: bool,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    table: &EntryTable,
+) -> anyhow::Result<String> {
+    let usage = "Reply to a file from `!faq export` with `!faq import`, or `!faq import replace` to replace this room's FAQ.";
+    let Some(target) = reply_target(event) else {
+        return Ok(usage.to_owned());
+    };
+    let source = match fetch_message(room, target)
+        .await?
+        .map(|target| target.content.msgtype)
+    {
+        Some(MessageType::File(file)) => file.source,
+        _ => return Ok(usage.to_owned()),
+    };
+
+    let data = origins::media(
+        &room.client(),
+        &MediaRequestParameters {
+            source,
+            format: MediaFormat::File,
+        },
+    )
+    .await?;
+    if data.len() > MAX_IMPORT_BYTES {
+        bail!("That file is too big to import.");
+    }
+    let entries: Vec<Entry> = serde_json::from_slice(&data)
+        .map_err(|err| anyhow!("That isn't an exported FAQ: {err}"))?;
+    for entry in &entries {
+        Matcher::compile(entry.kind, &entry.pattern)
+            .map_err(|err| anyhow!("`{}`: {err}", entry.pattern))?;
+    }
+
+    let room_id = room.room_id().as_str();
+    let existing = if replace {
+        0
+    } else {
+        table.list(room_id)?.len()
+    };
+    if existing + entries.len() > MAX_ENTRIES_PER_ROOM {
+        bail!("A room can have at most {MAX_ENTRIES_PER_ROOM} FAQ entries.");
+    }
+    table.import(room_id, &entries, replace)?;
+    info!(count = entries.len(), replace, "Imported FAQ entries");
+    Ok(format!("Imported {} FAQ entries.", entries.len()))
+}
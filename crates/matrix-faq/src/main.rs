@@ -0,0 +1,65 @@
+mod handlers;
+mod matcher;
+mod table;
+
+use std::time::Duration;
+
+use clap::Parser;
+use handlers::{on_room_message, Faq};
+use matcher::Matcher;
+use matrix_bot_core::{
+    permissions::{PermissionConfig, Permissions},
+    AccountConfig, Bot,
+};
+use table::EntryTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// How long an entry stays quiet in a room after responding
+    #[arg(long, default_value = "10m", value_parser = parse_cooldown, env = "FAQ_COOLDOWN")]
+    pub cooldown: Duration,
+
+    #[clap(flatten)]
+    pub permissions: PermissionConfig,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_cooldown(cooldown: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(cooldown).ok_or_else(|| format!("invalid cooldown: {cooldown}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-faq", config.account_config).await?;
+    let table = EntryTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Faq {
+        table,
+        matcher: Matcher::new(config.cooldown),
+        shadow: Matcher::new(config.cooldown),
+        permissions: Permissions::new(bot.client(), config.permissions),
+    });
+    bot.client().add_event_handler(on_room_message);
+    bot.help().add(
+        "!faq <list|add|promote|remove|export|import>",
+        "See or change what the bot answers in this room",
+    );
+
+    bot.run().await
+}
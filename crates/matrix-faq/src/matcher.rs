@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use matrix_sdk::ruma::OwnedRoomId;
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use tracing::warn;
+
+use crate::table::{Entry, Kind};
+
+/// The most memory a compiled trigger may use, so that moderators can't
+/// make the bot do unbounded work for each message.
+const REGEX_SIZE_LIMIT: usize = 256 * 1024;
+
+/// Compiles each room's triggers into one set, caching it between
+/// messages, and keeps track of when each entry last responded.
+#[derive(Clone)]
+pub struct Matcher {
+    /// How long an entry stays quiet in a room after responding.
+    pub cooldown: Duration,
+    compiled: Arc<Mutex<HashMap<OwnedRoomId, Compiled>>>,
+    last_response: Arc<Mutex<HashMap<(OwnedRoomId, i64), Instant>>>,
+}
+
+/// A room's triggers, compiled together so a message is only scanned once.
+struct Compiled {
+    /// The entries the set was compiled from.
+    entries: Vec<(i64, Kind, String)>,
+    set: RegexSet,
+    /// The index in `entries` of each pattern in the set, as invalid ones
+    /// are left out.
+    indices: Vec<usize>,
+}
+
+impl Compiled {
+    fn new(entries: &[(i64, Entry)]) -> anyhow::Result<Self> {
+        let mut patterns = Vec::new();
+        let mut indices = Vec::new();
+        for (index, (_, entry)) in entries.iter().enumerate() {
+            // Checked one at a time, so one bad pattern doesn't break the rest
+            if Matcher::compile(entry.kind, &entry.pattern).is_ok() {
+                patterns.push(pattern(entry.kind, &entry.pattern));
+                indices.push(index);
+            }
+        }
+        let set = RegexSetBuilder::new(patterns)
+            .case_insensitive(true)
+            .size_limit(REGEX_SIZE_LIMIT * indices.len().max(1))
+            .build()?;
+        Ok(Self {
+            entries: entries
+                .iter()
+                .map(|(id, entry)| (*id, entry.kind, entry.pattern.clone()))
+                .collect(),
+            set,
+            indices,
+        })
+    }
+
+    /// Whether the set was compiled from these entries.
+    fn is_for(&self, entries: &[(i64, Entry)]) -> bool {
+        self.entries.len() == entries.len()
+            && self
+                .entries
+                .iter()
+                .zip(entries)
+                .all(|((id, kind, pattern), (entry_id, entry))| {
+                    id == entry_id && *kind == entry.kind && *pattern == entry.pattern
+                })
+    }
+}
+
+/// The regular expression for a trigger.
+fn pattern(kind: Kind, pattern: &str) -> String {
+    match kind {
+        Kind::Regex => pattern.to_owned(),
+        // Not `\b`, which wouldn't match around keywords like `C++`
+        Kind::Keyword => format!(r"(?:^|\W){}(?:\W|$)", regex::escape(pattern.trim())),
+    }
+}
+
+impl Matcher {
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            compiled: Default::default(),
+            last_response: Default::default(),
+        }
+    }
+
+    /// Compile a trigger, returning an error a moderator can act on if it's
+    /// invalid.
+    pub fn compile(kind: Kind, pattern: &str) -> anyhow::Result<Regex> {
+        RegexBuilder::new(&self::pattern(kind, pattern))
+            .case_insensitive(true)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|err| anyhow!("That isn't a valid pattern: {err}"))
+    }
+
+    /// The first of `entries` that matches `body` and isn't cooling down,
+    /// which is then put on cooldown.
+    pub fn find<'a>(
+        &self,
+        room_id: &OwnedRoomId,
+        entries: &'a [(i64, Entry)],
+        body: &str,
+    ) -> Option<&'a Entry> {
+        let mut compiled = self.compiled.lock().unwrap();
+        if !compiled
+            .get(room_id)
+            .is_some_and(|compiled| compiled.is_for(entries))
+        {
+            match Compiled::new(entries) {
+                Ok(room) => {
+                    compiled.insert(room_id.clone(), room);
+                }
+                Err(err) => {
+                    warn!(room = room_id.as_str(), "Failed to compile triggers: {err}");
+                    compiled.remove(room_id);
+                    return None;
+                }
+            }
+        }
+        let room = &compiled[room_id];
+        let mut last_response = self.last_response.lock().unwrap();
+        let now = Instant::now();
+
+        for matched in room.set.matches(body).iter() {
+            let (id, entry) = &entries[room.indices[matched]];
+            let cooldown_key = (room_id.clone(), *id);
+            if last_response
+                .get(&cooldown_key)
+                .is_some_and(|last| now.duration_since(*last) < self.cooldown)
+            {
+                continue;
+            }
+            last_response.insert(cooldown_key, now);
+            return Some(entry);
+        }
+        None
+    }
+}
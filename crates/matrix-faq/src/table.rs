@@ -0,0 +1,151 @@
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE entries (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        pattern TEXT NOT NULL,
+        response TEXT NOT NULL,
+        shadow INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX entries_room ON entries (room_id, id);"];
+
+/// How a trigger is matched against messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// A case-insensitive regular expression found anywhere in the message.
+    Regex,
+    /// A case-insensitive word or phrase.
+    Keyword,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Regex => "regex",
+            Kind::Keyword => "keyword",
+        }
+    }
+}
+
+/// A trigger and its canned response, as stored and as exported.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub kind: Kind,
+    pub pattern: String,
+    pub response: String,
+    /// Only logged when it would have responded, to try it out on real
+    /// messages before it goes live.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shadow: bool,
+}
+
+/// Each room's FAQ entries, persisted in SQLite.
+#[derive(Clone)]
+pub struct EntryTable {
+    store: Store,
+}
+
+impl EntryTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn add(&self, room_id: &str, entry: &Entry) -> anyhow::Result<i64> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO entries (room_id, kind, pattern, response, shadow)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    room_id,
+                    entry.kind.as_str(),
+                    entry.pattern,
+                    entry.response,
+                    entry.shadow
+                ],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    /// Replace all of a room's entries, or add to them if `replace` is
+    /// false.
+    pub fn import(&self, room_id: &str, entries: &[Entry], replace: bool) -> anyhow::Result<()> {
+        self.store.transaction(|transaction| {
+            if replace {
+                transaction.execute("DELETE FROM entries WHERE room_id = ?1", params![room_id])?;
+            }
+            for entry in entries {
+                transaction.execute(
+                    "INSERT INTO entries (room_id, kind, pattern, response, shadow)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        room_id,
+                        entry.kind.as_str(),
+                        entry.pattern,
+                        entry.response,
+                        entry.shadow
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// A room's entries with their IDs, oldest first.
+    pub fn list(&self, room_id: &str) -> anyhow::Result<Vec<(i64, Entry)>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT id, kind, pattern, response, shadow FROM entries WHERE room_id = ?1
+                ORDER BY id",
+            )?;
+            let entries = statement
+                .query_map(params![room_id], |row| {
+                    let kind: String = row.get(1)?;
+                    Ok((
+                        row.get(0)?,
+                        Entry {
+                            kind: if kind == "keyword" {
+                                Kind::Keyword
+                            } else {
+                                Kind::Regex
+                            },
+                            pattern: row.get(2)?,
+                            response: row.get(3)?,
+                            shadow: row.get(4)?,
+                        },
+                    ))
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(entries)
+        })
+    }
+
+    /// Make a shadow entry respond for real. Returns whether there was a
+    /// shadow entry to promote.
+    pub fn promote(&self, room_id: &str, id: i64) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let promoted = conn.execute(
+                "UPDATE entries SET shadow = 0 WHERE room_id = ?1 AND id = ?2 AND shadow",
+                params![room_id, id],
+            )?;
+            Ok(promoted > 0)
+        })
+    }
+
+    /// Returns whether there was an entry to remove.
+    pub fn remove(&self, room_id: &str, id: i64) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM entries WHERE room_id = ?1 AND id = ?2",
+                params![room_id, id],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+}
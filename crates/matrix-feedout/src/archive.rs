@@ -0,0 +1,136 @@
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension, Row};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE items (
+        event_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        msgtype TEXT NOT NULL,
+        body TEXT NOT NULL,
+        formatted_body TEXT,
+        media_uri TEXT,
+        media_mimetype TEXT
+    );
+    CREATE INDEX items_room ON items (room_id, ts);"];
+
+/// A message in a feed.
+#[derive(Debug)]
+pub struct Item {
+    pub event_id: String,
+    pub sender: String,
+    /// Milliseconds since the Unix epoch.
+    pub ts: i64,
+    /// The message's `msgtype`, e.g. `m.text` or `m.image`.
+    pub msgtype: String,
+    /// The text of the message, as last edited.
+    pub body: String,
+    pub formatted_body: Option<String>,
+    /// The `mxc://` URI of an image or file message.
+    pub media_uri: Option<String>,
+    pub media_mimetype: Option<String>,
+}
+
+/// The messages of each feed's room, persisted in SQLite.
+#[derive(Clone)]
+pub struct Archive {
+    store: Store,
+}
+
+impl Archive {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Add a message, unless it's already there.
+    pub fn insert(&self, room_id: &str, item: &Item) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO items
+                (event_id, room_id, sender, ts, msgtype, body, formatted_body, media_uri, media_mimetype)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    item.event_id,
+                    room_id,
+                    item.sender,
+                    item.ts,
+                    item.msgtype,
+                    item.body,
+                    item.formatted_body,
+                    item.media_uri,
+                    item.media_mimetype,
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Apply an edit, if it's by whoever sent the message.
+    pub fn edit(
+        &self,
+        event_id: &str,
+        sender: &str,
+        body: &str,
+        formatted_body: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE items SET body = ?3, formatted_body = ?4 WHERE event_id = ?1 AND sender = ?2",
+                params![event_id, sender, body, formatted_body],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Take a redacted message out of its feed.
+    pub fn remove(&self, event_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute("DELETE FROM items WHERE event_id = ?1", [event_id])?;
+            Ok(())
+        })
+    }
+
+    /// A room's latest messages, newest first.
+    pub fn recent(&self, room_id: &str, limit: usize) -> anyhow::Result<Vec<Item>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT event_id, sender, ts, msgtype, body, formatted_body, media_uri, media_mimetype
+                FROM items WHERE room_id = ?1 ORDER BY ts DESC, rowid DESC LIMIT ?2",
+            )?;
+            let items = statement
+                .query_map(params![room_id, limit], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(items)
+        })
+    }
+
+    /// If a room's messages use some media, what type it is, so that only
+    /// media in a feed can be fetched through it.
+    pub fn media(&self, room_id: &str, uri: &str) -> anyhow::Result<Option<Option<String>>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT media_mimetype FROM items WHERE room_id = ?1
+                    AND (media_uri = ?2 OR instr(formatted_body, ?2) > 0)
+                    ORDER BY media_uri = ?2 DESC LIMIT 1",
+                params![room_id, uri],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Item> {
+    Ok(Item {
+        event_id: row.get(0)?,
+        sender: row.get(1)?,
+        ts: row.get(2)?,
+        msgtype: row.get(3)?,
+        body: row.get(4)?,
+        formatted_body: row.get(5)?,
+        media_uri: row.get(6)?,
+        media_mimetype: row.get(7)?,
+    })
+}
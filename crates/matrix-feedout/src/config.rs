@@ -0,0 +1,58 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, Context};
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use serde::Deserialize;
+
+/// The feeds file, e.g.
+///
+/// ```toml
+/// [feeds.announcements]
+/// room = "!abc:example.org"
+/// title = "Example announcements"
+/// description = "News about Example"
+/// token = "a long random string"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedsFile {
+    pub feeds: HashMap<String, FeedConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedConfig {
+    pub room: OwnedRoomId,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    /// If set, readers must give this as a bearer token or a `token` query
+    /// parameter. Otherwise the feed is public.
+    pub token: Option<String>,
+}
+
+impl FeedsFile {
+    /// Whether any feed is of this room.
+    pub fn has_room(&self, room_id: &RoomId) -> bool {
+        self.feeds.values().any(|feed| feed.room == room_id)
+    }
+}
+
+pub fn load(path: &Path) -> anyhow::Result<FeedsFile> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read feeds file {}", path.display()))?;
+    let file: FeedsFile = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse feeds file {}", path.display()))?;
+    for (name, feed) in &file.feeds {
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("feed name {name} can only have letters, numbers, - and _");
+        }
+        if feed.token.as_ref().is_some_and(|token| token.len() < 16) {
+            bail!("the token for feed {name} should be at least 16 characters");
+        }
+    }
+    Ok(file)
+}
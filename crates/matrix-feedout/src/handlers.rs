@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use matrix_bot_core::media::Attachment;
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::{
+        message::{MessageType, OriginalSyncRoomMessageEvent, Relation},
+        redaction::OriginalSyncRoomRedactionEvent,
+        MediaSource,
+    },
+    Room,
+};
+use tracing::{debug, instrument};
+
+use crate::{
+    archive::{Archive, Item},
+    config::FeedsFile,
+};
+
+#[derive(Clone)]
+pub struct Recorder {
+    pub archive: Archive,
+    pub feeds: Arc<FeedsFile>,
+}
+
+/// Add messages in feed rooms to their feeds.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    recorder: Ctx<Recorder>,
+) -> anyhow::Result<()> {
+    if !recorder.feeds.has_room(room.room_id()) {
+        return Ok(());
+    }
+
+    if let Some(Relation::Replacement(replacement)) = &event.content.relates_to {
+        let new_content = &replacement.new_content.msgtype;
+        recorder.archive.edit(
+            replacement.event_id.as_str(),
+            event.sender.as_str(),
+            new_content.body(),
+            formatted_body(new_content),
+        )?;
+        return Ok(());
+    }
+
+    let msgtype = &event.content.msgtype;
+    let (media_uri, media_mimetype) = match Attachment::from_message(msgtype) {
+        Some(Attachment {
+            source: MediaSource::Plain(uri),
+            mimetype,
+            ..
+        }) => (Some(uri.to_string()), mimetype),
+        // Encrypted media is left out, since it can't be linked to
+        Some(_) => return Ok(()),
+        None if is_text(msgtype) => (None, None),
+        // Verification requests, locations and the like
+        None => return Ok(()),
+    };
+    debug!("Adding message to feed");
+    recorder.archive.insert(
+        room.room_id().as_str(),
+        &Item {
+            event_id: event.event_id.to_string(),
+            sender: event.sender.to_string(),
+            ts: i64::from(event.origin_server_ts.get()),
+            msgtype: msgtype.msgtype().to_owned(),
+            body: msgtype.body().to_owned(),
+            formatted_body: formatted_body(msgtype).map(str::to_owned),
+            media_uri,
+            media_mimetype,
+        },
+    )?;
+    Ok(())
+}
+
+/// Take redacted messages out of their feeds.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_redaction(
+    event: OriginalSyncRoomRedactionEvent,
+    room: Room,
+    recorder: Ctx<Recorder>,
+) -> anyhow::Result<()> {
+    if !recorder.feeds.has_room(room.room_id()) {
+        return Ok(());
+    }
+    let redacts = event.content.redacts.as_ref().or(event.redacts.as_ref());
+    if let Some(redacts) = redacts {
+        debug!(redacts = redacts.as_str(), "Removing message from feed");
+        recorder.archive.remove(redacts.as_str())?;
+    }
+    Ok(())
+}
+
+fn is_text(msgtype: &MessageType) -> bool {
+    matches!(
+        msgtype,
+        MessageType::Text(_) | MessageType::Notice(_) | MessageType::Emote(_)
+    )
+}
+
+fn formatted_body(msgtype: &MessageType) -> Option<&str> {
+    let formatted = match msgtype {
+        MessageType::Text(content) => content.formatted.as_ref(),
+        MessageType::Notice(content) => content.formatted.as_ref(),
+        MessageType::Emote(content) => content.formatted.as_ref(),
+        _ => None,
+    };
+    formatted.map(|formatted| formatted.body.as_str())
+}
@@ -0,0 +1,86 @@
+mod archive;
+mod config;
+mod handlers;
+mod render;
+mod server;
+
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+
+use archive::Archive;
+use clap::Parser;
+use handlers::{on_redaction, on_room_message, Recorder};
+use matrix_bot_core::{
+    http::{HttpConfig, HttpServer},
+    AccountConfig, Bot,
+};
+use server::Server;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The address to serve feeds on
+    #[arg(long, default_value = "0.0.0.0:8090", env = "FEEDOUT_LISTEN")]
+    pub listen: SocketAddr,
+
+    #[clap(flatten)]
+    pub http: HttpConfig,
+
+    /// The URL feeds are reached at from outside, for the links in them,
+    /// e.g. https://feeds.example.org
+    #[arg(long, env = "FEEDOUT_PUBLIC_URL")]
+    pub public_url: String,
+
+    /// The TOML file defining the feeds
+    #[arg(long, env = "FEEDOUT_FEEDS")]
+    pub feeds: PathBuf,
+
+    /// How many of the latest messages each feed shows
+    #[arg(long, default_value_t = 50, env = "FEEDOUT_ITEMS")]
+    pub items: usize,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    // Fail on a bad feeds file before logging in
+    let feeds = Arc::new(config::load(&config.feeds)?);
+
+    let mut bot = Bot::login("matrix-feedout", config.account_config).await?;
+    let archive = Archive::open(&bot).await?;
+
+    // Unlike most bots, record messages from the initial sync too, so ones
+    // sent while the bot was down still make it into the feeds. Messages
+    // that were already recorded are skipped.
+    bot.client().add_event_handler_context(Recorder {
+        archive: archive.clone(),
+        feeds: feeds.clone(),
+    });
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_redaction);
+    bot.initial_sync().await?;
+
+    let server = Arc::new(Server {
+        client: bot.client().clone(),
+        archive,
+        feeds,
+        base_url: config.public_url.trim_end_matches('/').to_owned(),
+        items: config.items,
+    });
+    bot.serve_http(HttpServer::new(config.listen, config.http).merge(server::router(server)))
+        .await?;
+
+    bot.run().await
+}
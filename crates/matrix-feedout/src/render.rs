@@ -0,0 +1,282 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use matrix_bot_core::html::escape;
+use serde_json::json;
+
+use crate::{archive::Item, config::FeedConfig};
+
+/// How much of a message to use as its title.
+const TITLE_LENGTH: usize = 80;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl Format {
+    pub fn parse(file: &str) -> Option<Self> {
+        Some(match file {
+            "rss.xml" => Format::Rss,
+            "atom.xml" => Format::Atom,
+            "feed.json" => Format::Json,
+            _ => return None,
+        })
+    }
+
+    pub fn file(self) -> &'static str {
+        match self {
+            Format::Rss => "rss.xml",
+            Format::Atom => "atom.xml",
+            Format::Json => "feed.json",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Rss => "application/rss+xml; charset=utf-8",
+            Format::Atom => "application/atom+xml; charset=utf-8",
+            Format::Json => "application/feed+json; charset=utf-8",
+        }
+    }
+}
+
+/// Makes the links in a feed, which carry its token so feed readers can
+/// follow them.
+pub struct Links<'a> {
+    /// Where the server can be reached, without a trailing slash.
+    pub base_url: &'a str,
+    pub name: &'a str,
+    pub token: Option<&'a str>,
+}
+
+impl Links<'_> {
+    pub fn feed(&self, format: Format) -> String {
+        format!(
+            "{}/feeds/{}/{}{}",
+            self.base_url,
+            self.name,
+            format.file(),
+            self.query()
+        )
+    }
+
+    /// Where to fetch an `mxc://` URI's media from through this server.
+    pub fn media(&self, uri: &str) -> Option<String> {
+        let (server, media_id) = uri.strip_prefix("mxc://")?.split_once('/')?;
+        let server_valid = server
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-.:[]".contains(c));
+        let media_id_valid = media_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if server.is_empty() || media_id.is_empty() || !server_valid || !media_id_valid {
+            return None;
+        }
+        Some(format!(
+            "{}/feeds/{}/media/{server}/{media_id}{}",
+            self.base_url,
+            self.name,
+            self.query()
+        ))
+    }
+
+    fn query(&self) -> String {
+        match self.token {
+            Some(token) => format!("?token={}", percent_encode(token)),
+            None => String::new(),
+        }
+    }
+}
+
+pub fn render(format: Format, feed: &FeedConfig, items: &[Item], links: &Links<'_>) -> String {
+    match format {
+        Format::Rss => rss(feed, items, links),
+        Format::Atom => atom(feed, items, links),
+        Format::Json => json_feed(feed, items, links),
+    }
+}
+
+fn rss(feed: &FeedConfig, items: &[Item], links: &Links<'_>) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" \
+        xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<channel>\n\
+        <title>{}</title>\n<link>{}</link>\n<description>{}</description>\n\
+        <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+        escape(&feed.title),
+        escape(&room_link(feed)),
+        escape(&feed.description),
+        escape(&links.feed(Format::Rss)),
+    );
+    for item in items {
+        xml += &format!(
+            "<item>\n<title>{}</title>\n<link>{}</link>\n\
+            <guid isPermaLink=\"false\">{}</guid>\n<pubDate>{}</pubDate>\n\
+            <dc:creator>{}</dc:creator>\n<description>{}</description>\n</item>\n",
+            escape(&title(item)),
+            escape(&event_link(feed, item)),
+            escape(&item.event_id),
+            time(item).to_rfc2822(),
+            escape(&item.sender),
+            escape(&content(item, links)),
+        );
+    }
+    xml += "</channel>\n</rss>\n";
+    xml
+}
+
+fn atom(feed: &FeedConfig, items: &[Item], links: &Links<'_>) -> String {
+    let updated = items.first().map(time).unwrap_or_default();
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        <id>{}</id>\n<title>{}</title>\n<subtitle>{}</subtitle>\n<updated>{}</updated>\n\
+        <link rel=\"self\" href=\"{}\"/>\n<link rel=\"alternate\" href=\"{}\"/>\n",
+        escape(&room_link(feed)),
+        escape(&feed.title),
+        escape(&feed.description),
+        rfc3339(updated),
+        escape(&links.feed(Format::Atom)),
+        escape(&room_link(feed)),
+    );
+    for item in items {
+        let link = event_link(feed, item);
+        xml += &format!(
+            "<entry>\n<id>{}</id>\n<title>{}</title>\n<updated>{}</updated>\n\
+            <author><name>{}</name></author>\n<link rel=\"alternate\" href=\"{}\"/>\n\
+            <content type=\"html\">{}</content>\n</entry>\n",
+            escape(&link),
+            escape(&title(item)),
+            rfc3339(time(item)),
+            escape(&item.sender),
+            escape(&link),
+            escape(&content(item, links)),
+        );
+    }
+    xml += "</feed>\n";
+    xml
+}
+
+/// A [JSON Feed](https://www.jsonfeed.org/version/1.1/).
+fn json_feed(feed: &FeedConfig, items: &[Item], links: &Links<'_>) -> String {
+    let items: Vec<_> = items
+        .iter()
+        .map(|item| {
+            let mut entry = json!({
+                "id": item.event_id,
+                "url": event_link(feed, item),
+                "title": title(item),
+                "content_html": content(item, links),
+                "content_text": item.body,
+                "date_published": rfc3339(time(item)),
+                "authors": [{ "name": item.sender }],
+            });
+            let attachment = item
+                .media_uri
+                .as_deref()
+                .and_then(|uri| links.media(uri));
+            if let Some(url) = attachment {
+                entry["attachments"] = json!([{
+                    "url": url,
+                    "mime_type": item.media_mimetype.as_deref().unwrap_or("application/octet-stream"),
+                }]);
+            }
+            entry
+        })
+        .collect();
+    json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": feed.title,
+        "description": feed.description,
+        "home_page_url": room_link(feed),
+        "feed_url": links.feed(Format::Json),
+        "items": items,
+    })
+    .to_string()
+}
+
+/// The first line of a message, shortened.
+fn title(item: &Item) -> String {
+    let line = item
+        .body
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    match line.char_indices().nth(TITLE_LENGTH) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_owned(),
+    }
+}
+
+/// A message as HTML, with its media linked through this server.
+fn content(item: &Item, links: &Links<'_>) -> String {
+    let media = item.media_uri.as_deref().and_then(|uri| links.media(uri));
+    let html = match (&item.formatted_body, media) {
+        (_, Some(url)) if item.msgtype == "m.image" => {
+            format!(
+                "<img src=\"{}\" alt=\"{}\">",
+                escape(&url),
+                escape(&item.body)
+            )
+        }
+        (_, Some(url)) => format!("<a href=\"{}\">{}</a>", escape(&url), escape(&item.body)),
+        (Some(formatted), None) => rewrite_media(formatted, links),
+        (None, None) => escape(&item.body).replace('\n', "<br>"),
+    };
+    if item.msgtype == "m.emote" {
+        return format!("* {} {html}", escape(&item.sender));
+    }
+    html
+}
+
+/// Point `mxc://` URIs in a message's HTML, such as inline images, at this
+/// server.
+fn rewrite_media(html: &str, links: &Links<'_>) -> String {
+    let mut rewritten = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("mxc://") {
+        rewritten += &rest[..start];
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| c == '"' || c == '\'' || c == '<' || c == '>' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let uri = &rest[..end];
+        match links.media(uri) {
+            Some(url) => rewritten += &escape(&url),
+            None => rewritten += uri,
+        }
+        rest = &rest[end..];
+    }
+    rewritten += rest;
+    rewritten
+}
+
+fn room_link(feed: &FeedConfig) -> String {
+    format!("https://matrix.to/#/{}", feed.room)
+}
+
+fn event_link(feed: &FeedConfig, item: &Item) -> String {
+    format!("https://matrix.to/#/{}/{}", feed.room, item.event_id)
+}
+
+fn time(item: &Item) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(item.ts).unwrap_or_default()
+}
+
+fn rfc3339(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect()
+}
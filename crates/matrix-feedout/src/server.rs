@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{
+        header::{
+            AUTHORIZATION, CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use matrix_bot_core::{http::constant_time_eq, media::Attachment};
+use matrix_sdk::{
+    ruma::{events::room::MediaSource, OwnedMxcUri},
+    Client,
+};
+use serde::Deserialize;
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    archive::Archive,
+    config::{FeedConfig, FeedsFile},
+    render::{render, Format, Links},
+};
+
+/// The biggest file served from a feed, since it's held in memory.
+const MAX_MEDIA_SIZE: u64 = 100 * 1024 * 1024;
+
+pub struct Server {
+    pub client: Client,
+    pub archive: Archive,
+    pub feeds: Arc<FeedsFile>,
+    /// Where the server can be reached, without a trailing slash.
+    pub base_url: String,
+    /// How many messages each feed shows.
+    pub items: usize,
+}
+
+pub fn router(server: Arc<Server>) -> Router {
+    Router::new()
+        .route("/feeds/:name/:file", get(feed))
+        .route("/feeds/:name/media/:server/:media_id", get(media))
+        .with_state(server)
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+#[instrument(skip_all, fields(feed = name.as_str()))]
+async fn feed(
+    State(server): State<Arc<Server>>,
+    Path((name, file)): Path<(String, String)>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(feed) = server.feeds.feeds.get(&name) else {
+        return (StatusCode::NOT_FOUND, "no such feed").into_response();
+    };
+    let Some(format) = Format::parse(&file) else {
+        return (StatusCode::NOT_FOUND, "no such format").into_response();
+    };
+    if !authorized(feed, &headers, &query) {
+        debug!("Rejected request with a bad token");
+        return (StatusCode::UNAUTHORIZED, "bad token").into_response();
+    }
+
+    let items = match server.archive.recent(feed.room.as_str(), server.items) {
+        Ok(items) => items,
+        Err(err) => {
+            warn!("Failed to load feed: {err}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "failed to load feed").into_response();
+        }
+    };
+    let links = Links {
+        base_url: &server.base_url,
+        name: &name,
+        token: feed.token.as_deref(),
+    };
+    (
+        [(CONTENT_TYPE, format.content_type())],
+        render(format, feed, &items, &links),
+    )
+        .into_response()
+}
+
+/// Fetch media from a feed's messages, for readers that can't reach the
+/// homeserver's authenticated media.
+#[instrument(skip_all, fields(feed = name.as_str()))]
+async fn media(
+    State(server): State<Arc<Server>>,
+    Path((name, server_name, media_id)): Path<(String, String, String)>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(feed) = server.feeds.feeds.get(&name) else {
+        return (StatusCode::NOT_FOUND, "no such feed").into_response();
+    };
+    if !authorized(feed, &headers, &query) {
+        debug!("Rejected request with a bad token");
+        return (StatusCode::UNAUTHORIZED, "bad token").into_response();
+    }
+
+    // Only serve media from the feed, rather than anything on Matrix
+    let uri = format!("mxc://{server_name}/{media_id}");
+    let mimetype = match server.archive.media(feed.room.as_str(), &uri) {
+        Ok(Some(mimetype)) => mimetype,
+        Ok(None) => return (StatusCode::NOT_FOUND, "no such media").into_response(),
+        Err(err) => {
+            warn!("Failed to look up media: {err}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "failed to look up media").into_response();
+        }
+    };
+    let attachment = Attachment {
+        source: MediaSource::Plain(OwnedMxcUri::from(uri)),
+        filename: media_id,
+        mimetype,
+        size: None,
+    };
+    let data = match attachment.download(&server.client, MAX_MEDIA_SIZE).await {
+        Ok(data) => data,
+        Err(err) => {
+            warn!("Failed to fetch media: {err:#}");
+            return (StatusCode::BAD_GATEWAY, "failed to fetch media").into_response();
+        }
+    };
+    let content_type = attachment.mime(&data).to_string();
+    (
+        [
+            (CONTENT_TYPE, content_type.as_str()),
+            (CACHE_CONTROL, "public, max-age=86400"),
+            // The type comes from whoever sent the message, so don't let it
+            // be used to run scripts
+            (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (CONTENT_SECURITY_POLICY, "sandbox"),
+        ],
+        data,
+    )
+        .into_response()
+}
+
+fn authorized(feed: &FeedConfig, headers: &HeaderMap, query: &TokenQuery) -> bool {
+    let Some(expected) = &feed.token else {
+        return true;
+    };
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query.token.as_deref());
+    token.is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
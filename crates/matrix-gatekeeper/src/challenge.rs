@@ -0,0 +1,57 @@
+use rand::Rng;
+
+/// A question new members must answer before they can talk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    pub question: String,
+    /// Accepted answers, separated by `|`.
+    pub answer: String,
+}
+
+const NUMBERS: [&str; 11] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+];
+
+impl Challenge {
+    /// A simple sum, with the numbers written out so that it takes reading
+    /// rather than pattern matching.
+    pub fn arithmetic(rng: &mut impl Rng) -> Self {
+        let a = rng.gen_range(1..=10);
+        let b = rng.gen_range(1..=10);
+        Self {
+            question: format!("What is {} plus {}?", NUMBERS[a], NUMBERS[b]),
+            answer: (a + b).to_string(),
+        }
+    }
+
+    /// Parse a room's custom challenge, written as `question = answer`.
+    pub fn parse(input: &str) -> Option<Self> {
+        let (question, answer) = input.split_once('=')?;
+        let (question, answer) = (question.trim(), answer.trim());
+        (!question.is_empty() && !answer.is_empty()).then(|| Self {
+            question: question.to_owned(),
+            answer: answer.to_owned(),
+        })
+    }
+
+    pub fn accepts(&self, reply: &str) -> bool {
+        let reply = normalize(reply);
+        self.answer
+            .split('|')
+            .any(|answer| normalize(answer) == reply)
+    }
+}
+
+/// Lowercase `text` and drop punctuation and extra spaces.
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,276 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+use matrix_bot_core::{format_duration, policy::load_rules, send_or_log_error};
+use matrix_sdk::{
+    ruma::{
+        events::room::{
+            message::RoomMessageEventContent, power_levels::RoomPowerLevelsEventContent,
+        },
+        Int, OwnedRoomId, UserId,
+    },
+    Client, Room, RoomState,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    challenge::Challenge,
+    records::{now, Pending, Records},
+};
+
+/// How often to look for challenges that weren't answered in time.
+const EXPIRY_CHECK: Duration = Duration::from_secs(30);
+
+/// How many wrong answers someone may give before failing.
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// The power level new members are held at until they pass.
+const RESTRICTED_POWER_LEVEL: i32 = -1;
+
+/// What to do with people who fail the challenge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnFail {
+    /// Kick them from the room, and report it to the admin room
+    Kick,
+    /// Leave them restricted, and report them to the admin room
+    Report,
+}
+
+#[derive(Clone)]
+pub struct Gatekeeper {
+    pub client: Client,
+    pub records: Records,
+    /// The room commands are taken from and reports are posted to.
+    pub admin_room: OwnedRoomId,
+    /// Policy lists whose bans are applied as people join.
+    pub policy_lists: Vec<OwnedRoomId>,
+    /// How long new members have to answer.
+    pub timeout: Duration,
+    pub on_fail: OnFail,
+}
+
+impl Gatekeeper {
+    pub async fn report(&self, text: impl Into<String>) {
+        let text = text.into();
+        info!("{text}");
+        let Some(admin_room) = self.client.get_room(&self.admin_room) else {
+            return;
+        };
+        send_or_log_error(&admin_room, RoomMessageEventContent::notice_plain(text)).await;
+    }
+
+    /// Ban someone who joined a protected room if a policy list says so, or
+    /// restrict them and send them a challenge.
+    pub async fn on_join(&self, room: &Room, user_id: &UserId) -> anyhow::Result<()> {
+        let room_id = room.room_id().as_str();
+        if self.records.is_admitted(room_id, user_id.as_str())? {
+            return Ok(());
+        }
+
+        let rules = load_rules(&self.client, &self.policy_lists).await;
+        if let Some(rule) = rules.iter().find(|rule| rule.matches_user(user_id)) {
+            match room.ban_user(user_id, Some(rule.reason.as_str())).await {
+                Ok(()) => {
+                    self.report(format!(
+                        "🚫 Banned {user_id} from {room_id} as they joined: {} (from {})",
+                        rule.reason, rule.list
+                    ))
+                    .await
+                }
+                Err(err) => warn!("Failed to ban {user_id}: {err}"),
+            }
+            return Ok(());
+        }
+
+        if self
+            .records
+            .is_allowed_server(user_id.server_name().as_str())?
+        {
+            return Ok(());
+        }
+
+        // Moderators and the like rejoining aren't new members
+        let power_levels = room.power_levels().await?;
+        if power_levels.for_user(user_id) > power_levels.users_default {
+            return Ok(());
+        }
+        // Someone still restricted from before has nothing to give back
+        let previous_level = power_levels
+            .users
+            .get(user_id)
+            .map(|&level| i64::from(level))
+            .filter(|&level| level != i64::from(RESTRICTED_POWER_LEVEL));
+
+        if let Err(err) = room
+            .update_power_levels(vec![(user_id, Int::from(RESTRICTED_POWER_LEVEL))])
+            .await
+        {
+            self.report(format!(
+                "⚠️ I couldn't restrict {user_id} in {room_id}, so they weren't challenged: {err}"
+            ))
+            .await;
+            return Ok(());
+        }
+
+        let challenge = match self.records.room_challenge(room_id)? {
+            Some(challenge) => challenge,
+            None => Challenge::arithmetic(&mut rand::thread_rng()),
+        };
+        self.records.add_pending(
+            room_id,
+            user_id.as_str(),
+            &challenge,
+            now() + self.timeout.as_secs(),
+            previous_level,
+        )?;
+        info!(
+            room = room_id,
+            user = user_id.as_str(),
+            "Challenged new member"
+        );
+
+        let room_name = room_name(room);
+        let text = format!(
+            "Welcome to {room_name}! Before you can talk there, please answer this question by replying here within {}:\n\n{}",
+            format_duration(self.timeout),
+            challenge.question
+        );
+        let dm = match self.client.get_dm_room(user_id) {
+            Some(dm) => Ok(dm),
+            None => self.client.create_dm(user_id).await,
+        };
+        match dm {
+            Ok(dm) => send_or_log_error(&dm, RoomMessageEventContent::notice_plain(text)).await,
+            Err(err) => {
+                self.report(format!(
+                    "⚠️ I couldn't send {user_id} their challenge for {room_id}: {err}"
+                ))
+                .await
+            }
+        }
+        Ok(())
+    }
+
+    /// Check someone's answer to their challenge, returning what to tell
+    /// them.
+    pub async fn answer(&self, pending: &Pending, reply: &str) -> anyhow::Result<String> {
+        let Some(room) = self.joined_room(&pending.room_id) else {
+            self.records
+                .remove_pending(&pending.room_id, &pending.user_id)?;
+            return Ok("I'm no longer in that room.".to_owned());
+        };
+        let room_name = room_name(&room);
+
+        if pending.challenge.accepts(reply) {
+            self.admit(&room, &UserId::parse(&pending.user_id)?).await?;
+            return Ok(format!("Thanks, you can now talk in {room_name}."));
+        }
+
+        let attempts = self
+            .records
+            .add_attempt(&pending.room_id, &pending.user_id)?;
+        if attempts < MAX_ATTEMPTS {
+            return Ok(format!(
+                "That's not right, please try again. You have {} more tries.",
+                MAX_ATTEMPTS - attempts
+            ));
+        }
+        self.fail(pending, "answered wrongly too many times").await;
+        Ok(match self.on_fail {
+            OnFail::Kick => format!("That's not right either, so I've removed you from {room_name}."),
+            OnFail::Report => format!("That's not right either, so I've asked the moderators of {room_name} to take a look."),
+        })
+    }
+
+    /// Lift someone's restriction, giving them back the level they had
+    /// before it, and remember that they passed.
+    pub async fn admit(&self, room: &Room, user_id: &UserId) -> anyhow::Result<()> {
+        let previous_level = self
+            .records
+            .pending(room.room_id().as_str(), user_id.as_str())?
+            .and_then(|pending| pending.previous_level);
+        let mut power_levels = room.power_levels().await?;
+        if power_levels.users.get(user_id) == Some(&Int::from(RESTRICTED_POWER_LEVEL)) {
+            match previous_level {
+                Some(level) => {
+                    power_levels
+                        .users
+                        .insert(user_id.to_owned(), Int::new_saturating(level));
+                }
+                None => {
+                    power_levels.users.remove(user_id);
+                }
+            }
+            room.send_state_event(RoomPowerLevelsEventContent::from(power_levels))
+                .await?;
+        }
+        self.records
+            .admit(room.room_id().as_str(), user_id.as_str())?;
+        info!(
+            room = room.room_id().as_str(),
+            user = user_id.as_str(),
+            "Admitted new member"
+        );
+        Ok(())
+    }
+
+    async fn fail(&self, pending: &Pending, why: &str) {
+        let (room_id, user_id) = (&pending.room_id, &pending.user_id);
+        if let Err(err) = self.records.remove_pending(room_id, user_id) {
+            warn!("Failed to remove challenge: {err}");
+        }
+        let Some(room) = self.joined_room(room_id) else {
+            return;
+        };
+
+        let outcome = match self.on_fail {
+            OnFail::Kick => {
+                let Ok(user) = UserId::parse(user_id) else {
+                    warn!("Invalid user ID {user_id}");
+                    return;
+                };
+                match room
+                    .kick_user(&user, Some("Didn't pass the join challenge"))
+                    .await
+                {
+                    Ok(()) => "so I kicked them".to_owned(),
+                    Err(err) => format!("but I couldn't kick them: {err}"),
+                }
+            }
+            OnFail::Report => "and they're still restricted".to_owned(),
+        };
+        self.report(format!(
+            "🚪 {user_id} {why} in {room_id}, {outcome}. Use `!gatekeeper admit {room_id} {user_id}` to let them in."
+        ))
+        .await;
+    }
+
+    fn joined_room(&self, room_id: &str) -> Option<Room> {
+        let room_id = OwnedRoomId::try_from(room_id).ok()?;
+        self.client
+            .get_room(&room_id)
+            .filter(|room| room.state() == RoomState::Joined)
+    }
+
+    /// Fail people who don't answer in time, forever.
+    pub async fn run_expiry(self) {
+        loop {
+            match self.records.expired() {
+                Ok(expired) => {
+                    for pending in expired {
+                        self.fail(&pending, "didn't answer the join challenge in time")
+                            .await;
+                    }
+                }
+                Err(err) => error!("Failed to check for expired challenges: {err}"),
+            }
+            tokio::time::sleep(EXPIRY_CHECK).await;
+        }
+    }
+}
+
+pub fn room_name(room: &Room) -> String {
+    room.canonical_alias()
+        .map(|alias| alias.to_string())
+        .unwrap_or_else(|| room.room_id().to_string())
+}
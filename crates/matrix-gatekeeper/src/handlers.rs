@@ -0,0 +1,217 @@
+use matrix_bot_core::{
+    can_reply, is_moderator, reply_notice, rooms::resolve_room, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::{
+            member::{MembershipState, OriginalSyncRoomMemberEvent},
+            message::OriginalSyncRoomMessageEvent,
+        },
+        OwnedServerName, UserId,
+    },
+    Room, RoomState,
+};
+use tracing::instrument;
+
+use crate::{challenge::Challenge, gate::Gatekeeper};
+
+const HELP: &str = "Commands, from this room only:
+!gatekeeper protect <room> / !gatekeeper unprotect <room> / !gatekeeper rooms
+!gatekeeper question <room> <question> = <answer>, or `off` for a simple sum
+!gatekeeper allow <server> / !gatekeeper disallow <server> / !gatekeeper servers
+!gatekeeper admit <room> @user:example.org";
+
+/// Challenge people joining protected rooms, and forget challenges for
+/// people who leave.
+#[instrument(skip_all, fields(room = room.room_id().as_str(), user = event.state_key.as_str()))]
+pub async fn on_room_member(
+    event: OriginalSyncRoomMemberEvent,
+    room: Room,
+    gatekeeper: Ctx<Gatekeeper>,
+) -> anyhow::Result<()> {
+    let room_id = room.room_id().as_str();
+    let user_id = &event.state_key;
+    if room.state() != RoomState::Joined
+        || room.client().user_id() == Some(user_id.as_ref())
+        || !gatekeeper.records.is_protected(room_id)?
+    {
+        return Ok(());
+    }
+
+    match event.content.membership {
+        MembershipState::Join => {
+            // Profile changes are joins too, but only to a room they're already in
+            let was_joined = event
+                .unsigned
+                .prev_content
+                .as_ref()
+                .is_some_and(|prev| prev.membership == MembershipState::Join);
+            if !was_joined {
+                gatekeeper.on_join(&room, user_id).await?;
+            }
+        }
+        MembershipState::Leave | MembershipState::Ban => {
+            gatekeeper
+                .records
+                .remove_pending(room_id, user_id.as_str())?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Take commands in the admin room, and answers to challenges in DMs.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    gatekeeper: Ctx<Gatekeeper>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined || room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if room.room_id() == gatekeeper.admin_room {
+        let Some(args) = strip_command(body, "!gatekeeper") else {
+            return Ok(());
+        };
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        let response = if is_moderator(&room, &event.sender).await? {
+            command(args, &gatekeeper).await?
+        } else {
+            "Only moderators of this room can use me.".to_owned()
+        };
+        reply_notice(&room, &event, response).await;
+        return Ok(());
+    }
+
+    if !room.is_direct().await? {
+        return Ok(());
+    }
+    let Some(pending) = gatekeeper.records.pending_for(event.sender.as_str())? else {
+        return Ok(());
+    };
+    if can_reply(&room).await {
+        let response = gatekeeper.answer(&pending, body).await?;
+        reply_notice(&room, &event, response).await;
+    }
+    Ok(())
+}
+
+async fn command(args: &str, gatekeeper: &Gatekeeper) -> anyhow::Result<String> {
+    let client = &gatekeeper.client;
+    let records = &gatekeeper.records;
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+
+    match subcommand {
+        "rooms" => {
+            let rooms = records.rooms()?;
+            if rooms.is_empty() {
+                return Ok("No rooms are protected.".to_owned());
+            }
+            return Ok(rooms
+                .iter()
+                .map(|(room_id, challenge)| match challenge {
+                    Some(challenge) => format!("{room_id}: {}", challenge.question),
+                    None => format!("{room_id}: a simple sum"),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"));
+        }
+        "servers" => {
+            let servers = records.allowed_servers()?;
+            return Ok(if servers.is_empty() {
+                "No servers skip the challenge.".to_owned()
+            } else {
+                format!("These servers skip the challenge: {}", servers.join(", "))
+            });
+        }
+        "allow" | "disallow" => {
+            let Ok(server_name) = OwnedServerName::try_from(rest) else {
+                return Ok(format!(
+                    "Give a server name, e.g. `!gatekeeper {subcommand} example.org`."
+                ));
+            };
+            let allowed = subcommand == "allow";
+            return Ok(
+                match (
+                    records.set_allowed_server(server_name.as_str(), allowed)?,
+                    allowed,
+                ) {
+                    (true, true) => format!("People from {server_name} now skip the challenge."),
+                    (true, false) => format!("People from {server_name} now get challenged."),
+                    (false, true) => format!("{server_name} is already allowed."),
+                    (false, false) => format!("{server_name} wasn't allowed."),
+                },
+            );
+        }
+        "protect" | "unprotect" | "question" | "admit" => {}
+        _ => return Ok(HELP.to_owned()),
+    }
+
+    let (room, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let rest = rest.trim();
+    if room.is_empty() {
+        return Ok(HELP.to_owned());
+    }
+    let room_id = match resolve_room(client, room).await {
+        Ok(room_id) => room_id,
+        Err(_) => return Ok(format!("I couldn't find the room {room}.")),
+    };
+    let joined = client
+        .get_room(&room_id)
+        .filter(|room| room.state() == RoomState::Joined);
+
+    Ok(match subcommand {
+        "protect" => {
+            if joined.is_none() {
+                format!("I need to be in {room} to protect it.")
+            } else if records.set_protected(room_id.as_str(), true)? {
+                format!("New members of {room} will now be challenged.")
+            } else {
+                format!("{room} is already protected.")
+            }
+        }
+        "unprotect" => {
+            if records.set_protected(room_id.as_str(), false)? {
+                format!("New members of {room} won't be challenged any more.")
+            } else {
+                format!("{room} isn't protected.")
+            }
+        }
+        "question" => {
+            let challenge = match rest {
+                "off" => None,
+                _ => match Challenge::parse(rest) {
+                    Some(challenge) => Some(challenge),
+                    None => return Ok(HELP.to_owned()),
+                },
+            };
+            if !records.set_room_challenge(room_id.as_str(), challenge.as_ref())? {
+                format!("{room} isn't protected.")
+            } else if challenge.is_some() {
+                format!("New members of {room} will now be asked that question.")
+            } else {
+                format!("New members of {room} will now be asked a simple sum.")
+            }
+        }
+        "admit" => {
+            let Some(room) = joined else {
+                return Ok(format!("I'm not in {room}."));
+            };
+            let Ok(user_id) = UserId::parse(rest) else {
+                return Ok(HELP.to_owned());
+            };
+            gatekeeper.admit(&room, &user_id).await?;
+            format!("Let {user_id} into {}.", room.room_id())
+        }
+        _ => unreachable!(),
+    })
+}
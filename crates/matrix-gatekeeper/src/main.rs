@@ -0,0 +1,76 @@
+mod challenge;
+mod gate;
+mod handlers;
+mod records;
+
+use std::time::Duration;
+
+use clap::Parser;
+use gate::{Gatekeeper, OnFail};
+use handlers::{on_room_member, on_room_message};
+use matrix_bot_core::{AccountConfig, Bot};
+use matrix_sdk::ruma::OwnedRoomId;
+use records::Records;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The room to take commands from and post reports to
+    #[arg(long, env = "GATEKEEPER_ADMIN_ROOM")]
+    pub admin_room: OwnedRoomId,
+
+    /// Policy lists whose bans to apply as people join, such as the ones the
+    /// moderation bot watches
+    #[arg(long, value_delimiter = ',', env = "GATEKEEPER_POLICY_LISTS")]
+    pub policy_list: Vec<OwnedRoomId>,
+
+    /// How long new members have to answer their challenge
+    #[arg(long, default_value = "10m", value_parser = parse_timeout, env = "GATEKEEPER_TIMEOUT")]
+    pub timeout: Duration,
+
+    /// What to do with people who fail their challenge
+    #[arg(long, value_enum, default_value = "kick", env = "GATEKEEPER_ON_FAIL")]
+    pub on_fail: OnFail,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_timeout(timeout: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(timeout).ok_or_else(|| format!("invalid timeout: {timeout}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-gatekeeper", config.account_config).await?;
+    let records = Records::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let gatekeeper = Gatekeeper {
+        client: bot.client().clone(),
+        records,
+        admin_room: config.admin_room,
+        policy_lists: config.policy_list,
+        timeout: config.timeout,
+        on_fail: config.on_fail,
+    };
+    tokio::spawn(gatekeeper.clone().run_expiry());
+
+    // Now that we've synced, attach handlers for new events.
+    bot.client().add_event_handler_context(gatekeeper);
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_room_member);
+
+    bot.run().await
+}
@@ -0,0 +1,313 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+use crate::challenge::Challenge;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE rooms (
+        room_id TEXT PRIMARY KEY,
+        question TEXT,
+        answer TEXT
+    );
+    CREATE TABLE allowed_servers (
+        server_name TEXT PRIMARY KEY
+    );
+    CREATE TABLE pending (
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        question TEXT NOT NULL,
+        answer TEXT NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        deadline INTEGER NOT NULL,
+        previous_level INTEGER,
+        PRIMARY KEY (room_id, user_id)
+    );
+    CREATE TABLE admitted (
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        PRIMARY KEY (room_id, user_id)
+    );"];
+
+/// A challenge a new member hasn't answered yet.
+#[derive(Debug, Clone)]
+pub struct Pending {
+    pub room_id: String,
+    pub user_id: String,
+    pub challenge: Challenge,
+    pub attempts: u32,
+    pub deadline: u64,
+    /// The power level they had set before being restricted, if any, to
+    /// give back when they pass.
+    pub previous_level: Option<i64>,
+}
+
+/// Protected rooms, allowed servers and outstanding challenges, persisted in
+/// SQLite.
+#[derive(Clone)]
+pub struct Records {
+    store: Store,
+}
+
+impl Records {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn is_protected(&self, room_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM rooms WHERE room_id = ?1)",
+                [room_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    /// Protected rooms, with their custom challenge if they have one.
+    pub fn rooms(&self) -> anyhow::Result<Vec<(String, Option<Challenge>)>> {
+        self.store.with(|conn| {
+            let mut statement =
+                conn.prepare("SELECT room_id, question, answer FROM rooms ORDER BY room_id")?;
+            let rooms = statement
+                .query_map([], |row| {
+                    let question: Option<String> = row.get(1)?;
+                    let answer: Option<String> = row.get(2)?;
+                    Ok((
+                        row.get(0)?,
+                        question
+                            .zip(answer)
+                            .map(|(question, answer)| Challenge { question, answer }),
+                    ))
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(rooms)
+        })
+    }
+
+    /// A protected room's custom challenge, if it has one.
+    pub fn room_challenge(&self, room_id: &str) -> anyhow::Result<Option<Challenge>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT question, answer FROM rooms
+                    WHERE room_id = ?1 AND question IS NOT NULL AND answer IS NOT NULL",
+                [room_id],
+                |row| {
+                    Ok(Challenge {
+                        question: row.get(0)?,
+                        answer: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+
+    pub fn set_protected(&self, room_id: &str, protected: bool) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let changed = if protected {
+                conn.execute(
+                    "INSERT OR IGNORE INTO rooms (room_id) VALUES (?1)",
+                    [room_id],
+                )?
+            } else {
+                conn.execute("DELETE FROM rooms WHERE room_id = ?1", [room_id])?
+            };
+            Ok(changed > 0)
+        })
+    }
+
+    /// Set or clear a protected room's custom challenge, returning whether
+    /// it's protected.
+    pub fn set_room_challenge(
+        &self,
+        room_id: &str,
+        challenge: Option<&Challenge>,
+    ) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let updated = conn.execute(
+                "UPDATE rooms SET question = ?2, answer = ?3 WHERE room_id = ?1",
+                params![
+                    room_id,
+                    challenge.map(|challenge| &challenge.question),
+                    challenge.map(|challenge| &challenge.answer)
+                ],
+            )?;
+            Ok(updated > 0)
+        })
+    }
+
+    pub fn allowed_servers(&self) -> anyhow::Result<Vec<String>> {
+        self.store.with(|conn| {
+            let mut statement =
+                conn.prepare("SELECT server_name FROM allowed_servers ORDER BY server_name")?;
+            let servers = statement
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            Ok(servers)
+        })
+    }
+
+    pub fn is_allowed_server(&self, server_name: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM allowed_servers WHERE server_name = ?1)",
+                [server_name],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    pub fn set_allowed_server(&self, server_name: &str, allowed: bool) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let changed = if allowed {
+                conn.execute(
+                    "INSERT OR IGNORE INTO allowed_servers (server_name) VALUES (?1)",
+                    [server_name],
+                )?
+            } else {
+                conn.execute(
+                    "DELETE FROM allowed_servers WHERE server_name = ?1",
+                    [server_name],
+                )?
+            };
+            Ok(changed > 0)
+        })
+    }
+
+    pub fn is_admitted(&self, room_id: &str, user_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM admitted WHERE room_id = ?1 AND user_id = ?2)",
+                [room_id, user_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    /// Let a user into a room for good, dropping any challenge they had.
+    pub fn admit(&self, room_id: &str, user_id: &str) -> anyhow::Result<()> {
+        self.store.transaction(|transaction| {
+            transaction.execute(
+                "INSERT OR IGNORE INTO admitted (room_id, user_id) VALUES (?1, ?2)",
+                [room_id, user_id],
+            )?;
+            transaction.execute(
+                "DELETE FROM pending WHERE room_id = ?1 AND user_id = ?2",
+                [room_id, user_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn add_pending(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        challenge: &Challenge,
+        deadline: u64,
+        previous_level: Option<i64>,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO pending
+                (room_id, user_id, question, answer, deadline, previous_level)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    room_id,
+                    user_id,
+                    challenge.question,
+                    challenge.answer,
+                    deadline,
+                    previous_level
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// The challenge `user_id` has to answer for `room_id`, if any.
+    pub fn pending(&self, room_id: &str, user_id: &str) -> anyhow::Result<Option<Pending>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT room_id, user_id, question, answer, attempts, deadline, previous_level
+                    FROM pending WHERE room_id = ?1 AND user_id = ?2",
+                [room_id, user_id],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    /// The oldest challenge `user_id` still has to answer.
+    pub fn pending_for(&self, user_id: &str) -> anyhow::Result<Option<Pending>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT room_id, user_id, question, answer, attempts, deadline, previous_level
+                    FROM pending WHERE user_id = ?1 ORDER BY deadline LIMIT 1",
+                [user_id],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    /// Challenges whose deadline has passed.
+    pub fn expired(&self) -> anyhow::Result<Vec<Pending>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT room_id, user_id, question, answer, attempts, deadline, previous_level
+                FROM pending WHERE deadline <= ?1",
+            )?;
+            let pending = statement
+                .query_map([now()], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(pending)
+        })
+    }
+
+    /// Count a wrong answer, returning how many there have been.
+    pub fn add_attempt(&self, room_id: &str, user_id: &str) -> anyhow::Result<u32> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "UPDATE pending SET attempts = attempts + 1 WHERE room_id = ?1 AND user_id = ?2
+                RETURNING attempts",
+                [room_id, user_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    pub fn remove_pending(&self, room_id: &str, user_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM pending WHERE room_id = ?1 AND user_id = ?2",
+                [room_id, user_id],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Pending> {
+    Ok(Pending {
+        room_id: row.get(0)?,
+        user_id: row.get(1)?,
+        challenge: Challenge {
+            question: row.get(2)?,
+            answer: row.get(3)?,
+        },
+        attempts: row.get(4)?,
+        deadline: row.get(5)?,
+        previous_level: row.get(6)?,
+    })
+}
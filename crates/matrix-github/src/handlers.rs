@@ -0,0 +1,142 @@
+use matrix_bot_core::{can_reply, is_moderator, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState,
+};
+use tracing::{info, instrument};
+
+use crate::{
+    render::{Forge, Kind},
+    table::{Subscription, SubscriptionTable},
+};
+
+const HELP: &str = "Usage:
+!github watch <owner/repo> [push] [pr] [issues] [release] [ci]
+!github unwatch <owner/repo>
+!github list
+Use !gitlab for GitLab projects. Watching and unwatching needs moderator rights.";
+
+#[derive(Clone)]
+pub struct Subscriptions {
+    pub table: SubscriptionTable,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    subscriptions: Ctx<Subscriptions>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+    let (forge, args) = if let Some(args) = strip_command(body, "!github") {
+        (Forge::GitHub, args)
+    } else if let Some(args) = strip_command(body, "!gitlab") {
+        (Forge::GitLab, args)
+    } else {
+        return Ok(());
+    };
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let response = command(forge, args, &event, &room, &subscriptions.table).await?;
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn command(
+    forge: Forge,
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    table: &SubscriptionTable,
+) -> anyhow::Result<String> {
+    let mut words = args.split_whitespace();
+    let room_id = room.room_id();
+
+    Ok(match words.next() {
+        Some("list") => {
+            let subscriptions = table.for_room(room_id.as_str())?;
+            if subscriptions.is_empty() {
+                return Ok("This room isn't watching any repositories.".to_owned());
+            }
+            subscriptions
+                .iter()
+                .map(|subscription| {
+                    let kinds = if subscription.kinds.is_empty() {
+                        "everything".to_owned()
+                    } else {
+                        describe_kinds(&subscription.kinds)
+                    };
+                    format!(
+                        "{} {}: {kinds}",
+                        subscription.forge.as_str(),
+                        subscription.repo
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Some(subcommand @ ("watch" | "unwatch")) => {
+            let Some(repo) = words.next().filter(|repo| repo.contains('/')) else {
+                return Ok(format!(
+                    "Which repository? Use `!{} {subcommand} owner/repo`.",
+                    forge.as_str()
+                ));
+            };
+            if !is_moderator(room, &event.sender).await? {
+                return Ok(
+                    "Only moderators can change which repositories this room watches.".to_owned(),
+                );
+            }
+
+            if subcommand == "unwatch" {
+                return Ok(if table.unsubscribe(room_id.as_str(), forge, repo)? {
+                    info!(repo, "Unwatched repository");
+                    format!("No longer watching {repo}.")
+                } else {
+                    format!("This room isn't watching {repo}.")
+                });
+            }
+
+            let mut kinds = Vec::new();
+            for word in words {
+                match word.parse::<Kind>() {
+                    Ok(kind) if !kinds.contains(&kind) => kinds.push(kind),
+                    Ok(_) => {}
+                    Err(_) => {
+                        return Ok(format!(
+                            "I don't know the event kind {word}. Choose from {}.",
+                            describe_kinds(&Kind::ALL)
+                        ))
+                    }
+                }
+            }
+            table.subscribe(&Subscription {
+                room_id: room_id.to_owned(),
+                forge,
+                repo: repo.to_owned(),
+                kinds,
+            })?;
+            info!(repo, "Watching repository");
+            format!(
+                "Watching {repo}. Point its webhook at /{} on this bot.",
+                forge.as_str()
+            )
+        }
+        _ => HELP.to_owned(),
+    })
+}
+
+fn describe_kinds(kinds: &[Kind]) -> String {
+    kinds
+        .iter()
+        .map(|kind| kind.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,77 @@
+mod handlers;
+mod render;
+mod server;
+mod table;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use clap::Parser;
+use handlers::{on_room_message, Subscriptions};
+use matrix_bot_core::{
+    http::{HttpConfig, HttpServer},
+    AccountConfig, Bot,
+};
+use server::Receiver;
+use table::SubscriptionTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The address to listen for webhooks on
+    #[arg(long, default_value = "0.0.0.0:8080", env = "GITHUB_LISTEN")]
+    pub listen: SocketAddr,
+
+    #[clap(flatten)]
+    pub http: HttpConfig,
+
+    /// The secret GitHub webhooks are signed with. GitHub webhooks are
+    /// refused if unset
+    #[arg(long, env = "GITHUB_WEBHOOK_SECRET")]
+    pub github_secret: Option<String>,
+
+    /// The secret token GitLab webhooks are sent with. GitLab webhooks are
+    /// refused if unset
+    #[arg(long, env = "GITLAB_WEBHOOK_TOKEN")]
+    pub gitlab_token: Option<String>,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    if config.github_secret.is_none() && config.gitlab_token.is_none() {
+        anyhow::bail!("set at least one of --github-secret and --gitlab-token");
+    }
+
+    let mut bot = Bot::login("matrix-github", config.account_config).await?;
+    let table = SubscriptionTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let receiver = Arc::new(Receiver {
+        client: bot.client().clone(),
+        table: table.clone(),
+        github_secret: config.github_secret,
+        gitlab_token: config.gitlab_token,
+    });
+    bot.serve_http(HttpServer::new(config.listen, config.http).merge(server::router(receiver)))
+        .await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client()
+        .add_event_handler_context(Subscriptions { table });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,410 @@
+use std::{fmt::Write, str::FromStr};
+
+use anyhow::bail;
+use matrix_bot_core::html::escape;
+use serde_json::Value;
+
+/// The most commits listed for a push.
+const MAX_COMMITS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+}
+
+impl Forge {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Forge::GitHub => "github",
+            Forge::GitLab => "gitlab",
+        }
+    }
+}
+
+impl FromStr for Forge {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "github" => Forge::GitHub,
+            "gitlab" => Forge::GitLab,
+            _ => bail!("unknown forge {s}"),
+        })
+    }
+}
+
+/// The kinds of event a room can choose to receive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Push,
+    PullRequest,
+    Issue,
+    Release,
+    Ci,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 5] = [
+        Kind::Push,
+        Kind::PullRequest,
+        Kind::Issue,
+        Kind::Release,
+        Kind::Ci,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Push => "push",
+            Kind::PullRequest => "pr",
+            Kind::Issue => "issues",
+            Kind::Release => "release",
+            Kind::Ci => "ci",
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "push" | "pushes" => Kind::Push,
+            "pr" | "prs" | "mr" | "mrs" | "pull_request" | "merge_request" => Kind::PullRequest,
+            "issue" | "issues" => Kind::Issue,
+            "release" | "releases" => Kind::Release,
+            "ci" | "pipeline" | "pipelines" | "workflow" | "workflows" => Kind::Ci,
+            _ => bail!("unknown event kind {s}"),
+        })
+    }
+}
+
+/// A formatted notification for an event.
+#[derive(Debug)]
+pub struct Notification {
+    pub repo: String,
+    pub kind: Kind,
+    pub plain: String,
+    pub html: String,
+}
+
+impl Notification {
+    fn new(repo: &str, kind: Kind, plain: String, html: String) -> Self {
+        Self {
+            repo: repo.to_owned(),
+            kind,
+            plain: format!("[{repo}] {plain}"),
+            html: format!("<b>[{}]</b> {html}", escape(repo)),
+        }
+    }
+}
+
+/// Render a GitHub webhook, given the value of its `X-GitHub-Event` header.
+/// Returns `None` for events that aren't worth posting.
+pub fn github(event: &str, payload: &Value) -> Option<Notification> {
+    let repo = str_at(payload, "/repository/full_name")?;
+    let sender = str_at(payload, "/sender/login").unwrap_or("someone");
+    let action = str_at(payload, "/action").unwrap_or_default();
+
+    match event {
+        "push" => {
+            let branch = branch_name(str_at(payload, "/ref")?);
+            let commits: Vec<_> = payload["commits"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|commit| Some((str_at(commit, "/id")?, str_at(commit, "/message")?)))
+                .collect();
+            push(
+                repo,
+                sender,
+                branch,
+                payload["deleted"].as_bool().unwrap_or(false),
+                commits.len(),
+                &commits,
+                str_at(payload, "/compare"),
+            )
+        }
+        "pull_request" => {
+            let pr = &payload["pull_request"];
+            let action = match action {
+                "closed" if pr["merged"].as_bool() == Some(true) => "merged",
+                "opened" | "closed" | "reopened" => action,
+                "ready_for_review" => "marked as ready for review",
+                _ => return None,
+            };
+            item(
+                repo,
+                Kind::PullRequest,
+                sender,
+                action,
+                &format!("PR #{}", pr["number"].as_u64()?),
+                str_at(pr, "/title")?,
+                str_at(pr, "/html_url")?,
+            )
+        }
+        "issues" => {
+            let issue = &payload["issue"];
+            if !matches!(action, "opened" | "closed" | "reopened") {
+                return None;
+            }
+            item(
+                repo,
+                Kind::Issue,
+                sender,
+                action,
+                &format!("issue #{}", issue["number"].as_u64()?),
+                str_at(issue, "/title")?,
+                str_at(issue, "/html_url")?,
+            )
+        }
+        "release" => {
+            if action != "published" {
+                return None;
+            }
+            let release = &payload["release"];
+            let tag = str_at(release, "/tag_name")?;
+            let name = str_at(release, "/name")
+                .filter(|name| !name.is_empty())
+                .unwrap_or(tag);
+            release_published(repo, sender, name, str_at(release, "/html_url")?)
+        }
+        "workflow_run" => {
+            if action != "completed" {
+                return None;
+            }
+            let run = &payload["workflow_run"];
+            ci(
+                repo,
+                str_at(run, "/name")?,
+                str_at(run, "/head_branch").unwrap_or("?"),
+                str_at(run, "/conclusion")?,
+                str_at(run, "/html_url")?,
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Render a GitLab webhook. GitLab names the kind of event in the payload.
+/// Returns `None` for events that aren't worth posting.
+pub fn gitlab(payload: &Value) -> Option<Notification> {
+    let repo = str_at(payload, "/project/path_with_namespace")?;
+    let sender = str_at(payload, "/user_username")
+        .or_else(|| str_at(payload, "/user/username"))
+        .unwrap_or("someone");
+    let attributes = &payload["object_attributes"];
+    let action = str_at(attributes, "/action").unwrap_or_default();
+
+    match str_at(payload, "/object_kind")? {
+        "push" => {
+            let commits: Vec<_> = payload["commits"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|commit| Some((str_at(commit, "/id")?, str_at(commit, "/message")?)))
+                .collect();
+            let total = payload["total_commits_count"]
+                .as_u64()
+                .map_or(commits.len(), |total| total as usize);
+            push(
+                repo,
+                sender,
+                branch_name(str_at(payload, "/ref")?),
+                str_at(payload, "/after").is_some_and(|after| after.bytes().all(|b| b == b'0')),
+                total,
+                &commits,
+                None,
+            )
+        }
+        "merge_request" => {
+            let action = match action {
+                "open" => "opened",
+                "close" => "closed",
+                "reopen" => "reopened",
+                "merge" => "merged",
+                _ => return None,
+            };
+            item(
+                repo,
+                Kind::PullRequest,
+                sender,
+                action,
+                &format!("MR !{}", attributes["iid"].as_u64()?),
+                str_at(attributes, "/title")?,
+                str_at(attributes, "/url")?,
+            )
+        }
+        "issue" => {
+            let action = match action {
+                "open" => "opened",
+                "close" => "closed",
+                "reopen" => "reopened",
+                _ => return None,
+            };
+            item(
+                repo,
+                Kind::Issue,
+                sender,
+                action,
+                &format!("issue #{}", attributes["iid"].as_u64()?),
+                str_at(attributes, "/title")?,
+                str_at(attributes, "/url")?,
+            )
+        }
+        "release" => {
+            if str_at(payload, "/action")? != "create" {
+                return None;
+            }
+            let name = str_at(payload, "/name")
+                .filter(|name| !name.is_empty())
+                .or_else(|| str_at(payload, "/tag"))?;
+            release_published(repo, sender, name, str_at(payload, "/url")?)
+        }
+        "pipeline" => {
+            let status = str_at(attributes, "/status")?;
+            if !matches!(status, "success" | "failed" | "canceled") {
+                return None;
+            }
+            let url = format!(
+                "{}/-/pipelines/{}",
+                str_at(payload, "/project/web_url")?,
+                attributes["id"].as_u64()?
+            );
+            ci(
+                repo,
+                "pipeline",
+                str_at(attributes, "/ref").unwrap_or("?"),
+                status,
+                &url,
+            )
+        }
+        _ => None,
+    }
+}
+
+fn push(
+    repo: &str,
+    sender: &str,
+    branch: &str,
+    deleted: bool,
+    total: usize,
+    commits: &[(&str, &str)],
+    compare: Option<&str>,
+) -> Option<Notification> {
+    if deleted {
+        return Some(Notification::new(
+            repo,
+            Kind::Push,
+            format!("{sender} deleted {branch}"),
+            format!("{} deleted <code>{}</code>", escape(sender), escape(branch)),
+        ));
+    }
+    if total == 0 {
+        return None;
+    }
+
+    let what = if total == 1 {
+        "1 commit".to_owned()
+    } else {
+        format!("{total} commits")
+    };
+    let mut plain = format!("{sender} pushed {what} to {branch}");
+    let mut html = match compare {
+        Some(compare) => format!(
+            "{} pushed <a href=\"{}\">{what}</a> to <code>{}</code>",
+            escape(sender),
+            escape(compare),
+            escape(branch)
+        ),
+        None => format!(
+            "{} pushed {what} to <code>{}</code>",
+            escape(sender),
+            escape(branch)
+        ),
+    };
+
+    html += "<ul>";
+    for (id, message) in commits.iter().take(MAX_COMMITS) {
+        let short_id = &id[..id.len().min(8)];
+        let summary = message.lines().next().unwrap_or_default();
+        let _ = write!(plain, "\n• {short_id} {summary}");
+        let _ = write!(html, "<li><code>{short_id}</code> {}</li>", escape(summary));
+    }
+    if total > MAX_COMMITS {
+        let more = total - MAX_COMMITS;
+        let _ = write!(plain, "\n• …and {more} more");
+        let _ = write!(html, "<li>…and {more} more</li>");
+    }
+    html += "</ul>";
+
+    Some(Notification::new(repo, Kind::Push, plain, html))
+}
+
+fn item(
+    repo: &str,
+    kind: Kind,
+    sender: &str,
+    action: &str,
+    name: &str,
+    title: &str,
+    url: &str,
+) -> Option<Notification> {
+    Some(Notification::new(
+        repo,
+        kind,
+        format!("{sender} {action} {name}: {title} {url}"),
+        format!(
+            "{} {action} <a href=\"{}\">{name}</a>: {}",
+            escape(sender),
+            escape(url),
+            escape(title)
+        ),
+    ))
+}
+
+fn release_published(repo: &str, sender: &str, name: &str, url: &str) -> Option<Notification> {
+    Some(Notification::new(
+        repo,
+        Kind::Release,
+        format!("{sender} released {name} {url}"),
+        format!(
+            "{} released <a href=\"{}\">{}</a>",
+            escape(sender),
+            escape(url),
+            escape(name)
+        ),
+    ))
+}
+
+fn ci(repo: &str, name: &str, branch: &str, conclusion: &str, url: &str) -> Option<Notification> {
+    let icon = match conclusion {
+        "success" => "✅",
+        "failure" | "failed" | "timed_out" => "❌",
+        "cancelled" | "canceled" => "⏹️",
+        // Skipped and neutral runs aren't interesting
+        _ => return None,
+    };
+    Some(Notification::new(
+        repo,
+        Kind::Ci,
+        format!("{icon} {name} on {branch}: {conclusion} {url}"),
+        format!(
+            "{icon} <a href=\"{}\">{}</a> on <code>{}</code>: {conclusion}",
+            escape(url),
+            escape(name),
+            escape(branch)
+        ),
+    ))
+}
+
+fn branch_name(git_ref: &str) -> &str {
+    git_ref
+        .strip_prefix("refs/heads/")
+        .or_else(|| git_ref.strip_prefix("refs/tags/"))
+        .unwrap_or(git_ref)
+}
+
+fn str_at<'a>(value: &'a Value, pointer: &str) -> Option<&'a str> {
+    value.pointer(pointer)?.as_str()
+}
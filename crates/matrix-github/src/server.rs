@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use hmac::{Hmac, Mac};
+use matrix_bot_core::{can_reply, http::constant_time_eq, send_or_log_error};
+use matrix_sdk::{ruma::events::room::message::RoomMessageEventContent, Client, RoomState};
+use sha2::Sha256;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    render::{self, Forge, Notification},
+    table::SubscriptionTable,
+};
+
+pub struct Receiver {
+    pub client: Client,
+    pub table: SubscriptionTable,
+    /// The secret GitHub signs webhooks with. GitHub webhooks are refused
+    /// if this isn't set.
+    pub github_secret: Option<String>,
+    /// The token GitLab sends with webhooks. GitLab webhooks are refused if
+    /// this isn't set.
+    pub gitlab_token: Option<String>,
+}
+
+pub fn router(receiver: Arc<Receiver>) -> Router {
+    Router::new()
+        .route("/github", post(github))
+        .route("/gitlab", post(gitlab))
+        .with_state(receiver)
+}
+
+#[instrument(skip_all)]
+async fn github(
+    State(receiver): State<Arc<Receiver>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, &'static str) {
+    let Some(secret) = &receiver.github_secret else {
+        return (StatusCode::NOT_FOUND, "GitHub webhooks aren't enabled");
+    };
+    let signature = header(&headers, "x-hub-signature-256")
+        .and_then(|value| value.strip_prefix("sha256="))
+        .and_then(|value| hex::decode(value).ok());
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(&body);
+    if !signature.is_some_and(|signature| mac.verify_slice(&signature).is_ok()) {
+        debug!("Rejected GitHub webhook with a bad signature");
+        return (StatusCode::UNAUTHORIZED, "bad signature");
+    }
+
+    let Some(event) = header(&headers, "x-github-event") else {
+        return (StatusCode::BAD_REQUEST, "missing X-GitHub-Event");
+    };
+    let Ok(payload) = serde_json::from_slice(&body) else {
+        return (StatusCode::BAD_REQUEST, "invalid JSON");
+    };
+    debug!(event, "Received GitHub webhook");
+
+    deliver(&receiver, Forge::GitHub, render::github(event, &payload)).await
+}
+
+#[instrument(skip_all)]
+async fn gitlab(
+    State(receiver): State<Arc<Receiver>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, &'static str) {
+    let Some(token) = &receiver.gitlab_token else {
+        return (StatusCode::NOT_FOUND, "GitLab webhooks aren't enabled");
+    };
+    if !header(&headers, "x-gitlab-token")
+        .is_some_and(|sent| constant_time_eq(sent.as_bytes(), token.as_bytes()))
+    {
+        debug!("Rejected GitLab webhook with a bad token");
+        return (StatusCode::UNAUTHORIZED, "bad token");
+    }
+
+    let Ok(payload) = serde_json::from_slice(&body) else {
+        return (StatusCode::BAD_REQUEST, "invalid JSON");
+    };
+    debug!("Received GitLab webhook");
+
+    deliver(&receiver, Forge::GitLab, render::gitlab(&payload)).await
+}
+
+async fn deliver(
+    receiver: &Receiver,
+    forge: Forge,
+    notification: Option<Notification>,
+) -> (StatusCode, &'static str) {
+    let Some(notification) = notification else {
+        return (StatusCode::OK, "ignored");
+    };
+    let subscriptions = match receiver.table.for_repo(forge, &notification.repo) {
+        Ok(subscriptions) => subscriptions,
+        Err(err) => {
+            warn!("Failed to load subscriptions: {err}");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to load subscriptions",
+            );
+        }
+    };
+
+    let content = RoomMessageEventContent::notice_html(notification.plain, notification.html);
+    for subscription in subscriptions {
+        if !subscription.wants(notification.kind) {
+            continue;
+        }
+        let Some(room) = receiver.client.get_room(&subscription.room_id) else {
+            continue;
+        };
+        if room.state() != RoomState::Joined || !can_reply(&room).await {
+            continue;
+        }
+        send_or_log_error(&room, content.clone()).await;
+    }
+    info!(
+        repo = notification.repo.as_str(),
+        kind = notification.kind.as_str(),
+        "Delivered notification"
+    );
+    (StatusCode::ACCEPTED, "accepted")
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok()
+}
@@ -0,0 +1,133 @@
+use matrix_bot_core::{store::Store, Bot};
+use matrix_sdk::ruma::OwnedRoomId;
+use rusqlite::params;
+
+use crate::render::{Forge, Kind};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE subscriptions (
+        room_id TEXT NOT NULL,
+        forge TEXT NOT NULL,
+        repo TEXT NOT NULL,
+        kinds TEXT NOT NULL,
+        PRIMARY KEY (room_id, forge, repo)
+    );
+    CREATE INDEX subscriptions_repo ON subscriptions (forge, repo);"];
+
+/// A room's subscription to a repository's events.
+#[derive(Debug)]
+pub struct Subscription {
+    pub room_id: OwnedRoomId,
+    pub forge: Forge,
+    pub repo: String,
+    /// The kinds of event to post, or all of them if empty.
+    pub kinds: Vec<Kind>,
+}
+
+impl Subscription {
+    pub fn wants(&self, kind: Kind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+}
+
+/// Which rooms want which repositories' events, persisted in SQLite.
+#[derive(Clone)]
+pub struct SubscriptionTable {
+    store: Store,
+}
+
+impl SubscriptionTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Subscribe a room to a repository, replacing the kinds of event it
+    /// wants if it was already subscribed.
+    pub fn subscribe(&self, subscription: &Subscription) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO subscriptions (room_id, forge, repo, kinds) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT DO UPDATE SET kinds = excluded.kinds",
+                params![
+                    subscription.room_id.as_str(),
+                    subscription.forge.as_str(),
+                    subscription.repo.to_lowercase(),
+                    kinds_to_string(&subscription.kinds),
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Remove a subscription, returning whether it existed.
+    pub fn unsubscribe(&self, room_id: &str, forge: Forge, repo: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM subscriptions WHERE room_id = ?1 AND forge = ?2 AND repo = ?3",
+                params![room_id, forge.as_str(), repo.to_lowercase()],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+
+    pub fn for_room(&self, room_id: &str) -> anyhow::Result<Vec<Subscription>> {
+        self.query(
+            "SELECT room_id, forge, repo, kinds FROM subscriptions WHERE room_id = ?1
+            ORDER BY forge, repo",
+            [room_id],
+        )
+    }
+
+    pub fn for_repo(&self, forge: Forge, repo: &str) -> anyhow::Result<Vec<Subscription>> {
+        self.query(
+            "SELECT room_id, forge, repo, kinds FROM subscriptions WHERE forge = ?1 AND repo = ?2",
+            [forge.as_str(), &repo.to_lowercase()],
+        )
+    }
+
+    fn query<P: rusqlite::Params>(
+        &self,
+        sql: &str,
+        params: P,
+    ) -> anyhow::Result<Vec<Subscription>> {
+        let rows = self.store.with(|conn| {
+            let mut statement = conn.prepare(sql)?;
+            let rows = statement
+                .query_map(params, |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })?;
+
+        // Rows that no longer parse are skipped rather than failing every lookup
+        Ok(rows
+            .into_iter()
+            .filter_map(|(room_id, forge, repo, kinds)| {
+                Some(Subscription {
+                    room_id: room_id.try_into().ok()?,
+                    forge: forge.parse().ok()?,
+                    repo,
+                    kinds: kinds
+                        .split(',')
+                        .filter_map(|kind| kind.parse().ok())
+                        .collect(),
+                })
+            })
+            .collect())
+    }
+}
+
+fn kinds_to_string(kinds: &[Kind]) -> String {
+    kinds
+        .iter()
+        .map(|kind| kind.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,334 @@
+use std::time::Duration;
+
+use matrix_bot_core::{
+    can_reply, format_duration, is_moderator, parse_duration, reply_notice, rooms::resolve_room,
+    send_or_log_error, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::{
+            member::MembershipState,
+            message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+        },
+        OwnedRoomId, UserId,
+    },
+    Client, Room, RoomState,
+};
+use tracing::{info, instrument, warn};
+
+use crate::table::{now, Token, TokenTable, Unusable, TOKEN_LENGTH};
+
+/// The most uses a single token may have.
+const MAX_USES: u32 = 1000;
+
+/// The longest a token can last, other than forever.
+const MAX_EXPIRY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// How many entries `!invite-token log` shows.
+const LOG_COUNT: usize = 20;
+
+const HELP: &str = "Usage:
+!invite-token create <room> [uses] [expiry] to create a token, e.g. `!invite-token create #lounge:example.org 5 2d`
+!invite-token list <room> to see the tokens that still work
+!invite-token revoke <id> to stop a token working
+!invite-token log <room> to see who created, used and revoked tokens
+All of these are for the room's moderators. Anyone can DM me a token to be invited.";
+
+const DM_HELP: &str = "Send me an invite token to be invited to its room.";
+
+#[derive(Clone)]
+pub struct Invites {
+    pub table: TokenTable,
+    /// How long tokens last when no expiry is given.
+    pub default_expiry: Option<Duration>,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    invites: Ctx<Invites>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if let Some(args) = strip_command(body, "!invite-token") {
+        if can_reply(&room).await {
+            let response = command(args, &event, &room, &invites).await?;
+            reply_notice(&room, &event, response).await;
+        }
+        return Ok(());
+    }
+
+    // Tokens are only taken in DMs, so they aren't shared with a room
+    if !room.is_direct().await? || !can_reply(&room).await {
+        return Ok(());
+    }
+    let token = body.trim();
+    let response = if token.len() == TOKEN_LENGTH && token.chars().all(char::is_alphanumeric) {
+        redeem(token, &event.sender, &room.client(), &invites.table).await?
+    } else {
+        DM_HELP.to_owned()
+    };
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn redeem(
+    token: &str,
+    user_id: &UserId,
+    client: &Client,
+    table: &TokenTable,
+) -> anyhow::Result<String> {
+    const UNKNOWN: &str = "That isn't an invite token I know of.";
+
+    let Some(token) = table.find(token)? else {
+        return Ok(UNKNOWN.to_owned());
+    };
+    if let Err(unusable) = token.check(now()) {
+        return Ok(match unusable {
+            Unusable::Revoked => "That token has been revoked.",
+            Unusable::Expired => "That token has expired.",
+            Unusable::UsedUp => "That token has been used up.",
+        }
+        .to_owned());
+    }
+    let Some(target) = OwnedRoomId::try_from(token.room_id.as_str())
+        .ok()
+        .and_then(|room_id| client.get_room(&room_id))
+        .filter(|target| target.state() == RoomState::Joined)
+    else {
+        return Ok("I'm no longer in that token's room, so I can't invite you.".to_owned());
+    };
+
+    let name = room_name(&target);
+    if let Some(member) = target.get_member_no_sync(user_id).await? {
+        match member.membership() {
+            MembershipState::Join => return Ok(format!("You're already in {name}.")),
+            MembershipState::Invite => {
+                return Ok(format!("You've already been invited to {name}."))
+            }
+            MembershipState::Ban => return Ok(format!("You're banned from {name}.")),
+            _ => {}
+        }
+    }
+
+    // Claim a use first, so that a token can't be used more times than it
+    // allows by redeeming it twice at once
+    if !table.claim(token.id)? {
+        return Ok("That token can't be used any more.".to_owned());
+    }
+    if let Err(err) = target.invite_user_by_id(user_id).await {
+        warn!(token = token.id, "Failed to invite {user_id}: {err}");
+        table.release(token.id)?;
+        return Ok(format!(
+            "I couldn't invite you to {name}, please try again later."
+        ));
+    }
+    table.redeemed(&token, user_id.as_str())?;
+    info!(
+        token = token.id,
+        room = token.room_id.as_str(),
+        "Invited {user_id} with a token"
+    );
+    Ok(format!("I've invited you to {name}."))
+}
+
+async fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    invites: &Invites,
+) -> anyhow::Result<String> {
+    let client = room.client();
+    let table = &invites.table;
+    let mut words = args.split_whitespace();
+    let subcommand = words.next().unwrap_or_default();
+
+    if subcommand == "revoke" {
+        let Some(token) = words
+            .next()
+            .and_then(|id| id.trim_start_matches('#').parse().ok())
+            .and_then(|id| table.get(id).transpose())
+            .transpose()?
+        else {
+            return Ok("There's no token with that ID.".to_owned());
+        };
+        let Some(target) = moderated_room(&client, &token.room_id, &event.sender).await? else {
+            return Ok("Only the room's moderators can revoke its tokens.".to_owned());
+        };
+        if token.check(now()).is_err() {
+            return Ok(format!("Token #{} already doesn't work.", token.id));
+        }
+        table.revoke(&token, event.sender.as_str())?;
+        info!(token = token.id, "Revoked token");
+        return Ok(format!(
+            "Revoked token #{} for {}.",
+            token.id,
+            room_name(&target)
+        ));
+    }
+
+    if !matches!(subcommand, "create" | "list" | "log") {
+        return Ok(HELP.to_owned());
+    }
+    let Some(target) = words.next() else {
+        return Ok(HELP.to_owned());
+    };
+    let target_id = if target == "here" {
+        room.room_id().to_owned()
+    } else {
+        match resolve_room(&client, target).await {
+            Ok(room_id) => room_id,
+            Err(_) => return Ok(format!("I couldn't find the room {target}.")),
+        }
+    };
+    let Some(target) = moderated_room(&client, target_id.as_str(), &event.sender).await? else {
+        return Ok("Only moderators of a room I'm in can manage its tokens.".to_owned());
+    };
+    let name = room_name(&target);
+
+    Ok(match subcommand {
+        "create" => {
+            let mut uses = 1;
+            let mut expiry = invites.default_expiry;
+            for word in words {
+                if let Ok(n) = word.parse::<u32>() {
+                    if !(1..=MAX_USES).contains(&n) {
+                        return Ok(format!("A token can have from 1 to {MAX_USES} uses."));
+                    }
+                    uses = n;
+                } else if word == "never" {
+                    expiry = None;
+                } else if let Some(duration) = parse_duration(word) {
+                    expiry = Some(duration);
+                } else {
+                    return Ok(HELP.to_owned());
+                }
+            }
+            if expiry.is_some_and(|expiry| expiry > MAX_EXPIRY) {
+                return Ok("A token can last up to a year, or `never` expire.".to_owned());
+            }
+            let bot_id = client.user_id().expect("logged in");
+            if !target.can_user_invite(bot_id).await? {
+                return Ok(format!("I'm not allowed to invite people to {name}."));
+            }
+
+            let token = table.create(
+                target.room_id().as_str(),
+                event.sender.as_str(),
+                uses,
+                expiry.map(|expiry| now() + expiry.as_secs()),
+            )?;
+            info!(
+                token = token.id,
+                room = token.room_id.as_str(),
+                "Created token"
+            );
+            let message = format!(
+                "Invite token #{} for {name}: `{}`\nDM it to me to be invited. {}",
+                token.id,
+                token.token,
+                describe(&token),
+            );
+
+            // Don't post the token itself where others could use it
+            if room.is_direct().await? {
+                message
+            } else {
+                send_dm(&client, &event.sender, &message).await?;
+                format!("I've sent you token #{} for {name} in a DM.", token.id)
+            }
+        }
+        "list" => {
+            let tokens = table.usable(target.room_id().as_str())?;
+            if tokens.is_empty() {
+                format!("There are no working tokens for {name}.")
+            } else {
+                tokens
+                    .iter()
+                    .map(|token| {
+                        format!("#{} by {}: {}", token.id, token.created_by, describe(token))
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        "log" => {
+            let entries = table.audit_log(target.room_id().as_str(), LOG_COUNT)?;
+            if entries.is_empty() {
+                format!("Nothing has happened to tokens for {name} yet.")
+            } else {
+                let now = now();
+                entries
+                    .iter()
+                    .map(|entry| {
+                        format!(
+                            "{} ago: token #{} {} by {}",
+                            format_duration(Duration::from_secs(now.saturating_sub(entry.at))),
+                            entry.token_id,
+                            entry.action,
+                            entry.user_id,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        _ => unreachable!(),
+    })
+}
+
+/// How many uses a token has left and how long it lasts.
+fn describe(token: &Token) -> String {
+    let uses = match token.uses_left {
+        1 => "1 use".to_owned(),
+        n => format!("{n} uses"),
+    };
+    match token.expires_at {
+        Some(expires_at) => format!(
+            "{uses} left, expires in {}.",
+            format_duration(Duration::from_secs(expires_at.saturating_sub(now())))
+        ),
+        None => format!("{uses} left, never expires."),
+    }
+}
+
+/// The room `room_id`, if the bot is in it and `user_id` moderates it.
+async fn moderated_room(
+    client: &Client,
+    room_id: &str,
+    user_id: &UserId,
+) -> anyhow::Result<Option<Room>> {
+    let Some(room) = OwnedRoomId::try_from(room_id)
+        .ok()
+        .and_then(|room_id| client.get_room(&room_id))
+        .filter(|room| room.state() == RoomState::Joined)
+    else {
+        return Ok(None);
+    };
+    Ok(is_moderator(&room, user_id).await?.then_some(room))
+}
+
+fn room_name(room: &Room) -> String {
+    room.canonical_alias()
+        .map(|alias| alias.to_string())
+        .unwrap_or_else(|| room.room_id().to_string())
+}
+
+async fn send_dm(client: &Client, user_id: &UserId, text: &str) -> anyhow::Result<()> {
+    let room = match client.get_dm_room(user_id) {
+        Some(room) => room,
+        None => client.create_dm(user_id).await?,
+    };
+    send_or_log_error(&room, RoomMessageEventContent::text_markdown(text)).await;
+    Ok(())
+}
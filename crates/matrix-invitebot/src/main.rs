@@ -0,0 +1,56 @@
+mod handlers;
+mod table;
+
+use std::time::Duration;
+
+use clap::Parser;
+use handlers::{on_room_message, Invites};
+use matrix_bot_core::{AccountConfig, Bot};
+use table::TokenTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// How long tokens last when no expiry is given, or `never`
+    #[arg(long, default_value = "7d", value_parser = parse_expiry, env = "INVITEBOT_DEFAULT_EXPIRY")]
+    pub default_expiry: Option<Duration>,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_expiry(expiry: &str) -> Result<Option<Duration>, String> {
+    if expiry == "never" {
+        return Ok(None);
+    }
+    matrix_bot_core::parse_duration(expiry)
+        .map(Some)
+        .ok_or_else(|| format!("invalid expiry: {expiry}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-invitebot", config.account_config).await?;
+    let table = TokenTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Invites {
+        table,
+        default_expiry: config.default_expiry,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,254 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE tokens (
+        id INTEGER PRIMARY KEY,
+        token TEXT NOT NULL UNIQUE,
+        room_id TEXT NOT NULL,
+        created_by TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        uses_left INTEGER NOT NULL,
+        expires_at INTEGER,
+        revoked INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE audit (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        token_id INTEGER NOT NULL,
+        action TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        at INTEGER NOT NULL
+    );
+    CREATE INDEX audit_room ON audit (room_id, at);"];
+
+/// How many characters a token has.
+pub const TOKEN_LENGTH: usize = 24;
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub id: i64,
+    pub token: String,
+    pub room_id: String,
+    pub created_by: String,
+    pub uses_left: u32,
+    /// Unix time after which the token stops working, if it ever does.
+    pub expires_at: Option<u64>,
+    pub revoked: bool,
+}
+
+/// Why a token can't be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unusable {
+    Revoked,
+    Expired,
+    UsedUp,
+}
+
+impl Token {
+    pub fn check(&self, now: u64) -> Result<(), Unusable> {
+        if self.revoked {
+            Err(Unusable::Revoked)
+        } else if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            Err(Unusable::Expired)
+        } else if self.uses_left == 0 {
+            Err(Unusable::UsedUp)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditEntry {
+    pub at: u64,
+    pub token_id: i64,
+    /// `created`, `redeemed` or `revoked`.
+    pub action: String,
+    /// Who created or revoked the token, or who was invited with it.
+    pub user_id: String,
+}
+
+/// Invite tokens and the audit log of what happened to them, persisted in
+/// SQLite.
+#[derive(Clone)]
+pub struct TokenTable {
+    store: Store,
+}
+
+impl TokenTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Create a token for `room_id`, returning it.
+    pub fn create(
+        &self,
+        room_id: &str,
+        created_by: &str,
+        uses: u32,
+        expires_at: Option<u64>,
+    ) -> anyhow::Result<Token> {
+        let token: String = OsRng
+            .sample_iter(Alphanumeric)
+            .take(TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+
+        self.store.transaction(|transaction| {
+            transaction.execute(
+                "INSERT INTO tokens (token, room_id, created_by, created_at, uses_left, expires_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![token, room_id, created_by, now(), uses, expires_at],
+            )?;
+            let id = transaction.last_insert_rowid();
+            audit(transaction, room_id, id, "created", created_by)?;
+
+            Ok(Token {
+                id,
+                token,
+                room_id: room_id.to_owned(),
+                created_by: created_by.to_owned(),
+                uses_left: uses,
+                expires_at,
+                revoked: false,
+            })
+        })
+    }
+
+    pub fn get(&self, id: i64) -> anyhow::Result<Option<Token>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT id, token, room_id, created_by, uses_left, expires_at, revoked
+                    FROM tokens WHERE id = ?1",
+                [id],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    pub fn find(&self, token: &str) -> anyhow::Result<Option<Token>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT id, token, room_id, created_by, uses_left, expires_at, revoked
+                    FROM tokens WHERE token = ?1",
+                [token],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    /// Tokens for `room_id` that can still be used.
+    pub fn usable(&self, room_id: &str) -> anyhow::Result<Vec<Token>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT id, token, room_id, created_by, uses_left, expires_at, revoked
+                FROM tokens
+                WHERE room_id = ?1 AND revoked = 0 AND uses_left > 0
+                    AND (expires_at IS NULL OR expires_at > ?2)
+                ORDER BY id",
+            )?;
+            let tokens = statement
+                .query_map(params![room_id, now()], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(tokens)
+        })
+    }
+
+    /// Use up one of a token's uses, if it's still usable.
+    pub fn claim(&self, id: i64) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let claimed = conn.execute(
+                "UPDATE tokens SET uses_left = uses_left - 1
+                WHERE id = ?1 AND revoked = 0 AND uses_left > 0
+                    AND (expires_at IS NULL OR expires_at > ?2)",
+                params![id, now()],
+            )?;
+            Ok(claimed > 0)
+        })
+    }
+
+    /// Give back a use claimed for an invite that failed.
+    pub fn release(&self, id: i64) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE tokens SET uses_left = uses_left + 1 WHERE id = ?1",
+                [id],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn redeemed(&self, token: &Token, user_id: &str) -> anyhow::Result<()> {
+        self.store
+            .with(|conn| audit(conn, &token.room_id, token.id, "redeemed", user_id))
+    }
+
+    pub fn revoke(&self, token: &Token, revoked_by: &str) -> anyhow::Result<()> {
+        self.store.transaction(|transaction| {
+            transaction.execute("UPDATE tokens SET revoked = 1 WHERE id = ?1", [token.id])?;
+            audit(transaction, &token.room_id, token.id, "revoked", revoked_by)?;
+            Ok(())
+        })
+    }
+
+    /// The latest `limit` audit log entries for `room_id`, newest first.
+    pub fn audit_log(&self, room_id: &str, limit: usize) -> anyhow::Result<Vec<AuditEntry>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT at, token_id, action, user_id FROM audit
+                WHERE room_id = ?1 ORDER BY at DESC, id DESC LIMIT ?2",
+            )?;
+            let entries = statement
+                .query_map(params![room_id, limit], |row| {
+                    Ok(AuditEntry {
+                        at: row.get(0)?,
+                        token_id: row.get(1)?,
+                        action: row.get(2)?,
+                        user_id: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(entries)
+        })
+    }
+}
+
+fn audit(
+    conn: &Connection,
+    room_id: &str,
+    token_id: i64,
+    action: &str,
+    user_id: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO audit (room_id, token_id, action, user_id, at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![room_id, token_id, action, user_id, now()],
+    )?;
+    Ok(())
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Token> {
+    Ok(Token {
+        id: row.get(0)?,
+        token: row.get(1)?,
+        room_id: row.get(2)?,
+        created_by: row.get(3)?,
+        uses_left: row.get(4)?,
+        expires_at: row.get(5)?,
+        revoked: row.get(6)?,
+    })
+}
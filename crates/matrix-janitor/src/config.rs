@@ -0,0 +1,97 @@
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use anyhow::{bail, Context};
+use matrix_bot_core::parse_duration;
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use serde::Deserialize;
+
+/// The retention policies file, e.g.
+///
+/// ```toml
+/// # Redact everyone's messages after a month
+/// [[rooms]]
+/// room = "!abc:example.org"
+/// max_age = "30d"
+/// everyone = true
+///
+/// # Only clean up the bot's own media after a week
+/// [[rooms]]
+/// room = "!def:example.org"
+/// media_max_age = "7d"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoliciesFile {
+    pub rooms: Vec<Policy>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawPolicy")]
+pub struct Policy {
+    pub room: OwnedRoomId,
+    /// How long messages are kept for.
+    pub max_age: Option<Duration>,
+    /// Whether `max_age` applies to everyone's messages, which needs the
+    /// bot to be a moderator, or just the bot's own.
+    pub everyone: bool,
+    /// How long the bot's own media posts are kept for.
+    pub media_max_age: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPolicy {
+    room: OwnedRoomId,
+    max_age: Option<String>,
+    #[serde(default)]
+    everyone: bool,
+    media_max_age: Option<String>,
+}
+
+impl TryFrom<RawPolicy> for Policy {
+    type Error = String;
+
+    fn try_from(raw: RawPolicy) -> Result<Self, Self::Error> {
+        if raw.max_age.is_none() && raw.media_max_age.is_none() {
+            return Err("expected at least one of `max_age` or `media_max_age`".to_owned());
+        }
+        if raw.everyone && raw.max_age.is_none() {
+            return Err("`everyone` only applies along with `max_age`".to_owned());
+        }
+        let duration = |text: Option<String>| {
+            text.map(|text| {
+                parse_duration(&text).ok_or_else(|| format!("invalid duration: {text}"))
+            })
+            .transpose()
+        };
+        Ok(Policy {
+            room: raw.room,
+            max_age: duration(raw.max_age)?,
+            everyone: raw.everyone,
+            media_max_age: duration(raw.media_max_age)?,
+        })
+    }
+}
+
+impl PoliciesFile {
+    pub fn get(&self, room_id: &RoomId) -> Option<&Policy> {
+        self.rooms.iter().find(|policy| policy.room == room_id)
+    }
+}
+
+pub fn load(path: &Path) -> anyhow::Result<PoliciesFile> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read policies file {}", path.display()))?;
+    let file: PoliciesFile = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse policies file {}", path.display()))?;
+    if file.rooms.is_empty() {
+        bail!("no rooms in {}", path.display());
+    }
+    let mut seen = HashSet::new();
+    for policy in &file.rooms {
+        if !seen.insert(&policy.room) {
+            bail!("{} has more than one policy", policy.room);
+        }
+    }
+    Ok(file)
+}
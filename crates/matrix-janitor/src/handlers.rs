@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::{
+        message::{MessageType, OriginalSyncRoomMessageEvent},
+        redaction::OriginalSyncRoomRedactionEvent,
+    },
+    Room,
+};
+use tracing::{debug, instrument};
+
+use crate::{config::PoliciesFile, tracked::Tracked};
+
+#[derive(Clone)]
+pub struct Recorder {
+    pub tracked: Tracked,
+    pub policies: Arc<PoliciesFile>,
+}
+
+/// Keep track of messages in rooms with a retention policy.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    recorder: Ctx<Recorder>,
+) -> anyhow::Result<()> {
+    if recorder.policies.get(room.room_id()).is_none() {
+        return Ok(());
+    }
+    debug!("Recording message");
+    recorder.tracked.record(
+        room.room_id().as_str(),
+        event.event_id.as_str(),
+        event.sender.as_str(),
+        event.origin_server_ts.get().into(),
+        is_media(&event.content.msgtype),
+    )
+}
+
+/// Stop keeping track of messages redacted by anyone.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_redaction(
+    event: OriginalSyncRoomRedactionEvent,
+    room: Room,
+    recorder: Ctx<Recorder>,
+) -> anyhow::Result<()> {
+    if recorder.policies.get(room.room_id()).is_none() {
+        return Ok(());
+    }
+    let redacts = event.content.redacts.as_ref().or(event.redacts.as_ref());
+    if let Some(redacts) = redacts {
+        recorder.tracked.forget(redacts.as_str())?;
+    }
+    Ok(())
+}
+
+pub fn is_media(msgtype: &MessageType) -> bool {
+    matches!(
+        msgtype,
+        MessageType::Image(_)
+            | MessageType::File(_)
+            | MessageType::Video(_)
+            | MessageType::Audio(_)
+    )
+}
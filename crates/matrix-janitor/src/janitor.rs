@@ -0,0 +1,194 @@
+use std::{sync::Arc, time::Duration};
+
+use matrix_bot_core::{can_reply, is_moderator, send_or_log_error};
+use matrix_sdk::{
+    room::MessagesOptions,
+    ruma::{
+        events::{
+            room::message::RoomMessageEventContent, AnySyncMessageLikeEvent, AnySyncTimelineEvent,
+            SyncMessageLikeEvent,
+        },
+        EventId, OwnedRoomId,
+    },
+    Client, Room, RoomState,
+};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    config::{PoliciesFile, Policy},
+    handlers::is_media,
+    tracked::{Cutoffs, Tracked},
+};
+
+/// The most messages to redact in a room on each sweep, to go easy on the
+/// server's rate limits.
+const REDACT_BATCH: usize = 100;
+
+/// The reason given on redactions.
+const REASON: &str = "Retention policy";
+
+#[derive(Clone)]
+pub struct Janitor {
+    pub tracked: Tracked,
+    pub policies: Arc<PoliciesFile>,
+    /// Where to report what was pruned.
+    pub admin_room: Option<OwnedRoomId>,
+    /// How many past messages to look through in a room the first time.
+    pub backfill: usize,
+}
+
+/// What a sweep did in a room.
+#[derive(Default)]
+struct Pruned {
+    messages: usize,
+    media: usize,
+    failed: usize,
+}
+
+/// Sweep each room on an interval, forever.
+pub async fn run(client: Client, janitor: Janitor, every: Duration) {
+    let mut ticks = interval(every);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let mut report = Vec::new();
+        for policy in &janitor.policies.rooms {
+            let Some(room) = client
+                .get_room(&policy.room)
+                .filter(|room| room.state() == RoomState::Joined)
+            else {
+                debug!(room = policy.room.as_str(), "Not in room, skipping");
+                continue;
+            };
+            match janitor.sweep(&client, &room, policy).await {
+                Ok(pruned) => {
+                    if let Some(line) = pruned.describe(&policy.room) {
+                        report.push(line);
+                    }
+                }
+                Err(err) => {
+                    error!(room = policy.room.as_str(), "Failed to sweep room: {err:#}");
+                }
+            }
+        }
+        if !report.is_empty() {
+            info!("{}", report.join("; "));
+            janitor.report(&client, report.join("\n")).await;
+        }
+    }
+}
+
+impl Janitor {
+    #[instrument(skip_all, fields(room = room.room_id().as_str()))]
+    async fn sweep(&self, client: &Client, room: &Room, policy: &Policy) -> anyhow::Result<Pruned> {
+        let room_id = room.room_id().as_str();
+        if !self.tracked.is_backfilled(room_id)? {
+            self.backfill(room).await?;
+        }
+
+        let own_user_id = client.user_id().expect("logged in");
+        let mut everyone = policy.everyone;
+        if everyone && !is_moderator(room, own_user_id).await? {
+            warn!("Not a moderator, only redacting my own messages");
+            everyone = false;
+        }
+        let cutoffs = Cutoffs {
+            own_user_id: own_user_id.as_str(),
+            messages: policy.max_age,
+            everyone,
+            media: policy.media_max_age,
+        };
+
+        let mut pruned = Pruned::default();
+        for due in self.tracked.due(room_id, &cutoffs, REDACT_BATCH)? {
+            let event_id = <&EventId>::try_from(due.event_id.as_str())?;
+            match room.redact(event_id, Some(REASON), None).await {
+                Ok(_) => {
+                    self.tracked.forget(&due.event_id)?;
+                    if due.media {
+                        pruned.media += 1;
+                    } else {
+                        pruned.messages += 1;
+                    }
+                }
+                Err(err) => {
+                    warn!(event = due.event_id.as_str(), "Failed to redact: {err}");
+                    // The server won't ever let us, so don't try again
+                    if err.client_api_error_kind().is_some() {
+                        self.tracked.forget(&due.event_id)?;
+                    }
+                    pruned.failed += 1;
+                }
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Record the messages already in a room, so ones sent before the bot
+    /// joined are pruned too.
+    async fn backfill(&self, room: &Room) -> anyhow::Result<()> {
+        info!("Backfilling messages");
+        let mut scanned = 0;
+        let mut options = MessagesOptions::backward();
+        while scanned < self.backfill {
+            let messages = room.messages(options).await?;
+            if messages.chunk.is_empty() {
+                break;
+            }
+            for event in &messages.chunk {
+                scanned += 1;
+                let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+                    SyncMessageLikeEvent::Original(message),
+                ))) = event.raw().deserialize()
+                else {
+                    continue;
+                };
+                self.tracked.record(
+                    room.room_id().as_str(),
+                    message.event_id.as_str(),
+                    message.sender.as_str(),
+                    message.origin_server_ts.get().into(),
+                    is_media(&message.content.msgtype),
+                )?;
+            }
+            let Some(end) = messages.end else {
+                break;
+            };
+            options = MessagesOptions::backward().from(Some(end.as_str()));
+        }
+        self.tracked.set_backfilled(room.room_id().as_str())?;
+        info!("Backfilled {scanned} events");
+        Ok(())
+    }
+
+    async fn report(&self, client: &Client, text: String) {
+        let Some(room) = self
+            .admin_room
+            .as_ref()
+            .and_then(|room_id| client.get_room(room_id))
+            .filter(|room| room.state() == RoomState::Joined)
+        else {
+            return;
+        };
+        if can_reply(&room).await {
+            send_or_log_error(&room, RoomMessageEventContent::notice_plain(text)).await;
+        }
+    }
+}
+
+impl Pruned {
+    fn describe(&self, room_id: &OwnedRoomId) -> Option<String> {
+        if self.messages + self.media + self.failed == 0 {
+            return None;
+        }
+        let mut line = format!(
+            "{room_id}: redacted {} messages and {} expired media",
+            self.messages, self.media
+        );
+        if self.failed > 0 {
+            line += &format!(", {} failed", self.failed);
+        }
+        Some(line)
+    }
+}
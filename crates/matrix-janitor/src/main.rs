@@ -0,0 +1,83 @@
+mod config;
+mod handlers;
+mod janitor;
+mod tracked;
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use clap::Parser;
+use handlers::{on_redaction, on_room_message, Recorder};
+use janitor::Janitor;
+use matrix_bot_core::{AccountConfig, Bot};
+use matrix_sdk::ruma::OwnedRoomId;
+use tracing::info;
+use tracked::Tracked;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The TOML file with each room's retention policy
+    #[arg(long, env = "JANITOR_POLICIES")]
+    pub policies: PathBuf,
+
+    /// A room to report what was pruned to
+    #[arg(long, env = "JANITOR_ADMIN_ROOM")]
+    pub admin_room: Option<OwnedRoomId>,
+
+    /// How often to look for messages to prune
+    #[arg(long, default_value = "1h", value_parser = parse_interval, env = "JANITOR_INTERVAL")]
+    pub interval: Duration,
+
+    /// How many past events to look through in a room the first time
+    #[arg(long, default_value_t = 10000, env = "JANITOR_BACKFILL")]
+    pub backfill: usize,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_interval(interval: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(interval).ok_or_else(|| format!("invalid interval: {interval}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    // Fail on a bad policies file before logging in
+    let policies = Arc::new(config::load(&config.policies)?);
+
+    let mut bot = Bot::login("matrix-janitor", config.account_config).await?;
+    let tracked = Tracked::open(&bot).await?;
+
+    // Unlike most bots, record messages from the initial sync too, so ones
+    // sent while the bot was down are still pruned.
+    bot.client().add_event_handler_context(Recorder {
+        tracked: tracked.clone(),
+        policies: policies.clone(),
+    });
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_redaction);
+    bot.initial_sync().await?;
+
+    tokio::spawn(janitor::run(
+        bot.client().clone(),
+        Janitor {
+            tracked,
+            policies,
+            admin_room: config.admin_room,
+            backfill: config.backfill,
+        },
+        config.interval,
+    ));
+
+    bot.run().await
+}
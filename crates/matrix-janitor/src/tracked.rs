@@ -0,0 +1,144 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::params;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE messages (
+        event_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        sent_at INTEGER NOT NULL,
+        media INTEGER NOT NULL
+    );
+    CREATE INDEX messages_room_sent ON messages (room_id, sent_at);
+    CREATE TABLE backfilled (
+        room_id TEXT PRIMARY KEY
+    );"];
+
+/// The messages in rooms with a retention policy that haven't been pruned
+/// yet, persisted in SQLite.
+#[derive(Clone)]
+pub struct Tracked {
+    store: Store,
+}
+
+/// A message to redact.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Due {
+    pub event_id: String,
+    /// Whether it's being redacted as expired media, rather than for its age.
+    pub media: bool,
+}
+
+/// What to prune from a room.
+pub struct Cutoffs<'a> {
+    /// The bot's own user ID.
+    pub own_user_id: &'a str,
+    /// Messages sent before this are due, if set.
+    pub messages: Option<Duration>,
+    /// Whether `messages` applies to everyone's messages or just the bot's.
+    pub everyone: bool,
+    /// The bot's media sent before this is due, if set.
+    pub media: Option<Duration>,
+}
+
+impl Tracked {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Keep track of a message, `sent_at` being in milliseconds since the
+    /// Unix epoch.
+    pub fn record(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        sender: &str,
+        sent_at: u64,
+        media: bool,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO messages (event_id, room_id, sender, sent_at, media)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![event_id, room_id, sender, sent_at, media],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Stop tracking a message, once it's been redacted.
+    pub fn forget(&self, event_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute("DELETE FROM messages WHERE event_id = ?1", [event_id])?;
+            Ok(())
+        })
+    }
+
+    /// The oldest messages in a room that are due to be redacted.
+    pub fn due(&self, room_id: &str, cutoffs: &Cutoffs, limit: usize) -> anyhow::Result<Vec<Due>> {
+        let now = now_ms();
+        let before = |age: Option<Duration>| {
+            age.map(|age| now.saturating_sub(u64::try_from(age.as_millis()).unwrap_or(u64::MAX)))
+        };
+        self.store.with(|conn| {
+            // A NULL cutoff matches nothing
+            let mut statement = conn.prepare(
+                "SELECT event_id, media AND sender = ?4 AND sent_at < ?5 FROM messages
+                WHERE room_id = ?1 AND (
+                    (sent_at < ?2 AND (?3 OR sender = ?4))
+                    OR (media AND sender = ?4 AND sent_at < ?5)
+                )
+                ORDER BY sent_at LIMIT ?6",
+            )?;
+            let due = statement
+                .query_map(
+                    params![
+                        room_id,
+                        before(cutoffs.messages),
+                        cutoffs.everyone,
+                        cutoffs.own_user_id,
+                        before(cutoffs.media),
+                        limit
+                    ],
+                    |row| {
+                        Ok(Due {
+                            event_id: row.get(0)?,
+                            media: row.get::<_, Option<bool>>(1)?.unwrap_or(false),
+                        })
+                    },
+                )?
+                .collect::<Result<_, _>>()?;
+            Ok(due)
+        })
+    }
+
+    pub fn is_backfilled(&self, room_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM backfilled WHERE room_id = ?1)",
+                [room_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    pub fn set_backfilled(&self, room_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO backfilled (room_id) VALUES (?1)",
+                [room_id],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+/// Milliseconds since the Unix epoch, like `origin_server_ts`.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
@@ -0,0 +1,141 @@
+use matrix_bot_core::{can_reply, reply_notice, store::Store, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::message::{
+        sanitize::remove_html_reply_fallback, MessageFormat, MessageType,
+        OriginalSyncRoomMessageEvent,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, info, instrument};
+
+use crate::{
+    limits::{Limits, Refusal},
+    parse::{parse_changes, Target},
+};
+
+/// The most karma changes counted from a single message.
+const MAX_CHANGES_PER_MESSAGE: usize = 5;
+
+/// How many targets `!karma top` lists.
+const TOP_COUNT: usize = 10;
+
+const HELP: &str = "Give or take karma with thing++ or @user:example.org--.
+!karma <thing or user> to see a score
+!karma top to see the highest scores";
+
+/// Scores are counters, scoped to the room.
+#[derive(Clone)]
+pub struct Karma {
+    pub store: Store,
+    pub limits: Limits,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    karma: Ctx<Karma>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if let Some(args) = strip_command(body, "!karma") {
+        if can_reply(&room).await {
+            let response = command(args, &event, &room, &karma.store)?;
+            reply_notice(&room, &event, response).await;
+        }
+        return Ok(());
+    }
+
+    let formatted_body = match &event.content.msgtype {
+        MessageType::Text(text) => text
+            .formatted
+            .as_ref()
+            .filter(|formatted| formatted.format == MessageFormat::Html)
+            .map(|formatted| remove_html_reply_fallback(&formatted.body)),
+        _ => None,
+    };
+    let changes = parse_changes(body, formatted_body.as_deref());
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let room_id = room.room_id().to_owned();
+    let mut results = Vec::new();
+    let mut refusals = Vec::new();
+    for (target, delta) in changes.into_iter().take(MAX_CHANGES_PER_MESSAGE) {
+        if target == Target::User(event.sender.to_string()) {
+            refusals.push("You can't change your own karma.");
+            continue;
+        }
+        match karma.limits.check(&room_id, &event.sender, target.key()) {
+            Ok(()) => {
+                let score = karma
+                    .store
+                    .increment(room_id.as_str(), target.key(), delta)?;
+                debug!(target = target.key(), delta, score, "Changed karma");
+                results.push(format!("{} now has {score} karma.", target.key()));
+            }
+            Err(Refusal::Cooldown) => {
+                refusals.push("Slow down, you changed that recently.");
+            }
+            Err(Refusal::TooMany) => {
+                info!(sender = event.sender.as_str(), "Karma rate limit reached");
+                refusals.push("You've changed a lot of karma lately, try again later.");
+            }
+        }
+    }
+
+    // Each kind of refusal only needs saying once
+    refusals.dedup();
+    results.extend(refusals.into_iter().map(str::to_owned));
+    if can_reply(&room).await {
+        reply_notice(&room, &event, results.join("\n")).await;
+    }
+    Ok(())
+}
+
+fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    store: &Store,
+) -> anyhow::Result<String> {
+    let room_id = room.room_id().as_str();
+    Ok(match args {
+        "help" => HELP.to_owned(),
+        "top" => {
+            let top = store.counters(room_id)?;
+            if top.is_empty() {
+                return Ok("Nobody has any karma here yet.".to_owned());
+            }
+            top.iter()
+                .take(TOP_COUNT)
+                .enumerate()
+                .map(|(i, (target, score))| format!("{}. {target}: {score}", i + 1))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        args => {
+            // Prefer a mention, as the body only has the display name
+            let mentioned = event
+                .content
+                .mentions
+                .as_ref()
+                .filter(|mentions| mentions.user_ids.len() == 1)
+                .and_then(|mentions| mentions.user_ids.first());
+            let target = match (mentioned, args) {
+                (Some(user_id), _) => Target::User(user_id.to_string()),
+                (None, "") => Target::User(event.sender.to_string()),
+                (None, name) => Target::new(name),
+            };
+            let score = store.counter(room_id, target.key())?;
+            format!("{} has {score} karma.", target.key())
+        }
+    })
+}
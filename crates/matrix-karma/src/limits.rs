@@ -0,0 +1,87 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
+
+/// The window [`Limits::max_per_hour`] is counted over.
+const WINDOW: Duration = Duration::from_secs(3600);
+
+/// Why a karma change was refused.
+#[derive(Debug, PartialEq)]
+pub enum Refusal {
+    /// The same user changed the same target too recently.
+    Cooldown,
+    /// The user has made too many changes in the room recently.
+    TooMany,
+}
+
+/// Rate limits on karma changes, kept in memory as they only need to last
+/// a short while.
+#[derive(Clone)]
+pub struct Limits {
+    /// How long a user must wait before changing the same target again.
+    pub cooldown: Duration,
+    /// How many changes a user may make in a room per hour.
+    pub max_per_hour: usize,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    last_change: HashMap<(OwnedRoomId, OwnedUserId, String), Instant>,
+    recent: HashMap<(OwnedRoomId, OwnedUserId), VecDeque<Instant>>,
+}
+
+impl Limits {
+    pub fn new(cooldown: Duration, max_per_hour: usize) -> Self {
+        Self {
+            cooldown,
+            max_per_hour,
+            state: Default::default(),
+        }
+    }
+
+    /// Record a change by `user_id` to `target` if the limits allow it.
+    pub fn check(
+        &self,
+        room_id: &OwnedRoomId,
+        user_id: &OwnedUserId,
+        target: &str,
+    ) -> Result<(), Refusal> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        // Forget anything that can no longer refuse a change
+        state
+            .last_change
+            .retain(|_, last| now.duration_since(*last) < self.cooldown);
+        state.recent.retain(|_, times| {
+            while times
+                .front()
+                .is_some_and(|time| now.duration_since(*time) >= WINDOW)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+
+        let key = (room_id.clone(), user_id.clone(), target.to_owned());
+        if state.last_change.contains_key(&key) {
+            return Err(Refusal::Cooldown);
+        }
+        let recent = state
+            .recent
+            .entry((room_id.clone(), user_id.clone()))
+            .or_default();
+        if recent.len() >= self.max_per_hour {
+            return Err(Refusal::TooMany);
+        }
+
+        recent.push_back(now);
+        state.last_change.insert(key, now);
+        Ok(())
+    }
+}
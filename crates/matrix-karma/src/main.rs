@@ -0,0 +1,56 @@
+mod handlers;
+mod limits;
+mod parse;
+
+use std::time::Duration;
+
+use clap::Parser;
+use handlers::{on_room_message, Karma};
+use limits::Limits;
+use matrix_bot_core::{AccountConfig, Bot};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// How long a user must wait before changing the same karma again
+    #[arg(long, default_value = "1m", value_parser = parse_cooldown, env = "KARMA_COOLDOWN")]
+    pub cooldown: Duration,
+
+    /// How many karma changes a user may make in a room each hour
+    #[arg(long, default_value_t = 20, env = "KARMA_MAX_PER_HOUR")]
+    pub max_per_hour: usize,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_cooldown(cooldown: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(cooldown).ok_or_else(|| format!("invalid cooldown: {cooldown}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-karma", config.account_config).await?;
+    let store = bot.open_store(&[]).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Karma {
+        store,
+        limits: Limits::new(config.cooldown, config.max_per_hour),
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,85 @@
+use std::sync::LazyLock;
+
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+
+/// Links to users, as sent by clients for mentions.
+static PILL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<a\s+href="https://matrix\.to/#/((?:@|%40)[^"?/]+)"[^>]*>.*?</a>"#).unwrap()
+});
+
+/// Code, which is full of `i++` that isn't about karma.
+static CODE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<pre>.*?</pre>|<code>.*?</code>|```.*?```|`[^`]*`").unwrap());
+
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// A user ID or a word, followed by `++` or `--`. Pills may be followed by
+/// the `: ` that clients add after a mention at the start of a message.
+static CHANGE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|[\s(])(@[^\s:]+:[\w.:\-\[\]]*[\w\]]|[\p{L}\p{N}_][\p{L}\p{N}_.#\-]*?):?\s?(\+\+|--)(?:$|[\s.,;!?)])")
+        .unwrap()
+});
+
+/// Something that can have karma.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    User(String),
+    Thing(String),
+}
+
+impl Target {
+    pub fn new(name: &str) -> Self {
+        if name.starts_with('@') && name.contains(':') {
+            Target::User(name.to_owned())
+        } else {
+            Target::Thing(name.to_lowercase())
+        }
+    }
+
+    /// The key the target's score is stored under.
+    pub fn key(&self) -> &str {
+        match self {
+            Target::User(user_id) => user_id,
+            Target::Thing(thing) => thing,
+        }
+    }
+}
+
+/// Find the karma changes in a message, in the order they were given. Pills
+/// in the formatted body are counted as their user rather than their display
+/// name.
+pub fn parse_changes(body: &str, formatted_body: Option<&str>) -> Vec<(Target, i64)> {
+    let text = match formatted_body {
+        Some(html) => {
+            let html = CODE.replace_all(html, " ");
+            let html = PILL.replace_all(&html, |captures: &regex::Captures<'_>| {
+                percent_decode_str(&captures[1])
+                    .decode_utf8_lossy()
+                    .into_owned()
+            });
+            let text = TAG.replace_all(&html, " ");
+            text.replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&#39;", "'")
+                .replace("&amp;", "&")
+        }
+        None => CODE.replace_all(body, " ").into_owned(),
+    };
+
+    // Matches can't overlap, so `a++ b++` needs each line scanned until no
+    // more are found
+    let mut changes = Vec::new();
+    for line in text.lines() {
+        let mut start = 0;
+        while let Some(captures) = CHANGE.captures_at(line, start) {
+            let whole = captures.get(0).unwrap();
+            let delta = if &captures[2] == "++" { 1 } else { -1 };
+            changes.push((Target::new(&captures[1]), delta));
+            // Leave the trailing separator for the next match
+            start = captures.get(2).unwrap().end().max(whole.start() + 1);
+        }
+    }
+    changes
+}
@@ -0,0 +1,72 @@
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE renders (
+        formula TEXT NOT NULL,
+        dpi INTEGER NOT NULL,
+        uri TEXT NOT NULL,
+        width INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        size INTEGER NOT NULL,
+        PRIMARY KEY (formula, dpi)
+    );"];
+
+/// A formula that's already been rendered and uploaded.
+#[derive(Debug, Clone)]
+pub struct Cached {
+    pub uri: String,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+}
+
+/// Rendered formulas, so repeats can reuse the uploaded image, persisted in
+/// SQLite.
+#[derive(Clone)]
+pub struct Cache {
+    store: Store,
+}
+
+impl Cache {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn get(&self, formula: &str, dpi: u32) -> anyhow::Result<Option<Cached>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT uri, width, height, size FROM renders WHERE formula = ?1 AND dpi = ?2",
+                params![formula, dpi],
+                |row| {
+                    Ok(Cached {
+                        uri: row.get(0)?,
+                        width: row.get(1)?,
+                        height: row.get(2)?,
+                        size: row.get(3)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+
+    pub fn put(&self, formula: &str, dpi: u32, cached: &Cached) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO renders (formula, dpi, uri, width, height, size)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    formula,
+                    dpi,
+                    cached.uri,
+                    cached.width,
+                    cached.height,
+                    cached.size
+                ],
+            )?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,141 @@
+use matrix_bot_core::{can_reply, html, reply, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::{
+            message::{
+                ImageMessageEventContent, MessageType, OriginalSyncRoomMessageEvent,
+                RoomMessageEventContent,
+            },
+            ImageInfo,
+        },
+        OwnedMxcUri, UInt,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, instrument};
+
+use crate::{
+    cache::{Cache, Cached},
+    render::{check_formula, Output, Renderer},
+};
+
+/// The most formulas to render from one message.
+const MAX_FORMULAS: usize = 3;
+
+const HELP: &str = "!tex <formula> to render a formula, or put formulas between $$ signs in a message, like $$e^{i\\pi} + 1 = 0$$";
+
+#[derive(Clone)]
+pub struct Latex {
+    pub renderer: Renderer,
+    pub cache: Cache,
+    pub output: Output,
+}
+
+impl Latex {
+    /// An image of a formula, rendered and uploaded unless it's been done
+    /// before.
+    async fn image(&self, room: &Room, formula: &str) -> anyhow::Result<RoomMessageEventContent> {
+        let dpi = self.renderer.dpi;
+        let cached = match self.cache.get(formula, dpi)? {
+            Some(cached) => cached,
+            None => {
+                let rendered = self.renderer.render(formula).await?;
+                let size = rendered.png.len() as u64;
+                let response = room
+                    .client()
+                    .media()
+                    .upload(&mime::IMAGE_PNG, rendered.png, None)
+                    .await?;
+                let cached = Cached {
+                    uri: response.content_uri.to_string(),
+                    width: rendered.width,
+                    height: rendered.height,
+                    size,
+                };
+                self.cache.put(formula, dpi, &cached)?;
+                cached
+            }
+        };
+
+        let mut info = ImageInfo::new();
+        info.mimetype = Some(mime::IMAGE_PNG.to_string());
+        info.width = Some(UInt::from(cached.width));
+        info.height = Some(UInt::from(cached.height));
+        info.size = UInt::new(cached.size);
+        let image =
+            ImageMessageEventContent::plain(formula.to_owned(), OwnedMxcUri::from(cached.uri))
+                .info(Box::new(info));
+        Ok(RoomMessageEventContent::new(MessageType::Image(image)))
+    }
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    latex: Ctx<Latex>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    let command = strip_command(body, "!tex");
+    let formulas = match command {
+        Some("") => {
+            if can_reply(&room).await {
+                reply_notice(&room, &event, HELP).await;
+            }
+            return Ok(());
+        }
+        Some(formula) => vec![formula],
+        None => display_formulas(body),
+    };
+    if formulas.is_empty() || !can_reply(&room).await {
+        return Ok(());
+    }
+
+    for formula in formulas.into_iter().take(MAX_FORMULAS) {
+        let content = match check_formula(formula) {
+            Ok(()) if latex.output == Output::Maths => Ok(maths(formula)),
+            Ok(()) => latex
+                .image(&room, formula)
+                .await
+                .map_err(|err| format!("I couldn't render that: {err}")),
+            Err(reason) => Err(reason),
+        };
+        match content {
+            Ok(content) => reply(&room, &event, content).await,
+            // Only complain about formulas someone asked for
+            Err(reason) if command.is_some() => reply_notice(&room, &event, reason).await,
+            Err(reason) => debug!("Not rendering a formula: {reason}"),
+        }
+    }
+    Ok(())
+}
+
+/// The formulas between `$$` signs in a message.
+fn display_formulas(body: &str) -> Vec<&str> {
+    let parts: Vec<&str> = body.split("$$").collect();
+    // Every other part is inside a pair of `$$`, if it's closed
+    (1..parts.len().saturating_sub(1))
+        .step_by(2)
+        .map(|i| parts[i].trim())
+        .filter(|formula| !formula.is_empty())
+        .collect()
+}
+
+/// A formula as MSC2191 formatted math, for clients to render themselves.
+fn maths(formula: &str) -> RoomMessageEventContent {
+    let escaped = html::escape(formula);
+    RoomMessageEventContent::notice_html(
+        format!("$${formula}$$"),
+        format!("<div data-mx-maths=\"{escaped}\"><code>{escaped}</code></div>"),
+    )
+}
@@ -0,0 +1,75 @@
+mod cache;
+mod handlers;
+mod render;
+
+use std::{path::PathBuf, time::Duration};
+
+use cache::Cache;
+use clap::Parser;
+use handlers::{on_room_message, Latex};
+use matrix_bot_core::{AccountConfig, Bot};
+use render::{Output, Renderer};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Whether to post rendered images or formatted math
+    #[arg(long, value_enum, default_value = "image", env = "LATEX_OUTPUT")]
+    pub output: Output,
+
+    /// The latex program to run
+    #[arg(long, default_value = "latex", env = "LATEX_LATEX")]
+    pub latex: PathBuf,
+
+    /// The dvipng program to run
+    #[arg(long, default_value = "dvipng", env = "LATEX_DVIPNG")]
+    pub dvipng: PathBuf,
+
+    /// The resolution to render at
+    #[arg(long, default_value_t = 200, env = "LATEX_DPI")]
+    pub dpi: u32,
+
+    /// How long rendering may take before it's given up on
+    #[arg(long, default_value = "10s", value_parser = parse_timeout, env = "LATEX_TIMEOUT")]
+    pub timeout: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_timeout(timeout: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(timeout).ok_or_else(|| format!("invalid timeout: {timeout}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-latex", config.account_config).await?;
+    let cache = Cache::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Latex {
+        renderer: Renderer {
+            latex: config.latex,
+            dvipng: config.dvipng,
+            dpi: config.dpi,
+            timeout: config.timeout,
+        },
+        cache,
+        output: config.output,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,204 @@
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use clap::ValueEnum;
+use tokio::process::Command;
+
+/// The longest formula to render, in bytes.
+pub const MAX_FORMULA_LEN: usize = 1000;
+
+/// Commands that could read or write files, run programs, or redefine
+/// things to get around this list.
+const FORBIDDEN_COMMANDS: &[&str] = &[
+    "catcode",
+    "closein",
+    "closeout",
+    "csname",
+    "def",
+    "directlua",
+    "documentclass",
+    "edef",
+    "expandafter",
+    "gdef",
+    "immediate",
+    "include",
+    "includegraphics",
+    "input",
+    "jobname",
+    "let",
+    "loop",
+    "newcommand",
+    "newenvironment",
+    "newread",
+    "newwrite",
+    "openin",
+    "openout",
+    "read",
+    "readline",
+    "renewcommand",
+    "renewenvironment",
+    "special",
+    "usepackage",
+    "verbatiminput",
+    "write",
+    "xdef",
+];
+
+/// How formulas are posted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// Render each formula to a PNG image
+    Image,
+    /// Send formatted math for clients that support it, without rendering
+    Maths,
+}
+
+/// A rendered formula.
+pub struct Rendered {
+    pub png: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Renders formulas with `latex` and `dvipng`.
+#[derive(Clone, Debug)]
+pub struct Renderer {
+    pub latex: PathBuf,
+    pub dvipng: PathBuf,
+    pub dpi: u32,
+    /// How long each program may run for.
+    pub timeout: Duration,
+}
+
+/// Why a formula won't be rendered, if it's too long or uses commands that
+/// aren't allowed.
+pub fn check_formula(formula: &str) -> Result<(), String> {
+    if formula.len() > MAX_FORMULA_LEN {
+        return Err(format!(
+            "That's too long, I only render formulas up to {MAX_FORMULA_LEN} characters."
+        ));
+    }
+    // `^^` spells out characters by their code, which could hide a command
+    if formula.contains("^^") {
+        return Err("`^^` isn't allowed.".to_owned());
+    }
+    if formula.contains("filecontents") {
+        return Err("`filecontents` isn't allowed.".to_owned());
+    }
+    let mut rest = formula;
+    while let Some(start) = rest.find('\\') {
+        rest = &rest[start + 1..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphabetic() && c != '@')
+            .unwrap_or(rest.len());
+        let command = &rest[..end];
+        // Commands with `@` in them are LaTeX's internals
+        if FORBIDDEN_COMMANDS.contains(&command) || command.contains('@') {
+            return Err(format!("`\\{command}` isn't allowed."));
+        }
+        // Skip over an escaped backslash, so `\\input` isn't read as `\input`
+        if end == 0 && rest.starts_with('\\') {
+            rest = &rest[1..];
+        } else {
+            rest = &rest[end..];
+        }
+    }
+    Ok(())
+}
+
+impl Renderer {
+    /// Render a display formula, which must already have passed
+    /// [`check_formula`].
+    pub async fn render(&self, formula: &str) -> anyhow::Result<Rendered> {
+        let dir = tempfile::tempdir()?;
+        let tex = dir.path().join("formula.tex");
+        tokio::fs::write(
+            &tex,
+            format!(
+                "\\documentclass[12pt]{{article}}
+\\usepackage{{amsmath,amssymb}}
+\\pagestyle{{empty}}
+\\begin{{document}}
+\\[ {formula} \\]
+\\end{{document}}
+"
+            ),
+        )
+        .await?;
+
+        let output = self
+            .run(
+                Command::new(&self.latex)
+                    .current_dir(dir.path())
+                    .args([
+                        "-no-shell-escape",
+                        "-interaction=nonstopmode",
+                        "-halt-on-error",
+                    ])
+                    .arg("-output-directory")
+                    .arg(dir.path())
+                    .arg(&tex),
+            )
+            .await?;
+        if !output.status.success() {
+            // LaTeX reports errors on lines starting with `!`
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let error = stdout
+                .lines()
+                .find_map(|line| line.strip_prefix("! "))
+                .unwrap_or("LaTeX failed");
+            bail!("{error}");
+        }
+
+        let png = dir.path().join("formula.png");
+        let output = self
+            .run(
+                Command::new(&self.dvipng)
+                    .current_dir(dir.path())
+                    .args(["-q", "-T", "tight", "-bg", "White", "-D"])
+                    .arg(self.dpi.to_string())
+                    .arg("-o")
+                    .arg(&png)
+                    .arg(dir.path().join("formula.dvi")),
+            )
+            .await?;
+        ensure!(
+            output.status.success(),
+            "dvipng failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+
+        let png = tokio::fs::read(&png).await?;
+        let (width, height) = png_size(&png).context("dvipng didn't make a PNG")?;
+        Ok(Rendered { png, width, height })
+    }
+
+    /// Run a program with TeX's file access limited to the directory it's run
+    /// in, and kill it if it runs for too long.
+    async fn run(&self, command: &mut Command) -> anyhow::Result<std::process::Output> {
+        let child = command
+            .env("openin_any", "p")
+            .env("openout_any", "p")
+            .env("shell_escape", "f")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| anyhow!("that took too long to render"))?
+            .map_err(Into::into)
+    }
+}
+
+/// The width and height of a PNG image, from its header.
+fn png_size(png: &[u8]) -> Option<(u32, u32)> {
+    if png.get(..8)? != b"\x89PNG\r\n\x1a\n" || png.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(png.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(png.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
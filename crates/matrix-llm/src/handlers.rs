@@ -0,0 +1,309 @@
+use std::time::{Duration, Instant};
+
+use matrix_bot_core::{
+    can_reply, is_moderator, replacement, reply_notice, send, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::message::{
+            AddMentions, OriginalSyncRoomMessageEvent, Relation, ReplyWithinThread,
+            RoomMessageEventContent,
+        },
+        OwnedEventId, UserId,
+    },
+    Room, RoomState,
+};
+use tokio::sync::mpsc;
+use tracing::{instrument, warn};
+
+use crate::{
+    memory::Memory,
+    provider::{estimate_tokens, AnyProvider, ChatMessage, Provider, Role},
+};
+
+/// How often a reply is edited while it's being written.
+const EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+
+/// Shown after a reply that's still being written.
+const WRITING: &str = " …";
+
+/// The conversation for a DM's main timeline, rather than a thread in it.
+const DM_CONVERSATION: &str = "dm";
+
+const HELP: &str = "Mention or DM me to chat. In rooms, I reply in a thread and remember it.
+!llm prompt to see this room's system prompt
+!llm prompt <text> or !llm prompt reset to change it (moderators only)
+!llm forget to make me forget this conversation
+!llm usage to see how many tokens you've used today";
+
+#[derive(Clone)]
+pub struct Assistant {
+    pub provider: AnyProvider,
+    pub memory: Memory,
+    /// The system prompt for rooms that haven't set their own.
+    pub system_prompt: String,
+    /// How many tokens of a conversation to send with each request.
+    pub context_tokens: u32,
+    /// The most tokens a reply may have.
+    pub max_tokens: u32,
+    /// How many tokens each person may use a day, or 0 for no limit.
+    pub daily_budget: u32,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    assistant: Ctx<Assistant>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let client = room.client();
+    let Some(bot_id) = client.user_id() else {
+        return Ok(());
+    };
+    if *bot_id == *event.sender {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    let is_dm = room.is_direct().await?;
+    let thread = match &event.content.relates_to {
+        Some(Relation::Thread(thread)) => Some(thread.event_id.clone()),
+        _ => None,
+    };
+    let conversation = match (&thread, is_dm) {
+        (Some(thread), _) => thread.to_string(),
+        (None, true) => DM_CONVERSATION.to_owned(),
+        // A mention in the main timeline starts a thread
+        (None, false) => event.event_id.to_string(),
+    };
+
+    if let Some(args) = strip_command(body, "!llm") {
+        if can_reply(&room).await {
+            let response = command(args, &event, &room, is_dm, &conversation, &assistant).await?;
+            reply_notice(&room, &event, response).await;
+        }
+        return Ok(());
+    }
+
+    let room_id = room.room_id().as_str();
+    let prompt = match addressed(&room, bot_id, &event, body).await? {
+        Some(prompt) => prompt,
+        None if is_dm || assistant.memory.is_conversation(room_id, &conversation)? => {
+            body.to_owned()
+        }
+        None => return Ok(()),
+    };
+    if prompt.is_empty() || !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let sender = event.sender.as_str();
+    if assistant.daily_budget > 0 && assistant.memory.used_today(sender)? >= assistant.daily_budget
+    {
+        reply_notice(
+            &room,
+            &event,
+            "You've used up your tokens for today, please try again tomorrow.",
+        )
+        .await;
+        return Ok(());
+    }
+
+    // Several people can talk in a thread, so say who's talking
+    let prompt = if is_dm {
+        prompt
+    } else {
+        format!("{sender}: {prompt}")
+    };
+    let system_prompt = assistant
+        .memory
+        .prompt(room_id)?
+        .unwrap_or_else(|| assistant.system_prompt.clone());
+    let prompt_tokens = estimate_tokens(&prompt);
+    let history_budget = assistant
+        .context_tokens
+        .saturating_sub(estimate_tokens(&system_prompt) + prompt_tokens);
+    let (history, history_tokens) =
+        assistant
+            .memory
+            .history(room_id, &conversation, history_budget)?;
+
+    let mut messages = vec![ChatMessage {
+        role: Role::System,
+        content: system_prompt.clone(),
+    }];
+    messages.extend(history);
+    messages.push(ChatMessage {
+        role: Role::User,
+        content: prompt.clone(),
+    });
+    assistant
+        .memory
+        .add_turn(room_id, &conversation, Role::User, &prompt, prompt_tokens)?;
+
+    let original = event.clone().into_full_event(room.room_id().to_owned());
+    let placeholder = RoomMessageEventContent::text_plain(WRITING.trim());
+    let placeholder = if is_dm && thread.is_none() {
+        placeholder
+    } else {
+        placeholder.make_for_thread(&original, ReplyWithinThread::No, AddMentions::No)
+    };
+    let reply_id = send(&room, placeholder).await?;
+
+    let reply = stream_reply(&room, &reply_id, &assistant, &messages).await;
+    let reply_tokens = estimate_tokens(&reply);
+    assistant.memory.add_turn(
+        room_id,
+        &conversation,
+        Role::Assistant,
+        &reply,
+        reply_tokens,
+    )?;
+    assistant.memory.add_usage(
+        sender,
+        estimate_tokens(&system_prompt) + history_tokens + prompt_tokens + reply_tokens,
+    )?;
+    Ok(())
+}
+
+/// Generate a reply, editing the message `reply_id` as it's written, and
+/// return it.
+async fn stream_reply(
+    room: &Room,
+    reply_id: &OwnedEventId,
+    assistant: &Assistant,
+    messages: &[ChatMessage],
+) -> String {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let generate = assistant
+        .provider
+        .chat(messages, assistant.max_tokens, sender);
+    let edit = async {
+        let mut text = String::new();
+        let mut last_edit = Instant::now();
+        while let Some(chunk) = receiver.recv().await {
+            text.push_str(&chunk);
+            if last_edit.elapsed() >= EDIT_INTERVAL && !text.trim().is_empty() {
+                edit_reply(room, reply_id, format!("{text}{WRITING}")).await;
+                last_edit = Instant::now();
+            }
+        }
+        text
+    };
+    let (result, mut text) = tokio::join!(generate, edit);
+
+    if let Err(err) = result {
+        warn!("Failed to generate a reply: {err}");
+        if text.trim().is_empty() {
+            text = "Sorry, I couldn't come up with a reply. Please try again later.".to_owned();
+        } else {
+            text += "\n\n(I was cut off, sorry.)";
+        }
+    } else if text.trim().is_empty() {
+        text = "I don't have anything to say to that.".to_owned();
+    }
+    edit_reply(room, reply_id, text.clone()).await;
+    text
+}
+
+async fn edit_reply(room: &Room, reply_id: &OwnedEventId, text: String) {
+    let content = RoomMessageEventContent::text_markdown(text);
+    if let Err(err) = send(room, replacement(reply_id.clone(), content)).await {
+        warn!("Failed to edit reply: {err:#}");
+    }
+}
+
+/// If the message is addressed to the bot, either by mentioning it or by
+/// starting with its name, what it says without the name.
+async fn addressed(
+    room: &Room,
+    bot_id: &UserId,
+    event: &OriginalSyncRoomMessageEvent,
+    body: &str,
+) -> anyhow::Result<Option<String>> {
+    let mut names = vec![bot_id.to_string(), bot_id.localpart().to_owned()];
+    if let Some(name) = room
+        .get_member_no_sync(bot_id)
+        .await?
+        .and_then(|member| member.display_name().map(str::to_owned))
+    {
+        names.push(name);
+    }
+
+    for name in &names {
+        let Some(prefix) = body.get(..name.len()) else {
+            continue;
+        };
+        if prefix.eq_ignore_ascii_case(name) {
+            let rest = &body[name.len()..];
+            if let Some(rest) = rest.strip_prefix([':', ',']) {
+                return Ok(Some(rest.trim().to_owned()));
+            }
+        }
+    }
+
+    let mentioned = event
+        .content
+        .mentions
+        .as_ref()
+        .is_some_and(|mentions| mentions.user_ids.contains(bot_id))
+        || body.contains(bot_id.as_str());
+    Ok(mentioned.then(|| body.replace(bot_id.as_str(), "").trim().to_owned()))
+}
+
+async fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    is_dm: bool,
+    conversation: &str,
+    assistant: &Assistant,
+) -> anyhow::Result<String> {
+    let room_id = room.room_id().as_str();
+    let memory = &assistant.memory;
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+
+    Ok(match subcommand {
+        "prompt" if rest.is_empty() => match memory.prompt(room_id)? {
+            Some(prompt) => format!("This room's system prompt is: {prompt}"),
+            None => format!(
+                "This room uses the default system prompt: {}",
+                assistant.system_prompt
+            ),
+        },
+        "prompt" => {
+            if !is_dm && !is_moderator(room, &event.sender).await? {
+                return Ok("Only moderators can change this room's system prompt.".to_owned());
+            }
+            if rest == "reset" {
+                memory.set_prompt(room_id, None)?;
+                "This room now uses the default system prompt.".to_owned()
+            } else {
+                memory.set_prompt(room_id, Some(rest))?;
+                "Changed this room's system prompt.".to_owned()
+            }
+        }
+        "forget" => {
+            if memory.forget(room_id, conversation)? > 0 {
+                "I've forgotten this conversation.".to_owned()
+            } else {
+                "There's nothing to forget here.".to_owned()
+            }
+        }
+        "usage" => {
+            let used = memory.used_today(event.sender.as_str())?;
+            match assistant.daily_budget {
+                0 => format!("You've used about {used} tokens today."),
+                budget => format!("You've used about {used} of your {budget} tokens today."),
+            }
+        }
+        _ => HELP.to_owned(),
+    })
+}
@@ -0,0 +1,98 @@
+mod handlers;
+mod memory;
+mod provider;
+
+use std::time::Duration;
+
+use clap::Parser;
+use handlers::{on_room_message, Assistant};
+use matrix_bot_core::{AccountConfig, Bot};
+use memory::Memory;
+use provider::{AnyProvider, ProviderKind};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The kind of API to use
+    #[arg(long, value_enum, default_value = "openai", env = "LLM_PROVIDER")]
+    pub provider: ProviderKind,
+
+    /// The API's base URL. Defaults to OpenAI's, or Ollama on localhost
+    #[arg(long, env = "LLM_API_URL")]
+    pub api_url: Option<String>,
+
+    /// The API key, if the API needs one
+    #[arg(long, env = "LLM_API_KEY")]
+    pub api_key: Option<String>,
+
+    /// The model to use, e.g. `gpt-4o-mini` or `llama3.2`
+    #[arg(long, env = "LLM_MODEL")]
+    pub model: String,
+
+    /// The system prompt for rooms that haven't set their own
+    #[arg(
+        long,
+        default_value = "You are a helpful assistant in a Matrix chat. Keep your answers short.",
+        env = "LLM_SYSTEM_PROMPT"
+    )]
+    pub system_prompt: String,
+
+    /// How many tokens of a conversation to send with each request
+    #[arg(long, default_value_t = 4000, env = "LLM_CONTEXT_TOKENS")]
+    pub context_tokens: u32,
+
+    /// The most tokens a reply may have
+    #[arg(long, default_value_t = 800, env = "LLM_MAX_TOKENS")]
+    pub max_tokens: u32,
+
+    /// How many tokens each person may use a day, or 0 for no limit
+    #[arg(long, default_value_t = 50000, env = "LLM_DAILY_BUDGET")]
+    pub daily_budget: u32,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    // Replies are streamed, so this covers writing the whole reply
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("matrix-llm/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(300))
+        .build()?;
+    let provider = AnyProvider::new(
+        config.provider,
+        http,
+        config.api_url,
+        config.api_key,
+        config.model,
+    );
+
+    let mut bot = Bot::login("matrix-llm", config.account_config).await?;
+    let memory = Memory::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Assistant {
+        provider,
+        memory,
+        system_prompt: config.system_prompt,
+        context_tokens: config.context_tokens,
+        max_tokens: config.max_tokens,
+        daily_budget: config.daily_budget,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
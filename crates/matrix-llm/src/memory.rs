@@ -0,0 +1,187 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+use crate::provider::{ChatMessage, Role};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE prompts (
+        room_id TEXT PRIMARY KEY,
+        prompt TEXT NOT NULL
+    );
+    CREATE TABLE turns (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        conversation TEXT NOT NULL,
+        role TEXT NOT NULL,
+        content TEXT NOT NULL,
+        tokens INTEGER NOT NULL,
+        ts INTEGER NOT NULL
+    );
+    CREATE INDEX turns_conversation ON turns (room_id, conversation, id);
+    CREATE TABLE usage (
+        user_id TEXT NOT NULL,
+        day INTEGER NOT NULL,
+        tokens INTEGER NOT NULL,
+        PRIMARY KEY (user_id, day)
+    );"];
+
+/// How long conversations are remembered for.
+const RETENTION: Duration = Duration::from_secs(30 * 86400);
+
+/// Room prompts, conversations and token usage, persisted in SQLite.
+#[derive(Clone)]
+pub struct Memory {
+    store: Store,
+}
+
+impl Memory {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        let store = bot.open_store(MIGRATIONS).await?;
+        store.with(|conn| {
+            conn.execute(
+                "DELETE FROM turns WHERE ts < ?1",
+                [now().saturating_sub(RETENTION.as_secs())],
+            )?;
+            conn.execute("DELETE FROM usage WHERE day < ?1", [today() - 1])
+        })?;
+        Ok(Self { store })
+    }
+
+    pub fn prompt(&self, room_id: &str) -> anyhow::Result<Option<String>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT prompt FROM prompts WHERE room_id = ?1",
+                [room_id],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+
+    pub fn set_prompt(&self, room_id: &str, prompt: Option<&str>) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            match prompt {
+                Some(prompt) => conn.execute(
+                    "INSERT INTO prompts (room_id, prompt) VALUES (?1, ?2)
+                    ON CONFLICT (room_id) DO UPDATE SET prompt = excluded.prompt",
+                    [room_id, prompt],
+                )?,
+                None => conn.execute("DELETE FROM prompts WHERE room_id = ?1", [room_id])?,
+            };
+            Ok(())
+        })
+    }
+
+    /// Whether the bot has taken part in a conversation.
+    pub fn is_conversation(&self, room_id: &str, conversation: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM turns WHERE room_id = ?1 AND conversation = ?2)",
+                [room_id, conversation],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    pub fn add_turn(
+        &self,
+        room_id: &str,
+        conversation: &str,
+        role: Role,
+        content: &str,
+        tokens: u32,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO turns (room_id, conversation, role, content, tokens, ts)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![room_id, conversation, role.as_str(), content, tokens, now()],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// The latest turns of a conversation that fit in `budget` tokens,
+    /// oldest first, and how many tokens they take.
+    pub fn history(
+        &self,
+        room_id: &str,
+        conversation: &str,
+        budget: u32,
+    ) -> anyhow::Result<(Vec<ChatMessage>, u32)> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT role, content, tokens FROM turns
+                WHERE room_id = ?1 AND conversation = ?2 ORDER BY id DESC",
+            )?;
+            let mut rows = statement.query([room_id, conversation])?;
+
+            let mut messages = Vec::new();
+            let mut used = 0;
+            while let Some(row) = rows.next()? {
+                let tokens: u32 = row.get(2)?;
+                if used + tokens > budget {
+                    break;
+                }
+                let role: String = row.get(0)?;
+                let Some(role) = Role::parse(&role) else {
+                    continue;
+                };
+                used += tokens;
+                messages.push(ChatMessage {
+                    role,
+                    content: row.get(1)?,
+                });
+            }
+            messages.reverse();
+            Ok((messages, used))
+        })
+    }
+
+    pub fn forget(&self, room_id: &str, conversation: &str) -> anyhow::Result<usize> {
+        self.store.with(|conn| {
+            conn.execute(
+                "DELETE FROM turns WHERE room_id = ?1 AND conversation = ?2",
+                [room_id, conversation],
+            )
+        })
+    }
+
+    /// How many tokens `user_id` has used today.
+    pub fn used_today(&self, user_id: &str) -> anyhow::Result<u32> {
+        self.store.with(|conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT tokens FROM usage WHERE user_id = ?1 AND day = ?2",
+                    params![user_id, today()],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or(0))
+        })
+    }
+
+    pub fn add_usage(&self, user_id: &str, tokens: u32) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO usage (user_id, day, tokens) VALUES (?1, ?2, ?3)
+                ON CONFLICT (user_id, day) DO UPDATE SET tokens = tokens + excluded.tokens",
+                params![user_id, today(), tokens],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The number of the current UTC day.
+fn today() -> u64 {
+    now() / 86400
+}
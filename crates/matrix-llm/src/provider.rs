@@ -0,0 +1,258 @@
+use std::future::Future;
+
+use anyhow::bail;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        Some(match role {
+            "system" => Role::System,
+            "user" => Role::User,
+            "assistant" => Role::Assistant,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+/// A rough count of the tokens in `text`, at about four characters each, as
+/// there's no tokenizer that fits every model.
+pub fn estimate_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(4) + 1
+}
+
+/// A chat completion service.
+pub trait Provider {
+    /// Generate the next message in a conversation, sending each piece of it
+    /// to `chunks` as it arrives.
+    fn chat(
+        &self,
+        messages: &[ChatMessage],
+        max_tokens: u32,
+        chunks: UnboundedSender<String>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ProviderKind {
+    /// OpenAI, or anything with an OpenAI-compatible API
+    Openai,
+    /// A local Ollama server
+    Ollama,
+}
+
+/// One of the supported providers, picked at startup.
+#[derive(Clone)]
+pub enum AnyProvider {
+    OpenAi(OpenAi),
+    Ollama(Ollama),
+}
+
+impl AnyProvider {
+    pub fn new(
+        kind: ProviderKind,
+        http: reqwest::Client,
+        api_url: Option<String>,
+        api_key: Option<String>,
+        model: String,
+    ) -> Self {
+        match kind {
+            ProviderKind::Openai => Self::OpenAi(OpenAi {
+                http,
+                api_url: api_url.unwrap_or_else(|| "https://api.openai.com/v1".to_owned()),
+                api_key,
+                model,
+            }),
+            ProviderKind::Ollama => Self::Ollama(Ollama {
+                http,
+                api_url: api_url.unwrap_or_else(|| "http://localhost:11434".to_owned()),
+                model,
+            }),
+        }
+    }
+}
+
+impl Provider for AnyProvider {
+    async fn chat(
+        &self,
+        messages: &[ChatMessage],
+        max_tokens: u32,
+        chunks: UnboundedSender<String>,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::OpenAi(provider) => provider.chat(messages, max_tokens, chunks).await,
+            Self::Ollama(provider) => provider.chat(messages, max_tokens, chunks).await,
+        }
+    }
+}
+
+/// Call `f` with each line of a streamed response body as it arrives,
+/// until it returns `false`.
+async fn for_each_line(
+    mut response: reqwest::Response,
+    mut f: impl FnMut(&str) -> anyhow::Result<bool>,
+) -> anyhow::Result<()> {
+    let mut buffer = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = std::str::from_utf8(&line)?.trim();
+            if !line.is_empty() && !f(line)? {
+                return Ok(());
+            }
+        }
+    }
+    let line = std::str::from_utf8(&buffer)?.trim();
+    if !line.is_empty() {
+        f(line)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct OpenAi {
+    http: reqwest::Client,
+    api_url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChunk {
+    choices: Vec<OpenAiChoice>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    delta: OpenAiDelta,
+}
+
+#[derive(Deserialize)]
+struct OpenAiDelta {
+    content: Option<String>,
+}
+
+impl Provider for OpenAi {
+    async fn chat(
+        &self,
+        messages: &[ChatMessage],
+        max_tokens: u32,
+        chunks: UnboundedSender<String>,
+    ) -> anyhow::Result<()> {
+        let mut request = self
+            .http
+            .post(format!(
+                "{}/chat/completions",
+                self.api_url.trim_end_matches('/')
+            ))
+            .json(&json!({
+                "model": self.model,
+                "messages": messages,
+                "max_tokens": max_tokens,
+                "stream": true,
+            }));
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request.send().await?.error_for_status()?;
+
+        // Server-sent events, one `data:` line per chunk
+        for_each_line(response, |line| {
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                return Ok(true);
+            };
+            if data == "[DONE]" {
+                return Ok(false);
+            }
+            let chunk: OpenAiChunk = serde_json::from_str(data)?;
+            for choice in chunk.choices {
+                if let Some(content) = choice.delta.content {
+                    // The receiver only goes away if the reply was abandoned
+                    let _ = chunks.send(content);
+                }
+            }
+            Ok(true)
+        })
+        .await
+    }
+}
+
+#[derive(Clone)]
+pub struct Ollama {
+    http: reqwest::Client,
+    api_url: String,
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct OllamaChunk {
+    message: Option<OllamaMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OllamaMessage {
+    content: String,
+}
+
+impl Provider for Ollama {
+    async fn chat(
+        &self,
+        messages: &[ChatMessage],
+        max_tokens: u32,
+        chunks: UnboundedSender<String>,
+    ) -> anyhow::Result<()> {
+        let response = self
+            .http
+            .post(format!("{}/api/chat", self.api_url.trim_end_matches('/')))
+            .json(&json!({
+                "model": self.model,
+                "messages": messages,
+                "stream": true,
+                "options": { "num_predict": max_tokens },
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // Newline-delimited JSON, one object per chunk
+        for_each_line(response, |line| {
+            let chunk: OllamaChunk = serde_json::from_str(line)?;
+            if let Some(error) = chunk.error {
+                bail!("Ollama: {error}");
+            }
+            if let Some(message) = chunk.message {
+                let _ = chunks.send(message.content);
+            }
+            Ok(!chunk.done)
+        })
+        .await
+    }
+}
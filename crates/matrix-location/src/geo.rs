@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+/// The Earth's mean radius, in kilometres.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Rough average speeds, in km/h, for estimating travel times from a
+/// straight-line distance.
+pub const SPEEDS: [(&str, f64); 3] = [("walking", 5.0), ("cycling", 15.0), ("driving", 50.0)];
+
+/// A point on the Earth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Point {
+    /// A point, if the coordinates are in range.
+    pub fn new(latitude: f64, longitude: f64) -> Option<Self> {
+        let in_range = (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude);
+        in_range.then_some(Self {
+            latitude,
+            longitude,
+        })
+    }
+
+    /// Parse a `geo:` URI as used by `m.location` messages, e.g.
+    /// `geo:51.5008,0.1247;u=35`, ignoring any altitude and parameters.
+    pub fn from_geo_uri(uri: &str) -> Option<Self> {
+        let coordinates = uri.strip_prefix("geo:")?.split(';').next()?;
+        let mut parts = coordinates.split(',');
+        let latitude = parts.next()?.trim().parse().ok()?;
+        let longitude = parts.next()?.trim().parse().ok()?;
+        Self::new(latitude, longitude)
+    }
+
+    pub fn geo_uri(self) -> String {
+        format!("geo:{:.6},{:.6}", self.latitude, self.longitude)
+    }
+
+    /// The great-circle distance to another point.
+    pub fn distance_km(self, other: Point) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// How long `km` takes at `speed` km/h, to the minute.
+pub fn travel_time(km: f64, speed: f64) -> Duration {
+    let minutes = (km / speed * 60.0).ceil().max(1.0);
+    Duration::from_secs(minutes as u64 * 60)
+}
@@ -0,0 +1,217 @@
+use std::future::Future;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::geo::Point;
+
+/// A named place, as found by geocoding.
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub name: String,
+    pub point: Point,
+}
+
+/// A geocoding service.
+pub trait Geocoder {
+    /// Find a place by name or address.
+    fn search(&self, query: &str) -> impl Future<Output = anyhow::Result<Option<Place>>> + Send;
+
+    /// Name the place at a point.
+    fn reverse(&self, point: Point) -> impl Future<Output = anyhow::Result<Option<String>>> + Send;
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum GeocoderKind {
+    Nominatim,
+    Photon,
+}
+
+/// One of the supported geocoders, picked at startup.
+#[derive(Clone)]
+pub enum AnyGeocoder {
+    Nominatim(Nominatim),
+    Photon(Photon),
+}
+
+impl AnyGeocoder {
+    pub fn new(kind: GeocoderKind, http: reqwest::Client, api_url: Option<String>) -> Self {
+        match kind {
+            GeocoderKind::Nominatim => Self::Nominatim(Nominatim {
+                http,
+                api_url: api_url
+                    .unwrap_or_else(|| "https://nominatim.openstreetmap.org".to_owned()),
+            }),
+            GeocoderKind::Photon => Self::Photon(Photon {
+                http,
+                api_url: api_url.unwrap_or_else(|| "https://photon.komoot.io".to_owned()),
+            }),
+        }
+    }
+}
+
+impl Geocoder for AnyGeocoder {
+    async fn search(&self, query: &str) -> anyhow::Result<Option<Place>> {
+        match self {
+            Self::Nominatim(geocoder) => geocoder.search(query).await,
+            Self::Photon(geocoder) => geocoder.search(query).await,
+        }
+    }
+
+    async fn reverse(&self, point: Point) -> anyhow::Result<Option<String>> {
+        match self {
+            Self::Nominatim(geocoder) => geocoder.reverse(point).await,
+            Self::Photon(geocoder) => geocoder.reverse(point).await,
+        }
+    }
+}
+
+/// [Nominatim](https://nominatim.org), OpenStreetMap's geocoder. The public
+/// instance asks for no more than one request a second.
+#[derive(Clone)]
+pub struct Nominatim {
+    http: reqwest::Client,
+    api_url: String,
+}
+
+#[derive(Deserialize)]
+struct NominatimPlace {
+    display_name: Option<String>,
+    /// Nominatim gives coordinates as strings.
+    lat: Option<String>,
+    lon: Option<String>,
+}
+
+impl Geocoder for Nominatim {
+    async fn search(&self, query: &str) -> anyhow::Result<Option<Place>> {
+        let places: Vec<NominatimPlace> = self
+            .http
+            .get(format!("{}/search", self.api_url))
+            .query(&[("q", query), ("format", "jsonv2"), ("limit", "1")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(places.into_iter().find_map(|place| {
+            let point = Point::new(place.lat?.parse().ok()?, place.lon?.parse().ok()?)?;
+            Some(Place {
+                name: place.display_name?,
+                point,
+            })
+        }))
+    }
+
+    async fn reverse(&self, point: Point) -> anyhow::Result<Option<String>> {
+        // Nowhere near anything named gives an `error` field and no name
+        let place: NominatimPlace = self
+            .http
+            .get(format!("{}/reverse", self.api_url))
+            .query(&[
+                ("lat", point.latitude.to_string()),
+                ("lon", point.longitude.to_string()),
+                ("format", "jsonv2".to_owned()),
+                ("zoom", "17".to_owned()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(place.display_name)
+    }
+}
+
+/// [Photon](https://photon.komoot.io), a geocoder built on OpenStreetMap
+/// data that's easy to host yourself.
+#[derive(Clone)]
+pub struct Photon {
+    http: reqwest::Client,
+    api_url: String,
+}
+
+#[derive(Deserialize)]
+struct PhotonResponse {
+    #[serde(default)]
+    features: Vec<PhotonFeature>,
+}
+
+#[derive(Deserialize)]
+struct PhotonFeature {
+    geometry: PhotonGeometry,
+    properties: PhotonProperties,
+}
+
+#[derive(Deserialize)]
+struct PhotonGeometry {
+    /// Longitude first, as in GeoJSON.
+    coordinates: (f64, f64),
+}
+
+#[derive(Deserialize)]
+struct PhotonProperties {
+    name: Option<String>,
+    housenumber: Option<String>,
+    street: Option<String>,
+    city: Option<String>,
+    state: Option<String>,
+    country: Option<String>,
+}
+
+impl PhotonProperties {
+    fn display_name(self) -> Option<String> {
+        let street = match (self.street, self.housenumber) {
+            (Some(street), Some(number)) => Some(format!("{street} {number}")),
+            (street, _) => street,
+        };
+        let parts: Vec<String> = [self.name, street, self.city, self.state, self.country]
+            .into_iter()
+            .flatten()
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+impl Photon {
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> anyhow::Result<PhotonResponse> {
+        Ok(self
+            .http
+            .get(format!("{}/{path}", self.api_url))
+            .query(query)
+            .query(&[("limit", "1")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+impl Geocoder for Photon {
+    async fn search(&self, query: &str) -> anyhow::Result<Option<Place>> {
+        let response = self.get("api", &[("q", query.to_owned())]).await?;
+        Ok(response.features.into_iter().find_map(|feature| {
+            let (longitude, latitude) = feature.geometry.coordinates;
+            Some(Place {
+                point: Point::new(latitude, longitude)?,
+                name: feature.properties.display_name()?,
+            })
+        }))
+    }
+
+    async fn reverse(&self, point: Point) -> anyhow::Result<Option<String>> {
+        let response = self
+            .get(
+                "reverse",
+                &[
+                    ("lat", point.latitude.to_string()),
+                    ("lon", point.longitude.to_string()),
+                ],
+            )
+            .await?;
+        Ok(response
+            .features
+            .into_iter()
+            .find_map(|feature| feature.properties.display_name()))
+    }
+}
@@ -0,0 +1,166 @@
+use matrix_bot_core::{
+    can_reply, fetch_message, format_duration, reply, reply_notice, reply_target, strip_command,
+    text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::message::{
+        LocationMessageEventContent, MessageType, OriginalSyncRoomMessageEvent,
+        RoomMessageEventContent,
+    },
+    Room, RoomState,
+};
+use tracing::{instrument, warn};
+
+use crate::{
+    geo::{travel_time, Point, SPEEDS},
+    geocoder::{AnyGeocoder, Geocoder, Place},
+};
+
+const HELP: &str = "Usage:
+!where <place> to share a place's location
+!eta <place> to <place> for how far apart two places are, or reply to a location with !eta <place>";
+
+#[derive(Clone)]
+pub struct Locator {
+    pub geocoder: AnyGeocoder,
+    /// Whether to reply to location shares with the name of the place.
+    pub describe_shares: bool,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    locator: Ctx<Locator>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+
+    if let MessageType::Location(location) = &event.content.msgtype {
+        if locator.describe_shares && can_reply(&room).await {
+            describe(&room, &event, location, &locator).await;
+        }
+        return Ok(());
+    }
+
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+    if let Some(query) = strip_command(body, "!where") {
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        if query.is_empty() || query == "help" {
+            reply_notice(&room, &event, HELP).await;
+            return Ok(());
+        }
+        if let Some(place) = search(&room, &event, query, &locator).await {
+            let content = RoomMessageEventContent::new(MessageType::Location(
+                LocationMessageEventContent::new(place.name, place.point.geo_uri()),
+            ));
+            reply(&room, &event, content).await;
+        }
+    } else if let Some(args) = strip_command(body, "!eta") {
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        eta(&room, &event, args, &locator).await?;
+    }
+    Ok(())
+}
+
+/// Reply to a location share with the name of the place.
+async fn describe(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    location: &LocationMessageEventContent,
+    locator: &Locator,
+) {
+    let Some(point) = Point::from_geo_uri(&location.geo_uri) else {
+        return;
+    };
+    match locator.geocoder.reverse(point).await {
+        Ok(Some(name)) => reply_notice(room, event, format!("📍 {name}")).await,
+        Ok(None) => {}
+        Err(err) => warn!("Failed to look up location: {err:#}"),
+    }
+}
+
+/// How far apart two places are, with rough travel times.
+async fn eta(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    args: &str,
+    locator: &Locator,
+) -> anyhow::Result<()> {
+    // Replying to a location share measures from there
+    let shared = match reply_target(event) {
+        Some(target) => fetch_message(room, target).await?.and_then(|target| {
+            let MessageType::Location(location) = &target.content.msgtype else {
+                return None;
+            };
+            Some(Place {
+                name: location.body.clone(),
+                point: Point::from_geo_uri(&location.geo_uri)?,
+            })
+        }),
+        None => None,
+    };
+
+    let (from, to) = match (shared, args.split_once(" to ")) {
+        (Some(from), _) if !args.is_empty() => (from, args),
+        (None, Some((from, to))) if !from.trim().is_empty() && !to.trim().is_empty() => {
+            let Some(from) = search(room, event, from.trim(), locator).await else {
+                return Ok(());
+            };
+            (from, to.trim())
+        }
+        _ => {
+            reply_notice(room, event, HELP).await;
+            return Ok(());
+        }
+    };
+    let Some(to) = search(room, event, to, locator).await else {
+        return Ok(());
+    };
+
+    let km = from.point.distance_km(to.point);
+    let times: Vec<String> = SPEEDS
+        .iter()
+        .map(|(mode, speed)| format!("{} {mode}", format_duration(travel_time(km, *speed))))
+        .collect();
+    let response = format!(
+        "{} → {}: {km:.1} km as the crow flies, roughly {}.",
+        from.name,
+        to.name,
+        times.join(", ")
+    );
+    reply_notice(room, event, response).await;
+    Ok(())
+}
+
+/// Look up a place, telling the user if it couldn't be found.
+async fn search(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    query: &str,
+    locator: &Locator,
+) -> Option<Place> {
+    match locator.geocoder.search(query).await {
+        Ok(Some(place)) => Some(place),
+        Ok(None) => {
+            reply_notice(room, event, format!("I couldn't find {query}.")).await;
+            None
+        }
+        Err(err) => {
+            warn!("Failed to look up place: {err:#}");
+            reply_notice(room, event, "Sorry, I couldn't look that up.").await;
+            None
+        }
+    }
+}
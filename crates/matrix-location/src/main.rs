@@ -0,0 +1,71 @@
+mod geo;
+mod geocoder;
+mod handlers;
+
+use std::time::Duration;
+
+use clap::Parser;
+use geocoder::{AnyGeocoder, GeocoderKind};
+use handlers::{on_room_message, Locator};
+use matrix_bot_core::{AccountConfig, Bot};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The geocoding service to use
+    #[arg(
+        long,
+        value_enum,
+        default_value = "nominatim",
+        env = "LOCATION_GEOCODER"
+    )]
+    pub geocoder: GeocoderKind,
+
+    /// The geocoder's API URL, for a self-hosted instance [default: the
+    /// public one]
+    #[arg(long, env = "LOCATION_API_URL")]
+    pub api_url: Option<String>,
+
+    /// Reply to location shares with the name of the place
+    #[arg(long, env = "LOCATION_DESCRIBE_SHARES")]
+    pub describe_shares: bool,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    // Public geocoders ask for an identifying user agent
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("matrix-location/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let api_url = config
+        .api_url
+        .map(|url| url.trim_end_matches('/').to_owned());
+    let geocoder = AnyGeocoder::new(config.geocoder, http, api_url);
+
+    let mut bot = Bot::login("matrix-location", config.account_config).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Locator {
+        geocoder,
+        describe_shares: config.describe_shares,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
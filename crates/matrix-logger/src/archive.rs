@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+
+use matrix_bot_core::media::Attachment;
+use matrix_sdk::{
+    event_handler::{Ctx, RawEvent},
+    ruma::events::{
+        room::{
+            message::{MessageType, Relation, SyncRoomMessageEvent},
+            redaction::SyncRoomRedactionEvent,
+        },
+        AnySyncMessageLikeEvent, AnySyncTimelineEvent,
+    },
+    Client, Room,
+};
+use tracing::{debug, instrument, warn};
+
+use crate::timeline::{ArchivedEvent, Timeline};
+
+#[derive(Clone)]
+pub struct Logger {
+    pub timeline: Timeline,
+    /// Where to download media to, if media should be downloaded at all.
+    pub media_dir: Option<PathBuf>,
+}
+
+/// Archive every event in the timeline of every joined room.
+#[instrument(skip_all, fields(event = event.event_id().as_str(), room = room.room_id().as_str()))]
+pub async fn on_timeline_event(
+    event: AnySyncTimelineEvent,
+    raw: RawEvent,
+    room: Room,
+    client: Client,
+    logger: Ctx<Logger>,
+) -> anyhow::Result<()> {
+    let timeline = &logger.timeline;
+    let room_id = room.room_id().as_str();
+    let event_id = event.event_id().as_str();
+
+    let mut archived = ArchivedEvent {
+        event_id: event_id.to_owned(),
+        sender: event.sender().to_string(),
+        ts: i64::from(event.origin_server_ts().get()),
+        event_type: event.event_type().to_string(),
+        body: None,
+        formatted_body: None,
+        json: None,
+        edited: false,
+        redacted: false,
+        media_path: None,
+    };
+    let mut replaces = None;
+    let mut media = None;
+
+    match &event {
+        AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+            SyncRoomMessageEvent::Original(message),
+        )) => match &message.content.relates_to {
+            Some(Relation::Replacement(replacement)) => {
+                let new_content = &replacement.new_content.msgtype;
+                timeline.apply_edit(
+                    replacement.event_id.as_str(),
+                    Some(new_content.body()),
+                    formatted_body(new_content),
+                )?;
+                replaces = Some(replacement.event_id.as_str());
+            }
+            _ => {
+                archived.body = Some(message.content.body().to_owned());
+                archived.formatted_body =
+                    formatted_body(&message.content.msgtype).map(str::to_owned);
+                media = Attachment::from_message(&message.content.msgtype);
+            }
+        },
+        AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomRedaction(
+            SyncRoomRedactionEvent::Original(redaction),
+        )) => {
+            let redacts = redaction
+                .content
+                .redacts
+                .as_ref()
+                .or(redaction.redacts.as_ref());
+            if let Some(redacts) = redacts {
+                debug!(redacts = redacts.as_str(), "Redacting archived event");
+                for path in timeline.redact(redacts.as_str())? {
+                    if let Some(media_dir) = &logger.media_dir {
+                        if let Err(err) = tokio::fs::remove_file(media_dir.join(&path)).await {
+                            warn!("Failed to delete redacted media {path}: {err}");
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+
+    // Events that arrive already redacted are archived as tombstones
+    let redacted = match &event {
+        AnySyncTimelineEvent::MessageLike(event) => event.original_content().is_none(),
+        AnySyncTimelineEvent::State(event) => event.original_content().is_none(),
+    };
+    if redacted {
+        archived.redacted = true;
+    } else {
+        archived.json = Some(serde_json::from_str(raw.get())?);
+    }
+
+    if !timeline.insert(room_id, &archived, replaces)? {
+        return Ok(());
+    }
+
+    if let (Some(attachment), Some(media_dir)) = (media, &logger.media_dir) {
+        match download(&client, room_id, event_id, &attachment, media_dir).await {
+            Ok(path) => timeline.set_media_path(event_id, &path)?,
+            Err(err) => warn!("Failed to download media: {err:#}"),
+        }
+    }
+    Ok(())
+}
+
+fn formatted_body(msgtype: &MessageType) -> Option<&str> {
+    let formatted = match msgtype {
+        MessageType::Text(content) => content.formatted.as_ref(),
+        MessageType::Notice(content) => content.formatted.as_ref(),
+        MessageType::Emote(content) => content.formatted.as_ref(),
+        _ => None,
+    };
+    formatted.map(|formatted| formatted.body.as_str())
+}
+
+/// Download an event's media, returning where it was saved relative to
+/// `media_dir`.
+async fn download(
+    client: &Client,
+    room_id: &str,
+    event_id: &str,
+    attachment: &Attachment,
+    media_dir: &Path,
+) -> anyhow::Result<String> {
+    // Everything is archived, however big
+    let data = attachment.download(client, u64::MAX).await?;
+
+    // Event IDs are unique, unlike filenames, but keep the extension so the
+    // files open in the right program
+    let extension = Path::new(&attachment.filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .filter(|extension| extension.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(|extension| format!(".{extension}"))
+        .unwrap_or_default();
+    let path = format!("{}/{}{extension}", sanitize(room_id), sanitize(event_id));
+
+    let full_path = media_dir.join(&path);
+    if let Some(parent) = full_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&full_path, data).await?;
+    Ok(path)
+}
+
+/// Make an ID safe to use as a file name.
+fn sanitize(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
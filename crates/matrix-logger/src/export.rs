@@ -0,0 +1,148 @@
+use std::fmt::Write;
+
+use chrono::DateTime;
+use matrix_bot_core::html::escape;
+
+use crate::timeline::ArchivedEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Json,
+    Text,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.to_lowercase().as_str() {
+            "" | "html" => Some(Format::Html),
+            "json" => Some(Format::Json),
+            "text" | "txt" => Some(Format::Text),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Html => "html",
+            Format::Json => "json",
+            Format::Text => "txt",
+        }
+    }
+
+    pub fn mime(self) -> mime::Mime {
+        match self {
+            Format::Html => mime::TEXT_HTML_UTF_8,
+            Format::Json => mime::APPLICATION_JSON,
+            Format::Text => mime::TEXT_PLAIN_UTF_8,
+        }
+    }
+}
+
+pub fn export(
+    format: Format,
+    room_name: &str,
+    events: &[ArchivedEvent],
+) -> anyhow::Result<Vec<u8>> {
+    Ok(match format {
+        Format::Json => serde_json::to_vec_pretty(events)?,
+        Format::Text => text(events).into_bytes(),
+        Format::Html => html(room_name, events).into_bytes(),
+    })
+}
+
+fn timestamp(event: &ArchivedEvent) -> String {
+    DateTime::from_timestamp_millis(event.ts)
+        .unwrap_or_default()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// What to show for an event without a body.
+fn placeholder(event: &ArchivedEvent) -> Option<String> {
+    if event.redacted {
+        Some("[redacted]".to_owned())
+    } else if event.body.is_none() && event.event_type != "m.room.redaction" {
+        Some(format!("[{}]", event.event_type))
+    } else {
+        None
+    }
+}
+
+fn text(events: &[ArchivedEvent]) -> String {
+    let mut text = String::new();
+    for event in events {
+        if event.event_type == "m.room.redaction" {
+            continue;
+        }
+        let body = placeholder(event)
+            .or_else(|| event.body.clone())
+            .unwrap_or_default();
+        let edited = if event.edited { " (edited)" } else { "" };
+        let _ = writeln!(
+            text,
+            "[{}] <{}> {body}{edited}",
+            timestamp(event),
+            event.sender
+        );
+    }
+    text
+}
+
+fn html(room_name: &str, events: &[ArchivedEvent]) -> String {
+    let room_name = escape(room_name);
+    let mut html = format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{room_name}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60em; margin: auto; }}
+.event {{ margin: 0.25em 0; }}
+.time, .note {{ color: #6e7781; }}
+.sender {{ font-weight: bold; }}
+.body {{ white-space: pre-wrap; }}
+</style>
+</head>
+<body>
+<h1>{room_name}</h1>
+"
+    );
+    for event in events {
+        if event.event_type == "m.room.redaction" {
+            continue;
+        }
+        let _ = write!(
+            html,
+            "<div class=\"event\" id=\"{}\"><span class=\"time\">{}</span> <span class=\"sender\">{}</span> ",
+            escape(&event.event_id),
+            timestamp(event),
+            escape(&event.sender)
+        );
+        // Only the plain body is used, as the formatted body is untrusted
+        // HTML
+        match (placeholder(event), &event.body) {
+            (Some(placeholder), _) => {
+                let _ = write!(html, "<span class=\"note\">{}</span>", escape(&placeholder));
+            }
+            (None, Some(body)) => {
+                let _ = write!(html, "<span class=\"body\">{}</span>", escape(body));
+            }
+            (None, None) => {}
+        }
+        if let Some(media_path) = &event.media_path {
+            let _ = write!(
+                html,
+                " <a href=\"media/{}\">[media]</a>",
+                escape(media_path)
+            );
+        }
+        if event.edited {
+            html += " <span class=\"note\">(edited)</span>";
+        }
+        html += "</div>\n";
+    }
+    html += "</body>\n</html>\n";
+    html
+}
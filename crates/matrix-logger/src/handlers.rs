@@ -0,0 +1,55 @@
+use chrono::Utc;
+use matrix_bot_core::{can_reply, is_moderator, media, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState,
+};
+use tracing::{info, instrument};
+
+use crate::{
+    archive::Logger,
+    export::{export, Format},
+};
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    logger: Ctx<Logger>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!export")) else {
+        return Ok(());
+    };
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+    let Some(format) = Format::parse(args) else {
+        reply_notice(&room, &event, "Usage: !export [html|json|text]").await;
+        return Ok(());
+    };
+    // The archive may hold history that newer members can't otherwise see
+    if !is_moderator(&room, &event.sender).await? {
+        reply_notice(&room, &event, "Only moderators can export this room.").await;
+        return Ok(());
+    }
+
+    let events = logger.timeline.room_events(room.room_id().as_str())?;
+    let room_name = room.name().unwrap_or_else(|| room.room_id().to_string());
+    let data = export(format, &room_name, &events)?;
+    let filename = format!(
+        "export-{}.{}",
+        Utc::now().format("%Y-%m-%d"),
+        format.extension()
+    );
+
+    media::send_file(&room, &filename, &format.mime(), data).await?;
+    info!(
+        events = events.len(),
+        format = format.extension(),
+        "Exported room"
+    );
+    Ok(())
+}
@@ -0,0 +1,53 @@
+mod archive;
+mod export;
+mod handlers;
+mod timeline;
+
+use archive::{on_timeline_event, Logger};
+use clap::Parser;
+use handlers::on_room_message;
+use matrix_bot_core::{AccountConfig, Bot};
+use timeline::Timeline;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Download images, files, video and audio into the archive
+    #[arg(long, env = "LOGGER_DOWNLOAD_MEDIA")]
+    pub download_media: bool,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-logger", config.account_config).await?;
+    let timeline = Timeline::open(&bot).await?;
+    let media_dir = config.download_media.then(|| bot.data_dir().join("media"));
+
+    // Unlike the other bots, archive the events from the initial sync too.
+    // Events that were already archived are skipped.
+    bot.client().add_event_handler_context(Logger {
+        timeline,
+        media_dir,
+    });
+    bot.client().add_event_handler(on_timeline_event);
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,162 @@
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, Row};
+use serde::Serialize;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE events (
+        event_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        event_type TEXT NOT NULL,
+        body TEXT,
+        formatted_body TEXT,
+        json TEXT,
+        -- The event this one edits, for edits
+        replaces TEXT,
+        edited INTEGER NOT NULL DEFAULT 0,
+        redacted INTEGER NOT NULL DEFAULT 0,
+        media_path TEXT
+    );
+    CREATE INDEX events_room ON events (room_id, ts);"];
+
+/// An event as archived. Redacted events keep their metadata but lose
+/// their content.
+#[derive(Debug, Serialize)]
+pub struct ArchivedEvent {
+    pub event_id: String,
+    pub sender: String,
+    /// Milliseconds since the Unix epoch.
+    pub ts: i64,
+    pub event_type: String,
+    /// The text of the message, as last edited.
+    pub body: Option<String>,
+    pub formatted_body: Option<String>,
+    /// The full event as received, before any edits.
+    pub json: Option<serde_json::Value>,
+    pub edited: bool,
+    pub redacted: bool,
+    /// Where downloaded media was saved, relative to the media directory.
+    pub media_path: Option<String>,
+}
+
+/// The archive of every joined room's timeline, in SQLite.
+#[derive(Clone)]
+pub struct Timeline {
+    store: Store,
+}
+
+impl Timeline {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Archive an event, returning `false` if it was already archived.
+    pub fn insert(
+        &self,
+        room_id: &str,
+        event: &ArchivedEvent,
+        replaces: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let inserted = conn.execute(
+                "INSERT OR IGNORE INTO events
+                (event_id, room_id, sender, ts, event_type, body, formatted_body, json, replaces, redacted)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    event.event_id,
+                    room_id,
+                    event.sender,
+                    event.ts,
+                    event.event_type,
+                    event.body,
+                    event.formatted_body,
+                    event.json.as_ref().map(|json| json.to_string()),
+                    replaces,
+                    event.redacted,
+                ],
+            )?;
+            Ok(inserted > 0)
+        })
+    }
+
+    /// Replace the content of an edited message with its new content.
+    pub fn apply_edit(
+        &self,
+        event_id: &str,
+        body: Option<&str>,
+        formatted_body: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE events SET body = ?2, formatted_body = ?3, edited = 1
+                WHERE event_id = ?1 AND redacted = 0",
+                params![event_id, body, formatted_body],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Turn an event and its edits into tombstones, returning the media
+    /// paths that should be deleted.
+    pub fn redact(&self, event_id: &str) -> anyhow::Result<Vec<String>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT media_path FROM events
+                WHERE (event_id = ?1 OR replaces = ?1) AND media_path IS NOT NULL",
+            )?;
+            let media = statement
+                .query_map([event_id], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            conn.execute(
+                "UPDATE events
+                SET body = NULL, formatted_body = NULL, json = NULL, media_path = NULL, redacted = 1
+                WHERE event_id = ?1 OR replaces = ?1",
+                [event_id],
+            )?;
+            Ok(media)
+        })
+    }
+
+    pub fn set_media_path(&self, event_id: &str, path: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE events SET media_path = ?2 WHERE event_id = ?1",
+                [event_id, path],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// A room's archived events in timeline order, leaving out edits as
+    /// they've been applied to the events they edit.
+    pub fn room_events(&self, room_id: &str) -> anyhow::Result<Vec<ArchivedEvent>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT event_id, sender, ts, event_type, body, formatted_body, json, edited, redacted, media_path
+                FROM events WHERE room_id = ?1 AND replaces IS NULL ORDER BY ts, rowid",
+            )?;
+            let events = statement
+                .query_map([room_id], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(events)
+        })
+    }
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<ArchivedEvent> {
+    let json: Option<String> = row.get(6)?;
+    Ok(ArchivedEvent {
+        event_id: row.get(0)?,
+        sender: row.get(1)?,
+        ts: row.get(2)?,
+        event_type: row.get(3)?,
+        body: row.get(4)?,
+        formatted_body: row.get(5)?,
+        json: json.and_then(|json| serde_json::from_str(&json).ok()),
+        edited: row.get(7)?,
+        redacted: row.get(8)?,
+        media_path: row.get(9)?,
+    })
+}
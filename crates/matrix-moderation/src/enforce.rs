@@ -0,0 +1,366 @@
+use futures_util::{stream, StreamExt};
+use matrix_bot_core::{
+    policy::{load_rules, Rule, RuleKind},
+    send_or_log_error,
+};
+use matrix_sdk::{
+    room::MessagesOptions,
+    ruma::{
+        events::{
+            room::{
+                member::MembershipState, message::RoomMessageEventContent,
+                server_acl::RoomServerAclEventContent,
+            },
+            AnySyncTimelineEvent, SyncOrStrippedState, SyncStateEvent,
+        },
+        OwnedRoomId, UserId,
+    },
+    Client, Room, RoomMemberships, RoomState,
+};
+use tracing::{info, warn};
+
+use crate::records::{AuditEntry, Records};
+
+/// How many protected rooms are worked on at once when enforcing rules.
+const MAX_CONCURRENT_ROOMS: usize = 8;
+
+/// How many events back `!redact-recent` looks in each room.
+const REDACT_SCAN_LIMIT: usize = 500;
+
+#[derive(Clone)]
+pub struct Moderation {
+    pub client: Client,
+    pub records: Records,
+    /// The room commands are taken from and the audit trail is posted to.
+    pub admin_room: OwnedRoomId,
+}
+
+impl Moderation {
+    /// Add an entry to the audit trail, and post it in the admin room.
+    pub async fn record(&self, entry: AuditEntry) {
+        if let Err(err) = self.records.audit(&entry) {
+            warn!("Failed to record audit entry: {err}");
+        }
+        info!(
+            actor = entry.actor.as_str(),
+            action = entry.action.as_str(),
+            target = entry.target.as_str(),
+            room = entry.room_id.as_deref(),
+            "Moderation action"
+        );
+
+        let Some(admin_room) = self.client.get_room(&self.admin_room) else {
+            return;
+        };
+        let mut text = format!("📝 {} {} {}", entry.actor, entry.action, entry.target);
+        if let Some(room_id) = &entry.room_id {
+            text += &format!(" in {room_id}");
+        }
+        if let Some(reason) = &entry.reason {
+            text += &format!(": {reason}");
+        }
+        send_or_log_error(&admin_room, RoomMessageEventContent::notice_plain(text)).await;
+    }
+
+    /// The protected rooms the bot is in.
+    pub fn protected_rooms(&self) -> anyhow::Result<Vec<Room>> {
+        Ok(self
+            .records
+            .protected_rooms()?
+            .iter()
+            .filter_map(|room_id| self.client.get_room(room_id))
+            .filter(|room| room.state() == RoomState::Joined)
+            .collect())
+    }
+
+    /// Ban a user from every protected room, returning how many rooms they
+    /// were banned from.
+    pub async fn ban(
+        &self,
+        actor: &str,
+        user_id: &UserId,
+        reason: Option<&str>,
+    ) -> anyhow::Result<usize> {
+        let mut banned = 0;
+        for room in self.protected_rooms()? {
+            let already_banned = room
+                .get_member_no_sync(user_id)
+                .await?
+                .is_some_and(|member| *member.membership() == MembershipState::Ban);
+            if already_banned {
+                continue;
+            }
+            match room.ban_user(user_id, reason).await {
+                Ok(()) => {
+                    banned += 1;
+                    self.record(AuditEntry::new(
+                        actor,
+                        "banned",
+                        user_id.as_str(),
+                        Some(room.room_id().as_str()),
+                        reason,
+                    ))
+                    .await;
+                }
+                Err(err) => warn!(
+                    room = room.room_id().as_str(),
+                    "Failed to ban {user_id}: {err}"
+                ),
+            }
+        }
+        Ok(banned)
+    }
+
+    /// Lift a user's ban from every protected room, returning how many
+    /// rooms they were unbanned from.
+    pub async fn unban(&self, actor: &str, user_id: &UserId) -> anyhow::Result<usize> {
+        let mut unbanned = 0;
+        for room in self.protected_rooms()? {
+            let banned = room
+                .get_member_no_sync(user_id)
+                .await?
+                .is_some_and(|member| *member.membership() == MembershipState::Ban);
+            if !banned {
+                continue;
+            }
+            match room.unban_user(user_id, None).await {
+                Ok(()) => {
+                    unbanned += 1;
+                    self.record(AuditEntry::new(
+                        actor,
+                        "unbanned",
+                        user_id.as_str(),
+                        Some(room.room_id().as_str()),
+                        None,
+                    ))
+                    .await;
+                }
+                Err(err) => warn!(
+                    room = room.room_id().as_str(),
+                    "Failed to unban {user_id}: {err}"
+                ),
+            }
+        }
+        Ok(unbanned)
+    }
+
+    /// Kick a user from every protected room they're in, returning how many
+    /// rooms they were kicked from.
+    pub async fn kick(
+        &self,
+        actor: &str,
+        user_id: &UserId,
+        reason: Option<&str>,
+    ) -> anyhow::Result<usize> {
+        let mut kicked = 0;
+        for room in self.protected_rooms()? {
+            let joined = room
+                .get_member_no_sync(user_id)
+                .await?
+                .is_some_and(|member| *member.membership() == MembershipState::Join);
+            if !joined {
+                continue;
+            }
+            match room.kick_user(user_id, reason).await {
+                Ok(()) => {
+                    kicked += 1;
+                    self.record(AuditEntry::new(
+                        actor,
+                        "kicked",
+                        user_id.as_str(),
+                        Some(room.room_id().as_str()),
+                        reason,
+                    ))
+                    .await;
+                }
+                Err(err) => warn!(
+                    room = room.room_id().as_str(),
+                    "Failed to kick {user_id}: {err}"
+                ),
+            }
+        }
+        Ok(kicked)
+    }
+
+    /// Redact up to `limit` of a user's recent events in each protected
+    /// room, returning how many were redacted.
+    pub async fn redact_recent(
+        &self,
+        actor: &str,
+        user_id: &UserId,
+        limit: usize,
+    ) -> anyhow::Result<usize> {
+        let mut total = 0;
+        for room in self.protected_rooms()? {
+            let mut redacted = 0;
+            let mut scanned = 0;
+            let mut options = MessagesOptions::backward();
+
+            'pages: while scanned < REDACT_SCAN_LIMIT && redacted < limit {
+                let messages = room.messages(options).await?;
+                if messages.chunk.is_empty() {
+                    break;
+                }
+                for event in &messages.chunk {
+                    scanned += 1;
+                    let Ok(AnySyncTimelineEvent::MessageLike(event)) = event.raw().deserialize()
+                    else {
+                        continue;
+                    };
+                    // Already redacted events have nothing left to remove
+                    if event.sender() != user_id || event.original_content().is_none() {
+                        continue;
+                    }
+                    match room.redact(event.event_id(), Some("Spam"), None).await {
+                        Ok(_) => redacted += 1,
+                        Err(err) => {
+                            warn!(room = room.room_id().as_str(), "Failed to redact: {err}")
+                        }
+                    }
+                    if redacted >= limit {
+                        break 'pages;
+                    }
+                }
+                let Some(end) = messages.end else {
+                    break;
+                };
+                options = MessagesOptions::backward().from(Some(end.as_str()));
+            }
+
+            if redacted > 0 {
+                self.record(AuditEntry::new(
+                    actor,
+                    &format!("redacted {redacted} events from"),
+                    user_id.as_str(),
+                    Some(room.room_id().as_str()),
+                    None,
+                ))
+                .await;
+            }
+            total += redacted;
+        }
+        Ok(total)
+    }
+
+    /// Apply every watched policy list to every protected room.
+    pub async fn sync_policies(&self) -> anyhow::Result<usize> {
+        let rules = load_rules(&self.client, &self.records.policy_lists()?).await;
+        self.enforce(&rules).await
+    }
+
+    /// Apply ban rules to the members and server ACLs of every protected
+    /// room, a few rooms at a time, returning how many bans were issued.
+    pub async fn enforce(&self, rules: &[Rule]) -> anyhow::Result<usize> {
+        if rules.is_empty() {
+            return Ok(0);
+        }
+        let banned: Vec<anyhow::Result<usize>> = stream::iter(self.protected_rooms()?)
+            .map(|room| async move {
+                let members = room
+                    .members(RoomMemberships::JOIN | RoomMemberships::INVITE)
+                    .await?;
+                let mut banned = 0;
+                for member in members {
+                    banned += self.enforce_on_user(&room, member.user_id(), rules).await;
+                }
+                self.enforce_server_acl(&room, rules).await;
+                Ok(banned)
+            })
+            .buffer_unordered(MAX_CONCURRENT_ROOMS)
+            .collect()
+            .await;
+        banned.into_iter().sum()
+    }
+
+    /// Ban a user from a room if a rule matches them, returning how many
+    /// bans were issued.
+    pub async fn enforce_on_user(&self, room: &Room, user_id: &UserId, rules: &[Rule]) -> usize {
+        if self.client.user_id() == Some(user_id) {
+            return 0;
+        }
+        let Some(rule) = rules.iter().find(|rule| rule.matches_user(user_id)) else {
+            return 0;
+        };
+        let reason = (!rule.reason.is_empty()).then_some(rule.reason.as_str());
+        match room.ban_user(user_id, reason).await {
+            Ok(()) => {
+                self.record(AuditEntry::new(
+                    format!("policy list {}", rule.list),
+                    "banned",
+                    user_id.as_str(),
+                    Some(room.room_id().as_str()),
+                    reason,
+                ))
+                .await;
+                1
+            }
+            Err(err) => {
+                warn!(
+                    room = room.room_id().as_str(),
+                    "Failed to ban {user_id}: {err}"
+                );
+                0
+            }
+        }
+    }
+
+    /// Deny servers banned by `rules` in a room's server ACL.
+    async fn enforce_server_acl(&self, room: &Room, rules: &[Rule]) {
+        let Some(own_server) = self
+            .client
+            .user_id()
+            .map(|user_id| user_id.server_name().to_owned())
+        else {
+            return;
+        };
+        let mut acl = match room
+            .get_state_event_static::<RoomServerAclEventContent>()
+            .await
+        {
+            Ok(Some(raw)) => match raw.deserialize() {
+                Ok(SyncOrStrippedState::Sync(SyncStateEvent::Original(event))) => event.content,
+                _ => RoomServerAclEventContent::new(true, vec!["*".to_owned()], Vec::new()),
+            },
+            Ok(None) => RoomServerAclEventContent::new(true, vec!["*".to_owned()], Vec::new()),
+            Err(err) => {
+                warn!(
+                    room = room.room_id().as_str(),
+                    "Failed to get server ACL: {err}"
+                );
+                return;
+            }
+        };
+
+        let mut added = Vec::new();
+        for rule in rules.iter().filter(|rule| rule.kind == RuleKind::Server) {
+            // Never lock ourselves out
+            if rule.matches_server(&own_server) || acl.deny.contains(&rule.entity) {
+                continue;
+            }
+            acl.deny.push(rule.entity.clone());
+            added.push(rule);
+        }
+        if added.is_empty() {
+            return;
+        }
+
+        if let Err(err) = room.send_state_event(acl).await {
+            warn!(
+                room = room.room_id().as_str(),
+                "Failed to update server ACL: {err}"
+            );
+            return;
+        }
+        for rule in added {
+            let reason = (!rule.reason.is_empty()).then_some(rule.reason.as_str());
+            self.record(AuditEntry::new(
+                format!("policy list {}", rule.list),
+                "denied server",
+                rule.entity.as_str(),
+                Some(room.room_id().as_str()),
+                reason,
+            ))
+            .await;
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use chrono::DateTime;
+use matrix_bot_core::{
+    can_reply, is_moderator,
+    policy::{load_rules, Rule, RuleKind},
+    reply_notice,
+    rooms::resolve_room,
+    strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::{
+            policy::rule::{
+                server::PolicyRuleServerEventContent, user::PolicyRuleUserEventContent,
+                PolicyRuleEventContent,
+            },
+            room::{
+                member::{MembershipState, OriginalSyncRoomMemberEvent},
+                message::OriginalSyncRoomMessageEvent,
+            },
+            OriginalSyncStateEvent,
+        },
+        OwnedUserId,
+    },
+    Room, RoomState,
+};
+use tracing::{info, instrument};
+
+use crate::{enforce::Moderation, records::AuditEntry};
+
+/// How many events `!redact-recent` removes per room by default.
+const DEFAULT_REDACT_LIMIT: usize = 50;
+
+/// How many entries `!audit` shows by default.
+const DEFAULT_AUDIT_COUNT: usize = 10;
+
+const HELP: &str = "Commands, from this room only:
+!ban @user:example.org [reason]
+!unban @user:example.org
+!kick @user:example.org [reason]
+!redact-recent @user:example.org [count]
+!protect <room> / !unprotect <room> / !protected
+!watch <policy list room> / !unwatch <policy list room> / !lists
+!sync to apply the policy lists now
+!audit [count]";
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    moderation: Ctx<Moderation>,
+) -> anyhow::Result<()> {
+    if room.room_id() != moderation.admin_room || room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+    let Some((command, args)) = [
+        "!ban",
+        "!unban",
+        "!kick",
+        "!redact-recent",
+        "!protect",
+        "!unprotect",
+        "!protected",
+        "!watch",
+        "!unwatch",
+        "!lists",
+        "!sync",
+        "!audit",
+        "!mod",
+    ]
+    .into_iter()
+    .find_map(|command| Some((command, strip_command(body, command)?))) else {
+        return Ok(());
+    };
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+    if !is_moderator(&room, &event.sender).await? {
+        reply_notice(&room, &event, "Only moderators of this room can use me.").await;
+        return Ok(());
+    }
+
+    let response = run_command(command, args, &event, &moderation).await?;
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn run_command(
+    command: &str,
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    moderation: &Moderation,
+) -> anyhow::Result<String> {
+    let actor = event.sender.as_str();
+    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+    let reason = (!rest.is_empty()).then_some(rest);
+
+    Ok(match command {
+        "!ban" | "!unban" | "!kick" | "!redact-recent" => {
+            let Ok(user_id) = OwnedUserId::try_from(first) else {
+                return Ok(format!(
+                    "Give a full user ID, e.g. `{command} @spammer:example.org`."
+                ));
+            };
+            match command {
+                "!ban" => {
+                    let count = moderation.ban(actor, &user_id, reason).await?;
+                    format!("Banned {user_id} from {count} rooms.")
+                }
+                "!unban" => {
+                    let count = moderation.unban(actor, &user_id).await?;
+                    format!("Unbanned {user_id} from {count} rooms.")
+                }
+                "!kick" => {
+                    let count = moderation.kick(actor, &user_id, reason).await?;
+                    format!("Kicked {user_id} from {count} rooms.")
+                }
+                _ => {
+                    let limit = match reason {
+                        Some(limit) => match limit.parse() {
+                            Ok(limit) => limit,
+                            Err(_) => return Ok("The count should be a number.".to_owned()),
+                        },
+                        None => DEFAULT_REDACT_LIMIT,
+                    };
+                    let count = moderation.redact_recent(actor, &user_id, limit).await?;
+                    format!("Redacted {count} events from {user_id}.")
+                }
+            }
+        }
+        "!protect" | "!unprotect" | "!watch" | "!unwatch" => {
+            if first.is_empty() {
+                return Ok(format!("Which room? Use `{command} #room:example.org`."));
+            }
+            let room_id = match resolve_room(&moderation.client, first).await {
+                Ok(room_id) => room_id,
+                Err(err) => return Ok(format!("I can't find {first}: {err}")),
+            };
+            let records = &moderation.records;
+
+            let (changed, action) = match command {
+                "!protect" | "!watch" => {
+                    // We need to be in the room to enforce or read anything in it
+                    moderation.client.join_room_by_id(&room_id).await?;
+                    if command == "!protect" {
+                        (records.set_protected(room_id.as_str(), true)?, "protected")
+                    } else {
+                        (
+                            records.set_policy_list(room_id.as_str(), true)?,
+                            "watched policy list",
+                        )
+                    }
+                }
+                "!unprotect" => (
+                    records.set_protected(room_id.as_str(), false)?,
+                    "unprotected",
+                ),
+                _ => (
+                    records.set_policy_list(room_id.as_str(), false)?,
+                    "unwatched policy list",
+                ),
+            };
+            if !changed {
+                return Ok("Nothing to change.".to_owned());
+            }
+            moderation
+                .record(AuditEntry::new(actor, action, room_id.as_str(), None, None))
+                .await;
+
+            if matches!(command, "!protect" | "!watch") {
+                let banned = moderation.sync_policies().await?;
+                format!("Done, {action} {room_id}. Issued {banned} bans from policy lists.")
+            } else {
+                format!("Done, {action} {room_id}.")
+            }
+        }
+        "!protected" | "!lists" => {
+            let rooms = if command == "!protected" {
+                moderation.records.protected_rooms()?
+            } else {
+                moderation.records.policy_lists()?
+            };
+            if rooms.is_empty() {
+                "None yet.".to_owned()
+            } else {
+                rooms
+                    .iter()
+                    .map(|room_id| room_id.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        "!sync" => {
+            let banned = moderation.sync_policies().await?;
+            format!("Applied the policy lists, issuing {banned} bans.")
+        }
+        "!audit" => {
+            let count = first.parse().unwrap_or(DEFAULT_AUDIT_COUNT);
+            let entries = moderation.records.recent_audit(count)?;
+            if entries.is_empty() {
+                return Ok("The audit trail is empty.".to_owned());
+            }
+            entries
+                .iter()
+                .map(|entry| {
+                    let time = DateTime::from_timestamp(entry.ts, 0).unwrap_or_default();
+                    let mut line = format!(
+                        "{} {} {} {}",
+                        time.format("%Y-%m-%d %H:%M"),
+                        entry.actor,
+                        entry.action,
+                        entry.target
+                    );
+                    if let Some(room_id) = &entry.room_id {
+                        line += &format!(" in {room_id}");
+                    }
+                    if let Some(reason) = &entry.reason {
+                        line += &format!(": {reason}");
+                    }
+                    line
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        _ => HELP.to_owned(),
+    })
+}
+
+/// Ban people who join protected rooms if a policy list says so.
+#[instrument(skip_all, fields(room = room.room_id().as_str(), user = event.state_key.as_str()))]
+pub async fn on_room_member(
+    event: OriginalSyncRoomMemberEvent,
+    room: Room,
+    moderation: Ctx<Moderation>,
+) -> anyhow::Result<()> {
+    if !matches!(
+        event.content.membership,
+        MembershipState::Join | MembershipState::Invite
+    ) || !moderation.records.is_protected(room.room_id().as_str())?
+    {
+        return Ok(());
+    }
+
+    let rules = load_rules(&moderation.client, &moderation.records.policy_lists()?).await;
+    moderation
+        .enforce_on_user(&room, &event.state_key, &rules)
+        .await;
+    Ok(())
+}
+
+/// Apply new user bans from watched policy lists.
+#[instrument(skip_all, fields(room = room.room_id().as_str()))]
+pub async fn on_policy_user_rule(
+    event: OriginalSyncStateEvent<PolicyRuleUserEventContent>,
+    room: Room,
+    moderation: Ctx<Moderation>,
+) -> anyhow::Result<()> {
+    on_policy_rule(RuleKind::User, &event.content.0, &room, &moderation).await
+}
+
+/// Apply new server bans from watched policy lists.
+#[instrument(skip_all, fields(room = room.room_id().as_str()))]
+pub async fn on_policy_server_rule(
+    event: OriginalSyncStateEvent<PolicyRuleServerEventContent>,
+    room: Room,
+    moderation: Ctx<Moderation>,
+) -> anyhow::Result<()> {
+    on_policy_rule(RuleKind::Server, &event.content.0, &room, &moderation).await
+}
+
+async fn on_policy_rule(
+    kind: RuleKind,
+    content: &PolicyRuleEventContent,
+    room: &Room,
+    moderation: &Moderation,
+) -> anyhow::Result<()> {
+    if !moderation.records.is_policy_list(room.room_id().as_str())? {
+        return Ok(());
+    }
+    let Some(rule) = Rule::from_content(kind, content, room.room_id().to_owned()) else {
+        return Ok(());
+    };
+    info!(entity = rule.entity.as_str(), "New ban rule");
+    moderation.enforce(&[rule]).await?;
+    Ok(())
+}
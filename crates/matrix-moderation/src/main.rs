@@ -0,0 +1,60 @@
+mod enforce;
+mod handlers;
+mod records;
+
+use clap::Parser;
+use enforce::Moderation;
+use handlers::{on_policy_server_rule, on_policy_user_rule, on_room_member, on_room_message};
+use matrix_bot_core::{AccountConfig, Bot};
+use matrix_sdk::ruma::OwnedRoomId;
+use records::Records;
+use tracing::{info, warn};
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The room to take commands from and post the audit trail to
+    #[arg(long, env = "MODERATION_ADMIN_ROOM")]
+    pub admin_room: OwnedRoomId,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-moderation", config.account_config).await?;
+    let records = Records::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let moderation = Moderation {
+        client: bot.client().clone(),
+        records,
+        admin_room: config.admin_room,
+    };
+
+    // Catch up on anything the policy lists banned while we were offline
+    match moderation.sync_policies().await {
+        Ok(banned) => info!(banned, "Applied policy lists"),
+        Err(err) => warn!("Failed to apply policy lists: {err}"),
+    }
+
+    // Now that we've synced, attach handlers for new events.
+    bot.client().add_event_handler_context(moderation);
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_room_member);
+    bot.client().add_event_handler(on_policy_user_rule);
+    bot.client().add_event_handler(on_policy_server_rule);
+
+    bot.run().await
+}
@@ -0,0 +1,178 @@
+use chrono::Utc;
+use matrix_bot_core::{store::Store, Bot};
+use matrix_sdk::ruma::OwnedRoomId;
+use rusqlite::params;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE protected_rooms (
+        room_id TEXT PRIMARY KEY
+    );
+    CREATE TABLE policy_lists (
+        room_id TEXT PRIMARY KEY
+    );
+    CREATE TABLE audit (
+        id INTEGER PRIMARY KEY,
+        ts INTEGER NOT NULL,
+        actor TEXT NOT NULL,
+        action TEXT NOT NULL,
+        target TEXT NOT NULL,
+        room_id TEXT,
+        reason TEXT
+    );"];
+
+/// An action recorded in the audit trail.
+#[derive(Debug)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch.
+    pub ts: i64,
+    /// Who asked for the action, or the policy list that caused it.
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub room_id: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// Protected rooms, watched policy lists and the audit trail, persisted in
+/// SQLite.
+#[derive(Clone)]
+pub struct Records {
+    store: Store,
+}
+
+impl Records {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn protected_rooms(&self) -> anyhow::Result<Vec<OwnedRoomId>> {
+        self.room_ids("SELECT room_id FROM protected_rooms ORDER BY room_id")
+    }
+
+    pub fn is_protected(&self, room_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM protected_rooms WHERE room_id = ?1)",
+                [room_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    /// Protect or stop protecting a room, returning whether anything changed.
+    pub fn set_protected(&self, room_id: &str, protected: bool) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let changed = if protected {
+                conn.execute(
+                    "INSERT OR IGNORE INTO protected_rooms (room_id) VALUES (?1)",
+                    [room_id],
+                )?
+            } else {
+                conn.execute("DELETE FROM protected_rooms WHERE room_id = ?1", [room_id])?
+            };
+            Ok(changed > 0)
+        })
+    }
+
+    pub fn policy_lists(&self) -> anyhow::Result<Vec<OwnedRoomId>> {
+        self.room_ids("SELECT room_id FROM policy_lists ORDER BY room_id")
+    }
+
+    pub fn is_policy_list(&self, room_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM policy_lists WHERE room_id = ?1)",
+                [room_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    /// Watch or stop watching a policy list, returning whether anything
+    /// changed.
+    pub fn set_policy_list(&self, room_id: &str, watched: bool) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let changed = if watched {
+                conn.execute(
+                    "INSERT OR IGNORE INTO policy_lists (room_id) VALUES (?1)",
+                    [room_id],
+                )?
+            } else {
+                conn.execute("DELETE FROM policy_lists WHERE room_id = ?1", [room_id])?
+            };
+            Ok(changed > 0)
+        })
+    }
+
+    pub fn audit(&self, entry: &AuditEntry) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO audit (ts, actor, action, target, room_id, reason)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    entry.ts,
+                    entry.actor,
+                    entry.action,
+                    entry.target,
+                    entry.room_id,
+                    entry.reason
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// The most recent audit entries, newest first.
+    pub fn recent_audit(&self, limit: usize) -> anyhow::Result<Vec<AuditEntry>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT ts, actor, action, target, room_id, reason FROM audit
+                ORDER BY id DESC LIMIT ?1",
+            )?;
+            let entries = statement
+                .query_map([limit], |row| {
+                    Ok(AuditEntry {
+                        ts: row.get(0)?,
+                        actor: row.get(1)?,
+                        action: row.get(2)?,
+                        target: row.get(3)?,
+                        room_id: row.get(4)?,
+                        reason: row.get(5)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(entries)
+        })
+    }
+
+    fn room_ids(&self, sql: &str) -> anyhow::Result<Vec<OwnedRoomId>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(sql)?;
+            let room_ids = statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .filter_map(|room_id| room_id.ok()?.try_into().ok())
+                .collect();
+            Ok(room_ids)
+        })
+    }
+}
+
+impl AuditEntry {
+    pub fn new(
+        actor: impl Into<String>,
+        action: &str,
+        target: impl Into<String>,
+        room_id: Option<&str>,
+        reason: Option<&str>,
+    ) -> Self {
+        Self {
+            ts: Utc::now().timestamp(),
+            actor: actor.into(),
+            action: action.to_owned(),
+            target: target.into(),
+            room_id: room_id.map(str::to_owned),
+            reason: reason.map(str::to_owned),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use matrix_bot_core::{
+    can_reply, fetch_message, html,
+    media::{Attachment, TooBig},
+    reply, reply_notice, reply_target, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::message::{
+        MessageType, OriginalSyncRoomMessageEvent, RoomMessageEventContent,
+    },
+    Room, RoomState,
+};
+use tracing::{instrument, warn};
+
+use crate::tesseract::{is_language_list, Tesseract};
+
+/// The most text to send back, in characters.
+const MAX_TEXT_CHARS: usize = 8000;
+
+const HELP: &str = "Reply to an image with !ocr and I'll read the text in it.
+!ocr <languages> to read it in other languages, e.g. !ocr deu or !ocr eng+fra
+!ocr langs to list the languages I know";
+
+#[derive(Clone)]
+pub struct Ocr {
+    pub tesseract: Tesseract,
+    /// The languages to read in when none are given.
+    pub languages: String,
+    /// The biggest image to download, in bytes.
+    pub max_size: u64,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    ocr: Ctx<Ocr>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+    let Some(args) = strip_command(body, "!ocr") else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    if args == "langs" {
+        let response = match ocr.tesseract.languages().await {
+            Ok(languages) => format!("I can read: {}", languages.join(", ")),
+            Err(err) => {
+                warn!("Failed to list languages: {err}");
+                "Sorry, I couldn't list my languages.".to_owned()
+            }
+        };
+        reply_notice(&room, &event, response).await;
+        return Ok(());
+    }
+
+    let languages = match args {
+        "" => ocr.languages.as_str(),
+        languages if is_language_list(languages) => languages,
+        _ => {
+            reply_notice(&room, &event, HELP).await;
+            return Ok(());
+        }
+    };
+    let content = match read_image(&event, &room, &ocr, languages).await? {
+        Ok(text) if text.is_empty() => {
+            RoomMessageEventContent::notice_plain("I couldn't find any text in that.")
+        }
+        Ok(text) => code_block(&text),
+        Err(response) => RoomMessageEventContent::notice_plain(response),
+    };
+    reply(&room, &event, content).await;
+    Ok(())
+}
+
+/// Read the text in the image `event` replies to, or say why that didn't
+/// work.
+async fn read_image(
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    ocr: &Ocr,
+    languages: &str,
+) -> anyhow::Result<Result<String, String>> {
+    let Some(target) = reply_target(event) else {
+        return Ok(Err(HELP.to_owned()));
+    };
+    let Some(target) = fetch_message(room, target).await? else {
+        return Ok(Err("I couldn't find that message.".to_owned()));
+    };
+    let attachment = match &target.content.msgtype {
+        MessageType::Image(_) => Attachment::from_message(&target.content.msgtype),
+        _ => None,
+    };
+    let Some(attachment) = attachment else {
+        return Ok(Err("That isn't an image.".to_owned()));
+    };
+    let data = match attachment.download(&room.client(), ocr.max_size).await {
+        Ok(data) => data,
+        Err(err) if err.is::<TooBig>() => {
+            return Ok(Err(format!(
+                "That image is too big, I only read images up to {} MB.",
+                ocr.max_size / 1_000_000
+            )))
+        }
+        Err(err) => return Err(err),
+    };
+
+    Ok(match ocr.tesseract.read(&data, languages).await {
+        Ok(text) => Ok(text),
+        Err(err) => {
+            warn!("Failed to read an image: {err}");
+            Err("Sorry, I couldn't read that. `!ocr langs` lists the languages I know.".to_owned())
+        }
+    })
+}
+
+fn code_block(text: &str) -> RoomMessageEventContent {
+    let text = match text.char_indices().nth(MAX_TEXT_CHARS) {
+        Some((end, _)) => format!("{}\n…", &text[..end]),
+        None => text.to_owned(),
+    };
+    RoomMessageEventContent::notice_html(
+        format!("```\n{text}\n```"),
+        format!("<pre><code>{}</code></pre>", html::escape(&text)),
+    )
+}
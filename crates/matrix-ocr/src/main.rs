@@ -0,0 +1,70 @@
+mod handlers;
+mod tesseract;
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use handlers::{on_room_message, Ocr};
+use matrix_bot_core::{AccountConfig, Bot};
+use tesseract::{is_language_list, Tesseract};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The tesseract program to run
+    #[arg(long, default_value = "tesseract", env = "OCR_TESSERACT")]
+    pub tesseract: PathBuf,
+
+    /// The languages to read in when none are given, e.g. `eng` or `eng+deu`
+    #[arg(long, default_value = "eng", value_parser = parse_languages, env = "OCR_LANGUAGES")]
+    pub languages: String,
+
+    /// The biggest image to read, in megabytes
+    #[arg(long, default_value_t = 10, env = "OCR_MAX_SIZE_MB")]
+    pub max_size_mb: u64,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_languages(languages: &str) -> Result<String, String> {
+    if is_language_list(languages) {
+        Ok(languages.to_owned())
+    } else {
+        Err(format!("invalid languages: {languages}"))
+    }
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let tesseract = Tesseract {
+        binary: config.tesseract,
+    };
+    // Fail early rather than on the first request
+    let languages = tesseract.languages().await?;
+    info!("Tesseract can read {}", languages.join(", "));
+
+    let mut bot = Bot::login("matrix-ocr", config.account_config).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Ocr {
+        tesseract,
+        languages: config.languages,
+        max_size: config.max_size_mb * 1_000_000,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
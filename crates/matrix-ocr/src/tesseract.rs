@@ -0,0 +1,70 @@
+use std::{path::PathBuf, process::Stdio};
+
+use anyhow::ensure;
+use tokio::process::Command;
+
+/// Runs the tesseract command line program.
+#[derive(Clone, Debug)]
+pub struct Tesseract {
+    pub binary: PathBuf,
+}
+
+impl Tesseract {
+    /// The text in an image, read in the given languages, e.g. `eng+deu`.
+    pub async fn read(&self, image: &[u8], languages: &str) -> anyhow::Result<String> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("image");
+        tokio::fs::write(&input, image).await?;
+
+        let output = Command::new(&self.binary)
+            .arg(&input)
+            .arg("stdout")
+            .args(["-l", languages])
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        ensure!(
+            output.status.success(),
+            "tesseract failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    /// The languages tesseract has data for.
+    pub async fn languages(&self) -> anyhow::Result<Vec<String>> {
+        let output = Command::new(&self.binary)
+            .arg("--list-langs")
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        ensure!(
+            output.status.success(),
+            "tesseract failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        // The first line is a heading
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .skip(1)
+            .map(str::trim)
+            .filter(|language| !language.is_empty() && *language != "osd")
+            .map(str::to_owned)
+            .collect())
+    }
+}
+
+/// Whether `languages` looks like a list of tesseract language codes, such as
+/// `eng` or `chi_sim+eng`.
+pub fn is_language_list(languages: &str) -> bool {
+    languages.split('+').all(|language| {
+        !language.is_empty()
+            && language
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
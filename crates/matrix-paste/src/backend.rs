@@ -0,0 +1,78 @@
+use anyhow::{ensure, Context};
+use clap::ValueEnum;
+use matrix_sdk::{ruma::OwnedMxcUri, Client};
+use serde::Deserialize;
+
+/// Where long messages are moved to.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Backend {
+    /// Upload a text file to the homeserver's media repository.
+    Media,
+    /// POST the text to the paste URL, which responds with the paste's link,
+    /// as paste.rs does.
+    Raw,
+    /// A hastebin-compatible server.
+    Haste,
+}
+
+pub enum Paste {
+    Link(String),
+    Media(OwnedMxcUri),
+}
+
+#[derive(Clone)]
+pub struct Paster {
+    pub backend: Backend,
+    pub paste_url: Option<String>,
+    pub http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct HasteResponse {
+    key: String,
+}
+
+impl Paster {
+    pub async fn paste(&self, client: &Client, text: &str) -> anyhow::Result<Paste> {
+        let paste_url = || {
+            self.paste_url
+                .as_deref()
+                .map(|url| url.trim_end_matches('/'))
+                .context("no paste URL configured")
+        };
+
+        match self.backend {
+            Backend::Media => {
+                let response = client
+                    .media()
+                    .upload(&mime::TEXT_PLAIN_UTF_8, text.as_bytes().to_vec(), None)
+                    .await?;
+                Ok(Paste::Media(response.content_uri))
+            }
+            Backend::Raw => {
+                let response = self
+                    .http
+                    .post(paste_url()?)
+                    .body(text.to_owned())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                let link = response.text().await?.trim().to_owned();
+                ensure!(link.starts_with("http"), "unexpected response: {link}");
+                Ok(Paste::Link(link))
+            }
+            Backend::Haste => {
+                let base = paste_url()?;
+                let response = self
+                    .http
+                    .post(format!("{base}/documents"))
+                    .body(text.to_owned())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                let response: HasteResponse = serde_json::from_str(&response.text().await?)?;
+                Ok(Paste::Link(format!("{base}/{}", response.key)))
+            }
+        }
+    }
+}
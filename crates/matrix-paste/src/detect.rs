@@ -0,0 +1,52 @@
+/// How long a message can get before it's moved to a paste.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub max_lines: usize,
+    pub max_chars: usize,
+    pub max_code_lines: usize,
+}
+
+impl Limits {
+    /// Whether `body` is long enough to move out of the room.
+    pub fn exceeded_by(&self, body: &str) -> bool {
+        body.lines().count() > self.max_lines
+            || body.chars().count() > self.max_chars
+            || longest_code_block(body) > self.max_code_lines
+    }
+}
+
+/// The number of lines in the longest fenced code block in `body`.
+///
+/// An unclosed fence runs to the end of the message, as that's how it's
+/// rendered.
+fn longest_code_block(body: &str) -> usize {
+    let mut longest = 0;
+    let mut current = None;
+    for line in body.lines() {
+        if line.trim_start().starts_with("```") {
+            current = match current {
+                Some(lines) => {
+                    longest = longest.max(lines);
+                    None
+                }
+                None => Some(0),
+            };
+        } else if let Some(lines) = &mut current {
+            *lines += 1;
+        }
+    }
+    longest.max(current.unwrap_or(0))
+}
+
+/// The language of `body` if it's a single fenced code block, for picking a
+/// file extension.
+pub fn code_language(body: &str) -> Option<&str> {
+    let body = body.trim();
+    let rest = body.strip_prefix("```")?;
+    if !body.ends_with("```") || rest.trim_end_matches('`').contains("```") {
+        return None;
+    }
+    let language = rest.lines().next()?.trim();
+    (!language.is_empty() && language.chars().all(|c| c.is_ascii_alphanumeric()))
+        .then_some(language)
+}
@@ -0,0 +1,119 @@
+use matrix_bot_core::{
+    can_reply, fetch_message, reply, reply_notice, reply_target, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::message::{
+            FileInfo, FileMessageEventContent, MessageType, OriginalSyncRoomMessageEvent,
+            RoomMessageEventContent,
+        },
+        UInt,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    backend::{Paste, Paster},
+    detect::{code_language, Limits},
+};
+
+const HELP: &str = "Long messages and code blocks posted here are moved to a paste automatically.
+Reply to a message with !paste to move it on request.";
+
+#[derive(Clone)]
+pub struct Pastes {
+    pub paster: Paster,
+    pub limits: Limits,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    pastes: Ctx<Pastes>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if let Some(args) = strip_command(body, "!paste") {
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        let target = match reply_target(&event) {
+            Some(target) if args.is_empty() => fetch_message(&room, target).await?,
+            _ => {
+                reply_notice(&room, &event, HELP).await;
+                return Ok(());
+            }
+        };
+        match target
+            .as_ref()
+            .and_then(|target| Some((target, text_body(target)?)))
+        {
+            Some((target, text)) => paste(&room, target, text, &pastes.paster).await,
+            None => reply_notice(&room, &event, "I can only paste text messages.").await,
+        }
+        return Ok(());
+    }
+
+    if pastes.limits.exceeded_by(body) && can_reply(&room).await {
+        debug!(
+            lines = body.lines().count(),
+            "Moving long message to a paste"
+        );
+        paste(&room, &event, body, &pastes.paster).await;
+    }
+    Ok(())
+}
+
+/// Paste `text`, from `event`, and reply to it with the link.
+async fn paste(room: &Room, event: &OriginalSyncRoomMessageEvent, text: &str, paster: &Paster) {
+    let paste = match paster.paste(&room.client(), text).await {
+        Ok(paste) => paste,
+        Err(err) => {
+            warn!("Failed to paste message: {err:#}");
+            return;
+        }
+    };
+
+    let lines = text.lines().count();
+    match paste {
+        Paste::Link(link) => {
+            let response = format!("📋 {}'s message ({lines} lines) is at {link}", event.sender);
+            reply_notice(room, event, response).await;
+        }
+        Paste::Media(uri) => {
+            let filename = format!("message.{}", extension(code_language(text)));
+            let mut info = FileInfo::new();
+            info.mimetype = Some(mime::TEXT_PLAIN_UTF_8.to_string());
+            info.size = UInt::new(text.len() as u64);
+            let file = FileMessageEventContent::plain(filename, uri).info(Box::new(info));
+            let content = RoomMessageEventContent::new(MessageType::File(file));
+            reply(room, event, content).await;
+        }
+    }
+}
+
+/// A file extension for code in `language`.
+fn extension(language: Option<&str>) -> &str {
+    match language {
+        None => "txt",
+        Some("rust") => "rs",
+        Some("python") => "py",
+        Some("javascript") => "js",
+        Some("typescript") => "ts",
+        Some("bash" | "shell" | "console") => "sh",
+        Some("markdown") => "md",
+        Some("yml") => "yaml",
+        Some(language) => language,
+    }
+}
@@ -0,0 +1,77 @@
+mod backend;
+mod detect;
+mod handlers;
+
+use std::time::Duration;
+
+use backend::{Backend, Paster};
+use clap::Parser;
+use detect::Limits;
+use handlers::{on_room_message, Pastes};
+use matrix_bot_core::{AccountConfig, Bot};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Where to move long messages to
+    #[arg(long, value_enum, default_value = "media", env = "PASTE_BACKEND")]
+    pub backend: Backend,
+
+    /// The paste server to use with the `raw` and `haste` backends
+    #[arg(long, required_if_eq_any = [("backend", "raw"), ("backend", "haste")], env = "PASTE_URL")]
+    pub paste_url: Option<String>,
+
+    /// Move messages with more lines than this
+    #[arg(long, default_value_t = 40, env = "PASTE_MAX_LINES")]
+    pub max_lines: usize,
+
+    /// Move messages with more characters than this
+    #[arg(long, default_value_t = 4000, env = "PASTE_MAX_CHARS")]
+    pub max_chars: usize,
+
+    /// Move messages with a code block of more lines than this
+    #[arg(long, default_value_t = 20, env = "PASTE_MAX_CODE_LINES")]
+    pub max_code_lines: usize,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-paste", config.account_config).await?;
+    bot.initial_sync().await?;
+
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("matrix-paste/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(30))
+        .build()?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Pastes {
+        paster: Paster {
+            backend: config.backend,
+            paste_url: config.paste_url,
+            http,
+        },
+        limits: Limits {
+            max_lines: config.max_lines,
+            max_chars: config.max_chars,
+            max_code_lines: config.max_code_lines,
+        },
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,273 @@
+use chrono::DateTime;
+use matrix_bot_core::{can_reply, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{events::room::message::OriginalSyncRoomMessageEvent, UserId},
+    Room, RoomState,
+};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    proof::{statement, website_url, Kind, Verifier},
+    table::{now, ProofTable},
+};
+
+/// How long someone has to complete a challenge.
+const CHALLENGE_TTL: u64 = 60 * 60;
+
+const SIGNED_MESSAGE_HEADER: &str = "-----BEGIN PGP SIGNED MESSAGE-----";
+
+const HELP: &str = "Prove you control a PGP key or an account, in a DM with me:
+!verify pgp <fingerprint>
+!verify github <username>
+!verify website <domain>
+!verify check, once you've published the statement I give you
+!verify list, !verify revoke <kind> <subject> or !verify cancel
+Anywhere, !whois @user:example.org shows what someone has proven, and
+!whois <fingerprint>, !whois github <username> or !whois website <domain> who proved it.";
+
+#[derive(Clone)]
+pub struct Attestations {
+    pub table: ProofTable,
+    pub verifier: Verifier,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    attestations: Ctx<Attestations>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    let response = if let Some(args) = strip_command(body, "!whois") {
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        whois(&attestations.table, args)?
+    } else if let Some(args) = strip_command(body, "!verify") {
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        if !room.is_direct().await? {
+            "Let's do that in a direct message, send me !verify there.".to_owned()
+        } else {
+            verify(&attestations, &event.sender, args).await?
+        }
+    } else if body.trim_start().starts_with(SIGNED_MESSAGE_HEADER) && room.is_direct().await? {
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        check_signed(&attestations, &event.sender, body).await?
+    } else {
+        return Ok(());
+    };
+
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn verify(
+    attestations: &Attestations,
+    user_id: &UserId,
+    args: &str,
+) -> anyhow::Result<String> {
+    let table = &attestations.table;
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+
+    match subcommand {
+        "check" => check_published(attestations, user_id).await,
+        "cancel" => Ok(if table.cancel(user_id.as_str())? {
+            "Cancelled.".to_owned()
+        } else {
+            "You don't have a challenge going.".to_owned()
+        }),
+        "list" => Ok(list(table, user_id.as_str())?
+            .unwrap_or_else(|| "You haven't proven anything yet.".to_owned())),
+        "revoke" => {
+            let Some((kind, subject)) = rest.split_once(char::is_whitespace) else {
+                return Ok(HELP.to_owned());
+            };
+            let Ok(kind) = kind.parse::<Kind>() else {
+                return Ok(HELP.to_owned());
+            };
+            let subject = match kind.normalise(subject.trim()) {
+                Ok(subject) => subject,
+                Err(problem) => return Ok(problem),
+            };
+            Ok(if table.revoke(user_id.as_str(), kind, &subject)? {
+                info!(%kind, subject = subject.as_str(), "Revoked attestation");
+                format!("Removed {}.", kind.describe(&subject))
+            } else {
+                format!("You haven't proven {}.", kind.describe(&subject))
+            })
+        }
+        kind => {
+            let (Ok(kind), false) = (kind.parse::<Kind>(), rest.is_empty()) else {
+                return Ok(HELP.to_owned());
+            };
+            let subject = match kind.normalise(rest) {
+                Ok(subject) => subject,
+                Err(problem) => return Ok(problem),
+            };
+            let challenge = table.challenge(user_id.as_str(), kind, &subject)?;
+            let statement = statement(user_id.as_str(), kind, &subject, &challenge.token);
+            Ok(match kind {
+                Kind::Pgp => format!(
+                    "Sign this statement with the key, e.g. with `gpg --clearsign`, and send me \
+                     the whole signed message:\n\n{statement}\n\nMake sure the key is on \
+                     {}, I'll fetch it from there.",
+                    attestations.verifier.keyserver
+                ),
+                Kind::Github => format!(
+                    "Create a public gist on {subject}'s account containing this statement, \
+                     then send me !verify check:\n\n{statement}"
+                ),
+                Kind::Website => format!(
+                    "Publish this statement at {}, then send me !verify check:\n\n{statement}",
+                    website_url(&subject)
+                ),
+            } + "\n\nThis challenge expires in an hour.")
+        }
+    }
+}
+
+/// Finish a PGP challenge with the signed statement.
+async fn check_signed(
+    attestations: &Attestations,
+    user_id: &UserId,
+    signed: &str,
+) -> anyhow::Result<String> {
+    let challenge = match attestations.table.pending(user_id.as_str())? {
+        Some(challenge) if challenge.kind == Kind::Pgp => challenge,
+        _ => {
+            return Ok("Start with !verify pgp <fingerprint> to get something to sign.".to_owned())
+        }
+    };
+    if now().saturating_sub(challenge.created_at) > CHALLENGE_TTL {
+        return Ok("That challenge has expired, start again with !verify pgp.".to_owned());
+    }
+    let statement = statement(
+        user_id.as_str(),
+        Kind::Pgp,
+        &challenge.subject,
+        &challenge.token,
+    );
+    if let Err(err) = attestations
+        .verifier
+        .pgp(&challenge.subject, &statement, signed)
+        .await
+    {
+        warn!("PGP proof failed: {err:#}");
+        return Ok(format!("I couldn't verify that: {err:#}."));
+    }
+    attestations
+        .table
+        .attest(user_id.as_str(), Kind::Pgp, &challenge.subject, signed)?;
+    info!(subject = challenge.subject.as_str(), "Verified PGP key");
+    Ok(format!(
+        "Verified, you control {}.",
+        Kind::Pgp.describe(&challenge.subject)
+    ))
+}
+
+/// Finish a challenge whose statement has been published somewhere.
+async fn check_published(attestations: &Attestations, user_id: &UserId) -> anyhow::Result<String> {
+    let Some(challenge) = attestations.table.pending(user_id.as_str())? else {
+        return Ok("You don't have a challenge going.".to_owned());
+    };
+    if now().saturating_sub(challenge.created_at) > CHALLENGE_TTL {
+        return Ok("That challenge has expired, start again.".to_owned());
+    }
+    let (kind, subject) = (challenge.kind, &challenge.subject);
+    let statement = statement(user_id.as_str(), kind, subject, &challenge.token);
+    let found = match kind {
+        Kind::Pgp => return Ok("Send me the signed statement itself.".to_owned()),
+        Kind::Github => attestations.verifier.github(subject, &statement).await,
+        Kind::Website => attestations.verifier.website(subject, &statement).await,
+    };
+    let proof = match found {
+        Ok(Some(proof)) => proof,
+        Ok(None) => return Ok("I couldn't find the statement yet.".to_owned()),
+        Err(err) => {
+            warn!("Proof failed: {err:#}");
+            return Ok(format!("I couldn't check: {err:#}."));
+        }
+    };
+    attestations
+        .table
+        .attest(user_id.as_str(), kind, subject, &proof)?;
+    info!(%kind, subject = subject.as_str(), "Verified attestation");
+    Ok(format!(
+        "Verified, you control {}. You can remove the statement now, I've kept a record.",
+        kind.describe(subject)
+    ))
+}
+
+fn whois(table: &ProofTable, args: &str) -> anyhow::Result<String> {
+    if args.is_empty() {
+        return Ok(HELP.to_owned());
+    }
+    if let Ok(user_id) = UserId::parse(args) {
+        return Ok(list(table, user_id.as_str())?
+            .unwrap_or_else(|| format!("{user_id} hasn't proven anything.")));
+    }
+
+    let (kind, subject) = match args.split_once(char::is_whitespace) {
+        Some((kind, subject)) => match kind.parse::<Kind>() {
+            Ok(kind) => (kind, subject.trim()),
+            Err(()) => return Ok(HELP.to_owned()),
+        },
+        None => (Kind::Pgp, args),
+    };
+    let subject = match kind.normalise(subject) {
+        Ok(subject) => subject,
+        Err(problem) => return Ok(problem),
+    };
+    let holders = table.holders(kind, &subject)?;
+    Ok(if holders.is_empty() {
+        format!(
+            "Nobody has proven they control {}.",
+            kind.describe(&subject)
+        )
+    } else {
+        format!(
+            "{} proved they control {}.",
+            holders.join(", "),
+            kind.describe(&subject)
+        )
+    })
+}
+
+/// What a user has proven, if anything.
+fn list(table: &ProofTable, user_id: &str) -> anyhow::Result<Option<String>> {
+    let attestations = table.attestations(user_id)?;
+    if attestations.is_empty() {
+        return Ok(None);
+    }
+    let mut lines = vec![format!("{user_id} has proven they control:")];
+    for attestation in attestations {
+        let date = DateTime::from_timestamp(attestation.verified_at as i64, 0)
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        let proof = match attestation.kind {
+            // The signed statement is the proof, too long to show
+            Kind::Pgp => String::new(),
+            Kind::Github | Kind::Website => format!(", {}", attestation.proof),
+        };
+        lines.push(format!(
+            "- {} (verified {date}{proof})",
+            attestation.kind.describe(&attestation.subject)
+        ));
+    }
+    Ok(Some(lines.join("\n")))
+}
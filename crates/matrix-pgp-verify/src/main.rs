@@ -0,0 +1,61 @@
+mod handlers;
+mod proof;
+mod table;
+
+use std::time::Duration;
+
+use clap::Parser;
+use handlers::{on_room_message, Attestations};
+use matrix_bot_core::{AccountConfig, Bot};
+use proof::Verifier;
+use table::ProofTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Keyserver to fetch PGP keys from, which must support the VKS API
+    #[arg(
+        long,
+        default_value = "https://keys.openpgp.org",
+        env = "PGP_VERIFY_KEYSERVER"
+    )]
+    pub keyserver: String,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-pgp-verify", config.account_config).await?;
+    let table = ProofTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("matrix-pgp-verify/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(30))
+        .build()?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Attestations {
+        table,
+        verifier: Verifier {
+            http,
+            keyserver: config.keyserver,
+        },
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
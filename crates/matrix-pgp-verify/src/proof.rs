@@ -0,0 +1,231 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, Context};
+use pgp::{
+    cleartext::CleartextSignedMessage, types::PublicKeyTrait, Deserializable, SignedPublicKey,
+};
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+/// The most of a proof document we'll read.
+const MAX_PROOF_SIZE: usize = 64 * 1024;
+
+/// How many of someone's most recent gists to look through.
+const MAX_GISTS: usize = 10;
+
+/// What an attestation proves control of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Pgp,
+    Github,
+    Website,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Pgp => "pgp",
+            Kind::Github => "github",
+            Kind::Website => "website",
+        }
+    }
+
+    /// Check `subject` is something of this kind, returning it as it's
+    /// stored: fingerprints in upper case without spaces, accounts and
+    /// domains in lower case.
+    pub fn normalise(self, subject: &str) -> Result<String, String> {
+        match self {
+            Kind::Pgp => {
+                let fingerprint: String = subject
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .collect::<String>()
+                    .trim_start_matches("0x")
+                    .to_uppercase();
+                if !matches!(fingerprint.len(), 40 | 64)
+                    || !fingerprint.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    return Err(format!(
+                        "{subject} isn't a full key fingerprint, get it with `gpg --fingerprint`."
+                    ));
+                }
+                Ok(fingerprint)
+            }
+            Kind::Github => {
+                let username = subject.trim_start_matches('@').to_lowercase();
+                if username.is_empty()
+                    || username.len() > 39
+                    || !username
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+                {
+                    return Err(format!("{subject} isn't a GitHub username."));
+                }
+                Ok(username)
+            }
+            Kind::Website => {
+                let domain = subject
+                    .trim_start_matches("https://")
+                    .trim_end_matches('/')
+                    .to_lowercase();
+                if !domain.contains('.')
+                    || !domain
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                {
+                    return Err(format!("{subject} isn't a domain name."));
+                }
+                Ok(domain)
+            }
+        }
+    }
+
+    /// What the subject is, for people, e.g. `the PGP key ABCD…`.
+    pub fn describe(self, subject: &str) -> String {
+        match self {
+            Kind::Pgp => format!("the PGP key {subject}"),
+            Kind::Github => format!("the GitHub account {subject}"),
+            Kind::Website => format!("the website {subject}"),
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pgp" | "gpg" => Ok(Kind::Pgp),
+            "github" => Ok(Kind::Github),
+            "website" | "domain" => Ok(Kind::Website),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The statement someone has to sign or publish.
+pub fn statement(user_id: &str, kind: Kind, subject: &str, token: &str) -> String {
+    format!(
+        "I am {user_id} on Matrix, and I control {}. Proof: {token}",
+        kind.describe(subject)
+    )
+}
+
+/// Where people publish the statement to prove they control a website.
+pub fn website_url(domain: &str) -> String {
+    format!("https://{domain}/.well-known/matrix-attestation")
+}
+
+#[derive(Clone)]
+pub struct Verifier {
+    pub http: reqwest::Client,
+    /// A keyserver with the VKS API, to fetch PGP keys from.
+    pub keyserver: String,
+}
+
+#[derive(Deserialize)]
+struct Gist {
+    html_url: String,
+    files: std::collections::BTreeMap<String, GistFile>,
+}
+
+#[derive(Deserialize)]
+struct GistFile {
+    raw_url: String,
+    #[serde(default)]
+    size: usize,
+}
+
+impl Verifier {
+    /// Check `signed` is a cleartext-signed copy of `statement` by the key
+    /// with `fingerprint`, or one of its subkeys.
+    pub async fn pgp(
+        &self,
+        fingerprint: &str,
+        statement: &str,
+        signed: &str,
+    ) -> anyhow::Result<()> {
+        let url = format!(
+            "{}/vks/v1/by-fingerprint/{fingerprint}",
+            self.keyserver.trim_end_matches('/')
+        );
+        let response = self.http.get(&url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            bail!("the keyserver doesn't have that key, upload it there first");
+        }
+        let armored = response.error_for_status()?.text().await?;
+
+        let (key, _) = SignedPublicKey::from_string(&armored).context("the key is invalid")?;
+        key.verify()
+            .context("the key's self-signatures are invalid")?;
+        if hex::encode_upper(key.fingerprint()) != fingerprint {
+            bail!("the keyserver sent a different key");
+        }
+
+        let (message, _) =
+            CleartextSignedMessage::from_string(signed).context("that isn't a signed message")?;
+        let signed_by_key = message.verify(&key).is_ok()
+            || key
+                .public_subkeys
+                .iter()
+                .any(|subkey| message.verify(subkey).is_ok());
+        if !signed_by_key {
+            bail!("that message isn't signed by the key");
+        }
+        if message.signed_text().trim() != statement {
+            bail!("the signed message isn't the statement I asked for");
+        }
+        Ok(())
+    }
+
+    /// Look for the statement in the user's recent public gists, returning
+    /// the gist it's in.
+    pub async fn github(&self, username: &str, statement: &str) -> anyhow::Result<Option<String>> {
+        let gists: Vec<Gist> = self
+            .http
+            .get(format!(
+                "https://api.github.com/users/{username}/gists?per_page={MAX_GISTS}"
+            ))
+            .header("Accept", "application/vnd.github+json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        for gist in gists {
+            for file in gist.files.values() {
+                if file.size > MAX_PROOF_SIZE {
+                    continue;
+                }
+                if self.fetch(&file.raw_url).await?.contains(statement) {
+                    return Ok(Some(gist.html_url));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check the statement is published on the website, returning where.
+    pub async fn website(&self, domain: &str, statement: &str) -> anyhow::Result<Option<String>> {
+        let url = website_url(domain);
+        Ok(self.fetch(&url).await?.contains(statement).then_some(url))
+    }
+
+    async fn fetch(&self, url: &str) -> anyhow::Result<String> {
+        let response = self.http.get(url).send().await?.error_for_status()?;
+        if response
+            .content_length()
+            .is_some_and(|length| length > MAX_PROOF_SIZE as u64)
+        {
+            bail!("{url} is too big");
+        }
+        let body = response.bytes().await?;
+        Ok(String::from_utf8_lossy(&body[..body.len().min(MAX_PROOF_SIZE)]).into_owned())
+    }
+}
@@ -0,0 +1,201 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use rusqlite::{params, OptionalExtension};
+
+use crate::proof::Kind;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE challenges (
+        user_id TEXT PRIMARY KEY,
+        kind TEXT NOT NULL,
+        subject TEXT NOT NULL,
+        token TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE attestations (
+        user_id TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        subject TEXT NOT NULL,
+        proof TEXT NOT NULL,
+        verified_at INTEGER NOT NULL,
+        PRIMARY KEY (user_id, kind, subject)
+    );"];
+
+const TOKEN_LENGTH: usize = 24;
+
+/// Pending challenges and the attestations people have proven, persisted in
+/// SQLite.
+#[derive(Clone)]
+pub struct ProofTable {
+    store: Store,
+}
+
+/// What someone has been asked to prove.
+#[derive(Debug)]
+pub struct Challenge {
+    pub kind: Kind,
+    /// The key fingerprint, account or domain.
+    pub subject: String,
+    pub token: String,
+    pub created_at: u64,
+}
+
+#[derive(Debug)]
+pub struct Attestation {
+    pub kind: Kind,
+    pub subject: String,
+    /// The signed message, or where the challenge was published.
+    pub proof: String,
+    pub verified_at: u64,
+}
+
+impl ProofTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Start a challenge for the user, replacing any they had going.
+    pub fn challenge(&self, user_id: &str, kind: Kind, subject: &str) -> anyhow::Result<Challenge> {
+        let challenge = Challenge {
+            kind,
+            subject: subject.to_owned(),
+            token: OsRng
+                .sample_iter(Alphanumeric)
+                .take(TOKEN_LENGTH)
+                .map(char::from)
+                .collect(),
+            created_at: now(),
+        };
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO challenges (user_id, kind, subject, token, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    user_id,
+                    kind.as_str(),
+                    challenge.subject,
+                    challenge.token,
+                    challenge.created_at
+                ],
+            )?;
+            Ok(challenge)
+        })
+    }
+
+    pub fn pending(&self, user_id: &str) -> anyhow::Result<Option<Challenge>> {
+        self.store.with(|conn| {
+            let row = conn
+                .query_row(
+                    "SELECT kind, subject, token, created_at FROM challenges WHERE user_id = ?1",
+                    [user_id],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                        ))
+                    },
+                )
+                .optional()?;
+            Ok(row.and_then(|(kind, subject, token, created_at)| {
+                Some(Challenge {
+                    kind: kind.parse().ok()?,
+                    subject,
+                    token,
+                    created_at,
+                })
+            }))
+        })
+    }
+
+    pub fn cancel(&self, user_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            Ok(conn.execute("DELETE FROM challenges WHERE user_id = ?1", [user_id])? > 0)
+        })
+    }
+
+    /// Record a proven attestation, finishing the user's challenge.
+    pub fn attest(
+        &self,
+        user_id: &str,
+        kind: Kind,
+        subject: &str,
+        proof: &str,
+    ) -> anyhow::Result<()> {
+        self.store.transaction(|transaction| {
+            transaction.execute(
+                "INSERT OR REPLACE INTO attestations (user_id, kind, subject, proof, verified_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![user_id, kind.as_str(), subject, proof, now()],
+            )?;
+            transaction.execute("DELETE FROM challenges WHERE user_id = ?1", [user_id])?;
+            Ok(())
+        })
+    }
+
+    /// A user's attestations, oldest first.
+    pub fn attestations(&self, user_id: &str) -> anyhow::Result<Vec<Attestation>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT kind, subject, proof, verified_at FROM attestations
+                WHERE user_id = ?1 ORDER BY verified_at",
+            )?;
+            let rows = statement
+                .query_map([user_id], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows
+                .into_iter()
+                .filter_map(|(kind, subject, proof, verified_at)| {
+                    Some(Attestation {
+                        kind: kind.parse().ok()?,
+                        subject,
+                        proof,
+                        verified_at,
+                    })
+                })
+                .collect())
+        })
+    }
+
+    /// Remove one of a user's attestations, returning whether it existed.
+    pub fn revoke(&self, user_id: &str, kind: Kind, subject: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM attestations WHERE user_id = ?1 AND kind = ?2 AND subject = ?3",
+                params![user_id, kind.as_str(), subject],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+
+    /// The users who have proven they control a subject, e.g. who a key
+    /// belongs to.
+    pub fn holders(&self, kind: Kind, subject: &str) -> anyhow::Result<Vec<String>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT user_id FROM attestations WHERE kind = ?1 AND subject = ?2 ORDER BY verified_at",
+            )?;
+            let holders = statement
+                .query_map(params![kind.as_str(), subject], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            Ok(holders)
+        })
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use matrix_sdk::{
+    ruma::{EventId, RoomId},
+    Client, RoomState,
+};
+use tokio::{
+    sync::Notify,
+    time::{sleep, Duration},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    pins::Pins,
+    table::{now, Expiring, ExpiryTable},
+};
+
+/// How long to sleep when no pins are set to expire. New expiring pins wake
+/// the task up early, so this only guards against missed notifications.
+const IDLE_SLEEP: Duration = Duration::from_secs(3600);
+
+/// Unpin messages as their pins expire, forever.
+pub async fn run(client: Client, pins: Pins, table: ExpiryTable, wake: Arc<Notify>) {
+    loop {
+        match table.due(now()) {
+            Ok(due) => {
+                for expiring in due {
+                    expire(&client, &pins, &expiring).await;
+                    if let Err(err) = table.clear_expiry(&expiring.room_id, &expiring.event_id) {
+                        error!("Failed to forget expired pin: {err}");
+                    }
+                }
+            }
+            Err(err) => error!("Failed to load expired pins: {err}"),
+        }
+
+        let sleep_for = match table.next_due() {
+            Ok(Some(due)) => Duration::from_secs(due.saturating_sub(now())).min(IDLE_SLEEP),
+            Ok(None) => IDLE_SLEEP,
+            Err(err) => {
+                error!("Failed to get the next expiring pin: {err}");
+                IDLE_SLEEP
+            }
+        };
+
+        tokio::select! {
+            _ = sleep(sleep_for) => {}
+            _ = wake.notified() => {}
+        }
+    }
+}
+
+async fn expire(client: &Client, pins: &Pins, expiring: &Expiring) {
+    let (Ok(room_id), Ok(event_id)) = (
+        <&RoomId>::try_from(expiring.room_id.as_str()),
+        <&EventId>::try_from(expiring.event_id.as_str()),
+    ) else {
+        warn!(
+            room = expiring.room_id.as_str(),
+            "Invalid expiring pin {}", expiring.event_id
+        );
+        return;
+    };
+    let Some(room) = client
+        .get_room(room_id)
+        .filter(|room| room.state() == RoomState::Joined)
+    else {
+        warn!(
+            room = room_id.as_str(),
+            "Not in the room of an expiring pin"
+        );
+        return;
+    };
+
+    match pins.unpin(&room, event_id).await {
+        Ok(_) => info!(
+            room = room_id.as_str(),
+            unpinned = event_id.as_str(),
+            "Pin expired"
+        ),
+        Err(err) => warn!(
+            room = room_id.as_str(),
+            "Failed to unpin expired pin: {err:#}"
+        ),
+    }
+}
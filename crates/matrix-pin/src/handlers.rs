@@ -0,0 +1,256 @@
+use std::{sync::Arc, time::Duration};
+
+use matrix_bot_core::{
+    can_reply, fetch_message, format_duration, is_moderator, parse_duration, reply_notice,
+    reply_target, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::{
+            room::message::{sanitize::remove_plain_reply_fallback, OriginalSyncRoomMessageEvent},
+            StateEventType,
+        },
+        EventId, OwnedEventId,
+    },
+    Client, Room, RoomState,
+};
+use tokio::sync::Notify;
+use tracing::{info, instrument};
+
+use crate::{
+    pins::{self, Outcome, Pins},
+    table::{now, ExpiryTable},
+};
+
+/// How much of each message `!pins list` shows.
+const PREVIEW_LENGTH: usize = 80;
+
+/// The longest a message can be pinned for before it's unpinned.
+const MAX_PIN: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+const HELP: &str = "Usage, replying to a message:
+!pin to pin it, or !pin 3d to pin it for three days
+!unpin to unpin it
+Or anywhere:
+!unpin <number> to unpin a message from the list
+!pins list to list the pinned messages";
+
+#[derive(Clone)]
+pub struct PinBot {
+    pub pins: Pins,
+    pub table: ExpiryTable,
+    /// Wakes the expiry task when a pin is set to expire.
+    pub wake: Arc<Notify>,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    client: Client,
+    bot: Ctx<PinBot>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+    let command = if let Some(args) = strip_command(body, "!pin") {
+        Command::Pin(args)
+    } else if let Some(args) = strip_command(body, "!unpin") {
+        Command::Unpin(args)
+    } else if let Some(args) = strip_command(body, "!pins") {
+        Command::List(args)
+    } else {
+        return Ok(());
+    };
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let response = match command {
+        Command::List("" | "list") => list(&room, &bot.table).await?,
+        Command::List(_) => HELP.to_owned(),
+        Command::Pin(args) => match check_permissions(&room, &client, &event).await? {
+            Some(problem) => problem.to_owned(),
+            None => pin(&room, &event, &bot, args).await?,
+        },
+        Command::Unpin(args) => match check_permissions(&room, &client, &event).await? {
+            Some(problem) => problem.to_owned(),
+            None => unpin(&room, &event, &bot, args).await?,
+        },
+    };
+
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+enum Command<'a> {
+    Pin(&'a str),
+    Unpin(&'a str),
+    List(&'a str),
+}
+
+/// Why the sender or the bot can't change pins here, if they can't.
+async fn check_permissions(
+    room: &Room,
+    client: &Client,
+    event: &OriginalSyncRoomMessageEvent,
+) -> anyhow::Result<Option<&'static str>> {
+    if !is_moderator(room, &event.sender).await? {
+        return Ok(Some("Only moderators can pin and unpin messages."));
+    }
+    let Some(own_user_id) = client.user_id() else {
+        return Ok(None);
+    };
+    if !room
+        .can_user_send_state(own_user_id, StateEventType::RoomPinnedEvents)
+        .await?
+    {
+        return Ok(Some("I'm not allowed to change the pinned messages here."));
+    }
+    Ok(None)
+}
+
+async fn pin(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    bot: &PinBot,
+    args: &str,
+) -> anyhow::Result<String> {
+    let Some(target) = reply_target(event) else {
+        return Ok(HELP.to_owned());
+    };
+    let expires_in = match args {
+        "" => None,
+        args => match parse_duration(args) {
+            Some(duration) if duration > MAX_PIN => {
+                return Ok("That's too long, I can pin messages for up to a year.".to_owned())
+            }
+            Some(duration) if !duration.is_zero() => Some(duration),
+            _ => {
+                return Ok(format!(
+                    "{args} isn't a duration, try something like 3d or 12h."
+                ))
+            }
+        },
+    };
+    if fetch_message(room, target).await?.is_none() {
+        return Ok("I can only pin messages.".to_owned());
+    }
+
+    let outcome = bot.pins.pin(room, target).await?;
+    let (room_id, event_id) = (room.room_id().as_str(), target.as_str());
+    let response = match expires_in {
+        Some(duration) => {
+            bot.table.set_expiry(
+                room_id,
+                event_id,
+                now() + duration.as_secs(),
+                event.sender.as_str(),
+            )?;
+            bot.wake.notify_one();
+            format!("Pinned for {}.", format_duration(duration))
+        }
+        None => {
+            bot.table.clear_expiry(room_id, event_id)?;
+            match outcome {
+                Outcome::Changed => "Pinned.".to_owned(),
+                Outcome::Unchanged => "That's already pinned.".to_owned(),
+            }
+        }
+    };
+    info!(pinned = event_id, ?expires_in, "Pinned message");
+    Ok(response)
+}
+
+async fn unpin(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    bot: &PinBot,
+    args: &str,
+) -> anyhow::Result<String> {
+    let target: OwnedEventId = match (reply_target(event), args) {
+        (Some(target), "") => target.to_owned(),
+        (_, number) if !number.is_empty() => {
+            let pinned = pins::pinned(room).await?;
+            let index = number
+                .trim_start_matches('#')
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| number.checked_sub(1));
+            match index.and_then(|index| pinned.get(index)) {
+                Some(target) => target.clone(),
+                None => {
+                    return Ok(format!(
+                        "There's no pinned message {number}, see `!pins list`."
+                    ))
+                }
+            }
+        }
+        _ => return Ok(HELP.to_owned()),
+    };
+
+    let outcome = bot.pins.unpin(room, &target).await?;
+    bot.table
+        .clear_expiry(room.room_id().as_str(), target.as_str())?;
+    Ok(match outcome {
+        Outcome::Changed => {
+            info!(unpinned = target.as_str(), "Unpinned message");
+            "Unpinned.".to_owned()
+        }
+        Outcome::Unchanged => "That isn't pinned.".to_owned(),
+    })
+}
+
+async fn list(room: &Room, table: &ExpiryTable) -> anyhow::Result<String> {
+    let pinned = pins::pinned(room).await?;
+    if pinned.is_empty() {
+        return Ok("Nothing is pinned here.".to_owned());
+    }
+    let expiries = table.expiries(room.room_id().as_str())?;
+    let now = now();
+
+    let mut lines = Vec::new();
+    for (number, event_id) in pinned.iter().enumerate() {
+        let expiry = expiries
+            .get(event_id.as_str())
+            .map(|expires_at| {
+                let left = Duration::from_secs(expires_at.saturating_sub(now));
+                format!(" (unpins in {})", format_duration(left))
+            })
+            .unwrap_or_default();
+        lines.push(format!(
+            "{}. {}{expiry}\n   {}",
+            number + 1,
+            preview(room, event_id).await,
+            room.room_id().matrix_to_event_uri(event_id.clone())
+        ));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Who sent a pinned message and how it starts.
+async fn preview(room: &Room, event_id: &EventId) -> String {
+    match fetch_message(room, event_id).await {
+        Ok(Some(message)) => {
+            let body = remove_plain_reply_fallback(message.content.body());
+            let mut preview: String = body
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .chars()
+                .take(PREVIEW_LENGTH)
+                .collect();
+            if body.chars().count() > PREVIEW_LENGTH {
+                preview.push('…');
+            }
+            format!("{}: {preview}", message.sender)
+        }
+        Ok(None) => "Not a message".to_owned(),
+        Err(_) => "Couldn't load this message".to_owned(),
+    }
+}
@@ -0,0 +1,56 @@
+mod expiry;
+mod handlers;
+mod pins;
+mod table;
+
+use std::sync::Arc;
+
+use clap::Parser;
+use handlers::{on_room_message, PinBot};
+use matrix_bot_core::{AccountConfig, Bot};
+use pins::Pins;
+use table::ExpiryTable;
+use tokio::sync::Notify;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-pin", config.account_config).await?;
+    let table = ExpiryTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let pin_bot = PinBot {
+        pins: Pins::default(),
+        table,
+        wake: Arc::new(Notify::new()),
+    };
+    tokio::spawn(expiry::run(
+        bot.client().clone(),
+        pin_bot.pins.clone(),
+        pin_bot.table.clone(),
+        pin_bot.wake.clone(),
+    ));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(pin_bot);
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
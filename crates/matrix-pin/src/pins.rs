@@ -0,0 +1,111 @@
+//! Reading and changing a room's pinned messages without losing changes
+//! made by anyone else at the same time.
+
+use std::sync::Arc;
+
+use matrix_sdk::{
+    ruma::{
+        api::client::{error::ErrorKind, state::get_state_events_for_key},
+        events::{room::pinned_events::RoomPinnedEventsEventContent, StateEventType},
+        EventId, OwnedEventId,
+    },
+    Room,
+};
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+/// How many times to try a change that keeps getting overwritten.
+const MAX_ATTEMPTS: usize = 3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Changed,
+    /// The pins were already how they should be.
+    Unchanged,
+}
+
+/// Changes to pinned messages, one at a time.
+///
+/// Pinned messages are a single state event holding the whole list, so two
+/// clients changing it at once can each undo the other's change. Changes
+/// from this bot are serialized, and each one is based on the server's
+/// latest list rather than the sync cache, then read back to check nobody
+/// else's change landed on top of it.
+#[derive(Clone, Default)]
+pub struct Pins {
+    lock: Arc<Mutex<()>>,
+}
+
+impl Pins {
+    pub async fn pin(&self, room: &Room, event_id: &EventId) -> anyhow::Result<Outcome> {
+        self.update(room, |pinned| {
+            if pinned.iter().any(|pinned| pinned == event_id) {
+                return false;
+            }
+            pinned.push(event_id.to_owned());
+            true
+        })
+        .await
+    }
+
+    pub async fn unpin(&self, room: &Room, event_id: &EventId) -> anyhow::Result<Outcome> {
+        self.update(room, |pinned| {
+            let before = pinned.len();
+            pinned.retain(|pinned| pinned != event_id);
+            pinned.len() != before
+        })
+        .await
+    }
+
+    /// Apply `change` to the pinned messages, which returns whether it
+    /// changed anything. It's applied again to what the server has after the
+    /// change, so it must only change anything if the change hasn't landed.
+    async fn update(
+        &self,
+        room: &Room,
+        change: impl Fn(&mut Vec<OwnedEventId>) -> bool,
+    ) -> anyhow::Result<Outcome> {
+        let _guard = self.lock.lock().await;
+
+        let mut pinned = pinned(room).await?;
+        if !change(&mut pinned) {
+            return Ok(Outcome::Unchanged);
+        }
+        for attempt in 1..=MAX_ATTEMPTS {
+            room.send_state_event(RoomPinnedEventsEventContent::new(pinned))
+                .await?;
+            pinned = pinned_after(room).await?;
+            if !change(&mut pinned) {
+                return Ok(Outcome::Changed);
+            }
+            info!(attempt, "Pinned messages changed under us, trying again");
+        }
+        anyhow::bail!("the pinned messages kept changing, try again later")
+    }
+}
+
+/// The room's pinned messages, as the server has them right now.
+pub async fn pinned(room: &Room) -> anyhow::Result<Vec<OwnedEventId>> {
+    let request = get_state_events_for_key::v3::Request::new(
+        room.room_id().to_owned(),
+        StateEventType::RoomPinnedEvents,
+        String::new(),
+    );
+    match room.client().send(request, None).await {
+        Ok(response) => Ok(response
+            .content
+            .deserialize_as::<RoomPinnedEventsEventContent>()?
+            .pinned),
+        Err(err) if err.client_api_error_kind() == Some(&ErrorKind::NotFound) => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The pinned messages after sending a change, once it's had a moment to
+/// settle so a conflicting change sent at the same time shows up.
+async fn pinned_after(room: &Room) -> anyhow::Result<Vec<OwnedEventId>> {
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let pinned = pinned(room).await?;
+    debug!(count = pinned.len(), "Read back pinned messages");
+    Ok(pinned)
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::params;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE expiring (
+        room_id TEXT NOT NULL,
+        event_id TEXT NOT NULL,
+        expires_at INTEGER NOT NULL,
+        pinned_by TEXT NOT NULL,
+        PRIMARY KEY (room_id, event_id)
+    );
+    CREATE INDEX expiring_expires_at ON expiring (expires_at);"];
+
+/// When pins that should expire are due to be unpinned, persisted in SQLite
+/// so they survive restarts.
+#[derive(Clone)]
+pub struct ExpiryTable {
+    store: Store,
+}
+
+/// A pin that's due to be unpinned.
+#[derive(Debug)]
+pub struct Expiring {
+    pub room_id: String,
+    pub event_id: String,
+}
+
+impl ExpiryTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn set_expiry(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        expires_at: u64,
+        pinned_by: &str,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO expiring (room_id, event_id, expires_at, pinned_by)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![room_id, event_id, expires_at, pinned_by],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn clear_expiry(&self, room_id: &str, event_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "DELETE FROM expiring WHERE room_id = ?1 AND event_id = ?2",
+                [room_id, event_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// When each of a room's expiring pins expires, by event ID.
+    pub fn expiries(&self, room_id: &str) -> anyhow::Result<HashMap<String, u64>> {
+        self.store.with(|conn| {
+            let mut statement =
+                conn.prepare("SELECT event_id, expires_at FROM expiring WHERE room_id = ?1")?;
+            let expiries = statement
+                .query_map([room_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?;
+            Ok(expiries)
+        })
+    }
+
+    /// The pins that have expired by `now`, oldest first.
+    pub fn due(&self, now: u64) -> anyhow::Result<Vec<Expiring>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT room_id, event_id FROM expiring WHERE expires_at <= ?1 ORDER BY expires_at",
+            )?;
+            let due = statement
+                .query_map([now], |row| {
+                    Ok(Expiring {
+                        room_id: row.get(0)?,
+                        event_id: row.get(1)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(due)
+        })
+    }
+
+    /// When the next pin expires, if any are set to.
+    pub fn next_due(&self) -> anyhow::Result<Option<u64>> {
+        self.store.with(|conn| {
+            conn.query_row("SELECT MIN(expires_at) FROM expiring", [], |row| row.get(0))
+        })
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
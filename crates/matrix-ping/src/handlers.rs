@@ -0,0 +1,86 @@
+use matrix_bot_core::{can_reply, html, reply_notice, send_raw, strip_command, text_body};
+use matrix_sdk::{
+    ruma::{
+        events::room::message::{
+            AddMentions, ForwardThread, OriginalSyncRoomMessageEvent, RoomMessageEventContent,
+        },
+        serde::Raw,
+        MilliSecondsSinceUnixEpoch,
+    },
+    Room, RoomState,
+};
+use serde_json::json;
+use tracing::{debug, instrument};
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if let Some(text) = strip_command(body, "!ping") {
+        if can_reply(&room).await {
+            pong(&event, &room, text).await?;
+        }
+    } else if let Some(text) = strip_command(body, "!echo") {
+        if can_reply(&room).await && !text.is_empty() {
+            reply_notice(&room, &event, text).await;
+        }
+    }
+    Ok(())
+}
+
+/// Reply in the format used by maubot's echo plugin, which includes a
+/// machine-readable `pong` object so tools can measure federation latency.
+async fn pong(event: &OriginalSyncRoomMessageEvent, room: &Room, text: &str) -> anyhow::Result<()> {
+    let now = u64::from(MilliSecondsSinceUnixEpoch::now().get());
+    let sent = u64::from(event.origin_server_ts.get());
+    // Clocks on different servers don't agree exactly
+    let ms = now.saturating_sub(sent);
+    let took = if ms < 1000 {
+        format!("{ms} ms")
+    } else {
+        format!("{:.1} seconds", ms as f64 / 1000.0)
+    };
+
+    let ping = if text.is_empty() {
+        "ping".to_owned()
+    } else {
+        format!("ping \"{text}\"")
+    };
+    let link = room.room_id().matrix_to_event_uri(event.event_id.clone());
+    let content = RoomMessageEventContent::notice_html(
+        format!("{}: Pong! ({ping} took {took} to arrive)", event.sender),
+        format!(
+            "{}: Pong! (<a href=\"{link}\">{}</a> took {took} to arrive)",
+            html::user_pill(&event.sender),
+            html::escape(&ping)
+        ),
+    )
+    .make_reply_to(
+        &event.clone().into_full_event(room.room_id().to_owned()),
+        ForwardThread::Yes,
+        AddMentions::No,
+    );
+
+    let mut content = serde_json::to_value(content)?;
+    content["pong"] = json!({
+        "ms": ms,
+        "from": event.sender.server_name(),
+        "ping": event.event_id,
+    });
+    debug!(ms, "Pong");
+    send_raw(
+        &room,
+        "m.room.message".to_owned(),
+        Raw::new(&content)?.cast(),
+    )
+    .await?;
+    Ok(())
+}
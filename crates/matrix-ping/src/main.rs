@@ -0,0 +1,40 @@
+mod handlers;
+
+use clap::Parser;
+use handlers::on_room_message;
+use matrix_bot_core::{AccountConfig, Bot};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-ping", config.account_config).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler(on_room_message);
+    bot.help().add(
+        "!ping [text]",
+        "Check the bot is there and how long it took to hear you",
+    );
+    bot.help()
+        .add("!echo <text>", "Have the bot repeat something");
+
+    bot.run().await
+}
@@ -0,0 +1,287 @@
+use matrix_bot_core::{
+    can_reply, is_moderator, reply_notice, reply_target, send, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::{
+            poll::{
+                unstable_end::UnstablePollEndEventContent,
+                unstable_response::OriginalSyncUnstablePollResponseEvent,
+                unstable_start::{
+                    NewUnstablePollStartEventContent, UnstablePollAnswer, UnstablePollAnswers,
+                    UnstablePollStartContentBlock, UnstablePollStartEventContent,
+                },
+            },
+            room::message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+        },
+        UInt,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, info, instrument};
+
+use crate::{
+    parse::{parse_new_poll, parse_votes},
+    table::{Poll, PollTable},
+};
+
+const HELP: &str = "Usage:
+!poll \"Question\" \"Answer 1\" \"Answer 2\" … (add --multiple to allow several answers, --text for a text-only poll)
+!vote <number> to vote in the latest poll, or reply to a poll with it
+!poll results
+!poll close";
+
+#[derive(Clone)]
+pub struct Polls {
+    pub table: PollTable,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    polls: Ctx<Polls>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if let Some(args) = strip_command(body, "!vote") {
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        let response = text_vote(args, &event, &room, &polls.table).await?;
+        reply_notice(&room, &event, response).await;
+        return Ok(());
+    }
+
+    let Some(args) = strip_command(body, "!poll") else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    match args {
+        "" | "help" => reply_notice(&room, &event, HELP).await,
+        "results" | "close" => {
+            let Some(poll) = target_poll(&event, &room, &polls.table)? else {
+                reply_notice(&room, &event, "There's no open poll here.").await;
+                return Ok(());
+            };
+            if args == "results" {
+                let results = results(&poll, &polls.table)?;
+                reply_notice(&room, &event, results).await;
+            } else {
+                close(&poll, &event, &room, &polls.table).await?;
+            }
+        }
+        args => match parse_new_poll(args) {
+            Ok(new_poll) => {
+                let max_selections = if new_poll.multiple {
+                    new_poll.answers.len()
+                } else {
+                    1
+                };
+                let text = fallback_text(&new_poll.question, &new_poll.answers, max_selections);
+
+                let event_id = if new_poll.text_only {
+                    send(&room, RoomMessageEventContent::text_plain(text)).await?
+                } else {
+                    let answers: Vec<_> = new_poll
+                        .answers
+                        .iter()
+                        .enumerate()
+                        .map(|(i, answer)| UnstablePollAnswer::new(i.to_string(), answer))
+                        .collect();
+                    let mut block = UnstablePollStartContentBlock::new(
+                        &new_poll.question,
+                        UnstablePollAnswers::try_from(answers)?,
+                    );
+                    block.max_selections = UInt::from(max_selections as u32);
+                    let content: UnstablePollStartEventContent =
+                        NewUnstablePollStartEventContent::plain_text(text, block).into();
+                    send(&room, content).await?
+                };
+
+                let poll = Poll {
+                    id: 0,
+                    event_id,
+                    creator: event.sender.clone(),
+                    question: new_poll.question,
+                    answers: new_poll.answers,
+                    max_selections,
+                    native: !new_poll.text_only,
+                    closed: false,
+                };
+                let id = polls.table.add(room.room_id().as_str(), &poll)?;
+                info!(id, poll = poll.event_id.as_str(), "Started poll");
+            }
+            Err(err) => reply_notice(&room, &event, err.to_string()).await,
+        },
+    }
+    Ok(())
+}
+
+/// Record responses from clients that support polls.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_poll_response(
+    event: OriginalSyncUnstablePollResponseEvent,
+    room: Room,
+    polls: Ctx<Polls>,
+) -> anyhow::Result<()> {
+    let Some(poll) = polls
+        .table
+        .by_event(event.content.relates_to.event_id.as_str())?
+    else {
+        return Ok(());
+    };
+    if poll.closed {
+        debug!("Ignoring response to closed poll");
+        return Ok(());
+    }
+
+    // Unknown answers spoil the vote, as MSC3381 requires
+    let mut answers = Vec::new();
+    for answer in &event.content.poll_response.answers {
+        match answer.parse::<usize>() {
+            Ok(i) if i < poll.answers.len() => {
+                if !answers.contains(&i) {
+                    answers.push(i);
+                }
+            }
+            _ => {
+                answers.clear();
+                break;
+            }
+        }
+    }
+
+    polls.table.vote(
+        poll.id,
+        event.sender.as_str(),
+        &answers,
+        event.origin_server_ts.get().into(),
+    )?;
+    debug!(poll = poll.id, "Recorded vote");
+    Ok(())
+}
+
+/// Handle `!vote`, for clients that don't support polls.
+async fn text_vote(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    table: &PollTable,
+) -> anyhow::Result<String> {
+    let Some(poll) = target_poll(event, room, table)? else {
+        return Ok("There's no open poll here.".to_owned());
+    };
+    if poll.closed {
+        return Ok("That poll is closed.".to_owned());
+    }
+
+    let votes = match parse_votes(args, poll.answers.len()) {
+        Ok(votes) => votes,
+        Err(err) => return Ok(err.to_string()),
+    };
+    if votes.len() > poll.max_selections {
+        return Ok("You can only vote for one answer in that poll.".to_owned());
+    }
+
+    table.vote(
+        poll.id,
+        event.sender.as_str(),
+        &votes,
+        event.origin_server_ts.get().into(),
+    )?;
+    let answers: Vec<_> = votes.iter().map(|&i| poll.answers[i].as_str()).collect();
+    Ok(format!("Voted for {}.", answers.join(", ")))
+}
+
+/// The poll a command is about: the one it replies to, or else the latest
+/// open poll in the room.
+fn target_poll(
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    table: &PollTable,
+) -> anyhow::Result<Option<Poll>> {
+    match reply_target(event) {
+        Some(event_id) => table.by_event(event_id.as_str()),
+        None => table.latest_open(room.room_id().as_str()),
+    }
+}
+
+async fn close(
+    poll: &Poll,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    table: &PollTable,
+) -> anyhow::Result<()> {
+    if poll.closed {
+        reply_notice(room, event, "That poll is already closed.").await;
+        return Ok(());
+    }
+    if event.sender != poll.creator && !is_moderator(room, &event.sender).await? {
+        reply_notice(
+            room,
+            event,
+            "Only the poll's creator or a moderator can close it.",
+        )
+        .await;
+        return Ok(());
+    }
+
+    table.close(poll.id)?;
+    let results = results(poll, table)?;
+    if poll.native {
+        send(
+            room,
+            UnstablePollEndEventContent::new(results, poll.event_id.clone()),
+        )
+        .await?;
+    } else {
+        reply_notice(room, event, results).await;
+    }
+    info!(id = poll.id, "Closed poll");
+    Ok(())
+}
+
+fn fallback_text(question: &str, answers: &[String], max_selections: usize) -> String {
+    let mut text = format!("📊 {question}\n");
+    for (i, answer) in answers.iter().enumerate() {
+        text += &format!("{}. {answer}\n", i + 1);
+    }
+    if max_selections > 1 {
+        text += "Vote with !vote <numbers>, e.g. !vote 1, 3";
+    } else {
+        text += "Vote with !vote <number>";
+    }
+    text
+}
+
+fn results(poll: &Poll, table: &PollTable) -> anyhow::Result<String> {
+    let (counts, voters) = table.tally(poll)?;
+    let total: usize = counts.iter().sum();
+
+    let mut text = format!(
+        "📊 {}{} ({voters} {})\n",
+        poll.question,
+        if poll.closed {
+            " — final results"
+        } else {
+            ""
+        },
+        if voters == 1 { "voter" } else { "voters" },
+    );
+    for (answer, count) in poll.answers.iter().zip(counts) {
+        let percent = (count * 100).checked_div(total).unwrap_or(0);
+        let bar = "█".repeat(percent / 10) + &"░".repeat(10 - percent / 10);
+        text += &format!("{bar} {percent:>3}% {answer} ({count})\n");
+    }
+    Ok(text.trim_end().to_owned())
+}
@@ -0,0 +1,40 @@
+mod handlers;
+mod parse;
+mod table;
+
+use clap::Parser;
+use handlers::{on_poll_response, on_room_message, Polls};
+use matrix_bot_core::{AccountConfig, Bot};
+use table::PollTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-poll", config.account_config).await?;
+    let table = PollTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages and votes.
+    bot.client().add_event_handler_context(Polls { table });
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_poll_response);
+
+    bot.run().await
+}
@@ -0,0 +1,72 @@
+use anyhow::bail;
+use matrix_bot_core::quoted_args;
+
+/// The most answers a poll may have, as allowed by MSC3381.
+pub const MAX_ANSWERS: usize = 20;
+
+#[derive(Debug, PartialEq)]
+pub struct NewPoll {
+    pub question: String,
+    pub answers: Vec<String>,
+    /// Allow voting for more than one answer.
+    pub multiple: bool,
+    /// Post a plain message rather than a poll event.
+    pub text_only: bool,
+}
+
+/// Parse `"Question" "A" "B" "C"`, optionally preceded by `--multiple` and
+/// `--text`.
+pub fn parse_new_poll(input: &str) -> anyhow::Result<NewPoll> {
+    let mut multiple = false;
+    let mut text_only = false;
+    let mut args = Vec::new();
+
+    for arg in quoted_args(input)? {
+        match arg.as_str() {
+            "--multiple" | "-m" if args.is_empty() => multiple = true,
+            "--text" | "-t" if args.is_empty() => text_only = true,
+            _ => args.push(arg),
+        }
+    }
+
+    let mut args = args.into_iter();
+    let Some(question) = args.next() else {
+        bail!("Usage: !poll \"Question\" \"Answer 1\" \"Answer 2\" …");
+    };
+    let answers: Vec<_> = args.collect();
+    if answers.len() < 2 {
+        bail!("A poll needs at least two answers.");
+    }
+    if answers.len() > MAX_ANSWERS {
+        bail!("A poll can have at most {MAX_ANSWERS} answers.");
+    }
+
+    Ok(NewPoll {
+        question,
+        answers,
+        multiple,
+        text_only,
+    })
+}
+
+/// Parse the answer numbers given to `!vote`, e.g. `2` or `1, 3`.
+pub fn parse_votes(input: &str, answers: usize) -> anyhow::Result<Vec<usize>> {
+    let mut votes = Vec::new();
+    for vote in input.split(|c: char| c == ',' || c.is_whitespace()) {
+        if vote.is_empty() {
+            continue;
+        }
+        match vote.parse::<usize>() {
+            Ok(n) if (1..=answers).contains(&n) => {
+                if !votes.contains(&(n - 1)) {
+                    votes.push(n - 1);
+                }
+            }
+            _ => bail!("Vote with an answer number from 1 to {answers}."),
+        }
+    }
+    if votes.is_empty() {
+        bail!("Vote with an answer number from 1 to {answers}.");
+    }
+    Ok(votes)
+}
@@ -0,0 +1,173 @@
+use matrix_bot_core::{store::Store, Bot};
+use matrix_sdk::ruma::{OwnedEventId, OwnedUserId};
+use rusqlite::{params, OptionalExtension, Row};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE polls (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        event_id TEXT NOT NULL UNIQUE,
+        creator TEXT NOT NULL,
+        question TEXT NOT NULL,
+        answers TEXT NOT NULL,
+        max_selections INTEGER NOT NULL,
+        native INTEGER NOT NULL,
+        closed INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE votes (
+        poll_id INTEGER NOT NULL REFERENCES polls (id) ON DELETE CASCADE,
+        user_id TEXT NOT NULL,
+        answers TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        PRIMARY KEY (poll_id, user_id)
+    );"];
+
+#[derive(Debug, Clone)]
+pub struct Poll {
+    pub id: i64,
+    pub event_id: OwnedEventId,
+    pub creator: OwnedUserId,
+    pub question: String,
+    pub answers: Vec<String>,
+    pub max_selections: usize,
+    /// Whether the poll was posted as a poll event, rather than as text.
+    pub native: bool,
+    pub closed: bool,
+}
+
+/// Polls and their votes, persisted in SQLite.
+#[derive(Clone)]
+pub struct PollTable {
+    store: Store,
+}
+
+impl PollTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Store a new poll, returning its ID.
+    pub fn add(&self, room_id: &str, poll: &Poll) -> anyhow::Result<i64> {
+        let answers = serde_json::to_string(&poll.answers)?;
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO polls (room_id, event_id, creator, question, answers, max_selections, native)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    room_id,
+                    poll.event_id.as_str(),
+                    poll.creator.as_str(),
+                    poll.question,
+                    answers,
+                    poll.max_selections,
+                    poll.native
+                ],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    pub fn by_event(&self, event_id: &str) -> anyhow::Result<Option<Poll>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT id, event_id, creator, question, answers, max_selections, native, closed
+                    FROM polls WHERE event_id = ?1",
+                [event_id],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    /// The most recently started poll in a room that is still open.
+    pub fn latest_open(&self, room_id: &str) -> anyhow::Result<Option<Poll>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT id, event_id, creator, question, answers, max_selections, native, closed
+                    FROM polls WHERE room_id = ?1 AND closed = 0 ORDER BY id DESC LIMIT 1",
+                [room_id],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    /// Record a user's vote, replacing any earlier one. Votes older than the
+    /// one already recorded are ignored, as only a user's latest vote counts.
+    pub fn vote(
+        &self,
+        poll_id: i64,
+        user_id: &str,
+        answers: &[usize],
+        ts: u64,
+    ) -> anyhow::Result<()> {
+        let answers = serde_json::to_string(answers)?;
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO votes (poll_id, user_id, answers, ts) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT DO UPDATE SET answers = excluded.answers, ts = excluded.ts
+                WHERE excluded.ts >= votes.ts",
+                params![poll_id, user_id, answers, ts],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// The number of votes for each answer, and the number of voters.
+    pub fn tally(&self, poll: &Poll) -> anyhow::Result<(Vec<usize>, usize)> {
+        let votes = self.store.with(|conn| {
+            let mut statement = conn.prepare("SELECT answers FROM votes WHERE poll_id = ?1")?;
+            let votes = statement
+                .query_map([poll.id], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(votes)
+        })?;
+
+        let mut counts = vec![0; poll.answers.len()];
+        let mut voters = 0;
+        for answers in votes {
+            let answers: Vec<usize> = serde_json::from_str(&answers)?;
+            voters += 1;
+            for answer in answers.into_iter().take(poll.max_selections) {
+                if let Some(count) = counts.get_mut(answer) {
+                    *count += 1;
+                }
+            }
+        }
+        Ok((counts, voters))
+    }
+
+    pub fn close(&self, poll_id: i64) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute("UPDATE polls SET closed = 1 WHERE id = ?1", [poll_id])?;
+            Ok(())
+        })
+    }
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Poll> {
+    fn invalid(
+        index: usize,
+        err: impl std::error::Error + Send + Sync + 'static,
+    ) -> rusqlite::Error {
+        rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(err))
+    }
+
+    Ok(Poll {
+        id: row.get(0)?,
+        event_id: row
+            .get::<_, String>(1)?
+            .try_into()
+            .map_err(|e| invalid(1, e))?,
+        creator: row
+            .get::<_, String>(2)?
+            .try_into()
+            .map_err(|e| invalid(2, e))?,
+        question: row.get(3)?,
+        answers: serde_json::from_str(&row.get::<_, String>(4)?).map_err(|e| invalid(4, e))?,
+        max_selections: row.get(5)?,
+        native: row.get(6)?,
+        closed: row.get(7)?,
+    })
+}
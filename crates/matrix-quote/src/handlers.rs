@@ -0,0 +1,180 @@
+use chrono::DateTime;
+use matrix_bot_core::{
+    can_reply, fetch_message, html, is_moderator, reply, reply_notice, reply_target, strip_command,
+    text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+    Room, RoomState,
+};
+use tracing::{info, instrument};
+
+use crate::table::{Quote, QuoteTable};
+
+/// The most quotes shown for a search.
+const MAX_SEARCH_RESULTS: usize = 5;
+
+const HELP: &str = "Usage:
+!quote add (in reply to a message)
+!quote random
+!quote search <term>
+!quote <number>
+!quote delete <number> (moderators only)";
+
+#[derive(Clone)]
+pub struct Quotes {
+    pub table: QuoteTable,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    quotes: Ctx<Quotes>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!quote")) else {
+        return Ok(());
+    };
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let table = &quotes.table;
+    let room_id = room.room_id().as_str();
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+
+    match subcommand {
+        "help" => reply_notice(&room, &event, HELP).await,
+        "add" => {
+            let response = add(&event, &room, table).await?;
+            reply_notice(&room, &event, response).await;
+        }
+        "" | "random" => match table.random(room_id)? {
+            Some(quote) => reply(&room, &event, render(&quote)).await,
+            None => reply_notice(
+                &room,
+                &event,
+                "There are no quotes here yet. Reply to a message with `!quote add` to save one.",
+            )
+            .await,
+        },
+        "search" => {
+            if rest.is_empty() {
+                reply_notice(&room, &event, "What should I search for?").await;
+                return Ok(());
+            }
+            let found = table.search(room_id, rest, MAX_SEARCH_RESULTS)?;
+            if found.is_empty() {
+                reply_notice(&room, &event, "No quotes match that.").await;
+            } else {
+                reply(&room, &event, render_list(&found)).await;
+            }
+        }
+        "delete" | "remove" => {
+            let Some(number) = parse_number(rest) else {
+                reply_notice(&room, &event, "Which quote should I delete?").await;
+                return Ok(());
+            };
+            let response = if !is_moderator(&room, &event.sender).await? {
+                "Only moderators can delete quotes.".to_owned()
+            } else if table.delete(room_id, number)? {
+                info!(number, "Deleted quote");
+                format!("Deleted quote #{number}.")
+            } else {
+                format!("There's no quote #{number}.")
+            };
+            reply_notice(&room, &event, response).await;
+        }
+        number => match parse_number(number) {
+            Some(number) => match table.get(room_id, number)? {
+                Some(quote) => reply(&room, &event, render(&quote)).await,
+                None => reply_notice(&room, &event, format!("There's no quote #{number}.")).await,
+            },
+            None => reply_notice(&room, &event, HELP).await,
+        },
+    }
+    Ok(())
+}
+
+async fn add(
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    table: &QuoteTable,
+) -> anyhow::Result<String> {
+    let Some(target_id) = reply_target(event) else {
+        return Ok("Reply to the message you want to quote with `!quote add`.".to_owned());
+    };
+    let Some(target) = fetch_message(room, target_id).await? else {
+        return Ok("I can only quote messages.".to_owned());
+    };
+    let Some(text) = text_body(&target)
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+    else {
+        return Ok("I can only quote text messages.".to_owned());
+    };
+
+    let quote = Quote {
+        number: 0,
+        text: text.to_owned(),
+        author: target.sender.clone(),
+        added_by: event.sender.clone(),
+        said_at: DateTime::from_timestamp_millis(target.origin_server_ts.get().into())
+            .unwrap_or_default(),
+    };
+    Ok(
+        match table.add(room.room_id().as_str(), target.event_id.as_str(), &quote)? {
+            Some(number) => {
+                info!(number, "Added quote");
+                format!("Saved as quote #{number}.")
+            }
+            None => "That message is already quoted.".to_owned(),
+        },
+    )
+}
+
+fn parse_number(input: &str) -> Option<i64> {
+    input.trim_start_matches('#').parse().ok()
+}
+
+fn render(quote: &Quote) -> RoomMessageEventContent {
+    let date = quote.said_at.format("%Y-%m-%d");
+    RoomMessageEventContent::notice_html(
+        format!(
+            "#{}: “{}” — {}, {date}",
+            quote.number, quote.text, quote.author
+        ),
+        format!(
+            "<blockquote>{}</blockquote><p>— {}, {date} · #{}</p>",
+            html::escape(&quote.text).replace('\n', "<br>"),
+            html::user_pill(&quote.author),
+            quote.number
+        ),
+    )
+}
+
+fn render_list(quotes: &[Quote]) -> RoomMessageEventContent {
+    let plain = quotes
+        .iter()
+        .map(|quote| format!("#{}: “{}” — {}", quote.number, quote.text, quote.author))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let html = quotes
+        .iter()
+        .map(|quote| {
+            format!(
+                "<li>#{}: “{}” — {}</li>",
+                quote.number,
+                html::escape(&quote.text),
+                html::user_pill(&quote.author)
+            )
+        })
+        .collect::<String>();
+    RoomMessageEventContent::notice_html(plain, format!("<ul>{html}</ul>"))
+}
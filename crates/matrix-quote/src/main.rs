@@ -0,0 +1,38 @@
+mod handlers;
+mod table;
+
+use clap::Parser;
+use handlers::{on_room_message, Quotes};
+use matrix_bot_core::{AccountConfig, Bot};
+use table::QuoteTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-quote", config.account_config).await?;
+    let table = QuoteTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Quotes { table });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
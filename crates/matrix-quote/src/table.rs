@@ -0,0 +1,159 @@
+use chrono::{DateTime, Utc};
+use matrix_bot_core::{store::Store, Bot};
+use matrix_sdk::ruma::OwnedUserId;
+use rusqlite::{params, OptionalExtension, Row};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE quotes (
+        room_id TEXT NOT NULL,
+        number INTEGER NOT NULL,
+        event_id TEXT NOT NULL,
+        text TEXT NOT NULL,
+        author TEXT NOT NULL,
+        added_by TEXT NOT NULL,
+        said_at INTEGER NOT NULL,
+        PRIMARY KEY (room_id, number),
+        UNIQUE (room_id, event_id)
+    );"];
+
+#[derive(Debug)]
+pub struct Quote {
+    /// The quote's number within its room.
+    pub number: i64,
+    pub text: String,
+    /// Who said it.
+    pub author: OwnedUserId,
+    /// Who saved it.
+    pub added_by: OwnedUserId,
+    /// When it was said.
+    pub said_at: DateTime<Utc>,
+}
+
+/// Each room's quotes, persisted in SQLite.
+#[derive(Clone)]
+pub struct QuoteTable {
+    store: Store,
+}
+
+const COLUMNS: &str = "number, text, author, added_by, said_at";
+
+impl QuoteTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Save a quote, returning its number, or `None` if the message was
+    /// already saved.
+    pub fn add(&self, room_id: &str, event_id: &str, quote: &Quote) -> anyhow::Result<Option<i64>> {
+        self.store.with(|conn| {
+            let existing: Option<i64> = conn
+                .query_row(
+                    "SELECT number FROM quotes WHERE room_id = ?1 AND event_id = ?2",
+                    [room_id, event_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if existing.is_some() {
+                return Ok(None);
+            }
+
+            // Numbers aren't reused after a deletion, so links to quotes stay stable
+            let number: i64 = conn.query_row(
+                "SELECT COALESCE(MAX(number), 0) + 1 FROM quotes WHERE room_id = ?1",
+                [room_id],
+                |row| row.get(0),
+            )?;
+            conn.execute(
+                "INSERT INTO quotes (room_id, number, event_id, text, author, added_by, said_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    room_id,
+                    number,
+                    event_id,
+                    quote.text,
+                    quote.author.as_str(),
+                    quote.added_by.as_str(),
+                    quote.said_at.timestamp_millis(),
+                ],
+            )?;
+            Ok(Some(number))
+        })
+    }
+
+    pub fn get(&self, room_id: &str, number: i64) -> anyhow::Result<Option<Quote>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                &format!("SELECT {COLUMNS} FROM quotes WHERE room_id = ?1 AND number = ?2"),
+                params![room_id, number],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    pub fn random(&self, room_id: &str) -> anyhow::Result<Option<Quote>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {COLUMNS} FROM quotes WHERE room_id = ?1 ORDER BY RANDOM() LIMIT 1"
+                ),
+                [room_id],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    /// Quotes containing `term`, case-insensitively, newest first.
+    pub fn search(&self, room_id: &str, term: &str, limit: usize) -> anyhow::Result<Vec<Quote>> {
+        let pattern = format!(
+            "%{}%",
+            term.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {COLUMNS} FROM quotes
+                WHERE room_id = ?1 AND text LIKE ?2 ESCAPE '\\'
+                ORDER BY number DESC LIMIT ?3"
+            ))?;
+            let quotes = statement
+                .query_map(params![room_id, pattern, limit], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(quotes)
+        })
+    }
+
+    /// Delete a quote, returning whether it existed.
+    pub fn delete(&self, room_id: &str, number: i64) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM quotes WHERE room_id = ?1 AND number = ?2",
+                params![room_id, number],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Quote> {
+    fn parse(index: usize, value: String) -> rusqlite::Result<OwnedUserId> {
+        OwnedUserId::try_from(value).map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(
+                index,
+                rusqlite::types::Type::Text,
+                err.into(),
+            )
+        })
+    }
+
+    Ok(Quote {
+        number: row.get(0)?,
+        text: row.get(1)?,
+        author: parse(2, row.get(2)?)?,
+        added_by: parse(3, row.get(3)?)?,
+        said_at: DateTime::from_timestamp_millis(row.get(4)?).unwrap_or_default(),
+    })
+}
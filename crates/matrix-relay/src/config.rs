@@ -0,0 +1,103 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{bail, Context};
+use matrix_bot_core::policy::glob_matches;
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use serde::Deserialize;
+
+/// The relays file, e.g.
+///
+/// ```toml
+/// # Everything said in one room is said in the others
+/// [groups.general]
+/// rooms = ["!abc:one.example", "!def:two.example"]
+///
+/// # Announcements go one way, from the first room to the rest, without
+/// # anything bots say
+/// [groups.announcements]
+/// rooms = ["!ghi:one.example", "!jkl:two.example"]
+/// one_way = true
+/// ignore_senders = ["@*bot:*"]
+/// ignore_notices = true
+/// ```
+///
+/// Groups are relayed separately: a message relayed into a room isn't
+/// relayed on from there by another group.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelaysFile {
+    pub groups: BTreeMap<String, Group>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    pub rooms: Vec<OwnedRoomId>,
+    /// Only relay from the first room to the others.
+    #[serde(default)]
+    pub one_way: bool,
+    /// Globs of the senders whose messages aren't relayed.
+    #[serde(default)]
+    pub ignore_senders: Vec<String>,
+    /// Don't relay notices, which are usually from bots.
+    #[serde(default)]
+    pub ignore_notices: bool,
+    /// Relay images, files and other media, not just text.
+    #[serde(default = "default_true")]
+    pub media: bool,
+    /// Relay messages from encrypted rooms into unencrypted ones. They're
+    /// kept out of them unless this is set.
+    #[serde(default)]
+    pub allow_unencrypted: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Group {
+    /// The rooms a message in `room_id` is relayed to by this group.
+    pub fn targets(&self, room_id: &RoomId) -> Vec<&OwnedRoomId> {
+        match self.rooms.iter().position(|room| room == room_id) {
+            Some(0) => self.rooms[1..].iter().collect(),
+            Some(_) if !self.one_way => self.rooms.iter().filter(|room| *room != room_id).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn ignores(&self, sender: &str) -> bool {
+        self.ignore_senders
+            .iter()
+            .any(|glob| glob_matches(glob, sender))
+    }
+}
+
+impl RelaysFile {
+    /// Whether messages in the room are relayed anywhere.
+    pub fn is_source(&self, room_id: &RoomId) -> bool {
+        self.groups
+            .values()
+            .any(|group| !group.targets(room_id).is_empty())
+    }
+}
+
+pub fn load(path: &Path) -> anyhow::Result<RelaysFile> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read relays file {}", path.display()))?;
+    let file: RelaysFile = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse relays file {}", path.display()))?;
+    if file.groups.is_empty() {
+        bail!("no groups in {}", path.display());
+    }
+    for (name, group) in &file.groups {
+        if group.rooms.len() < 2 {
+            bail!("group {name} needs at least two rooms");
+        }
+        for (i, room) in group.rooms.iter().enumerate() {
+            if group.rooms[..i].contains(room) {
+                bail!("group {name} has {room} more than once");
+            }
+        }
+    }
+    Ok(file)
+}
@@ -0,0 +1,63 @@
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::params;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE relayed (
+        event_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        source_event_id TEXT NOT NULL
+    );
+    CREATE INDEX relayed_source ON relayed (source_event_id);"];
+
+/// Which messages each message was relayed as, persisted in SQLite so
+/// edits and redactions can follow it.
+#[derive(Clone)]
+pub struct Copies {
+    store: Store,
+}
+
+impl Copies {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn insert(
+        &self,
+        source_event_id: &str,
+        room_id: &str,
+        event_id: &str,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO relayed (event_id, room_id, source_event_id)
+                VALUES (?1, ?2, ?3)",
+                params![event_id, room_id, source_event_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// The rooms and events a message was relayed as.
+    pub fn relayed(&self, source_event_id: &str) -> anyhow::Result<Vec<(String, String)>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT room_id, event_id FROM relayed WHERE source_event_id = ?1 ORDER BY rowid",
+            )?;
+            let relayed = statement
+                .query_map([source_event_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?;
+            Ok(relayed)
+        })
+    }
+
+    pub fn remove(&self, source_event_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "DELETE FROM relayed WHERE source_event_id = ?1",
+                [source_event_id],
+            )?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,49 @@
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::{
+        message::OriginalSyncRoomMessageEvent, redaction::OriginalSyncRoomRedactionEvent,
+    },
+    Room, RoomState,
+};
+use tracing::instrument;
+
+use crate::relay::Relay;
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    relay: Ctx<Relay>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    // Everything the bot sends is relayed already, so this keeps messages
+    // from going round in circles
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    if !relay.relays.is_source(room.room_id()) {
+        return Ok(());
+    }
+    relay.message(&room, &event).await
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_redaction(
+    event: OriginalSyncRoomRedactionEvent,
+    room: Room,
+    relay: Ctx<Relay>,
+) -> anyhow::Result<()> {
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    if !relay.relays.is_source(room.room_id()) {
+        return Ok(());
+    }
+    let redacts = event.content.redacts.as_ref().or(event.redacts.as_ref());
+    if let Some(redacts) = redacts {
+        relay.redaction(&room, redacts).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+mod config;
+mod copies;
+mod handlers;
+mod relay;
+
+use std::{path::PathBuf, sync::Arc};
+
+use clap::Parser;
+use copies::Copies;
+use handlers::{on_redaction, on_room_message};
+use matrix_bot_core::{AccountConfig, Bot};
+use relay::Relay;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The TOML file defining which rooms to relay between
+    #[arg(long, env = "RELAY_RELAYS")]
+    pub relays: PathBuf,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    // Fail on a bad relays file before logging in
+    let relays = Arc::new(config::load(&config.relays)?);
+
+    let mut bot = Bot::login("matrix-relay", config.account_config).await?;
+    let copies = Copies::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client()
+        .add_event_handler_context(Relay { copies, relays });
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_redaction);
+
+    bot.run().await
+}
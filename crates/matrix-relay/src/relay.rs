@@ -0,0 +1,233 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use matrix_bot_core::{can_reply, html, replacement, send};
+use matrix_sdk::{
+    ruma::{
+        events::room::message::{
+            sanitize::remove_plain_reply_fallback, FormattedBody, MessageFormat, MessageType,
+            OriginalSyncRoomMessageEvent, Relation, RoomMessageEventContent,
+        },
+        EventId, OwnedRoomId, RoomId, UserId,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, info, warn};
+
+use crate::{config::RelaysFile, copies::Copies};
+
+#[derive(Clone)]
+pub struct Relay {
+    pub copies: Copies,
+    pub relays: Arc<RelaysFile>,
+}
+
+impl Relay {
+    /// The rooms a message should be relayed to, and whether each may be
+    /// unencrypted even if the message's room isn't.
+    fn targets(
+        &self,
+        room_id: &RoomId,
+        sender: &UserId,
+        msgtype: &MessageType,
+    ) -> BTreeMap<OwnedRoomId, bool> {
+        let mut targets: BTreeMap<OwnedRoomId, bool> = BTreeMap::new();
+        for group in self.relays.groups.values() {
+            if group.ignores(sender.as_str())
+                || (group.ignore_notices && matches!(msgtype, MessageType::Notice(_)))
+                || (!group.media && !is_text(msgtype))
+            {
+                continue;
+            }
+            for target in group.targets(room_id) {
+                *targets.entry(target.clone()).or_default() |= group.allow_unencrypted;
+            }
+        }
+        targets
+    }
+
+    /// Relay a new message, or an edit of one that was relayed.
+    pub async fn message(
+        &self,
+        room: &Room,
+        event: &OriginalSyncRoomMessageEvent,
+    ) -> anyhow::Result<()> {
+        if let Some(Relation::Replacement(edit)) = &event.content.relates_to {
+            return self
+                .edit(
+                    room,
+                    &event.sender,
+                    &edit.event_id,
+                    &edit.new_content.msgtype,
+                )
+                .await;
+        }
+
+        let msgtype = &event.content.msgtype;
+        let targets = self.targets(room.room_id(), &event.sender, msgtype);
+        if targets.is_empty() {
+            return Ok(());
+        }
+        let is_reply = matches!(event.content.relates_to, Some(Relation::Reply { .. }));
+        let name = sender_name(room, &event.sender).await;
+        let encrypted = room.encryption_settings().is_some();
+
+        for (room_id, allow_unencrypted) in targets {
+            let Some(target) = joined_room(room, &room_id) else {
+                debug!(to = room_id.as_str(), "Not in target room");
+                continue;
+            };
+            if encrypted && !allow_unencrypted && target.encryption_settings().is_none() {
+                debug!(
+                    to = room_id.as_str(),
+                    "Not relaying into an unencrypted room"
+                );
+                continue;
+            }
+            if !can_reply(&target).await {
+                continue;
+            }
+
+            // Media can't carry attribution, so it gets a message of its own
+            let contents = match attributed(&name, msgtype, is_reply) {
+                Some(content) => vec![content],
+                None => vec![
+                    RoomMessageEventContent::notice_plain(format!("{name} sent:")),
+                    RoomMessageEventContent::new(msgtype.clone()),
+                ],
+            };
+            for content in contents {
+                match send(&target, content).await {
+                    Ok(event_id) => self.copies.insert(
+                        event.event_id.as_str(),
+                        room_id.as_str(),
+                        event_id.as_str(),
+                    )?,
+                    Err(err) => {
+                        warn!(to = room_id.as_str(), "Failed to relay message: {err:#}");
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn edit(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        original: &EventId,
+        msgtype: &MessageType,
+    ) -> anyhow::Result<()> {
+        let name = sender_name(room, sender).await;
+        let Some(content) = attributed(&name, msgtype, false) else {
+            return Ok(());
+        };
+        for (room_id, event_id) in self.copies.relayed(original.as_str())? {
+            let (Ok(room_id), Ok(event_id)) = (RoomId::parse(&room_id), EventId::parse(&event_id))
+            else {
+                continue;
+            };
+            let Some(target) = joined_room(room, &room_id) else {
+                continue;
+            };
+            if let Err(err) = send(&target, replacement(event_id, content.clone())).await {
+                warn!(to = room_id.as_str(), "Failed to relay edit: {err:#}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Redact everything a redacted message was relayed as.
+    pub async fn redaction(&self, room: &Room, redacts: &EventId) -> anyhow::Result<()> {
+        let relayed = self.copies.relayed(redacts.as_str())?;
+        if relayed.is_empty() {
+            return Ok(());
+        }
+        for (room_id, event_id) in relayed {
+            let (Ok(room_id), Ok(event_id)) = (RoomId::parse(&room_id), EventId::parse(&event_id))
+            else {
+                continue;
+            };
+            let Some(target) = joined_room(room, &room_id) else {
+                continue;
+            };
+            if let Err(err) = target
+                .redact(&event_id, Some("Redacted in the original room"), None)
+                .await
+            {
+                warn!(to = room_id.as_str(), "Failed to relay redaction: {err}");
+            }
+        }
+        self.copies.remove(redacts.as_str())?;
+        info!("Relayed redaction");
+        Ok(())
+    }
+}
+
+fn joined_room(room: &Room, room_id: &RoomId) -> Option<Room> {
+    room.client()
+        .get_room(room_id)
+        .filter(|room| room.state() == RoomState::Joined)
+}
+
+/// The sender's display name in the room they wrote in, or their user ID.
+async fn sender_name(room: &Room, user_id: &UserId) -> String {
+    match room.get_member_no_sync(user_id).await {
+        Ok(Some(member)) => member
+            .display_name()
+            .map(str::to_owned)
+            .unwrap_or_else(|| user_id.to_string()),
+        _ => user_id.to_string(),
+    }
+}
+
+fn is_text(msgtype: &MessageType) -> bool {
+    matches!(
+        msgtype,
+        MessageType::Text(_) | MessageType::Notice(_) | MessageType::Emote(_)
+    )
+}
+
+/// A text message with the sender's name in front, or `None` for media.
+///
+/// Replies lose their formatting, as the reply fallback in it would quote a
+/// message the other rooms don't have.
+fn attributed(
+    name: &str,
+    msgtype: &MessageType,
+    is_reply: bool,
+) -> Option<RoomMessageEventContent> {
+    let (body, formatted, emote, notice) = match msgtype {
+        MessageType::Text(content) => (&content.body, &content.formatted, false, false),
+        MessageType::Notice(content) => (&content.body, &content.formatted, false, true),
+        MessageType::Emote(content) => (&content.body, &content.formatted, true, false),
+        _ => return None,
+    };
+    let body = remove_plain_reply_fallback(body);
+    let formatted = match formatted {
+        Some(FormattedBody {
+            format: MessageFormat::Html,
+            body,
+            ..
+        }) if !is_reply => body.clone(),
+        _ => html::escape(body).replace('\n', "<br>"),
+    };
+    let name_html = format!("<b>{}</b>", html::escape(name));
+    let (plain, formatted) = if emote {
+        (
+            format!("* {name} {body}"),
+            format!("* {name_html} {formatted}"),
+        )
+    } else {
+        (
+            format!("{name}: {body}"),
+            format!("{name_html}: {formatted}"),
+        )
+    };
+    Some(if notice {
+        RoomMessageEventContent::notice_html(plain, formatted)
+    } else {
+        RoomMessageEventContent::text_html(plain, formatted)
+    })
+}
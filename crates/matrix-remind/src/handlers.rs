@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use matrix_bot_core::{can_reply, reply_notice, rooms, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::{
+            member::MembershipState,
+            message::{OriginalSyncRoomMessageEvent, Relation},
+        },
+        OwnedRoomOrAliasId,
+    },
+    Client, Room, RoomState,
+};
+use tokio::sync::Notify;
+use tracing::{info, instrument};
+
+use crate::{
+    parse::{self, Command, Recurrence, Target},
+    table::{Reminder, ReminderTable},
+};
+
+/// The most reminders a single user can have pending at once.
+const MAX_REMINDERS_PER_USER: usize = 50;
+
+const HELP: &str = "Usage:
+!remind me in 2h take the bread out
+!remind me tomorrow 9am call the dentist
+!remind #room:example.org every monday 9am standup
+!remind list
+!remind cancel <number>";
+
+#[derive(Clone)]
+pub struct Reminders {
+    pub table: ReminderTable,
+    /// Wakes the scheduler when a reminder is added.
+    pub wake: Arc<Notify>,
+    pub timezone: Tz,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    client: Client,
+    reminders: Ctx<Reminders>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!remind")) else {
+        return Ok(());
+    };
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let response = match parse::parse(args, Utc::now(), reminders.timezone) {
+        Ok(command) => run_command(command, &event, &room, &client, &reminders).await?,
+        Err(err) => err.to_string(),
+    };
+
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn run_command(
+    command: Command,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    client: &Client,
+    reminders: &Reminders,
+) -> anyhow::Result<String> {
+    let sender = &event.sender;
+    let tz = reminders.timezone;
+
+    Ok(match command {
+        Command::Help => HELP.to_owned(),
+        Command::List => {
+            let pending = reminders.table.for_user(sender.as_str())?;
+            if pending.is_empty() {
+                return Ok("You have no reminders.".to_owned());
+            }
+            pending
+                .iter()
+                .map(|reminder| {
+                    let repeat = match reminder.recurrence {
+                        Some(recurrence) => format!(" ({})", describe(recurrence)),
+                        None => String::new(),
+                    };
+                    let room_name = if reminder.room_id == room.room_id() {
+                        "here".to_owned()
+                    } else {
+                        format!("in {}", reminder.room_id)
+                    };
+                    format!(
+                        "{}. {} {room_name}{repeat}: {}",
+                        reminder.id,
+                        reminder
+                            .due
+                            .with_timezone(&tz)
+                            .format("%a %Y-%m-%d %H:%M %Z"),
+                        reminder.message
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Command::Cancel(id) => {
+            if reminders.table.cancel(id, sender.as_str())? {
+                info!(id, "Cancelled reminder");
+                format!("Cancelled reminder {id}.")
+            } else {
+                format!("You don't have a reminder {id}.")
+            }
+        }
+        Command::Add {
+            target,
+            schedule,
+            message,
+        } => {
+            if reminders.table.count_for_user(sender.as_str())? >= MAX_REMINDERS_PER_USER {
+                return Ok(format!(
+                    "You already have {MAX_REMINDERS_PER_USER} reminders, cancel some first."
+                ));
+            }
+
+            let (room_id, thread_root, reply_to) = match target {
+                Target::Here => {
+                    // Deliver in the thread the command was sent in, or start one from it
+                    let thread_root = match &event.content.relates_to {
+                        Some(Relation::Thread(thread)) => thread.event_id.clone(),
+                        _ => event.event_id.clone(),
+                    };
+                    (
+                        room.room_id().to_owned(),
+                        Some(thread_root),
+                        Some(event.event_id.clone()),
+                    )
+                }
+                Target::Room(name) => {
+                    let Ok(target) = OwnedRoomOrAliasId::try_from(name.as_str()) else {
+                        return Ok(format!("{name} isn't a valid room ID or alias."));
+                    };
+                    let room_id = rooms::resolve(client, &target).await?;
+                    let Some(target) = client.get_room(&room_id) else {
+                        return Ok("I'm not in that room.".to_owned());
+                    };
+                    // Don't let people use the bot to post in rooms they aren't in
+                    let is_member = target
+                        .get_member_no_sync(sender)
+                        .await?
+                        .is_some_and(|member| *member.membership() == MembershipState::Join);
+                    if target.state() != RoomState::Joined || !is_member {
+                        return Ok("We both need to be in that room.".to_owned());
+                    }
+                    (room_id, None, None)
+                }
+            };
+
+            let mut reminder = Reminder {
+                id: 0,
+                room_id,
+                user_id: sender.clone(),
+                thread_root,
+                reply_to,
+                message,
+                due: schedule.due,
+                recurrence: schedule.recurrence,
+            };
+            reminder.id = reminders.table.add(&reminder)?;
+            reminders.wake.notify_one();
+            info!(id = reminder.id, due = %reminder.due, "Added reminder");
+
+            let repeat = match reminder.recurrence {
+                Some(recurrence) => format!(", then {}", describe(recurrence)),
+                None => String::new(),
+            };
+            format!(
+                "Okay, I'll remind you on {}{repeat}. Cancel with `!remind cancel {}`.",
+                reminder
+                    .due
+                    .with_timezone(&tz)
+                    .format("%a %Y-%m-%d at %H:%M %Z"),
+                reminder.id
+            )
+        }
+    })
+}
+
+fn describe(recurrence: Recurrence) -> String {
+    match recurrence {
+        Recurrence::Interval { seconds } => {
+            let (amount, unit) = [
+                (604800, "week"),
+                (86400, "day"),
+                (3600, "hour"),
+                (60, "minute"),
+            ]
+            .into_iter()
+            .find(|(unit, _)| seconds % unit == 0)
+            .map_or((seconds, "second"), |(unit, name)| (seconds / unit, name));
+            if amount == 1 {
+                format!("every {unit}")
+            } else {
+                format!("every {amount} {unit}s")
+            }
+        }
+        Recurrence::Daily { time } => format!("every day at {}", time.format("%H:%M")),
+        Recurrence::Weekly { weekday, time } => {
+            format!("every {weekday} at {}", time.format("%H:%M"))
+        }
+    }
+}
@@ -0,0 +1,63 @@
+mod handlers;
+mod parse;
+mod scheduler;
+mod table;
+
+use std::sync::Arc;
+
+use chrono_tz::Tz;
+use clap::Parser;
+use handlers::{on_room_message, Reminders};
+use matrix_bot_core::{AccountConfig, Bot};
+use table::ReminderTable;
+use tokio::sync::Notify;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Time zone used to interpret times like `9am`, e.g. `Europe/Berlin`
+    #[arg(long, default_value_t = Tz::UTC, env = "REMIND_TIMEZONE")]
+    pub timezone: Tz,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-remind", config.account_config).await?;
+    let table = ReminderTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let reminders = Reminders {
+        table,
+        wake: Arc::new(Notify::new()),
+        timezone: config.timezone,
+    };
+    tokio::spawn(matrix_bot_core::scheduler::run(
+        scheduler::Scheduler {
+            client: bot.client().clone(),
+            table: reminders.table.clone(),
+            timezone: reminders.timezone,
+        },
+        reminders.wake.clone(),
+    ));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(reminders);
+    bot.client().add_event_handler(on_room_message);
+    bot.enable_snooze()?;
+
+    bot.run().await
+}
@@ -0,0 +1,316 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use matrix_bot_core::{parse_duration, unit_seconds};
+use serde::{Deserialize, Serialize};
+
+/// The shortest interval a repeating reminder may have.
+const MIN_INTERVAL: u64 = 60;
+
+/// The furthest ahead a reminder can be set, and the longest interval a
+/// repeating one may have, so dates stay well within what chrono handles.
+const MAX_DURATION: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// The time used when a day is given without a time, e.g. `tomorrow`.
+const DEFAULT_TIME: NaiveTime = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    List,
+    Cancel(i64),
+    Add {
+        target: Target,
+        schedule: Schedule,
+        message: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    /// The room the command was sent in.
+    Here,
+    /// A room ID or alias.
+    Room(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Schedule {
+    pub due: DateTime<Utc>,
+    pub recurrence: Option<Recurrence>,
+}
+
+/// How a reminder repeats after it has fired.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Recurrence {
+    Interval { seconds: u64 },
+    Daily { time: NaiveTime },
+    Weekly { weekday: Weekday, time: NaiveTime },
+}
+
+impl Recurrence {
+    /// The first time this recurrence fires strictly after `after`.
+    pub fn next_after(&self, after: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+        match *self {
+            // Only intervals stored before they were capped can overflow,
+            // and those would never fire anyway
+            Recurrence::Interval { seconds } => i64::try_from(seconds)
+                .ok()
+                .and_then(TimeDelta::try_seconds)
+                .and_then(|interval| after.checked_add_signed(interval))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            Recurrence::Daily { time } => next_time(after, tz, time, |_| true),
+            Recurrence::Weekly { weekday, time } => {
+                next_time(after, tz, time, |date| date.weekday() == weekday)
+            }
+        }
+    }
+}
+
+/// The first `time` in `tz`, on a day matching `on`, strictly after `after`.
+fn next_time(
+    after: DateTime<Utc>,
+    tz: Tz,
+    time: NaiveTime,
+    on: impl Fn(NaiveDate) -> bool,
+) -> DateTime<Utc> {
+    let mut date = after.with_timezone(&tz).date_naive();
+    loop {
+        if on(date) {
+            let candidate = localize(tz, date, time);
+            if candidate > after {
+                return candidate;
+            }
+        }
+        date = date + Days::new(1);
+    }
+}
+
+fn localize(tz: Tz, date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    let local = date.and_time(time);
+    // Times skipped by a DST change fire at the equivalent UTC time instead
+    tz.from_local_datetime(&local)
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&local))
+}
+
+/// Parse the text following `!remind`.
+pub fn parse(input: &str, now: DateTime<Utc>, tz: Tz) -> anyhow::Result<Command> {
+    let Some((first, rest)) = next_word(input) else {
+        return Ok(Command::Help);
+    };
+
+    let target = match first.to_lowercase().as_str() {
+        "help" => return Ok(Command::Help),
+        "list" => return Ok(Command::List),
+        "cancel" | "delete" | "remove" => {
+            let id = next_word(rest)
+                .and_then(|(id, _)| id.trim_start_matches('#').parse().ok())
+                .ok_or_else(|| anyhow!("Which reminder should I cancel? Give its number from `!remind list`."))?;
+            return Ok(Command::Cancel(id));
+        }
+        "me" | "here" | "us" => Target::Here,
+        _ if first.starts_with('#') || first.starts_with('!') => Target::Room(first.to_owned()),
+        _ => bail!("Who should I remind? Start with `me` or a room, e.g. `!remind me in 2h take the bread out`."),
+    };
+
+    let (schedule, message) = parse_schedule(rest, now, tz)?;
+    let message = message.trim();
+    if message.is_empty() {
+        bail!("What should I remind you about?");
+    }
+
+    Ok(Command::Add {
+        target,
+        schedule,
+        message: message.to_owned(),
+    })
+}
+
+/// Parse a schedule from the start of `input`, returning the rest of it.
+fn parse_schedule(input: &str, now: DateTime<Utc>, tz: Tz) -> anyhow::Result<(Schedule, &str)> {
+    let (word, rest) = next_word(input).ok_or_else(|| anyhow!("When should I remind you?"))?;
+
+    let once = |due, rest| {
+        Ok((
+            Schedule {
+                due,
+                recurrence: None,
+            },
+            rest,
+        ))
+    };
+    let today = now.with_timezone(&tz).date_naive();
+
+    match word.to_lowercase().as_str() {
+        "in" => {
+            let (seconds, rest) = parse_duration_words(rest)?;
+            let due = TimeDelta::try_seconds(seconds as i64)
+                .and_then(|wait| now.checked_add_signed(wait))
+                .ok_or_else(|| anyhow!("That's too far in the future."))?;
+            once(due, rest)
+        }
+        "at" => {
+            let (time, rest) =
+                parse_time_of_day(input).ok_or_else(|| anyhow!("I don't understand that time. Try something like `9am` or `17:30`."))?;
+            once(next_time(now, tz, time, |_| true), rest)
+        }
+        "today" => {
+            let (time, rest) = parse_time_of_day(rest)
+                .ok_or_else(|| anyhow!("What time today? Try something like `today 5pm`."))?;
+            let due = localize(tz, today, time);
+            if due <= now {
+                bail!("That time has already passed today.");
+            }
+            once(due, rest)
+        }
+        "tomorrow" => {
+            let (time, rest) = parse_time_of_day(rest).unwrap_or((DEFAULT_TIME, rest));
+            once(localize(tz, today + Days::new(1), time), rest)
+        }
+        "every" => {
+            let (recurrence, rest) = parse_recurrence(rest)?;
+            if let Recurrence::Interval { seconds } = recurrence {
+                if seconds < MIN_INTERVAL {
+                    bail!("I can only repeat reminders at most once a minute.");
+                }
+            }
+            Ok((
+                Schedule {
+                    due: recurrence.next_after(now, tz),
+                    recurrence: Some(recurrence),
+                },
+                rest,
+            ))
+        }
+        "on" => {
+            let (weekday, rest) = next_word(rest)
+                .and_then(|(word, rest)| Some((Weekday::from_str(word).ok()?, rest)))
+                .ok_or_else(|| anyhow!("Which day? Try something like `on friday 5pm`."))?;
+            let (time, rest) = parse_time_of_day(rest).unwrap_or((DEFAULT_TIME, rest));
+            once(next_time(now, tz, time, |date| date.weekday() == weekday), rest)
+        }
+        word => match Weekday::from_str(word) {
+            Ok(weekday) => {
+                let (time, rest) = parse_time_of_day(rest).unwrap_or((DEFAULT_TIME, rest));
+                once(next_time(now, tz, time, |date| date.weekday() == weekday), rest)
+            }
+            Err(_) => bail!("When should I remind you? Try `in 2h`, `at 17:30`, `tomorrow 9am` or `every monday 9am`."),
+        },
+    }
+}
+
+/// Parse what follows `every`: an interval, `day`, or a day of the week.
+fn parse_recurrence(input: &str) -> anyhow::Result<(Recurrence, &str)> {
+    let (word, rest) = next_word(input).ok_or_else(|| anyhow!("How often should I remind you?"))?;
+
+    if word.eq_ignore_ascii_case("day") {
+        return Ok(match parse_time_of_day(rest) {
+            Some((time, rest)) => (Recurrence::Daily { time }, rest),
+            None => (Recurrence::Interval { seconds: 86400 }, rest),
+        });
+    }
+    if let Ok(weekday) = Weekday::from_str(word) {
+        let (time, rest) = parse_time_of_day(rest).unwrap_or((DEFAULT_TIME, rest));
+        return Ok((Recurrence::Weekly { weekday, time }, rest));
+    }
+
+    // `every hour` is short for `every 1 hour`
+    let (seconds, rest) = match unit_seconds(word) {
+        Some(seconds) => (seconds, rest),
+        None => parse_duration_words(input)?,
+    };
+    Ok((Recurrence::Interval { seconds }, rest))
+}
+
+/// Parse a duration such as `2h`, `1h30m`, `an hour` or `3 days 4 hours`.
+fn parse_duration_words(input: &str) -> anyhow::Result<(u64, &str)> {
+    let mut total = 0u64;
+    let mut rest = input;
+
+    while let Some((word, after)) = next_word(rest) {
+        let word = word.to_lowercase();
+        let seconds = if let Some(duration) = parse_duration(&word) {
+            Some((duration.as_secs(), after))
+        } else if let Some(amount) = word
+            .parse::<u64>()
+            .ok()
+            .or_else(|| ["a", "an"].contains(&word.as_str()).then_some(1))
+        {
+            next_word(after)
+                .and_then(|(unit, after)| Some((amount.checked_mul(unit_seconds(unit)?)?, after)))
+        } else {
+            None
+        };
+
+        let Some((seconds, after)) = seconds else {
+            break;
+        };
+        total = total
+            .checked_add(seconds)
+            .ok_or_else(|| anyhow!("That's too far in the future."))?;
+        rest = after;
+    }
+
+    if total == 0 {
+        bail!("How long should I wait? Try something like `2h`, `30 minutes` or `1d`.");
+    }
+    if total > MAX_DURATION {
+        bail!("That's too far in the future.");
+    }
+    Ok((total, rest))
+}
+
+/// Parse a time of day such as `9am`, `at 9:30pm`, `17:30` or `noon`.
+fn parse_time_of_day(input: &str) -> Option<(NaiveTime, &str)> {
+    let (word, rest) = next_word(input)?;
+    if word.eq_ignore_ascii_case("at") {
+        return parse_time_of_day(rest);
+    }
+
+    let word = word.to_lowercase();
+    let time = match word.as_str() {
+        "noon" | "midday" => NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" => NaiveTime::from_hms_opt(0, 0, 0),
+        _ => {
+            let (clock, offset) = if let Some(clock) = word.strip_suffix("am") {
+                (clock, Some(0))
+            } else if let Some(clock) = word.strip_suffix("pm") {
+                (clock, Some(12))
+            } else {
+                (word.as_str(), None)
+            };
+            let (hour, minute) = match clock.split_once(':') {
+                Some((hour, minute)) if minute.len() == 2 => (hour, minute.parse().ok()?),
+                Some(_) => return None,
+                // A bare number is too ambiguous without am/pm
+                None if offset.is_some() => (clock, 0),
+                None => return None,
+            };
+            let hour: u32 = hour.parse().ok()?;
+            let hour = match offset {
+                Some(offset) if (1..=12).contains(&hour) => hour % 12 + offset,
+                Some(_) => return None,
+                None => hour,
+            };
+            NaiveTime::from_hms_opt(hour, minute, 0)
+        }
+    }?;
+    Some((time, rest))
+}
+
+/// Split the first whitespace-separated word off `input`.
+fn next_word(input: &str) -> Option<(&str, &str)> {
+    let input = input.trim_start();
+    if input.is_empty() {
+        return None;
+    }
+    Some(match input.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (input, ""),
+    })
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use matrix_bot_core::{can_reply, html, scheduler::Jobs, send_or_log_error};
+use matrix_sdk::{
+    ruma::events::{
+        room::message::{Relation, RoomMessageEventContent, Thread},
+        Mentions,
+    },
+    Client, RoomState,
+};
+use tracing::{error, info, instrument, warn};
+
+use crate::table::{Reminder, ReminderTable};
+
+/// Reminders, delivered as they come due.
+pub struct Scheduler {
+    pub client: Client,
+    pub table: ReminderTable,
+    pub timezone: Tz,
+}
+
+impl Jobs for Scheduler {
+    fn next_due(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.table.next_due()
+    }
+
+    async fn fire_due(&self, now: DateTime<Utc>) {
+        let reminders = match self.table.due(now) {
+            Ok(reminders) => reminders,
+            Err(err) => {
+                error!("Failed to load due reminders: {err}");
+                return;
+            }
+        };
+        for reminder in reminders {
+            deliver(&self.client, &reminder).await;
+
+            let result = match reminder.recurrence {
+                Some(recurrence) => {
+                    // Reminders missed while offline fire once, then carry on from now
+                    self.table
+                        .reschedule(reminder.id, recurrence.next_after(now, self.timezone))
+                }
+                None => self.table.remove(reminder.id),
+            };
+            if let Err(err) = result {
+                error!(id = reminder.id, "Failed to update reminder: {err}");
+            }
+        }
+    }
+}
+
+#[instrument(skip_all, fields(id = reminder.id, room = reminder.room_id.as_str()))]
+async fn deliver(client: &Client, reminder: &Reminder) {
+    let Some(room) = client.get_room(&reminder.room_id) else {
+        warn!("Not in the room for this reminder, dropping it");
+        return;
+    };
+    if room.state() != RoomState::Joined || !can_reply(&room).await {
+        warn!("Can't post in the room for this reminder, dropping it");
+        return;
+    }
+
+    let user = &reminder.user_id;
+    let mut content = RoomMessageEventContent::text_html(
+        format!("⏰ {user}: {}", reminder.message),
+        format!(
+            "⏰ {}: {}",
+            html::user_pill(user),
+            html::escape(&reminder.message)
+        ),
+    )
+    .add_mentions(Mentions::with_user_ids([user.clone()]));
+
+    if let (Some(thread_root), Some(reply_to)) = (&reminder.thread_root, &reminder.reply_to) {
+        content.relates_to = Some(Relation::Thread(Thread::reply(
+            thread_root.clone(),
+            reply_to.clone(),
+        )));
+    }
+
+    info!("Delivering reminder");
+    send_or_log_error(&room, content).await;
+}
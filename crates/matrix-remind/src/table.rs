@@ -0,0 +1,189 @@
+use chrono::{DateTime, Utc};
+use matrix_bot_core::{store::Store, Bot};
+use matrix_sdk::ruma::{OwnedEventId, OwnedRoomId, OwnedUserId};
+use rusqlite::{params, Row};
+
+use crate::parse::Recurrence;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE reminders (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        thread_root TEXT,
+        reply_to TEXT,
+        message TEXT NOT NULL,
+        due INTEGER NOT NULL,
+        recurrence TEXT
+    );
+    CREATE INDEX reminders_due ON reminders (due);
+    CREATE INDEX reminders_user ON reminders (user_id);"];
+
+/// A reminder waiting to be delivered.
+#[derive(Debug)]
+pub struct Reminder {
+    pub id: i64,
+    pub room_id: OwnedRoomId,
+    pub user_id: OwnedUserId,
+    /// The thread to deliver the reminder in, if it should be threaded.
+    pub thread_root: Option<OwnedEventId>,
+    /// The message that asked for the reminder, if it was sent in the same room.
+    pub reply_to: Option<OwnedEventId>,
+    pub message: String,
+    pub due: DateTime<Utc>,
+    pub recurrence: Option<Recurrence>,
+}
+
+/// Reminders persisted in SQLite, so that they survive restarts.
+#[derive(Clone)]
+pub struct ReminderTable {
+    store: Store,
+}
+
+impl ReminderTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Store a new reminder, returning its ID.
+    pub fn add(&self, reminder: &Reminder) -> anyhow::Result<i64> {
+        let recurrence = reminder
+            .recurrence
+            .map(|r| serde_json::to_string(&r))
+            .transpose()?;
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO reminders (room_id, user_id, thread_root, reply_to, message, due, recurrence)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    reminder.room_id.as_str(),
+                    reminder.user_id.as_str(),
+                    reminder.thread_root.as_ref().map(|id| id.as_str()),
+                    reminder.reply_to.as_ref().map(|id| id.as_str()),
+                    reminder.message,
+                    reminder.due.timestamp(),
+                    recurrence,
+                ],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    /// The reminders that are due at `now`, oldest first.
+    pub fn due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<Reminder>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT id, room_id, user_id, thread_root, reply_to, message, due, recurrence
+                FROM reminders WHERE due <= ?1 ORDER BY due",
+            )?;
+            let reminders = statement
+                .query_map([now.timestamp()], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(reminders)
+        })
+    }
+
+    /// When the next reminder is due, if there are any.
+    pub fn next_due(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.store.with(|conn| {
+            let due: Option<i64> =
+                conn.query_row("SELECT MIN(due) FROM reminders", [], |row| row.get(0))?;
+            Ok(due.and_then(|due| DateTime::from_timestamp(due, 0)))
+        })
+    }
+
+    /// All of a user's reminders, soonest first.
+    pub fn for_user(&self, user_id: &str) -> anyhow::Result<Vec<Reminder>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT id, room_id, user_id, thread_root, reply_to, message, due, recurrence
+                FROM reminders WHERE user_id = ?1 ORDER BY due",
+            )?;
+            let reminders = statement
+                .query_map([user_id], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(reminders)
+        })
+    }
+
+    pub fn count_for_user(&self, user_id: &str) -> anyhow::Result<usize> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM reminders WHERE user_id = ?1",
+                [user_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    pub fn reschedule(&self, id: i64, due: DateTime<Utc>) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE reminders SET due = ?2 WHERE id = ?1",
+                params![id, due.timestamp()],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn remove(&self, id: i64) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute("DELETE FROM reminders WHERE id = ?1", [id])?;
+            Ok(())
+        })
+    }
+
+    /// Remove one of a user's reminders, returning whether it existed.
+    pub fn cancel(&self, id: i64, user_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM reminders WHERE id = ?1 AND user_id = ?2",
+                params![id, user_id],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Reminder> {
+    fn parse<T: TryFrom<String>>(index: usize, value: String) -> rusqlite::Result<T>
+    where
+        T::Error: std::error::Error + Send + Sync + 'static,
+    {
+        T::try_from(value).map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(
+                index,
+                rusqlite::types::Type::Text,
+                err.into(),
+            )
+        })
+    }
+
+    let recurrence: Option<String> = row.get(7)?;
+    Ok(Reminder {
+        id: row.get(0)?,
+        room_id: parse(1, row.get(1)?)?,
+        user_id: parse(2, row.get(2)?)?,
+        thread_root: row
+            .get::<_, Option<String>>(3)?
+            .map(|id| parse(3, id))
+            .transpose()?,
+        reply_to: row
+            .get::<_, Option<String>>(4)?
+            .map(|id| parse(4, id))
+            .transpose()?,
+        message: row.get(5)?,
+        due: DateTime::from_timestamp(row.get(6)?, 0).unwrap_or_default(),
+        recurrence: recurrence
+            .map(|r| serde_json::from_str(&r))
+            .transpose()
+            .map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    7,
+                    rusqlite::types::Type::Text,
+                    err.into(),
+                )
+            })?,
+    })
+}
@@ -0,0 +1,251 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use matrix_bot_core::{html, replacement, send};
+use matrix_sdk::{
+    ruma::{
+        events::{
+            room::{message::RoomMessageEventContent, pinned_events::RoomPinnedEventsEventContent},
+            space::child::SpaceChildEventContent,
+            SyncOrStrippedState, SyncStateEvent,
+        },
+        EventId, OwnedRoomId, RoomId,
+    },
+    Client, Room, RoomState,
+};
+use tokio::{sync::Notify, time::sleep};
+use tracing::{info, instrument, warn};
+
+use crate::table::{Advert, AdvertTable, CatalogMessage};
+
+/// Where a space's catalog is posted.
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    pub space: OwnedRoomId,
+    pub room: OwnedRoomId,
+}
+
+#[derive(Clone)]
+pub struct Directory {
+    pub table: AdvertTable,
+    pub catalogs: Arc<Vec<Catalog>>,
+    /// Wakes the catalog task when an advert changes.
+    pub wake: Arc<Notify>,
+}
+
+/// A room as it's listed, with what we know about it.
+pub struct Listing {
+    pub name: String,
+    pub link: String,
+    pub members: u64,
+    pub advert: Advert,
+}
+
+impl Listing {
+    pub fn new(client: &Client, advert: Advert) -> Self {
+        let room = <&RoomId>::try_from(advert.room_id.as_str())
+            .ok()
+            .and_then(|room_id| client.get_room(room_id));
+        let Some(room) = room else {
+            return Self {
+                name: advert.room_id.clone(),
+                link: format!("https://matrix.to/#/{}", advert.room_id),
+                members: 0,
+                advert,
+            };
+        };
+        let alias = room.canonical_alias();
+        Self {
+            name: room
+                .name()
+                .or_else(|| alias.as_ref().map(|alias| alias.to_string()))
+                .unwrap_or_else(|| advert.room_id.clone()),
+            link: match &alias {
+                Some(alias) => alias.matrix_to_uri().to_string(),
+                None => room.room_id().matrix_to_uri().to_string(),
+            },
+            members: room.joined_members_count(),
+            advert,
+        }
+    }
+
+    pub fn plain(&self) -> String {
+        let tags = if self.advert.tags.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", self.advert.tags.join(", "))
+        };
+        format!(
+            "{} ({} members): {}{tags}\n   {}",
+            self.name, self.members, self.advert.description, self.link
+        )
+    }
+
+    pub fn html(&self) -> String {
+        let tags = if self.advert.tags.is_empty() {
+            String::new()
+        } else {
+            format!(" <i>{}</i>", html::escape(&self.advert.tags.join(", ")))
+        };
+        format!(
+            "<a href=\"{}\">{}</a> ({} members): {}{tags}",
+            html::escape(&self.link),
+            html::escape(&self.name),
+            self.members,
+            html::escape(&self.advert.description)
+        )
+    }
+}
+
+/// Keep each space's catalog up to date, refreshing it every `every` to pick
+/// up renamed rooms and changes to the space, and whenever an advert changes.
+pub async fn run(client: Client, directory: Directory, every: Duration) {
+    loop {
+        for catalog in directory.catalogs.iter() {
+            if let Err(err) = refresh(&client, &directory.table, catalog).await {
+                warn!(
+                    space = catalog.space.as_str(),
+                    "Failed to update catalog: {err:#}"
+                );
+            }
+        }
+
+        tokio::select! {
+            _ = sleep(every) => {}
+            _ = directory.wake.notified() => {}
+        }
+    }
+}
+
+#[instrument(skip_all, fields(space = catalog.space.as_str()))]
+async fn refresh(client: &Client, table: &AdvertTable, catalog: &Catalog) -> anyhow::Result<()> {
+    let Some(space) = joined(client, &catalog.space) else {
+        warn!("Not in the space, skipping its catalog");
+        return Ok(());
+    };
+    let Some(room) = joined(client, &catalog.room) else {
+        warn!(
+            room = catalog.room.as_str(),
+            "Not in the catalog's room, skipping it"
+        );
+        return Ok(());
+    };
+
+    let children = space_children(&space).await?;
+    let mut listings: Vec<Listing> = table
+        .adverts()?
+        .into_iter()
+        .filter(|advert| children.contains(advert.room_id.as_str()))
+        .map(|advert| Listing::new(client, advert))
+        .collect();
+    listings.sort_by_key(|listing| listing.name.to_lowercase());
+    let content = render(&space, &listings);
+
+    let existing = table
+        .catalog(catalog.space.as_str())?
+        .filter(|message| message.room_id == catalog.room.as_str());
+    match existing {
+        Some(message) if message.body == content.body() => {}
+        Some(message) => {
+            let event_id = EventId::parse(&message.event_id)?;
+            send(&room, replacement(event_id, content.clone())).await?;
+            table.set_catalog(
+                catalog.space.as_str(),
+                &CatalogMessage {
+                    body: content.body().to_owned(),
+                    ..message
+                },
+            )?;
+            info!("Updated catalog");
+        }
+        None => {
+            let event_id = send(&room, content.clone()).await?;
+            pin(&room, &event_id).await;
+            table.set_catalog(
+                catalog.space.as_str(),
+                &CatalogMessage {
+                    room_id: catalog.room.to_string(),
+                    event_id: event_id.to_string(),
+                    body: content.body().to_owned(),
+                },
+            )?;
+            info!("Posted catalog");
+        }
+    }
+    Ok(())
+}
+
+fn render(space: &Room, listings: &[Listing]) -> RoomMessageEventContent {
+    let space_name = space.name().unwrap_or_else(|| space.room_id().to_string());
+    let title = format!("Rooms in {space_name}");
+    let footer = "Advertise a room you run here with !advertise in it.";
+    if listings.is_empty() {
+        return RoomMessageEventContent::notice_html(
+            format!("{title}\n\nNo rooms are advertised yet. {footer}"),
+            format!(
+                "<h3>{}</h3><p>No rooms are advertised yet. {footer}</p>",
+                html::escape(&title)
+            ),
+        );
+    }
+    let plain: Vec<String> = listings.iter().map(Listing::plain).collect();
+    let items: String = listings
+        .iter()
+        .map(|listing| format!("<li>{}</li>", listing.html()))
+        .collect();
+    RoomMessageEventContent::notice_html(
+        format!("{title}\n\n{}\n\n{footer}", plain.join("\n")),
+        format!(
+            "<h3>{}</h3><ul>{items}</ul><p>{footer}</p>",
+            html::escape(&title)
+        ),
+    )
+}
+
+/// Pin the catalog, if we're allowed to.
+async fn pin(room: &Room, event_id: &EventId) {
+    let mut pinned = match room
+        .get_state_event_static::<RoomPinnedEventsEventContent>()
+        .await
+    {
+        Ok(Some(raw)) => match raw.deserialize() {
+            Ok(SyncOrStrippedState::Sync(SyncStateEvent::Original(event))) => event.content.pinned,
+            _ => Vec::new(),
+        },
+        Ok(None) => Vec::new(),
+        Err(err) => {
+            warn!("Failed to get pinned messages: {err}");
+            return;
+        }
+    };
+    pinned.push(event_id.to_owned());
+    if let Err(err) = room
+        .send_state_event(RoomPinnedEventsEventContent::new(pinned))
+        .await
+    {
+        info!("Couldn't pin the catalog: {err}");
+    }
+}
+
+fn joined(client: &Client, room_id: &RoomId) -> Option<Room> {
+    client
+        .get_room(room_id)
+        .filter(|room| room.state() == RoomState::Joined)
+}
+
+/// The rooms a space lists as its children, leaving out ones it has removed.
+async fn space_children(space: &Room) -> anyhow::Result<HashSet<String>> {
+    let events = space
+        .get_state_events_static::<SpaceChildEventContent>()
+        .await?;
+    Ok(events
+        .into_iter()
+        .filter_map(|raw| match raw.deserialize().ok()? {
+            SyncOrStrippedState::Sync(SyncStateEvent::Original(event))
+                if !event.content.via.is_empty() =>
+            {
+                Some(event.state_key.to_string())
+            }
+            _ => None,
+        })
+        .collect())
+}
@@ -0,0 +1,199 @@
+use matrix_bot_core::{can_reply, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::{join_rules::JoinRule, message::OriginalSyncRoomMessageEvent},
+    Client, Room, RoomState,
+};
+use tracing::{info, instrument};
+
+use crate::directory::{Directory, Listing};
+
+/// The power level needed to advertise a room, matching the default for
+/// admins.
+const OWNER_POWER_LEVEL: i64 = 100;
+
+const MAX_DESCRIPTION_LENGTH: usize = 300;
+const MAX_TAGS: usize = 5;
+const MAX_TAG_LENGTH: usize = 30;
+
+/// The most rooms a search lists.
+const MAX_RESULTS: usize = 10;
+
+const ADVERTISE_HELP: &str = "Usage, in the room you want to advertise:
+!advertise A friendly place to talk about gardening | plants, outdoors
+!advertise remove
+Room admins can advertise their room, with up to 5 tags after the |.";
+
+const ROOMS_HELP: &str = "Usage:
+!rooms find <tag or word>
+!rooms tags
+!rooms list";
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    client: Client,
+    directory: Ctx<Directory>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+    let advertise = strip_command(body, "!advertise");
+    let rooms = strip_command(body, "!rooms");
+    if advertise.is_none() && rooms.is_none() {
+        return Ok(());
+    }
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let response = match (advertise, rooms) {
+        (Some(args), _) => advertise_command(&room, &event, &directory, args).await?,
+        (_, Some(args)) => rooms_command(&client, &directory, args)?,
+        _ => return Ok(()),
+    };
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn advertise_command(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    directory: &Directory,
+    args: &str,
+) -> anyhow::Result<String> {
+    let room_id = room.room_id().as_str();
+    if args.is_empty() {
+        return Ok(match directory.table.advert(room_id)? {
+            Some(advert) => format!(
+                "This room is advertised by {} as: {}\n\n{ADVERTISE_HELP}",
+                advert.advertised_by, advert.description
+            ),
+            None => ADVERTISE_HELP.to_owned(),
+        });
+    }
+
+    let is_owner = room
+        .get_member(&event.sender)
+        .await?
+        .is_some_and(|member| member.power_level() >= OWNER_POWER_LEVEL);
+    if !is_owner {
+        return Ok("Only room admins can advertise a room.".to_owned());
+    }
+
+    if args == "remove" {
+        if !directory.table.withdraw(room_id)? {
+            return Ok("This room isn't advertised.".to_owned());
+        }
+        directory.wake.notify_one();
+        info!("Withdrew advert");
+        return Ok("This room is no longer advertised.".to_owned());
+    }
+
+    if room.join_rule() == JoinRule::Invite {
+        return Ok(
+            "This room is invite-only, so nobody could join it from the catalog.".to_owned(),
+        );
+    }
+    let (description, tags) = match parse_advert(args) {
+        Ok(advert) => advert,
+        Err(problem) => return Ok(problem),
+    };
+    directory
+        .table
+        .advertise(room_id, &description, &tags, event.sender.as_str())?;
+    directory.wake.notify_one();
+    info!(?tags, "Advertised room");
+    Ok("This room is advertised now. It's listed in the catalog of any space it's in.".to_owned())
+}
+
+/// Split `description | tag, tag` into the description and normalised tags.
+fn parse_advert(args: &str) -> Result<(String, Vec<String>), String> {
+    let (description, tags) = args.split_once('|').unwrap_or((args, ""));
+    let description = description.split_whitespace().collect::<Vec<_>>().join(" ");
+    if description.is_empty() {
+        return Err(ADVERTISE_HELP.to_owned());
+    }
+    if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        return Err(format!(
+            "Descriptions can be at most {MAX_DESCRIPTION_LENGTH} characters long."
+        ));
+    }
+
+    let mut normalised: Vec<String> = Vec::new();
+    for tag in tags.split([',', ' ']) {
+        let tag = tag.trim().trim_start_matches('#').to_lowercase();
+        if tag.is_empty() || normalised.contains(&tag) {
+            continue;
+        }
+        if tag.chars().count() > MAX_TAG_LENGTH
+            || !tag.chars().all(|c| c.is_alphanumeric() || c == '-')
+        {
+            return Err(format!(
+                "{tag} can't be a tag, use letters, numbers and dashes, up to {MAX_TAG_LENGTH} of them."
+            ));
+        }
+        normalised.push(tag);
+    }
+    if normalised.len() > MAX_TAGS {
+        return Err(format!("A room can have at most {MAX_TAGS} tags."));
+    }
+    Ok((description, normalised))
+}
+
+fn rooms_command(client: &Client, directory: &Directory, args: &str) -> anyhow::Result<String> {
+    let (subcommand, query) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let query = query.trim().trim_start_matches('#');
+
+    let (adverts, empty) = match subcommand {
+        "find" | "search" if !query.is_empty() => (
+            directory.table.find(query)?,
+            format!("No advertised rooms match {query}."),
+        ),
+        "list" | "" => (
+            directory.table.adverts()?,
+            "No rooms are advertised yet.".to_owned(),
+        ),
+        "tags" => {
+            let tags = directory.table.tags()?;
+            if tags.is_empty() {
+                return Ok("No rooms have tags yet.".to_owned());
+            }
+            let tags: Vec<String> = tags
+                .into_iter()
+                .map(|(tag, count)| format!("{tag} ({count})"))
+                .collect();
+            return Ok(format!("Tags: {}", tags.join(", ")));
+        }
+        _ => return Ok(ROOMS_HELP.to_owned()),
+    };
+    if adverts.is_empty() {
+        return Ok(empty);
+    }
+
+    let total = adverts.len();
+    let mut listings: Vec<Listing> = adverts
+        .into_iter()
+        .map(|advert| Listing::new(client, advert))
+        .collect();
+    if subcommand == "list" || subcommand.is_empty() {
+        listings.sort_by_key(|listing| listing.name.to_lowercase());
+    }
+    let mut lines: Vec<String> = listings
+        .iter()
+        .take(MAX_RESULTS)
+        .map(Listing::plain)
+        .collect();
+    if total > MAX_RESULTS {
+        lines.push(format!(
+            "…and {} more, try a narrower search.",
+            total - MAX_RESULTS
+        ));
+    }
+    Ok(lines.join("\n"))
+}
@@ -0,0 +1,83 @@
+mod directory;
+mod handlers;
+mod table;
+
+use std::{sync::Arc, time::Duration};
+
+use clap::Parser;
+use directory::{Catalog, Directory};
+use handlers::on_room_message;
+use matrix_bot_core::{AccountConfig, Bot};
+use matrix_sdk::ruma::OwnedRoomId;
+use table::AdvertTable;
+use tokio::sync::Notify;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Keep a pinned catalog of a space's advertised rooms in a room, as
+    /// `!space:example.org=!room:example.org`. May be given more than once
+    #[arg(long = "catalog", value_parser = parse_catalog, value_delimiter = ',', env = "ROOMDIRECTORY_CATALOGS")]
+    pub catalogs: Vec<Catalog>,
+
+    /// How often to refresh catalogs, to pick up renamed rooms and changes
+    /// to spaces
+    #[arg(long, default_value = "1h", value_parser = parse_refresh, env = "ROOMDIRECTORY_REFRESH")]
+    pub refresh: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_catalog(catalog: &str) -> Result<Catalog, String> {
+    let (space, room) = catalog
+        .split_once('=')
+        .ok_or_else(|| format!("expected !space:example.org=!room:example.org, got {catalog}"))?;
+    let parse = |room_id: &str| {
+        OwnedRoomId::try_from(room_id.trim()).map_err(|err| format!("{room_id}: {err}"))
+    };
+    Ok(Catalog {
+        space: parse(space)?,
+        room: parse(room)?,
+    })
+}
+
+fn parse_refresh(refresh: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(refresh)
+        .ok_or_else(|| format!("invalid refresh interval: {refresh}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-roomdirectory", config.account_config).await?;
+    let table = AdvertTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let directory = Directory {
+        table,
+        catalogs: Arc::new(config.catalogs),
+        wake: Arc::new(Notify::new()),
+    };
+    tokio::spawn(directory::run(
+        bot.client().clone(),
+        directory.clone(),
+        config.refresh,
+    ));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(directory);
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,187 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension, Row};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE adverts (
+        room_id TEXT PRIMARY KEY,
+        description TEXT NOT NULL,
+        tags TEXT NOT NULL,
+        advertised_by TEXT NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE catalogs (
+        space_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        event_id TEXT NOT NULL,
+        body TEXT NOT NULL
+    );"];
+
+/// Advertised rooms and the catalog messages listing them, persisted in
+/// SQLite.
+#[derive(Clone)]
+pub struct AdvertTable {
+    store: Store,
+}
+
+#[derive(Debug, Clone)]
+pub struct Advert {
+    pub room_id: String,
+    pub description: String,
+    /// Lowercase, without duplicates.
+    pub tags: Vec<String>,
+    pub advertised_by: String,
+}
+
+impl Advert {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let tags: String = row.get(2)?;
+        Ok(Self {
+            room_id: row.get(0)?,
+            description: row.get(1)?,
+            tags: tags
+                .split(',')
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            advertised_by: row.get(3)?,
+        })
+    }
+}
+
+/// The message listing a space's advertised rooms.
+#[derive(Debug)]
+pub struct CatalogMessage {
+    pub room_id: String,
+    pub event_id: String,
+    /// What it says now, so it's only edited when that changes.
+    pub body: String,
+}
+
+const ADVERT_COLUMNS: &str = "room_id, description, tags, advertised_by";
+
+impl AdvertTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Add or replace a room's advert.
+    pub fn advertise(
+        &self,
+        room_id: &str,
+        description: &str,
+        tags: &[String],
+        advertised_by: &str,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO adverts (room_id, description, tags, advertised_by, updated_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![room_id, description, tags.join(","), advertised_by, now()],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Remove a room's advert, returning whether it had one.
+    pub fn withdraw(&self, room_id: &str) -> anyhow::Result<bool> {
+        self.store
+            .with(|conn| Ok(conn.execute("DELETE FROM adverts WHERE room_id = ?1", [room_id])? > 0))
+    }
+
+    pub fn advert(&self, room_id: &str) -> anyhow::Result<Option<Advert>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                &format!("SELECT {ADVERT_COLUMNS} FROM adverts WHERE room_id = ?1"),
+                [room_id],
+                Advert::from_row,
+            )
+            .optional()
+        })
+    }
+
+    pub fn adverts(&self) -> anyhow::Result<Vec<Advert>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!("SELECT {ADVERT_COLUMNS} FROM adverts"))?;
+            let adverts = statement
+                .query_map([], Advert::from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(adverts)
+        })
+    }
+
+    /// Adverts with the tag, or that mention it in their description.
+    pub fn find(&self, query: &str) -> anyhow::Result<Vec<Advert>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {ADVERT_COLUMNS} FROM adverts
+                WHERE ',' || tags || ',' LIKE '%,' || ?1 || ',%' ESCAPE '\\'
+                    OR description LIKE '%' || ?1 || '%' ESCAPE '\\'
+                ORDER BY ',' || tags || ',' LIKE '%,' || ?1 || ',%' ESCAPE '\\' DESC"
+            ))?;
+            let adverts = statement
+                .query_map([escape_like(&query.to_lowercase())], Advert::from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(adverts)
+        })
+    }
+
+    /// Every tag in use, with how many rooms have it, most used first.
+    pub fn tags(&self) -> anyhow::Result<Vec<(String, usize)>> {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for advert in self.adverts()? {
+            for tag in advert.tags {
+                match counts.iter_mut().find(|(known, _)| *known == tag) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((tag, 1)),
+                }
+            }
+        }
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        Ok(counts)
+    }
+
+    /// A space's catalog message, if one has been posted.
+    pub fn catalog(&self, space_id: &str) -> anyhow::Result<Option<CatalogMessage>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT room_id, event_id, body FROM catalogs WHERE space_id = ?1",
+                [space_id],
+                |row| {
+                    Ok(CatalogMessage {
+                        room_id: row.get(0)?,
+                        event_id: row.get(1)?,
+                        body: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+
+    pub fn set_catalog(&self, space_id: &str, message: &CatalogMessage) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO catalogs (space_id, room_id, event_id, body)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![space_id, message.room_id, message.event_id, message.body],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+/// Escape `%`, `_` and `\` for a `LIKE` pattern using `\` as the escape.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
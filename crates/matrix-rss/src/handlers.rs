@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use matrix_bot_core::{
+    can_reply, format_duration, is_moderator, parse_duration, reply_notice, strip_command,
+    text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState,
+};
+use tracing::{info, instrument};
+
+use crate::{
+    poller::fetch,
+    render::{DEFAULT_TEMPLATE, PLACEHOLDERS},
+    table::FeedTable,
+};
+
+const HELP: &str = "Usage:
+!rss add <url> [interval, e.g. 30m]
+!rss list
+!rss remove <id>
+!rss interval <id> <interval>
+!rss template <id> <template|reset>";
+
+#[derive(Clone)]
+pub struct Feeds {
+    pub table: FeedTable,
+    pub http: reqwest::Client,
+    pub default_interval: Duration,
+    pub min_interval: Duration,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    feeds: Ctx<Feeds>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!rss")) else {
+        return Ok(());
+    };
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let response = run_command(args, &event, &room, &feeds).await?;
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn run_command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    feeds: &Feeds,
+) -> anyhow::Result<String> {
+    let room_id = room.room_id().as_str();
+    let (subcommand, args) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let args = args.trim();
+
+    if matches!(subcommand, "add" | "remove" | "interval" | "template")
+        && !is_moderator(room, &event.sender).await?
+    {
+        return Ok("Only moderators can change this room's feeds.".to_owned());
+    }
+
+    // Most subcommands start with a feed ID
+    let (id, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let id = id.parse::<i64>().ok();
+    let rest = rest.trim();
+
+    Ok(match (subcommand, id) {
+        ("add", _) => {
+            let (url, interval) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            let interval = match interval.trim() {
+                "" => feeds.default_interval,
+                interval => match parse_duration(interval) {
+                    Some(interval) => interval.max(feeds.min_interval),
+                    None => return Ok(format!("I don't understand the interval {interval}.")),
+                },
+            };
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Ok("Give me the feed's http(s) URL.".to_owned());
+            }
+
+            let parsed = match fetch(&feeds.http, url).await {
+                Ok(parsed) => parsed,
+                Err(err) => return Ok(format!("I couldn't load that feed: {err}")),
+            };
+            let title = parsed
+                .title
+                .as_ref()
+                .map_or(url.to_owned(), |title| title.content.clone());
+
+            let id = match feeds.table.add(room_id, url, &title, interval) {
+                Ok(id) => id,
+                Err(err)
+                    if err
+                        .downcast_ref::<rusqlite::Error>()
+                        .and_then(|e| e.sqlite_error_code())
+                        == Some(rusqlite::ErrorCode::ConstraintViolation) =>
+                {
+                    return Ok("That feed is already in this room.".to_owned());
+                }
+                Err(err) => return Err(err),
+            };
+            // Only post items published from now on
+            feeds
+                .table
+                .mark_seen(id, parsed.entries.iter().map(|e| e.id.as_str()))?;
+            info!(id, url, "Added feed");
+            format!(
+                "Added feed {id}: {title}, checking every {}.",
+                format_duration(interval)
+            )
+        }
+        ("list", _) => {
+            let in_room = feeds.table.in_room(room_id)?;
+            if in_room.is_empty() {
+                return Ok("There are no feeds in this room.".to_owned());
+            }
+            in_room
+                .iter()
+                .map(|feed| {
+                    format!(
+                        "{}. {} <{}> every {}{}",
+                        feed.id,
+                        feed.title,
+                        feed.url,
+                        format_duration(feed.interval),
+                        if feed.template.is_some() {
+                            " (custom template)"
+                        } else {
+                            ""
+                        }
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        ("remove", Some(id)) => {
+            if feeds.table.remove(room_id, id)? {
+                info!(id, "Removed feed");
+                format!("Removed feed {id}.")
+            } else {
+                format!("There's no feed {id} in this room.")
+            }
+        }
+        ("interval", Some(id)) => {
+            let Some(interval) = parse_duration(rest) else {
+                return Ok("How often should I check it? e.g. `30m` or `2h`".to_owned());
+            };
+            let interval = interval.max(feeds.min_interval);
+            if feeds.table.set_interval(room_id, id, interval)? {
+                format!("Checking feed {id} every {}.", format_duration(interval))
+            } else {
+                format!("There's no feed {id} in this room.")
+            }
+        }
+        ("template", Some(id)) => {
+            if rest.is_empty() {
+                let template = feeds
+                    .table
+                    .get(room_id, id)?
+                    .and_then(|feed| feed.template)
+                    .unwrap_or_else(|| DEFAULT_TEMPLATE.to_owned());
+                return Ok(format!(
+                    "Feed {id} uses the template {template}\nAvailable placeholders: {}",
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+            let template = (rest != "reset").then_some(rest);
+            if feeds.table.set_template(room_id, id, template)? {
+                format!("Updated the template for feed {id}.")
+            } else {
+                format!("There's no feed {id} in this room.")
+            }
+        }
+        ("remove" | "interval" | "template", None) => {
+            "Which feed? Give its number from `!rss list`.".to_owned()
+        }
+        _ => HELP.to_owned(),
+    })
+}
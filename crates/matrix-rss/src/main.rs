@@ -0,0 +1,74 @@
+mod handlers;
+mod poller;
+mod render;
+mod table;
+
+use std::time::Duration;
+
+use clap::Parser;
+use handlers::{on_room_message, Feeds};
+use matrix_bot_core::{portable, AccountConfig, Bot};
+use table::FeedTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// How often to poll feeds that don't set their own interval
+    #[arg(long, default_value = "15m", value_parser = parse_interval, env = "RSS_DEFAULT_INTERVAL")]
+    pub default_interval: Duration,
+
+    /// The shortest interval rooms may poll a feed at
+    #[arg(long, default_value = "5m", value_parser = parse_interval, env = "RSS_MIN_INTERVAL")]
+    pub min_interval: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_interval(interval: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(interval).ok_or_else(|| format!("invalid interval: {interval}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    if portable::requested() {
+        return portable::run("matrix-rss").await;
+    }
+
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-rss", config.account_config).await?;
+    let table = FeedTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("matrix-rss/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(30))
+        .build()?;
+
+    tokio::spawn(poller::run(
+        bot.client().clone(),
+        table.clone(),
+        http.clone(),
+    ));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Feeds {
+        table,
+        http,
+        default_interval: config.default_interval,
+        min_interval: config.min_interval,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use feed_rs::model::Feed as ParsedFeed;
+use matrix_bot_core::{can_reply, send_or_log_error};
+use matrix_sdk::{Client, RoomState};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    render::{render, DEFAULT_TEMPLATE},
+    table::{Feed, FeedTable},
+};
+
+/// How often to check for feeds that are due to be polled.
+const TICK: Duration = Duration::from_secs(30);
+
+/// The most new items to post from one poll, so a feed that suddenly
+/// republishes everything doesn't flood the room.
+const MAX_ITEMS_PER_POLL: usize = 5;
+
+/// The largest feed document we're willing to download.
+const MAX_FEED_SIZE: usize = 5 * 1024 * 1024;
+
+/// Fetch and parse a feed.
+pub async fn fetch(http: &reqwest::Client, url: &str) -> anyhow::Result<ParsedFeed> {
+    let mut response = http.get(url).send().await?.error_for_status()?;
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_FEED_SIZE {
+            anyhow::bail!("feed is larger than {MAX_FEED_SIZE} bytes");
+        }
+    }
+
+    Ok(feed_rs::parser::parse_with_uri(body.as_slice(), Some(url))?)
+}
+
+/// Poll feeds as they come due, forever.
+pub async fn run(client: Client, table: FeedTable, http: reqwest::Client) {
+    let mut ticks = interval(TICK);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticks.tick().await;
+
+        let feeds = match table.due() {
+            Ok(feeds) => feeds,
+            Err(err) => {
+                warn!("Failed to load feeds: {err}");
+                continue;
+            }
+        };
+        for feed in feeds {
+            poll(&client, &table, &http, &feed).await;
+            if let Err(err) = table.schedule_next_poll(&feed) {
+                warn!(feed = feed.id, "Failed to schedule next poll: {err}");
+            }
+        }
+    }
+}
+
+#[instrument(skip_all, fields(feed = feed.id, url = feed.url.as_str()))]
+async fn poll(client: &Client, table: &FeedTable, http: &reqwest::Client, feed: &Feed) {
+    let Some(room) = client
+        .get_room(&feed.room_id)
+        .filter(|room| room.state() == RoomState::Joined)
+    else {
+        debug!("Not in the feed's room, skipping");
+        return;
+    };
+
+    let parsed = match fetch(http, &feed.url).await {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            warn!("Failed to fetch feed: {err}");
+            return;
+        }
+    };
+
+    let new = match table.mark_seen(feed.id, parsed.entries.iter().map(|e| e.id.as_str())) {
+        Ok(new) => new,
+        Err(err) => {
+            warn!("Failed to record seen items: {err}");
+            return;
+        }
+    };
+    if new.is_empty() || !can_reply(&room).await {
+        return;
+    }
+
+    // Feeds list the newest items first, post them oldest first
+    let mut entries: Vec<_> = parsed
+        .entries
+        .iter()
+        .filter(|entry| new.contains(&entry.id.as_str()))
+        .collect();
+    entries.sort_by_key(|entry| entry.published.or(entry.updated));
+    if entries.len() > MAX_ITEMS_PER_POLL {
+        info!(
+            skipped = entries.len() - MAX_ITEMS_PER_POLL,
+            "Too many new items, only posting the latest"
+        );
+        entries.drain(..entries.len() - MAX_ITEMS_PER_POLL);
+    }
+
+    let template = feed.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+    for entry in entries {
+        info!(item = entry.id, "Posting new item");
+        send_or_log_error(&room, render(template, &feed.title, &parsed, entry)).await;
+    }
+}
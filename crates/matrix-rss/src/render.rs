@@ -0,0 +1,102 @@
+use feed_rs::model::{Entry, Feed as ParsedFeed};
+use matrix_bot_core::html;
+use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+
+/// Template used for feeds without a custom one.
+pub const DEFAULT_TEMPLATE: &str = "<b>{feed}</b>: <a href=\"{link}\">{title}</a>";
+
+/// The most characters of an item's summary to include.
+const SUMMARY_LENGTH: usize = 300;
+
+/// Placeholders available in templates.
+pub const PLACEHOLDERS: &[&str] = &[
+    "{feed}",
+    "{title}",
+    "{link}",
+    "{summary}",
+    "{author}",
+    "{published}",
+];
+
+/// Render a feed item with an HTML template containing [`PLACEHOLDERS`].
+pub fn render(
+    template: &str,
+    feed_title: &str,
+    feed: &ParsedFeed,
+    entry: &Entry,
+) -> RoomMessageEventContent {
+    let feed_title = feed
+        .title
+        .as_ref()
+        .map_or(feed_title, |title| title.content.as_str());
+    let title = entry
+        .title
+        .as_ref()
+        .map_or("Untitled", |title| title.content.as_str());
+    let link = entry
+        .links
+        .first()
+        .map_or(String::new(), |link| link.href.clone());
+    let summary = entry
+        .summary
+        .as_ref()
+        .map(|summary| truncate(&strip_tags(&summary.content), SUMMARY_LENGTH))
+        .unwrap_or_default();
+    let author = entry
+        .authors
+        .first()
+        .map_or(String::new(), |author| author.name.clone());
+    let published = entry
+        .published
+        .or(entry.updated)
+        .map_or(String::new(), |date| {
+            date.format("%Y-%m-%d %H:%M UTC").to_string()
+        });
+
+    let values = [
+        feed_title,
+        title,
+        link.as_str(),
+        summary.as_str(),
+        author.as_str(),
+        published.as_str(),
+    ];
+    let fill = |escape: fn(&str) -> String| {
+        PLACEHOLDERS
+            .iter()
+            .zip(values)
+            .fold(template.to_owned(), |text, (placeholder, value)| {
+                text.replace(placeholder, &escape(value))
+            })
+    };
+
+    let formatted = fill(html::escape);
+    let mut plain = strip_tags(&fill(str::to_owned));
+    // Links are usually only in an `href`, which doesn't survive stripping
+    if !link.is_empty() && !plain.contains(&link) {
+        plain = format!("{plain} {link}");
+    }
+    RoomMessageEventContent::notice_html(plain, formatted)
+}
+
+/// Remove HTML tags, for plain-text bodies and summaries.
+fn strip_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn truncate(text: &str, length: usize) -> String {
+    match text.char_indices().nth(length) {
+        Some((index, _)) => format!("{}…", &text[..index]),
+        None => text.to_owned(),
+    }
+}
@@ -0,0 +1,211 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use matrix_sdk::ruma::OwnedRoomId;
+use rusqlite::{params, OptionalExtension, Row};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE feeds (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        url TEXT NOT NULL,
+        title TEXT NOT NULL,
+        interval INTEGER NOT NULL,
+        template TEXT,
+        next_poll INTEGER NOT NULL,
+        UNIQUE (room_id, url)
+    );
+    CREATE TABLE seen (
+        feed_id INTEGER NOT NULL REFERENCES feeds (id) ON DELETE CASCADE,
+        item_id TEXT NOT NULL,
+        seen_at INTEGER NOT NULL,
+        PRIMARY KEY (feed_id, item_id)
+    );"];
+
+/// How long to remember items that have dropped out of a feed.
+const SEEN_RETENTION: Duration = Duration::from_secs(90 * 86400);
+
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub id: i64,
+    pub room_id: OwnedRoomId,
+    pub url: String,
+    pub title: String,
+    pub interval: Duration,
+    /// A custom template for new items, see [`crate::render`].
+    pub template: Option<String>,
+}
+
+/// Feeds and the items already posted from them, persisted in SQLite.
+#[derive(Clone)]
+pub struct FeedTable {
+    store: Store,
+}
+
+impl FeedTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Add a feed to a room, returning its ID.
+    pub fn add(
+        &self,
+        room_id: &str,
+        url: &str,
+        title: &str,
+        interval: Duration,
+    ) -> anyhow::Result<i64> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO feeds (room_id, url, title, interval, next_poll) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![room_id, url, title, interval.as_secs(), now() + interval.as_secs()],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    pub fn get(&self, room_id: &str, id: i64) -> anyhow::Result<Option<Feed>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT id, room_id, url, title, interval, template FROM feeds
+                    WHERE room_id = ?1 AND id = ?2",
+                params![room_id, id],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    pub fn in_room(&self, room_id: &str) -> anyhow::Result<Vec<Feed>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT id, room_id, url, title, interval, template FROM feeds
+                WHERE room_id = ?1 ORDER BY id",
+            )?;
+            let feeds = statement
+                .query_map([room_id], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(feeds)
+        })
+    }
+
+    /// Feeds that are due to be polled.
+    pub fn due(&self) -> anyhow::Result<Vec<Feed>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT id, room_id, url, title, interval, template FROM feeds
+                WHERE next_poll <= ?1 ORDER BY next_poll",
+            )?;
+            let feeds = statement
+                .query_map([now()], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(feeds)
+        })
+    }
+
+    pub fn schedule_next_poll(&self, feed: &Feed) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE feeds SET next_poll = ?2 WHERE id = ?1",
+                params![feed.id, now() + feed.interval.as_secs()],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn set_interval(&self, room_id: &str, id: i64, interval: Duration) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let updated = conn.execute(
+                "UPDATE feeds SET interval = ?3, next_poll = MIN(next_poll, ?4)
+                WHERE room_id = ?1 AND id = ?2",
+                params![room_id, id, interval.as_secs(), now() + interval.as_secs()],
+            )?;
+            Ok(updated > 0)
+        })
+    }
+
+    pub fn set_template(
+        &self,
+        room_id: &str,
+        id: i64,
+        template: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let updated = conn.execute(
+                "UPDATE feeds SET template = ?3 WHERE room_id = ?1 AND id = ?2",
+                params![room_id, id, template],
+            )?;
+            Ok(updated > 0)
+        })
+    }
+
+    pub fn remove(&self, room_id: &str, id: i64) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM feeds WHERE room_id = ?1 AND id = ?2",
+                params![room_id, id],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+
+    /// Record items as seen, returning the IDs that weren't seen before.
+    pub fn mark_seen<'a>(
+        &self,
+        feed_id: i64,
+        item_ids: impl IntoIterator<Item = &'a str>,
+    ) -> anyhow::Result<Vec<&'a str>> {
+        self.store.transaction(|transaction| {
+            let mut new = Vec::new();
+            {
+                let mut insert = transaction.prepare(
+                    "INSERT INTO seen (feed_id, item_id, seen_at) VALUES (?1, ?2, ?3)
+                    ON CONFLICT DO UPDATE SET seen_at = excluded.seen_at",
+                )?;
+                let mut exists = transaction
+                    .prepare("SELECT 1 FROM seen WHERE feed_id = ?1 AND item_id = ?2")?;
+                for item_id in item_ids {
+                    if !exists.exists(params![feed_id, item_id])? {
+                        new.push(item_id);
+                    }
+                    insert.execute(params![feed_id, item_id, now()])?;
+                }
+            }
+            // Items that are still in the feed were just refreshed, so this only
+            // forgets ones that dropped out of it long ago.
+            transaction.execute(
+                "DELETE FROM seen WHERE feed_id = ?1 AND seen_at < ?2",
+                params![feed_id, now().saturating_sub(SEEN_RETENTION.as_secs())],
+            )?;
+            Ok(new)
+        })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Feed> {
+    let room_id: String = row.get(1)?;
+    Ok(Feed {
+        id: row.get(0)?,
+        room_id: room_id
+            .try_into()
+            .map_err(|err: matrix_sdk::ruma::IdParseError| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    1,
+                    rusqlite::types::Type::Text,
+                    err.into(),
+                )
+            })?,
+        url: row.get(2)?,
+        title: row.get(3)?,
+        interval: Duration::from_secs(row.get(4)?),
+        template: row.get(5)?,
+    })
+}
@@ -0,0 +1,80 @@
+//! Working out who wrote a bridged message, so corrections in bridged rooms
+//! know whose message they are.
+//!
+//! Puppeting bridges give each remote user their own Matrix user, like
+//! `@irc_alice:example.org`, and relaying bridges post everyone's messages
+//! from one user with the name in front, like `[irc] <alice> hello`. Both
+//! are configured with patterns whose first group captures the remote
+//! user's name, so the same person is recognised either way.
+
+use std::sync::OnceLock;
+
+use clap::Parser;
+use matrix_sdk::ruma::{OwnedUserId, UserId};
+use regex::Regex;
+
+static CONFIG: OnceLock<BridgeConfig> = OnceLock::new();
+
+#[derive(Parser, Debug, Clone, Default)]
+pub struct BridgeConfig {
+    /// A pattern matching bridge puppets' user IDs, capturing the remote
+    /// user's name, like `^@irc_(.+):example\.org$`. Can be given more than
+    /// once
+    #[arg(long = "bridge-puppet", env = "SED_BRIDGE_PUPPET")]
+    pub puppets: Vec<Regex>,
+    /// A pattern matching the name a relaying bridge puts in front of
+    /// messages, capturing the name, like `^\[irc\] <([^>]+)> `. Can be
+    /// given more than once
+    #[arg(long = "bridge-prefix", env = "SED_BRIDGE_PREFIX")]
+    pub prefixes: Vec<Regex>,
+}
+
+/// Who wrote a message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Speaker {
+    Matrix(OwnedUserId),
+    /// Someone on the other side of a bridge, by name.
+    Bridged(String),
+}
+
+pub fn configure(config: BridgeConfig) {
+    let _ = CONFIG.set(config);
+}
+
+/// Who wrote the message `body` sent by `sender`.
+pub fn speaker(sender: &UserId, body: &str) -> Speaker {
+    let Some(config) = CONFIG.get() else {
+        return Speaker::Matrix(sender.to_owned());
+    };
+    let captured = config
+        .prefixes
+        .iter()
+        .find_map(|prefix| {
+            prefix
+                .captures(body)
+                .filter(|captures| captures.get(0).is_some_and(|found| found.start() == 0))
+        })
+        .or_else(|| {
+            config
+                .puppets
+                .iter()
+                .find_map(|puppet| puppet.captures(sender.as_str()))
+        })
+        .and_then(|captures| captures.get(1));
+    match captured {
+        Some(name) => Speaker::Bridged(name.as_str().to_owned()),
+        None => Speaker::Matrix(sender.to_owned()),
+    }
+}
+
+/// `body` without a relaying bridge's name in front.
+pub fn strip_prefix(body: &str) -> &str {
+    let Some(config) = CONFIG.get() else {
+        return body;
+    };
+    config
+        .prefixes
+        .iter()
+        .find_map(|prefix| prefix.find(body).filter(|found| found.start() == 0))
+        .map_or(body, |found| &body[found.end()..])
+}
@@ -1,10 +1,32 @@
-use std::future::Future;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    future::Future,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
+use matrix_bot_core::origins;
 use matrix_sdk::{
-    deserialized_responses::SyncTimelineEvent, room::edit::EditError, ruma::EventId, Room,
+    deserialized_responses::SyncTimelineEvent,
+    room::edit::EditError,
+    ruma::{
+        events::room::message::{OriginalRoomMessageEvent, OriginalSyncRoomMessageEvent, Relation},
+        EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+    },
+    Room,
 };
 use tracing::{debug, trace};
 
+/// How long a fetched target is kept.
+const TARGET_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How many fetched targets are kept.
+const MAX_TARGETS: usize = 256;
+
+/// How long after a command the same one from the same person is ignored.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(10);
+
 pub trait EventSource {
     fn get_event(
         &self,
@@ -28,9 +50,215 @@ impl<'a> EventSource for &'a Room {
         }
 
         trace!("trying with /event now");
-        self.event(event_id, None)
+        origins::event(self, event_id)
             .await
             .map(Into::into)
             .map_err(|err| EditError::Fetch(Box::new(err)))
     }
 }
+
+/// The last few messages seen in each room from syncing, so most targets
+/// can be found without asking the homeserver. Cloning it shares the cache.
+#[derive(Clone)]
+pub struct RecentMessages {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    messages_per_room: usize,
+    /// When there are more rooms than this, the one that's been quiet the
+    /// longest is forgotten.
+    max_rooms: usize,
+    rooms: HashMap<OwnedRoomId, RoomMessages>,
+    /// How many messages are cached across all rooms.
+    cached: usize,
+    /// How many messages have been seen, to order rooms by activity.
+    seen: u64,
+}
+
+#[derive(Default)]
+struct RoomMessages {
+    /// Oldest first.
+    messages: VecDeque<OriginalSyncRoomMessageEvent>,
+    /// When a message was last seen in the room, counted in messages.
+    last_seen: u64,
+}
+
+impl RecentMessages {
+    pub fn new(messages_per_room: usize, max_rooms: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                messages_per_room,
+                max_rooms,
+                rooms: HashMap::new(),
+                cached: 0,
+                seen: 0,
+            })),
+        }
+    }
+
+    /// Remember a message, in the order they come down the sync.
+    pub fn record(&self, room_id: &RoomId, event: &OriginalSyncRoomMessageEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.messages_per_room == 0 || inner.max_rooms == 0 {
+            return;
+        }
+        inner.seen += 1;
+        if !inner.rooms.contains_key(room_id) && inner.rooms.len() >= inner.max_rooms {
+            let quietest = inner
+                .rooms
+                .iter()
+                .min_by_key(|(_, room)| room.last_seen)
+                .map(|(room_id, _)| room_id.clone());
+            if let Some(room) = quietest.and_then(|quietest| inner.rooms.remove(&quietest)) {
+                inner.cached -= room.messages.len();
+            }
+        }
+
+        let Inner {
+            messages_per_room,
+            rooms,
+            cached,
+            seen,
+            ..
+        } = &mut *inner;
+        let room = rooms.entry(room_id.to_owned()).or_default();
+        if room.messages.len() >= *messages_per_room {
+            room.messages.pop_front();
+            *cached -= 1;
+        }
+        room.messages.push_back(event.clone());
+        room.last_seen = *seen;
+        *cached += 1;
+
+        metrics::gauge!("sed_recent_messages").set(*cached as f64);
+        metrics::gauge!("sed_recent_rooms").set(rooms.len() as f64);
+    }
+
+    /// Forget every message.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.rooms.clear();
+        inner.cached = 0;
+        metrics::gauge!("sed_recent_messages").set(0.0);
+        metrics::gauge!("sed_recent_rooms").set(0.0);
+    }
+
+    /// A message by its ID, if it's recent.
+    pub fn get(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Option<OriginalSyncRoomMessageEvent> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .rooms
+            .get(room_id)?
+            .messages
+            .iter()
+            .rev()
+            .find(|message| message.event_id == event_id)
+            .cloned()
+    }
+
+    /// The last message outside of a thread before `event_id`, if both are
+    /// recent.
+    pub fn before(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Option<OriginalSyncRoomMessageEvent> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .rooms
+            .get(room_id)?
+            .messages
+            .iter()
+            .rev()
+            .skip_while(|message| message.event_id != event_id)
+            .skip(1)
+            .find(|message| !matches!(message.content.relates_to, Some(Relation::Thread(_))))
+            .cloned()
+    }
+}
+
+/// Targets fetched from the homeserver, so a message several people correct
+/// is only fetched and parsed once. Cloning it shares the cache.
+///
+/// Targets are kept by room as well as event ID, so a reply in one room
+/// can't correct a message from another that the bot fetched recently.
+#[derive(Clone, Default)]
+pub struct Targets {
+    targets: Arc<Mutex<HashMap<TargetKey, (Instant, OriginalRoomMessageEvent)>>>,
+}
+
+type TargetKey = (OwnedRoomId, OwnedEventId);
+
+impl Targets {
+    /// A target in `room_id` fetched in the last [`TARGET_TTL`].
+    pub fn get(&self, room_id: &RoomId, event_id: &EventId) -> Option<OriginalRoomMessageEvent> {
+        let targets = self.targets.lock().unwrap();
+        let (fetched, target) = targets.get(&(room_id.to_owned(), event_id.to_owned()))?;
+        (fetched.elapsed() < TARGET_TTL).then(|| target.clone())
+    }
+
+    /// Forget every target.
+    pub fn clear(&self) {
+        self.targets.lock().unwrap().clear();
+        metrics::gauge!("sed_cached_targets").set(0.0);
+    }
+
+    pub fn insert(&self, target: OriginalRoomMessageEvent) {
+        let mut targets = self.targets.lock().unwrap();
+        targets.retain(|_, (fetched, _)| fetched.elapsed() < TARGET_TTL);
+        if targets.len() >= MAX_TARGETS {
+            let oldest = targets
+                .iter()
+                .min_by_key(|(_, (fetched, _))| *fetched)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                targets.remove(&oldest);
+            }
+        }
+        targets.insert(
+            (target.room_id.clone(), target.event_id.clone()),
+            (Instant::now(), target),
+        );
+        metrics::gauge!("sed_cached_targets").set(targets.len() as f64);
+    }
+}
+
+/// Commands seen in the last [`DUPLICATE_WINDOW`], so one a client sent
+/// twice or someone double-posted is only answered once. Cloning it shares
+/// them.
+#[derive(Clone, Default)]
+pub struct Duplicates {
+    seen: Arc<Mutex<HashMap<(OwnedRoomId, OwnedUserId, u64), Instant>>>,
+}
+
+impl Duplicates {
+    /// Note a command, returning whether the same one was already sent in
+    /// the room by `sender` a moment ago. Commands replying to different
+    /// messages aren't the same.
+    pub fn check(
+        &self,
+        room_id: &RoomId,
+        sender: &UserId,
+        body: &str,
+        reply_to: Option<&EventId>,
+    ) -> bool {
+        let mut hasher = DefaultHasher::new();
+        (body, reply_to).hash(&mut hasher);
+        let key = (room_id.to_owned(), sender.to_owned(), hasher.finish());
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, at| at.elapsed() < DUPLICATE_WINDOW);
+        let duplicate = seen.contains_key(&key);
+        if duplicate {
+            debug!(room = room_id.as_str(), "Ignoring a duplicate command");
+        } else {
+            seen.insert(key, Instant::now());
+        }
+        duplicate
+    }
+}
@@ -0,0 +1,98 @@
+//! The ways a correction can fail, so people can tell what went wrong from
+//! the reply and operators can from the `sed_failures_total` metric.
+
+use std::fmt;
+
+use matrix_sdk::{room::edit::EditError, ruma::api::client::error::ErrorKind};
+
+/// Why a correction failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The command isn't a valid substitution.
+    ParseError,
+    /// There was no message to correct, or the bot couldn't see it.
+    TargetNotFound,
+    /// The homeserver didn't let the bot do something it needed to.
+    PermissionDenied,
+    /// Correcting took too long.
+    Timeout,
+    /// Something went wrong talking to the homeserver, or in the bot.
+    ServerError,
+}
+
+impl Failure {
+    /// Work out which kind of failure `err` is.
+    pub fn of(err: &anyhow::Error) -> Self {
+        if let Some(failure) = err.downcast_ref::<Self>() {
+            return *failure;
+        }
+        if err.is::<sedregex::ErrorKind>() {
+            return Self::ParseError;
+        }
+        if err.is::<HistoryHidden>() {
+            return Self::TargetNotFound;
+        }
+        if err.is::<tokio::time::error::Elapsed>() {
+            return Self::Timeout;
+        }
+        let kind = if let Some(err) = err.downcast_ref::<matrix_sdk::Error>() {
+            err.client_api_error_kind()
+        } else if let Some(EditError::Fetch(err)) = err.downcast_ref::<EditError>() {
+            err.client_api_error_kind()
+        } else {
+            None
+        };
+        match kind {
+            Some(ErrorKind::NotFound) => Self::TargetNotFound,
+            Some(ErrorKind::Forbidden { .. }) => Self::PermissionDenied,
+            _ => Self::ServerError,
+        }
+    }
+
+    /// The code for the failure, shown in replies and used as the metric
+    /// label.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::ParseError => "parse_error",
+            Self::TargetNotFound => "target_not_found",
+            Self::PermissionDenied => "permission_denied",
+            Self::Timeout => "timeout",
+            Self::ServerError => "server_error",
+        }
+    }
+
+    /// Whether the failure is the bot's or the homeserver's problem rather
+    /// than the command's, and so worth logging as an error.
+    pub fn is_unexpected(self) -> bool {
+        matches!(self, Self::Timeout | Self::ServerError)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::ParseError => "That isn't a substitution I understand",
+            Self::TargetNotFound => "I couldn't find a message to correct",
+            Self::PermissionDenied => "I'm not allowed to see the message to correct",
+            Self::Timeout => "That correction took too long",
+            Self::ServerError => "I couldn't make that correction",
+        })
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The target was sent before the bot could see the room's history.
+#[derive(Debug)]
+pub struct HistoryHidden;
+
+impl fmt::Display for HistoryHidden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            "I can't see that message. This room only shows people messages sent since they \
+             joined, and it was sent before I did",
+        )
+    }
+}
+
+impl std::error::Error for HistoryHidden {}
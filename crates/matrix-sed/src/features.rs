@@ -0,0 +1,263 @@
+//! Optional behaviours, which can be turned on and off per room and per
+//! person, and `!sed features` to see which are on.
+//!
+//! Each feature is worked out from layers, later ones winning: the bot's
+//! config, the room's [`FEATURES_EVENT_TYPE`] state event, what the room's
+//! moderators have chosen by DMing the bot `config`, and what the person
+//! has chosen with `!sed features <feature> on|off`. The state event maps
+//! feature names to whether they're on:
+//!
+//! ```json
+//! { "html_diff": false, "feedback": true }
+//! ```
+
+use std::{collections::HashMap, fmt::Write, sync::OnceLock};
+
+use matrix_bot_core::{pacing, reply_notice, store::Store, wizard::Setting};
+use matrix_sdk::{
+    deserialized_responses::RawAnySyncOrStrippedState,
+    ruma::{
+        events::{room::message::OriginalSyncRoomMessageEvent, StateEventType},
+        UserId,
+    },
+    Room,
+};
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+/// The state event type rooms set features with.
+pub const FEATURES_EVENT_TYPE: &str = "io.github.jadedblueeyes.bots.sed.features";
+
+const HELP: &str = "Usage:
+!sed features to see what's on here, for you
+!sed features <feature> on|off|default to choose for yourself
+Features: html_diff underlines what changed, feedback says why corrections fail";
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// The features rooms can choose with the config wizard, under the same
+/// keys as people's choices.
+pub const ROOM_SETTINGS: &[Setting] = &[
+    Setting::toggle(
+        "feature.html_diff",
+        "Underline what corrections changed",
+        true,
+    ),
+    Setting::toggle("feature.feedback", "Say why corrections fail", false),
+];
+
+/// Something that can be turned on and off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Underlining what a correction changed.
+    HtmlDiff,
+    /// Saying why a correction failed.
+    Feedback,
+}
+
+impl Feature {
+    const ALL: [Self; 2] = [Self::HtmlDiff, Self::Feedback];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::HtmlDiff => "html_diff",
+            Self::Feedback => "feedback",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|feature| feature.name() == name)
+    }
+
+    /// The preference the person's choice is kept under.
+    fn pref(self) -> String {
+        format!("feature.{}", self.name())
+    }
+}
+
+/// Where a feature's setting came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Config,
+    Room,
+    User,
+}
+
+impl Layer {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Config => "the bot's config",
+            Self::Room => "this room",
+            Self::User => "your choice",
+        }
+    }
+}
+
+/// Whether a feature is on, and which layer said so.
+#[derive(Debug, Clone, Copy)]
+pub struct Resolved {
+    pub feature: Feature,
+    pub enabled: bool,
+    pub from: Layer,
+}
+
+/// The config layer, and the store people's choices are kept in.
+pub struct Settings {
+    pub store: Store,
+    pub html_diff: bool,
+    pub feedback: bool,
+}
+
+impl Settings {
+    fn configured(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::HtmlDiff => self.html_diff,
+            Feature::Feedback => self.feedback,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FeaturesEvent {
+    content: HashMap<String, bool>,
+}
+
+pub fn configure(settings: Settings) {
+    let _ = SETTINGS.set(settings);
+}
+
+/// Work out every feature for `user_id` in the room.
+pub async fn resolve(room: &Room, user_id: &UserId) -> Vec<Resolved> {
+    let Some(settings) = SETTINGS.get() else {
+        return Vec::new();
+    };
+    let room_features = room_features(room).await;
+    Feature::ALL
+        .into_iter()
+        .map(|feature| {
+            let mut resolved = Resolved {
+                feature,
+                enabled: settings.configured(feature),
+                from: Layer::Config,
+            };
+            if let Some(&enabled) = room_features.get(feature.name()) {
+                resolved.enabled = enabled;
+                resolved.from = Layer::Room;
+            }
+            match settings
+                .store
+                .room_setting::<bool>(room.room_id(), &feature.pref())
+            {
+                Ok(Some(enabled)) => {
+                    resolved.enabled = enabled;
+                    resolved.from = Layer::Room;
+                }
+                Ok(None) => {}
+                Err(err) => warn!("Failed to look up the room's feature setting: {err:#}"),
+            }
+            match settings.store.user_pref::<bool>(user_id, &feature.pref()) {
+                Ok(Some(enabled)) => {
+                    resolved.enabled = enabled;
+                    resolved.from = Layer::User;
+                }
+                Ok(None) => {}
+                Err(err) => warn!("Failed to look up a feature preference: {err:#}"),
+            }
+            resolved
+        })
+        .collect()
+}
+
+/// Whether `feature` is on for `user_id` in the room.
+pub async fn enabled(room: &Room, user_id: &UserId, feature: Feature) -> bool {
+    resolve(room, user_id)
+        .await
+        .into_iter()
+        .any(|resolved| resolved.feature == feature && resolved.enabled)
+}
+
+/// The features the room's state event sets.
+async fn room_features(room: &Room) -> HashMap<String, bool> {
+    let event = match room
+        .get_state_event(StateEventType::from(FEATURES_EVENT_TYPE), "")
+        .await
+    {
+        Ok(Some(event)) => event,
+        Ok(None) => return HashMap::new(),
+        Err(err) => {
+            warn!("Failed to look up the room's features: {err}");
+            return HashMap::new();
+        }
+    };
+    let event: Option<FeaturesEvent> = match event {
+        RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as().ok(),
+        RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as().ok(),
+    };
+    event.map(|event| event.content).unwrap_or_default()
+}
+
+/// Answer `!sed features`, with `args` being what came after `features`.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn features(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    args: String,
+) -> anyhow::Result<()> {
+    let response = match args.split_whitespace().collect::<Vec<_>>()[..] {
+        [] => describe(&room, &event.sender).await,
+        [feature, choice] => match (Feature::parse(feature), SETTINGS.get()) {
+            (Some(feature), Some(settings)) => {
+                let store = &settings.store;
+                match choice {
+                    "on" => store.set_user_pref(&event.sender, &feature.pref(), &true)?,
+                    "off" => store.set_user_pref(&event.sender, &feature.pref(), &false)?,
+                    "default" => {
+                        store.remove_user_pref(&event.sender, &feature.pref())?;
+                    }
+                    _ => return help(&room, &event).await,
+                }
+                describe(&room, &event.sender).await
+            }
+            _ => return help(&room, &event).await,
+        },
+        _ => return help(&room, &event).await,
+    };
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn help(room: &Room, event: &OriginalSyncRoomMessageEvent) -> anyhow::Result<()> {
+    reply_notice(room, event, HELP).await;
+    Ok(())
+}
+
+async fn describe(room: &Room, user_id: &UserId) -> String {
+    let mut response = "Here, for you:".to_owned();
+    for resolved in resolve(room, user_id).await {
+        let _ = write!(
+            response,
+            "\n- {}: {} ({})",
+            resolved.feature.name(),
+            if resolved.enabled { "on" } else { "off" },
+            resolved.from.describe()
+        );
+    }
+    let encrypted = room.is_encrypted().await.unwrap_or_default();
+    let _ = write!(
+        response,
+        "\n- encryption: {}",
+        if encrypted { "on" } else { "off" }
+    );
+    let limits = pacing::limits();
+    if limits.is_empty() {
+        response.push_str("\n- rate limits: none in effect");
+    }
+    for (homeserver, interval) in limits {
+        let _ = write!(
+            response,
+            "\n- rate limits: one message every {}ms to {homeserver}",
+            interval.as_millis()
+        );
+    }
+    response
+}
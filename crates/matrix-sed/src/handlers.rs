@@ -1,84 +1,208 @@
-use crate::cache::EventSource;
+use crate::{
+    bridge,
+    cache::{Duplicates, EventSource, RecentMessages, Targets},
+    failure::{Failure, HistoryHidden},
+    features::{self, Feature},
+    limits::{self, LimitConfig},
+    selftest::selftest,
+};
+use matrix_bot_core::{
+    abuse::Abuse,
+    can_reply,
+    egress::{self, Egress},
+    permissions::Permissions,
+    replacement, reply, reply_notice, send_or_log_error, strip_command, within_budget,
+    workers::Workers,
+};
 use matrix_sdk::{
-    room::MessagesOptions,
+    deserialized_responses::SyncTimelineEvent,
+    event_handler::Ctx,
+    room::{edit::EditError, MessagesOptions},
     ruma::{
+        api::client::error::ErrorKind,
         events::{
-            room::{
-                member::StrippedRoomMemberEvent,
-                message::{
-                    sanitize::remove_plain_reply_fallback, AddMentions, ForwardThread, MessageType,
-                    OriginalSyncRoomMessageEvent, Relation, ReplyWithinThread,
-                    RoomMessageEventContent,
-                },
+            room::history_visibility::HistoryVisibility,
+            room::message::{
+                sanitize::remove_plain_reply_fallback, AddMentions, ForwardThread, MessageType,
+                OriginalRoomMessageEvent, OriginalSyncRoomMessageEvent, Relation,
+                ReplyWithinThread, RoomMessageEventContent,
             },
             AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent,
         },
-        uint,
+        uint, EventId, OwnedEventId, UInt,
     },
 };
-use matrix_sdk::{Client, Room, RoomState};
+use matrix_sdk::{Room, RoomState};
+use memchr::memmem;
 use regex::Regex;
 use similar::utils::TextDiffRemapper;
 use similar::{ChangeTag, TextDiff};
-use std::{collections::VecDeque, sync::LazyLock};
-use tokio::time::{sleep, Duration};
-use tracing::{error, info, instrument, trace, warn};
+use std::{collections::VecDeque, sync::LazyLock, time::Duration};
+use tracing::{debug, instrument, trace};
 
-pub async fn send_or_log_error(room: &Room, message: RoomMessageEventContent) {
-    if let Err(e) = room.send(message).await {
-        warn!("Failed to send message to room {}: {}", room.room_id(), e);
-    }
+/// Repeating a command that's being ignored as a duplicate.
+const FLOODING: &str = "repeating ignored commands";
+
+/// Correcting the same person's messages over and over.
+const TARGETING: &str = "correcting one person";
+
+/// How many events either side of a command to look at for its target.
+const CONTEXT_LIMIT: UInt = uint!(10);
+
+/// How long before a command its target can have been sent before it's
+/// worth checking whether federation delivered a newer message late.
+const FEDERATION_LAG_MS: u64 = 60_000;
+
+/// How many pages of history to look back through when backfilling a
+/// target.
+const BACKFILL_PAGES: usize = 5;
+
+/// How long a correction can take before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many bytes of message and command are worth substituting on a
+/// blocking thread rather than in the handler.
+const BLOCKING_THRESHOLD: usize = 4096;
+
+/// A cheap check for whether a message could be a command, so most
+/// messages never reach the regexes or the queue.
+fn might_be_command(body: &str) -> bool {
+    body.starts_with("s/")
+        || body.starts_with("s#")
+        || memmem::find(body.as_bytes(), b"sed s").is_some()
 }
 
-#[instrument(fields(room_member = room_member.state_key.as_str(), room = room.room_id().as_str(), client = client.user_id().map(|u| u.as_str()).unwrap_or("None")))]
-pub async fn on_stripped_state_member(
-    room_member: StrippedRoomMemberEvent,
-    client: Client,
+/// Whether to have the homeserver backfill targets it doesn't have yet, set
+/// from [`Config::backfill`](crate::Config::backfill).
+#[derive(Debug, Clone, Copy)]
+pub struct Backfill(pub bool);
+
+/// Queue the message to be handled in order with the rest of its room, so
+/// looking up targets doesn't hold up other rooms.
+#[allow(clippy::too_many_arguments)]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
     room: Room,
+    workers: Ctx<Workers>,
+    recent: Ctx<RecentMessages>,
+    targets: Ctx<Targets>,
+    duplicates: Ctx<Duplicates>,
+    egress: Ctx<Egress>,
+    abuse: Ctx<Abuse>,
+    permissions: Ctx<Permissions>,
+    limits: Ctx<LimitConfig>,
+    backfill: Ctx<Backfill>,
 ) {
-    if room_member.state_key != client.user_id().unwrap() {
+    let room_id = room.room_id().to_owned();
+    // Recorded here rather than in the worker, to keep the sync's order
+    recent.record(&room_id, &event);
+    let MessageType::Text(text_content) = &event.content.msgtype else {
+        return;
+    };
+    let body = bridge::strip_prefix(remove_plain_reply_fallback(&text_content.body));
+    // Other uses of !sed, like `!sed s/a/b/`, are corrections
+    let subcommand = strip_command(body, "!sed");
+    if let Some(args) = subcommand.and_then(|args| strip_command(args, "features")) {
+        if room.state() != RoomState::Joined
+            || room.client().user_id() == Some(&*event.sender)
+            || !can_reply(&room).await
+        {
+            return;
+        }
+        let sent_at = event.origin_server_ts;
+        let event_id = event.event_id.clone();
+        let args = args.to_owned();
+        workers.spawn(
+            &room_id,
+            &event_id,
+            sent_at,
+            "!sed features",
+            features::features(event, room, args),
+        );
         return;
     }
-
-    tokio::spawn(async move {
-        info!("Autojoining room {}", room.room_id());
-        let mut delay = 2;
-
-        while let Err(err) = room.join().await {
-            // retry autojoin due to synapse sending invites, before the
-            // invited user can join for more information see
-            // https://github.com/matrix-org/synapse/issues/4345
-            warn!(
-                "Failed to join room {} ({err:?}), retrying in {delay}s",
-                room.room_id()
+    if subcommand == Some("selftest") {
+        if room.state() != RoomState::Joined
+            || room.client().user_id() == Some(&*event.sender)
+            || !can_reply(&room).await
+        {
+            return;
+        }
+        if !permissions.is_admin(&event.sender) {
+            reply_notice(
+                &room,
+                &event,
+                "Only the bot's admins can run the self-test.",
+            )
+            .await;
+        } else {
+            let sent_at = event.origin_server_ts;
+            let event_id = event.event_id.clone();
+            workers.spawn(
+                &room_id,
+                &event_id,
+                sent_at,
+                "!sed selftest",
+                selftest(event, room, LimitConfig::clone(&limits)),
             );
-
-            sleep(Duration::from_secs(delay)).await;
-            delay *= 2;
-
-            if delay > 3600 {
-                error!("Can't join room {} ({err:?})", room.room_id());
-                break;
-            }
         }
-        info!("Successfully joined room {}", room.room_id());
-    });
+        return;
+    }
+    if !might_be_command(body) {
+        return;
+    }
+    let reply_to = match &event.content.relates_to {
+        Some(Relation::Reply { in_reply_to }) => Some(&*in_reply_to.event_id),
+        Some(Relation::Thread(thread)) => thread
+            .in_reply_to
+            .as_ref()
+            .map(|in_reply_to| &*in_reply_to.event_id),
+        _ => None,
+    };
+    if duplicates.check(&room_id, &event.sender, body, reply_to) {
+        abuse.record(&room, &event.event_id, &event.sender, FLOODING, None);
+        return;
+    }
+    let sent_at = event.origin_server_ts;
+    let recent = RecentMessages::clone(&recent);
+    let targets = Targets::clone(&targets);
+    let egress = Egress::clone(&egress);
+    let abuse = Abuse::clone(&abuse);
+    let limits = LimitConfig::clone(&limits);
+    let event_id = event.event_id.clone();
+    let command = body.to_owned();
+    workers.spawn(
+        &room_id,
+        &event_id,
+        sent_at,
+        &command,
+        handle(
+            event, room, recent, targets, egress, abuse, limits, *backfill,
+        ),
+    );
 }
 
-#[instrument(fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
-pub async fn on_room_message(
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(recent, targets, egress, abuse, limits, backfill), fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+async fn handle(
     event: OriginalSyncRoomMessageEvent,
     room: Room,
+    recent: RecentMessages,
+    targets: Targets,
+    egress: Egress,
+    abuse: Abuse,
+    limits: LimitConfig,
+    backfill: Backfill,
 ) -> anyhow::Result<()> {
     let room = &room;
     if room.state() != RoomState::Joined {
         return Ok(());
     }
-    let MessageType::Text(text_content) = event.content.msgtype else {
+    let MessageType::Text(text_content) = &event.content.msgtype else {
         return Ok(());
     };
 
-    let body_text = remove_plain_reply_fallback(&text_content.body);
+    let body_text = bridge::strip_prefix(remove_plain_reply_fallback(&text_content.body));
 
     static MATCH_COMMAND: LazyLock<Regex> =
         LazyLock::new(|| Regex::new(r"(?:^|[^a-zA-Z0-9])sed (s.+)").unwrap());
@@ -92,7 +216,122 @@ pub async fn on_room_message(
     } else {
         return Ok(());
     };
+    if !limits.command_fits(&command) {
+        debug!(bytes = command.len(), "Ignoring a command that's too long");
+        return Ok(());
+    }
+
+    if !can_reply(room).await {
+        return Ok(());
+    }
+
+    let work = async {
+        let Some((target_event_message, thread_root)) =
+            find_target(&event, room, &recent, &targets, backfill).await?
+        else {
+            return Ok(None);
+        };
+
+        let target_event_text = bridge::strip_prefix(remove_plain_reply_fallback(
+            target_event_message.content.body(),
+        ));
+        let (target_event_text, cut_off) = limits.body(target_event_text);
+        let (mut result, mut changes) = if target_event_text.len() + command.len()
+            > BLOCKING_THRESHOLD
+        {
+            // Big enough that running it here could hold up other rooms
+            let target_event_text = target_event_text.to_owned();
+            let command = command.clone();
+            let limits = limits.clone();
+            tokio::task::spawn_blocking(move || substitute(&command, &target_event_text, &limits))
+                .await??
+        } else {
+            substitute(&command, target_event_text, &limits)?
+        };
+        if cut_off {
+            result.push_str(limits::MARKER);
+            changes.push_str(limits::MARKER);
+        }
+        anyhow::Ok(Some((target_event_message, thread_root, result, changes)))
+    };
+    let work = async {
+        tokio::time::timeout(TIMEOUT, work)
+            .await
+            .unwrap_or_else(|elapsed| Err(elapsed.into()))
+    };
+    let (outcome, still_working) = within_budget(room, &event, work).await;
+    let outcome = outcome.and_then(|outcome| outcome.ok_or_else(|| Failure::TargetNotFound.into()));
+    let (target_event_message, thread_root, result, changes) = match outcome {
+        Ok(outcome) => outcome,
+        Err(err) => return fail(room, &event, still_working, err).await,
+    };
+    let changes = limits.formatted(changes);
+    let content = match changes {
+        Some(changes) if features::enabled(room, &event.sender, Feature::HtmlDiff).await => {
+            RoomMessageEventContent::notice_html(result, changes)
+        }
+        _ => RoomMessageEventContent::notice_plain(result),
+    };
 
+    if let Some(still_working) = still_working {
+        // Edits can't change what a message replies to, so the answer stays
+        // a reply to the command
+        send_or_log_error(room, replacement(still_working, content)).await;
+    } else {
+        let message = if thread_root.is_some() {
+            // If the original message is not in a thread, make_reply_to won't create a reply in the thread
+            // so we need to make_for_thread instead, which will always reply in the thread.
+            content.make_for_thread(
+                &target_event_message,
+                ReplyWithinThread::Yes,
+                AddMentions::No,
+            )
+        } else {
+            content.make_reply_to(&target_event_message, ForwardThread::Yes, AddMentions::No)
+        };
+        send_or_log_error(room, message).await;
+    }
+    egress.notify(
+        egress::CORRECTION,
+        Some(room.room_id()),
+        serde_json::json!({
+            "sender": event.sender,
+            "event_id": event.event_id,
+            "target_event_id": target_event_message.event_id,
+            "command": command,
+        }),
+    );
+    // Relays post everyone's messages as one user, so compare who actually
+    // wrote them
+    let speaker = bridge::speaker(
+        &event.sender,
+        remove_plain_reply_fallback(&text_content.body),
+    );
+    let target_speaker = bridge::speaker(
+        &target_event_message.sender,
+        remove_plain_reply_fallback(target_event_message.content.body()),
+    );
+    if target_speaker != speaker {
+        abuse.record(
+            room,
+            &event.event_id,
+            &event.sender,
+            TARGETING,
+            Some(&target_event_message.sender),
+        );
+    }
+    Ok(())
+}
+
+/// Find the message a command corrects, and the thread it's in if the
+/// command is in one.
+async fn find_target(
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    recent: &RecentMessages,
+    targets: &Targets,
+    backfill: Backfill,
+) -> anyhow::Result<Option<(OriginalRoomMessageEvent, Option<OwnedEventId>)>> {
     let mut thread_root = None;
 
     trace!("Searching for target");
@@ -100,6 +339,7 @@ pub async fn on_room_message(
         event
             .content
             .relates_to
+            .clone()
             .and_then(|relation| match relation {
                 // Normal replies
                 Relation::Reply { in_reply_to } => Some(in_reply_to.event_id),
@@ -116,45 +356,66 @@ pub async fn on_room_message(
                 }
                 _ => None,
             }) {
-        let target_event = room
-            .get_event(&target_id)
-            .await?
-            .into_raw()
-            .deserialize()?
-            .into_full_event(room.room_id().to_owned());
-
-        let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
-            MessageLikeEvent::Original(target_event_message),
-        )) = target_event
-        else {
-            trace!("Target is not a message");
-            return Ok(());
-        };
-        target_event_message
+        if let Some(target) = recent.get(room.room_id(), &target_id) {
+            trace!("Target is a recent message");
+            target.into_full_event(room.room_id().to_owned())
+        } else if let Some(target) = targets.get(room.room_id(), &target_id) {
+            trace!("Target was fetched recently");
+            target
+        } else {
+            let target_event = fetch_target(room, &target_id, backfill).await?;
+
+            let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Original(target_event_message),
+            )) = target_event
+            else {
+                trace!("Target is not a message");
+                return Ok(None);
+            };
+            targets.insert(target_event_message.clone());
+            target_event_message
+        }
+    } else if let Some(target) = recent.before(room.room_id(), &event.event_id) {
+        trace!("No related event found, using the last recent message");
+        target.into_full_event(room.room_id().to_owned())
     } else {
         trace!("No related event found, using event context");
         // TODO: Filter to only events outside of a thread
         let context = room
-            .event_with_context(&event.event_id, false, uint!(2), None)
+            .event_with_context(&event.event_id, false, CONTEXT_LIMIT, None)
             .await?;
+        // Slow federation can deliver a message after the command even
+        // though it was sent before it, so note what's either side
+        let nearby: Vec<_> = context
+            .events_before
+            .iter()
+            .chain(&context.events_after)
+            .filter_map(|nearby| {
+                top_level_message(
+                    nearby
+                        .raw()
+                        .deserialize()
+                        .ok()?
+                        .into_full_event(room.room_id().to_owned()),
+                )
+            })
+            .filter(|nearby| {
+                nearby.event_id != event.event_id
+                    && nearby.origin_server_ts <= event.origin_server_ts
+            })
+            .collect();
         let mut queue = VecDeque::from(context.events_before);
         let mut paginaton_token = context.prev_batch_token;
 
-        loop {
+        let target_event_message = loop {
             let event = queue
                 .pop_front()
                 .unwrap()
                 .raw()
                 .deserialize()?
                 .into_full_event(room.room_id().to_owned());
-            if let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
-                MessageLikeEvent::Original(target_event_message),
-            )) = event
-            {
-                match target_event_message.content.relates_to {
-                    Some(Relation::Thread(_)) => (), // Skip messages in threads
-                    _ => break target_event_message,
-                };
+            if let Some(target_event_message) = top_level_message(event) {
+                break target_event_message;
             }
             if queue.is_empty() {
                 trace!("searching for more messages");
@@ -164,20 +425,172 @@ pub async fn on_room_message(
                 paginaton_token = messages.end;
                 queue = VecDeque::from(messages.chunk);
             }
-        }
+        };
+        correct_for_lag(target_event_message, nearby, event)
     };
 
     trace!(
         id = target_event_message.event_id.as_str(),
         "Target message found"
     );
+    Ok(Some((target_event_message, thread_root)))
+}
+
+/// Count a failed correction, and tell whoever sent it what went wrong if
+/// they turned on [`Feature::Feedback`] or were told it was being worked on. Only
+/// failures that aren't the command's fault are returned as errors.
+async fn fail(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    still_working: Option<OwnedEventId>,
+    err: anyhow::Error,
+) -> anyhow::Result<()> {
+    let failure = Failure::of(&err);
+    metrics::counter!("sed_failures_total", "code" => failure.code()).increment(1);
+    // The explanation is worth giving whether or not they asked
+    let hidden = err.is::<HistoryHidden>();
+    let explanation = if hidden {
+        err.to_string()
+    } else {
+        failure.to_string()
+    };
+    let content =
+        RoomMessageEventContent::notice_plain(format!("{explanation}. ({})", failure.code()));
+    match still_working {
+        Some(still_working) => {
+            send_or_log_error(room, replacement(still_working, content)).await;
+        }
+        None if hidden || features::enabled(room, &event.sender, Feature::Feedback).await => {
+            reply(room, event, content).await
+        }
+        None => {}
+    }
+    if failure.is_unexpected() {
+        return Err(err);
+    }
+    debug!(code = failure.code(), "Correction failed: {err:#}");
+    Ok(())
+}
+
+/// Fetch a target that isn't cached, failing with [`HistoryHidden`] if the
+/// room's history visibility is why it can't be.
+async fn fetch_target(
+    room: &Room,
+    target_id: &EventId,
+    backfill: Backfill,
+) -> anyhow::Result<AnyTimelineEvent> {
+    let err = match room.get_event(target_id).await {
+        Ok(target) => return Ok(full_event(room, target)?),
+        Err(err) => err,
+    };
+    let kind = match &err {
+        EditError::Fetch(err) => err.client_api_error_kind(),
+        _ => None,
+    };
+    if !matches!(
+        kind,
+        Some(ErrorKind::NotFound | ErrorKind::Forbidden { .. })
+    ) {
+        return Err(err.into());
+    }
+    if matches!(
+        room.history_visibility(),
+        HistoryVisibility::Joined | HistoryVisibility::Invited
+    ) {
+        return Err(HistoryHidden.into());
+    }
+    if !backfill.0 {
+        return Err(err.into());
+    }
+
+    // Paginating back has the homeserver backfill over federation what it's
+    // missing, which may include the target
+    debug!(
+        id = target_id.as_str(),
+        "Target isn't on the homeserver, backfilling"
+    );
+    let mut from = None;
+    for _ in 0..BACKFILL_PAGES {
+        let options = MessagesOptions::backward().from(from.as_deref());
+        let messages = room.messages(options).await?;
+        if let Some(target) = messages
+            .chunk
+            .into_iter()
+            .find(|message| message.event_id().as_deref() == Some(target_id))
+        {
+            return Ok(full_event(room, target.into())?);
+        }
+        if messages.end.is_none() {
+            break;
+        }
+        from = messages.end;
+    }
+    Err(err.into())
+}
+
+fn full_event(room: &Room, event: SyncTimelineEvent) -> serde_json::Result<AnyTimelineEvent> {
+    Ok(event
+        .into_raw()
+        .deserialize()?
+        .into_full_event(room.room_id().to_owned()))
+}
+
+/// The event if it's a message outside of a thread.
+fn top_level_message(event: AnyTimelineEvent) -> Option<OriginalRoomMessageEvent> {
+    match event {
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+            MessageLikeEvent::Original(message),
+        )) if !matches!(message.content.relates_to, Some(Relation::Thread(_))) => Some(message),
+        _ => None,
+    }
+}
 
-    let target_event_text = remove_plain_reply_fallback(target_event_message.content.body());
-    let command = sedregex::ReplaceCommand::new(&command)?;
-    let result = command.execute(target_event_text);
+/// If `target` was sent long before `command`, the last message sent before
+/// the command among `nearby` instead, since federation may have delivered
+/// it out of order.
+fn correct_for_lag(
+    target: OriginalRoomMessageEvent,
+    nearby: Vec<OriginalRoomMessageEvent>,
+    command: &OriginalSyncRoomMessageEvent,
+) -> OriginalRoomMessageEvent {
+    let lag =
+        u64::from(command.origin_server_ts.0).saturating_sub(target.origin_server_ts.0.into());
+    if lag <= FEDERATION_LAG_MS {
+        return target;
+    }
+    match nearby
+        .into_iter()
+        .filter(|nearby| nearby.origin_server_ts > target.origin_server_ts)
+        .max_by_key(|nearby| nearby.origin_server_ts)
+    {
+        Some(newer) => {
+            trace!(
+                lag_ms = lag,
+                id = newer.event_id.as_str(),
+                "Target predates the command a lot, using a message sent since"
+            );
+            newer
+        }
+        None => target,
+    }
+}
+
+/// Run a sed command on some text, returning the result and the HTML of it
+/// with the changes underlined, if they're small enough to work out.
+pub fn substitute(
+    command: &str,
+    text: &str,
+    limits: &LimitConfig,
+) -> anyhow::Result<(String, String)> {
+    let command = sedregex::ReplaceCommand::new(command)?;
+    let result = command.execute(text);
+    if !limits.diffable(text, &result) {
+        let result = result.into_owned();
+        return Ok((result.clone(), result));
+    }
 
-    let diff = TextDiff::from_words(target_event_text, &result);
-    let remapper = TextDiffRemapper::from_text_diff(&diff, target_event_text, &result);
+    let diff = TextDiff::from_words(text, &result);
+    let remapper = TextDiffRemapper::from_text_diff(&diff, text, &result);
     let changes: String = diff
         .ops()
         .iter()
@@ -188,23 +601,95 @@ pub async fn on_room_message(
             ChangeTag::Insert => "<u>".to_string() + text + "</u>",
         })
         .collect();
+    Ok((result.into_owned(), changes))
+}
 
-    let message = if thread_root.is_some() {
-        // If the original message is not in a thread, make_reply_to won't create a reply in the thread
-        // so we need to make_for_thread instead, which will always reply in the thread.
-        RoomMessageEventContent::notice_html(result, changes).make_for_thread(
-            &target_event_message,
-            ReplyWithinThread::Yes,
-            AddMentions::No,
-        )
-    } else {
-        RoomMessageEventContent::notice_html(result, changes).make_reply_to(
-            &target_event_message,
-            ForwardThread::Yes,
-            AddMentions::No,
-        )
-    };
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
 
-    send_or_log_error(room, message).await;
-    Ok(())
+    use super::*;
+
+    /// The command is sent at this time, in milliseconds.
+    const NOW: u64 = 1_700_000_000_000;
+
+    fn event(id: &str, ts: u64) -> serde_json::Value {
+        json!({
+            "type": "m.room.message",
+            "event_id": id,
+            "sender": "@alice:example.org",
+            "origin_server_ts": ts,
+            "room_id": "!room:example.org",
+            "content": { "msgtype": "m.text", "body": id },
+        })
+    }
+
+    fn message(id: &str, ts: u64) -> OriginalRoomMessageEvent {
+        serde_json::from_value(event(id, ts)).unwrap()
+    }
+
+    fn command() -> OriginalSyncRoomMessageEvent {
+        serde_json::from_value(event("$command", NOW)).unwrap()
+    }
+
+    /// Which event `correct_for_lag` picks for a target and nearby messages
+    /// sent this many milliseconds before the command.
+    fn corrected(target: u64, nearby: &[(&str, u64)]) -> OwnedEventId {
+        let nearby = nearby
+            .iter()
+            .map(|&(id, ago)| message(id, NOW - ago))
+            .collect();
+        correct_for_lag(message("$target", NOW - target), nearby, &command()).event_id
+    }
+
+    #[test]
+    fn prefilter_lets_commands_through() {
+        assert!(might_be_command("s/teh/the/"));
+        assert!(might_be_command("s#a/b#c#g"));
+        assert!(might_be_command("!sed s/a/b/"));
+        assert!(might_be_command("oops, sed s/a/b/"));
+        assert!(!might_be_command("hello"));
+        assert!(!might_be_command("sounds good"));
+        assert!(!might_be_command(" s/a/b/"));
+        assert!(!might_be_command("I like sed"));
+    }
+
+    #[test]
+    fn recent_targets_are_kept() {
+        let nearby = [("$newer", 1_000)];
+        assert_eq!(corrected(30_000, &nearby), "$target");
+        // Right on the edge of the window
+        assert_eq!(corrected(FEDERATION_LAG_MS, &nearby), "$target");
+    }
+
+    #[test]
+    fn targets_from_the_future_are_kept() {
+        // A server with its clock ahead can send a target stamped after the
+        // command, which mustn't underflow into a huge lag
+        let target = message("$target", NOW + 5_000);
+        let nearby = vec![message("$newer", NOW - 1_000)];
+        let corrected = correct_for_lag(target, nearby, &command());
+        assert_eq!(corrected.event_id, "$target");
+    }
+
+    #[test]
+    fn old_targets_give_way_to_the_newest_message_since() {
+        let nearby = [
+            ("$older", 200_000),
+            ("$newer", 30_000),
+            ("$newest", 5_000),
+            ("$between", 60_000),
+        ];
+        assert_eq!(corrected(FEDERATION_LAG_MS + 1, &nearby), "$newest");
+        assert_eq!(corrected(120_000, &nearby), "$newest");
+    }
+
+    #[test]
+    fn old_targets_are_kept_without_anything_newer() {
+        assert_eq!(corrected(120_000, &[]), "$target");
+        assert_eq!(
+            corrected(120_000, &[("$older", 200_000), ("$same", 120_000)]),
+            "$target"
+        );
+    }
 }
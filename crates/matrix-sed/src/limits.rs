@@ -0,0 +1,116 @@
+//! Caps on how much of a message the bot works with and sends, so a giant
+//! message can't tie it up or have a giant correction sent to the
+//! homeserver.
+//!
+//! Messages longer than the cap are corrected up to it, and the correction
+//! ends with [`MARKER`] to show some was left off. Commands longer than it
+//! are ignored, as a cut-off command would do something else.
+//!
+//! The limits are set by [`LimitConfig`], which handlers are given as
+//! context.
+
+use clap::Parser;
+
+/// Ends a correction of a message that was cut off.
+pub const MARKER: &str = " […]";
+
+const DEFAULT_MAX_BODY: usize = 16 * 1024;
+const DEFAULT_MAX_FORMATTED: usize = 32 * 1024;
+const DEFAULT_MAX_DIFF: usize = 8 * 1024;
+
+#[derive(Parser, Debug, Clone)]
+pub struct LimitConfig {
+    /// The most bytes of a message to correct, leaving the rest off the
+    /// correction. Longer commands are ignored
+    #[arg(long, default_value_t = DEFAULT_MAX_BODY, env = "SED_MAX_BODY")]
+    pub max_body: usize,
+    /// The most bytes of HTML to send with a correction, sending it as
+    /// plain text instead when there'd be more
+    #[arg(long, default_value_t = DEFAULT_MAX_FORMATTED, env = "SED_MAX_FORMATTED")]
+    pub max_formatted: usize,
+    /// The most bytes of message and correction to work out the changes
+    /// between, leaving them not underlined beyond it
+    #[arg(long, default_value_t = DEFAULT_MAX_DIFF, env = "SED_MAX_DIFF")]
+    pub max_diff: usize,
+}
+
+impl LimitConfig {
+    /// Whether a command is short enough to run.
+    pub fn command_fits(&self, command: &str) -> bool {
+        command.len() <= self.max_body
+    }
+
+    /// As much of a message as should be corrected, and whether any was
+    /// left off.
+    pub fn body<'a>(&self, body: &'a str) -> (&'a str, bool) {
+        if body.len() <= self.max_body {
+            return (body, false);
+        }
+        let mut end = self.max_body;
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        (&body[..end], true)
+    }
+
+    /// Whether a message and its correction are small enough to work out
+    /// the changes between.
+    pub fn diffable(&self, text: &str, result: &str) -> bool {
+        text.len() + result.len() <= self.max_diff
+    }
+
+    /// The HTML to send with a correction, or `None` if it's too big to.
+    pub fn formatted(&self, html: String) -> Option<String> {
+        (html.len() <= self.max_formatted).then_some(html)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_max_body(max_body: usize) -> LimitConfig {
+        LimitConfig {
+            max_body,
+            max_formatted: 20,
+            max_diff: 10,
+        }
+    }
+
+    #[test]
+    fn long_commands_dont_fit() {
+        let limits = with_max_body(8);
+        assert!(limits.command_fits("s/a/b/"));
+        assert!(limits.command_fits("s/ab/cd/"));
+        assert!(!limits.command_fits("s/abc/de/"));
+    }
+
+    #[test]
+    fn long_bodies_are_cut_off() {
+        let limits = with_max_body(5);
+        assert_eq!(limits.body("hello"), ("hello", false));
+        assert_eq!(limits.body("hello world"), ("hello", true));
+        assert_eq!(limits.body(""), ("", false));
+    }
+
+    #[test]
+    fn bodies_are_cut_off_between_characters() {
+        // "é" is two bytes, so the cap lands in the middle of the third
+        let limits = with_max_body(5);
+        assert_eq!(limits.body("ééé"), ("éé", true));
+        assert_eq!(limits.body("aéé"), ("aéé", false));
+        assert_eq!(limits.body("🦀🦀"), ("🦀", true));
+        assert_eq!(with_max_body(3).body("🦀"), ("", true));
+    }
+
+    #[test]
+    fn big_diffs_and_html_are_skipped() {
+        let limits = with_max_body(100);
+        assert!(limits.diffable("hello", "world"));
+        assert!(!limits.diffable("hello", "world!"));
+
+        let html = "<u>a</u>".repeat(2);
+        assert_eq!(limits.formatted(html.clone()), Some(html));
+        assert_eq!(limits.formatted("<u>a</u>".repeat(3)), None);
+    }
+}
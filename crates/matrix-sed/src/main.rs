@@ -1,26 +1,22 @@
+mod bridge;
 mod cache;
+mod failure;
+mod features;
 mod handlers;
+mod limits;
+mod selftest;
 
-use std::path::Path;
-
+use cache::{Duplicates, RecentMessages, Targets};
 use clap::Parser;
-use handlers::on_room_message;
-use matrix_sdk::{
-    config::SyncSettings,
-    matrix_auth::MatrixSession,
-    ruma::{api::client::{
-        filter::FilterDefinition,
-        uiaa::{AuthData, Password, UserIdentifier},
-    }, presence::PresenceState},
-    Client, LoopCtrl,
+use handlers::{on_room_message, Backfill};
+#[cfg(unix)]
+use matrix_bot_core::control;
+use matrix_bot_core::{
+    doctor,
+    permissions::{PermissionConfig, Permissions},
+    portable, replay, wizard, AccountConfig, Bot, ScaleConfig,
 };
-use rand::{distributions::Alphanumeric, Rng};
-use rpassword::prompt_password;
-use serde::{Deserialize, Serialize};
-use tokio::fs;
-use tracing::{error, info, trace, warn};
-use tracing_log::AsTrace;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing::info;
 
 #[derive(Parser, Debug)]
 pub struct Config {
@@ -28,346 +24,135 @@ pub struct Config {
     pub account_config: AccountConfig,
 
     #[clap(flatten)]
-    pub(crate) verbose: clap_verbosity_flag::Verbosity,
-}
+    pub scale: ScaleConfig,
+
+    /// How many recent messages to keep in memory for each room, to find
+    /// targets without asking the homeserver
+    #[arg(long, default_value_t = 50, env = "SED_CACHE_MESSAGES_PER_ROOM")]
+    pub cache_messages_per_room: usize,
+    /// How many rooms to keep recent messages for, forgetting the quietest
+    /// first
+    #[arg(long, default_value_t = 500, env = "SED_CACHE_ROOMS")]
+    pub cache_rooms: usize,
+    /// Serve a D-Bus interface on the session bus, to check on and pause
+    /// the bot from the desktop
+    #[arg(long, env = "SED_DBUS")]
+    pub dbus: bool,
+    /// When a target isn't on the homeserver yet, have it backfill history
+    /// over federation to look for it
+    #[arg(long, env = "SED_BACKFILL")]
+    pub backfill: bool,
+    /// Reply to corrections that fail saying why, rather than staying quiet.
+    /// Rooms and people can choose otherwise with `!sed features`
+    #[arg(long, env = "SED_FEEDBACK")]
+    pub feedback: bool,
+    /// Send corrections as plain text, without underlining what changed.
+    /// Rooms and people can choose otherwise with `!sed features`
+    #[arg(long, env = "SED_PLAIN")]
+    pub plain: bool,
 
-#[derive(Parser, Debug)]
-pub struct AccountConfig {
-    /// URL of the homeserver to connect to
-    #[arg(short, long, env = "MATRIX_SERVER")]
-    pub server: String,
-    /// Username of the bot
-    #[arg(short, long, env = "MATRIX_USERNAME")]
-    pub username: String,
-    /// Password of the bot
-    #[arg(short, long, env = "MATRIX_PASSWORD")]
-    pub password: Option<String>,
-    /// Delete devices other than the one being used by this instance
-    #[arg(long)]
-    pub delete_other_devices: bool,
-    /// Device name to set, if it doesn't exist
-    #[arg(long, default_value_t = String::from("matrix-sed client"), env = "MATRIX_CLIENT_NAME")]
-    pub device_name: String,
-    /// Set the device name, even if it already exists
-    #[arg(long, default_value_t = false)]
-    pub set_device_name: bool,
-}
-
-/// The data needed to re-build a client.
-#[derive(Debug, Serialize, Deserialize)]
-struct ClientSession {
-    /// The URL of the homeserver of the user.
-    homeserver: String,
-
-    /// The path of the database.
-    db_path: std::path::PathBuf,
-
-    /// The passphrase of the database.
-    passphrase: String,
-}
+    #[clap(flatten)]
+    pub permissions: PermissionConfig,
 
-/// The full session to persist.
-#[derive(Debug, Serialize, Deserialize)]
-struct FullSession {
-    /// The data to re-build the client.
-    client_session: ClientSession,
+    #[clap(flatten)]
+    pub bridge: bridge::BridgeConfig,
 
-    /// The Matrix user session.
-    user_session: MatrixSession,
+    #[clap(flatten)]
+    pub limits: limits::LimitConfig,
 
-    /// The latest sync token.
-    ///
-    /// It is only needed to persist it when using `Client::sync_once()` and we
-    /// want to make our syncs faster by not receiving all the initial sync
-    /// again.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    sync_token: Option<String>,
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
 }
 
-#[tokio::main(flavor = "current_thread")]
+#[tokio::main(flavor = "multi_thread")]
 async fn main() -> anyhow::Result<()> {
-    // Read args
-    let config = Config::parse();
-
-    // Logging
-    let filter = tracing_subscriber::EnvFilter::builder()
-        .with_default_directive(config.verbose.log_level_filter().as_trace().into())
-        .from_env_lossy();
-    tracing_subscriber::registry()
-        .with(filter)
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    info!("Starting up");
-
-    let data_dir = dirs::data_dir()
-        .expect("no data_dir directory found")
-        .join("matrix-sed");
-    let session_file = data_dir.join("session");
+    #[cfg(unix)]
+    if control::requested() {
+        return control::ctl("matrix-sed").await;
+    }
+    if portable::requested() {
+        return portable::run("matrix-sed").await;
+    }
+    if doctor::requested() {
+        return doctor::run("matrix-sed").await;
+    }
 
-    let (client, sync_token) = if session_file.exists() {
-        restore_session(&session_file).await?
+    // Read args, which point the bot at the events when replaying
+    let config = if replay::requested() {
+        Config::parse_from(replay::start("matrix-sed").await?)
     } else {
-        (
-            login(&data_dir, &session_file, &config.account_config).await?,
-            None,
-        )
+        Config::parse()
     };
 
-    client.event_cache().subscribe()?;
-
-    run(client, sync_token, &session_file, config).await?;
-
-    Ok(())
-}
-
-/// Restore a previous session.
-async fn restore_session(session_file: &Path) -> anyhow::Result<(Client, Option<String>)> {
-    info!(
-        "Previous session found in '{}'",
-        session_file.to_string_lossy()
-    );
-
-    // The session was serialized as JSON in a file.
-    let serialized_session = fs::read_to_string(session_file).await?;
-    let FullSession {
-        client_session,
-        user_session,
-        sync_token,
-    } = serde_json::from_str(&serialized_session)?;
-
-    // Build the client with the previous settings from the session.
-    let client = Client::builder()
-        .homeserver_url(client_session.homeserver)
-        .sqlite_store(client_session.db_path, Some(&client_session.passphrase))
-        .build()
-        .await?;
-
-    info!("Restoring session for {}…", user_session.meta.user_id);
-
-    // Restore the Matrix user session.
-    client.restore_session(user_session).await?;
-
-    Ok((client, sync_token))
-}
-
-/// Login to a new session.
-async fn login(
-    data_dir: &std::path::Path,
-    session_file: &std::path::Path,
-    config: &AccountConfig,
-) -> anyhow::Result<Client> {
-    info!("No previous session found, logging in…");
-    let mut rng = rand::thread_rng();
-
-    // Generate a random passphrase.
-    let passphrase: String = (&mut rng)
-        .sample_iter(Alphanumeric)
-        .take(32)
-        .map(char::from)
-        .collect();
-
-    let db_subfolder: String = (&mut rng)
-        .sample_iter(Alphanumeric)
-        .take(7)
-        .map(char::from)
-        .collect();
-    let db_path = data_dir.join(db_subfolder);
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
 
-    let client = Client::builder()
-        .homeserver_url(&config.server)
-        .sqlite_store(&db_path, Some(&passphrase))
-        .build()
-        .await?;
+    info!("Starting up");
 
-    let client_session = ClientSession {
-        homeserver: config.server.clone(),
-        db_path,
-        passphrase,
+    let account_config = AccountConfig {
+        scale: config.scale,
+        ..config.account_config
     };
-    let matrix_auth = client.matrix_auth();
-
-    loop {
-        let username = &config.username;
-        let password = config.password.clone().unwrap_or_else(|| {
-            println!("Type password for the bot (characters won't show up as you type them)");
-            match prompt_password("Password: ") {
-                Ok(p) => p,
-                Err(err) => {
-                    panic!("FATAL: failed to get password: {err}");
-                }
-            }
-        });
+    let mut bot = Bot::login("matrix-sed", account_config).await?;
+    bot.initial_sync().await?;
+
+    let store = bot.open_store(&[]).await?;
+    bot.enable_settings_backup(&store).await;
+    bot.enable_sharding(&store)?;
+    let permissions = Permissions::new(bot.client(), config.permissions);
+    wizard::enable(
+        bot.client(),
+        store.clone(),
+        permissions.clone(),
+        features::ROOM_SETTINGS,
+    );
+    features::configure(features::Settings {
+        store,
+        html_diff: !config.plain,
+        feedback: config.feedback,
+    });
+    bridge::configure(config.bridge);
 
-        match matrix_auth
-            .login_username(username, &password)
-            .initial_device_display_name(&config.device_name)
-            .await
-        {
-            Ok(_) => {
-                info!("Logged in as {username}");
-                break;
-            }
-            Err(error) => {
-                error!("Error logging in: {error}");
-                if config.password.is_some() {
-                    return Err(error.into());
-                }
-            }
-        }
+    // Now that we've synced, attach handlers for new messages.
+    let recent = RecentMessages::new(config.cache_messages_per_room, config.cache_rooms);
+    let targets = Targets::default();
+    bot.client().add_event_handler_context(recent.clone());
+    bot.client().add_event_handler_context(targets.clone());
+    bot.client()
+        .add_event_handler_context(Duplicates::default());
+    bot.client().add_event_handler_context(permissions);
+    bot.client().add_event_handler_context(config.limits);
+    bot.client()
+        .add_event_handler_context(Backfill(config.backfill));
+    bot.client().add_event_handler(on_room_message);
+    bot.help().add(
+        "s/find/replace/",
+        "Correct the message you reply to, or the last one",
+    );
+    bot.help().add(
+        "!sed features",
+        "See which optional behaviours are on here, or choose for yourself",
+    );
+    bot.help().add(
+        "config",
+        "DM me to choose which features rooms you moderate have",
+    );
+    bot.help().add(
+        "!sed selftest",
+        "Check corrections work from end to end (admins only)",
+    );
+    bot.enable_snooze()?;
+    #[cfg(target_os = "linux")]
+    if config.dbus {
+        bot.enable_dbus().await?;
     }
-
-    // Persist the session to reuse it later.
-    // This is not very secure, for simplicity. If the system provides a way of
-    // storing secrets securely, it should be used instead.
-    // Note that we could also build the user session from the login response.
-    let user_session = matrix_auth
-        .session()
-        .expect("A logged-in client should have a session");
-    let serialized_session = serde_json::to_string(&FullSession {
-        client_session,
-        user_session,
-        sync_token: None,
+    // There's no config to reload, so reloading starts the caches afresh
+    #[cfg(unix)]
+    bot.enable_control(move || {
+        recent.clear();
+        targets.clear();
+        Ok(())
     })?;
-    fs::write(session_file, serialized_session).await?;
-
-    info!("Session persisted in {}", session_file.to_string_lossy());
-
-    Ok(client)
-}
-
-async fn run(
-    client: Client,
-    initial_sync_token: Option<String>,
-    session_file: &Path,
-    config: Config,
-) -> anyhow::Result<()> {
-    // handler for autojoin
-    // Handers here run for historic messages too
-    client.add_event_handler(crate::handlers::on_stripped_state_member);
-
-    info!("Launching a first sync to ignore past messages…");
-
-    // Enable room members lazy-loading, it will speed up the initial sync a lot
-    // with accounts in lots of rooms.
-    // See <https://spec.matrix.org/v1.6/client-server-api/#lazy-loading-room-members>.
-    let filter = FilterDefinition::with_lazy_loading();
-
-    let mut sync_settings = SyncSettings::default().filter(filter.into()).set_presence(PresenceState::Online);
-
-    // We restore the sync where we left.
-    // This is not necessary when not using `sync_once`. The other sync methods get
-    // the sync token from the store.
-    if let Some(sync_token) = initial_sync_token {
-        sync_settings = sync_settings.token(sync_token);
-    }
-
-    // Let's ignore messages before the program was launched.
-    // This is a loop in case the initial sync is longer than our timeout. The
-    // server should cache the response and it will ultimately take less time to
-    // receive.
-    loop {
-        match client.sync_once(sync_settings.clone()).await {
-            Ok(response) => {
-                // This is the last time we need to provide this token, the sync method after
-                // will handle it on its own.
-                sync_settings = sync_settings.token(response.next_batch.clone());
-                persist_sync_token(session_file, response.next_batch).await?;
-                break;
-            }
-            Err(error) => {
-                warn!("An error occurred during initial sync: {error}");
-            }
-        }
-    }
-    info!("Initial sync done");
-
-    let current_session = client.device_id().map(|d| d.to_owned());
-    if config.account_config.delete_other_devices {
-        info!(
-            current_session = format!("{current_session:?}"),
-            "Checking for other devices to delete"
-        );
-        let other_devices: Vec<_> = client
-            .devices()
-            .await?
-            .devices
-            .iter()
-            .filter(|device| Some(&device.device_id) != current_session.as_ref())
-            .map(|device| device.device_id.clone())
-            .collect();
-        if !other_devices.is_empty() {
-            trace!(
-                current_session = format!("{current_session:?}"),
-                other_devices = format!("{other_devices:?}"),
-                "Deleting other devices"
-            );
-            client
-                .delete_devices(
-                    &other_devices,
-                    Some(AuthData::Password(Password::new(
-                        UserIdentifier::UserIdOrLocalpart(config.account_config.username.clone()),
-                        config.account_config.password.clone().unwrap_or_else(|| {
-                            println!(
-                            "Type password for the bot (characters won't show up as you type them)"
-                        );
-                            match prompt_password("Password: ") {
-                                Ok(p) => p,
-                                Err(err) => {
-                                    panic!("FATAL: failed to get password: {err}");
-                                }
-                            }
-                        }),
-                    ))),
-                )
-                .await?;
-        }
-    }
-
-    if config.account_config.set_device_name {
-        if let Some(current_session) = current_session {
-            info!(
-                current_session = format!("{current_session:?}"),
-                "Renaming device to {}", &config.account_config.device_name
-            );
-            client
-                .rename_device(&current_session, &config.account_config.device_name)
-                .await?;
-        } else {
-            warn!("No device ID found, cannot name device");
-        }
-    }
-
-    // Now that we've synced, attach handlers for new messages.
-    client.add_event_handler(on_room_message);
-
-    // This loops until we kill the program or an error happens.
-    client
-        .sync_with_result_callback(sync_settings, |sync_result| async move {
-            let response = sync_result?;
-
-            // We persist the token each time to be able to restore our session
-            persist_sync_token(session_file, response.next_batch)
-                .await
-                .map_err(|err| matrix_sdk::Error::UnknownError(err.into()))?;
-
-            Ok(LoopCtrl::Continue)
-        })
-        .await?;
-
-    Ok(())
-}
-
-/// Persist the sync token for a future session.
-/// Note that this is needed only when using `sync_once`. Other sync methods get
-/// the sync token from the store.
-async fn persist_sync_token(session_file: &Path, sync_token: String) -> anyhow::Result<()> {
-    let serialized_session = fs::read_to_string(session_file).await?;
-    let mut full_session: FullSession = serde_json::from_str(&serialized_session)?;
-
-    full_session.sync_token = Some(sync_token);
-    let serialized_session = serde_json::to_string(&full_session)?;
-    fs::write(session_file, serialized_session).await?;
 
-    Ok(())
+    bot.run().await
 }
@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use matrix_bot_core::{fetch_message, reply_notice, send};
+use matrix_sdk::{
+    ruma::{
+        events::room::message::{
+            sanitize::remove_plain_reply_fallback, AddMentions, ForwardThread,
+            OriginalSyncRoomMessageEvent, RoomMessageEventContent,
+        },
+        EventId,
+    },
+    Room,
+};
+use tracing::{info, instrument, warn};
+
+use crate::{handlers::substitute, limits::LimitConfig};
+
+// Not mentioning sed, so the bot doesn't take it for a command
+const TEST_MESSAGE: &str = "Self-test: the quick brown fox";
+const TEST_COMMAND: &str = "s/quick brown/slow red/";
+const EXPECTED: &str = "Self-test: the slow red fox";
+
+/// How long to keep looking for the bot's own messages.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The steps of a self-test, and how long each took.
+#[derive(Default)]
+struct Timings {
+    steps: Vec<(&'static str, Duration)>,
+    started: Option<Instant>,
+}
+
+impl Timings {
+    /// Run one step, timing it.
+    async fn step<T>(
+        &mut self,
+        name: &'static str,
+        step: impl std::future::Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let started = Instant::now();
+        self.started.get_or_insert(started);
+        let result = step.await.with_context(|| format!("failed {name}"))?;
+        self.steps.push((name, started.elapsed()));
+        Ok(result)
+    }
+
+    fn report(&self) -> String {
+        self.steps
+            .iter()
+            .map(|(name, took)| format!("\n- {name}: {}ms", took.as_millis()))
+            .collect()
+    }
+}
+
+/// Post a test message, correct it and check the correction, the way a
+/// real one would go, then say how it went.
+#[instrument(skip_all, fields(room = room.room_id().as_str()))]
+pub async fn selftest(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    limits: LimitConfig,
+) -> anyhow::Result<()> {
+    let mut timings = Timings::default();
+    let result = run(&room, &limits, &mut timings).await;
+    let total = timings
+        .started
+        .map_or(Duration::ZERO, |started| started.elapsed());
+    let response = match result {
+        Ok(()) => {
+            info!(took_ms = total.as_millis(), "Self-test passed");
+            format!(
+                "Self-test passed in {}ms:{}",
+                total.as_millis(),
+                timings.report()
+            )
+        }
+        Err(err) => {
+            warn!("Self-test failed: {err:#}");
+            format!(
+                "Self-test failed after {}ms: {err:#}{}",
+                total.as_millis(),
+                timings.report()
+            )
+        }
+    };
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn run(room: &Room, limits: &LimitConfig, timings: &mut Timings) -> anyhow::Result<()> {
+    let test_id = timings
+        .step(
+            "posting the test message",
+            send(room, RoomMessageEventContent::text_plain(TEST_MESSAGE)),
+        )
+        .await?;
+    let target = timings
+        .step("finding it again", fetch(room, &test_id))
+        .await?;
+    let (result, changes) = timings
+        .step("substituting", async {
+            substitute(
+                TEST_COMMAND,
+                remove_plain_reply_fallback(target.content.body()),
+                limits,
+            )
+        })
+        .await?;
+    let target = target.into_full_event(room.room_id().to_owned());
+    let reply = RoomMessageEventContent::notice_html(result, changes).make_reply_to(
+        &target,
+        ForwardThread::Yes,
+        AddMentions::No,
+    );
+    let reply_id = timings
+        .step("sending the correction", send(room, reply))
+        .await?;
+    let reply = timings
+        .step("reading it back", fetch(room, &reply_id))
+        .await?;
+
+    let body = remove_plain_reply_fallback(reply.content.body());
+    if body != EXPECTED {
+        bail!("the correction said {body:?} rather than {EXPECTED:?}");
+    }
+    Ok(())
+}
+
+/// Fetch one of the bot's own messages, waiting for it to come down the
+/// sync if it isn't there straight away.
+async fn fetch(room: &Room, event_id: &EventId) -> anyhow::Result<OriginalSyncRoomMessageEvent> {
+    let started = Instant::now();
+    loop {
+        match fetch_message(room, event_id).await {
+            Ok(Some(message)) => return Ok(message),
+            Ok(None) => bail!("{event_id} isn't a message"),
+            Err(err) if started.elapsed() > FETCH_TIMEOUT => return Err(err),
+            Err(_) => tokio::time::sleep(Duration::from_millis(250)).await,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{bail, Context};
+use chrono::{NaiveTime, Weekday};
+use chrono_tz::Tz;
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, UserId};
+use serde::Deserialize;
+
+/// The teams file, e.g.
+///
+/// ```toml
+/// [teams.backend]
+/// room = "!abc:example.org"
+/// members = ["@alice:example.org", "@bob:example.org"]
+/// questions = ["What did you do yesterday?", "What are you doing today?", "Is anything blocking you?"]
+/// ask_at = "09:00"
+/// post_at = "11:00"
+/// days = ["mon", "tue", "wed", "thu", "fri"]
+/// timezone = "Europe/Berlin"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TeamsFile {
+    pub teams: BTreeMap<String, Team>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Team {
+    /// Where the summary is posted.
+    pub room: OwnedRoomId,
+    pub members: Vec<OwnedUserId>,
+    #[serde(default = "default_questions")]
+    pub questions: Vec<String>,
+    /// When members are sent the questions.
+    pub ask_at: NaiveTime,
+    /// When the summary is posted. Answers after this are too late.
+    pub post_at: NaiveTime,
+    #[serde(default = "default_days")]
+    pub days: Vec<Weekday>,
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+}
+
+impl Team {
+    pub fn has_member(&self, user_id: &UserId) -> bool {
+        self.members.iter().any(|member| member == user_id)
+    }
+}
+
+fn default_questions() -> Vec<String> {
+    vec![
+        "What did you do since the last standup?".to_owned(),
+        "What are you working on next?".to_owned(),
+        "Is anything blocking you?".to_owned(),
+    ]
+}
+
+fn default_days() -> Vec<Weekday> {
+    vec![
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+    ]
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+pub fn load(path: &Path) -> anyhow::Result<TeamsFile> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read teams file {}", path.display()))?;
+    let file: TeamsFile = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse teams file {}", path.display()))?;
+    for (name, team) in &file.teams {
+        if team.members.is_empty() {
+            bail!("team {name} has no members");
+        }
+        if team.questions.is_empty() {
+            bail!("team {name} has no questions");
+        }
+        if team.post_at <= team.ask_at {
+            bail!("team {name} posts its summary before asking its questions");
+        }
+    }
+    Ok(file)
+}
@@ -0,0 +1,158 @@
+use chrono::{Days, NaiveDate, Utc};
+use matrix_bot_core::{can_reply, parse_duration, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState,
+};
+use tracing::{debug, instrument};
+
+use crate::standup::{send_dm, Pending, Standups};
+
+const HELP: &str = "Usage:
+Answer the standup questions I send you in our DM, or reply `skip` to skip today.
+!standup skip to skip today's standups
+!standup vacation <last day, e.g. 2025-06-10, or a length, e.g. 1w>
+!standup back to end your vacation early
+!standup status";
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    standups: Ctx<Standups>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if let Some(args) = strip_command(body, "!standup") {
+        if can_reply(&room).await {
+            let response = command(args, &event, &standups)?;
+            reply_notice(&room, &event, response).await;
+        }
+        return Ok(());
+    }
+
+    // Anything else only counts as an answer in the sender's DM with the bot
+    let is_dm = room
+        .client()
+        .get_dm_room(&event.sender)
+        .is_some_and(|dm| dm.room_id() == room.room_id());
+    if !is_dm {
+        return Ok(());
+    }
+    let Some(pending) = standups.pending(&event.sender)? else {
+        return Ok(());
+    };
+
+    let sender = event.sender.as_str();
+    let previous_team = pending.team_name;
+    if body.trim().eq_ignore_ascii_case("skip") {
+        standups
+            .table
+            .skip(pending.team_name, pending.date, sender)?;
+        debug!(team = pending.team_name, "Skipped standup");
+    } else {
+        standups.table.add_answer(
+            pending.team_name,
+            pending.date,
+            sender,
+            pending.question,
+            body.trim(),
+        )?;
+        debug!(
+            team = pending.team_name,
+            question = pending.question,
+            "Recorded answer"
+        );
+    }
+
+    let text = match standups.pending(&event.sender)? {
+        Some(Pending {
+            team_name,
+            team,
+            question,
+            ..
+        }) if team_name == previous_team => team.questions[question].clone(),
+        Some(Pending {
+            team_name, team, ..
+        }) => format!(
+            "Thanks! Next up is the {team_name} standup. Reply `skip` to skip it.\n\n{}",
+            team.questions[0]
+        ),
+        None => "Thanks, that's everything!".to_owned(),
+    };
+    send_dm(&room.client(), &event.sender, &text).await;
+    Ok(())
+}
+
+fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    standups: &Standups,
+) -> anyhow::Result<String> {
+    let sender = event.sender.as_str();
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let today = Utc::now().date_naive();
+
+    Ok(match subcommand {
+        "skip" => match standups.skip_today(&event.sender)? {
+            0 => "You aren't in any standups.".to_owned(),
+            _ => "Skipping today's standup.".to_owned(),
+        },
+        "vacation" => match parse_until(rest.trim(), today) {
+            Some(until) => {
+                standups.table.set_vacation(sender, until)?;
+                format!(
+                    "Enjoy your time off! I won't ask you any standup questions until after {}.",
+                    until.format("%A %-d %B")
+                )
+            }
+            None => "When's your last day off? e.g. `!standup vacation 2025-06-10` or `!standup vacation 1w`".to_owned(),
+        },
+        "back" => {
+            if standups.table.end_vacation(sender)? {
+                "Welcome back!".to_owned()
+            } else {
+                "You weren't on vacation.".to_owned()
+            }
+        }
+        "status" => {
+            let teams: Vec<_> = standups
+                .teams
+                .teams
+                .iter()
+                .filter(|(_, team)| team.has_member(&event.sender))
+                .map(|(name, _)| name.as_str())
+                .collect();
+            let mut status = if teams.is_empty() {
+                "You aren't in any standups.".to_owned()
+            } else {
+                format!("You're in the {} standups.", teams.join(", "))
+            };
+            if let Some(until) = standups.table.vacation(sender, today)? {
+                status.push_str(&format!(
+                    " You're on vacation until {}.",
+                    until.format("%A %-d %B")
+                ));
+            }
+            status
+        }
+        _ => HELP.to_owned(),
+    })
+}
+
+/// Parse the last day of a vacation, given as a date or as a length from
+/// today.
+fn parse_until(input: &str, today: NaiveDate) -> Option<NaiveDate> {
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return (date >= today).then_some(date);
+    }
+    let days = parse_duration(input)?.as_secs().div_ceil(86400);
+    today.checked_add_days(Days::new(days.checked_sub(1)?))
+}
@@ -0,0 +1,55 @@
+mod config;
+mod handlers;
+mod standup;
+mod table;
+
+use std::{path::PathBuf, sync::Arc};
+
+use clap::Parser;
+use handlers::on_room_message;
+use matrix_bot_core::{AccountConfig, Bot};
+use standup::Standups;
+use table::StandupTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// TOML file describing each team's members, questions and schedule
+    #[arg(long, env = "STANDUP_TEAMS")]
+    pub teams: PathBuf,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let teams = config::load(&config.teams)?;
+
+    let mut bot = Bot::login("matrix-standup", config.account_config).await?;
+    let table = StandupTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let standups = Standups {
+        table,
+        teams: Arc::new(teams),
+    };
+    tokio::spawn(standup::run(bot.client().clone(), standups.clone()));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(standups);
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use chrono::{Datelike, NaiveDate, Utc};
+use matrix_bot_core::{html, send_or_log_error};
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, UserId},
+    Client, Room,
+};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    config::{Team, TeamsFile},
+    table::StandupTable,
+};
+
+/// How often to check whether a standup needs starting or posting.
+const TICK: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct Standups {
+    pub table: StandupTable,
+    pub teams: Arc<TeamsFile>,
+}
+
+/// A question a user has yet to answer.
+pub struct Pending<'a> {
+    pub team_name: &'a str,
+    pub team: &'a Team,
+    pub date: NaiveDate,
+    pub question: usize,
+}
+
+impl Standups {
+    /// The next question waiting for an answer from `user_id`, across all
+    /// their teams.
+    pub fn pending(&self, user_id: &UserId) -> anyhow::Result<Option<Pending<'_>>> {
+        for (team_name, team) in &self.teams.teams {
+            if !team.has_member(user_id) {
+                continue;
+            }
+            let Some(date) = self.table.open_run(team_name)? else {
+                continue;
+            };
+            if self.table.is_skipped(team_name, date, user_id.as_str())?
+                || self.table.vacation(user_id.as_str(), date)?.is_some()
+            {
+                continue;
+            }
+            let answered = self.table.answers(team_name, date, user_id.as_str())?.len();
+            if answered < team.questions.len() {
+                return Ok(Some(Pending {
+                    team_name,
+                    team,
+                    date,
+                    question: answered,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Skip today's standup, or the next one, for all of the user's teams.
+    pub fn skip_today(&self, user_id: &UserId) -> anyhow::Result<usize> {
+        let mut skipped = 0;
+        for (team_name, team) in &self.teams.teams {
+            if !team.has_member(user_id) {
+                continue;
+            }
+            let today = Utc::now().with_timezone(&team.timezone).date_naive();
+            let date = self.table.open_run(team_name)?.unwrap_or(today);
+            self.table.skip(team_name, date, user_id.as_str())?;
+            skipped += 1;
+        }
+        Ok(skipped)
+    }
+}
+
+/// Start and post standups at their configured times, forever.
+pub async fn run(client: Client, standups: Standups) {
+    let mut interval = interval(TICK);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        for (team_name, team) in &standups.teams.teams {
+            if let Err(err) = tick(&client, &standups, team_name, team).await {
+                error!(team = team_name.as_str(), "Failed to run standup: {err}");
+            }
+        }
+    }
+}
+
+async fn tick(
+    client: &Client,
+    standups: &Standups,
+    team_name: &str,
+    team: &Team,
+) -> anyhow::Result<()> {
+    let now = Utc::now().with_timezone(&team.timezone);
+    let today = now.date_naive();
+    let time = now.time();
+
+    // Post any open standup once its time comes, even one left over from
+    // before a restart
+    if let Some(date) = standups.table.open_run(team_name)? {
+        if date < today || time >= team.post_at {
+            post_summary(client, standups, team_name, team, date).await?;
+        }
+    }
+
+    if team.days.contains(&today.weekday())
+        && time >= team.ask_at
+        && time < team.post_at
+        && standups.table.start_run(team_name, today)?
+    {
+        start(client, standups, team_name, team, today).await?;
+    }
+    Ok(())
+}
+
+#[instrument(skip_all, fields(team = team_name))]
+async fn start(
+    client: &Client,
+    standups: &Standups,
+    team_name: &str,
+    team: &Team,
+    date: NaiveDate,
+) -> anyhow::Result<()> {
+    info!("Starting standup");
+    for member in &team.members {
+        if standups.table.vacation(member.as_str(), date)?.is_some()
+            || standups
+                .table
+                .is_skipped(team_name, date, member.as_str())?
+        {
+            continue;
+        }
+        // Members of several teams answer them one at a time
+        let Some(pending) = standups.pending(member)? else {
+            continue;
+        };
+        if pending.team_name != team_name || pending.question != 0 {
+            continue;
+        }
+        let text = format!(
+            "It's time for the {team_name} standup! Reply `skip` to skip today.\n\n{}",
+            team.questions[0]
+        );
+        send_dm(client, member, &text).await;
+    }
+    Ok(())
+}
+
+#[instrument(skip_all, fields(team = team_name))]
+async fn post_summary(
+    client: &Client,
+    standups: &Standups,
+    team_name: &str,
+    team: &Team,
+    date: NaiveDate,
+) -> anyhow::Result<()> {
+    let table = &standups.table;
+    let mut plain = format!("Standup for {team_name}, {}\n", date.format("%A %-d %B"));
+    let mut formatted = format!(
+        "<h4>Standup for {}, {}</h4>",
+        html::escape(team_name),
+        date.format("%A %-d %B")
+    );
+    let mut skipped = Vec::new();
+    let mut away = Vec::new();
+    let mut silent = Vec::new();
+
+    for member in &team.members {
+        let answers = table.answers(team_name, date, member.as_str())?;
+        if !answers.is_empty() {
+            plain.push_str(&format!("\n{member}\n"));
+            formatted.push_str(&format!("<p>{}</p><ul>", html::user_pill(member)));
+            for (question, answer) in team.questions.iter().zip(&answers) {
+                plain.push_str(&format!("{question}\n{answer}\n"));
+                formatted.push_str(&format!(
+                    "<li><i>{}</i><br>{}</li>",
+                    html::escape(question),
+                    html::escape(answer).replace('\n', "<br>")
+                ));
+            }
+            formatted.push_str("</ul>");
+        } else if table.is_skipped(team_name, date, member.as_str())? {
+            skipped.push(member);
+        } else if table.vacation(member.as_str(), date)?.is_some() {
+            away.push(member);
+        } else {
+            silent.push(member);
+        }
+    }
+
+    for (label, members) in [
+        ("Skipped", skipped),
+        ("On vacation", away),
+        ("No response", silent),
+    ] {
+        if members.is_empty() {
+            continue;
+        }
+        let names: Vec<_> = members.iter().map(|member| member.as_str()).collect();
+        let pills: Vec<_> = members
+            .iter()
+            .map(|member| html::user_pill(member))
+            .collect();
+        plain.push_str(&format!("\n{label}: {}", names.join(", ")));
+        formatted.push_str(&format!("<p>{label}: {}</p>", pills.join(", ")));
+    }
+
+    // Mark it first, so a room the bot can't post in doesn't get retried
+    // every tick
+    table.mark_posted(team_name, date)?;
+    match client.get_room(&team.room) {
+        Some(room) => {
+            info!("Posting standup summary");
+            send_or_log_error(
+                &room,
+                RoomMessageEventContent::notice_html(plain, formatted),
+            )
+            .await;
+        }
+        None => warn!("Not in the team's room, not posting the summary"),
+    }
+    Ok(())
+}
+
+/// Send a message to a user in their DM with the bot, creating it if needed.
+pub async fn send_dm(client: &Client, user_id: &UserId, text: &str) {
+    let room = match dm_room(client, user_id).await {
+        Ok(room) => room,
+        Err(err) => {
+            warn!("Failed to open a DM with {user_id}: {err}");
+            return;
+        }
+    };
+    send_or_log_error(&room, RoomMessageEventContent::text_markdown(text)).await;
+}
+
+async fn dm_room(client: &Client, user_id: &UserId) -> anyhow::Result<Room> {
+    if let Some(room) = client.get_dm_room(user_id) {
+        return Ok(room);
+    }
+    Ok(client.create_dm(user_id).await?)
+}
@@ -0,0 +1,171 @@
+use chrono::NaiveDate;
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE runs (
+        team TEXT NOT NULL,
+        date TEXT NOT NULL,
+        posted INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (team, date)
+    );
+    CREATE TABLE answers (
+        team TEXT NOT NULL,
+        date TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        question INTEGER NOT NULL,
+        answer TEXT NOT NULL,
+        PRIMARY KEY (team, date, user_id, question)
+    );
+    CREATE TABLE skips (
+        team TEXT NOT NULL,
+        date TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        PRIMARY KEY (team, date, user_id)
+    );
+    CREATE TABLE vacations (
+        user_id TEXT PRIMARY KEY,
+        until TEXT NOT NULL
+    );"];
+
+/// Standup runs, answers, skips and vacations.
+///
+/// Dates are the standup's day in the team's time zone.
+#[derive(Clone)]
+pub struct StandupTable {
+    store: Store,
+}
+
+impl StandupTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Start a team's standup for the day. Returns false if it had already
+    /// started.
+    pub fn start_run(&self, team: &str, date: NaiveDate) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let inserted = conn.execute(
+                "INSERT OR IGNORE INTO runs (team, date) VALUES (?1, ?2)",
+                params![team, date],
+            )?;
+            Ok(inserted > 0)
+        })
+    }
+
+    /// The day of the team's standup that's collecting answers, if any.
+    pub fn open_run(&self, team: &str) -> anyhow::Result<Option<NaiveDate>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT date FROM runs WHERE team = ?1 AND NOT posted ORDER BY date DESC",
+                params![team],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+
+    pub fn mark_posted(&self, team: &str, date: NaiveDate) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE runs SET posted = 1 WHERE team = ?1 AND date = ?2",
+                params![team, date],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn add_answer(
+        &self,
+        team: &str,
+        date: NaiveDate,
+        user_id: &str,
+        question: usize,
+        answer: &str,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO answers (team, date, user_id, question, answer)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![team, date, user_id, question, answer],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// A user's answers, in question order.
+    pub fn answers(
+        &self,
+        team: &str,
+        date: NaiveDate,
+        user_id: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT answer FROM answers WHERE team = ?1 AND date = ?2 AND user_id = ?3
+                ORDER BY question",
+            )?;
+            let answers = statement
+                .query_map(params![team, date, user_id], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            Ok(answers)
+        })
+    }
+
+    pub fn skip(&self, team: &str, date: NaiveDate, user_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO skips (team, date, user_id) VALUES (?1, ?2, ?3)",
+                params![team, date, user_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn is_skipped(&self, team: &str, date: NaiveDate, user_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT 1 FROM skips WHERE team = ?1 AND date = ?2 AND user_id = ?3",
+                    params![team, date, user_id],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some())
+        })
+    }
+
+    /// Set the last day of a user's vacation.
+    pub fn set_vacation(&self, user_id: &str, until: NaiveDate) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO vacations (user_id, until) VALUES (?1, ?2)
+                ON CONFLICT (user_id) DO UPDATE SET until = excluded.until",
+                params![user_id, until],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Returns whether the user was on vacation.
+    pub fn end_vacation(&self, user_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed =
+                conn.execute("DELETE FROM vacations WHERE user_id = ?1", params![user_id])?;
+            Ok(removed > 0)
+        })
+    }
+
+    /// The last day of a user's vacation, if they're away on `date`.
+    pub fn vacation(&self, user_id: &str, date: NaiveDate) -> anyhow::Result<Option<NaiveDate>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT until FROM vacations WHERE user_id = ?1 AND until >= ?2",
+                params![user_id, date],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+}
@@ -0,0 +1,181 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use matrix_bot_core::{
+    can_reply, is_moderator, media, reply, reply_notice, send_or_log_error, strip_command,
+    text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::{
+            reaction::OriginalSyncReactionEvent,
+            room::message::{MessageType, OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+        },
+        MilliSecondsSinceUnixEpoch,
+    },
+    Room, RoomState,
+};
+use tracing::{instrument, warn};
+
+use crate::{
+    report::{chart, extract_emoji, hourly, render},
+    table::{Reports, StatsTable},
+};
+
+const HELP: &str = "Usage:
+!stats [day|week|month] for this room's activity
+!stats reports [daily|weekly|both|off] to see or change this room's regular reports (moderators only)";
+
+#[derive(Clone)]
+pub struct Stats {
+    pub table: StatsTable,
+    pub timezone: Tz,
+    /// Send a chart of activity by hour with each report.
+    pub charts: bool,
+}
+
+impl Stats {
+    /// Send a report on the room's activity since `since`, in reply to
+    /// `event` if it was asked for.
+    pub async fn send_report(
+        &self,
+        room: &Room,
+        event: Option<&OriginalSyncRoomMessageEvent>,
+        since: DateTime<Utc>,
+        period: &str,
+    ) -> anyhow::Result<()> {
+        let activity = self.table.activity(room.room_id().as_str(), since)?;
+        let (plain, formatted) = render(&activity, period, self.timezone);
+        let content = RoomMessageEventContent::notice_html(plain, formatted);
+        match event {
+            Some(event) => reply(room, event, content).await,
+            None => send_or_log_error(room, content).await,
+        }
+
+        if self.charts && activity.messages > 0 {
+            let png = chart(&hourly(&activity, self.timezone))?;
+            if let Err(err) = media::send_file(room, "activity.png", &mime::IMAGE_PNG, png).await {
+                warn!("Failed to send activity chart: {err:#}");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn timestamp(ts: MilliSecondsSinceUnixEpoch) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(ts.get().into()).unwrap_or_else(Utc::now)
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    stats: Ctx<Stats>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+
+    if let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!stats")) {
+        if can_reply(&room).await {
+            command(args, &event, &room, &stats).await?;
+        }
+        return Ok(());
+    }
+
+    let emoji = match &event.content.msgtype {
+        MessageType::Text(content) => extract_emoji(&content.body),
+        MessageType::Emote(content) => extract_emoji(&content.body),
+        _ => Vec::new(),
+    };
+    stats.table.record_message(
+        room.room_id().as_str(),
+        event.sender.as_str(),
+        timestamp(event.origin_server_ts),
+        &emoji,
+    )?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_reaction(
+    event: OriginalSyncReactionEvent,
+    room: Room,
+    stats: Ctx<Stats>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if let Some(emoji) = extract_emoji(&event.content.relates_to.key)
+        .into_iter()
+        .next()
+    {
+        stats.table.record_emoji(
+            room.room_id().as_str(),
+            &emoji,
+            timestamp(event.origin_server_ts),
+        )?;
+    }
+    Ok(())
+}
+
+async fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    stats: &Stats,
+) -> anyhow::Result<()> {
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let now = Utc::now();
+    let (days, period) = match subcommand {
+        "day" | "today" => (1, "the last day"),
+        "" | "week" => (7, "the last 7 days"),
+        "month" => (30, "the last 30 days"),
+        "reports" => {
+            let response = set_reports(rest.trim(), event, room, &stats.table).await?;
+            reply_notice(room, event, response).await;
+            return Ok(());
+        }
+        _ => {
+            reply_notice(room, event, HELP).await;
+            return Ok(());
+        }
+    };
+    stats
+        .send_report(
+            room,
+            Some(event),
+            now - chrono::Duration::days(days),
+            period,
+        )
+        .await
+}
+
+async fn set_reports(
+    reports: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    table: &StatsTable,
+) -> anyhow::Result<String> {
+    let room_id = room.room_id().as_str();
+    if reports.is_empty() {
+        return Ok(match table.room_reports(room_id)? {
+            Reports::Off => "This room doesn't get activity reports.".to_owned(),
+            reports => format!("This room gets {} activity reports.", reports.as_str()),
+        });
+    }
+    let Some(reports) = Reports::parse(reports) else {
+        return Ok(HELP.to_owned());
+    };
+    if !is_moderator(room, &event.sender).await? {
+        return Ok("Only moderators can change this room's reports.".to_owned());
+    }
+    table.set_room_reports(room_id, reports)?;
+    Ok(match reports {
+        Reports::Off => "This room won't get activity reports any more.".to_owned(),
+        reports => format!("This room will get {} activity reports.", reports.as_str()),
+    })
+}
@@ -0,0 +1,66 @@
+mod handlers;
+mod report;
+mod scheduler;
+mod table;
+
+use chrono::NaiveTime;
+use chrono_tz::Tz;
+use clap::Parser;
+use handlers::{on_reaction, on_room_message, Stats};
+use matrix_bot_core::{AccountConfig, Bot};
+use table::StatsTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Time zone used for reports and peak hours, e.g. `Europe/Berlin`
+    #[arg(long, default_value_t = Tz::UTC, env = "STATS_TIMEZONE")]
+    pub timezone: Tz,
+
+    /// The time of day to send reports, e.g. `09:00`
+    #[arg(long, default_value = "09:00", env = "STATS_REPORT_AT")]
+    pub report_at: NaiveTime,
+
+    /// Send a chart of activity by hour with each report
+    #[arg(long, env = "STATS_CHARTS")]
+    pub charts: bool,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-stats", config.account_config).await?;
+    let table = StatsTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let stats = Stats {
+        table,
+        timezone: config.timezone,
+        charts: config.charts,
+    };
+    tokio::spawn(scheduler::run(
+        bot.client().clone(),
+        stats.clone(),
+        config.report_at,
+    ));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(stats);
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_reaction);
+
+    bot.run().await
+}
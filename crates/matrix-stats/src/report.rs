@@ -0,0 +1,177 @@
+use chrono::Timelike;
+use chrono_tz::Tz;
+use matrix_bot_core::html;
+
+use crate::table::Activity;
+
+/// How many people and emoji a report lists.
+const TOP_COUNT: usize = 5;
+
+/// The most emoji counted from a single message.
+const MAX_EMOJI_PER_MESSAGE: usize = 20;
+
+/// Pick out the emoji in `text`, keeping modifiers, ZWJ sequences and flags
+/// together.
+pub fn extract_emoji(text: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !is_emoji(c) {
+            continue;
+        }
+        let mut emoji = String::from(c);
+        if is_regional_indicator(c) {
+            if let Some(next) = chars.next_if(|&next| is_regional_indicator(next)) {
+                emoji.push(next);
+            }
+        } else {
+            while let Some(next) = chars.next_if(|&next| is_modifier(next) || next == '\u{200D}') {
+                emoji.push(next);
+                if next == '\u{200D}' {
+                    if let Some(joined) = chars.next_if(|&joined| is_emoji(joined)) {
+                        emoji.push(joined);
+                    }
+                }
+            }
+        }
+        found.push(emoji);
+        if found.len() == MAX_EMOJI_PER_MESSAGE {
+            break;
+        }
+    }
+    found
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32, 0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF) && !is_modifier(c)
+}
+
+fn is_modifier(c: char) -> bool {
+    matches!(c as u32, 0xFE0F | 0x1F3FB..=0x1F3FF)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1F1E6..=0x1F1FF)
+}
+
+/// How many messages were sent in each hour of the day, in `tz`.
+pub fn hourly(activity: &Activity, tz: Tz) -> [u64; 24] {
+    let mut hours = [0; 24];
+    for timestamp in &activity.timestamps {
+        hours[timestamp.with_timezone(&tz).hour() as usize] += 1;
+    }
+    hours
+}
+
+/// The plain and HTML bodies of a report on `activity` over `period`, such
+/// as "the last 7 days".
+pub fn render(activity: &Activity, period: &str, tz: Tz) -> (String, String) {
+    if activity.messages == 0 {
+        let text = format!("📊 Nobody has said anything in {period}.");
+        return (text.clone(), text);
+    }
+
+    let mut plain = vec![format!(
+        "📊 {} messages from {} people in {period}.",
+        activity.messages,
+        activity.senders.len()
+    )];
+    let mut formatted = vec![format!(
+        "📊 <b>{}</b> messages from <b>{}</b> people in {period}.",
+        activity.messages,
+        activity.senders.len()
+    )];
+
+    let top: Vec<_> = activity.senders.iter().take(TOP_COUNT).collect();
+    plain.push(format!(
+        "Most active: {}",
+        top.iter()
+            .map(|(user, count)| format!("{user} ({count})"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    formatted.push(format!(
+        "Most active: {}",
+        top.iter()
+            .map(|(user, count)| format!("{} ({count})", html::escape(user)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+
+    let hours = hourly(activity, tz);
+    let (peak, _) = hours
+        .iter()
+        .enumerate()
+        .max_by_key(|(hour, count)| (**count, std::cmp::Reverse(*hour)))
+        .unwrap();
+    let peak = format!("Busiest hour: {peak:02}:00–{:02}:00", (peak + 1) % 24);
+    plain.push(peak.clone());
+    formatted.push(peak);
+
+    if !activity.emoji.is_empty() {
+        let emoji = format!(
+            "Top emoji: {}",
+            activity
+                .emoji
+                .iter()
+                .take(TOP_COUNT)
+                .map(|(emoji, count)| format!("{emoji} ×{count}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        formatted.push(html::escape(&emoji));
+        plain.push(emoji);
+    }
+
+    (plain.join("\n"), formatted.join("<br>"))
+}
+
+/// A PNG bar chart of messages per hour of the day.
+pub fn chart(hours: &[u64; 24]) -> anyhow::Result<Vec<u8>> {
+    const BAR_WIDTH: u32 = 16;
+    const GAP: u32 = 4;
+    const MARGIN: u32 = 10;
+    const HEIGHT: u32 = 160;
+    const BACKGROUND: [u8; 3] = [255, 255, 255];
+    const BAR: [u8; 3] = [59, 130, 246];
+    const AXIS: [u8; 3] = [160, 160, 160];
+
+    let width = MARGIN * 2 + 24 * (BAR_WIDTH + GAP) - GAP;
+    let height = MARGIN * 2 + HEIGHT;
+    let max = hours.iter().copied().max().unwrap_or(0).max(1);
+
+    let mut pixels = vec![0u8; (width * height * 3) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let colour = if y == MARGIN + HEIGHT && x >= MARGIN && x < width - MARGIN {
+                AXIS
+            } else if x >= MARGIN
+                && x < width - MARGIN
+                && (x - MARGIN) % (BAR_WIDTH + GAP) < BAR_WIDTH
+                && y >= MARGIN
+                && y < MARGIN + HEIGHT
+            {
+                let hour = ((x - MARGIN) / (BAR_WIDTH + GAP)) as usize;
+                let bar_height = (hours[hour] * HEIGHT as u64 / max) as u32;
+                if y >= MARGIN + HEIGHT - bar_height {
+                    BAR
+                } else {
+                    BACKGROUND
+                }
+            } else {
+                BACKGROUND
+            };
+            let offset = ((y * width + x) * 3) as usize;
+            pixels[offset..offset + 3].copy_from_slice(&colour);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(png)
+}
@@ -0,0 +1,83 @@
+use chrono::{Datelike, NaiveTime, Utc, Weekday};
+use matrix_bot_core::can_reply;
+use matrix_sdk::{ruma::RoomId, Client, RoomState};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::{error, info, warn};
+
+use crate::handlers::Stats;
+
+/// How often to check whether reports are due.
+const TICK: Duration = Duration::from_secs(60);
+
+/// The day weekly reports are sent.
+const WEEKLY_REPORT_DAY: Weekday = Weekday::Mon;
+
+/// Send daily and weekly reports at `report_at` each day, forever.
+pub async fn run(client: Client, stats: Stats, report_at: NaiveTime) {
+    let mut interval = interval(TICK);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(err) = tick(&client, &stats, report_at).await {
+            error!("Failed to send reports: {err}");
+        }
+    }
+}
+
+async fn tick(client: &Client, stats: &Stats, report_at: NaiveTime) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let local = now.with_timezone(&stats.timezone);
+    if local.time() < report_at {
+        return Ok(());
+    }
+    let today = local.date_naive().to_string();
+
+    for (kind, due, days, period) in [
+        ("daily", true, 1, "the last day"),
+        (
+            "weekly",
+            local.date_naive().weekday() == WEEKLY_REPORT_DAY,
+            7,
+            "the last week",
+        ),
+    ] {
+        if !due || stats.table.last_report(kind)?.as_deref() == Some(today.as_str()) {
+            continue;
+        }
+        // Mark it first, so that a failing room doesn't get reports every
+        // minute
+        stats.table.set_last_report(kind, &today)?;
+        info!(kind, "Sending reports");
+
+        for (room_id, reports) in stats.table.report_rooms()? {
+            let wanted = match kind {
+                "daily" => reports.daily(),
+                _ => reports.weekly(),
+            };
+            if !wanted {
+                continue;
+            }
+            let Some(room) = <&RoomId>::try_from(room_id.as_str())
+                .ok()
+                .and_then(|room_id| client.get_room(room_id))
+            else {
+                warn!(room = room_id.as_str(), "Not in the room for a report");
+                continue;
+            };
+            if room.state() != RoomState::Joined || !can_reply(&room).await {
+                continue;
+            }
+            if let Err(err) = stats
+                .send_report(&room, None, now - chrono::Duration::days(days), period)
+                .await
+            {
+                error!(room = room_id.as_str(), "Failed to send report: {err}");
+            }
+        }
+
+        if kind == "daily" {
+            stats.table.prune(now)?;
+        }
+    }
+    Ok(())
+}
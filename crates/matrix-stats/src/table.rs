@@ -0,0 +1,244 @@
+use chrono::{DateTime, Utc};
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE messages (
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        ts INTEGER NOT NULL
+    );
+    CREATE INDEX messages_room ON messages (room_id, ts);
+    CREATE TABLE emoji (
+        room_id TEXT NOT NULL,
+        emoji TEXT NOT NULL,
+        ts INTEGER NOT NULL
+    );
+    CREATE INDEX emoji_room ON emoji (room_id, ts);
+    CREATE TABLE room_reports (
+        room_id TEXT PRIMARY KEY,
+        reports TEXT NOT NULL
+    );
+    CREATE TABLE sent_reports (
+        kind TEXT PRIMARY KEY,
+        date TEXT NOT NULL
+    );"];
+
+/// How long activity is kept for, in days.
+const RETENTION_DAYS: i64 = 90;
+
+/// Which reports a room gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reports {
+    Off,
+    Daily,
+    Weekly,
+    Both,
+}
+
+impl Reports {
+    pub fn parse(reports: &str) -> Option<Self> {
+        Some(match reports {
+            "off" | "none" => Reports::Off,
+            "daily" => Reports::Daily,
+            "weekly" => Reports::Weekly,
+            "both" => Reports::Both,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reports::Off => "off",
+            Reports::Daily => "daily",
+            Reports::Weekly => "weekly",
+            Reports::Both => "both",
+        }
+    }
+
+    pub fn daily(self) -> bool {
+        matches!(self, Reports::Daily | Reports::Both)
+    }
+
+    pub fn weekly(self) -> bool {
+        matches!(self, Reports::Weekly | Reports::Both)
+    }
+}
+
+/// Activity in a room over some period.
+#[derive(Debug, Default)]
+pub struct Activity {
+    pub messages: u64,
+    /// Senders and their message counts, most active first.
+    pub senders: Vec<(String, u64)>,
+    /// When each message was sent.
+    pub timestamps: Vec<DateTime<Utc>>,
+    /// Emoji and how often they were used, most used first.
+    pub emoji: Vec<(String, u64)>,
+}
+
+/// Room activity and report settings, persisted in SQLite.
+#[derive(Clone)]
+pub struct StatsTable {
+    store: Store,
+}
+
+impl StatsTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn record_message(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        ts: DateTime<Utc>,
+        emoji: &[String],
+    ) -> anyhow::Result<()> {
+        self.store.transaction(|transaction| {
+            transaction.execute(
+                "INSERT INTO messages (room_id, user_id, ts) VALUES (?1, ?2, ?3)",
+                params![room_id, user_id, ts.timestamp()],
+            )?;
+            for emoji in emoji {
+                transaction.execute(
+                    "INSERT INTO emoji (room_id, emoji, ts) VALUES (?1, ?2, ?3)",
+                    params![room_id, emoji, ts.timestamp()],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn record_emoji(
+        &self,
+        room_id: &str,
+        emoji: &str,
+        ts: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO emoji (room_id, emoji, ts) VALUES (?1, ?2, ?3)",
+                params![room_id, emoji, ts.timestamp()],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// A room's activity since `since`.
+    pub fn activity(&self, room_id: &str, since: DateTime<Utc>) -> anyhow::Result<Activity> {
+        self.store.with(|conn| {
+            let since = since.timestamp();
+
+            let mut statement = conn.prepare(
+                "SELECT user_id, COUNT(*) AS count FROM messages WHERE room_id = ?1 AND ts >= ?2
+                GROUP BY user_id ORDER BY count DESC, user_id",
+            )?;
+            let senders: Vec<(String, u64)> = statement
+                .query_map(params![room_id, since], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .collect::<Result<_, _>>()?;
+
+            let mut statement =
+                conn.prepare("SELECT ts FROM messages WHERE room_id = ?1 AND ts >= ?2")?;
+            let timestamps = statement
+                .query_map(params![room_id, since], |row| row.get(0))?
+                .collect::<Result<Vec<i64>, _>>()?
+                .into_iter()
+                .filter_map(|ts| DateTime::from_timestamp(ts, 0))
+                .collect();
+
+            let mut statement = conn.prepare(
+                "SELECT emoji, COUNT(*) AS count FROM emoji WHERE room_id = ?1 AND ts >= ?2
+                GROUP BY emoji ORDER BY count DESC, emoji",
+            )?;
+            let emoji = statement
+                .query_map(params![room_id, since], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .collect::<Result<_, _>>()?;
+
+            Ok(Activity {
+                messages: senders.iter().map(|(_, count)| count).sum(),
+                senders,
+                timestamps,
+                emoji,
+            })
+        })
+    }
+
+    /// Forget activity older than the retention period.
+    pub fn prune(&self, now: DateTime<Utc>) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            let cutoff = (now - chrono::Duration::days(RETENTION_DAYS)).timestamp();
+            conn.execute("DELETE FROM messages WHERE ts < ?1", params![cutoff])?;
+            conn.execute("DELETE FROM emoji WHERE ts < ?1", params![cutoff])?;
+            Ok(())
+        })
+    }
+
+    pub fn room_reports(&self, room_id: &str) -> anyhow::Result<Reports> {
+        self.store.with(|conn| {
+            let reports: Option<String> = conn
+                .query_row(
+                    "SELECT reports FROM room_reports WHERE room_id = ?1",
+                    params![room_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(reports
+                .and_then(|reports| Reports::parse(&reports))
+                .unwrap_or(Reports::Off))
+        })
+    }
+
+    pub fn set_room_reports(&self, room_id: &str, reports: Reports) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO room_reports (room_id, reports) VALUES (?1, ?2)
+                ON CONFLICT (room_id) DO UPDATE SET reports = excluded.reports",
+                params![room_id, reports.as_str()],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Rooms that get any reports, with which ones.
+    pub fn report_rooms(&self) -> anyhow::Result<Vec<(String, Reports)>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare("SELECT room_id, reports FROM room_reports")?;
+            let rooms = statement
+                .query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rooms
+                .into_iter()
+                .filter_map(|(room_id, reports)| Some((room_id, Reports::parse(&reports)?)))
+                .collect())
+        })
+    }
+
+    /// The date the last report of `kind` was sent, as `YYYY-MM-DD`.
+    pub fn last_report(&self, kind: &str) -> anyhow::Result<Option<String>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT date FROM sent_reports WHERE kind = ?1",
+                params![kind],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+
+    pub fn set_last_report(&self, kind: &str, date: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO sent_reports (kind, date) VALUES (?1, ?2)
+                ON CONFLICT (kind) DO UPDATE SET date = excluded.date",
+                params![kind, date],
+            )?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use chrono::DateTime;
+use matrix_bot_core::{format_duration, html, reply_notice, send, send_or_log_error};
+use matrix_sdk::{
+    ruma::{
+        events::{
+            relation::Thread,
+            room::message::{OriginalSyncRoomMessageEvent, Relation, RoomMessageEventContent},
+            Mentions,
+        },
+        EventId, OwnedEventId, OwnedRoomId, RoomId, UserId,
+    },
+    Client, Room, RoomState,
+};
+use tracing::{info, warn};
+
+use crate::table::{now, Ticket, TicketTable};
+
+const STAFF_HELP: &str = "In a ticket's thread, anything you say is passed on to the user, \
+unless it starts with !. Commands:
+!ticket close or !ticket reopen
+!ticket assign [user] to assign it to someone, or yourself, and !ticket unassign
+!ticket transcript for everything said so far
+!ticket list, anywhere in this room, for the open tickets";
+
+#[derive(Clone)]
+pub struct Helpdesk {
+    pub table: TicketTable,
+    /// Where tickets are posted, one thread each. Its members are the staff.
+    pub staff_room: OwnedRoomId,
+    /// Whether to leave staff names out of the replies users get.
+    pub anonymous: bool,
+}
+
+impl Helpdesk {
+    fn staff_room(&self, client: &Client) -> anyhow::Result<Room> {
+        client
+            .get_room(&self.staff_room)
+            .filter(|room| room.state() == RoomState::Joined)
+            .ok_or_else(|| anyhow::anyhow!("not in the staff room {}", self.staff_room))
+    }
+
+    /// Pass on a message a user sent in their DM with the bot, opening a
+    /// ticket if they don't have one going.
+    pub async fn from_user(
+        &self,
+        room: &Room,
+        event: &OriginalSyncRoomMessageEvent,
+        body: &str,
+    ) -> anyhow::Result<()> {
+        let staff_room = self.staff_room(&room.client())?;
+        let user_id = &event.sender;
+        let room_id = room.room_id().as_str();
+
+        let ticket = match self.table.open_for_user(user_id.as_str())? {
+            Some(ticket) => {
+                if ticket.dm_room_id != room_id {
+                    self.table.set_dm_room(ticket.id, room_id)?;
+                }
+                ticket
+            }
+            None => {
+                let id = self.table.create(user_id.as_str(), room_id)?;
+                let content = RoomMessageEventContent::text_html(
+                    format!("🎫 Ticket #{id} opened by {user_id}:\n\n{body}"),
+                    format!(
+                        "🎫 Ticket #{id} opened by {}:<br><br>{}",
+                        html::user_pill(user_id),
+                        html::escape(body).replace('\n', "<br>")
+                    ),
+                );
+                let root = send(&staff_room, content).await?;
+                self.table.set_thread(id, root.as_str())?;
+                self.table.add_message(id, user_id.as_str(), false, body)?;
+                info!(ticket = id, "Opened ticket");
+                reply_notice(
+                    room,
+                    event,
+                    format!("Thanks, I've opened ticket #{id}. Staff will reply to you here."),
+                )
+                .await;
+                return Ok(());
+            }
+        };
+
+        self.table
+            .add_message(ticket.id, user_id.as_str(), false, body)?;
+        // Still being opened, the transcript will have it
+        let Some(root) = thread_root(&ticket) else {
+            return Ok(());
+        };
+        let content = in_thread(
+            RoomMessageEventContent::text_plain(format!("{user_id}: {body}")),
+            root,
+        );
+        send(&staff_room, content).await?;
+        Ok(())
+    }
+
+    /// Handle a message in the staff room: commands, and replies in ticket
+    /// threads to pass on.
+    pub async fn from_staff(
+        &self,
+        room: &Room,
+        event: &OriginalSyncRoomMessageEvent,
+        body: &str,
+    ) -> anyhow::Result<()> {
+        let ticket = match &event.content.relates_to {
+            Some(Relation::Thread(thread)) => self.table.by_thread(thread.event_id.as_str())?,
+            _ => None,
+        };
+        let command = body
+            .trim()
+            .strip_prefix("!ticket")
+            .filter(|args| args.is_empty() || args.starts_with(char::is_whitespace))
+            .map(str::trim);
+
+        let Some(ticket) = ticket else {
+            let response = match command {
+                Some("list") => self.list()?,
+                Some(_) => STAFF_HELP.to_owned(),
+                None => return Ok(()),
+            };
+            reply_notice(room, event, response).await;
+            return Ok(());
+        };
+
+        if let Some(args) = command {
+            if let Some(response) = self.command(room, event, &ticket, args).await? {
+                reply_notice(room, event, response).await;
+            }
+            return Ok(());
+        }
+        // Notes among staff
+        if body.starts_with('!') {
+            return Ok(());
+        }
+
+        if !ticket.open {
+            let response = format!(
+                "Ticket #{} is closed. Reopen it with `!ticket reopen` to reply.",
+                ticket.id
+            );
+            reply_notice(room, event, response).await;
+            return Ok(());
+        }
+        let dm = <&RoomId>::try_from(ticket.dm_room_id.as_str())
+            .ok()
+            .and_then(|room_id| room.client().get_room(room_id))
+            .filter(|dm| dm.state() == RoomState::Joined);
+        let Some(dm) = dm else {
+            reply_notice(room, event, "The user has left our DM, so I can't reply.").await;
+            return Ok(());
+        };
+
+        let signature = if self.anonymous {
+            "Staff".to_owned()
+        } else {
+            staff_name(room, &event.sender).await
+        };
+        send(
+            &dm,
+            RoomMessageEventContent::text_plain(format!("{signature}: {body}")),
+        )
+        .await?;
+        self.table
+            .add_message(ticket.id, event.sender.as_str(), true, body)?;
+        Ok(())
+    }
+
+    async fn command(
+        &self,
+        room: &Room,
+        event: &OriginalSyncRoomMessageEvent,
+        ticket: &Ticket,
+        args: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let rest = rest.trim();
+        let id = ticket.id;
+
+        let response = match subcommand {
+            "close" if !ticket.open => format!("Ticket #{id} is already closed."),
+            "close" => {
+                self.table.set_open(id, false)?;
+                self.notify_user(
+                    room,
+                    ticket,
+                    format!(
+                        "Ticket #{id} has been closed. Message me again if you need anything else."
+                    ),
+                )
+                .await;
+                info!(ticket = id, "Closed ticket");
+                format!("Closed ticket #{id}.")
+            }
+            "reopen" if ticket.open => format!("Ticket #{id} is already open."),
+            "reopen" => {
+                if let Some(other) = self.table.open_for_user(&ticket.user_id)? {
+                    return Ok(Some(format!(
+                        "{} has opened ticket #{} since, reply there instead.",
+                        ticket.user_id, other.id
+                    )));
+                }
+                self.table.set_open(id, true)?;
+                self.notify_user(room, ticket, format!("Ticket #{id} has been reopened."))
+                    .await;
+                format!("Reopened ticket #{id}.")
+            }
+            "assign" => {
+                let assignee = if rest.is_empty() {
+                    event.sender.clone()
+                } else {
+                    match UserId::parse(rest) {
+                        Ok(user_id) => user_id,
+                        Err(_) => return Ok(Some(format!("{rest} isn't a user ID."))),
+                    }
+                };
+                self.table.assign(id, Some(assignee.as_str()))?;
+                let content = RoomMessageEventContent::notice_html(
+                    format!("Assigned ticket #{id} to {assignee}."),
+                    format!("Assigned ticket #{id} to {}.", html::user_pill(&assignee)),
+                )
+                .add_mentions(Mentions::with_user_ids([assignee.clone()]));
+                // Said in the thread rather than as a reply, to mention them
+                if let Some(root) = thread_root(ticket) {
+                    send_or_log_error(room, in_thread(content, root)).await;
+                }
+                return Ok(None);
+            }
+            "unassign" => {
+                self.table.assign(id, None)?;
+                format!("Ticket #{id} is unassigned.")
+            }
+            "transcript" => self.transcript(ticket)?,
+            _ => STAFF_HELP.to_owned(),
+        };
+        Ok(Some(response))
+    }
+
+    /// Tell the user something about their ticket in their DM.
+    async fn notify_user(&self, room: &Room, ticket: &Ticket, text: String) {
+        let dm = <&RoomId>::try_from(ticket.dm_room_id.as_str())
+            .ok()
+            .and_then(|room_id| room.client().get_room(room_id))
+            .filter(|dm| dm.state() == RoomState::Joined);
+        if let Some(dm) = dm {
+            send_or_log_error(&dm, RoomMessageEventContent::notice_plain(text)).await;
+        }
+    }
+
+    fn list(&self) -> anyhow::Result<String> {
+        let tickets = self.table.list_open()?;
+        if tickets.is_empty() {
+            return Ok("There are no open tickets.".to_owned());
+        }
+        let now = now();
+        Ok(tickets
+            .iter()
+            .map(|ticket| {
+                let age =
+                    format_duration(Duration::from_secs(now.saturating_sub(ticket.opened_at)));
+                let assignee = ticket
+                    .assignee
+                    .as_deref()
+                    .map(|assignee| format!(", assigned to {assignee}"))
+                    .unwrap_or_default();
+                format!(
+                    "#{} from {}, opened {age} ago{assignee}",
+                    ticket.id, ticket.user_id
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    fn transcript(&self, ticket: &Ticket) -> anyhow::Result<String> {
+        let messages = self.table.transcript(ticket.id)?;
+        let mut lines = vec![format!("Transcript of ticket #{}:", ticket.id)];
+        for message in messages {
+            let time = DateTime::from_timestamp(message.sent_at as i64, 0)
+                .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or_default();
+            let role = if message.from_staff { " (staff)" } else { "" };
+            lines.push(format!(
+                "[{time}] {}{role}: {}",
+                message.sender, message.body
+            ));
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+fn thread_root(ticket: &Ticket) -> Option<OwnedEventId> {
+    let root = ticket.thread_root.as_deref()?;
+    match EventId::parse(root) {
+        Ok(root) => Some(root),
+        Err(err) => {
+            warn!(ticket = ticket.id, "Invalid thread root {root}: {err}");
+            None
+        }
+    }
+}
+
+fn in_thread(mut content: RoomMessageEventContent, root: OwnedEventId) -> RoomMessageEventContent {
+    content.relates_to = Some(Relation::Thread(Thread::plain(root.clone(), root)));
+    content
+}
+
+/// How a staff member signs their replies: their display name in the staff
+/// room, or their user ID.
+async fn staff_name(room: &Room, user_id: &UserId) -> String {
+    match room.get_member_no_sync(user_id).await {
+        Ok(Some(member)) => member
+            .display_name()
+            .map(str::to_owned)
+            .unwrap_or_else(|| user_id.to_string()),
+        _ => user_id.to_string(),
+    }
+}
@@ -0,0 +1,41 @@
+use matrix_bot_core::{can_reply, reply_notice, text_body};
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState,
+};
+use tracing::instrument;
+
+use crate::desk::Helpdesk;
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    helpdesk: Ctx<Helpdesk>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+
+    let is_staff_room = room.room_id() == helpdesk.staff_room;
+    if !is_staff_room && !room.is_direct().await? {
+        return Ok(());
+    }
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        if !is_staff_room {
+            reply_notice(&room, &event, "Sorry, I can only pass on text messages.").await;
+        }
+        return Ok(());
+    };
+
+    if is_staff_room {
+        helpdesk.from_staff(&room, &event, body).await
+    } else {
+        helpdesk.from_user(&room, &event, body).await
+    }
+}
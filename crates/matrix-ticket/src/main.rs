@@ -0,0 +1,54 @@
+mod desk;
+mod handlers;
+mod table;
+
+use clap::Parser;
+use desk::Helpdesk;
+use handlers::on_room_message;
+use matrix_bot_core::{AccountConfig, Bot};
+use matrix_sdk::ruma::OwnedRoomId;
+use table::TicketTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The room tickets are posted to. Everyone in it is staff
+    #[arg(long, env = "TICKET_STAFF_ROOM")]
+    pub staff_room: OwnedRoomId,
+
+    /// Sign replies to users as "Staff" rather than with the staff member's
+    /// name
+    #[arg(long, env = "TICKET_ANONYMOUS")]
+    pub anonymous: bool,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-ticket", config.account_config).await?;
+    let table = TicketTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Helpdesk {
+        table,
+        staff_room: config.staff_room,
+        anonymous: config.anonymous,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
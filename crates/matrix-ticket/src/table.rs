@@ -0,0 +1,209 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension, Row};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE tickets (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        user_id TEXT NOT NULL,
+        dm_room_id TEXT NOT NULL,
+        thread_root TEXT UNIQUE,
+        open INTEGER NOT NULL,
+        assignee TEXT,
+        opened_at INTEGER NOT NULL
+    );
+    CREATE TABLE messages (
+        ticket_id INTEGER NOT NULL REFERENCES tickets (id),
+        sender TEXT NOT NULL,
+        from_staff INTEGER NOT NULL,
+        body TEXT NOT NULL,
+        sent_at INTEGER NOT NULL
+    );"];
+
+/// Tickets and everything said in them, persisted in SQLite.
+#[derive(Clone)]
+pub struct TicketTable {
+    store: Store,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ticket {
+    pub id: i64,
+    pub user_id: String,
+    /// The DM the user opened the ticket from, where staff replies go.
+    pub dm_room_id: String,
+    /// The message in the staff room that the ticket's thread hangs off.
+    pub thread_root: Option<String>,
+    pub open: bool,
+    pub assignee: Option<String>,
+    pub opened_at: u64,
+}
+
+/// A message relayed in a ticket.
+#[derive(Debug)]
+pub struct Message {
+    pub sender: String,
+    pub from_staff: bool,
+    pub body: String,
+    pub sent_at: u64,
+}
+
+const TICKET_COLUMNS: &str = "id, user_id, dm_room_id, thread_root, open, assignee, opened_at";
+
+impl Ticket {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            user_id: row.get(1)?,
+            dm_room_id: row.get(2)?,
+            thread_root: row.get(3)?,
+            open: row.get(4)?,
+            assignee: row.get(5)?,
+            opened_at: row.get(6)?,
+        })
+    }
+}
+
+impl TicketTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Open a ticket, returning its number. Its thread is set once the
+    /// first message is posted to the staff room.
+    pub fn create(&self, user_id: &str, dm_room_id: &str) -> anyhow::Result<i64> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO tickets (user_id, dm_room_id, open, opened_at) VALUES (?1, ?2, 1, ?3)",
+                params![user_id, dm_room_id, now()],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    pub fn set_thread(&self, id: i64, thread_root: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE tickets SET thread_root = ?2 WHERE id = ?1",
+                params![id, thread_root],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// The user's open ticket, if they have one.
+    pub fn open_for_user(&self, user_id: &str) -> anyhow::Result<Option<Ticket>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {TICKET_COLUMNS} FROM tickets WHERE user_id = ?1 AND open
+                        ORDER BY id DESC LIMIT 1"
+                ),
+                [user_id],
+                Ticket::from_row,
+            )
+            .optional()
+        })
+    }
+
+    /// The ticket whose thread starts at `thread_root`.
+    pub fn by_thread(&self, thread_root: &str) -> anyhow::Result<Option<Ticket>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                &format!("SELECT {TICKET_COLUMNS} FROM tickets WHERE thread_root = ?1"),
+                [thread_root],
+                Ticket::from_row,
+            )
+            .optional()
+        })
+    }
+
+    pub fn list_open(&self) -> anyhow::Result<Vec<Ticket>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {TICKET_COLUMNS} FROM tickets WHERE open ORDER BY id"
+            ))?;
+            let tickets = statement
+                .query_map([], Ticket::from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(tickets)
+        })
+    }
+
+    /// Move the user's open ticket to the DM they're writing from now.
+    pub fn set_dm_room(&self, id: i64, dm_room_id: &str) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE tickets SET dm_room_id = ?2 WHERE id = ?1",
+                params![id, dm_room_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn set_open(&self, id: i64, open: bool) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE tickets SET open = ?2 WHERE id = ?1",
+                params![id, open],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn assign(&self, id: i64, assignee: Option<&str>) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "UPDATE tickets SET assignee = ?2 WHERE id = ?1",
+                params![id, assignee],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn add_message(
+        &self,
+        id: i64,
+        sender: &str,
+        from_staff: bool,
+        body: &str,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO messages (ticket_id, sender, from_staff, body, sent_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![id, sender, from_staff, body, now()],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Everything relayed in a ticket, oldest first.
+    pub fn transcript(&self, id: i64) -> anyhow::Result<Vec<Message>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT sender, from_staff, body, sent_at FROM messages
+                WHERE ticket_id = ?1 ORDER BY rowid",
+            )?;
+            let messages = statement
+                .query_map([id], |row| {
+                    Ok(Message {
+                        sender: row.get(0)?,
+                        from_staff: row.get(1)?,
+                        body: row.get(2)?,
+                        sent_at: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(messages)
+        })
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use matrix_bot_core::parse_duration;
+
+/// The time of day a task is due when only a date is given.
+const DEFAULT_TIME: NaiveTime = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+
+const USAGE: &str =
+    "I don't understand that due date. Try `tomorrow`, `friday 17:00`, `2025-06-01` or `3d`.";
+
+/// Split a trailing `due <when>` off a task's text.
+pub fn split_due(text: &str) -> (&str, Option<&str>) {
+    match text.rfind(" due ") {
+        Some(index) => (text[..index].trim(), Some(text[index + 5..].trim())),
+        None => (text, None),
+    }
+}
+
+/// Parse when a task is due: a day such as `tomorrow`, `friday` or
+/// `2025-06-01`, optionally followed by a time such as `17:00`, or a
+/// duration from now such as `3d`.
+pub fn parse_due(input: &str, now: DateTime<Utc>, tz: Tz) -> anyhow::Result<DateTime<Utc>> {
+    let input = input.trim().to_lowercase();
+    if let Some(duration) = parse_duration(&input) {
+        return chrono::Duration::from_std(duration)
+            .ok()
+            .and_then(|wait| now.checked_add_signed(wait))
+            .ok_or_else(|| anyhow!("That due date is too far in the future."));
+    }
+
+    let (day, time) = input
+        .split_once(char::is_whitespace)
+        .unwrap_or((&input, ""));
+    let time = match time.trim() {
+        "" => DEFAULT_TIME,
+        time => match NaiveTime::parse_from_str(time, "%H:%M") {
+            Ok(time) => time,
+            Err(_) => bail!(USAGE),
+        },
+    };
+
+    let today = now.with_timezone(&tz).date_naive();
+    let date = match day {
+        "today" => today,
+        "tomorrow" => today + Days::new(1),
+        day => {
+            if let Ok(weekday) = Weekday::from_str(day) {
+                // The next such day, or a week today when it's today
+                let ahead = (weekday.num_days_from_monday() + 7
+                    - today.weekday().num_days_from_monday())
+                    % 7;
+                today + Days::new(if ahead == 0 { 7 } else { ahead.into() })
+            } else if let Ok(date) = NaiveDate::parse_from_str(day, "%Y-%m-%d") {
+                date
+            } else {
+                bail!(USAGE);
+            }
+        }
+    };
+
+    let local = date.and_time(time);
+    // Times skipped by a DST change are due at the equivalent UTC time instead
+    Ok(tz
+        .from_local_datetime(&local)
+        .earliest()
+        .map(|due| due.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&local)))
+}
@@ -0,0 +1,237 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use matrix_bot_core::{can_reply, is_moderator, media, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{events::room::message::OriginalSyncRoomMessageEvent, OwnedUserId, UserId},
+    Room, RoomState,
+};
+use tokio::sync::Notify;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    due::{parse_due, split_due},
+    render,
+    table::TaskTable,
+};
+
+const HELP: &str = "Usage:
+!todo add <task> [due tomorrow|friday 17:00|2025-06-01|3d], mention someone to assign it to them
+!todo list [all], !todo mine
+!todo done <number>, !todo undo <number>
+!todo assign <number> <@user or me>, !todo unassign <number>
+!todo due <number> <when or none>
+!todo remove <number>
+!todo export";
+
+#[derive(Clone)]
+pub struct Todos {
+    pub table: TaskTable,
+    /// Wakes the scheduler when a due date is set.
+    pub wake: Arc<Notify>,
+    pub timezone: Tz,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    todos: Ctx<Todos>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!todo")) else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+
+    if subcommand == "export" {
+        export(&room, &event, &todos).await?;
+        return Ok(());
+    }
+    let response = match command(subcommand, rest, &event, &room, &todos).await {
+        Ok(response) => response,
+        Err(err) => err.to_string(),
+    };
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn command(
+    subcommand: &str,
+    rest: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    todos: &Todos,
+) -> anyhow::Result<String> {
+    let table = &todos.table;
+    let room_id = room.room_id().as_str();
+    let tz = todos.timezone;
+
+    Ok(match subcommand {
+        "add" => {
+            let (text, due) = split_due(rest);
+            if text.is_empty() {
+                return Ok(
+                    "What needs doing? e.g. `!todo add water the plants due friday`".to_owned(),
+                );
+            }
+            let due_at = due.map(|due| parse_due(due, Utc::now(), tz)).transpose()?;
+            let assignee = mentioned_user(event, room, rest);
+            let number = table.add(
+                room_id,
+                text,
+                event.sender.as_str(),
+                assignee.as_ref().map(|assignee| assignee.as_str()),
+                due_at,
+            )?;
+            if due_at.is_some() {
+                todos.wake.notify_one();
+            }
+            info!(number, "Added task");
+            format!("Added task #{number}.")
+        }
+        "" | "list" | "mine" => {
+            let include_done = rest == "all";
+            let mut tasks = table.list(room_id, include_done)?;
+            if subcommand == "mine" {
+                tasks.retain(|task| task.owner() == event.sender.as_str());
+            }
+            if tasks.is_empty() {
+                "Nothing to do!".to_owned()
+            } else {
+                tasks
+                    .iter()
+                    .map(|task| render::line(task, tz))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+        "done" | "undo" => {
+            let number = parse_number(rest)?;
+            if table.set_done(room_id, number, subcommand == "done")? {
+                if subcommand == "done" {
+                    format!("Done with task #{number}. 🎉")
+                } else {
+                    format!("Reopened task #{number}.")
+                }
+            } else {
+                format!("There's no task #{number}.")
+            }
+        }
+        "assign" | "unassign" => {
+            let (number, who) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let number = parse_number(number)?;
+            let assignee = match (subcommand, who.trim()) {
+                ("unassign", _) => None,
+                (_, "me") => Some(event.sender.clone()),
+                (_, who) => Some(mentioned_user(event, room, who).ok_or_else(|| {
+                    anyhow::anyhow!("Who should I assign it to? Mention them, or say `me`.")
+                })?),
+            };
+            if !table.assign(room_id, number, assignee.as_deref().map(UserId::as_str))? {
+                format!("There's no task #{number}.")
+            } else if let Some(assignee) = assignee {
+                format!("Assigned task #{number} to {assignee}.")
+            } else {
+                format!("Unassigned task #{number}.")
+            }
+        }
+        "due" => {
+            let (number, when) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let number = parse_number(number)?;
+            let due_at = match when.trim() {
+                "" => anyhow::bail!("When is it due? e.g. `!todo due {number} friday`"),
+                "none" | "never" => None,
+                when => Some(parse_due(when, Utc::now(), tz)?),
+            };
+            if !table.set_due(room_id, number, due_at)? {
+                format!("There's no task #{number}.")
+            } else if let Some(due_at) = due_at {
+                todos.wake.notify_one();
+                format!(
+                    "Task #{number} is now due {}.",
+                    due_at.with_timezone(&tz).format("%a %-d %b %H:%M")
+                )
+            } else {
+                format!("Task #{number} no longer has a due date.")
+            }
+        }
+        "remove" | "delete" => {
+            let number = parse_number(rest)?;
+            match table.get(room_id, number)? {
+                None => format!("There's no task #{number}."),
+                Some(task)
+                    if task.creator != event.sender.as_str()
+                        && !is_moderator(room, &event.sender).await? =>
+                {
+                    "Only the task's creator or a moderator can remove it.".to_owned()
+                }
+                Some(_) => {
+                    table.remove(room_id, number)?;
+                    format!("Removed task #{number}.")
+                }
+            }
+        }
+        _ => HELP.to_owned(),
+    })
+}
+
+/// Send the room's tasks as a Markdown file.
+async fn export(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    todos: &Todos,
+) -> anyhow::Result<()> {
+    let tasks = todos.table.list(room.room_id().as_str(), true)?;
+    if tasks.is_empty() {
+        reply_notice(room, event, "There are no tasks to export.").await;
+        return Ok(());
+    }
+    let room_name = room.name().unwrap_or_else(|| room.room_id().to_string());
+    let markdown = render::markdown(&room_name, &tasks, todos.timezone);
+    let content_type: mime::Mime = "text/markdown; charset=utf-8".parse()?;
+    if let Err(err) = media::send_file(room, "todo.md", &content_type, markdown.into_bytes()).await
+    {
+        warn!("Failed to send task export: {err:#}");
+        reply_notice(room, event, "Sorry, I couldn't export the tasks.").await;
+    }
+    Ok(())
+}
+
+/// The user mentioned in a message, preferring its intentional mentions and
+/// falling back to a user ID typed in `text`. The bot itself doesn't count.
+fn mentioned_user(
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    text: &str,
+) -> Option<OwnedUserId> {
+    let own_user_id = room.client().user_id().map(ToOwned::to_owned);
+    let mentioned = event
+        .content
+        .mentions
+        .iter()
+        .flat_map(|mentions| mentions.user_ids.iter().cloned());
+    let typed = text
+        .split_whitespace()
+        .filter_map(|word| UserId::parse(word.trim_end_matches([',', '.', ':', ';'])).ok());
+    mentioned
+        .chain(typed)
+        .find(|user_id| Some(user_id) != own_user_id.as_ref())
+}
+
+fn parse_number(number: &str) -> anyhow::Result<i64> {
+    number
+        .trim()
+        .trim_start_matches('#')
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Which task? Give its number from `!todo list`."))
+}
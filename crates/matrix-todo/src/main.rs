@@ -0,0 +1,63 @@
+mod due;
+mod handlers;
+mod render;
+mod scheduler;
+mod table;
+
+use std::sync::Arc;
+
+use chrono_tz::Tz;
+use clap::Parser;
+use handlers::{on_room_message, Todos};
+use matrix_bot_core::{AccountConfig, Bot};
+use table::TaskTable;
+use tokio::sync::Notify;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Time zone used to interpret due dates like `friday 17:00`, e.g.
+    /// `Europe/Berlin`
+    #[arg(long, default_value_t = Tz::UTC, env = "TODO_TIMEZONE")]
+    pub timezone: Tz,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-todo", config.account_config).await?;
+    let table = TaskTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    let todos = Todos {
+        table,
+        wake: Arc::new(Notify::new()),
+        timezone: config.timezone,
+    };
+    tokio::spawn(matrix_bot_core::scheduler::run(
+        scheduler::Scheduler {
+            client: bot.client().clone(),
+            table: todos.table.clone(),
+        },
+        todos.wake.clone(),
+    ));
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(todos);
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,48 @@
+use chrono_tz::Tz;
+
+use crate::table::Task;
+
+/// A task as a line of `!todo list`.
+pub fn line(task: &Task, tz: Tz) -> String {
+    let mut line = format!(
+        "{} #{} {}",
+        if task.done { "☑" } else { "☐" },
+        task.number,
+        task.text
+    );
+    line.push_str(&details(task, tz, "%a %-d %b %H:%M"));
+    line
+}
+
+/// A room's tasks as a Markdown checklist.
+pub fn markdown(room_name: &str, tasks: &[Task], tz: Tz) -> String {
+    let mut markdown = format!("# Tasks for {room_name}\n\n");
+    for task in tasks {
+        markdown.push_str(&format!(
+            "- [{}] {}{}\n",
+            if task.done { "x" } else { " " },
+            task.text,
+            details(task, tz, "%Y-%m-%d %H:%M")
+        ));
+    }
+    markdown
+}
+
+/// Who a task is assigned to and when it's due, if anyone or anytime.
+fn details(task: &Task, tz: Tz, date_format: &str) -> String {
+    let mut details = Vec::new();
+    if let Some(assignee) = &task.assignee {
+        details.push(assignee.clone());
+    }
+    if let Some(due_at) = task.due_at {
+        details.push(format!(
+            "due {}",
+            due_at.with_timezone(&tz).format(date_format)
+        ));
+    }
+    if details.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", details.join(", "))
+    }
+}
@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use matrix_bot_core::{can_reply, html, scheduler::Jobs, send_or_log_error};
+use matrix_sdk::{
+    ruma::{
+        events::{room::message::RoomMessageEventContent, Mentions},
+        RoomId, UserId,
+    },
+    Client, RoomState,
+};
+use tracing::{error, info, instrument, warn};
+
+use crate::table::{Task, TaskTable};
+
+/// Tasks with due dates, whose owners are pinged as they come due.
+pub struct Scheduler {
+    pub client: Client,
+    pub table: TaskTable,
+}
+
+impl Jobs for Scheduler {
+    fn next_due(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.table.next_due()
+    }
+
+    async fn fire_due(&self, now: DateTime<Utc>) {
+        let tasks = match self.table.due(now) {
+            Ok(tasks) => tasks,
+            Err(err) => {
+                error!("Failed to load due tasks: {err}");
+                return;
+            }
+        };
+        for task in tasks {
+            remind(&self.client, &task).await;
+            // Reminders are only tried once, so a room the bot can't
+            // post in doesn't get retried forever
+            if let Err(err) = self.table.mark_reminded(&task.room_id, task.number) {
+                error!(
+                    number = task.number,
+                    "Failed to mark task as reminded: {err}"
+                );
+            }
+        }
+    }
+}
+
+#[instrument(skip_all, fields(number = task.number, room = task.room_id.as_str()))]
+async fn remind(client: &Client, task: &Task) {
+    let Ok(room_id) = <&RoomId>::try_from(task.room_id.as_str()) else {
+        return;
+    };
+    let Some(room) = client.get_room(room_id) else {
+        warn!("Not in the room for this task, not reminding");
+        return;
+    };
+    if room.state() != RoomState::Joined || !can_reply(&room).await {
+        warn!("Can't post in the room for this task, not reminding");
+        return;
+    }
+
+    let text = format!("⏰ Task #{} is due: {}", task.number, task.text);
+    let formatted = format!(
+        "⏰ Task #{} is due: {}",
+        task.number,
+        html::escape(&task.text)
+    );
+    let content = match <&UserId>::try_from(task.owner()) {
+        Ok(owner) => RoomMessageEventContent::text_html(
+            format!("{owner}: {text}"),
+            format!("{}: {formatted}", html::user_pill(owner)),
+        )
+        .add_mentions(Mentions::with_user_ids([owner.to_owned()])),
+        Err(_) => RoomMessageEventContent::text_html(text, formatted),
+    };
+
+    info!("Sending due task reminder");
+    send_or_log_error(&room, content).await;
+}
@@ -0,0 +1,210 @@
+use chrono::{DateTime, Utc};
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension, Row};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE tasks (
+        room_id TEXT NOT NULL,
+        number INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        creator TEXT NOT NULL,
+        assignee TEXT,
+        due_at INTEGER,
+        reminded INTEGER NOT NULL DEFAULT 0,
+        done INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (room_id, number)
+    );
+    CREATE INDEX tasks_due ON tasks (done, reminded, due_at);"];
+
+/// A task on a room's list.
+#[derive(Debug)]
+pub struct Task {
+    pub room_id: String,
+    /// The task's number within its room.
+    pub number: i64,
+    pub text: String,
+    pub creator: String,
+    pub assignee: Option<String>,
+    pub due_at: Option<DateTime<Utc>>,
+    pub done: bool,
+}
+
+impl Task {
+    /// Who to ping about the task.
+    pub fn owner(&self) -> &str {
+        self.assignee.as_deref().unwrap_or(&self.creator)
+    }
+}
+
+/// Each room's tasks, persisted in SQLite.
+#[derive(Clone)]
+pub struct TaskTable {
+    store: Store,
+}
+
+const COLUMNS: &str = "room_id, number, text, creator, assignee, due_at, done";
+
+impl TaskTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    /// Add a task, returning its number.
+    pub fn add(
+        &self,
+        room_id: &str,
+        text: &str,
+        creator: &str,
+        assignee: Option<&str>,
+        due_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<i64> {
+        self.store.with(|conn| {
+            let number: i64 = conn.query_row(
+                "INSERT INTO tasks (room_id, number, text, creator, assignee, due_at)
+                VALUES (
+                    ?1,
+                    (SELECT COALESCE(MAX(number), 0) + 1 FROM tasks WHERE room_id = ?1),
+                    ?2, ?3, ?4, ?5
+                )
+                RETURNING number",
+                params![
+                    room_id,
+                    text,
+                    creator,
+                    assignee,
+                    due_at.map(|due_at| due_at.timestamp())
+                ],
+                |row| row.get(0),
+            )?;
+            Ok(number)
+        })
+    }
+
+    pub fn get(&self, room_id: &str, number: i64) -> anyhow::Result<Option<Task>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                &format!("SELECT {COLUMNS} FROM tasks WHERE room_id = ?1 AND number = ?2"),
+                params![room_id, number],
+                from_row,
+            )
+            .optional()
+        })
+    }
+
+    /// A room's tasks, open ones first, in order of when they're due.
+    pub fn list(&self, room_id: &str, include_done: bool) -> anyhow::Result<Vec<Task>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {COLUMNS} FROM tasks WHERE room_id = ?1 AND (?2 OR NOT done)
+                ORDER BY done, due_at IS NULL, due_at, number"
+            ))?;
+            let tasks = statement
+                .query_map(params![room_id, include_done], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(tasks)
+        })
+    }
+
+    /// Returns whether the task exists.
+    pub fn set_done(&self, room_id: &str, number: i64, done: bool) -> anyhow::Result<bool> {
+        self.update(room_id, number, "done = ?3", done)
+    }
+
+    /// Returns whether the task exists.
+    pub fn assign(
+        &self,
+        room_id: &str,
+        number: i64,
+        assignee: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        self.update(room_id, number, "assignee = ?3", assignee)
+    }
+
+    /// Returns whether the task exists.
+    pub fn set_due(
+        &self,
+        room_id: &str,
+        number: i64,
+        due_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<bool> {
+        // A new due date gets a new reminder
+        self.update(
+            room_id,
+            number,
+            "due_at = ?3, reminded = 0",
+            due_at.map(|due_at| due_at.timestamp()),
+        )
+    }
+
+    fn update(
+        &self,
+        room_id: &str,
+        number: i64,
+        set: &str,
+        value: impl rusqlite::ToSql,
+    ) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let updated = conn.execute(
+                &format!("UPDATE tasks SET {set} WHERE room_id = ?1 AND number = ?2"),
+                params![room_id, number, value],
+            )?;
+            Ok(updated > 0)
+        })
+    }
+
+    /// Returns whether the task existed.
+    pub fn remove(&self, room_id: &str, number: i64) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM tasks WHERE room_id = ?1 AND number = ?2",
+                params![room_id, number],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+
+    /// Open tasks that have come due without a reminder being sent.
+    pub fn due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<Task>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {COLUMNS} FROM tasks
+                WHERE NOT done AND NOT reminded AND due_at <= ?1 ORDER BY due_at"
+            ))?;
+            let tasks = statement
+                .query_map(params![now.timestamp()], from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(tasks)
+        })
+    }
+
+    /// When the next reminder needs sending.
+    pub fn next_due(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.store.with(|conn| {
+            let due_at: Option<i64> = conn.query_row(
+                "SELECT MIN(due_at) FROM tasks WHERE NOT done AND NOT reminded",
+                [],
+                |row| row.get(0),
+            )?;
+            Ok(due_at.and_then(|due_at| DateTime::from_timestamp(due_at, 0)))
+        })
+    }
+
+    pub fn mark_reminded(&self, room_id: &str, number: i64) -> anyhow::Result<()> {
+        self.update(room_id, number, "reminded = ?3", true)?;
+        Ok(())
+    }
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Task> {
+    let due_at: Option<i64> = row.get(5)?;
+    Ok(Task {
+        room_id: row.get(0)?,
+        number: row.get(1)?,
+        text: row.get(2)?,
+        creator: row.get(3)?,
+        assignee: row.get(4)?,
+        due_at: due_at.and_then(|due_at| DateTime::from_timestamp(due_at, 0)),
+        done: row.get(6)?,
+    })
+}
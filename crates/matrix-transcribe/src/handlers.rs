@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use matrix_bot_core::{
+    can_reply, fetch_message, format_duration, is_moderator, media::Attachment, reply_notice,
+    reply_target, send_or_log_error, store::Store, strip_command, text_body, wizard::Setting,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::room::message::{
+            AddMentions, AudioMessageEventContent, MessageType, OriginalSyncRoomMessageEvent,
+            ReplyWithinThread, RoomMessageEventContent,
+        },
+        RoomId,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, instrument, warn};
+
+use crate::provider::{AnyProvider, Provider};
+
+const HELP: &str = "I transcribe voice messages in rooms that turn me on.
+!transcribe on / !transcribe off to transcribe every voice message here (moderators only)
+!transcribe in reply to a voice message to transcribe just that one";
+
+/// Whether a room has opted in to transcribing every voice message.
+const ENABLED: Setting = Setting::toggle("enabled", "Transcribe voice messages", false);
+
+#[derive(Clone)]
+pub struct Transcriber {
+    pub provider: AnyProvider,
+    pub store: Store,
+    /// The biggest audio file to download, in bytes.
+    pub max_size: u64,
+    /// The longest audio to transcribe.
+    pub max_duration: Duration,
+}
+
+impl Transcriber {
+    /// Why an audio message is too big or long to transcribe, if it is.
+    fn over_limits(&self, audio: &AudioMessageEventContent) -> Option<String> {
+        let info = audio.info.as_deref()?;
+        if info
+            .size
+            .is_some_and(|size| u64::from(size) > self.max_size)
+        {
+            return Some(format!(
+                "That's too big, I only transcribe files up to {} MB.",
+                self.max_size / 1_000_000
+            ));
+        }
+        if info
+            .duration
+            .is_some_and(|duration| duration > self.max_duration)
+        {
+            return Some(format!(
+                "That's too long, I only transcribe up to {}.",
+                format_duration(self.max_duration)
+            ));
+        }
+        None
+    }
+
+    async fn transcribe(
+        &self,
+        room: &Room,
+        audio: &AudioMessageEventContent,
+    ) -> anyhow::Result<String> {
+        let attachment = Attachment::from_message(&MessageType::Audio(audio.clone()))
+            .context("not an attachment")?;
+        let data = attachment.download(&room.client(), self.max_size).await?;
+
+        // Voice messages are Ogg, when neither the file nor the sender says
+        let mime = attachment.mime(&data);
+        let mimetype = match mime.essence_str() {
+            "application/octet-stream" => "audio/ogg",
+            mimetype => mimetype,
+        };
+        let filename = upload_name(&attachment.filename, mimetype);
+        self.provider.transcribe(data, &filename, mimetype).await
+    }
+
+    /// Transcribe a voice message and send the transcript in its thread.
+    async fn reply_with_transcript(
+        &self,
+        room: &Room,
+        event: &OriginalSyncRoomMessageEvent,
+        audio: &AudioMessageEventContent,
+    ) -> anyhow::Result<()> {
+        let transcript = self.transcribe(room, audio).await?;
+        let text = if transcript.is_empty() {
+            "(no speech)".to_owned()
+        } else {
+            format!("📝 {transcript}")
+        };
+        let original = event.clone().into_full_event(room.room_id().to_owned());
+        let content = RoomMessageEventContent::notice_plain(text).make_for_thread(
+            &original,
+            ReplyWithinThread::No,
+            AddMentions::No,
+        );
+        send_or_log_error(room, content).await;
+        Ok(())
+    }
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    transcriber: Ctx<Transcriber>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+
+    if let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!transcribe")) {
+        if can_reply(&room).await {
+            command(args, &event, &room, &transcriber).await?;
+        }
+        return Ok(());
+    }
+
+    let MessageType::Audio(audio) = &event.content.msgtype else {
+        return Ok(());
+    };
+    if !ENABLED.enabled(&transcriber.store, room.room_id())? || !can_reply(&room).await {
+        return Ok(());
+    }
+    if let Some(reason) = transcriber.over_limits(audio) {
+        debug!("Not transcribing: {reason}");
+        return Ok(());
+    }
+    if let Err(err) = transcriber
+        .reply_with_transcript(&room, &event, audio)
+        .await
+    {
+        warn!("Failed to transcribe a voice message: {err}");
+    }
+    Ok(())
+}
+
+async fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    transcriber: &Transcriber,
+) -> anyhow::Result<()> {
+    let room_id = room.room_id();
+    let response = match args {
+        "on" | "off" => {
+            if !is_moderator(room, &event.sender).await? {
+                "Only moderators can change that.".to_owned()
+            } else {
+                let enabled = args == "on";
+                match (set_enabled(&transcriber.store, room_id, enabled)?, enabled) {
+                    (true, true) => "I'll transcribe voice messages here from now on.",
+                    (true, false) => "I won't transcribe voice messages here any more.",
+                    (false, true) => "I'm already transcribing voice messages here.",
+                    (false, false) => "I'm not transcribing voice messages here.",
+                }
+                .to_owned()
+            }
+        }
+        "" => match on_demand(event, room, transcriber).await? {
+            Some(response) => response,
+            None => return Ok(()),
+        },
+        _ => HELP.to_owned(),
+    };
+    reply_notice(room, event, response).await;
+    Ok(())
+}
+
+/// Transcribe the voice message `event` replies to, returning what to tell
+/// the sender if that didn't work.
+async fn on_demand(
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    transcriber: &Transcriber,
+) -> anyhow::Result<Option<String>> {
+    let Some(target) = reply_target(event) else {
+        return Ok(Some(HELP.to_owned()));
+    };
+    let Some(target) = fetch_message(room, target).await? else {
+        return Ok(Some("I couldn't find that message.".to_owned()));
+    };
+    let MessageType::Audio(audio) = &target.content.msgtype else {
+        return Ok(Some("That isn't a voice message.".to_owned()));
+    };
+    if let Some(reason) = transcriber.over_limits(audio) {
+        return Ok(Some(reason));
+    }
+    if let Err(err) = transcriber
+        .reply_with_transcript(room, &target, audio)
+        .await
+    {
+        warn!("Failed to transcribe a voice message: {err}");
+        return Ok(Some("Sorry, I couldn't transcribe that.".to_owned()));
+    }
+    Ok(None)
+}
+
+/// A file name for the audio with an extension that matches its type, as
+/// some services go by the extension.
+fn upload_name(name: &str, mimetype: &str) -> String {
+    let extension = match mimetype.split(';').next().unwrap_or_default().trim() {
+        "audio/ogg" | "audio/opus" => "ogg",
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/mp4" | "audio/x-m4a" | "audio/aac" => "m4a",
+        "audio/webm" => "webm",
+        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+        "audio/flac" | "audio/x-flac" => "flac",
+        _ => return name.to_owned(),
+    };
+    match name.rsplit_once('.') {
+        Some((_, existing)) if existing.eq_ignore_ascii_case(extension) => name.to_owned(),
+        _ => format!("{name}.{extension}"),
+    }
+}
+
+/// Turn transcription on or off in a room, returning whether that changed
+/// anything.
+fn set_enabled(store: &Store, room_id: &RoomId, enabled: bool) -> anyhow::Result<bool> {
+    if ENABLED.enabled(store, room_id)? == enabled {
+        return Ok(false);
+    }
+    store.set_room_setting(room_id, ENABLED.key, &enabled)?;
+    Ok(true)
+}
@@ -0,0 +1,111 @@
+mod handlers;
+mod provider;
+
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+use handlers::{on_room_message, Transcriber};
+use matrix_bot_core::{AccountConfig, Bot};
+use provider::{AnyProvider, Api, ProviderKind, WhisperCpp};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// How to transcribe audio
+    #[arg(long, value_enum, default_value = "api", env = "TRANSCRIBE_PROVIDER")]
+    pub provider: ProviderKind,
+
+    /// The transcription API's base URL
+    #[arg(
+        long,
+        default_value = "https://api.openai.com/v1",
+        env = "TRANSCRIBE_API_URL"
+    )]
+    pub api_url: String,
+
+    /// The API key, if the API needs one
+    #[arg(long, env = "TRANSCRIBE_API_KEY")]
+    pub api_key: Option<String>,
+
+    /// The model for the API to use
+    #[arg(long, default_value = "whisper-1", env = "TRANSCRIBE_MODEL")]
+    pub model: String,
+
+    /// The whisper.cpp program to run with the `whisper-cpp` provider
+    #[arg(long, default_value = "whisper-cli", env = "TRANSCRIBE_WHISPER_BINARY")]
+    pub whisper_binary: PathBuf,
+
+    /// The ggml model file for whisper.cpp
+    #[arg(
+        long,
+        required_if_eq("provider", "whisper-cpp"),
+        env = "TRANSCRIBE_WHISPER_MODEL"
+    )]
+    pub whisper_model: Option<PathBuf>,
+
+    /// ffmpeg, to convert audio for whisper.cpp
+    #[arg(long, default_value = "ffmpeg", env = "TRANSCRIBE_FFMPEG")]
+    pub ffmpeg: PathBuf,
+
+    /// The biggest voice message to transcribe, in megabytes
+    #[arg(long, default_value_t = 10, env = "TRANSCRIBE_MAX_SIZE_MB")]
+    pub max_size_mb: u64,
+
+    /// The longest voice message to transcribe
+    #[arg(long, default_value = "5m", value_parser = parse_max_duration, env = "TRANSCRIBE_MAX_DURATION")]
+    pub max_duration: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_max_duration(duration: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(duration).ok_or_else(|| format!("invalid duration: {duration}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let provider = match config.provider {
+        ProviderKind::Api => AnyProvider::Api(Api {
+            http: reqwest::Client::builder()
+                .user_agent(concat!("matrix-transcribe/", env!("CARGO_PKG_VERSION")))
+                .timeout(Duration::from_secs(300))
+                .build()?,
+            api_url: config.api_url,
+            api_key: config.api_key,
+            model: config.model,
+        }),
+        ProviderKind::WhisperCpp => AnyProvider::WhisperCpp(WhisperCpp {
+            binary: config.whisper_binary,
+            // Required by clap with this provider
+            model: config.whisper_model.unwrap_or_default(),
+            ffmpeg: config.ffmpeg,
+        }),
+    };
+
+    let mut bot = Bot::login("matrix-transcribe", config.account_config).await?;
+    let store = bot.open_store(&[]).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Transcriber {
+        provider,
+        store,
+        max_size: config.max_size_mb * 1_000_000,
+        max_duration: config.max_duration,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,154 @@
+use std::{future::Future, path::PathBuf, process::Stdio};
+
+use anyhow::ensure;
+use clap::ValueEnum;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use tokio::process::Command;
+
+/// A speech-to-text service.
+pub trait Provider {
+    /// Transcribe an audio file, returning what was said.
+    fn transcribe(
+        &self,
+        audio: Vec<u8>,
+        filename: &str,
+        mimetype: &str,
+    ) -> impl Future<Output = anyhow::Result<String>> + Send;
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ProviderKind {
+    /// OpenAI's transcription API, or anything compatible with it
+    Api,
+    /// A local whisper.cpp binary
+    WhisperCpp,
+}
+
+/// One of the supported providers, picked at startup.
+#[derive(Clone)]
+pub enum AnyProvider {
+    Api(Api),
+    WhisperCpp(WhisperCpp),
+}
+
+impl Provider for AnyProvider {
+    async fn transcribe(
+        &self,
+        audio: Vec<u8>,
+        filename: &str,
+        mimetype: &str,
+    ) -> anyhow::Result<String> {
+        match self {
+            Self::Api(provider) => provider.transcribe(audio, filename, mimetype).await,
+            Self::WhisperCpp(provider) => provider.transcribe(audio, filename, mimetype).await,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Api {
+    pub http: reqwest::Client,
+    pub api_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+#[derive(Deserialize)]
+struct Transcription {
+    text: String,
+}
+
+impl Provider for Api {
+    async fn transcribe(
+        &self,
+        audio: Vec<u8>,
+        filename: &str,
+        mimetype: &str,
+    ) -> anyhow::Result<String> {
+        let file = Part::bytes(audio)
+            .file_name(filename.to_owned())
+            .mime_str(mimetype)?;
+        let form = Form::new()
+            .text("model", self.model.clone())
+            .part("file", file);
+        let mut request = self
+            .http
+            .post(format!(
+                "{}/audio/transcriptions",
+                self.api_url.trim_end_matches('/')
+            ))
+            .multipart(form);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let transcription: Transcription = request.send().await?.error_for_status()?.json().await?;
+        Ok(transcription.text.trim().to_owned())
+    }
+}
+
+#[derive(Clone)]
+pub struct WhisperCpp {
+    /// The whisper.cpp command line program.
+    pub binary: PathBuf,
+    /// The ggml model file for it to use.
+    pub model: PathBuf,
+    /// ffmpeg, to convert audio to the WAV that whisper.cpp wants.
+    pub ffmpeg: PathBuf,
+}
+
+impl Provider for WhisperCpp {
+    async fn transcribe(
+        &self,
+        audio: Vec<u8>,
+        _filename: &str,
+        _mimetype: &str,
+    ) -> anyhow::Result<String> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input");
+        let wav = dir.path().join("input.wav");
+        tokio::fs::write(&input, audio).await?;
+
+        // whisper.cpp only reads 16kHz mono WAV
+        let output = Command::new(&self.ffmpeg)
+            .args(["-nostdin", "-loglevel", "error", "-i"])
+            .arg(&input)
+            .args(["-ar", "16000", "-ac", "1", "-c:a", "pcm_s16le"])
+            .arg(&wav)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        ensure!(
+            output.status.success(),
+            "ffmpeg failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+
+        let output = Command::new(&self.binary)
+            .arg("--model")
+            .arg(&self.model)
+            .args(["--language", "auto", "--no-timestamps", "--no-prints"])
+            .arg("--file")
+            .arg(&wav)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        ensure!(
+            output.status.success(),
+            "whisper.cpp failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+
+        // One line per segment
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+}
@@ -0,0 +1,63 @@
+/// The language to translate into for a flag emoji reaction, such as `de`
+/// for 🇩🇪 or 🇦🇹.
+///
+/// Countries with more than one major language get the most widely spoken
+/// one.
+pub fn flag_language(emoji: &str) -> Option<&'static str> {
+    let mut letters = emoji.chars().map(|c| {
+        let offset = (c as u32).checked_sub('🇦' as u32)?;
+        (offset < 26).then(|| (b'A' + offset as u8) as char)
+    });
+    let country: String = [letters.next()??, letters.next()??].into_iter().collect();
+    if letters.next().is_some() {
+        return None;
+    }
+
+    Some(match country.as_str() {
+        "GB" | "US" | "AU" | "NZ" | "IE" | "CA" => "en",
+        "DE" | "AT" | "CH" | "LI" => "de",
+        "FR" | "MC" => "fr",
+        "ES" | "MX" | "AR" | "CO" | "CL" | "PE" | "VE" | "CU" => "es",
+        "IT" | "SM" => "it",
+        "PT" | "BR" | "AO" | "MZ" => "pt",
+        "NL" | "BE" | "SR" => "nl",
+        "JP" => "ja",
+        "CN" | "TW" | "HK" => "zh",
+        "KR" => "ko",
+        "RU" => "ru",
+        "UA" => "uk",
+        "PL" => "pl",
+        "CZ" => "cs",
+        "SK" => "sk",
+        "SI" => "sl",
+        "HU" => "hu",
+        "RO" | "MD" => "ro",
+        "BG" => "bg",
+        "GR" | "CY" => "el",
+        "TR" => "tr",
+        "SE" => "sv",
+        "NO" => "nb",
+        "DK" => "da",
+        "FI" => "fi",
+        "EE" => "et",
+        "LV" => "lv",
+        "LT" => "lt",
+        "IL" => "he",
+        "SA" | "EG" | "AE" | "MA" | "DZ" | "IQ" | "JO" => "ar",
+        "IR" => "fa",
+        "IN" => "hi",
+        "ID" => "id",
+        "VN" => "vi",
+        "TH" => "th",
+        _ => return None,
+    })
+}
+
+/// Whether `code` looks like a language code, such as `de` or `pt-BR`.
+pub fn is_language_code(code: &str) -> bool {
+    let (language, region) = code.split_once('-').unwrap_or((code, ""));
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && (region.is_empty()
+            || ((2..=4).contains(&region.len()) && region.chars().all(|c| c.is_ascii_alphabetic())))
+}
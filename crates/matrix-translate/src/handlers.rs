@@ -0,0 +1,202 @@
+use matrix_bot_core::{
+    can_reply, fetch_message, is_moderator, reply_notice, reply_target, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::{
+        reaction::OriginalSyncReactionEvent, room::message::OriginalSyncRoomMessageEvent,
+    },
+    Room, RoomState,
+};
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    flags::{flag_language, is_language_code},
+    provider::{AnyProvider, Provider, Translation},
+    table::TranslationTable,
+};
+
+const HELP: &str = "Usage:
+!translate [language] in reply to a message, e.g. !translate de
+!translate <language> <text>
+React to a message with a flag, e.g. 🇫🇷, to translate it
+!translate default <language> / !translate default off to set this room's default language (moderators only)";
+
+#[derive(Clone)]
+pub struct Translator {
+    pub provider: AnyProvider,
+    pub table: TranslationTable,
+    /// The language to translate into when neither the command nor the room
+    /// says.
+    pub default_language: String,
+}
+
+impl Translator {
+    fn target_language(&self, room: &Room) -> anyhow::Result<String> {
+        Ok(self
+            .table
+            .room_language(room.room_id().as_str())?
+            .unwrap_or_else(|| self.default_language.clone()))
+    }
+
+    /// Translate `text`, using the cache where possible.
+    async fn translate(&self, text: &str, target: &str) -> anyhow::Result<Translation> {
+        let provider = self.provider.name();
+        if let Some(translation) = self.table.cached(provider, target, text)? {
+            debug!(target, "Using cached translation");
+            return Ok(translation);
+        }
+        let translation = self.provider.translate(text, target).await?;
+        self.table.cache(provider, target, text, &translation)?;
+        Ok(translation)
+    }
+
+    /// Translate `event` and reply to it with the translation.
+    async fn reply_translated(
+        &self,
+        room: &Room,
+        event: &OriginalSyncRoomMessageEvent,
+        text: &str,
+        target: &str,
+    ) {
+        let response = match self.translate(text, target).await {
+            Ok(translation) if translation.source_language.as_deref() == Some(target) => {
+                format!("That's already in {target}.")
+            }
+            Ok(translation) => match translation.source_language {
+                Some(source) => format!("🌐 [{source} → {target}] {}", translation.text),
+                None => format!("🌐 [{target}] {}", translation.text),
+            },
+            Err(err) => {
+                warn!("Failed to translate message: {err:#}");
+                "Sorry, I couldn't translate that.".to_owned()
+            }
+        };
+        reply_notice(room, event, response).await;
+    }
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    translator: Ctx<Translator>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!translate")) else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+
+    if first == "help" {
+        reply_notice(&room, &event, HELP).await;
+        return Ok(());
+    }
+    if first == "default" {
+        let response = set_default(&room, &event, rest, &translator).await?;
+        reply_notice(&room, &event, response).await;
+        return Ok(());
+    }
+
+    let (target, text) = if is_language_code(first) {
+        (first.to_lowercase(), rest)
+    } else {
+        (translator.target_language(&room)?, args)
+    };
+
+    if !text.is_empty() {
+        translator
+            .reply_translated(&room, &event, text, &target)
+            .await;
+        return Ok(());
+    }
+
+    let Some(target_event_id) = reply_target(&event) else {
+        reply_notice(&room, &event, HELP).await;
+        return Ok(());
+    };
+    match fetch_message(&room, target_event_id).await? {
+        Some(target_event) => match text_body(&target_event) {
+            Some(text) => {
+                translator
+                    .reply_translated(&room, &target_event, text, &target)
+                    .await
+            }
+            None => reply_notice(&room, &event, "I can only translate text messages.").await,
+        },
+        None => reply_notice(&room, &event, "I can only translate text messages.").await,
+    }
+    Ok(())
+}
+
+async fn set_default(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    language: &str,
+    translator: &Translator,
+) -> anyhow::Result<String> {
+    if language.is_empty() {
+        return Ok(format!(
+            "Messages here are translated into {} by default.",
+            translator.target_language(room)?
+        ));
+    }
+    if !is_moderator(room, &event.sender).await? {
+        return Ok("Only moderators can change the default language.".to_owned());
+    }
+
+    let room_id = room.room_id().as_str();
+    if language == "off" {
+        translator.table.set_room_language(room_id, None)?;
+        return Ok(format!(
+            "Messages here will be translated into {} by default.",
+            translator.default_language
+        ));
+    }
+    if !is_language_code(language) {
+        return Ok("Give a language code, e.g. `!translate default de`.".to_owned());
+    }
+    let language = language.to_lowercase();
+    translator
+        .table
+        .set_room_language(room_id, Some(&language))?;
+    Ok(format!(
+        "Messages here will be translated into {language} by default."
+    ))
+}
+
+/// Translate messages that are reacted to with a flag.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_reaction(
+    event: OriginalSyncReactionEvent,
+    room: Room,
+    translator: Ctx<Translator>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let annotation = &event.content.relates_to;
+    let Some(target) = flag_language(&annotation.key) else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let Some(target_event) = fetch_message(&room, &annotation.event_id).await? else {
+        return Ok(());
+    };
+    if let Some(text) = text_body(&target_event) {
+        translator
+            .reply_translated(&room, &target_event, text, target)
+            .await;
+    }
+    Ok(())
+}
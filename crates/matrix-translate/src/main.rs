@@ -0,0 +1,71 @@
+mod flags;
+mod handlers;
+mod provider;
+mod table;
+
+use std::time::Duration;
+
+use clap::Parser;
+use handlers::{on_reaction, on_room_message, Translator};
+use matrix_bot_core::{AccountConfig, Bot};
+use provider::{AnyProvider, ProviderKind};
+use table::TranslationTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The translation service to use
+    #[arg(long, value_enum, env = "TRANSLATE_PROVIDER")]
+    pub provider: ProviderKind,
+
+    /// The provider's API URL. Needed for LibreTranslate, optional otherwise
+    #[arg(long, env = "TRANSLATE_API_URL")]
+    pub api_url: Option<String>,
+
+    /// The provider's API key. Needed for DeepL and Google
+    #[arg(long, env = "TRANSLATE_API_KEY")]
+    pub api_key: Option<String>,
+
+    /// The language to translate into when none is given and the room has no
+    /// default
+    #[arg(long, default_value = "en", env = "TRANSLATE_DEFAULT_LANGUAGE")]
+    pub default_language: String,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("matrix-translate/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let provider = AnyProvider::new(config.provider, http, config.api_url, config.api_key)?;
+
+    let mut bot = Bot::login("matrix-translate", config.account_config).await?;
+    let table = TranslationTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Translator {
+        provider,
+        table,
+        default_language: config.default_language.to_lowercase(),
+    });
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_reaction);
+
+    bot.run().await
+}
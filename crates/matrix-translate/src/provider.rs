@@ -0,0 +1,247 @@
+use std::future::Future;
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::json;
+
+/// A translated message.
+#[derive(Debug, Clone)]
+pub struct Translation {
+    pub text: String,
+    /// The language the provider detected the original as, if it said.
+    pub source_language: Option<String>,
+}
+
+/// A translation service.
+pub trait Provider {
+    /// Translate `text` into the language with the code `target`, such as
+    /// `de`, detecting the language it's in.
+    fn translate(
+        &self,
+        text: &str,
+        target: &str,
+    ) -> impl Future<Output = anyhow::Result<Translation>> + Send;
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ProviderKind {
+    Deepl,
+    Libretranslate,
+    Google,
+}
+
+/// One of the supported providers, picked at startup.
+#[derive(Clone)]
+pub enum AnyProvider {
+    Deepl(DeepL),
+    LibreTranslate(LibreTranslate),
+    Google(Google),
+}
+
+impl AnyProvider {
+    pub fn new(
+        kind: ProviderKind,
+        http: reqwest::Client,
+        api_url: Option<String>,
+        api_key: Option<String>,
+    ) -> anyhow::Result<Self> {
+        Ok(match kind {
+            ProviderKind::Deepl => {
+                let api_key = api_key.context("DeepL needs an API key")?;
+                // Keys for the free API are marked with a suffix
+                let api_url = api_url.unwrap_or_else(|| {
+                    if api_key.ends_with(":fx") {
+                        "https://api-free.deepl.com".to_owned()
+                    } else {
+                        "https://api.deepl.com".to_owned()
+                    }
+                });
+                Self::Deepl(DeepL {
+                    http,
+                    api_url,
+                    api_key,
+                })
+            }
+            ProviderKind::Libretranslate => Self::LibreTranslate(LibreTranslate {
+                http,
+                api_url: api_url.context("LibreTranslate needs an API URL")?,
+                api_key,
+            }),
+            ProviderKind::Google => Self::Google(Google {
+                http,
+                api_url: api_url.unwrap_or_else(|| "https://translation.googleapis.com".to_owned()),
+                api_key: api_key.context("Google Translate needs an API key")?,
+            }),
+        })
+    }
+
+    /// A name for the provider, so that cached translations from one aren't
+    /// used for another.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Deepl(_) => "deepl",
+            Self::LibreTranslate(_) => "libretranslate",
+            Self::Google(_) => "google",
+        }
+    }
+}
+
+impl Provider for AnyProvider {
+    async fn translate(&self, text: &str, target: &str) -> anyhow::Result<Translation> {
+        match self {
+            Self::Deepl(provider) => provider.translate(text, target).await,
+            Self::LibreTranslate(provider) => provider.translate(text, target).await,
+            Self::Google(provider) => provider.translate(text, target).await,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DeepL {
+    http: reqwest::Client,
+    api_url: String,
+    api_key: String,
+}
+
+#[derive(Deserialize)]
+struct DeepLResponse {
+    translations: Vec<DeepLTranslation>,
+}
+
+#[derive(Deserialize)]
+struct DeepLTranslation {
+    detected_source_language: Option<String>,
+    text: String,
+}
+
+impl Provider for DeepL {
+    async fn translate(&self, text: &str, target: &str) -> anyhow::Result<Translation> {
+        let response: DeepLResponse = self
+            .http
+            .post(format!(
+                "{}/v2/translate",
+                self.api_url.trim_end_matches('/')
+            ))
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.api_key))
+            .json(&json!({
+                "text": [text],
+                "target_lang": target.to_uppercase(),
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let translation = response
+            .translations
+            .into_iter()
+            .next()
+            .context("DeepL returned no translations")?;
+        Ok(Translation {
+            text: translation.text,
+            source_language: translation
+                .detected_source_language
+                .map(|language| language.to_lowercase()),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct LibreTranslate {
+    http: reqwest::Client,
+    api_url: String,
+    api_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LibreTranslateResponse {
+    translated_text: String,
+    detected_language: Option<LibreTranslateLanguage>,
+}
+
+#[derive(Deserialize)]
+struct LibreTranslateLanguage {
+    language: String,
+}
+
+impl Provider for LibreTranslate {
+    async fn translate(&self, text: &str, target: &str) -> anyhow::Result<Translation> {
+        let response: LibreTranslateResponse = self
+            .http
+            .post(format!("{}/translate", self.api_url.trim_end_matches('/')))
+            .json(&json!({
+                "q": text,
+                "source": "auto",
+                "target": target,
+                "format": "text",
+                "api_key": self.api_key,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(Translation {
+            text: response.translated_text,
+            source_language: response.detected_language.map(|detected| detected.language),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Google {
+    http: reqwest::Client,
+    api_url: String,
+    api_key: String,
+}
+
+#[derive(Deserialize)]
+struct GoogleResponse {
+    data: GoogleData,
+}
+
+#[derive(Deserialize)]
+struct GoogleData {
+    translations: Vec<GoogleTranslation>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleTranslation {
+    translated_text: String,
+    detected_source_language: Option<String>,
+}
+
+impl Provider for Google {
+    async fn translate(&self, text: &str, target: &str) -> anyhow::Result<Translation> {
+        let response: GoogleResponse = self
+            .http
+            .post(format!(
+                "{}/language/translate/v2",
+                self.api_url.trim_end_matches('/')
+            ))
+            .query(&[("key", &self.api_key)])
+            .json(&json!({
+                "q": text,
+                "target": target,
+                "format": "text",
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let translation = response
+            .data
+            .translations
+            .into_iter()
+            .next()
+            .context("Google returned no translations")?;
+        Ok(Translation {
+            text: translation.translated_text,
+            source_language: translation.detected_source_language,
+        })
+    }
+}
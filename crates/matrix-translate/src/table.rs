@@ -0,0 +1,127 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+use crate::provider::Translation;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE room_languages (
+        room_id TEXT PRIMARY KEY,
+        language TEXT NOT NULL
+    );
+    CREATE TABLE translations (
+        provider TEXT NOT NULL,
+        target TEXT NOT NULL,
+        text TEXT NOT NULL,
+        translation TEXT NOT NULL,
+        source_language TEXT,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (provider, target, text)
+    );"];
+
+/// How long translations are cached for, in seconds.
+const CACHE_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+/// Each room's default language, and a cache of translations so that the
+/// same message isn't paid for twice.
+#[derive(Clone)]
+pub struct TranslationTable {
+    store: Store,
+}
+
+impl TranslationTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn room_language(&self, room_id: &str) -> anyhow::Result<Option<String>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT language FROM room_languages WHERE room_id = ?1",
+                params![room_id],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+
+    /// Set the room's default language, or clear it with `None`.
+    pub fn set_room_language(&self, room_id: &str, language: Option<&str>) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            match language {
+                Some(language) => conn.execute(
+                    "INSERT INTO room_languages (room_id, language) VALUES (?1, ?2)
+                    ON CONFLICT (room_id) DO UPDATE SET language = excluded.language",
+                    params![room_id, language],
+                )?,
+                None => conn.execute(
+                    "DELETE FROM room_languages WHERE room_id = ?1",
+                    params![room_id],
+                )?,
+            };
+            Ok(())
+        })
+    }
+
+    pub fn cached(
+        &self,
+        provider: &str,
+        target: &str,
+        text: &str,
+    ) -> anyhow::Result<Option<Translation>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT translation, source_language FROM translations
+                    WHERE provider = ?1 AND target = ?2 AND text = ?3 AND created_at >= ?4",
+                params![provider, target, text, now() - CACHE_SECONDS],
+                |row| {
+                    Ok(Translation {
+                        text: row.get(0)?,
+                        source_language: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+
+    /// Cache a translation, and forget any that have expired.
+    pub fn cache(
+        &self,
+        provider: &str,
+        target: &str,
+        text: &str,
+        translation: &Translation,
+    ) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            let now = now();
+            conn.execute(
+                "DELETE FROM translations WHERE created_at < ?1",
+                params![now - CACHE_SECONDS],
+            )?;
+            conn.execute(
+                "INSERT OR REPLACE INTO translations
+                (provider, target, text, translation, source_language, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    provider,
+                    target,
+                    text,
+                    translation.text,
+                    translation.source_language,
+                    now
+                ],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,376 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use matrix_bot_core::{format_duration, send, send_or_log_error, store::Store};
+use matrix_sdk::{
+    ruma::{
+        events::{
+            reaction::ReactionEventContent, relation::Annotation,
+            room::message::RoomMessageEventContent,
+        },
+        EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+    },
+    Room,
+};
+use rand::seq::SliceRandom;
+use tracing::warn;
+
+use crate::{
+    trivia::{Question, KEYS},
+    words::{Hangman, Outcome, Wordle, WORDLE_LENGTH},
+};
+
+/// The most questions in a round of trivia.
+pub const MAX_QUESTIONS: usize = 20;
+
+/// How many people to show on the scoreboard.
+const TOP_COUNT: usize = 10;
+
+pub enum Game {
+    Trivia(Round),
+    Hangman(Hangman),
+    Wordle(Wordle),
+}
+
+/// The question a round of trivia is on, and who's answered it.
+#[derive(Default)]
+pub struct Round {
+    question: Option<Question>,
+    event_id: Option<OwnedEventId>,
+    /// Each person's first answer.
+    answers: HashMap<OwnedUserId, usize>,
+}
+
+struct Session {
+    /// Tells a finished game's round apart from a new one in the same room.
+    id: u64,
+    game: Game,
+}
+
+/// The game going on in each room, and the scores.
+///
+/// Scores are counters, scoped to the room.
+#[derive(Clone)]
+pub struct Games {
+    pub store: Store,
+    pub questions: Arc<Vec<Question>>,
+    pub words: Arc<Vec<String>>,
+    /// How long each trivia question is open for.
+    pub question_time: Duration,
+    sessions: Arc<Mutex<HashMap<OwnedRoomId, Session>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Games {
+    pub fn new(
+        store: Store,
+        questions: Vec<Question>,
+        words: Vec<String>,
+        question_time: Duration,
+    ) -> Self {
+        Self {
+            store,
+            questions: Arc::new(questions),
+            words: Arc::new(words),
+            question_time,
+            sessions: Default::default(),
+            next_id: Default::default(),
+        }
+    }
+
+    /// Start a game, unless there's one going already, returning its ID.
+    fn start(&self, room_id: &RoomId, game: Game) -> Option<u64> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.contains_key(room_id) {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        sessions.insert(room_id.to_owned(), Session { id, game });
+        Some(id)
+    }
+
+    pub fn stop(&self, room_id: &RoomId) -> bool {
+        self.sessions.lock().unwrap().remove(room_id).is_some()
+    }
+
+    /// Run `f` on a room's game, if it's still the one with this ID.
+    fn with_game<T>(&self, room_id: &RoomId, id: u64, f: impl FnOnce(&mut Game) -> T) -> Option<T> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(room_id)
+            .filter(|session| session.id == id)?;
+        Some(f(&mut session.game))
+    }
+
+    fn finish(&self, room_id: &RoomId, id: u64) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions
+            .get(room_id)
+            .is_some_and(|session| session.id == id)
+        {
+            sessions.remove(room_id);
+        }
+    }
+
+    pub fn start_trivia(&self, room: &Room, count: usize) -> String {
+        if self.questions.is_empty() {
+            return "I don't have any trivia questions.".to_owned();
+        }
+        let questions: Vec<Question> = self
+            .questions
+            .choose_multiple(&mut rand::thread_rng(), count.clamp(1, MAX_QUESTIONS))
+            .cloned()
+            .collect();
+        let count = questions.len();
+        let Some(id) = self.start(room.room_id(), Game::Trivia(Round::default())) else {
+            return "There's already a game going on here.".to_owned();
+        };
+        tokio::spawn(self.clone().run_trivia(room.clone(), id, questions));
+        format!(
+            "Starting a round of {count} questions! Each one is open for {}. \
+            React with the number of your answer, or say `!guess <number>`.",
+            format_duration(self.question_time)
+        )
+    }
+
+    pub fn start_hangman(&self, room_id: &RoomId) -> String {
+        let word = self
+            .words
+            .choose(&mut rand::thread_rng())
+            .cloned()
+            .unwrap_or_default();
+        let game = Hangman::new(&word);
+        let status = game.status();
+        match self.start(room_id, Game::Hangman(game)) {
+            Some(_) => format!("Let's play hangman! Guess with `!guess <letter>`.\n{status}"),
+            None => "There's already a game going on here.".to_owned(),
+        }
+    }
+
+    pub fn start_wordle(&self, room_id: &RoomId) -> String {
+        let words: Vec<&String> = self
+            .words
+            .iter()
+            .filter(|word| word.chars().count() == WORDLE_LENGTH)
+            .collect();
+        let Some(word) = words.choose(&mut rand::thread_rng()) else {
+            return format!("I don't know any {WORDLE_LENGTH} letter words.");
+        };
+        match self.start(room_id, Game::Wordle(Wordle::new(word))) {
+            Some(_) => format!(
+                "Let's play wordle! Guess the {WORDLE_LENGTH} letter word with `!guess <word>`."
+            ),
+            None => "There's already a game going on here.".to_owned(),
+        }
+    }
+
+    /// Take a `!guess` at the room's game, returning what to say about it.
+    pub fn guess(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+        guess: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(session) = sessions.get_mut(room_id) else {
+            return Ok(Some(
+                "There's no game going on here. Ask a moderator to start one with `!game`."
+                    .to_owned(),
+            ));
+        };
+
+        let (outcome, response) = match &mut session.game {
+            Game::Trivia(round) => {
+                let Some(question) = &round.question else {
+                    return Ok(Some("Wait for the next question!".to_owned()));
+                };
+                let Some(answer) = question.parse_answer(guess) else {
+                    return Ok(Some(format!(
+                        "Answer with a number from 1 to {}.",
+                        question.answers.len()
+                    )));
+                };
+                round.answers.entry(user_id.to_owned()).or_insert(answer);
+                return Ok(None);
+            }
+            Game::Hangman(game) => {
+                let outcome = game.guess(guess);
+                let response = match &outcome {
+                    Outcome::Invalid(reason) => reason.to_string(),
+                    Outcome::Repeat => format!("That's been guessed already.\n{}", game.status()),
+                    Outcome::Right => format!("Yes!\n{}", game.status()),
+                    Outcome::Wrong => format!("Nope.\n{}", game.status()),
+                    Outcome::Won => format!("{user_id} got it, the word was {}!", game.word()),
+                    Outcome::Lost => format!("Out of lives! The word was {}.", game.word()),
+                };
+                (outcome, response)
+            }
+            Game::Wordle(game) => {
+                let (outcome, squares) = game.guess(guess);
+                let response = match &outcome {
+                    Outcome::Invalid(reason) => reason.to_string(),
+                    Outcome::Won => {
+                        format!("{squares}\n{user_id} got it, the word was {}!", game.word())
+                    }
+                    Outcome::Lost => {
+                        format!("{squares}\nOut of guesses! The word was {}.", game.word())
+                    }
+                    _ => format!("{squares}\n{} guesses left.", game.attempts_left()),
+                };
+                (outcome, response)
+            }
+        };
+
+        match outcome {
+            Outcome::Won => {
+                sessions.remove(room_id);
+                drop(sessions);
+                let points = self
+                    .store
+                    .increment(room_id.as_str(), user_id.as_str(), 1)?;
+                Ok(Some(format!("{response} Their score is now {points}.")))
+            }
+            Outcome::Lost => {
+                sessions.remove(room_id);
+                Ok(Some(response))
+            }
+            _ => Ok(Some(response)),
+        }
+    }
+
+    /// Answer the current trivia question by reacting to it.
+    pub fn react(&self, room_id: &RoomId, event_id: &EventId, user_id: &UserId, key: &str) {
+        let Some(answer) = KEYS.iter().position(|&k| k == key) else {
+            return;
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(Session {
+            game: Game::Trivia(round),
+            ..
+        }) = sessions.get_mut(room_id)
+        {
+            let open = round
+                .question
+                .as_ref()
+                .is_some_and(|question| answer < question.answers.len());
+            if open && round.event_id.as_deref() == Some(event_id) {
+                round.answers.entry(user_id.to_owned()).or_insert(answer);
+            }
+        }
+    }
+
+    pub fn scores(&self, room_id: &RoomId) -> anyhow::Result<String> {
+        let top = self.store.counters(room_id.as_str())?;
+        if top.is_empty() {
+            return Ok("Nobody has scored here yet.".to_owned());
+        }
+        Ok(top
+            .iter()
+            .take(TOP_COUNT)
+            .enumerate()
+            .map(|(i, (user_id, points))| format!("{}. {user_id}: {points}", i + 1))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Ask each question in turn, giving a point to everyone who gets it
+    /// right, until the round is over or stopped.
+    async fn run_trivia(self, room: Room, id: u64, questions: Vec<Question>) {
+        let room_id = room.room_id().to_owned();
+        let total = questions.len();
+        for (n, question) in questions.iter().enumerate() {
+            let question = question.shuffled();
+            let event_id = match send(&room, ask(&question, n + 1, total)).await {
+                Ok(event_id) => event_id,
+                Err(err) => {
+                    warn!("Failed to ask a question: {err:#}");
+                    break;
+                }
+            };
+            // Put the answers out to click on
+            for key in &KEYS[..question.answers.len()] {
+                let reaction =
+                    ReactionEventContent::new(Annotation::new(event_id.clone(), (*key).to_owned()));
+                if let Err(err) = send(&room, reaction).await {
+                    warn!("Failed to react to a question: {err:#}");
+                    break;
+                }
+            }
+
+            let correct = question.correct;
+            let answer = question.answers[correct].clone();
+            let asked = self.with_game(&room_id, id, |game| {
+                if let Game::Trivia(round) = game {
+                    round.question = Some(question);
+                    round.event_id = Some(event_id);
+                    round.answers.clear();
+                }
+            });
+            if asked.is_none() {
+                return;
+            }
+
+            tokio::time::sleep(self.question_time).await;
+            let answers = self.with_game(&room_id, id, |game| match game {
+                Game::Trivia(round) => {
+                    round.question = None;
+                    std::mem::take(&mut round.answers)
+                }
+                _ => HashMap::new(),
+            });
+            let Some(answers) = answers else {
+                // Stopped while the question was open
+                return;
+            };
+
+            let mut winners: Vec<String> = answers
+                .into_iter()
+                .filter(|(_, answer)| *answer == correct)
+                .map(|(user_id, _)| user_id.to_string())
+                .collect();
+            winners.sort();
+            for user_id in &winners {
+                if let Err(err) = self.store.increment(room_id.as_str(), user_id, 1) {
+                    warn!("Failed to give {user_id} a point: {err}");
+                }
+            }
+            let who = if winners.is_empty() {
+                "Nobody got it.".to_owned()
+            } else {
+                format!("Well done {}!", winners.join(", "))
+            };
+            let reveal = format!("The answer was {} {answer}. {who}", KEYS[correct]);
+            send_or_log_error(&room, RoomMessageEventContent::notice_plain(reveal)).await;
+        }
+
+        self.finish(&room_id, id);
+        let scores = match self.scores(&room_id) {
+            Ok(scores) => scores,
+            Err(err) => {
+                warn!("Failed to get scores: {err}");
+                return;
+            }
+        };
+        let message = format!("That's the end of the round! Scores:\n{scores}");
+        send_or_log_error(&room, RoomMessageEventContent::notice_plain(message)).await;
+    }
+}
+
+fn ask(question: &Question, n: usize, total: usize) -> RoomMessageEventContent {
+    let category = question
+        .category
+        .as_deref()
+        .map(|category| format!(" ({category})"))
+        .unwrap_or_default();
+    let mut text = format!("Question {n}/{total}{category}: {}", question.question);
+    for (key, answer) in KEYS.iter().zip(&question.answers) {
+        text += &format!("\n{key} {answer}");
+    }
+    RoomMessageEventContent::notice_plain(text)
+}
@@ -0,0 +1,128 @@
+use matrix_bot_core::{can_reply, is_moderator, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::{
+        reaction::OriginalSyncReactionEvent, room::message::OriginalSyncRoomMessageEvent,
+    },
+    Room, RoomState,
+};
+use tracing::instrument;
+
+use crate::games::{Games, MAX_QUESTIONS};
+
+/// How many questions a round of trivia has unless asked for more or fewer.
+const DEFAULT_QUESTIONS: usize = 5;
+
+const HELP: &str = "Games, which moderators can start and stop:
+!game trivia [questions] for a round of trivia
+!game hangman or !game wordle for a word game
+!game stop to end the game going on
+!guess <answer> to play
+!game scores for the scoreboard, or !game scores reset to clear it";
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    games: Ctx<Games>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if let Some(guess) = strip_command(body, "!guess") {
+        let response = games.guess(room.room_id(), &event.sender, guess)?;
+        if let Some(response) = response {
+            if can_reply(&room).await {
+                reply_notice(&room, &event, response).await;
+            }
+        }
+        return Ok(());
+    }
+
+    let Some(args) = strip_command(body, "!game") else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+    let response = command(args, &event, &room, &games).await?;
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+/// Answer trivia questions with reactions.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_reaction(
+    event: OriginalSyncReactionEvent,
+    room: Room,
+    games: Ctx<Games>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    // Not the reactions the bot puts out to click on
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let relation = &event.content.relates_to;
+    games.react(
+        room.room_id(),
+        &relation.event_id,
+        &event.sender,
+        &relation.key,
+    );
+    Ok(())
+}
+
+async fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    games: &Games,
+) -> anyhow::Result<String> {
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+    let room_id = room.room_id();
+
+    match (subcommand, rest) {
+        ("scores", "") => return games.scores(room_id),
+        ("trivia" | "hangman" | "wordle" | "stop", _) | ("scores", "reset") => {}
+        _ => return Ok(HELP.to_owned()),
+    }
+    if !is_moderator(room, &event.sender).await? {
+        return Ok("Only moderators can do that.".to_owned());
+    }
+
+    Ok(match subcommand {
+        "trivia" => {
+            let count = match rest {
+                "" => DEFAULT_QUESTIONS,
+                count => match count.parse() {
+                    Ok(count) if (1..=MAX_QUESTIONS).contains(&count) => count,
+                    _ => return Ok(format!("A round can have 1 to {MAX_QUESTIONS} questions.")),
+                },
+            };
+            games.start_trivia(room, count)
+        }
+        "hangman" => games.start_hangman(room_id),
+        "wordle" => games.start_wordle(room_id),
+        "stop" => {
+            if games.stop(room_id) {
+                "Stopped the game.".to_owned()
+            } else {
+                "There's no game going on here.".to_owned()
+            }
+        }
+        _ => {
+            games.store.reset_counters(room_id.as_str())?;
+            "Cleared the scoreboard.".to_owned()
+        }
+    })
+}
@@ -0,0 +1,77 @@
+mod games;
+mod handlers;
+mod trivia;
+mod words;
+
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+use games::Games;
+use handlers::{on_reaction, on_room_message};
+use matrix_bot_core::{AccountConfig, Bot};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// A JSON file of trivia questions, each with a `question`, a list of
+    /// `answers`, the index of the `correct` one and optionally a `category`
+    #[arg(long, env = "TRIVIA_QUESTIONS")]
+    pub questions: Option<PathBuf>,
+
+    /// A file of words for hangman and wordle, one per line. Defaults to a
+    /// short built-in list
+    #[arg(long, env = "TRIVIA_WORDS")]
+    pub words: Option<PathBuf>,
+
+    /// How long each trivia question is open for
+    #[arg(long, default_value = "30s", value_parser = parse_question_time, env = "TRIVIA_QUESTION_TIME")]
+    pub question_time: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_question_time(time: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(time).ok_or_else(|| format!("invalid duration: {time}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let questions = match &config.questions {
+        Some(path) => trivia::load(path)?,
+        None => Vec::new(),
+    };
+    let words = words::load(config.words.as_deref())?;
+    info!(
+        "Loaded {} trivia questions and {} words",
+        questions.len(),
+        words.len()
+    );
+
+    let mut bot = Bot::login("matrix-trivia", config.account_config).await?;
+    let store = bot.open_store(&[]).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new events.
+    bot.client().add_event_handler_context(Games::new(
+        store,
+        questions,
+        words,
+        config.question_time,
+    ));
+    bot.client().add_event_handler(on_room_message);
+    bot.client().add_event_handler(on_reaction);
+
+    bot.run().await
+}
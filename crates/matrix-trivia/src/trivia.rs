@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+
+/// The reactions to answer with, one per choice.
+pub const KEYS: [&str; 6] = ["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣", "6️⃣"];
+
+/// A multiple choice question, as written in the questions file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Question {
+    pub question: String,
+    pub answers: Vec<String>,
+    /// The index of the right answer in `answers`.
+    pub correct: usize,
+    pub category: Option<String>,
+}
+
+impl Question {
+    /// The same question with its answers in a random order.
+    pub fn shuffled(&self) -> Self {
+        let mut order: Vec<usize> = (0..self.answers.len()).collect();
+        order.shuffle(&mut rand::thread_rng());
+        Self {
+            question: self.question.clone(),
+            answers: order.iter().map(|&i| self.answers[i].clone()).collect(),
+            correct: order.iter().position(|&i| i == self.correct).unwrap_or(0),
+            category: self.category.clone(),
+        }
+    }
+
+    /// Which answer a guess like `2`, `2️⃣` or the answer itself is for.
+    pub fn parse_answer(&self, guess: &str) -> Option<usize> {
+        let guess = guess.trim();
+        if let Some(i) = KEYS.iter().position(|&key| key == guess) {
+            return (i < self.answers.len()).then_some(i);
+        }
+        if let Ok(n) = guess.parse::<usize>() {
+            return (1..=self.answers.len()).contains(&n).then(|| n - 1);
+        }
+        self.answers
+            .iter()
+            .position(|answer| answer.eq_ignore_ascii_case(guess))
+    }
+}
+
+/// Load trivia questions from a JSON file, a list of objects with
+/// `question`, `answers`, `correct` and optionally `category`.
+pub fn load(path: &Path) -> anyhow::Result<Vec<Question>> {
+    let questions: Vec<Question> = serde_json::from_str(&std::fs::read_to_string(path)?)
+        .with_context(|| format!("couldn't read questions from {}", path.display()))?;
+    for question in &questions {
+        if !(2..=KEYS.len()).contains(&question.answers.len()) {
+            bail!(
+                "\"{}\" needs between 2 and {} answers",
+                question.question,
+                KEYS.len()
+            );
+        }
+        if question.correct >= question.answers.len() {
+            bail!(
+                "\"{}\" has no answer {}",
+                question.question,
+                question.correct
+            );
+        }
+    }
+    Ok(questions)
+}
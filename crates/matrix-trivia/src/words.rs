@@ -0,0 +1,212 @@
+use std::{collections::BTreeSet, path::Path};
+
+use anyhow::ensure;
+
+/// How many wrong guesses lose a game of hangman.
+pub const HANGMAN_LIVES: usize = 6;
+
+/// How many guesses a game of wordle allows.
+pub const WORDLE_ATTEMPTS: usize = 6;
+
+/// The length of a wordle word.
+pub const WORDLE_LENGTH: usize = 5;
+
+/// Words to play with when no word list is given.
+const DEFAULT_WORDS: &[&str] = &[
+    "about", "acorn", "adobe", "agent", "alarm", "album", "alley", "amber", "angel", "apple",
+    "arena", "attic", "bacon", "badge", "baker", "beach", "berry", "blaze", "bloom", "board",
+    "brain", "bread", "brick", "brush", "cabin", "camel", "candy", "cargo", "chair", "chalk",
+    "charm", "chess", "cider", "cloud", "coral", "crane", "crown", "dance", "delta", "diary",
+    "drift", "eagle", "earth", "ember", "fable", "feast", "fiber", "flame", "flute", "forge",
+    "frost", "fruit", "ghost", "giant", "glass", "globe", "grape", "guard", "guide", "heart",
+    "honey", "house", "jelly", "jewel", "juice", "kayak", "knife", "lemon", "light", "lunar",
+    "magic", "maple", "march", "medal", "melon", "mango", "money", "music", "noble", "ocean",
+    "olive", "opera", "orbit", "otter", "paint", "pearl", "piano", "pilot", "plant", "plaza",
+    "prism", "queen", "quilt", "radio", "raven", "river", "robin", "salad", "scarf", "shell",
+    "skate", "smile", "snake", "solar", "spice", "storm", "sugar", "table", "tiger", "toast",
+    "torch", "tower", "train", "trout", "tulip", "umbra", "unity", "vapor", "video", "viola",
+    "water", "whale", "wheat", "witch", "world", "yacht", "zebra",
+];
+
+/// Load a word list, one word per line, or the default one.
+pub fn load(path: Option<&Path>) -> anyhow::Result<Vec<String>> {
+    let words: Vec<String> = match path {
+        Some(path) => std::fs::read_to_string(path)?
+            .lines()
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty() && word.chars().all(|c| c.is_alphabetic()))
+            .collect(),
+        None => DEFAULT_WORDS.iter().map(|&word| word.to_owned()).collect(),
+    };
+    ensure!(!words.is_empty(), "the word list is empty");
+    Ok(words)
+}
+
+/// What became of a guess.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// It doesn't fit the game, like a number in hangman.
+    Invalid(&'static str),
+    /// That letter was already guessed.
+    Repeat,
+    Right,
+    Wrong,
+    Won,
+    Lost,
+}
+
+pub struct Hangman {
+    word: Vec<char>,
+    guessed: BTreeSet<char>,
+    wrong: usize,
+}
+
+impl Hangman {
+    pub fn new(word: &str) -> Self {
+        Self {
+            word: word.chars().collect(),
+            guessed: BTreeSet::new(),
+            wrong: 0,
+        }
+    }
+
+    pub fn word(&self) -> String {
+        self.word.iter().collect()
+    }
+
+    pub fn lives(&self) -> usize {
+        HANGMAN_LIVES - self.wrong
+    }
+
+    /// Guess a letter, or the whole word.
+    pub fn guess(&mut self, guess: &str) -> Outcome {
+        let guess = guess.trim().to_lowercase();
+        let mut letters = guess.chars();
+        let outcome = match (letters.next(), letters.next()) {
+            (Some(letter), None) if letter.is_alphabetic() => {
+                if !self.guessed.insert(letter) {
+                    return Outcome::Repeat;
+                }
+                if self.word.contains(&letter) {
+                    Outcome::Right
+                } else {
+                    Outcome::Wrong
+                }
+            }
+            (Some(_), Some(_)) if guess.chars().all(char::is_alphabetic) => {
+                if guess == self.word() {
+                    self.guessed.extend(self.word.iter().copied());
+                    Outcome::Right
+                } else {
+                    Outcome::Wrong
+                }
+            }
+            _ => return Outcome::Invalid("Guess a letter, or the whole word."),
+        };
+
+        if outcome == Outcome::Wrong {
+            self.wrong += 1;
+            if self.wrong >= HANGMAN_LIVES {
+                return Outcome::Lost;
+            }
+        }
+        if self.word.iter().all(|letter| self.guessed.contains(letter)) {
+            return Outcome::Won;
+        }
+        outcome
+    }
+
+    /// The word with the letters that haven't been guessed blanked out, and
+    /// the wrong guesses so far.
+    pub fn status(&self) -> String {
+        let masked: Vec<String> = self
+            .word
+            .iter()
+            .map(|letter| {
+                if self.guessed.contains(letter) {
+                    letter.to_string()
+                } else {
+                    "_".to_owned()
+                }
+            })
+            .collect();
+        let misses: String = self
+            .guessed
+            .iter()
+            .filter(|letter| !self.word.contains(letter))
+            .collect();
+        let mut status = format!("{}  ({} lives left)", masked.join(" "), self.lives());
+        if !misses.is_empty() {
+            status += &format!(", not: {misses}");
+        }
+        status
+    }
+}
+
+pub struct Wordle {
+    word: Vec<char>,
+    attempts: usize,
+}
+
+impl Wordle {
+    pub fn new(word: &str) -> Self {
+        Self {
+            word: word.chars().collect(),
+            attempts: 0,
+        }
+    }
+
+    pub fn word(&self) -> String {
+        self.word.iter().collect()
+    }
+
+    pub fn attempts_left(&self) -> usize {
+        WORDLE_ATTEMPTS - self.attempts
+    }
+
+    /// Guess the word, returning how close it was as coloured squares.
+    pub fn guess(&mut self, guess: &str) -> (Outcome, String) {
+        let guess: Vec<char> = guess.trim().to_lowercase().chars().collect();
+        if guess.len() != self.word.len() || !guess.iter().all(|c| c.is_alphabetic()) {
+            return (
+                Outcome::Invalid("Guess a word with the right number of letters."),
+                String::new(),
+            );
+        }
+        self.attempts += 1;
+        let squares = feedback(&self.word, &guess);
+        let outcome = if guess == self.word {
+            Outcome::Won
+        } else if self.attempts >= WORDLE_ATTEMPTS {
+            Outcome::Lost
+        } else {
+            Outcome::Wrong
+        };
+        (outcome, squares)
+    }
+}
+
+/// 🟩 for letters in the right place, 🟨 for letters in the word but
+/// elsewhere, and ⬛ for the rest. Repeated letters are only marked as often
+/// as they're in the word.
+fn feedback(word: &[char], guess: &[char]) -> String {
+    let mut squares = vec!['⬛'; guess.len()];
+    let mut unmatched: Vec<char> = Vec::new();
+    for (i, (&letter, &actual)) in guess.iter().zip(word).enumerate() {
+        if letter == actual {
+            squares[i] = '🟩';
+        } else {
+            unmatched.push(actual);
+        }
+    }
+    for (i, &letter) in guess.iter().enumerate() {
+        if squares[i] == '🟩' {
+            continue;
+        }
+        if let Some(position) = unmatched.iter().position(|&actual| actual == letter) {
+            unmatched.swap_remove(position);
+            squares[i] = '🟨';
+        }
+    }
+    squares.into_iter().collect()
+}
@@ -0,0 +1,132 @@
+use std::{collections::BTreeMap, fmt, path::Path, time::Duration};
+
+use anyhow::{bail, Context};
+use matrix_bot_core::parse_duration;
+use matrix_sdk::ruma::OwnedRoomId;
+use serde::Deserialize;
+
+/// The targets file, e.g.
+///
+/// ```toml
+/// [targets.website]
+/// room = "!abc:example.org"
+/// http = "https://example.org/health"
+/// interval = "30s"
+///
+/// [targets.database]
+/// room = "!abc:example.org"
+/// tcp = "db.example.org:5432"
+///
+/// [targets.router]
+/// room = "!abc:example.org"
+/// icmp = "192.0.2.1"
+/// failures = 5
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetsFile {
+    pub targets: BTreeMap<String, Target>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawTarget")]
+pub struct Target {
+    /// Where alerts are posted.
+    pub room: OwnedRoomId,
+    pub check: Check,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// How many checks in a row have to fail before the target is down.
+    pub failures: u32,
+    /// How many checks in a row have to pass before a down target is back up.
+    pub recoveries: u32,
+}
+
+#[derive(Debug)]
+pub enum Check {
+    /// Fetch a URL, expecting a particular status or any successful one.
+    Http { url: String, status: Option<u16> },
+    /// Open a TCP connection to a `host:port`.
+    Tcp(String),
+    /// Ping a host.
+    Icmp(String),
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Check::Http { url, .. } => write!(f, "{url}"),
+            Check::Tcp(address) => write!(f, "tcp {address}"),
+            Check::Icmp(host) => write!(f, "ping {host}"),
+        }
+    }
+}
+
+/// A target as written, with exactly one of `http`, `tcp` or `icmp`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTarget {
+    room: OwnedRoomId,
+    http: Option<String>,
+    /// The status `http` has to respond with.
+    status: Option<u16>,
+    tcp: Option<String>,
+    icmp: Option<String>,
+    interval: Option<String>,
+    timeout: Option<String>,
+    #[serde(default = "default_failures")]
+    failures: u32,
+    #[serde(default = "default_recoveries")]
+    recoveries: u32,
+}
+
+fn default_failures() -> u32 {
+    3
+}
+
+fn default_recoveries() -> u32 {
+    2
+}
+
+impl TryFrom<RawTarget> for Target {
+    type Error = String;
+
+    fn try_from(raw: RawTarget) -> Result<Self, Self::Error> {
+        if raw.status.is_some() && raw.http.is_none() {
+            return Err("`status` only applies to `http` checks".to_owned());
+        }
+        let check = match (raw.http, raw.tcp, raw.icmp) {
+            (Some(url), None, None) => Check::Http {
+                url,
+                status: raw.status,
+            },
+            (None, Some(address), None) => Check::Tcp(address),
+            (None, None, Some(host)) if !host.starts_with('-') => Check::Icmp(host),
+            (None, None, Some(host)) => return Err(format!("invalid host: {host}")),
+            _ => return Err("expected exactly one of `http`, `tcp` or `icmp`".to_owned()),
+        };
+        let duration = |text: Option<String>, default| match text {
+            Some(text) => parse_duration(&text).ok_or_else(|| format!("invalid duration: {text}")),
+            None => Ok(default),
+        };
+        Ok(Target {
+            room: raw.room,
+            check,
+            interval: duration(raw.interval, Duration::from_secs(60))?,
+            timeout: duration(raw.timeout, Duration::from_secs(10))?,
+            failures: raw.failures.max(1),
+            recoveries: raw.recoveries.max(1),
+        })
+    }
+}
+
+pub fn load(path: &Path) -> anyhow::Result<TargetsFile> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read targets file {}", path.display()))?;
+    let file: TargetsFile = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse targets file {}", path.display()))?;
+    if file.targets.is_empty() {
+        bail!("no targets in {}", path.display());
+    }
+    Ok(file)
+}
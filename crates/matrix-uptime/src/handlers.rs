@@ -0,0 +1,37 @@
+use matrix_bot_core::{can_reply, reply_notice, strip_command, text_body};
+use matrix_sdk::{
+    event_handler::Ctx, ruma::events::room::message::OriginalSyncRoomMessageEvent, Room, RoomState,
+};
+use tracing::instrument;
+
+use crate::monitor::Monitor;
+
+const HELP: &str = "Usage:
+!uptime status to see how the services I watch for this room are doing";
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    monitor: Ctx<Monitor>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!uptime")) else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let response = match args.trim() {
+        "status" => monitor.summary(room.room_id())?,
+        _ => HELP.to_owned(),
+    };
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
@@ -0,0 +1,70 @@
+mod config;
+mod handlers;
+mod monitor;
+mod probe;
+mod status;
+
+use std::{path::PathBuf, sync::Arc};
+
+use clap::Parser;
+use handlers::on_room_message;
+use matrix_bot_core::{AccountConfig, Bot};
+use monitor::Monitor;
+use probe::Prober;
+use status::StatusTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// TOML file describing each target to check, how, and where to alert
+    #[arg(long, env = "UPTIME_TARGETS")]
+    pub targets: PathBuf,
+
+    /// The ping binary, for ICMP checks
+    #[arg(long, default_value = "ping", env = "UPTIME_PING")]
+    pub ping: PathBuf,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let targets = config::load(&config.targets)?;
+
+    let mut bot = Bot::login("matrix-uptime", config.account_config).await?;
+    let table = StatusTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Targets set their own timeouts
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("matrix-uptime/", env!("CARGO_PKG_VERSION")))
+        .redirect(reqwest::redirect::Policy::limited(5))
+        .build()?;
+    let monitor = Monitor {
+        table,
+        targets: Arc::new(targets),
+        prober: Arc::new(Prober {
+            http,
+            ping: config.ping,
+        }),
+    };
+    monitor::run(bot.client().clone(), monitor.clone());
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(monitor);
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
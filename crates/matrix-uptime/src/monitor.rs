@@ -0,0 +1,125 @@
+use std::{sync::Arc, time::Duration};
+
+use matrix_bot_core::{can_reply, format_duration, send_or_log_error};
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, RoomId},
+    Client, RoomState,
+};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    config::{Target, TargetsFile},
+    probe::Prober,
+    status::{now, Change, Status, StatusTable},
+};
+
+#[derive(Clone)]
+pub struct Monitor {
+    pub table: StatusTable,
+    pub targets: Arc<TargetsFile>,
+    pub prober: Arc<Prober>,
+}
+
+impl Monitor {
+    /// A line on how each of a room's targets is doing.
+    pub fn summary(&self, room_id: &RoomId) -> anyhow::Result<String> {
+        let mut lines = Vec::new();
+        for (name, target) in &self.targets.targets {
+            if *target.room != *room_id {
+                continue;
+            }
+            let status = self.table.get(name)?;
+            let line = match status.filter(|status| status.checked_at.is_some()) {
+                None => format!("⚪ {name} ({}): not checked yet", target.check),
+                Some(status) => {
+                    let state = if status.up { "🟢" } else { "🔴" };
+                    let since =
+                        format_duration(Duration::from_secs(now().saturating_sub(status.since)));
+                    let last = match (&status.latency, &status.error) {
+                        (Some(latency), _) => format!("{}ms", latency.as_millis()),
+                        (None, Some(error)) => error.clone(),
+                        (None, None) => "no result".to_owned(),
+                    };
+                    let word = if status.up { "up" } else { "down" };
+                    format!(
+                        "{state} {name} ({}): {word} for {since}, last check: {last}",
+                        target.check
+                    )
+                }
+            };
+            lines.push(line);
+        }
+        if lines.is_empty() {
+            return Ok("I'm not watching anything for this room.".to_owned());
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+/// Check each target on its own interval, forever.
+pub fn run(client: Client, monitor: Monitor) {
+    for name in monitor.targets.targets.keys() {
+        tokio::spawn(watch(client.clone(), monitor.clone(), name.clone()));
+    }
+}
+
+async fn watch(client: Client, monitor: Monitor, name: String) {
+    let target = &monitor.targets.targets[&name];
+    let mut ticks = interval(target.interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        if let Err(err) = check(&client, &monitor, &name, target).await {
+            error!(name = name.as_str(), "Failed to check target: {err}");
+        }
+    }
+}
+
+#[instrument(skip_all, fields(name = name))]
+async fn check(
+    client: &Client,
+    monitor: &Monitor,
+    name: &str,
+    target: &Target,
+) -> anyhow::Result<()> {
+    let result = monitor.prober.probe(&target.check, target.timeout).await;
+    if let Err(err) = &result {
+        debug!("Check failed: {err:#}");
+    }
+
+    let mut status = monitor.table.get(name)?.unwrap_or_default();
+    let change = status.record(target, &result);
+    monitor.table.save(name, &status)?;
+
+    let Some(change) = change else {
+        return Ok(());
+    };
+    let text = match change {
+        Change::Down => {
+            info!("Target is down");
+            let reason = status.error.as_deref().unwrap_or("unknown error");
+            format!("🔴 {name} ({}) is down: {reason}", target.check)
+        }
+        Change::Up { after } => {
+            info!("Target is back up");
+            format!(
+                "🟢 {name} ({}) is back up after {}",
+                target.check,
+                format_duration(after)
+            )
+        }
+    };
+
+    let Some(room) = client
+        .get_room(&target.room)
+        .filter(|room| room.state() == RoomState::Joined)
+    else {
+        warn!("Not in the target's room, can't post alert");
+        return Ok(());
+    };
+    if can_reply(&room).await {
+        send_or_log_error(&room, RoomMessageEventContent::notice_plain(text)).await;
+    }
+    Ok(())
+}
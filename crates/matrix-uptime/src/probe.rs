@@ -0,0 +1,65 @@
+use std::{
+    path::PathBuf,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, ensure};
+use matrix_bot_core::format_duration;
+use tokio::{net::TcpStream, process::Command};
+
+use crate::config::Check;
+
+/// Runs checks against targets.
+pub struct Prober {
+    pub http: reqwest::Client,
+    /// The `ping` binary, for ICMP checks.
+    pub ping: PathBuf,
+}
+
+impl Prober {
+    /// Run a check, returning how long it took if it passed.
+    pub async fn probe(&self, check: &Check, timeout: Duration) -> anyhow::Result<Duration> {
+        let start = Instant::now();
+        tokio::time::timeout(timeout, self.run(check, timeout))
+            .await
+            .map_err(|_| anyhow!("timed out after {}", format_duration(timeout)))??;
+        Ok(start.elapsed())
+    }
+
+    async fn run(&self, check: &Check, timeout: Duration) -> anyhow::Result<()> {
+        match check {
+            Check::Http { url, status } => {
+                let response = self.http.get(url).send().await?;
+                let got = response.status();
+                match status {
+                    Some(expected) => {
+                        ensure!(got.as_u16() == *expected, "expected {expected}, got {got}")
+                    }
+                    None => ensure!(got.is_success(), "got {got}"),
+                }
+            }
+            Check::Tcp(address) => {
+                TcpStream::connect(address).await?;
+            }
+            Check::Icmp(host) => {
+                let wait = timeout.as_secs().max(1).to_string();
+                let output = Command::new(&self.ping)
+                    .args(["-c", "1", "-W", &wait, host])
+                    .stdin(Stdio::null())
+                    .kill_on_drop(true)
+                    .output()
+                    .await?;
+                // ping explains unknown hosts on stderr, and lost packets
+                // only in its summary on stdout
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let reason = match stderr.trim() {
+                    "" => "no reply",
+                    stderr => stderr,
+                };
+                ensure!(output.status.success(), "{reason}");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+use crate::config::Target;
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE status (
+        target TEXT PRIMARY KEY,
+        up INTEGER NOT NULL,
+        streak INTEGER NOT NULL,
+        since INTEGER NOT NULL,
+        checked_at INTEGER,
+        latency_ms INTEGER,
+        error TEXT
+    );"];
+
+/// Where a target stands, as of its last check.
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub up: bool,
+    /// How many checks in a row have disagreed with `up`.
+    pub streak: u32,
+    /// When the target last went up or down.
+    pub since: u64,
+    pub checked_at: Option<u64>,
+    /// How long the last check took, if it passed.
+    pub latency: Option<Duration>,
+    /// Why the last check failed, if it did.
+    pub error: Option<String>,
+}
+
+/// A target going down or coming back up.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    Down,
+    Up { after: Duration },
+}
+
+/// A target nobody has checked yet, presumed up.
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            up: true,
+            streak: 0,
+            since: now(),
+            checked_at: None,
+            latency: None,
+            error: None,
+        }
+    }
+}
+
+impl Status {
+    /// Record the result of a check. The target only changes state once
+    /// enough checks in a row agree, so one that flaps doesn't flood the room
+    /// with alerts.
+    pub fn record(&mut self, target: &Target, result: &anyhow::Result<Duration>) -> Option<Change> {
+        let now = now();
+        self.checked_at = Some(now);
+        self.latency = result.as_ref().ok().copied();
+        self.error = result.as_ref().err().map(|err| format!("{err:#}"));
+
+        if result.is_ok() == self.up {
+            self.streak = 0;
+            return None;
+        }
+        self.streak += 1;
+        let needed = if self.up {
+            target.failures
+        } else {
+            target.recoveries
+        };
+        if self.streak < needed {
+            return None;
+        }
+
+        let change = if self.up {
+            Change::Down
+        } else {
+            Change::Up {
+                after: Duration::from_secs(now.saturating_sub(self.since)),
+            }
+        };
+        self.up = !self.up;
+        self.streak = 0;
+        self.since = now;
+        Some(change)
+    }
+}
+
+/// Each target's status, persisted in SQLite so a restart doesn't repeat or
+/// lose alerts.
+#[derive(Clone)]
+pub struct StatusTable {
+    store: Store,
+}
+
+impl StatusTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn get(&self, target: &str) -> anyhow::Result<Option<Status>> {
+        self.store.with(|conn| {
+            conn.query_row(
+                "SELECT up, streak, since, checked_at, latency_ms, error FROM status
+                    WHERE target = ?1",
+                [target],
+                |row| {
+                    Ok(Status {
+                        up: row.get(0)?,
+                        streak: row.get(1)?,
+                        since: row.get(2)?,
+                        checked_at: row.get(3)?,
+                        latency: row.get::<_, Option<u64>>(4)?.map(Duration::from_millis),
+                        error: row.get(5)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+
+    pub fn save(&self, target: &str, status: &Status) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO status (target, up, streak, since, checked_at, latency_ms, error)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    target,
+                    status.up,
+                    status.streak,
+                    status.since,
+                    status.checked_at,
+                    status.latency.map(|latency| latency.as_millis() as u64),
+                    status.error,
+                ],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,215 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::LazyLock,
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context};
+use regex::Regex;
+use reqwest::{header, redirect, StatusCode};
+use url::Url;
+
+/// The most of a page that's read looking for metadata.
+const MAX_BYTES: usize = 1024 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAX_REDIRECTS: usize = 3;
+
+static TITLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+
+static META: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<meta\s[^>]*>").unwrap());
+
+static ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?is)([a-z:_-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+#[derive(Debug, Default)]
+pub struct Preview {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+}
+
+/// Fetch a page's title and OpenGraph metadata.
+///
+/// Hosts are resolved up front and the connection pinned to the checked
+/// address, so that neither the URL, a redirect nor a DNS change can point
+/// the bot at a private network.
+pub async fn fetch(url: &Url) -> anyhow::Result<Preview> {
+    let mut url = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let host = url.host_str().context("URL has no host")?.to_owned();
+        let port = url.port_or_known_default().context("URL has no port")?;
+        ensure!(
+            matches!(url.scheme(), "http" | "https"),
+            "unsupported scheme"
+        );
+
+        let addr = resolve_public(&host, port).await?;
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("matrix-urlpreview/", env!("CARGO_PKG_VERSION")))
+            .timeout(TIMEOUT)
+            .redirect(redirect::Policy::none())
+            .resolve(&host, addr)
+            .build()?;
+
+        let mut response = client
+            .get(url.clone())
+            .header(header::ACCEPT, "text/html")
+            .send()
+            .await?;
+
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .context("redirect without a location")?;
+            url = url.join(location)?;
+            continue;
+        }
+        ensure!(
+            response.status() == StatusCode::OK,
+            "status {}",
+            response.status()
+        );
+
+        let is_html = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("text/html"));
+        ensure!(is_html, "not an HTML page");
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            // Only look for the end of the head in the new chunk, plus enough
+            // before it to catch a tag split across chunks
+            let searched = body.len().saturating_sub(6);
+            body.extend_from_slice(&chunk);
+            // The metadata is in the head, so a truncated page is fine
+            if body.len() >= MAX_BYTES
+                || body[searched..]
+                    .windows(7)
+                    .any(|w| w.eq_ignore_ascii_case(b"</head>"))
+            {
+                break;
+            }
+        }
+        body.truncate(MAX_BYTES);
+        return Ok(parse(&String::from_utf8_lossy(&body)));
+    }
+    bail!("too many redirects")
+}
+
+/// Resolve a host, refusing to go anywhere that isn't on the public
+/// internet.
+async fn resolve_public(host: &str, port: u16) -> anyhow::Result<SocketAddr> {
+    let addrs: Vec<_> = tokio::net::lookup_host((host, port)).await?.collect();
+    ensure!(!addrs.is_empty(), "{host} doesn't resolve");
+    // Every address has to be public, or a rebinding trick could pick the
+    // private one later
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        bail!("{host} resolves to non-public address {}", addr.ip());
+    }
+    Ok(addrs[0])
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // Carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // "This network" and reserved ranges
+        || a == 0
+        || a >= 240
+        // Benchmarking
+        || (a == 198 && (18..20).contains(&b)))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local
+        || (first & 0xfe00) == 0xfc00
+        // Link local
+        || (first & 0xffc0) == 0xfe80
+        // NAT64 and IPv4-compatible addresses can reach IPv4 networks
+        || (first == 0x0064 && ip.segments()[1] == 0xff9b)
+        || ip.segments()[..6].iter().all(|&segment| segment == 0))
+}
+
+fn parse(html: &str) -> Preview {
+    let mut preview = Preview::default();
+    for meta in META.find_iter(html) {
+        let mut key = None;
+        let mut content = None;
+        for attribute in ATTRIBUTE.captures_iter(meta.as_str()) {
+            let value = attribute
+                .get(2)
+                .or_else(|| attribute.get(3))
+                .map_or("", |value| value.as_str());
+            match attribute[1].to_lowercase().as_str() {
+                "property" | "name" => key = Some(value.to_lowercase()),
+                "content" => content = Some(decode_entities(value)),
+                _ => {}
+            }
+        }
+        let (Some(key), Some(content)) = (key, content) else {
+            continue;
+        };
+        if content.is_empty() {
+            continue;
+        }
+        match key.as_str() {
+            "og:title" | "twitter:title" => {
+                preview.title.get_or_insert(content);
+            }
+            "og:description" | "twitter:description" | "description" => {
+                preview.description.get_or_insert(content);
+            }
+            "og:site_name" => {
+                preview.site_name.get_or_insert(content);
+            }
+            _ => {}
+        }
+    }
+
+    if preview.title.is_none() {
+        preview.title = TITLE
+            .captures(html)
+            .map(|title| decode_entities(title[1].trim()))
+            .filter(|title| !title.is_empty());
+    }
+    preview
+}
+
+fn decode_entities(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
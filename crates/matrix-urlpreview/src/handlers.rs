@@ -0,0 +1,215 @@
+use std::sync::LazyLock;
+
+use matrix_bot_core::{
+    can_reply, html, is_moderator, reply_notice, send_or_log_error, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::message::{
+        AddMentions, OriginalSyncRoomMessageEvent, ReplyWithinThread, RoomMessageEventContent,
+    },
+    Room, RoomState,
+};
+use regex::Regex;
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::{
+    fetch::{fetch, Preview},
+    table::{is_allowed, PreviewTable},
+};
+
+/// The most links previewed from a single message.
+const MAX_PREVIEWS_PER_MESSAGE: usize = 3;
+
+/// Descriptions are cut down to this many characters.
+const MAX_DESCRIPTION_CHARS: usize = 300;
+
+const HELP: &str = "Usage:
+!preview on / !preview off to turn link previews on or off in this room
+!preview allow <domain> to only preview links to allowed domains
+!preview deny <domain> to never preview links to a domain
+!preview remove <domain> to remove a domain from the lists
+!preview list to show this room's settings
+Changing settings needs moderator power.";
+
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"https?://[^\s<>"]+"#).unwrap());
+
+#[derive(Clone)]
+pub struct Previews {
+    pub table: PreviewTable,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    previews: Ctx<Previews>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    if room.client().user_id() == Some(&*event.sender) {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    if let Some(args) = strip_command(body, "!preview") {
+        if can_reply(&room).await {
+            let response = command(args, &event, &room, &previews.table).await?;
+            reply_notice(&room, &event, response).await;
+        }
+        return Ok(());
+    }
+
+    let room_id = room.room_id().as_str();
+    if !previews.table.is_enabled(room_id)? {
+        return Ok(());
+    }
+    let rules = previews.table.rules(room_id)?;
+    let links: Vec<_> = find_links(body)
+        .into_iter()
+        .filter(|url| url.host_str().is_some_and(|host| is_allowed(&rules, host)))
+        .take(MAX_PREVIEWS_PER_MESSAGE)
+        .collect();
+    if links.is_empty() || !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let original = event.clone().into_full_event(room.room_id().to_owned());
+    for url in links {
+        let preview = match fetch(&url).await {
+            Ok(preview) => preview,
+            Err(err) => {
+                debug!(url = url.as_str(), "Not previewing link: {err:#}");
+                continue;
+            }
+        };
+        let Some((plain, formatted)) = render(&preview) else {
+            continue;
+        };
+        let content = RoomMessageEventContent::notice_html(plain, formatted).make_for_thread(
+            &original,
+            ReplyWithinThread::No,
+            AddMentions::No,
+        );
+        send_or_log_error(&room, content).await;
+    }
+    Ok(())
+}
+
+async fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    table: &PreviewTable,
+) -> anyhow::Result<String> {
+    let room_id = room.room_id().as_str();
+    let (subcommand, domain) = args.split_once(' ').unwrap_or((args, ""));
+    let domain = domain.trim().trim_end_matches('.').to_lowercase();
+
+    if subcommand == "list" {
+        let enabled = if table.is_enabled(room_id)? {
+            "Link previews are on in this room."
+        } else {
+            "Link previews are off in this room."
+        };
+        let rules = table.rules(room_id)?;
+        let allowed: Vec<_> = rules.iter().filter(|rule| rule.allow).collect();
+        let denied: Vec<_> = rules.iter().filter(|rule| !rule.allow).collect();
+        let mut response = enabled.to_owned();
+        for (label, list) in [("Allowed", allowed), ("Denied", denied)] {
+            if !list.is_empty() {
+                let domains: Vec<_> = list.iter().map(|rule| rule.domain.as_str()).collect();
+                response.push_str(&format!("\n{label}: {}", domains.join(", ")));
+            }
+        }
+        return Ok(response);
+    }
+    if !matches!(subcommand, "on" | "off" | "allow" | "deny" | "remove") {
+        return Ok(HELP.to_owned());
+    }
+
+    if !is_moderator(room, &event.sender).await? {
+        return Ok("Only moderators can change link preview settings.".to_owned());
+    }
+    Ok(match subcommand {
+        "on" => {
+            table.set_enabled(room_id, true)?;
+            "Link previews are now on in this room.".to_owned()
+        }
+        "off" => {
+            table.set_enabled(room_id, false)?;
+            "Link previews are now off in this room.".to_owned()
+        }
+        _ if domain.is_empty() || domain.contains(['/', ':', ' ']) => {
+            format!("Give a domain, e.g. `!preview {subcommand} example.org`.")
+        }
+        "allow" => {
+            table.set_rule(room_id, &domain, true)?;
+            format!("Links to {domain} will be previewed. Once any domain is allowed, only allowed domains are.")
+        }
+        "deny" => {
+            table.set_rule(room_id, &domain, false)?;
+            format!("Links to {domain} won't be previewed.")
+        }
+        _ => {
+            if table.remove_rule(room_id, &domain)? {
+                format!("Removed {domain} from this room's lists.")
+            } else {
+                format!("{domain} isn't on this room's lists.")
+            }
+        }
+    })
+}
+
+/// Find the links in a message, ignoring punctuation that's more likely to
+/// end the sentence than the URL.
+fn find_links(body: &str) -> Vec<Url> {
+    let mut links: Vec<Url> = Vec::new();
+    for link in LINK.find_iter(body) {
+        let mut link = link
+            .as_str()
+            .trim_end_matches(['.', ',', ';', ':', '!', '?', '\'']);
+        // Keep brackets that are part of the URL, like Wikipedia's
+        while link.ends_with(')') && link.matches('(').count() < link.matches(')').count() {
+            link = &link[..link.len() - 1];
+        }
+        if let Ok(url) = Url::parse(link) {
+            if !links.contains(&url) {
+                links.push(url);
+            }
+        }
+    }
+    links
+}
+
+/// The plain and HTML bodies of a preview, or `None` if the page had
+/// nothing worth showing.
+fn render(preview: &Preview) -> Option<(String, String)> {
+    let title = preview.title.as_deref()?;
+    let description = preview.description.as_deref().map(|description| {
+        if description.chars().count() > MAX_DESCRIPTION_CHARS {
+            let truncated: String = description.chars().take(MAX_DESCRIPTION_CHARS).collect();
+            format!("{}…", truncated.trim_end())
+        } else {
+            description.to_owned()
+        }
+    });
+
+    let mut plain = String::new();
+    let mut formatted = String::new();
+    if let Some(site_name) = &preview.site_name {
+        plain.push_str(&format!("{site_name}: "));
+        formatted.push_str(&format!("{}: ", html::escape(site_name)));
+    }
+    plain.push_str(title);
+    formatted.push_str(&format!("<b>{}</b>", html::escape(title)));
+    if let Some(description) = description {
+        plain.push_str(&format!("\n{description}"));
+        formatted.push_str(&format!("<br>{}", html::escape(&description)));
+    }
+    Some((plain, formatted))
+}
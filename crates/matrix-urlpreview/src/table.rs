@@ -0,0 +1,121 @@
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE rooms (
+        room_id TEXT PRIMARY KEY,
+        enabled INTEGER NOT NULL
+    );
+    CREATE TABLE domains (
+        room_id TEXT NOT NULL,
+        domain TEXT NOT NULL,
+        allow INTEGER NOT NULL,
+        PRIMARY KEY (room_id, domain)
+    );"];
+
+/// Which rooms have opted in to previews, and each room's domain allow and
+/// deny lists.
+#[derive(Clone)]
+pub struct PreviewTable {
+    store: Store,
+}
+
+/// A domain on a room's allow or deny list.
+#[derive(Debug)]
+pub struct DomainRule {
+    pub domain: String,
+    pub allow: bool,
+}
+
+impl PreviewTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn is_enabled(&self, room_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT enabled FROM rooms WHERE room_id = ?1",
+                    params![room_id],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or(false))
+        })
+    }
+
+    pub fn set_enabled(&self, room_id: &str, enabled: bool) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO rooms (room_id, enabled) VALUES (?1, ?2)
+                ON CONFLICT (room_id) DO UPDATE SET enabled = excluded.enabled",
+                params![room_id, enabled],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Add `domain` to the room's allow or deny list, replacing any existing
+    /// rule for it.
+    pub fn set_rule(&self, room_id: &str, domain: &str, allow: bool) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT INTO domains (room_id, domain, allow) VALUES (?1, ?2, ?3)
+                ON CONFLICT (room_id, domain) DO UPDATE SET allow = excluded.allow",
+                params![room_id, domain, allow],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Returns whether there was a rule to remove.
+    pub fn remove_rule(&self, room_id: &str, domain: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM domains WHERE room_id = ?1 AND domain = ?2",
+                params![room_id, domain],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+
+    pub fn rules(&self, room_id: &str) -> anyhow::Result<Vec<DomainRule>> {
+        self.store.with(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT domain, allow FROM domains WHERE room_id = ?1 ORDER BY allow DESC, domain",
+            )?;
+            let rules = statement
+                .query_map(params![room_id], |row| {
+                    Ok(DomainRule {
+                        domain: row.get(0)?,
+                        allow: row.get(1)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(rules)
+        })
+    }
+}
+
+/// Whether `host` may be previewed under `rules`.
+///
+/// Rules match the domain and its subdomains. Denied domains are never
+/// previewed, and once a room allows any domain, only allowed domains are.
+pub fn is_allowed(rules: &[DomainRule], host: &str) -> bool {
+    let matches = |domain: &str| {
+        host == domain
+            || host
+                .strip_suffix(domain)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    };
+    if rules
+        .iter()
+        .any(|rule| !rule.allow && matches(&rule.domain))
+    {
+        return false;
+    }
+    let mut allowed = rules.iter().filter(|rule| rule.allow).peekable();
+    allowed.peek().is_none() || allowed.any(|rule| matches(&rule.domain))
+}
@@ -0,0 +1,103 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    time::Duration,
+};
+
+use serde_json::Value;
+
+/// The state event type of a MatrixRTC (Element Call) membership.
+pub const CALL_MEMBER: &str = "org.matrix.msc3401.call.member";
+
+/// The state event type of a room widget.
+pub const WIDGET: &str = "im.vector.modular.widgets";
+
+/// A call going on in a room.
+#[derive(Debug)]
+struct Call {
+    /// When it started, in milliseconds since the Unix epoch.
+    started_at: u64,
+    /// Who's in it now, by whatever identifies their presence: a membership
+    /// state key, widget or VoIP call ID.
+    present: HashSet<String>,
+    /// Everyone who's been in it.
+    participants: BTreeSet<String>,
+}
+
+/// A call that's over.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ended {
+    pub duration: Duration,
+    pub participants: Vec<String>,
+}
+
+/// The calls going on in each room.
+///
+/// This only knows about calls that started while the bot was running.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    calls: HashMap<String, Call>,
+}
+
+impl Tracker {
+    /// Note that someone is in a room's call, returning whether that started
+    /// the call.
+    pub fn join(&mut self, room_id: &str, key: &str, user_id: &str, at: u64) -> bool {
+        let mut started = false;
+        let call = self.calls.entry(room_id.to_owned()).or_insert_with(|| {
+            started = true;
+            Call {
+                started_at: at,
+                present: HashSet::new(),
+                participants: BTreeSet::new(),
+            }
+        });
+        call.present.insert(key.to_owned());
+        call.participants.insert(user_id.to_owned());
+        started
+    }
+
+    /// Note that whoever matched `key` has left a room's call, returning how
+    /// it went if that was the last of them.
+    pub fn leave(&mut self, room_id: &str, key: impl Fn(&str) -> bool, at: u64) -> Option<Ended> {
+        let call = self.calls.get_mut(room_id)?;
+        call.present.retain(|present| !key(present));
+        if !call.present.is_empty() {
+            return None;
+        }
+        let call = self.calls.remove(room_id)?;
+        Some(Ended {
+            duration: Duration::from_millis(at.saturating_sub(call.started_at)),
+            participants: call.participants.into_iter().collect(),
+        })
+    }
+}
+
+/// Whether the content of a call membership means the sender is in a call,
+/// in either the current format with one device per state key, or the
+/// older one with a list of memberships. Leaving empties the content.
+pub fn is_membership_active(content: &Value) -> bool {
+    if let Some(memberships) = content.get("memberships") {
+        return memberships
+            .as_array()
+            .is_some_and(|memberships| !memberships.is_empty());
+    }
+    content
+        .get("application")
+        .and_then(Value::as_str)
+        .is_some_and(|application| application == "m.call")
+}
+
+/// Whether a widget's content is for a call, like Jitsi or Element Call.
+/// Removing a widget empties its content.
+pub fn is_call_widget(content: &Value) -> bool {
+    let contains_call = |field: &str| {
+        content
+            .get(field)
+            .and_then(Value::as_str)
+            .is_some_and(|value| {
+                let value = value.to_lowercase();
+                value.contains("jitsi") || value.contains("call")
+            })
+    };
+    contains_call("type") || contains_call("url")
+}
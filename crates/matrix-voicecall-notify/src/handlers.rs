@@ -0,0 +1,220 @@
+use std::sync::{Arc, Mutex};
+
+use matrix_bot_core::{can_reply, format_duration, html, send_or_log_error};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::{
+            call::{
+                answer::OriginalSyncCallAnswerEvent, hangup::OriginalSyncCallHangupEvent,
+                invite::OriginalSyncCallInviteEvent,
+            },
+            room::message::RoomMessageEventContent,
+            AnySyncStateEvent, Mentions,
+        },
+        serde::Raw,
+        MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedUserId, RoomId, UserId,
+    },
+    Room, RoomState,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, info, instrument};
+
+use crate::calls::{is_call_widget, is_membership_active, Ended, Tracker, CALL_MEMBER, WIDGET};
+
+#[derive(Clone)]
+pub struct Notifier {
+    pub tracker: Arc<Mutex<Tracker>>,
+    /// The rooms to watch for calls, or all of them if empty.
+    pub rooms: Vec<OwnedRoomId>,
+    /// Where to post notifications, instead of the room with the call.
+    pub announce_room: Option<OwnedRoomId>,
+    /// People to mention when a call starts.
+    pub ping: Vec<OwnedUserId>,
+}
+
+/// The parts of a state event needed to follow calls, whatever its type.
+#[derive(Deserialize)]
+struct StateEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    state_key: String,
+    sender: OwnedUserId,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    #[serde(default)]
+    content: Value,
+}
+
+/// Follow Element Call memberships and call widgets.
+#[instrument(skip_all, fields(room = room.room_id().as_str()))]
+pub async fn on_state_event(
+    event: Raw<AnySyncStateEvent>,
+    room: Room,
+    notifier: Ctx<Notifier>,
+) -> anyhow::Result<()> {
+    if !notifier.watches(room.room_id()) {
+        return Ok(());
+    }
+    let event: StateEvent = event.deserialize_as()?;
+    let (key, active) = match event.event_type.as_str() {
+        CALL_MEMBER => (
+            format!("rtc:{}", event.state_key),
+            is_membership_active(&event.content),
+        ),
+        WIDGET => (
+            format!("widget:{}", event.state_key),
+            is_call_widget(&event.content),
+        ),
+        _ => return Ok(()),
+    };
+    let at = event.origin_server_ts.get().into();
+    if active {
+        notifier.joined(&room, &key, &event.sender, at).await;
+    } else {
+        notifier.left(&room, |present| present == key, at).await;
+    }
+    Ok(())
+}
+
+/// Follow calls started the old way, mostly in DMs.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_call_invite(
+    event: OriginalSyncCallInviteEvent,
+    room: Room,
+    notifier: Ctx<Notifier>,
+) -> anyhow::Result<()> {
+    if !notifier.watches(room.room_id()) {
+        return Ok(());
+    }
+    let key = format!("voip:{}:{}", event.content.call_id, event.sender);
+    let at = event.origin_server_ts.get().into();
+    notifier.joined(&room, &key, &event.sender, at).await;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_call_answer(
+    event: OriginalSyncCallAnswerEvent,
+    room: Room,
+    notifier: Ctx<Notifier>,
+) -> anyhow::Result<()> {
+    if !notifier.watches(room.room_id()) {
+        return Ok(());
+    }
+    let key = format!("voip:{}:{}", event.content.call_id, event.sender);
+    let at = event.origin_server_ts.get().into();
+    notifier.joined(&room, &key, &event.sender, at).await;
+    Ok(())
+}
+
+/// Either side hanging up ends the whole call.
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_call_hangup(
+    event: OriginalSyncCallHangupEvent,
+    room: Room,
+    notifier: Ctx<Notifier>,
+) -> anyhow::Result<()> {
+    if !notifier.watches(room.room_id()) {
+        return Ok(());
+    }
+    let prefix = format!("voip:{}:", event.content.call_id);
+    let at = event.origin_server_ts.get().into();
+    notifier
+        .left(&room, |present| present.starts_with(&prefix), at)
+        .await;
+    Ok(())
+}
+
+impl Notifier {
+    fn watches(&self, room_id: &RoomId) -> bool {
+        self.rooms.is_empty() || self.rooms.iter().any(|room| room == room_id)
+    }
+
+    async fn joined(&self, room: &Room, key: &str, user_id: &UserId, at: u64) {
+        let started =
+            self.tracker
+                .lock()
+                .unwrap()
+                .join(room.room_id().as_str(), key, user_id.as_str(), at);
+        if !started {
+            return;
+        }
+        info!(room = room.room_id().as_str(), "Call started");
+
+        let (name, link) = room_link(room);
+        let mut plain = format!("📞 A call started in {name} by {user_id}");
+        let mut formatted = format!(
+            "📞 A call started in {link} by {}",
+            html::user_pill(user_id)
+        );
+        if !self.ping.is_empty() {
+            let users: Vec<&str> = self.ping.iter().map(|user| user.as_str()).collect();
+            let pills: Vec<String> = self.ping.iter().map(|user| html::user_pill(user)).collect();
+            plain += &format!(" ({})", users.join(", "));
+            formatted += &format!(" ({})", pills.join(", "));
+        }
+        let content = RoomMessageEventContent::notice_html(plain, formatted)
+            .add_mentions(Mentions::with_user_ids(self.ping.iter().cloned()));
+        self.post(room, content).await;
+    }
+
+    async fn left(&self, room: &Room, key: impl Fn(&str) -> bool, at: u64) {
+        let ended = self
+            .tracker
+            .lock()
+            .unwrap()
+            .leave(room.room_id().as_str(), key, at);
+        let Some(Ended {
+            duration,
+            participants,
+        }) = ended
+        else {
+            return;
+        };
+        info!(room = room.room_id().as_str(), "Call ended");
+
+        let (name, link) = room_link(room);
+        let duration = format_duration(duration);
+        let plain = format!(
+            "📞 The call in {name} ended after {duration}. Participants: {}",
+            participants.join(", ")
+        );
+        let formatted = format!(
+            "📞 The call in {link} ended after {duration}. Participants: {}",
+            html::escape(&participants.join(", "))
+        );
+        self.post(room, RoomMessageEventContent::notice_html(plain, formatted))
+            .await;
+    }
+
+    /// Post to the announcement room if there is one, or else the room with
+    /// the call.
+    async fn post(&self, room: &Room, content: RoomMessageEventContent) {
+        let target = match &self.announce_room {
+            Some(room_id) => room.client().get_room(room_id),
+            None => Some(room.clone()),
+        };
+        let Some(target) = target.filter(|target| target.state() == RoomState::Joined) else {
+            debug!("Not in the room to post to");
+            return;
+        };
+        if can_reply(&target).await {
+            send_or_log_error(&target, content).await;
+        }
+    }
+}
+
+/// The room's name, and a link to it for the formatted body.
+fn room_link(room: &Room) -> (String, String) {
+    let name = room
+        .name()
+        .or_else(|| room.canonical_alias().map(|alias| alias.to_string()))
+        .unwrap_or_else(|| room.room_id().to_string());
+    let uri = match room.canonical_alias() {
+        Some(alias) => alias.matrix_to_uri().to_string(),
+        None => room.room_id().matrix_to_uri().to_string(),
+    };
+    let link = format!("<a href=\"{uri}\">{}</a>", html::escape(&name));
+    (name, link)
+}
@@ -0,0 +1,59 @@
+mod calls;
+mod handlers;
+
+use std::sync::{Arc, Mutex};
+
+use clap::Parser;
+use handlers::{on_call_answer, on_call_hangup, on_call_invite, on_state_event, Notifier};
+use matrix_bot_core::{AccountConfig, Bot};
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Rooms to watch for calls [default: every room the bot is in]
+    #[arg(long, value_delimiter = ',', env = "VOICECALL_ROOMS")]
+    pub room: Vec<OwnedRoomId>,
+
+    /// A room to post notifications to, instead of the room with the call
+    #[arg(long, env = "VOICECALL_ANNOUNCE_ROOM")]
+    pub announce_room: Option<OwnedRoomId>,
+
+    /// People to mention when a call starts
+    #[arg(long, value_delimiter = ',', env = "VOICECALL_PING")]
+    pub ping: Vec<OwnedUserId>,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-voicecall-notify", config.account_config).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new calls.
+    bot.client().add_event_handler_context(Notifier {
+        tracker: Arc::new(Mutex::new(Default::default())),
+        rooms: config.room,
+        announce_room: config.announce_room,
+        ping: config.ping,
+    });
+    bot.client().add_event_handler(on_state_event);
+    bot.client().add_event_handler(on_call_invite);
+    bot.client().add_event_handler(on_call_answer);
+    bot.client().add_event_handler(on_call_hangup);
+
+    bot.run().await
+}
@@ -0,0 +1,216 @@
+use matrix_bot_core::{
+    can_reply, is_moderator, reply, reply_notice, store::Store, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::events::room::message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+    Room, RoomState,
+};
+use tracing::{instrument, warn};
+
+use crate::{
+    provider::{Location, OpenMeteo, Provider, Units, MAX_FORECAST_DAYS},
+    render,
+};
+
+/// How many days `!forecast` covers when not told.
+const DEFAULT_FORECAST_DAYS: u32 = 3;
+
+/// The user preference holding someone's saved [`Location`].
+const LOCATION: &str = "location";
+
+/// The room setting holding the room's [`Units`].
+const UNITS: &str = "units";
+
+const HELP: &str = "Usage:
+!weather [place]
+!forecast [days, e.g. 5d] [place]
+!weather set <place> to save your location, and !weather unset to forget it
+!weather units [metric|imperial] to see or change this room's units (moderators only)";
+
+#[derive(Clone)]
+pub struct Weather {
+    pub provider: OpenMeteo,
+    pub store: Store,
+    /// The units used in rooms that haven't picked any.
+    pub default_units: Units,
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    weather: Ctx<Weather>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(body) = text_body(&event) else {
+        return Ok(());
+    };
+
+    let (args, is_forecast) = if let Some(args) = strip_command(body, "!weather") {
+        (args, false)
+    } else if let Some(args) = strip_command(body, "!forecast") {
+        (args, true)
+    } else {
+        return Ok(());
+    };
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let units = weather
+        .store
+        .room_setting::<String>(room.room_id(), UNITS)?
+        .and_then(|units| units.parse().ok())
+        .unwrap_or(weather.default_units);
+
+    if is_forecast {
+        let (days, place) = match args.split_once(char::is_whitespace).unwrap_or((args, "")) {
+            (days, place) if parse_days(days).is_some() => (parse_days(days), place.trim()),
+            _ => (None, args),
+        };
+        let days = days.unwrap_or(DEFAULT_FORECAST_DAYS);
+        if !(1..=MAX_FORECAST_DAYS).contains(&days) {
+            let response = format!("I can forecast 1 to {MAX_FORECAST_DAYS} days ahead.");
+            reply_notice(&room, &event, response).await;
+            return Ok(());
+        }
+        let Some(location) = locate(&room, &event, place, &weather).await? else {
+            return Ok(());
+        };
+        match weather.provider.forecast(&location, days, units).await {
+            Ok(days) => {
+                let (plain, formatted) = render::forecast(&location, &days, units);
+                reply(
+                    &room,
+                    &event,
+                    RoomMessageEventContent::notice_html(plain, formatted),
+                )
+                .await;
+            }
+            Err(err) => {
+                warn!("Failed to get forecast: {err:#}");
+                reply_notice(&room, &event, "Sorry, I couldn't get the forecast.").await;
+            }
+        }
+        return Ok(());
+    }
+
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+    match subcommand {
+        "help" => reply_notice(&room, &event, HELP).await,
+        "set" => {
+            let response = if rest.is_empty() {
+                "Where are you? e.g. `!weather set Berlin`".to_owned()
+            } else {
+                match weather.provider.geocode(rest).await? {
+                    Some(location) => {
+                        weather
+                            .store
+                            .set_user_pref(&event.sender, LOCATION, &location)?;
+                        format!("Saved your location as {}.", location.name)
+                    }
+                    None => format!("I couldn't find {rest}."),
+                }
+            };
+            reply_notice(&room, &event, response).await;
+        }
+        "unset" => {
+            let response = if weather.store.remove_user_pref(&event.sender, LOCATION)? {
+                "Forgot your location."
+            } else {
+                "You didn't have a location saved."
+            };
+            reply_notice(&room, &event, response).await;
+        }
+        "units" => {
+            let response = set_units(&room, &event, rest, units, &weather.store).await?;
+            reply_notice(&room, &event, response).await;
+        }
+        _ => {
+            let Some(location) = locate(&room, &event, args, &weather).await? else {
+                return Ok(());
+            };
+            match weather.provider.current(&location, units).await {
+                Ok(current) => {
+                    let (plain, formatted) = render::current(&location, &current, units);
+                    reply(
+                        &room,
+                        &event,
+                        RoomMessageEventContent::notice_html(plain, formatted),
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    warn!("Failed to get weather: {err:#}");
+                    reply_notice(&room, &event, "Sorry, I couldn't get the weather.").await;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find `place`, or the sender's saved location if it's empty. Replies
+/// explaining why and returns `None` if there's no location to use.
+async fn locate(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    place: &str,
+    weather: &Weather,
+) -> anyhow::Result<Option<Location>> {
+    if place.is_empty() {
+        let location = weather.store.user_pref(&event.sender, LOCATION)?;
+        if location.is_none() {
+            reply_notice(
+                room,
+                event,
+                "Where? e.g. `!weather Berlin`, or save your location with `!weather set Berlin`.",
+            )
+            .await;
+        }
+        return Ok(location);
+    }
+
+    match weather.provider.geocode(place).await {
+        Ok(Some(location)) => Ok(Some(location)),
+        Ok(None) => {
+            reply_notice(room, event, format!("I couldn't find {place}.")).await;
+            Ok(None)
+        }
+        Err(err) => {
+            warn!("Failed to look up location: {err:#}");
+            reply_notice(room, event, "Sorry, I couldn't look that place up.").await;
+            Ok(None)
+        }
+    }
+}
+
+async fn set_units(
+    room: &Room,
+    event: &OriginalSyncRoomMessageEvent,
+    units: &str,
+    current: Units,
+    store: &Store,
+) -> anyhow::Result<String> {
+    if units.is_empty() {
+        return Ok(format!("This room uses {current} units."));
+    }
+    let units: Units = match units.parse() {
+        Ok(units) => units,
+        Err(err) => return Ok(err.to_string()),
+    };
+    if !is_moderator(room, &event.sender).await? {
+        return Ok("Only moderators can change this room's units.".to_owned());
+    }
+    store.set_room_setting(room.room_id(), UNITS, &units.to_string())?;
+    Ok(format!("This room now uses {units} units."))
+}
+
+/// Parse a number of days such as `3d` or `3`.
+fn parse_days(days: &str) -> Option<u32> {
+    days.strip_suffix('d').unwrap_or(days).parse().ok()
+}
@@ -0,0 +1,55 @@
+mod handlers;
+mod provider;
+mod render;
+
+use std::time::Duration;
+
+use clap::Parser;
+use handlers::{on_room_message, Weather};
+use matrix_bot_core::{AccountConfig, Bot};
+use provider::{OpenMeteo, Units};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The units to use in rooms that haven't picked any, `metric` or
+    /// `imperial`
+    #[arg(long, default_value = "metric", env = "WEATHER_UNITS")]
+    pub units: Units,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-weather", config.account_config).await?;
+    let store = bot.open_store(&[]).await?;
+    bot.initial_sync().await?;
+
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("matrix-weather/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(30))
+        .build()?;
+
+    // Now that we've synced, attach handlers for new messages.
+    bot.client().add_event_handler_context(Weather {
+        provider: OpenMeteo { http },
+        store,
+        default_units: config.units,
+    });
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,294 @@
+use std::{fmt, future::Future, str::FromStr};
+
+use anyhow::{bail, Context};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// The most days a forecast can cover.
+pub const MAX_FORECAST_DAYS: u32 = 7;
+
+/// A named place, as found by geocoding.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Location {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Units {
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    pub fn temperature(self) -> &'static str {
+        match self {
+            Units::Metric => "°C",
+            Units::Imperial => "°F",
+        }
+    }
+
+    pub fn speed(self) -> &'static str {
+        match self {
+            Units::Metric => "km/h",
+            Units::Imperial => "mph",
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "metric" | "c" | "celsius" => Ok(Units::Metric),
+            "imperial" | "f" | "fahrenheit" => Ok(Units::Imperial),
+            _ => bail!("Units are either `metric` or `imperial`."),
+        }
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Units::Metric => "metric",
+            Units::Imperial => "imperial",
+        })
+    }
+}
+
+/// The weather right now.
+#[derive(Debug)]
+pub struct Current {
+    pub condition: Condition,
+    pub temperature: f64,
+    pub feels_like: f64,
+    pub humidity: f64,
+    pub wind_speed: f64,
+}
+
+/// The weather expected for a day.
+#[derive(Debug)]
+pub struct Day {
+    pub date: NaiveDate,
+    pub condition: Condition,
+    pub min: f64,
+    pub max: f64,
+    pub precipitation_chance: Option<f64>,
+}
+
+/// A WMO weather interpretation code.
+#[derive(Debug, Clone, Copy)]
+pub struct Condition(pub u8);
+
+impl Condition {
+    pub fn emoji(self) -> &'static str {
+        match self.0 {
+            0 => "☀️",
+            1 => "🌤️",
+            2 => "⛅",
+            3 => "☁️",
+            45 | 48 => "🌫️",
+            51..=57 => "🌦️",
+            61..=67 | 80..=82 => "🌧️",
+            71..=77 | 85 | 86 => "🌨️",
+            95..=99 => "⛈️",
+            _ => "🌡️",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self.0 {
+            0 => "Clear sky",
+            1 => "Mainly clear",
+            2 => "Partly cloudy",
+            3 => "Overcast",
+            45 | 48 => "Fog",
+            51 | 53 | 55 => "Drizzle",
+            56 | 57 => "Freezing drizzle",
+            61 | 63 | 65 => "Rain",
+            66 | 67 => "Freezing rain",
+            71 | 73 | 75 | 77 => "Snow",
+            80..=82 => "Rain showers",
+            85 | 86 => "Snow showers",
+            95 => "Thunderstorm",
+            96 | 99 => "Thunderstorm with hail",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// A weather service.
+pub trait Provider {
+    /// Find a place by name.
+    fn geocode(&self, name: &str) -> impl Future<Output = anyhow::Result<Option<Location>>> + Send;
+
+    fn current(
+        &self,
+        location: &Location,
+        units: Units,
+    ) -> impl Future<Output = anyhow::Result<Current>> + Send;
+
+    /// The forecast for the next `days` days, starting today.
+    fn forecast(
+        &self,
+        location: &Location,
+        days: u32,
+        units: Units,
+    ) -> impl Future<Output = anyhow::Result<Vec<Day>>> + Send;
+}
+
+/// [Open-Meteo](https://open-meteo.com), which needs no API key.
+#[derive(Clone)]
+pub struct OpenMeteo {
+    pub http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct GeocodingResponse {
+    #[serde(default)]
+    results: Vec<GeocodingResult>,
+}
+
+#[derive(Deserialize)]
+struct GeocodingResult {
+    name: String,
+    latitude: f64,
+    longitude: f64,
+    admin1: Option<String>,
+    country: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ForecastResponse {
+    current: Option<CurrentResponse>,
+    daily: Option<DailyResponse>,
+}
+
+#[derive(Deserialize)]
+struct CurrentResponse {
+    temperature_2m: f64,
+    apparent_temperature: f64,
+    relative_humidity_2m: f64,
+    weather_code: u8,
+    wind_speed_10m: f64,
+}
+
+#[derive(Deserialize)]
+struct DailyResponse {
+    time: Vec<NaiveDate>,
+    weather_code: Vec<u8>,
+    temperature_2m_max: Vec<f64>,
+    temperature_2m_min: Vec<f64>,
+    precipitation_probability_max: Vec<Option<f64>>,
+}
+
+impl OpenMeteo {
+    async fn get_forecast(
+        &self,
+        location: &Location,
+        units: Units,
+        query: &[(&str, &str)],
+    ) -> anyhow::Result<ForecastResponse> {
+        let mut request = self
+            .http
+            .get("https://api.open-meteo.com/v1/forecast")
+            .query(&[
+                ("latitude", location.latitude.to_string()),
+                ("longitude", location.longitude.to_string()),
+                ("timezone", "auto".to_owned()),
+            ])
+            .query(query);
+        if units == Units::Imperial {
+            request = request.query(&[
+                ("temperature_unit", "fahrenheit"),
+                ("wind_speed_unit", "mph"),
+            ]);
+        }
+        Ok(request.send().await?.error_for_status()?.json().await?)
+    }
+}
+
+impl Provider for OpenMeteo {
+    async fn geocode(&self, name: &str) -> anyhow::Result<Option<Location>> {
+        let response: GeocodingResponse = self
+            .http
+            .get("https://geocoding-api.open-meteo.com/v1/search")
+            .query(&[("name", name), ("count", "1")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.results.into_iter().next().map(|result| {
+            let name = [Some(result.name), result.admin1, result.country]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(", ");
+            Location {
+                name,
+                latitude: result.latitude,
+                longitude: result.longitude,
+            }
+        }))
+    }
+
+    async fn current(&self, location: &Location, units: Units) -> anyhow::Result<Current> {
+        let response = self
+            .get_forecast(
+                location,
+                units,
+                &[(
+                    "current",
+                    "temperature_2m,apparent_temperature,relative_humidity_2m,weather_code,wind_speed_10m",
+                )],
+            )
+            .await?;
+        let current = response.current.context("no current weather")?;
+        Ok(Current {
+            condition: Condition(current.weather_code),
+            temperature: current.temperature_2m,
+            feels_like: current.apparent_temperature,
+            humidity: current.relative_humidity_2m,
+            wind_speed: current.wind_speed_10m,
+        })
+    }
+
+    async fn forecast(
+        &self,
+        location: &Location,
+        days: u32,
+        units: Units,
+    ) -> anyhow::Result<Vec<Day>> {
+        let days = days.to_string();
+        let response = self
+            .get_forecast(
+                location,
+                units,
+                &[
+                    (
+                        "daily",
+                        "weather_code,temperature_2m_max,temperature_2m_min,precipitation_probability_max",
+                    ),
+                    ("forecast_days", &days),
+                ],
+            )
+            .await?;
+        let daily = response.daily.context("no daily forecast")?;
+        Ok((0..daily.time.len())
+            .map(|i| Day {
+                date: daily.time[i],
+                condition: Condition(daily.weather_code.get(i).copied().unwrap_or(u8::MAX)),
+                min: daily.temperature_2m_min.get(i).copied().unwrap_or(f64::NAN),
+                max: daily.temperature_2m_max.get(i).copied().unwrap_or(f64::NAN),
+                precipitation_chance: daily
+                    .precipitation_probability_max
+                    .get(i)
+                    .copied()
+                    .flatten(),
+            })
+            .collect())
+    }
+}
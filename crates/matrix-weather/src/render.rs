@@ -0,0 +1,64 @@
+use matrix_bot_core::html;
+
+use crate::provider::{Current, Day, Location, Units};
+
+/// The plain and HTML bodies of a message about the current weather.
+pub fn current(location: &Location, current: &Current, units: Units) -> (String, String) {
+    let temperature = units.temperature();
+    let details = format!(
+        "{} {}, {:.0}{temperature} (feels like {:.0}{temperature}), humidity {:.0}%, wind {:.0} {}",
+        current.condition.emoji(),
+        current.condition.description(),
+        current.temperature,
+        current.feels_like,
+        current.humidity,
+        current.wind_speed,
+        units.speed(),
+    );
+    (
+        format!("{}: {details}", location.name),
+        format!(
+            "<b>{}</b>: {}",
+            html::escape(&location.name),
+            html::escape(&details)
+        ),
+    )
+}
+
+/// The plain and HTML bodies of a message with a daily forecast.
+pub fn forecast(location: &Location, days: &[Day], units: Units) -> (String, String) {
+    let lines: Vec<_> = days
+        .iter()
+        .map(|day| {
+            let mut line = format!(
+                "{} {}, {:.0}–{:.0}{}",
+                day.condition.emoji(),
+                day.condition.description(),
+                day.min,
+                day.max,
+                units.temperature()
+            );
+            if let Some(chance) = day.precipitation_chance.filter(|&chance| chance > 0.0) {
+                line.push_str(&format!(", {chance:.0}% chance of precipitation"));
+            }
+            (day.date.format("%a %-d %b").to_string(), line)
+        })
+        .collect();
+
+    let plain = lines
+        .iter()
+        .map(|(date, line)| format!("{date}: {line}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let items: String = lines
+        .iter()
+        .map(|(date, line)| format!("<li><b>{date}</b>: {}</li>", html::escape(line)))
+        .collect();
+    (
+        format!("Forecast for {}:\n{plain}", location.name),
+        format!(
+            "Forecast for <b>{}</b>:<ul>{items}</ul>",
+            html::escape(&location.name)
+        ),
+    )
+}
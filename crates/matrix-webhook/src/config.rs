@@ -0,0 +1,60 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Context;
+use matrix_sdk::ruma::OwnedRoomId;
+use serde::Deserialize;
+
+/// The hooks file, e.g.
+///
+/// ```toml
+/// [hooks.deploys]
+/// token = "a long random string"
+/// secret = "shared HMAC key"
+/// rooms = ["!abc:example.org"]
+/// template = "{{ payload.service }} deployed {{ payload.version }}"
+/// html_template = "<b>{{ payload.service }}</b> deployed {{ payload.version }}"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HooksFile {
+    pub hooks: HashMap<String, HookConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookConfig {
+    /// Sent by callers as a bearer token or a `token` query parameter.
+    pub token: String,
+    /// If set, requests must be signed with HMAC-SHA256 using this key.
+    pub secret: Option<String>,
+    pub rooms: Vec<OwnedRoomId>,
+    /// A Tera template for the plain-text body. The JSON payload is
+    /// available as `payload`, and the `pill`, `room_link` and `timestamp`
+    /// filters format IDs and times.
+    pub template: String,
+    /// A Tera template for the HTML body. Values are escaped automatically.
+    pub html_template: Option<String>,
+    /// How many requests the hook accepts per minute.
+    #[serde(default = "default_rate_limit")]
+    pub rate_limit: usize,
+}
+
+fn default_rate_limit() -> usize {
+    30
+}
+
+pub fn load(path: &Path) -> anyhow::Result<HooksFile> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read hooks file {}", path.display()))?;
+    let file: HooksFile = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse hooks file {}", path.display()))?;
+    for (name, hook) in &file.hooks {
+        if hook.token.len() < 16 {
+            anyhow::bail!("the token for hook {name} should be at least 16 characters");
+        }
+        if hook.rooms.is_empty() {
+            anyhow::bail!("hook {name} has no rooms");
+        }
+    }
+    Ok(file)
+}
@@ -0,0 +1,55 @@
+mod config;
+mod server;
+
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+
+use clap::Parser;
+use matrix_bot_core::{
+    http::{HttpConfig, HttpServer},
+    AccountConfig, Bot,
+};
+use server::Hooks;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// The address to listen for webhooks on
+    #[arg(long, default_value = "0.0.0.0:8080", env = "WEBHOOK_LISTEN")]
+    pub listen: SocketAddr,
+
+    #[clap(flatten)]
+    pub http: HttpConfig,
+
+    /// The TOML file defining the hooks
+    #[arg(long, env = "WEBHOOK_HOOKS")]
+    pub hooks: PathBuf,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    // Fail on a bad hooks file before logging in
+    let hooks_file = config::load(&config.hooks)?;
+
+    let mut bot = Bot::login("matrix-webhook", config.account_config).await?;
+    bot.initial_sync().await?;
+
+    let hooks = Arc::new(Hooks::new(bot.client().clone(), hooks_file)?);
+    bot.serve_http(HttpServer::new(config.listen, config.http).merge(server::router(hooks)))
+        .await?;
+
+    bot.run().await
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use hmac::{Hmac, Mac};
+use matrix_bot_core::{
+    can_reply,
+    http::constant_time_eq,
+    send_or_log_error,
+    template::{Context, Templates},
+};
+use matrix_sdk::{ruma::events::room::message::RoomMessageEventContent, Client, RoomState};
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::{debug, info, instrument, warn};
+
+use crate::config::{HookConfig, HooksFile};
+
+/// The window a hook's rate limit is counted over.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Headers a request's signature may be sent in, as `sha256=<hex>`.
+const SIGNATURE_HEADERS: [&str; 2] = ["x-signature-256", "x-hub-signature-256"];
+
+struct Hook {
+    config: HookConfig,
+    recent: Mutex<VecDeque<Instant>>,
+}
+
+pub struct Hooks {
+    client: Client,
+    hooks: HashMap<String, Hook>,
+    templates: Templates,
+}
+
+impl Hooks {
+    pub fn new(client: Client, file: HooksFile) -> anyhow::Result<Self> {
+        let mut templates = Templates::new();
+        for (name, hook) in &file.hooks {
+            templates.add(name, &hook.template, hook.html_template.as_deref())?;
+        }
+
+        let hooks = file
+            .hooks
+            .into_iter()
+            .map(|(name, config)| {
+                let hook = Hook {
+                    config,
+                    recent: Default::default(),
+                };
+                (name, hook)
+            })
+            .collect();
+        Ok(Self {
+            client,
+            hooks,
+            templates,
+        })
+    }
+
+    fn render(
+        &self,
+        name: &str,
+        payload: serde_json::Value,
+    ) -> anyhow::Result<RoomMessageEventContent> {
+        let mut context = Context::new();
+        context.insert("hook", name);
+        context.insert("payload", &payload);
+        self.templates.render_notice(name, &context)
+    }
+}
+
+pub fn router(hooks: Arc<Hooks>) -> Router {
+    Router::new()
+        .route("/hooks/:name", post(receive))
+        .with_state(hooks)
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+#[instrument(skip_all, fields(hook = name.as_str()))]
+async fn receive(
+    State(hooks): State<Arc<Hooks>>,
+    Path(name): Path<String>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, &'static str) {
+    let Some(hook) = hooks.hooks.get(&name) else {
+        return (StatusCode::NOT_FOUND, "no such hook");
+    };
+
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query.token.as_deref());
+    if !token.is_some_and(|token| constant_time_eq(token.as_bytes(), hook.config.token.as_bytes()))
+    {
+        debug!("Rejected request with a bad token");
+        return (StatusCode::UNAUTHORIZED, "bad token");
+    }
+
+    if let Some(secret) = &hook.config.secret {
+        if !signature_valid(secret, &headers, &body) {
+            debug!("Rejected request with a bad signature");
+            return (StatusCode::UNAUTHORIZED, "bad signature");
+        }
+    }
+
+    if !hook.allow() {
+        warn!("Rate limit reached");
+        return (StatusCode::TOO_MANY_REQUESTS, "rate limited");
+    }
+
+    let payload: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(_) => return (StatusCode::BAD_REQUEST, "invalid JSON"),
+    };
+
+    let content = match hooks.render(&name, payload) {
+        Ok(content) => content,
+        Err(err) => {
+            warn!("Failed to render template: {err:#}");
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "failed to render template",
+            );
+        }
+    };
+
+    for room_id in &hook.config.rooms {
+        let Some(room) = hooks.client.get_room(room_id) else {
+            warn!(room = room_id.as_str(), "Not in a room the hook posts to");
+            continue;
+        };
+        if room.state() != RoomState::Joined || !can_reply(&room).await {
+            continue;
+        }
+        send_or_log_error(&room, content.clone()).await;
+    }
+    info!("Delivered webhook");
+    (StatusCode::ACCEPTED, "accepted")
+}
+
+impl Hook {
+    /// Count a request against the rate limit, returning whether it's allowed.
+    fn allow(&self) -> bool {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        while recent
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= RATE_WINDOW)
+        {
+            recent.pop_front();
+        }
+        if recent.len() >= self.config.rate_limit {
+            return false;
+        }
+        recent.push_back(now);
+        true
+    }
+}
+
+fn signature_valid(secret: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+    let Some(signature) = SIGNATURE_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("sha256="))
+        .and_then(|value| hex::decode(value).ok())
+    else {
+        return false;
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
@@ -0,0 +1,49 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use matrix_sdk::ruma::OwnedRoomId;
+
+/// Tracks joins per room, so that a flood of joins (a raid, or a room being
+/// linked somewhere busy) doesn't become a flood of greetings.
+#[derive(Clone)]
+pub struct Burst {
+    window: Duration,
+    limit: usize,
+    joins: Arc<Mutex<HashMap<OwnedRoomId, VecDeque<Instant>>>>,
+}
+
+impl Burst {
+    pub fn new(window: Duration, limit: usize) -> Self {
+        Self {
+            window,
+            limit,
+            joins: Default::default(),
+        }
+    }
+
+    /// Count a join, returning whether the room is quiet enough to greet it.
+    pub fn allow(&self, room_id: &OwnedRoomId) -> bool {
+        let now = Instant::now();
+        let mut joins = self.joins.lock().unwrap();
+        joins.retain(|_, times| {
+            times
+                .back()
+                .is_some_and(|time| now.duration_since(*time) < self.window)
+        });
+
+        let times = joins.entry(room_id.clone()).or_default();
+        while times
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= self.window)
+        {
+            times.pop_front();
+        }
+        // Joins during a burst still count, so it has to die down before
+        // greetings resume
+        times.push_back(now);
+        times.len() <= self.limit
+    }
+}
@@ -0,0 +1,218 @@
+use matrix_bot_core::{
+    can_reply, html, is_moderator, reply_notice, send, strip_command, text_body,
+};
+use matrix_sdk::{
+    event_handler::Ctx,
+    ruma::{
+        events::{
+            room::{
+                member::{MembershipState, OriginalSyncRoomMemberEvent},
+                message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+            },
+            Mentions,
+        },
+        UserId,
+    },
+    Client, Room, RoomState,
+};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    burst::Burst,
+    table::{Settings, WelcomeTable, DEFAULT_TEMPLATE},
+};
+
+const HELP: &str = "Usage:
+!welcome on / off
+!welcome template <text>, using {user}, {displayname} and {room}
+!welcome mode room / dm
+!welcome first-time on / off
+!welcome show
+Changing settings needs moderator rights.";
+
+#[derive(Clone)]
+pub struct Welcome {
+    pub table: WelcomeTable,
+    pub burst: Burst,
+}
+
+#[instrument(skip_all, fields(room = room.room_id().as_str(), user = event.state_key.as_str()))]
+pub async fn on_room_member(
+    event: OriginalSyncRoomMemberEvent,
+    room: Room,
+    client: Client,
+    welcome: Ctx<Welcome>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined || event.content.membership != MembershipState::Join {
+        return Ok(());
+    }
+    // Profile changes are joins too, but only to a room they're already in
+    let was_joined = event
+        .unsigned
+        .prev_content
+        .as_ref()
+        .is_some_and(|prev| prev.membership == MembershipState::Join);
+    let user_id = &event.state_key;
+    if was_joined || client.user_id() == Some(user_id.as_ref()) {
+        return Ok(());
+    }
+
+    let room_id = room.room_id();
+    let settings = welcome.table.settings(room_id.as_str())?;
+    let first_time = welcome
+        .table
+        .record_join(room_id.as_str(), user_id.as_str())?;
+    if !settings.enabled || (settings.first_time_only && !first_time) {
+        return Ok(());
+    }
+    if !welcome.burst.allow(&room_id.to_owned()) {
+        debug!("Too many joins at once, not greeting");
+        return Ok(());
+    }
+
+    let room_name = room
+        .name()
+        .or_else(|| room.canonical_alias().map(|alias| alias.to_string()))
+        .unwrap_or_else(|| room_id.to_string());
+    let display_name = event
+        .content
+        .displayname
+        .as_deref()
+        .unwrap_or(user_id.localpart());
+    let (plain, formatted) = render(&settings.template, user_id, display_name, &room_name);
+
+    if settings.dm {
+        let dm = match client.get_dm_room(user_id) {
+            Some(dm) => dm,
+            None => client.create_dm(user_id).await?,
+        };
+        send(&dm, RoomMessageEventContent::notice_html(plain, formatted)).await?;
+    } else {
+        if !can_reply(&room).await {
+            return Ok(());
+        }
+        let content = RoomMessageEventContent::notice_html(plain, formatted)
+            .add_mentions(Mentions::with_user_ids([user_id.clone()]));
+        if let Err(err) = send(&room, content).await {
+            warn!("Failed to send greeting: {err:#}");
+            return Ok(());
+        }
+    }
+    info!(dm = settings.dm, "Greeted new member");
+    Ok(())
+}
+
+#[instrument(skip_all, fields(event = event.event_id.as_str(), room = room.room_id().as_str()))]
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    welcome: Ctx<Welcome>,
+) -> anyhow::Result<()> {
+    if room.state() != RoomState::Joined {
+        return Ok(());
+    }
+    let Some(args) = text_body(&event).and_then(|body| strip_command(body, "!welcome")) else {
+        return Ok(());
+    };
+
+    if !can_reply(&room).await {
+        return Ok(());
+    }
+
+    let response = command(args, &event, &room, &welcome.table).await?;
+    reply_notice(&room, &event, response).await;
+    Ok(())
+}
+
+async fn command(
+    args: &str,
+    event: &OriginalSyncRoomMessageEvent,
+    room: &Room,
+    table: &WelcomeTable,
+) -> anyhow::Result<String> {
+    let room_id = room.room_id().as_str();
+    let mut settings = table.settings(room_id)?;
+    let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+
+    match subcommand {
+        "show" => return Ok(describe(&settings)),
+        "on" | "off" | "template" | "mode" | "first-time" => {}
+        _ => return Ok(HELP.to_owned()),
+    }
+
+    if !is_moderator(room, &event.sender).await? {
+        return Ok("Only moderators can change how this room welcomes people.".to_owned());
+    }
+
+    let response = match (subcommand, rest) {
+        ("on", _) => {
+            settings.enabled = true;
+            "I'll welcome people who join this room."
+        }
+        ("off", _) => {
+            settings.enabled = false;
+            "I'll stop welcoming people here."
+        }
+        ("template", "") => return Ok("What should the welcome say?".to_owned()),
+        ("template", "default" | "reset") => {
+            settings.template = DEFAULT_TEMPLATE.to_owned();
+            "Reset the welcome message."
+        }
+        ("template", template) => {
+            settings.template = template.to_owned();
+            "Updated the welcome message."
+        }
+        ("mode", "room") => {
+            settings.dm = false;
+            "I'll welcome people in this room."
+        }
+        ("mode", "dm") => {
+            settings.dm = true;
+            "I'll welcome people in a direct message."
+        }
+        ("first-time", "on") => {
+            settings.first_time_only = true;
+            "I'll only welcome people the first time they join."
+        }
+        ("first-time", "off") => {
+            settings.first_time_only = false;
+            "I'll welcome people every time they join."
+        }
+        _ => return Ok(HELP.to_owned()),
+    };
+    table.set_settings(room_id, &settings)?;
+    info!(subcommand, "Changed welcome settings");
+    Ok(response.to_owned())
+}
+
+fn describe(settings: &Settings) -> String {
+    format!(
+        "Welcomes are {}, sent {}{}.\nTemplate: {}",
+        if settings.enabled { "on" } else { "off" },
+        if settings.dm {
+            "by direct message"
+        } else {
+            "in this room"
+        },
+        if settings.first_time_only {
+            ", only for first-time joiners"
+        } else {
+            ""
+        },
+        settings.template
+    )
+}
+
+/// Fill in a template, returning its plain and HTML forms.
+fn render(template: &str, user_id: &UserId, display_name: &str, room: &str) -> (String, String) {
+    let plain = template
+        .replace("{user}", display_name)
+        .replace("{displayname}", display_name)
+        .replace("{room}", room);
+    let formatted = html::escape(template)
+        .replace("{user}", &html::user_pill(user_id))
+        .replace("{displayname}", &html::escape(display_name))
+        .replace("{room}", &html::escape(room));
+    (plain, formatted)
+}
@@ -0,0 +1,59 @@
+mod burst;
+mod handlers;
+mod table;
+
+use std::time::Duration;
+
+use burst::Burst;
+use clap::Parser;
+use handlers::{on_room_member, on_room_message, Welcome};
+use matrix_bot_core::{AccountConfig, Bot};
+use table::WelcomeTable;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct Config {
+    #[clap(flatten)]
+    pub account_config: AccountConfig,
+
+    /// Stop greeting in a room once this many people join within the burst window
+    #[arg(long, default_value_t = 5, env = "WELCOME_BURST_LIMIT")]
+    pub burst_limit: usize,
+
+    /// The window joins are counted over for burst suppression
+    #[arg(long, default_value = "1m", value_parser = parse_window, env = "WELCOME_BURST_WINDOW")]
+    pub burst_window: Duration,
+
+    #[clap(flatten)]
+    pub(crate) verbose: clap_verbosity_flag::Verbosity,
+}
+
+fn parse_window(window: &str) -> Result<Duration, String> {
+    matrix_bot_core::parse_duration(window).ok_or_else(|| format!("invalid window: {window}"))
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+    // Read args
+    let config = Config::parse();
+
+    // Logging
+    matrix_bot_core::init_logging(&config.verbose);
+
+    info!("Starting up");
+
+    let mut bot = Bot::login("matrix-welcome", config.account_config).await?;
+    let table = WelcomeTable::open(&bot).await?;
+    bot.initial_sync().await?;
+
+    // Now that we've synced, attach handlers for new joins and messages, so
+    // that people who joined while we were offline aren't greeted late.
+    bot.client().add_event_handler_context(Welcome {
+        table,
+        burst: Burst::new(config.burst_window, config.burst_limit),
+    });
+    bot.client().add_event_handler(on_room_member);
+    bot.client().add_event_handler(on_room_message);
+
+    bot.run().await
+}
@@ -0,0 +1,103 @@
+use matrix_bot_core::{store::Store, Bot};
+use rusqlite::{params, OptionalExtension};
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE rooms (
+        room_id TEXT PRIMARY KEY,
+        enabled INTEGER NOT NULL,
+        template TEXT NOT NULL,
+        dm INTEGER NOT NULL,
+        first_time_only INTEGER NOT NULL
+    );
+    CREATE TABLE seen (
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        PRIMARY KEY (room_id, user_id)
+    );"];
+
+/// The template used until a room sets its own.
+pub const DEFAULT_TEMPLATE: &str = "Welcome to {room}, {user}!";
+
+/// How a room wants its new members greeted.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub enabled: bool,
+    pub template: String,
+    /// Greet new members in a direct message rather than in the room.
+    pub dm: bool,
+    /// Only greet people the first time they join.
+    pub first_time_only: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            template: DEFAULT_TEMPLATE.to_owned(),
+            dm: false,
+            first_time_only: false,
+        }
+    }
+}
+
+/// Each room's settings and who has joined it, persisted in SQLite.
+#[derive(Clone)]
+pub struct WelcomeTable {
+    store: Store,
+}
+
+impl WelcomeTable {
+    pub async fn open(bot: &Bot) -> anyhow::Result<Self> {
+        Ok(Self {
+            store: bot.open_store(MIGRATIONS).await?,
+        })
+    }
+
+    pub fn settings(&self, room_id: &str) -> anyhow::Result<Settings> {
+        self.store.with(|conn| {
+            let settings = conn
+                .query_row(
+                    "SELECT enabled, template, dm, first_time_only FROM rooms WHERE room_id = ?1",
+                    [room_id],
+                    |row| {
+                        Ok(Settings {
+                            enabled: row.get(0)?,
+                            template: row.get(1)?,
+                            dm: row.get(2)?,
+                            first_time_only: row.get(3)?,
+                        })
+                    },
+                )
+                .optional()?;
+            Ok(settings.unwrap_or_default())
+        })
+    }
+
+    pub fn set_settings(&self, room_id: &str, settings: &Settings) -> anyhow::Result<()> {
+        self.store.with(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO rooms (room_id, enabled, template, dm, first_time_only)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    room_id,
+                    settings.enabled,
+                    settings.template,
+                    settings.dm,
+                    settings.first_time_only
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Remember that a user joined a room, returning whether this is the
+    /// first time we've seen them join it.
+    pub fn record_join(&self, room_id: &str, user_id: &str) -> anyhow::Result<bool> {
+        self.store.with(|conn| {
+            let inserted = conn.execute(
+                "INSERT OR IGNORE INTO seen (room_id, user_id) VALUES (?1, ?2)",
+                [room_id, user_id],
+            )?;
+            Ok(inserted > 0)
+        })
+    }
+}